@@ -2,8 +2,12 @@
 //!
 //! Run with: cargo bench
 
-use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use bytes::Bytes;
+use criterion::{
+    black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput,
+};
 use in_memory_cache::{Cache, CacheConfig};
+use std::sync::Arc;
 use std::time::Duration;
 
 /// Benchmark single-threaded get/set operations.
@@ -55,6 +59,16 @@ fn bench_single_threaded(c: &mut Criterion) {
         });
     });
 
+    // Borrowed keys: overwriting should not allocate a key String
+    let keys: Vec<String> = (0..10_000).map(|i| format!("key_{}", i)).collect();
+    group.bench_function("set_existing_borrowed", |b| {
+        let mut i = 0;
+        b.iter(|| {
+            cache.set(keys[i % keys.len()].as_str(), "updated_value");
+            i += 1;
+        });
+    });
+
     group.finish();
 }
 
@@ -104,6 +118,302 @@ fn bench_concurrent(c: &mut Criterion) {
     group.finish();
 }
 
+/// Compare concurrent gets on one shard and on eight.
+///
+/// Every get promotes its entry under the write lock of its shard, so with
+/// a single shard the readers take turns; with eight, the time per batch
+/// should stay close to flat as threads are added. A sharded run that
+/// scales like the unsharded one is a regression.
+fn bench_sharded(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sharded");
+
+    for shards in [1, 8] {
+        let cache = Cache::new(CacheConfig::new().max_capacity(100_000).shards(shards));
+        for i in 0..10_000 {
+            cache.set(format!("key_{}", i), format!("value_{}", i));
+        }
+
+        for num_threads in [1, 2, 4, 8] {
+            group.throughput(Throughput::Elements(1000 * num_threads as u64));
+            group.bench_with_input(
+                BenchmarkId::new(format!("gets_shards_{}", shards), num_threads),
+                &num_threads,
+                |b, &num_threads| {
+                    b.iter(|| {
+                        let handles: Vec<_> = (0..num_threads)
+                            .map(|t| {
+                                let cache = cache.clone();
+                                std::thread::spawn(move || {
+                                    for i in 0..1000 {
+                                        let key = format!("key_{}", (t * 1000 + i) % 10_000);
+                                        black_box(cache.get(&key));
+                                    }
+                                })
+                            })
+                            .collect();
+
+                        for handle in handles {
+                            handle.join().unwrap();
+                        }
+                    });
+                },
+            );
+        }
+    }
+
+    group.finish();
+}
+
+/// Time `clear` spends on the caller's thread for a 1M-entry cache.
+fn bench_clear(c: &mut Criterion) {
+    let mut group = c.benchmark_group("clear_1m");
+    group.sample_size(10);
+
+    for background in [false, true].iter() {
+        group.bench_with_input(
+            BenchmarkId::new("background", background),
+            background,
+            |b, &background| {
+                b.iter_batched(
+                    || {
+                        let cache = Cache::new(CacheConfig::new().background_clear(background));
+                        for i in 0..1_000_000 {
+                            cache.set(format!("key_{}", i), "value");
+                        }
+                        cache
+                    },
+                    |cache| cache.clear(),
+                    criterion::BatchSize::PerIteration,
+                );
+            },
+        );
+    }
+
+    group.finish();
+}
+
+/// Sweeping a million entries that never expire next to a hundred that do.
+fn bench_cleanup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cleanup_1m");
+    group.sample_size(10);
+
+    let cache = Cache::new(CacheConfig::default());
+    for i in 0..1_000_000 {
+        cache.set(format!("key_{}", i), "value");
+    }
+    let add_expiring = || {
+        for i in 0..100 {
+            cache.set_with_ttl(format!("ttl_{}", i), "value", Duration::from_millis(1));
+        }
+    };
+
+    group.bench_function("none_due", |b| b.iter(|| cache.cleanup_expired()));
+    group.bench_function("100_due", |b| {
+        b.iter_batched(
+            || {
+                add_expiring();
+                std::thread::sleep(Duration::from_millis(2));
+            },
+            |_| cache.cleanup_expired(),
+            BatchSize::PerIteration,
+        );
+    });
+
+    group.finish();
+}
+
+/// Hot-key reads with and without a promotion threshold.
+fn bench_promotion_threshold(c: &mut Criterion) {
+    let mut group = c.benchmark_group("promotion_threshold");
+    let num_threads = 8;
+
+    for threshold_ms in [0u64, 100].iter() {
+        let config = CacheConfig::new()
+            .max_capacity(100_000)
+            .promotion_threshold(Duration::from_millis(*threshold_ms))
+            .build();
+        let cache = Cache::new(config);
+        for i in 0..100 {
+            cache.set(format!("hot_{}", i), "value");
+        }
+        let keys: Arc<Vec<String>> = Arc::new((0..100).map(|i| format!("hot_{}", i)).collect());
+
+        group.throughput(Throughput::Elements(num_threads as u64 * 1000));
+        group.bench_with_input(
+            BenchmarkId::new("hot_gets_ms", threshold_ms),
+            threshold_ms,
+            |b, _| {
+                b.iter(|| {
+                    let handles: Vec<_> = (0..num_threads)
+                        .map(|t| {
+                            let cache = cache.clone();
+                            let keys = Arc::clone(&keys);
+                            std::thread::spawn(move || {
+                                for i in 0..1000 {
+                                    black_box(cache.get(&keys[(t * 7 + i) % keys.len()]));
+                                }
+                            })
+                        })
+                        .collect();
+
+                    for handle in handles {
+                        handle.join().unwrap();
+                    }
+                });
+            },
+        );
+    }
+
+    group.finish();
+}
+
+/// Compare locked and frozen reads under many threads.
+fn bench_frozen(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frozen");
+    let num_threads = 8;
+
+    let cache = Cache::new(CacheConfig::new().max_capacity(100_000).build());
+    for i in 0..10_000 {
+        cache.set(format!("key_{}", i), format!("value_{}", i));
+    }
+    let frozen = cache.freeze();
+    let keys: Arc<Vec<String>> = Arc::new((0..10_000).map(|i| format!("key_{}", i)).collect());
+
+    group.throughput(Throughput::Elements(num_threads * 1000));
+    group.bench_function("locked_get_8_threads", |b| {
+        b.iter(|| {
+            let handles: Vec<_> = (0..num_threads as usize)
+                .map(|t| {
+                    let cache = cache.clone();
+                    let keys = Arc::clone(&keys);
+                    std::thread::spawn(move || {
+                        for i in 0..1000 {
+                            black_box(cache.get(&keys[(t * 1000 + i) % 10_000]));
+                        }
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+        });
+    });
+    group.bench_function("frozen_get_8_threads", |b| {
+        b.iter(|| {
+            let handles: Vec<_> = (0..num_threads as usize)
+                .map(|t| {
+                    let frozen = frozen.clone();
+                    let keys = Arc::clone(&keys);
+                    std::thread::spawn(move || {
+                        for i in 0..1000 {
+                            black_box(frozen.get(&keys[(t * 1000 + i) % 10_000]));
+                        }
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+        });
+    });
+
+    group.finish();
+}
+
+/// Benchmark a contended mixed workload with lock wait recording enabled.
+///
+/// Prints the recorded p50/p99 lock waits so the cost of contention can be
+/// compared against the uncontended case.
+fn bench_lock_waits(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lock_waits");
+
+    for num_threads in [1, 8].iter() {
+        let config = CacheConfig::new()
+            .max_capacity(100_000)
+            .record_lock_waits(true)
+            .build();
+        let cache = Cache::new(config);
+
+        for i in 0..10_000 {
+            cache.set(format!("key_{}", i), format!("value_{}", i));
+        }
+
+        group.throughput(Throughput::Elements(1000));
+        group.bench_with_input(
+            BenchmarkId::new("mixed_ops_recorded", num_threads),
+            num_threads,
+            |b, &num_threads| {
+                b.iter(|| {
+                    let handles: Vec<_> = (0..num_threads)
+                        .map(|t| {
+                            let cache = cache.clone();
+                            std::thread::spawn(move || {
+                                for i in 0..1000 {
+                                    let key = format!("key_{}", (t * 1000 + i) % 10_000);
+                                    if i % 5 == 0 {
+                                        cache.set(key, "value");
+                                    } else {
+                                        black_box(cache.get(&key));
+                                    }
+                                }
+                            })
+                        })
+                        .collect();
+
+                    for handle in handles {
+                        handle.join().unwrap();
+                    }
+                });
+            },
+        );
+
+        let stats = cache.stats();
+        eprintln!(
+            "lock_waits/{} threads: p50={}ns p99={}ns",
+            num_threads, stats.lock_wait_p50_ns, stats.lock_wait_p99_ns
+        );
+    }
+
+    group.finish();
+}
+
+/// Benchmark the cost of `checksum_values` on 1 KiB values.
+fn bench_checksum(c: &mut Criterion) {
+    let mut group = c.benchmark_group("checksum");
+    let value = vec![0x5Au8; 1024];
+
+    for enabled in [false, true] {
+        let config = CacheConfig::new()
+            .max_capacity(100_000)
+            .checksum_values(enabled)
+            .build();
+        let cache = Cache::new(config);
+        for i in 0..10_000 {
+            cache.set(format!("key_{}", i), value.clone());
+        }
+        let label = if enabled { "on" } else { "off" };
+
+        group.bench_with_input(BenchmarkId::new("get", label), &cache, |b, cache| {
+            let mut i = 0;
+            b.iter(|| {
+                let key = format!("key_{}", i % 10_000);
+                black_box(cache.get(&key));
+                i += 1;
+            });
+        });
+
+        group.bench_with_input(BenchmarkId::new("set", label), &cache, |b, cache| {
+            let mut i = 0;
+            b.iter(|| {
+                cache.set(format!("key_{}", i % 10_000), value.clone());
+                i += 1;
+            });
+        });
+    }
+
+    group.finish();
+}
+
 /// Benchmark TTL operations.
 fn bench_ttl(c: &mut Criterion) {
     let mut group = c.benchmark_group("ttl");
@@ -121,6 +431,69 @@ fn bench_ttl(c: &mut Criterion) {
     group.finish();
 }
 
+/// A batched lookup of 50 keys against 50 individual gets.
+fn bench_get_many(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get_many");
+
+    let cache = Cache::new(CacheConfig::new().max_capacity(100_000).build());
+    for i in 0..10_000 {
+        cache.set(format!("key_{}", i), format!("value_{}", i));
+    }
+    let keys: Vec<String> = (0..50).map(|i| format!("key_{}", i * 199)).collect();
+    group.throughput(Throughput::Elements(keys.len() as u64));
+
+    group.bench_function("individual_gets", |b| {
+        b.iter(|| {
+            for key in &keys {
+                black_box(cache.get(key));
+            }
+        });
+    });
+
+    group.bench_function("get_many", |b| {
+        b.iter(|| black_box(cache.get_many(&keys)));
+    });
+
+    group.finish();
+}
+
+/// Loading 10k entries with one batched set against a loop of sets.
+fn bench_set_many(c: &mut Criterion) {
+    let mut group = c.benchmark_group("set_many");
+
+    let pairs: Vec<(String, Bytes)> = (0..10_000)
+        .map(|i| (format!("key_{}", i), Bytes::from(format!("value_{}", i))))
+        .collect();
+    group.throughput(Throughput::Elements(pairs.len() as u64));
+    let config = CacheConfig::new().max_capacity(100_000).build();
+
+    group.bench_function("set_loop", |b| {
+        b.iter_batched(
+            || (Cache::new(config.clone()), pairs.clone()),
+            |(cache, pairs)| {
+                for (key, value) in pairs {
+                    cache.set(key, value);
+                }
+                cache
+            },
+            BatchSize::LargeInput,
+        );
+    });
+
+    group.bench_function("set_many", |b| {
+        b.iter_batched(
+            || (Cache::new(config.clone()), pairs.clone()),
+            |(cache, pairs)| {
+                cache.set_many(pairs);
+                cache
+            },
+            BatchSize::LargeInput,
+        );
+    });
+
+    group.finish();
+}
+
 /// Benchmark eviction under pressure.
 fn bench_eviction(c: &mut Criterion) {
     let mut group = c.benchmark_group("eviction");
@@ -145,11 +518,85 @@ fn bench_eviction(c: &mut Criterion) {
     group.finish();
 }
 
+/// Direct sets against batched sets from 8 producer tasks.
+#[cfg(feature = "tokio")]
+fn bench_batch_writer(c: &mut Criterion) {
+    let mut group = c.benchmark_group("batch_writer");
+    let producers = 8;
+    let per_producer = 2_000;
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let keys: Arc<Vec<String>> =
+        Arc::new((0..per_producer).map(|i| format!("key_{}", i)).collect());
+
+    group.throughput(Throughput::Elements((producers * per_producer) as u64));
+    group.bench_function("direct_sets", |b| {
+        let cache = Cache::new(CacheConfig::new().max_capacity(100_000).build());
+        b.iter(|| {
+            runtime.block_on(async {
+                let tasks: Vec<_> = (0..producers)
+                    .map(|t| {
+                        let cache = cache.clone();
+                        let keys = Arc::clone(&keys);
+                        tokio::spawn(async move {
+                            for key in keys.iter() {
+                                cache.set(format!("{}:{}", t, key), "value");
+                            }
+                        })
+                    })
+                    .collect();
+                for task in tasks {
+                    task.await.unwrap();
+                }
+            })
+        });
+    });
+
+    group.bench_function("batched_sets", |b| {
+        let cache = Cache::new(CacheConfig::new().max_capacity(100_000).build());
+        let writer = runtime.block_on(async { cache.batch_writer(1024, Duration::from_millis(1)) });
+        b.iter(|| {
+            runtime.block_on(async {
+                let tasks: Vec<_> = (0..producers)
+                    .map(|t| {
+                        let writer = writer.clone();
+                        let keys = Arc::clone(&keys);
+                        tokio::spawn(async move {
+                            for key in keys.iter() {
+                                writer.set(format!("{}:{}", t, key), "value").await.unwrap();
+                            }
+                        })
+                    })
+                    .collect();
+                for task in tasks {
+                    task.await.unwrap();
+                }
+                // Count the time until the writes are visible
+                writer.flush().await.unwrap();
+            })
+        });
+    });
+
+    group.finish();
+}
+
+#[cfg(not(feature = "tokio"))]
+fn bench_batch_writer(_c: &mut Criterion) {}
+
 criterion_group!(
     benches,
     bench_single_threaded,
     bench_concurrent,
+    bench_sharded,
+    bench_lock_waits,
+    bench_promotion_threshold,
+    bench_clear,
+    bench_cleanup,
+    bench_frozen,
+    bench_checksum,
     bench_ttl,
+    bench_get_many,
+    bench_set_many,
     bench_eviction,
+    bench_batch_writer,
 );
 criterion_main!(benches);
@@ -0,0 +1,56 @@
+//! Embeds the git commit the crate is built from as
+//! `IN_MEMORY_CACHE_GIT_HASH`, for `in_memory_cache::version()`.
+//!
+//! Builds outside a git checkout (such as from crates.io) get "unknown",
+//! unless the variable is already set in the build environment.
+
+use std::path::Path;
+use std::process::Command;
+
+const VAR: &str = "IN_MEMORY_CACHE_GIT_HASH";
+
+fn main() {
+    println!("cargo:rerun-if-env-changed={}", VAR);
+    println!("cargo:rerun-if-changed=build.rs");
+    watch_git_head();
+
+    let hash = std::env::var(VAR)
+        .ok()
+        .filter(|hash| !hash.is_empty())
+        .or_else(git_hash)
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env={}={}", VAR, hash);
+}
+
+/// The abbreviated hash of `HEAD`, if git is installed and this is a
+/// checkout.
+fn git_hash() -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let hash = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (!hash.is_empty()).then_some(hash)
+}
+
+/// Re-run when a commit is made or another branch is checked out. Paths
+/// that do not exist are not watched, since cargo would then re-run the
+/// script on every build.
+fn watch_git_head() {
+    let head = Path::new(".git/HEAD");
+    if !head.exists() {
+        return;
+    }
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    if let Ok(contents) = std::fs::read_to_string(head) {
+        if let Some(reference) = contents.trim().strip_prefix("ref: ") {
+            let path = Path::new(".git").join(reference);
+            if path.exists() {
+                println!("cargo:rerun-if-changed={}", path.display());
+            }
+        }
+    }
+}
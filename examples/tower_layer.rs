@@ -0,0 +1,139 @@
+//! Put the cache in front of a slow origin with `CacheLayer`.
+//!
+//! The origin answers with a page and a `max-age`, which becomes the TTL of
+//! the cached copy; pages marked `no-store` are passed through uncached.
+//! Eight concurrent requests for one page reach the origin once.
+//!
+//! ```text
+//! cargo run --example tower_layer --features tower
+//! ```
+
+use bytes::Bytes;
+use in_memory_cache::layer::CacheLayer;
+use in_memory_cache::Cache;
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tower::{Layer, Service};
+
+/// An HTTP-like response: a `Cache-Control` header and a body.
+///
+/// The cache stores responses as bytes, so the header travels in front of
+/// the body, separated by a newline.
+#[derive(Debug)]
+struct Page {
+    cache_control: String,
+    body: String,
+}
+
+impl Page {
+    /// The TTL `Cache-Control` allows, or `None` if the page must not be
+    /// stored.
+    fn max_age(&self) -> Option<Duration> {
+        if self.cache_control.contains("no-store") {
+            return None;
+        }
+        self.cache_control
+            .split(',')
+            .find_map(|directive| directive.trim().strip_prefix("max-age="))
+            .and_then(|secs| secs.parse().ok())
+            .map(Duration::from_secs)
+    }
+}
+
+impl From<Page> for Bytes {
+    fn from(page: Page) -> Bytes {
+        Bytes::from(format!("{}\n{}", page.cache_control, page.body))
+    }
+}
+
+impl From<Bytes> for Page {
+    fn from(bytes: Bytes) -> Page {
+        let text = String::from_utf8_lossy(&bytes);
+        let (cache_control, body) = text.split_once('\n').unwrap_or(("", &text));
+        Page {
+            cache_control: cache_control.to_string(),
+            body: body.to_string(),
+        }
+    }
+}
+
+/// A slow origin that renders a page for every path and counts its calls.
+#[derive(Clone, Default)]
+struct Origin {
+    calls: Arc<AtomicUsize>,
+}
+
+impl Service<String> for Origin {
+    type Response = Page;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Page, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, path: String) -> Self::Future {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        Box::pin(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let cache_control = if path.starts_with("/account") {
+                "private, no-store"
+            } else {
+                "public, max-age=60"
+            };
+            Ok(Page {
+                cache_control: cache_control.to_string(),
+                body: format!("<html>{}</html>", path),
+            })
+        })
+    }
+}
+
+/// Wait for `service` to be ready and send it `request`.
+async fn oneshot<S: Service<R>, R>(mut service: S, request: R) -> Result<S::Response, S::Error> {
+    std::future::poll_fn(|cx| service.poll_ready(cx)).await?;
+    service.call(request).await
+}
+
+#[tokio::main]
+async fn main() {
+    let origin = Origin::default();
+    let origin_calls = Arc::clone(&origin.calls);
+
+    let cache = Cache::default();
+    let layer = CacheLayer::new(
+        cache.clone(),
+        |path: &String| Some(path.clone()),
+        Page::max_age,
+    );
+    let service = layer.layer(origin);
+
+    let requests: Vec<_> = (0..8)
+        .map(|_| tokio::spawn(oneshot(service.clone(), "/index.html".to_string())))
+        .collect();
+    for request in requests {
+        let page = request.await.unwrap().unwrap();
+        assert_eq!(page.body, "<html>/index.html</html>");
+    }
+    println!(
+        "8 concurrent requests for /index.html: {} origin call(s), ttl {:?}",
+        origin_calls.load(Ordering::SeqCst),
+        cache.ttl("/index.html").flatten()
+    );
+
+    for _ in 0..2 {
+        oneshot(service.clone(), "/account".to_string())
+            .await
+            .unwrap();
+    }
+    println!(
+        "2 requests for /account (no-store): {} origin call(s) in total, cached: {}",
+        origin_calls.load(Ordering::SeqCst),
+        cache.contains("/account")
+    );
+}
@@ -0,0 +1,303 @@
+//! Batched writes for high set throughput (feature `tokio`).
+//!
+//! Every [`Cache::set`](crate::Cache::set) takes the write lock, which caps
+//! how fast many producers can write. A [`BatchWriter`] queues sets on a
+//! bounded channel instead. A background task applies them in batches of
+//! up to `max_batch` writes, each batch under a single write-lock
+//! acquisition, in the order they were queued.
+//!
+//! Batching trades visibility for throughput. Once a set's future
+//! completes, the write is queued but not yet visible. It becomes visible
+//! when its batch is applied: at the latest `max_delay` after the first
+//! write of the batch was taken off the queue, plus the time to apply it.
+//! A batch is applied early when it fills up or when
+//! [`BatchWriter::flush`] is called. The flush completes once every write
+//! queued before it is visible.
+//!
+//! ```
+//! use in_memory_cache::Cache;
+//! use std::time::Duration;
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let cache = Cache::default();
+//! let writer = cache.batch_writer(1024, Duration::from_millis(5));
+//! for i in 0..100 {
+//!     writer.set(format!("event:{}", i), "payload").await.unwrap();
+//! }
+//! writer.flush().await.unwrap();
+//! assert_eq!(cache.len(), 100);
+//! # });
+//! ```
+
+use bytes::Bytes;
+use std::borrow::Cow;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{self, Instant};
+
+use crate::error::{CacheError, CacheResult};
+use crate::storage::Db;
+
+/// What a [`BatchWriter`] does when its queue is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Wait until the queue has room.
+    #[default]
+    Wait,
+    /// Fail with [`CacheError::CapacityExceeded`] without queuing the write.
+    Reject,
+}
+
+/// A queued write: key, value and TTL (`None` for the default TTL).
+type Write = (String, Bytes, Option<Duration>);
+
+enum Op {
+    Set(Write),
+    /// Apply the current batch, then signal.
+    Flush(oneshot::Sender<()>),
+}
+
+/// A handle that queues sets for batched application. Clones share the same
+/// queue.
+///
+/// Created by [`Cache::batch_writer`](crate::Cache::batch_writer); see the
+/// [module documentation](self) for when writes become visible. Once every
+/// clone has been dropped, the background task applies whatever is still
+/// queued and ends.
+#[derive(Debug, Clone)]
+pub struct BatchWriter {
+    tx: mpsc::Sender<Op>,
+    capacity: usize,
+    backpressure: Backpressure,
+}
+
+impl BatchWriter {
+    /// Spawn the task that applies batches to `db` on the current Tokio
+    /// runtime. The queue holds up to two batches.
+    pub(crate) fn start(db: Arc<Db>, max_batch: usize, max_delay: Duration) -> Self {
+        let max_batch = max_batch.max(1);
+        let capacity = max_batch.saturating_mul(2);
+        let (tx, rx) = mpsc::channel(capacity);
+        tokio::spawn(drain(db, rx, max_batch, max_delay));
+        Self {
+            tx,
+            capacity,
+            backpressure: Backpressure::default(),
+        }
+    }
+
+    /// Use `backpressure` when the queue is full. Defaults to
+    /// [`Backpressure::Wait`].
+    pub fn backpressure(mut self, backpressure: Backpressure) -> Self {
+        self.backpressure = backpressure;
+        self
+    }
+
+    /// Queue a set with the default TTL.
+    ///
+    /// Fails with [`CacheError::CapacityExceeded`] if the queue is full and
+    /// the handle uses [`Backpressure::Reject`], and with
+    /// [`CacheError::IoError`] if the background task has stopped, e.g.
+    /// because its runtime shut down.
+    pub async fn set<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+    ) -> CacheResult<()> {
+        let write = (key.into().into_owned(), value.into(), None);
+        self.send(Op::Set(write)).await
+    }
+
+    /// Queue a set with `ttl`. Fails like [`BatchWriter::set`].
+    ///
+    /// The TTL counts from when the batch is applied, not from the call.
+    pub async fn set_with_ttl<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+        ttl: Duration,
+    ) -> CacheResult<()> {
+        let write = (key.into().into_owned(), value.into(), Some(ttl));
+        self.send(Op::Set(write)).await
+    }
+
+    /// Wait until every write queued before this call, through any clone,
+    /// is visible in the cache.
+    ///
+    /// Always waits for queue room, whatever the backpressure setting.
+    /// Fails with [`CacheError::IoError`] if the background task has
+    /// stopped.
+    pub async fn flush(&self) -> CacheResult<()> {
+        let (done_tx, done_rx) = oneshot::channel();
+        self.tx
+            .send(Op::Flush(done_tx))
+            .await
+            .map_err(|_| stopped())?;
+        done_rx.await.map_err(|_| stopped())
+    }
+
+    async fn send(&self, op: Op) -> CacheResult<()> {
+        match self.backpressure {
+            Backpressure::Wait => self.tx.send(op).await.map_err(|_| stopped()),
+            Backpressure::Reject => self.tx.try_send(op).map_err(|e| match e {
+                mpsc::error::TrySendError::Full(_) => CacheError::CapacityExceeded {
+                    current: self.capacity,
+                    max: self.capacity,
+                },
+                mpsc::error::TrySendError::Closed(_) => stopped(),
+            }),
+        }
+    }
+}
+
+fn stopped() -> CacheError {
+    CacheError::IoError(io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        "batch writer has stopped",
+    ))
+}
+
+/// Apply queued writes to `db` until every sender is gone.
+async fn drain(db: Arc<Db>, mut rx: mpsc::Receiver<Op>, max_batch: usize, max_delay: Duration) {
+    while let Some(first) = rx.recv().await {
+        // `None` for a delay too long to represent: wait as long as it takes
+        let deadline = Instant::now().checked_add(max_delay);
+        let mut batch = Vec::new();
+        let mut flushes = Vec::new();
+        let mut next = Some(first);
+        while let Some(op) = next.take() {
+            match op {
+                Op::Set(write) => batch.push(write),
+                Op::Flush(done) => {
+                    flushes.push(done);
+                    break;
+                }
+            }
+            if batch.len() >= max_batch {
+                break;
+            }
+            next = match deadline {
+                // Ready writes are taken even once the deadline has passed
+                Some(deadline) => time::timeout_at(deadline, rx.recv()).await.ok().flatten(),
+                None => rx.recv().await,
+            };
+        }
+
+        if !batch.is_empty() {
+            db.set_many(batch);
+        }
+        for done in flushes {
+            let _ = done.send(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cache, CacheConfig};
+
+    #[tokio::test]
+    async fn test_flush_makes_writes_visible() {
+        let cache = Cache::default();
+        let writer = cache.batch_writer(16, Duration::from_secs(60));
+        writer.set("a", "1").await.unwrap();
+        writer
+            .set_with_ttl("b", "2", Duration::from_secs(30))
+            .await
+            .unwrap();
+        // The batch is neither full nor due
+        tokio::task::yield_now().await;
+        assert!(cache.is_empty());
+
+        writer.flush().await.unwrap();
+        assert_eq!(cache.get("a"), Some(Bytes::from("1")));
+        assert!(cache.ttl("b").unwrap().unwrap() <= Duration::from_secs(30));
+        assert_eq!(cache.stats().sets, 2);
+    }
+
+    #[tokio::test]
+    async fn test_batches_apply_at_max_delay_and_in_order() {
+        let cache = Cache::new(CacheConfig::new().default_ttl(Duration::from_secs(60)));
+        let writer = cache.batch_writer(1000, Duration::from_millis(10));
+        for i in 0..10 {
+            writer.set("key", i.to_string()).await.unwrap();
+        }
+        time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(cache.get("key"), Some(Bytes::from("9")));
+        assert!(cache.ttl("key").unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_producers_share_the_queue() {
+        let cache = Cache::default();
+        let writer = cache.batch_writer(64, Duration::from_millis(1));
+        let tasks: Vec<_> = (0..8)
+            .map(|task| {
+                let writer = writer.clone();
+                tokio::spawn(async move {
+                    for i in 0..500 {
+                        writer.set(format!("{}:{}", task, i), "v").await.unwrap();
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        writer.flush().await.unwrap();
+        assert_eq!(cache.len(), 8 * 500);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_reject_when_full() {
+        let cache = Cache::default();
+        // The drain task cannot run until this task yields, so the queue of
+        // two batches of one fills up
+        let writer = cache
+            .batch_writer(1, Duration::from_secs(60))
+            .backpressure(Backpressure::Reject);
+        writer.set("a", "1").await.unwrap();
+        writer.set("b", "2").await.unwrap();
+        match writer.set("c", "3").await {
+            Err(CacheError::CapacityExceeded { current, max }) => {
+                assert_eq!((current, max), (2, 2))
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        writer.flush().await.unwrap();
+        assert_eq!(cache.keys_sorted(), vec!["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn test_dropping_the_writer_applies_the_rest() {
+        let cache = Cache::default();
+        let writer = cache.batch_writer(100, Duration::from_secs(60));
+        writer.set("a", "1").await.unwrap();
+        drop(writer);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while cache.is_empty() && Instant::now() < deadline {
+            time::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(cache.get("a"), Some(Bytes::from("1")));
+    }
+
+    #[test]
+    fn test_stopped_runtime_is_an_error() {
+        let cache = Cache::default();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let writer = runtime.block_on(async { cache.batch_writer(4, Duration::ZERO) });
+        drop(runtime);
+
+        let other = tokio::runtime::Runtime::new().unwrap();
+        let result = other.block_on(writer.set("a", "1"));
+        assert!(
+            matches!(result, Err(CacheError::IoError(_))),
+            "{:?}",
+            result
+        );
+    }
+}
@@ -4,12 +4,19 @@
 
 use bytes::BytesMut;
 use clap::Parser;
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
-};
+use tokio::{io::AsyncWriteExt, net::TcpStream};
 
 use in_memory_cache::cli::{Cli, ClientCommand};
+use in_memory_cache::protocol::{
+    decode_multi_bulk, generate_trace_id, prefix_trace_id, quote, read_reply, strip_trace_id,
+    ProtocolError,
+};
+use std::borrow::Cow;
+
+#[cfg(feature = "tools")]
+use in_memory_cache::cli::ImportFormat;
+#[cfg(feature = "tools")]
+use in_memory_cache::import::{ImportOp, ImportReport, RedisProtoReader};
 
 /// Default server address.
 const DEFAULT_HOST: &str = "127.0.0.1";
@@ -20,6 +27,7 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Cli::parse();
 
     let addr = format!("{}:{}", DEFAULT_HOST, DEFAULT_PORT);
+
     let mut stream = match TcpStream::connect(&addr).await {
         Ok(s) => s,
         Err(e) => {
@@ -29,23 +37,50 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     };
 
+    // Bytes read past the end of a reply
+    let mut pending = BytesMut::new();
+
+    if args.compress {
+        // Replies are decompressed by read_reply from here on
+        send(&mut stream, b"hello compression=lz4", None).await?;
+        checked(&read_reply(&mut stream, &mut pending).await?);
+    }
+
+    let trace_id = args.trace.then(generate_trace_id);
+    if let Some(id) = &trace_id {
+        eprintln!("trace id: {}", id);
+    }
+    let trace = trace_id.as_deref();
+
     match args.command {
-        ClientCommand::Set { key, value } => {
-            // Send: set <key> <value>
-            let cmd = format!("set {} {}", key, value);
-            stream.write_all(cmd.as_bytes()).await?;
-
-            let mut buf = BytesMut::with_capacity(1024);
-            let _ = stream.read_buf(&mut buf).await?;
-
-            match std::str::from_utf8(&buf) {
-                Ok("r Ok") => println!("Updated key '{}'", key),
-                Ok("Ok") => println!("Set key '{}'", key),
-                Ok(resp) if resp.starts_with("ERR") => {
-                    eprintln!("Error: {}", resp);
-                    std::process::exit(1);
-                }
-                Ok(resp) => println!("Response: {}", resp),
+        ClientCommand::Set {
+            key,
+            value,
+            verbose,
+        } => {
+            // Send: set <key> <value> [verbose], quoting either if needed
+            let mut cmd = format!("set {} {}", word(&key), word(&value));
+            if verbose {
+                cmd.push_str(" verbose");
+            }
+            send(&mut stream, cmd.as_bytes(), trace).await?;
+
+            let reply = read_reply(&mut stream, &mut pending).await?;
+            let buf = checked(untraced(&reply));
+
+            // A verbose reply carries the eviction report after the status
+            match std::str::from_utf8(buf) {
+                Ok(resp) => match (resp.strip_prefix("r Ok"), resp.strip_prefix("Ok")) {
+                    (Some(report), _) => {
+                        println!("Updated key '{}'", key);
+                        print_stat_fields(report);
+                    }
+                    (_, Some(report)) => {
+                        println!("Set key '{}'", key);
+                        print_stat_fields(report);
+                    }
+                    _ => println!("Response: {}", resp),
+                },
                 Err(e) => {
                     eprintln!("Failed to parse response: {}", e);
                     std::process::exit(1);
@@ -55,19 +90,40 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
         ClientCommand::Get { key } => {
             // Send: get <key>
-            let cmd = format!("get {}", key);
-            stream.write_all(cmd.as_bytes()).await?;
+            let cmd = format!("get {}", word(&key));
+            send(&mut stream, cmd.as_bytes(), trace).await?;
 
-            let mut buf = BytesMut::with_capacity(1024);
-            let _ = stream.read_buf(&mut buf).await?;
+            let reply = read_reply(&mut stream, &mut pending).await?;
+            let buf = checked(untraced(&reply));
 
-            match std::str::from_utf8(&buf) {
+            match std::str::from_utf8(buf) {
                 Ok("") => println!("Key '{}' not found", key),
-                Ok(resp) if resp.starts_with("ERR") => {
-                    eprintln!("Error: {}", resp);
+                Ok(value) => println!("{}", value),
+                Err(e) => {
+                    eprintln!("Failed to parse response: {}", e);
                     std::process::exit(1);
                 }
-                Ok(value) => println!("{}", value),
+            }
+        }
+
+        ClientCommand::Mget { keys } => {
+            // Send: mget <key> [<key> ...]
+            let words: Vec<_> = keys.iter().map(|key| word(key)).collect();
+            let cmd = format!("mget {}", words.join(" "));
+            send(&mut stream, cmd.as_bytes(), trace).await?;
+
+            let reply = read_reply(&mut stream, &mut pending).await?;
+            let buf = checked(untraced(&reply));
+
+            match decode_multi_bulk(buf) {
+                Ok(values) => {
+                    for (key, value) in keys.iter().zip(values) {
+                        match value {
+                            Some(value) => println!("{}: {}", key, String::from_utf8_lossy(&value)),
+                            None => println!("{}: (nil)", key),
+                        }
+                    }
+                }
                 Err(e) => {
                     eprintln!("Failed to parse response: {}", e);
                     std::process::exit(1);
@@ -77,13 +133,13 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
         ClientCommand::Delete { key } => {
             // Send: delete <key>
-            let cmd = format!("delete {}", key);
-            stream.write_all(cmd.as_bytes()).await?;
+            let cmd = format!("delete {}", word(&key));
+            send(&mut stream, cmd.as_bytes(), trace).await?;
 
-            let mut buf = BytesMut::with_capacity(1024);
-            let _ = stream.read_buf(&mut buf).await?;
+            let reply = read_reply(&mut stream, &mut pending).await?;
+            let buf = checked(untraced(&reply));
 
-            match std::str::from_utf8(&buf) {
+            match std::str::from_utf8(buf) {
                 Ok("Ok") => println!("Deleted key '{}'", key),
                 Ok("") => println!("Key '{}' not found", key),
                 Ok(resp) => println!("Response: {}", resp),
@@ -94,13 +150,54 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
         }
 
+        ClientCommand::Keys { pattern } => {
+            // Send: keys <pattern>
+            let cmd = format!("keys {}", word(&pattern));
+            send(&mut stream, cmd.as_bytes(), trace).await?;
+
+            let reply = read_reply(&mut stream, &mut pending).await?;
+            let buf = checked(untraced(&reply));
+
+            match decode_multi_bulk(buf) {
+                Ok(keys) if keys.is_empty() => println!("No keys match '{}'", pattern),
+                Ok(keys) => {
+                    for key in keys.into_iter().flatten() {
+                        println!("{}", String::from_utf8_lossy(&key));
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Failed to parse response: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        ClientCommand::Ttl { key } => {
+            // Send: ttl <key>
+            let cmd = format!("ttl {}", word(&key));
+            send(&mut stream, cmd.as_bytes(), trace).await?;
+
+            let reply = read_reply(&mut stream, &mut pending).await?;
+            let buf = checked(untraced(&reply));
+
+            match std::str::from_utf8(buf) {
+                Ok("-2") => println!("Key '{}' not found", key),
+                Ok("-1") => println!("Key '{}' has no expiration", key),
+                Ok(secs) => println!("Key '{}' expires in {} seconds", key, secs),
+                Err(e) => {
+                    eprintln!("Failed to parse response: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+
         ClientCommand::Ping => {
-            stream.write_all(b"ping").await?;
+            send(&mut stream, b"ping", trace).await?;
 
-            let mut buf = BytesMut::with_capacity(1024);
-            let _ = stream.read_buf(&mut buf).await?;
+            let reply = read_reply(&mut stream, &mut pending).await?;
+            let buf = checked(untraced(&reply));
 
-            match std::str::from_utf8(&buf) {
+            match std::str::from_utf8(buf) {
                 Ok("PONG") => println!("PONG"),
                 Ok(resp) => println!("Response: {}", resp),
                 Err(e) => {
@@ -110,18 +207,70 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
         }
 
-        ClientCommand::Stats => {
-            stream.write_all(b"stats").await?;
+        #[cfg(feature = "tools")]
+        ClientCommand::Import {
+            format,
+            file,
+            quiet,
+        } => {
+            import(format, &file, quiet, &mut stream).await?;
+        }
+
+        ClientCommand::Stats { prefix, reset } => {
+            let cmd = match (&prefix, reset) {
+                (_, true) => "stats reset".to_string(),
+                (Some(prefix), false) => format!("stats prefix {}", word(prefix)),
+                (None, false) => "stats".to_string(),
+            };
+            send(&mut stream, cmd.as_bytes(), trace).await?;
 
-            let mut buf = BytesMut::with_capacity(1024);
-            let _ = stream.read_buf(&mut buf).await?;
+            let reply = read_reply(&mut stream, &mut pending).await?;
+            let buf = checked(untraced(&reply));
 
-            match std::str::from_utf8(&buf) {
+            match std::str::from_utf8(buf) {
+                Ok(resp) if reset => println!("Response: {}", resp),
                 Ok(resp) => {
+                    let mut lines = resp.lines();
                     println!("Cache Statistics:");
-                    for part in resp.split_whitespace() {
-                        if let Some((key, value)) = part.split_once(':') {
+                    print_stat_fields(lines.next().unwrap_or(""));
+                    // The prefix itself may contain ':', so it is split off
+                    // before the field:value pairs
+                    if let Some((prefix, fields)) = lines
+                        .next()
+                        .and_then(|line| line.strip_prefix("prefix "))
+                        .and_then(|rest| rest.split_once(' '))
+                    {
+                        println!();
+                        println!("Prefix '{}':", prefix);
+                        print_stat_fields(fields);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Failed to parse response: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        ClientCommand::Info { section } => {
+            let cmd = match section {
+                Some(section) => format!("info {}", section),
+                None => "info".to_string(),
+            };
+            send(&mut stream, cmd.as_bytes(), trace).await?;
+
+            let reply = read_reply(&mut stream, &mut pending).await?;
+            let buf = checked(untraced(&reply));
+
+            match std::str::from_utf8(buf) {
+                Ok(resp) => {
+                    for line in resp.lines() {
+                        if let Some(title) = line.strip_prefix("# ") {
+                            println!("{}:", title);
+                        } else if let Some((key, value)) = line.split_once(':') {
                             println!("  {}: {}", key, value);
+                        } else {
+                            println!();
                         }
                     }
                 }
@@ -135,3 +284,130 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+/// Send a request line, prefixed with the trace id if one is set.
+async fn send(stream: &mut TcpStream, request: &[u8], trace: Option<&str>) -> std::io::Result<()> {
+    let mut line = BytesMut::from(&prefix_trace_id(trace, request)[..]);
+    line.extend_from_slice(b"\n");
+    stream.write_all(&line).await
+}
+
+/// `word` as written in a request line, or exit if it contains a line
+/// break, which no request can carry.
+fn word(word: &str) -> Cow<'_, str> {
+    if word.contains(['\n', '\r']) {
+        eprintln!("Error: {:?} contains a line break", word);
+        std::process::exit(1);
+    }
+    quote(word)
+}
+
+/// Strip the server's trace id echo from a reply.
+fn untraced(reply: &[u8]) -> &[u8] {
+    strip_trace_id(reply).1
+}
+
+/// The reply, or exit reporting the error the server sent instead.
+fn checked(reply: &[u8]) -> &[u8] {
+    match ProtocolError::from_wire(reply) {
+        None => reply,
+        Some(e) => {
+            eprintln!("Error ({}): {}", e.code(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Send every importable string key in `file` to the server over
+/// `stream`, with its TTL, and apply the file's expirations. Each record is
+/// counted once: as imported, as an applied expiration or as skipped.
+#[cfg(feature = "tools")]
+async fn import(
+    format: ImportFormat,
+    file: &std::path::Path,
+    quiet: bool,
+    stream: &mut TcpStream,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let ImportFormat::RedisProto = format;
+    let mut pending = BytesMut::new();
+    let reader = std::io::BufReader::new(std::fs::File::open(file)?);
+
+    let mut report = ImportReport::default();
+    for op in RedisProtoReader::new(reader) {
+        let op = match op {
+            Ok(op) => op,
+            Err(e) => {
+                eprintln!("Error: {}: {}", file.display(), e);
+                std::process::exit(1);
+            }
+        };
+        let (key, value, ttl) = match op {
+            ImportOp::Set { key, value, ttl } => (key, value, ttl),
+            ImportOp::Expire { key, ttl } => {
+                if key.contains(['\n', '\r']) {
+                    skip(&mut report, "unsendable key");
+                    continue;
+                }
+                let cmd = format!("expire {} {}", quote(&key), whole_secs(ttl));
+                send(stream, cmd.as_bytes(), None).await?;
+                match checked(&read_reply(stream, &mut pending).await?) {
+                    b"1" => report.expirations += 1,
+                    _ => skip(&mut report, "expire of unknown key"),
+                }
+                continue;
+            }
+            ImportOp::Skip { kind } => {
+                skip(&mut report, &kind);
+                continue;
+            }
+        };
+
+        let value = match std::str::from_utf8(&value) {
+            Ok(value) if !value.contains(['\n', '\r']) => value,
+            _ => {
+                skip(&mut report, "unsendable value");
+                continue;
+            }
+        };
+        if key.contains(['\n', '\r']) {
+            skip(&mut report, "unsendable key");
+            continue;
+        }
+
+        let mut cmd = format!("set {} {}", quote(&key), quote(value));
+        if let Some(ttl) = ttl {
+            cmd.push_str(&format!(" ex {}", whole_secs(ttl)));
+        }
+        if quiet {
+            cmd.push_str(" quiet");
+        }
+        send(stream, cmd.as_bytes(), None).await?;
+        checked(&read_reply(stream, &mut pending).await?);
+        report.imported += 1;
+    }
+
+    println!("{}", report);
+    Ok(())
+}
+
+/// Count a record `client import` could not send.
+#[cfg(feature = "tools")]
+fn skip(report: &mut ImportReport, kind: &str) {
+    *report.skipped.entry(kind.to_string()).or_insert(0) += 1;
+}
+
+/// `ttl` in whole seconds for `ex` and `expire`, rounded up so a key never
+/// expires early, and at least 1, which the server requires.
+#[cfg(feature = "tools")]
+fn whole_secs(ttl: std::time::Duration) -> u64 {
+    (ttl.as_secs() + u64::from(ttl.subsec_nanos() > 0)).max(1)
+}
+
+/// Print whitespace-separated `field:value` pairs, one per line.
+fn print_stat_fields(line: &str) {
+    for part in line.split_whitespace() {
+        if let Some((key, value)) = part.split_once(':') {
+            println!("  {}: {}", key, value);
+        }
+    }
+}
@@ -4,12 +4,32 @@
 //! It wraps the internal storage and provides a clean, thread-safe API.
 
 use bytes::Bytes;
-use std::sync::Arc;
-use std::time::Duration;
+use std::borrow::Cow;
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use crate::cleanup::CleanupTask;
+use crate::clock::Clock;
 use crate::config::CacheConfig;
-use crate::stats::{CacheStats, StatsSnapshot};
+use crate::entry::EntryInfo;
+use crate::error::{CacheError, CacheResult};
+use crate::export;
+#[cfg(feature = "test-util")]
+use crate::fault;
+use crate::frozen::FrozenCache;
+use crate::handle::{CacheReader, CacheWriter};
+use crate::health::{HealthEvent, HealthInput, HealthMonitor, HealthReport, HealthTracker};
+use crate::memory::MemoryBreakdown;
+use crate::ops::SetOutcome;
+use crate::outcome::{self, TryInsertError};
+use crate::ratelimit::RateDecision;
+use crate::scan::ScanCursor;
+use crate::singleflight::{InFlight, LoadError};
+use crate::stats::{CacheStats, PrefixStats, StatsSnapshot};
 use crate::storage::Db;
+use crate::txn::TxnView;
 
 /// A thread-safe, in-memory cache with optional TTL and LRU eviction.
 ///
@@ -49,9 +69,24 @@ use crate::storage::Db;
 pub struct Cache {
     /// Internal storage.
     db: Arc<Db>,
+
+    /// Loads in progress for `get_or_load`, shared by all clones.
+    flights: Arc<InFlight>,
+
+    /// Soft threshold state for `health`, shared by all clones.
+    health: Arc<HealthTracker>,
+
+    /// Expiration sweeps started by `new`, until `start_cleanup` hands them
+    /// out. Shared by all clones, and stopped when the last one is dropped.
+    cleanup: Arc<Mutex<Option<CleanupTask>>>,
 }
 
 impl Cache {
+    /// Bytes of bookkeeping stored per entry on top of its key and value
+    /// (times, checksum and weight, plus the value handle), excluding the map's
+    /// own per-slot overhead.
+    pub const ENTRY_OVERHEAD: usize = crate::entry::ENTRY_OVERHEAD;
+
     /// Create a new cache with the given configuration.
     ///
     /// # Arguments
@@ -64,8 +99,19 @@ impl Cache {
     /// let cache = Cache::new(CacheConfig::default());
     /// ```
     pub fn new(config: CacheConfig) -> Self {
+        let db = Arc::new(Db::new(config));
+        let config = db.config();
+        let cleanup = match config.cleanup_interval {
+            Some(interval) if config.background_cleanup => {
+                Some(CleanupTask::start(&db, interval, config.cleanup_bounds))
+            }
+            _ => None,
+        };
         Self {
-            db: Arc::new(Db::new(config)),
+            health: db.health(),
+            db,
+            flights: Arc::new(InFlight::default()),
+            cleanup: Arc::new(Mutex::new(cleanup)),
         }
     }
 
@@ -93,141 +139,517 @@ impl Cache {
         self.db.get(key)
     }
 
-    /// Set a value in the cache.
+    /// Get a value, reporting a failed integrity check as an error.
     ///
-    /// If a `default_ttl` is configured, entries will use that TTL.
-    /// Otherwise, entries will not expire.
+    /// Behaves like [`Cache::get`], except that with
+    /// [`CacheConfig::checksum_values`] enabled, a value that no longer
+    /// matches its checksum yields [`CacheError::Corrupted`] instead of
+    /// `None`. The corrupted entry is removed either way.
     ///
-    /// # Arguments
-    /// * `key` - The key to store the value under.
-    /// * `value` - The value to store (anything that can be converted to `Bytes`).
+    /// [`CacheError::Corrupted`]: crate::CacheError::Corrupted
     ///
     /// # Example
     /// ```
     /// use in_memory_cache::{Cache, CacheConfig};
     ///
-    /// let cache = Cache::new(CacheConfig::default());
-    /// cache.set("string_key", "string value");
-    /// cache.set("bytes_key", vec![1, 2, 3, 4]);
+    /// let cache = Cache::new(CacheConfig::new().checksum_values(true));
+    /// cache.set("key", "value");
+    /// assert_eq!(cache.try_get("key").unwrap(), Some("value".into()));
+    /// assert_eq!(cache.try_get("missing").unwrap(), None);
     /// ```
-    pub fn set(&self, key: impl Into<String>, value: impl Into<Bytes>) {
-        self.db.set(key, value);
+    pub fn try_get(&self, key: &str) -> CacheResult<Option<Bytes>> {
+        self.db.try_get(key)
     }
 
-    /// Set a value in the cache with a specific TTL.
+    /// Inspect a value in place without copying it.
     ///
-    /// The entry will be removed after the specified duration.
+    /// Calls `f` with a borrow of the stored bytes while the read lock is
+    /// held, and returns its result, or `None` if the key doesn't exist or has
+    /// expired. No `Bytes` clone or copy is made, which makes this the
+    /// cheapest way to parse a small piece (e.g. a header) out of a large
+    /// value. Hit/miss statistics and LRU promotion behave as with
+    /// [`Cache::get`].
     ///
-    /// # Arguments
-    /// * `key` - The key to store the value under.
-    /// * `value` - The value to store.
-    /// * `ttl` - How long the entry should live.
+    /// # Hazards
+    /// - Writers are blocked for as long as `f` runs, so keep it short.
+    /// - `f` must not call back into this cache with an operation that needs
+    ///   the write lock (`get`, `set`, `delete`, ...): the calling thread
+    ///   already holds the read lock and will deadlock. The non-blocking
+    ///   variants are safe but will report the cache as busy.
+    /// - A panic in `f` propagates to the caller; the read lock is released
+    ///   during unwinding and the cache stays usable.
     ///
     /// # Example
     /// ```
     /// use in_memory_cache::{Cache, CacheConfig};
-    /// use std::time::Duration;
     ///
     /// let cache = Cache::new(CacheConfig::default());
-    /// cache.set_with_ttl("session", "data", Duration::from_secs(3600));
+    /// cache.set("blob", vec![3, 0, 0, 0, 42]);
+    ///
+    /// let version = cache.get_ref("blob", |bytes| bytes[0]);
+    /// assert_eq!(version, Some(3));
     /// ```
-    pub fn set_with_ttl(&self, key: impl Into<String>, value: impl Into<Bytes>, ttl: Duration) {
-        self.db.set_with_ttl(key, value, ttl);
+    pub fn get_ref<R>(&self, key: &str, f: impl FnOnce(&[u8]) -> R) -> Option<R> {
+        self.db.get_ref(key, f)
     }
 
-    /// Delete a key from the cache.
-    ///
-    /// Returns `true` if the key existed and was removed.
+    /// Get a value without side effects on the cache.
     ///
-    /// # Arguments
-    /// * `key` - The key to delete.
+    /// Unlike [`Cache::get`], a peek counts neither a hit nor a miss and
+    /// does not mark the entry as recently used, so it can be used for
+    /// inspection without skewing statistics or eviction order.
     ///
     /// # Example
     /// ```
     /// use in_memory_cache::{Cache, CacheConfig};
     ///
-    /// let cache = Cache::new(CacheConfig::default());
-    /// cache.set("key", "value");
-    /// assert!(cache.delete("key"));
-    /// assert!(!cache.delete("key")); // Already deleted
+    /// let cache = Cache::new(CacheConfig::new().max_capacity(2));
+    /// cache.set("a", "1");
+    /// cache.set("b", "2");
+    /// assert_eq!(cache.peek("a"), Some("1".into()));
+    ///
+    /// cache.set("c", "3"); // "a" is still the least recently used
+    /// assert!(!cache.contains("a"));
+    /// assert_eq!(cache.stats().hits, 0);
     /// ```
-    pub fn delete(&self, key: &str) -> bool {
-        self.db.delete(key)
+    pub fn peek(&self, key: &str) -> Option<Bytes> {
+        self.db.peek(key)
     }
 
-    /// Check if a key exists in the cache.
+    /// Mark `key` as most recently used without fetching its value.
     ///
-    /// Returns `false` if the key doesn't exist or has expired.
-    /// Note: This does NOT update the LRU access time.
-    ///
-    /// # Arguments
-    /// * `key` - The key to check.
+    /// Saves a large value from eviction without the cost of a
+    /// [`Cache::get`]. The entry is promoted regardless of
+    /// [`promotion_threshold`](crate::CacheConfig::promotion_threshold).
+    /// Returns whether the key exists and is unexpired; an expired entry is
+    /// removed. Counts neither a hit nor a miss.
     ///
     /// # Example
     /// ```
     /// use in_memory_cache::{Cache, CacheConfig};
     ///
-    /// let cache = Cache::new(CacheConfig::default());
-    /// assert!(!cache.contains("key"));
-    /// cache.set("key", "value");
-    /// assert!(cache.contains("key"));
+    /// let cache = Cache::new(CacheConfig::new().max_capacity(2));
+    /// cache.set("blob", "large value");
+    /// cache.set("other", "x");
+    /// assert!(cache.touch("blob"));
+    ///
+    /// cache.set("new", "y"); // "other" is now the least recently used
+    /// assert!(cache.contains("blob"));
+    /// assert!(!cache.contains("other"));
+    /// assert_eq!(cache.stats().hits, 0);
     /// ```
-    pub fn contains(&self, key: &str) -> bool {
-        self.db.contains(key)
+    pub fn touch(&self, key: &str) -> bool {
+        self.db.touch(key)
     }
 
-    /// Get the number of entries in the cache.
+    /// Remaining time to live of a key.
     ///
-    /// Note: This may include expired entries that haven't been
-    /// cleaned up yet by lazy expiration or background cleanup.
+    /// Returns `None` if the key is missing or expired, and `Some(None)` if
+    /// it never expires. Like [`Cache::peek`], this counts neither a hit
+    /// nor a miss and does not promote the entry. With
+    /// [`CacheConfig::ttl_jitter`] the time left is measured to the
+    /// jittered deadline, so it may exceed the TTL the entry was written
+    /// with.
     ///
     /// # Example
     /// ```
     /// use in_memory_cache::{Cache, CacheConfig};
+    /// use std::time::Duration;
     ///
     /// let cache = Cache::new(CacheConfig::default());
-    /// assert_eq!(cache.len(), 0);
-    /// cache.set("key", "value");
-    /// assert_eq!(cache.len(), 1);
+    /// cache.set_with_ttl("session", "data", Duration::from_secs(60));
+    /// cache.set("config", "x");
+    ///
+    /// assert!(cache.ttl("session").unwrap().unwrap() <= Duration::from_secs(60));
+    /// assert_eq!(cache.ttl("config"), Some(None));
+    /// assert_eq!(cache.ttl("missing"), None);
     /// ```
-    pub fn len(&self) -> usize {
-        self.db.len()
+    pub fn ttl(&self, key: &str) -> Option<Option<Duration>> {
+        self.db.ttl(key)
     }
 
-    /// Check if the cache is empty.
+    /// Metadata of a key: its age, idle time, remaining TTL and size.
+    ///
+    /// Returns `None` if the key is missing or expired. Like
+    /// [`Cache::peek`], this counts neither a hit nor a miss and does not
+    /// promote the entry, so it is safe to use for debugging eviction.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::Cache;
+    /// use std::time::Duration;
+    ///
+    /// let cache = Cache::default();
+    /// cache.set_with_ttl("session", "data", Duration::from_secs(60));
+    ///
+    /// let info = cache.entry_info("session").unwrap();
+    /// assert_eq!(info.size_bytes, 4);
+    /// assert!(info.ttl_remaining.unwrap() <= Duration::from_secs(60));
+    /// assert_eq!(cache.entry_info("missing"), None);
+    /// ```
+    pub fn entry_info(&self, key: &str) -> Option<EntryInfo> {
+        self.db.entry_info(key)
+    }
+
+    /// Get the value for `key`, or store and return the value `init`
+    /// produces if there is none.
+    ///
+    /// The lookup and the insert happen under one write-lock acquisition, so
+    /// concurrent callers for the same key run `init` once and all get its
+    /// value. `init` runs with the lock held and must be cheap; it must not
+    /// call back into this cache. For slow, fallible loads use
+    /// [`Cache::get_or_load`], which runs the loader without the lock.
+    /// With [`CacheConfig::spill_over`], a value large enough to spill is
+    /// written to disk after the lock is released, and is only stored if
+    /// no other write to the key landed meanwhile.
+    ///
+    /// The value is stored with the default TTL. A hit counts as a hit; a
+    /// call that runs `init` counts as a miss and a set. If `init` panics,
+    /// nothing is stored and the panic propagates.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::Cache;
+    /// use bytes::Bytes;
+    ///
+    /// let cache = Cache::default();
+    /// let first = cache.get_or_insert_with("counter", || Bytes::from("0"));
+    /// let second = cache.get_or_insert_with("counter", || Bytes::from("1"));
+    /// assert_eq!((first, second), (Bytes::from("0"), Bytes::from("0")));
+    /// assert_eq!((cache.stats().hits, cache.stats().misses), (1, 1));
+    /// ```
+    pub fn get_or_insert_with(&self, key: &str, init: impl FnOnce() -> Bytes) -> Bytes {
+        self.db.get_or_insert_with(key, None, init)
+    }
+
+    /// Like [`Cache::get_or_insert_with`], storing the value with `ttl`
+    /// instead of the default TTL.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::Cache;
+    /// use bytes::Bytes;
+    /// use std::time::Duration;
+    ///
+    /// let cache = Cache::default();
+    /// let ttl = Duration::from_secs(30);
+    /// cache.get_or_insert_with_ttl("token", ttl, || Bytes::from("abc"));
+    /// assert!(cache.ttl("token").unwrap().unwrap() <= ttl);
+    /// ```
+    pub fn get_or_insert_with_ttl(
+        &self,
+        key: &str,
+        ttl: Duration,
+        init: impl FnOnce() -> Bytes,
+    ) -> Bytes {
+        self.db.get_or_insert_with(key, Some(ttl), init)
+    }
+
+    /// Get the value for `key`, loading and storing it on a miss.
+    ///
+    /// `load` returns the value together with its TTL (`None` uses the
+    /// default TTL), so the TTL can be derived from the loaded data, e.g.
+    /// from an HTTP `Cache-Control` header. Concurrent misses on the same
+    /// key are collapsed: one caller runs its loader and the others wait for
+    /// that value instead of hitting the backend themselves.
+    ///
+    /// If the running loader fails, its caller gets the error and each
+    /// waiter retries with its own loader, so errors are not shared or
+    /// cached. A panicking loader is treated as a failure for the waiters,
+    /// and so is a load cancelled with [`Cache::cancel_in_flight`]. Waiters
+    /// wait as long as the load takes; use [`Cache::get_or_load_within`] to
+    /// bound the wait.
     ///
     /// # Example
     /// ```
     /// use in_memory_cache::{Cache, CacheConfig};
+    /// use bytes::Bytes;
+    /// use std::time::Duration;
     ///
     /// let cache = Cache::new(CacheConfig::default());
-    /// assert!(cache.is_empty());
-    /// cache.set("key", "value");
-    /// assert!(!cache.is_empty());
+    /// let page = cache.get_or_load("/index.html", || {
+    ///     // Fetch from the origin and honour its max-age
+    ///     Ok::<_, std::io::Error>((Bytes::from("<html/>"), Some(Duration::from_secs(60))))
+    /// });
+    /// assert_eq!(page.unwrap(), Bytes::from("<html/>"));
+    /// assert!(cache.contains("/index.html"));
     /// ```
-    pub fn is_empty(&self) -> bool {
-        self.db.is_empty()
+    pub fn get_or_load<E>(
+        &self,
+        key: &str,
+        load: impl FnOnce() -> Result<(Bytes, Option<Duration>), E>,
+    ) -> Result<Bytes, E> {
+        self.load_through(key, None, load).map_err(|e| match e {
+            LoadError::Failed(e) => e,
+            // Only waits with a deadline end without a value of their own
+            LoadError::TimedOut | LoadError::Cancelled => unreachable!(),
+        })
     }
 
-    /// Remove all entries from the cache.
+    /// Like [`Cache::get_or_load`], but wait at most `wait` for a load
+    /// another caller is running.
+    ///
+    /// A caller still waiting after `wait` fails with
+    /// [`CacheError::Timeout`] and counts in
+    /// [`StatsSnapshot::loader_timeouts`]; the running load carries on. If
+    /// the load is cancelled with [`Cache::cancel_in_flight`], its waiters
+    /// fail with [`CacheError::LoadCancelled`] right away. A caller that
+    /// runs its own loader is not bounded by `wait`.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::{Cache, CacheError};
+    /// use bytes::Bytes;
+    /// use std::time::Duration;
+    ///
+    /// let cache = Cache::default();
+    /// let value = cache.get_or_load_within("config", Duration::from_secs(1), || {
+    ///     Ok::<_, CacheError>((Bytes::from("v1"), None))
+    /// });
+    /// assert_eq!(value.unwrap(), Bytes::from("v1"));
+    /// ```
+    pub fn get_or_load_within<E: From<CacheError>>(
+        &self,
+        key: &str,
+        wait: Duration,
+        load: impl FnOnce() -> Result<(Bytes, Option<Duration>), E>,
+    ) -> Result<Bytes, E> {
+        let deadline = Instant::now().checked_add(wait);
+        self.load_through(key, deadline, load).map_err(|e| match e {
+            LoadError::Failed(e) => e,
+            LoadError::TimedOut => {
+                self.db.stats().record_loader_timeout();
+                CacheError::Timeout(format!("waiting for the load of '{}'", key)).into()
+            }
+            LoadError::Cancelled => CacheError::LoadCancelled {
+                key: key.to_string(),
+            }
+            .into(),
+        })
+    }
+
+    /// Get the value for `key`, loading it with a fallible loader on a miss.
+    ///
+    /// A loaded value is stored with the default TTL and concurrent misses
+    /// are collapsed as in [`Cache::get_or_load`]. A loader failure is
+    /// returned as [`TryInsertError::Loader`]. With
+    /// [`CacheConfig::error_ttl`] set, it is also cached as a failure
+    /// outcome holding the error's `Display` text (see [`crate::outcome`]).
+    /// Until that expires, calls get [`TryInsertError::Cached`] without
+    /// running their loader, counted in [`StatsSnapshot::error_hits`].
+    /// Outcomes stored with [`Cache::set_outcome`] are served the same way.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::{Cache, CacheConfig, TryInsertError};
+    /// use bytes::Bytes;
+    /// use std::time::Duration;
+    ///
+    /// let cache = Cache::new(CacheConfig::new().error_ttl(Duration::from_secs(5)));
+    /// let first = cache.get_or_try_insert_with("rates", || Err::<Bytes, _>("upstream 503"));
+    /// assert_eq!(first, Err(TryInsertError::Loader("upstream 503")));
+    ///
+    /// // The failure is served from the cache until it expires
+    /// let second = cache.get_or_try_insert_with("rates", || Ok::<_, &str>(Bytes::from("1.08")));
+    /// assert_eq!(second, Err(TryInsertError::Cached(Bytes::from("upstream 503"))));
+    /// ```
+    pub fn get_or_try_insert_with<E: std::fmt::Display>(
+        &self,
+        key: &str,
+        load: impl FnOnce() -> Result<Bytes, E>,
+    ) -> Result<Bytes, TryInsertError<E>> {
+        if let Some(value) = self.get(key) {
+            return self.serve_outcome(value);
+        }
+        let loaded = self.flights.load(key, None, || {
+            // A caller retrying after a failed load may find it cached
+            if let Some(value) = self.peek(key) {
+                return self.serve_outcome(value);
+            }
+            match load() {
+                Ok(value) => {
+                    self.set(key, value.clone());
+                    Ok(value)
+                }
+                Err(err) => {
+                    if let Some(ttl) = self.db.config().error_ttl {
+                        let failure = Err(Bytes::from(err.to_string()));
+                        self.set_with_ttl(key, outcome::encode(&failure), ttl);
+                    }
+                    Err(TryInsertError::Loader(err))
+                }
+            }
+        });
+        loaded.map_err(|e| match e {
+            LoadError::Failed(e) => e,
+            // Only waits with a deadline end without a value of their own
+            LoadError::TimedOut | LoadError::Cancelled => unreachable!(),
+        })
+    }
+
+    /// A stored value as served by `get_or_try_insert_with`: outcomes are
+    /// unpacked, other values returned as they are.
+    fn serve_outcome<E>(&self, value: Bytes) -> Result<Bytes, TryInsertError<E>> {
+        match outcome::decode(&value) {
+            Some(Ok(payload)) => Ok(payload),
+            Some(Err(payload)) => {
+                self.db.stats().record_error_hit();
+                Err(TryInsertError::Cached(payload))
+            }
+            None => Ok(value),
+        }
+    }
+
+    /// Store the outcome of a fallible call: a success payload for `ok_ttl`
+    /// or a failure payload for `err_ttl`.
+    ///
+    /// The outcome is stored as an ordinary value with a length-prefixed
+    /// tag, described in [`crate::outcome`], so payloads may hold any bytes.
+    /// Read it back with [`Cache::get_outcome`].
+    pub fn set_outcome<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        outcome: Result<Bytes, Bytes>,
+        ok_ttl: Duration,
+        err_ttl: Duration,
+    ) {
+        let ttl = if outcome.is_ok() { ok_ttl } else { err_ttl };
+        self.db.set_with_ttl(key, outcome::encode(&outcome), ttl);
+    }
+
+    /// Get an outcome stored with [`Cache::set_outcome`] or cached by
+    /// [`Cache::get_or_try_insert_with`].
+    ///
+    /// Returns `None` for a missing key and for a value that is not an
+    /// outcome. Serving a failure counts in [`StatsSnapshot::error_hits`]
+    /// as well as in the hits.
+    pub fn get_outcome(&self, key: &str) -> Option<Result<Bytes, Bytes>> {
+        let outcome = outcome::decode(&self.get(key)?)?;
+        if outcome.is_err() {
+            self.db.stats().record_error_hit();
+        }
+        Some(outcome)
+    }
+
+    /// Keys currently being loaded through [`Cache::get_or_load`] or
+    /// [`Cache::get_or_load_within`], with how long each load has been
+    /// running, longest first.
+    ///
+    /// A load that stays in this list is stuck; see
+    /// [`Cache::cancel_in_flight`].
+    pub fn in_flight(&self) -> Vec<(String, Duration)> {
+        self.flights.snapshot()
+    }
+
+    /// Cancel the load in flight for `key`, returning `false` if there is
+    /// none.
+    ///
+    /// The key is freed at once, so the next miss starts a new load.
+    /// Waiters in [`Cache::get_or_load_within`] fail with
+    /// [`CacheError::LoadCancelled`] and waiters in [`Cache::get_or_load`]
+    /// retry with their own loaders. The cancelled loader is not
+    /// interrupted: it runs to completion, and a value it returns is still
+    /// stored and returned to its caller.
+    pub fn cancel_in_flight(&self, key: &str) -> bool {
+        self.flights.cancel(key)
+    }
+
+    /// Serve `key` from the cache or through a collapsed load, storing the
+    /// loaded value.
+    fn load_through<E>(
+        &self,
+        key: &str,
+        deadline: Option<Instant>,
+        load: impl FnOnce() -> Result<(Bytes, Option<Duration>), E>,
+    ) -> Result<Bytes, LoadError<E>> {
+        if let Some(value) = self.get(key) {
+            return Ok(value);
+        }
+        self.flights.load(key, deadline, || {
+            let (value, ttl) = load()?;
+            match ttl {
+                Some(ttl) => self.set_with_ttl(key, value.clone(), ttl),
+                None => self.set(key, value.clone()),
+            }
+            Ok(value)
+        })
+    }
+
+    /// Get several values at once.
+    ///
+    /// Values are returned in the order of `keys`, with `None` for missing or
+    /// expired keys. All lookups happen under a single lock acquisition.
+    pub fn multi_get(&self, keys: &[&str]) -> Vec<Option<Bytes>> {
+        self.db.multi_get(keys)
+    }
+
+    /// Get several values at once, from any collection of keys.
+    ///
+    /// Like [`Cache::multi_get`], the lookups, the removal of expired
+    /// entries and the LRU promotions all happen under a single lock
+    /// acquisition, and every key counts as a hit or a miss. Values are
+    /// returned in the order of `keys`.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::Cache;
+    /// use bytes::Bytes;
+    ///
+    /// let cache = Cache::default();
+    /// cache.set("user:1", "alice");
+    /// let ids = [1, 2];
+    /// let values = cache.get_many(ids.iter().map(|id| format!("user:{}", id)));
+    /// assert_eq!(values, vec![Some(Bytes::from("alice")), None]);
+    /// ```
+    pub fn get_many<I>(&self, keys: I) -> Vec<Option<Bytes>>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let keys: Vec<I::Item> = keys.into_iter().collect();
+        let keys: Vec<&str> = keys.iter().map(AsRef::as_ref).collect();
+        self.db.multi_get(&keys)
+    }
+
+    /// Get several values and slide the TTL of every hit.
+    ///
+    /// Behaves like [`Cache::multi_get`], and when `extend_by` is `Some`, each
+    /// hit that has a TTL is kept alive for at least `extend_by` from now
+    /// (a later existing deadline is left alone). Entries stored without a
+    /// TTL stay without one. This is meant for sliding sessions, where every
+    /// validated session should also be renewed in the same pass.
     ///
     /// # Example
     /// ```
     /// use in_memory_cache::{Cache, CacheConfig};
+    /// use std::time::Duration;
     ///
     /// let cache = Cache::new(CacheConfig::default());
-    /// cache.set("key1", "value1");
-    /// cache.set("key2", "value2");
-    /// cache.clear();
-    /// assert!(cache.is_empty());
+    /// cache.set_with_ttl("session:1", "alice", Duration::from_secs(5));
+    ///
+    /// let values = cache.multi_get_and_touch(
+    ///     &["session:1", "session:2"],
+    ///     Some(Duration::from_secs(1800)),
+    /// );
+    /// assert_eq!(values, vec![Some("alice".into()), None]);
     /// ```
-    pub fn clear(&self) {
-        self.db.clear();
+    pub fn multi_get_and_touch(
+        &self,
+        keys: &[&str],
+        extend_by: Option<Duration>,
+    ) -> Vec<Option<Bytes>> {
+        self.db.multi_get_and_touch(keys, extend_by)
     }
 
-    /// Get a snapshot of the cache statistics.
+    /// Get a value without waiting for the lock.
     ///
-    /// Returns a point-in-time snapshot of hits, misses, evictions, etc.
+    /// This is an explicit opt-out from blocking semantics for
+    /// latency-sensitive callers: if another thread currently holds the
+    /// write lock, the call returns immediately with `None`, meaning "cache
+    /// busy, treat as a bypass". Otherwise it returns `Some` with the same
+    /// result `get` would have produced. A promotion that needs the write lock
+    /// (see [`refresh_ttl_on_access`](crate::CacheConfig::refresh_ttl_on_access))
+    /// is skipped rather than waited for when the lock is contended.
     ///
     /// # Example
     /// ```
@@ -235,92 +657,3214 @@ impl Cache {
     ///
     /// let cache = Cache::new(CacheConfig::default());
     /// cache.set("key", "value");
-    /// let _ = cache.get("key");        // Hit
-    /// let _ = cache.get("missing");    // Miss
     ///
-    /// let stats = cache.stats();
-    /// println!("Hits: {}, Misses: {}", stats.hits, stats.misses);
+    /// match cache.get_nonblocking("key") {
+    ///     Some(Some(value)) => println!("Hit: {:?}", value),
+    ///     Some(None) => println!("Miss"),
+    ///     None => println!("Cache busy, going to the source"),
+    /// }
     /// ```
-    pub fn stats(&self) -> StatsSnapshot {
-        self.db.stats().snapshot()
+    pub fn get_nonblocking(&self, key: &str) -> Option<Option<Bytes>> {
+        self.db.get_nonblocking(key)
     }
 
-    /// Manually trigger cleanup of expired entries.
+    /// Set a value in the cache.
     ///
-    /// Returns the number of entries that were removed.
-    /// This is useful if you want to control when cleanup happens
-    /// instead of relying on lazy expiration or background cleanup.
+    /// If a `default_ttl` is configured, entries will use that TTL.
+    /// Otherwise, entries will not expire.
+    ///
+    /// # Arguments
+    /// * `key` - The key to store the value under: a `&str`, `&String` or
+    ///   `String`. Overwriting an existing key with a borrowed key does not
+    ///   allocate a new `String`.
+    /// * `value` - The value to store (anything that can be converted to `Bytes`).
     ///
     /// # Example
     /// ```
     /// use in_memory_cache::{Cache, CacheConfig};
-    /// use std::time::Duration;
     ///
     /// let cache = Cache::new(CacheConfig::default());
-    /// cache.set_with_ttl("key", "value", Duration::from_millis(1));
-    /// std::thread::sleep(Duration::from_millis(10));
-    /// let removed = cache.cleanup_expired();
-    /// println!("Removed {} expired entries", removed);
+    /// cache.set("string_key", "string value");
+    /// cache.set("bytes_key", vec![1, 2, 3, 4]);
     /// ```
-    pub fn cleanup_expired(&self) -> usize {
-        self.db.cleanup_expired()
+    pub fn set<'k>(&self, key: impl Into<Cow<'k, str>>, value: impl Into<Bytes>) {
+        self.db.set(key, value);
     }
 
-    /// Get a reference to the internal statistics counter.
+    /// Set a value, reporting a key or value over its size limit.
     ///
-    /// This is useful for integrating with external metrics systems.
-    pub fn stats_ref(&self) -> Arc<CacheStats> {
-        self.db.stats()
+    /// Behaves like [`Cache::set`], except that a key longer than
+    /// [`CacheConfig::max_key_length`] or a value larger than
+    /// [`CacheConfig::max_value_size`] yields [`CacheError::InvalidKey`] or
+    /// [`CacheError::InvalidValue`]. Either way the write is not stored and
+    /// counts in `rejected_sets`.
+    ///
+    /// [`CacheError::InvalidKey`]: crate::CacheError::InvalidKey
+    /// [`CacheError::InvalidValue`]: crate::CacheError::InvalidValue
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::{Cache, CacheConfig, CacheError};
+    ///
+    /// let cache = Cache::new(CacheConfig::new().max_value_size(4));
+    /// assert!(cache.try_set("key", "tiny").is_ok());
+    /// assert!(matches!(
+    ///     cache.try_set("key", "too large"),
+    ///     Err(CacheError::InvalidValue(_))
+    /// ));
+    /// assert_eq!(cache.get("key"), Some("tiny".into()));
+    /// ```
+    pub fn try_set<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+    ) -> CacheResult<()> {
+        self.db.try_set(key, value)
     }
-}
 
-impl Default for Cache {
-    fn default() -> Self {
-        Self::new(CacheConfig::default())
+    /// [`Cache::set_with_ttl`], reporting size limits like
+    /// [`Cache::try_set`].
+    pub fn try_set_with_ttl<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+        ttl: Duration,
+    ) -> CacheResult<()> {
+        self.db.try_set_with_ttl(key, value, ttl)
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_cache_basic_operations() {
-        let cache = Cache::default();
 
-        cache.set("key", "value");
-        assert_eq!(cache.get("key"), Some(Bytes::from("value")));
-        assert!(cache.contains("key"));
-
-        cache.delete("key");
-        assert!(!cache.contains("key"));
+    /// [`Cache::try_set`] with `ttl` (`None` for the default TTL),
+    /// reporting what the write did and the entries and bytes it evicted,
+    /// for the server. Without `notify_replaced` the listener is not told
+    /// about a value the write replaces.
+    #[cfg(feature = "server")]
+    pub(crate) fn try_set_returning_outcome<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+        ttl: Option<Duration>,
+        notify_replaced: bool,
+    ) -> CacheResult<(SetOutcome, u64, u64)> {
+        self.db
+            .try_set_returning_outcome(key, value, ttl, notify_replaced)
     }
 
-    #[test]
-    fn test_cache_is_clone() {
-        let cache1 = Cache::default();
-        cache1.set("key", "value");
+    /// The configuration the cache was built with, for the server.
+    #[cfg(feature = "server")]
+    pub(crate) fn config(&self) -> &CacheConfig {
+        self.db.config()
+    }
 
-        let cache2 = cache1.clone();
+    /// The clock the cache reads its time from.
+    pub(crate) fn clock(&self) -> Arc<dyn Clock> {
+        Arc::clone(&self.db.config().clock)
+    }
 
-        // Both point to the same underlying data
-        assert_eq!(cache2.get("key"), Some(Bytes::from("value")));
+    /// Check a write against the key and value size limits, counting a
+    /// rejected one in `rejected_sets`.
+    #[cfg(feature = "server")]
+    pub(crate) fn check_size(&self, key: &str, value: &[u8]) -> CacheResult<()> {
+        self.db.check_size(key, value)
+    }
 
-        cache2.set("key2", "value2");
-        assert_eq!(cache1.get("key2"), Some(Bytes::from("value2")));
+    /// Set a value without waiting for the lock.
+    ///
+    /// Returns `true` if the value was stored. If another thread currently
+    /// holds the lock, the write is dropped, the `dropped_sets` statistic is
+    /// incremented and `false` is returned. The `default_ttl` applies as with
+    /// [`Cache::set`].
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::{Cache, CacheConfig};
+    ///
+    /// let cache = Cache::new(CacheConfig::default());
+    /// if !cache.set_nonblocking("key", "value") {
+    ///     println!("Cache busy, write skipped");
+    /// }
+    /// ```
+    pub fn set_nonblocking<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+    ) -> bool {
+        self.db.set_nonblocking(key, value)
     }
 
-    #[test]
-    fn test_cache_stats() {
-        let cache = Cache::default();
+    /// Set a value in the cache with a specific TTL.
+    ///
+    /// The entry will be removed after the specified duration, randomized
+    /// by [`CacheConfig::ttl_jitter`] if that is set.
+    ///
+    /// # Arguments
+    /// * `key` - The key to store the value under.
+    /// * `value` - The value to store.
+    /// * `ttl` - How long the entry should live.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::{Cache, CacheConfig};
+    /// use std::time::Duration;
+    ///
+    /// let cache = Cache::new(CacheConfig::default());
+    /// cache.set_with_ttl("session", "data", Duration::from_secs(3600));
+    /// ```
+    pub fn set_with_ttl<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+        ttl: Duration,
+    ) {
+        self.db.set_with_ttl(key, value, ttl);
+    }
+
+    /// Set a value that expires exactly `ttl` from now, even if the cache
+    /// has a [`CacheConfig::ttl_jitter`].
+    ///
+    /// For entries whose lifetime is a contract rather than a freshness
+    /// hint, such as locks or one-time tokens.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::{Cache, CacheConfig};
+    /// use std::time::Duration;
+    ///
+    /// let cache = Cache::new(CacheConfig::new().ttl_jitter(0.5));
+    /// cache.set_with_exact_ttl("otp:42", "913204", Duration::from_secs(60));
+    /// assert!(cache.ttl("otp:42").unwrap().unwrap() <= Duration::from_secs(60));
+    /// ```
+    pub fn set_with_exact_ttl<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+        ttl: Duration,
+    ) {
+        self.db.set_with_exact_ttl(key, value, ttl);
+    }
+
+    /// Set a value and report what the write did.
+    ///
+    /// Behaves exactly like [`Cache::set`]; the returned [`SetOutcome`] says
+    /// whether the key was new, replaced a live value, was coalesced with an
+    /// identical value, or was dropped. It is decided under the same lock
+    /// as the write, so unlike checking [`Cache::contains`] first it cannot
+    /// race with other writers.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::{Cache, SetOutcome};
+    ///
+    /// let cache = Cache::default();
+    /// assert_eq!(cache.set_returning_outcome("key", "a"), SetOutcome::Inserted);
+    /// assert_eq!(cache.set_returning_outcome("key", "b"), SetOutcome::Replaced);
+    /// ```
+    pub fn set_returning_outcome<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+    ) -> SetOutcome {
+        self.db.set_returning_outcome(key, value)
+    }
+
+    /// Set a value and return the value it displaced.
+    ///
+    /// Behaves exactly like [`Cache::set`], returning the previous live
+    /// value, or `None` if the key was absent. An expired previous value is
+    /// returned as `None` and counted as an expiration. The previous value
+    /// is taken under the same lock as the write, so of several racing
+    /// writers each sees the value the one before it stored.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::Cache;
+    /// use bytes::Bytes;
+    ///
+    /// let cache = Cache::default();
+    /// assert_eq!(cache.set_returning("key", "a"), None);
+    /// assert_eq!(cache.set_returning("key", "b"), Some(Bytes::from("a")));
+    /// ```
+    pub fn set_returning<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+    ) -> Option<Bytes> {
+        self.db.set_returning(key, value)
+    }
+
+    /// [`Cache::set_with_ttl`], returning the value it displaced as
+    /// [`Cache::set_returning`] does.
+    pub fn set_with_ttl_returning<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+        ttl: Duration,
+    ) -> Option<Bytes> {
+        self.db.set_with_ttl_returning(key, value, ttl)
+    }
+
+    /// [`Cache::set_with_ttl`], reporting what the write did as
+    /// [`Cache::set_returning_outcome`] does.
+    pub fn set_with_ttl_returning_outcome<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+        ttl: Duration,
+    ) -> SetOutcome {
+        self.db.set_with_ttl_returning_outcome(key, value, ttl)
+    }
+
+    /// Set a value only if `key` is absent, with the default TTL.
+    ///
+    /// Returns `true` if the value was inserted and `false` if a live value
+    /// was already stored, which is left untouched. An expired entry counts
+    /// as absent. The check and the insert happen under one write lock, so
+    /// of several racing callers exactly one succeeds, which makes this
+    /// usable as a simple in-process lock.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::Cache;
+    /// use bytes::Bytes;
+    ///
+    /// let cache = Cache::default();
+    /// assert!(cache.set_if_absent("lock:job", "worker-1"));
+    /// assert!(!cache.set_if_absent("lock:job", "worker-2"));
+    /// assert_eq!(cache.get("lock:job"), Some(Bytes::from("worker-1")));
+    /// ```
+    pub fn set_if_absent<'k>(&self, key: impl Into<Cow<'k, str>>, value: impl Into<Bytes>) -> bool {
+        self.db.set_if_absent(key.into(), value.into(), None)
+    }
+
+    /// [`Cache::set_if_absent`] with `ttl` instead of the default TTL, e.g.
+    /// for a lock that frees itself if its holder goes away.
+    ///
+    /// A live value is left as it is, including its TTL.
+    pub fn set_if_absent_with_ttl<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+        ttl: Duration,
+    ) -> bool {
+        self.db.set_if_absent(key.into(), value.into(), Some(ttl))
+    }
+
+    /// Replace the value of `key` with `new` only if it currently equals
+    /// `expected`.
+    ///
+    /// The comparison and the write happen under one write lock, so a
+    /// read-modify-write loop built on it needs no outside mutex: read the
+    /// value, compute the new one, and retry from the read if the swap
+    /// reports `Ok(false)` because another writer got there first. On
+    /// success the entry keeps its TTL.
+    ///
+    /// Fails with [`CacheError::KeyNotFound`] if the key is missing or
+    /// expired, and with [`CacheError::Corrupted`] if its value fails
+    /// checksum verification.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::Cache;
+    ///
+    /// let cache = Cache::default();
+    /// cache.set("visits", "1");
+    /// loop {
+    ///     let current = cache.get("visits").unwrap();
+    ///     let next: u64 = std::str::from_utf8(&current).unwrap().parse::<u64>().unwrap() + 1;
+    ///     if cache.compare_and_swap("visits", &current, next.to_string()).unwrap() {
+    ///         break;
+    ///     }
+    /// }
+    /// assert_eq!(cache.get("visits").unwrap(), "2");
+    /// ```
+    pub fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: &[u8],
+        new: impl Into<Bytes>,
+    ) -> CacheResult<bool> {
+        self.db.compare_and_swap(key, expected, new.into())
+    }
+
+    /// Set many values at once, with the default TTL.
+    ///
+    /// The writes are applied in order under a single write-lock
+    /// acquisition, which makes bulk loads, e.g. warming the cache at
+    /// startup, much faster than a loop of [`Cache::set`]. Each write
+    /// otherwise behaves like [`Cache::set`]: a later write to the same key
+    /// wins, and when the batch overflows `max_capacity` the least recently
+    /// used entries are evicted, earlier writes of the batch before later
+    /// ones.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::Cache;
+    /// use bytes::Bytes;
+    ///
+    /// let cache = Cache::default();
+    /// cache.set_many((0..100).map(|i| (format!("key:{}", i), Bytes::from(i.to_string()))));
+    /// assert_eq!(cache.len(), 100);
+    /// assert_eq!(cache.stats().sets, 100);
+    /// ```
+    pub fn set_many(&self, pairs: impl IntoIterator<Item = (String, Bytes)>) {
+        let writes = pairs
+            .into_iter()
+            .map(|(key, value)| (key, value, None))
+            .collect();
+        self.db.set_many(writes);
+    }
+
+    /// [`Cache::set_many`] with a TTL for each write.
+    pub fn set_many_with_ttl(&self, writes: impl IntoIterator<Item = (String, Bytes, Duration)>) {
+        let writes = writes
+            .into_iter()
+            .map(|(key, value, ttl)| (key, value, Some(ttl)))
+            .collect();
+        self.db.set_many(writes);
+    }
+
+    /// Delete a key from the cache.
+    ///
+    /// Returns `true` if the key existed and was removed. The removed value
+    /// is reported to the eviction listener as
+    /// [`RemovalCause::Deleted`](crate::RemovalCause::Deleted).
+    ///
+    /// # Arguments
+    /// * `key` - The key to delete.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::{Cache, CacheConfig};
+    ///
+    /// let cache = Cache::new(CacheConfig::default());
+    /// cache.set("key", "value");
+    /// assert!(cache.delete("key"));
+    /// assert!(!cache.delete("key")); // Already deleted
+    /// ```
+    pub fn delete(&self, key: &str) -> bool {
+        self.db.delete(key)
+    }
+
+    /// Remove `key` and return its value, as one atomic step.
+    ///
+    /// Of several callers racing to take the same key, exactly one gets the
+    /// value, which makes this suitable for one-shot tokens and job claims
+    /// where a [`Cache::get`] followed by a [`Cache::delete`] would let two
+    /// callers see the value. A taken value counts as a hit and a delete.
+    /// An expired entry is removed, counted as an expiration, and reads as
+    /// missing.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::Cache;
+    /// use bytes::Bytes;
+    ///
+    /// let cache = Cache::default();
+    /// cache.set("reset:abc123", "user:42");
+    /// assert_eq!(cache.take("reset:abc123"), Some(Bytes::from("user:42")));
+    /// assert_eq!(cache.take("reset:abc123"), None);
+    /// ```
+    pub fn take(&self, key: &str) -> Option<Bytes> {
+        self.db.take(key)
+    }
+
+    /// Delete several keys at once, returning how many of them existed.
+    ///
+    /// All keys are removed under a single write-lock acquisition, and each
+    /// removed key counts as one delete. A key listed twice is only counted
+    /// once.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::Cache;
+    ///
+    /// let cache = Cache::default();
+    /// cache.set("user:1", "alice");
+    /// cache.set("user:1:posts", "[]");
+    /// assert_eq!(cache.delete_many(&["user:1", "user:1:posts", "user:1:likes"]), 2);
+    /// assert!(cache.is_empty());
+    /// ```
+    pub fn delete_many<K: AsRef<str>>(&self, keys: &[K]) -> usize {
+        self.db.delete_many(keys)
+    }
+
+    /// Delete every key starting with `prefix`, returning how many were
+    /// removed.
+    ///
+    /// This scans the whole cache under one write-lock acquisition, without
+    /// collecting the keys first. Each removed key counts as one delete and,
+    /// like [`Cache::delete`], is reported to the eviction listener as
+    /// [`RemovalCause::Deleted`](crate::RemovalCause::Deleted). An empty
+    /// prefix removes everything.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::Cache;
+    ///
+    /// let cache = Cache::default();
+    /// cache.set("user:123:profile", "alice");
+    /// cache.set("user:123:settings", "{}");
+    /// cache.set("user:1234:profile", "bob");
+    /// assert_eq!(cache.delete_prefix("user:123:"), 2);
+    /// assert_eq!(cache.keys(), vec!["user:1234:profile"]);
+    /// ```
+    pub fn delete_prefix(&self, prefix: &str) -> usize {
+        self.db.delete_prefix(prefix)
+    }
+
+    /// Read and write several keys as one atomic step.
+    ///
+    /// `f` gets a [`TxnView`] limited to `keys`; its writes are buffered
+    /// and applied together once it returns `Ok`, so no other operation
+    /// ever sees some of them without the others. If `f` returns an error
+    /// or panics (reported as [`CacheError::CallbackPanic`]), nothing is
+    /// applied. Capacity eviction caused by the transaction only picks
+    /// entries outside `keys`; declaring more keys than `max_capacity` (or,
+    /// with [`shards`](crate::CacheConfig::shards), than one shard's part of
+    /// it), or writing more key and value bytes than `max_memory`, fails
+    /// with [`CacheError::CapacityExceeded`].
+    ///
+    /// Reads inside the transaction do not count as hits or misses and do
+    /// not promote entries. Deletes notify the eviction listener once the
+    /// transaction is applied, as with [`Cache::delete`].
+    ///
+    /// # Hazards
+    /// - `f` runs while the write lock is held: every other operation waits
+    ///   for it, so keep it short.
+    /// - `f` must not call back into this cache (other than through the
+    ///   view); the calling thread already holds the write lock and will
+    ///   deadlock.
+    ///
+    /// [`CacheError::CallbackPanic`]: crate::CacheError::CallbackPanic
+    /// [`CacheError::CapacityExceeded`]: crate::CacheError::CapacityExceeded
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::{Cache, CacheConfig, CacheError};
+    ///
+    /// let cache = Cache::new(CacheConfig::default());
+    /// cache.set("hold:42", "seat 7A");
+    ///
+    /// // Move the reservation without a window where neither key exists
+    /// cache
+    ///     .transaction(&["hold:42", "confirmed:42"], |txn| {
+    ///         let seat = txn
+    ///             .get("hold:42")?
+    ///             .ok_or_else(|| CacheError::KeyNotFound("hold:42".to_string()))?;
+    ///         txn.delete("hold:42")?;
+    ///         txn.set("confirmed:42", seat)
+    ///     })
+    ///     .unwrap();
+    /// assert!(!cache.contains("hold:42"));
+    /// assert_eq!(cache.get("confirmed:42"), Some("seat 7A".into()));
+    /// ```
+    pub fn transaction(
+        &self,
+        keys: &[&str],
+        f: impl FnOnce(&mut TxnView<'_>) -> CacheResult<()>,
+    ) -> CacheResult<()> {
+        self.db.transaction(keys, f)
+    }
+
+    /// Check if a key exists in the cache.
+    ///
+    /// Returns `false` if the key doesn't exist or has expired.
+    /// Note: This does NOT update the LRU access time.
+    ///
+    /// # Arguments
+    /// * `key` - The key to check.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::{Cache, CacheConfig};
+    ///
+    /// let cache = Cache::new(CacheConfig::default());
+    /// assert!(!cache.contains("key"));
+    /// cache.set("key", "value");
+    /// assert!(cache.contains("key"));
+    /// ```
+    pub fn contains(&self, key: &str) -> bool {
+        self.db.contains(key)
+    }
+
+    /// Get the number of entries in the cache.
+    ///
+    /// Note: This may include expired entries that haven't been
+    /// cleaned up yet by lazy expiration or background cleanup.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::{Cache, CacheConfig};
+    ///
+    /// let cache = Cache::new(CacheConfig::default());
+    /// assert_eq!(cache.len(), 0);
+    /// cache.set("key", "value");
+    /// assert_eq!(cache.len(), 1);
+    /// ```
+    pub fn len(&self) -> usize {
+        self.db.len()
+    }
+
+    /// Whether the cache has no `max_capacity` and so never evicts to make
+    /// room.
+    ///
+    /// Frameworks that embed the cache can use this to refuse unbounded
+    /// caches outright; otherwise the growth alarm (see
+    /// [`CacheConfig::growth_alarm`]) reports their growth.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::{Cache, CacheConfig};
+    ///
+    /// assert!(Cache::new(CacheConfig::default()).is_unbounded());
+    /// assert!(!Cache::new(CacheConfig::new().max_capacity(100)).is_unbounded());
+    /// ```
+    pub fn is_unbounded(&self) -> bool {
+        self.db.config().max_capacity.is_none()
+    }
+
+    /// Check if the cache is empty.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::{Cache, CacheConfig};
+    ///
+    /// let cache = Cache::new(CacheConfig::default());
+    /// assert!(cache.is_empty());
+    /// cache.set("key", "value");
+    /// assert!(!cache.is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        self.db.is_empty()
+    }
+
+    /// Remove all entries from the cache.
+    ///
+    /// The write lock is held only to swap in an empty map. The old entries
+    /// are freed and reported to the eviction listener as
+    /// [`RemovalCause::Cleared`](crate::RemovalCause::Cleared) after the lock
+    /// is released, on the calling thread or, with
+    /// [`CacheConfig::background_clear`], on a background thread.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::{Cache, CacheConfig};
+    ///
+    /// let cache = Cache::new(CacheConfig::default());
+    /// cache.set("key1", "value1");
+    /// cache.set("key2", "value2");
+    /// cache.clear();
+    /// assert!(cache.is_empty());
+    /// ```
+    pub fn clear(&self) {
+        self.db.clear();
+    }
+
+    /// Append `value` to a capped "most recent items" list under `prefix`.
+    ///
+    /// The value is stored under a generated key, `prefix` followed by a
+    /// zero-padded sequence number, using the default TTL. If more than
+    /// `cap` live entries pushed under `prefix` then exist, the oldest are
+    /// removed in the same write-lock pass and counted as evictions, so the
+    /// cap holds exactly even under concurrent pushes. Returns the generated
+    /// key, or `None` if the value could not be written to the spill-over
+    /// file.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::Cache;
+    ///
+    /// let cache = Cache::default();
+    /// for page in ["home", "search", "cart", "checkout"] {
+    ///     cache.push_capped("recent:user:1:", page, 3);
+    /// }
+    /// let recent: Vec<_> = cache.recent("recent:user:1:", 2);
+    /// assert_eq!(recent, vec!["cart", "checkout"]);
+    /// ```
+    pub fn push_capped(&self, prefix: &str, value: impl Into<Bytes>, cap: usize) -> Option<String> {
+        self.db.push_capped(prefix, value.into(), cap)
+    }
+
+    /// Append `suffix` to the value of `key` and return the new length in
+    /// bytes.
+    ///
+    /// A missing or expired key is created holding just `suffix`, with the
+    /// default TTL; an existing entry keeps its TTL. The read and the write
+    /// happen under one write lock, so concurrent appends are never lost,
+    /// and the append counts as a set. Returns 0 if the write is dropped,
+    /// e.g. because a spill file could not be written.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::Cache;
+    ///
+    /// let cache = Cache::default();
+    /// assert_eq!(cache.append("log", "started;"), 8);
+    /// assert_eq!(cache.append("log", "done;"), 13);
+    /// assert_eq!(cache.get("log").unwrap(), "started;done;");
+    /// ```
+    pub fn append(&self, key: &str, suffix: impl Into<Bytes>) -> usize {
+        self.db.append(key, suffix.into())
+    }
+
+    /// Add `delta` to the counter stored under `key` and return the new
+    /// value.
+    ///
+    /// The stored value is parsed as a decimal integer, updated and written
+    /// back under one write lock, so concurrent increments are never lost.
+    /// A missing or expired key counts as 0 and is created with the default
+    /// TTL; an existing counter keeps its TTL.
+    ///
+    /// Fails with [`CacheError::InvalidValue`] if the key holds something
+    /// other than an integer or if the counter would overflow.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::Cache;
+    ///
+    /// let cache = Cache::default();
+    /// assert_eq!(cache.increment("views:home", 1).unwrap(), 1);
+    /// assert_eq!(cache.increment("views:home", 10).unwrap(), 11);
+    /// assert_eq!(cache.get("views:home").unwrap(), "11");
+    /// ```
+    pub fn increment(&self, key: &str, delta: i64) -> CacheResult<i64> {
+        self.db.increment(key, delta)
+    }
+
+    /// Subtract `delta` from the counter stored under `key` and return the
+    /// new value. Behaves like [`Cache::increment`] otherwise.
+    pub fn decrement(&self, key: &str, delta: i64) -> CacheResult<i64> {
+        let delta = delta.checked_neg().ok_or_else(|| {
+            CacheError::InvalidValue(format!("decrementing '{}' would overflow", key))
+        })?;
+        self.db.increment(key, delta)
+    }
+
+    /// Add `delta` to a counter that lives for a fixed `window`.
+    ///
+    /// The first increment creates the counter with the value `delta` and a
+    /// TTL of `window`; later increments add to it without touching the
+    /// TTL, and once the window is over the next increment starts a new
+    /// one. Returns the new count and the time left in the window.
+    ///
+    /// Fails with [`CacheError::InvalidValue`] if the key holds something
+    /// other than an integer, if the count would overflow, or if `window` is
+    /// shorter than a millisecond. The window ends exactly on time,
+    /// regardless of
+    /// [`CacheConfig::expiration_grace`](crate::CacheConfig::expiration_grace).
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::Cache;
+    /// use std::time::Duration;
+    ///
+    /// let cache = Cache::default();
+    /// let window = Duration::from_secs(60);
+    /// assert_eq!(cache.incr_window("logins:alice", 1, window).unwrap().0, 1);
+    /// let (count, reset_in) = cache.incr_window("logins:alice", 2, window).unwrap();
+    /// assert_eq!(count, 3);
+    /// assert!(reset_in <= window);
+    /// ```
+    pub fn incr_window(
+        &self,
+        key: &str,
+        delta: i64,
+        window: Duration,
+    ) -> CacheResult<(i64, Duration)> {
+        self.incr_window_at(key, delta, window, self.db.now())
+    }
+
+    /// [`Cache::incr_window`] at a given instant.
+    pub fn incr_window_at(
+        &self,
+        key: &str,
+        delta: i64,
+        window: Duration,
+        now: Instant,
+    ) -> CacheResult<(i64, Duration)> {
+        self.db.incr_window_at(key, delta, window, now)
+    }
+
+    /// Count a call against a limit of `limit` calls per fixed `window`,
+    /// keyed by `key` (for example `"api:<client id>"`).
+    ///
+    /// Built on [`Cache::incr_window`], so every call counts, including
+    /// refused ones, and the window resets `window` after the first call in
+    /// it. A refused decision carries the time until the reset.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::Cache;
+    /// use std::time::Duration;
+    ///
+    /// let cache = Cache::default();
+    /// let window = Duration::from_secs(1);
+    /// assert!(cache.rate_limit("api:alice", 2, window).unwrap().allowed);
+    /// assert!(cache.rate_limit("api:alice", 2, window).unwrap().allowed);
+    ///
+    /// let decision = cache.rate_limit("api:alice", 2, window).unwrap();
+    /// assert!(!decision.allowed);
+    /// assert!(decision.retry_after <= window);
+    /// ```
+    pub fn rate_limit(&self, key: &str, limit: u64, window: Duration) -> CacheResult<RateDecision> {
+        self.rate_limit_at(key, limit, window, self.db.now())
+    }
+
+    /// [`Cache::rate_limit`] at a given instant.
+    pub fn rate_limit_at(
+        &self,
+        key: &str,
+        limit: u64,
+        window: Duration,
+        now: Instant,
+    ) -> CacheResult<RateDecision> {
+        let (count, reset_in) = self.incr_window_at(key, 1, window, now)?;
+        Ok(RateDecision::new(count, limit, reset_in))
+    }
+
+    /// The newest `n` values pushed with [`Cache::push_capped`] under
+    /// `prefix`, oldest first.
+    ///
+    /// Entries deleted, evicted or expired since they were pushed are
+    /// skipped. Reading does not affect LRU order or statistics.
+    pub fn recent(&self, prefix: &str, n: usize) -> Vec<Bytes> {
+        self.db.recent(prefix, n)
+    }
+
+    /// Get a snapshot of all live keys.
+    ///
+    /// This is a point-in-time copy taken under the read lock, not a live
+    /// view: writes made after the call do not show up in it, and the lock
+    /// is released before it is returned, so processing it never blocks
+    /// other callers. Entries that are expired at snapshot time are
+    /// skipped, and LRU order and statistics are not touched.
+    ///
+    /// Keys come in storage order: shard by shard (see
+    /// [`CacheConfig::shards`]), least recently used first within each.
+    /// Copying is O(n) in the number of entries; use
+    /// [`Cache::keys_sorted`] for a stable order or [`Cache::scan_keys`] to
+    /// page through a large cache without copying all of it at once.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::{Cache, CacheConfig};
+    ///
+    /// let cache = Cache::new(CacheConfig::new().shards(1));
+    /// cache.set("b", "2");
+    /// cache.set("a", "1");
+    /// let keys = cache.keys();
+    /// cache.set("c", "3");
+    /// assert_eq!(keys, vec!["b", "a"]);
+    /// ```
+    pub fn keys(&self) -> Vec<String> {
+        self.db.keys()
+    }
+
+    /// Get a snapshot of all live entries.
+    ///
+    /// The value-carrying counterpart of [`Cache::keys`], with the same
+    /// point-in-time semantics and order. Cloning the values only bumps the
+    /// `Bytes` reference counts; spilled values are read back from disk
+    /// after the lock is released.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::Cache;
+    /// use bytes::Bytes;
+    ///
+    /// let cache = Cache::default();
+    /// cache.set("a", "1");
+    /// assert_eq!(cache.iter(), vec![("a".to_string(), Bytes::from("1"))]);
+    /// ```
+    pub fn iter(&self) -> Vec<(String, Bytes)> {
+        self.db.live_entries()
+    }
+
+    /// Get all live keys in lexicographic order.
+    ///
+    /// Unlike the map's internal order, which follows LRU recency and changes
+    /// on every access, this order depends only on the contents of the cache.
+    /// That makes it suitable for golden-file tests and diffable dumps.
+    ///
+    /// The keys are collected under the read lock and sorted after it is
+    /// released, so the cost is O(n log n) in the number of entries. Expired
+    /// entries are skipped and LRU order is not touched.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::{Cache, CacheConfig};
+    ///
+    /// let cache = Cache::new(CacheConfig::default());
+    /// cache.set("b", "2");
+    /// cache.set("a", "1");
+    /// assert_eq!(cache.keys_sorted(), vec!["a", "b"]);
+    /// ```
+    pub fn keys_sorted(&self) -> Vec<String> {
+        self.db.keys_sorted()
+    }
+
+    /// Get the live keys matching the glob `pattern`, in lexicographic
+    /// order.
+    ///
+    /// `*` matches any run of characters, `?` any single character and
+    /// `[...]` one character from a set such as `[a-z]` (`[!...]` for one
+    /// outside it); a backslash escapes the next character. The pattern
+    /// must match the whole key. Expired entries are skipped and LRU order
+    /// is not touched.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::Cache;
+    ///
+    /// let cache = Cache::default();
+    /// cache.set("catalog:2", "b");
+    /// cache.set("catalog:1", "a");
+    /// cache.set("cart:1", "c");
+    /// assert_eq!(cache.keys_matching("catalog:*"), vec!["catalog:1", "catalog:2"]);
+    /// assert_eq!(cache.keys_matching("ca*:1"), vec!["cart:1", "catalog:1"]);
+    /// ```
+    pub fn keys_matching(&self, pattern: &str) -> Vec<String> {
+        self.db.keys_matching(pattern)
+    }
+
+    /// Scan for the live keys matching the glob `pattern`, in
+    /// lexicographic order.
+    ///
+    /// The scan the server's `keys` command runs; patterns work as in
+    /// [`Cache::keys_matching`]. Expired entries are skipped, and neither
+    /// LRU order nor the hit and miss counters are touched. For large
+    /// caches, [`Cache::scan_keys`] pages through keys instead.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::Cache;
+    ///
+    /// let cache = Cache::default();
+    /// cache.set("user:10", "b");
+    /// cache.set("user:1", "a");
+    /// cache.set("session:1", "c");
+    /// assert_eq!(cache.scan("user:*"), vec!["user:1", "user:10"]);
+    /// assert_eq!(cache.scan("user:?"), vec!["user:1"]);
+    /// assert!(cache.scan("order:*").is_empty());
+    /// ```
+    pub fn scan(&self, pattern: &str) -> Vec<String> {
+        self.db.keys_matching(pattern)
+    }
+
+    /// Get the next page of at most `limit` live keys, in lexicographic
+    /// order, and the cursor to continue from.
+    ///
+    /// Start with [`ScanCursor::start`] and pass each returned cursor back
+    /// in until [`ScanCursor::is_done`]. Keys that stay live for the whole
+    /// scan are returned exactly once and no key is returned twice, however
+    /// the cache changes between pages; see [`crate::scan`] for the details.
+    /// The server's `scan` command uses the same cursors.
+    ///
+    /// Each page scans every entry under the read lock, in O(n log limit).
+    /// A `limit` of zero is raised to one so that every call makes progress.
+    /// Expired entries are skipped, and LRU order and statistics are not
+    /// touched.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::{Cache, ScanCursor};
+    ///
+    /// let cache = Cache::default();
+    /// for key in ["c", "a", "d", "b", "e"] {
+    ///     cache.set(key, "value");
+    /// }
+    ///
+    /// let (page, cursor) = cache.scan_keys(ScanCursor::start(), 2);
+    /// assert_eq!(page, vec!["a", "b"]);
+    ///
+    /// // Cursors survive a round trip through a string, e.g. a query parameter
+    /// let cursor: ScanCursor = cursor.to_string().parse().unwrap();
+    /// let (page, cursor) = cache.scan_keys(cursor, 10);
+    /// assert_eq!(page, vec!["c", "d", "e"]);
+    /// assert!(cursor.is_done());
+    /// ```
+    pub fn scan_keys(&self, cursor: ScanCursor, limit: usize) -> (Vec<String>, ScanCursor) {
+        if cursor.is_done() {
+            return (Vec::new(), cursor);
+        }
+        let (keys, more) = self.db.scan_keys(cursor.last_key(), limit.max(1));
+        let next = match keys.last() {
+            Some(last) if more => ScanCursor::after(last.as_str()),
+            _ => ScanCursor::done(),
+        };
+        (keys, next)
+    }
+
+    /// Get all live entries in lexicographic key order.
+    ///
+    /// This is the value-carrying counterpart of [`Cache::keys_sorted`] and
+    /// has the same O(n log n) cost. Cloning the values only bumps the
+    /// `Bytes` reference counts.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::{Cache, CacheConfig};
+    /// use bytes::Bytes;
+    ///
+    /// let cache = Cache::new(CacheConfig::default());
+    /// cache.set("b", "2");
+    /// cache.set("a", "1");
+    ///
+    /// let items = cache.iter_sorted();
+    /// assert_eq!(items[0], ("a".to_string(), Bytes::from("1")));
+    /// assert_eq!(items[1], ("b".to_string(), Bytes::from("2")));
+    /// ```
+    pub fn iter_sorted(&self) -> Vec<(String, Bytes)> {
+        self.db.iter_sorted()
+    }
+
+    /// The first live entry, in storage order (see [`Cache::keys`]), for
+    /// which `pred` returns `true`.
+    ///
+    /// The scan stops at the first match, so a match near the front does
+    /// not pay for visiting the rest. Expired entries are skipped; nothing is
+    /// promoted and hit/miss statistics are not updated.
+    ///
+    /// # Hazards
+    /// `pred` runs while the read lock is held, with the same caveats as
+    /// [`Cache::get_ref`]: writers wait for the whole scan, and calling back
+    /// into the cache with an operation that needs the write lock deadlocks.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::{Cache, CacheConfig};
+    ///
+    /// let cache = Cache::new(CacheConfig::default());
+    /// cache.set("user:1", "alice");
+    /// cache.set("user:2", "bob");
+    ///
+    /// let (key, _) = cache.find(|_, value| value.as_ref() == b"bob").unwrap();
+    /// assert_eq!(key, "user:2");
+    /// ```
+    pub fn find(&self, pred: impl FnMut(&str, &Bytes) -> bool) -> Option<(String, Bytes)> {
+        self.db.find(pred)
+    }
+
+    /// Call `f` on each live entry, in storage order, until it returns
+    /// `ControlFlow::Break`.
+    ///
+    /// Like [`Cache::find`], this skips expired entries, promotes nothing,
+    /// and runs `f` under the read lock with the same hazards.
+    ///
+    /// # Example
+    /// ```
+    /// use std::ops::ControlFlow;
+    /// use in_memory_cache::{Cache, CacheConfig};
+    ///
+    /// // One shard, so storage order is the order of the sets
+    /// let cache = Cache::new(CacheConfig::new().shards(1));
+    /// for i in 0..10 {
+    ///     cache.set(format!("key{}", i), "v");
+    /// }
+    ///
+    /// let mut first_three = Vec::new();
+    /// cache.for_each_while(|key, _| {
+    ///     first_three.push(key.to_string());
+    ///     if first_three.len() == 3 {
+    ///         ControlFlow::Break(())
+    ///     } else {
+    ///         ControlFlow::Continue(())
+    ///     }
+    /// });
+    /// assert_eq!(first_three, vec!["key0", "key1", "key2"]);
+    /// ```
+    pub fn for_each_while(&self, f: impl FnMut(&str, &Bytes) -> ControlFlow<()>) {
+        self.db.for_each_while(f)
+    }
+
+    /// Get a snapshot of the cache statistics.
+    ///
+    /// Returns a point-in-time snapshot of hits, misses, evictions, etc.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::{Cache, CacheConfig};
+    ///
+    /// let cache = Cache::new(CacheConfig::default());
+    /// cache.set("key", "value");
+    /// let _ = cache.get("key");        // Hit
+    /// let _ = cache.get("missing");    // Miss
+    ///
+    /// let stats = cache.stats();
+    /// println!("Hits: {}, Misses: {}", stats.hits, stats.misses);
+    /// ```
+    pub fn stats(&self) -> StatsSnapshot {
+        self.db.stats().snapshot()
+    }
+
+    /// Reset the hit, miss, eviction and other event counters to zero.
+    ///
+    /// The entry count is left alone since it describes the current
+    /// contents rather than past events. Resetting affects every reader of
+    /// the stats; to measure a window without that, subtract two
+    /// [`StatsSnapshot`]s instead.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::{Cache, CacheConfig};
+    ///
+    /// let cache = Cache::new(CacheConfig::default());
+    /// cache.set("key", "value");
+    /// cache.get("key");
+    /// cache.reset_stats();
+    ///
+    /// let stats = cache.stats();
+    /// assert_eq!(stats.hits, 0);
+    /// assert_eq!(stats.size, 1);
+    /// ```
+    pub fn reset_stats(&self) {
+        self.db.stats().reset();
+    }
+
+    /// Count and size of live entries whose key starts with `prefix`.
+    ///
+    /// This scans every entry under the read lock, so it is meant for
+    /// occasional reporting rather than hot paths.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::{Cache, CacheConfig};
+    ///
+    /// let cache = Cache::new(CacheConfig::default());
+    /// cache.set("user:1", "alice");
+    /// cache.set("user:2", "bob");
+    /// cache.set("order:1", "book");
+    ///
+    /// let stats = cache.prefix_stats("user:");
+    /// assert_eq!(stats.entries, 2);
+    /// assert_eq!(stats.bytes, 6 + 5 + 6 + 3);
+    /// ```
+    pub fn prefix_stats(&self, prefix: &str) -> PrefixStats {
+        self.db.prefix_stats(prefix)
+    }
+
+    /// Remove every entry for which `f` returns `false`, returning how many
+    /// entries were removed.
+    ///
+    /// The walk happens under the write lock, so `f` should be cheap and
+    /// must not call back into this cache. It is called once per live
+    /// entry, in storage order, and all calls happen before anything is
+    /// removed. Removed entries count as deletes and, like
+    /// [`Cache::delete`], are reported to the eviction listener as
+    /// [`RemovalCause::Deleted`](crate::RemovalCause::Deleted).
+    /// Expired entries are removed whatever `f` says, are counted as
+    /// expirations as in [`Cache::cleanup_expired`], and are included in
+    /// the returned count. If `f` panics, nothing is removed and the panic
+    /// propagates.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::Cache;
+    ///
+    /// let cache = Cache::default();
+    /// cache.set("page:/a", "v1");
+    /// cache.set("page:/b", "v2");
+    /// cache.set("user:1", "v1");
+    /// // Invalidate pages rendered by the previous deployment
+    /// let removed = cache.retain(|key, value| !(key.starts_with("page:") && value == "v1"));
+    /// assert_eq!(removed, 1);
+    /// assert_eq!(cache.keys_sorted(), vec!["page:/b", "user:1"]);
+    /// ```
+    pub fn retain(&self, f: impl FnMut(&str, &Bytes) -> bool) -> usize {
+        self.db.retain(f)
+    }
+
+    /// Manually trigger cleanup of expired entries.
+    ///
+    /// Returns the number of entries that were removed.
+    /// This is useful if you want to control when cleanup happens
+    /// instead of relying on lazy expiration or background cleanup.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::{Cache, CacheConfig};
+    /// use std::time::Duration;
+    ///
+    /// let cache = Cache::new(CacheConfig::default());
+    /// cache.set_with_ttl("key", "value", Duration::from_millis(1));
+    /// std::thread::sleep(Duration::from_millis(10));
+    /// let removed = cache.cleanup_expired();
+    /// println!("Removed {} expired entries", removed);
+    /// ```
+    pub fn cleanup_expired(&self) -> usize {
+        self.db.cleanup_expired()
+    }
+
+    /// Remove the expired entries among the next `max_items` entries and
+    /// return how many were removed.
+    ///
+    /// Unlike [`Cache::cleanup_expired`], this looks at a bounded slice of
+    /// the cache per call, resuming in storage order where the previous
+    /// call stopped and starting over after the last entry. Calls covering
+    /// [`Cache::len`] entries in total remove every entry that was expired
+    /// throughout; entries promoted by reads in between may be looked at
+    /// twice or not until the next round. The background task sweeps this
+    /// way with [`CacheConfig::cleanup_batch_size`] set.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::{Cache, CacheConfig};
+    /// use std::time::Duration;
+    ///
+    /// let cache = Cache::new(CacheConfig::new().shards(1));
+    /// for i in 0..10 {
+    ///     cache.set_with_ttl(format!("key:{}", i), "value", Duration::from_millis(1));
+    /// }
+    /// std::thread::sleep(Duration::from_millis(10));
+    /// assert_eq!(cache.cleanup_expired_batch(4), 4);
+    /// assert_eq!(cache.len(), 6);
+    /// ```
+    pub fn cleanup_expired_batch(&self, max_items: usize) -> usize {
+        self.db.cleanup_expired_batch(max_items)
+    }
+
+    /// Get a handle that queues sets and applies them in batches of up to
+    /// `max_batch`, each under one write-lock acquisition (feature `tokio`).
+    ///
+    /// Queued writes become visible when their batch is applied, at most
+    /// `max_delay` after the batch started collecting, or earlier on
+    /// [`BatchWriter::flush`]; see [`crate::batch`]. `max_batch` is raised to
+    /// at least 1.
+    ///
+    /// # Panics
+    /// Panics if called outside a Tokio runtime, which runs the task that
+    /// applies the batches.
+    ///
+    /// [`BatchWriter::flush`]: crate::BatchWriter::flush
+    #[cfg(feature = "tokio")]
+    pub fn batch_writer(&self, max_batch: usize, max_delay: Duration) -> crate::BatchWriter {
+        crate::BatchWriter::start(Arc::clone(&self.db), max_batch, max_delay)
+    }
+
+    /// Take over the thread that removes expired entries in the background.
+    ///
+    /// With [`CacheConfig::background_cleanup`] on, [`Cache::new`] starts
+    /// sweeps every [`CacheConfig::cleanup_interval`], adapted to the share
+    /// of expired entries within [`CacheConfig::cleanup_bounds`] if those
+    /// are set (see [`crate::cleanup`]). They normally run until the last
+    /// clone of the cache is dropped. The returned handle stops them when
+    /// it is stopped or dropped instead, even if the cache lives on.
+    /// Returns `None` if background cleanup is off, the interval is
+    /// disabled, or the task was already taken. The current interval and
+    /// the result of the last sweep appear in [`Cache::stats`].
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::{Cache, CacheConfig};
+    /// use std::time::Duration;
+    ///
+    /// let cache = Cache::new(
+    ///     CacheConfig::new()
+    ///         .background_cleanup(true)
+    ///         .cleanup_interval(Duration::from_secs(5)),
+    /// );
+    /// let _cleanup = cache.start_cleanup();
+    /// cache.set_with_ttl("session", "data", Duration::from_secs(1));
+    /// ```
+    pub fn start_cleanup(&self) -> Option<CleanupTask> {
+        self.cleanup
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+    }
+
+    /// Split into a read-only and a write-only handle over this cache.
+    ///
+    /// Both handles share storage with this cache and with each other, and
+    /// both are cheap to clone. Keep the [`CacheWriter`] in the module that
+    /// owns mutation and hand out [`CacheReader`]s elsewhere; see
+    /// [`crate::handle`] for how methods are divided.
+    pub fn split(&self) -> (CacheReader, CacheWriter) {
+        (
+            CacheReader::new(self.clone()),
+            CacheWriter::new(self.clone()),
+        )
+    }
+
+    /// Take an immutable, lock-free snapshot of the live entries.
+    ///
+    /// Reads on the returned [`FrozenCache`] take no lock and update no
+    /// statistics, which makes it much faster under many reader threads.
+    /// TTLs are dropped: frozen entries never expire. This cache is left
+    /// untouched and stays fully usable.
+    pub fn freeze(&self) -> FrozenCache {
+        FrozenCache::new(self.db.live_entries(), self.db.config().clone())
+    }
+
+    /// Approximate memory used by keys and values, in bytes.
+    ///
+    /// Sums the key and value lengths of all stored entries (including
+    /// expired ones not yet cleaned up) under a read lock. Per-entry
+    /// bookkeeping is not included; add `len() * Cache::ENTRY_OVERHEAD` for
+    /// an estimate that covers it.
+    pub fn approx_bytes(&self) -> usize {
+        self.db.approx_bytes()
+    }
+
+    /// Estimate memory use per key prefix from a sample of entries.
+    ///
+    /// Inspects up to `samples` entries, groups them by key prefix up to the
+    /// `prefix_depth`-th `:` separator (`user:42:profile` at depth 1 is
+    /// `user:`), and scales the sampled counts and key+value bytes to the
+    /// whole cache. Sampling picks evenly spaced entries, so results are
+    /// deterministic for given contents.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::Cache;
+    ///
+    /// let cache = Cache::default();
+    /// for i in 0..100 {
+    ///     cache.set(format!("user:{}", i), "profile");
+    /// }
+    /// cache.set("config", "x");
+    ///
+    /// let breakdown = cache.memory_breakdown(1000, 1);
+    /// assert_eq!(breakdown.groups[0].prefix, "user:");
+    /// assert_eq!(breakdown.groups[0].estimated_count, 100);
+    /// ```
+    pub fn memory_breakdown(&self, samples: usize, prefix_depth: usize) -> MemoryBreakdown {
+        let (total, sample) = self.db.sample_sizes(samples);
+        MemoryBreakdown::from_sample(total, sample, prefix_depth)
+    }
+
+    /// Keys of entries that have not been read for longer than `idle`, in
+    /// storage order.
+    ///
+    /// Idleness is measured from the last read or write, independently of
+    /// any TTL. Expired entries are skipped.
+    pub fn idle_longer_than(&self, idle: Duration) -> Vec<String> {
+        self.db.idle_longer_than(idle)
+    }
+
+    /// Remove entries that have not been read for longer than `idle`,
+    /// returning how many were removed.
+    ///
+    /// Removals are counted as evictions and reported to the eviction
+    /// listener with [`RemovalCause::Idle`](crate::RemovalCause::Idle). The
+    /// write lock is taken in bounded chunks, so concurrent operations are
+    /// not stalled by a large purge.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::Cache;
+    /// use std::time::Duration;
+    ///
+    /// let cache = Cache::default();
+    /// cache.set("key", "value");
+    /// assert_eq!(cache.purge_idle(Duration::from_secs(3600)), 0);
+    /// assert!(cache.contains("key"));
+    /// ```
+    pub fn purge_idle(&self, idle: Duration) -> usize {
+        self.db.purge_idle(idle)
+    }
+
+    /// Give every live entry whose key matches the glob `pattern` a TTL of
+    /// `ttl` from now, returning how many entries were updated.
+    ///
+    /// Patterns work as in [`Cache::keys_matching`]. Entries without a TTL
+    /// get one; values and LRU order are left alone. The write lock is taken
+    /// in bounded chunks, so concurrent operations are not stalled when many
+    /// keys match.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::Cache;
+    /// use std::time::Duration;
+    ///
+    /// let cache = Cache::default();
+    /// cache.set("catalog:1", "a");
+    /// cache.set_with_ttl("catalog:2", "b", Duration::from_secs(3600));
+    /// cache.set("cart:1", "c");
+    ///
+    /// assert_eq!(cache.expire_matching("catalog:*", Duration::from_secs(60)), 2);
+    /// assert!(cache.ttl("catalog:1").unwrap().unwrap() <= Duration::from_secs(60));
+    /// assert_eq!(cache.ttl("cart:1"), Some(None));
+    /// ```
+    pub fn expire_matching(&self, pattern: &str, ttl: Duration) -> usize {
+        self.db.expire_matching(pattern, ttl)
+    }
+
+    /// Give an existing entry a TTL of `ttl` from now, replacing any TTL it
+    /// had.
+    ///
+    /// Returns whether the key existed; a missing or expired key is left
+    /// alone. The value and LRU order are untouched, and since nothing is
+    /// written this counts as neither a set nor a replacement, and the
+    /// eviction listener is not called.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::Cache;
+    /// use std::time::Duration;
+    ///
+    /// let cache = Cache::default();
+    /// cache.set("session", "alice");
+    /// assert!(cache.expire("session", Duration::from_secs(60)));
+    /// assert!(cache.ttl("session").unwrap().unwrap() <= Duration::from_secs(60));
+    /// assert!(!cache.expire("missing", Duration::from_secs(60)));
+    /// assert_eq!(cache.stats().sets, 1);
+    /// ```
+    pub fn expire(&self, key: &str, ttl: Duration) -> bool {
+        self.db.expire(key, ttl)
+    }
+
+    /// Remove the TTL of an existing entry, so it no longer expires.
+    ///
+    /// Returns whether the key existed, whether or not it had a TTL. Like
+    /// [`Cache::expire`], this is not a set.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::Cache;
+    /// use std::time::Duration;
+    ///
+    /// let cache = Cache::default();
+    /// cache.set_with_ttl("session", "alice", Duration::from_secs(60));
+    /// assert!(cache.persist("session"));
+    /// assert_eq!(cache.ttl("session"), Some(None));
+    /// ```
+    pub fn persist(&self, key: &str) -> bool {
+        self.db.persist(key)
+    }
+
+    /// Remove the TTL of every live entry whose key matches the glob
+    /// `pattern`, returning how many entries had one.
+    ///
+    /// The counterpart of [`Cache::expire_matching`], taking the write lock
+    /// in the same bounded chunks.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::Cache;
+    /// use std::time::Duration;
+    ///
+    /// let cache = Cache::default();
+    /// cache.set_with_ttl("session:1", "a", Duration::from_secs(60));
+    /// cache.set("session:2", "b");
+    ///
+    /// assert_eq!(cache.persist_matching("session:*"), 1);
+    /// assert_eq!(cache.ttl("session:1"), Some(None));
+    /// ```
+    pub fn persist_matching(&self, pattern: &str) -> usize {
+        self.db.persist_matching(pattern)
+    }
+
+    /// Wrap a handle to this cache in a fault-injecting [`CacheOps`]
+    /// implementation (feature `test-util`).
+    ///
+    /// See [`fault`] for the available faults.
+    ///
+    /// [`CacheOps`]: crate::CacheOps
+    #[cfg(feature = "test-util")]
+    pub fn with_fault_injection(&self, policy: fault::FaultPolicy) -> fault::FaultyCache {
+        let seed = self.db.component_seed("fault");
+        fault::FaultyCache::new(self.clone(), policy, seed)
+    }
+
+    /// Export every live entry into `path` as one file per key.
+    ///
+    /// Each key produces `<name>.val` with the raw value and
+    /// `<name>.meta.json` with the real key, remaining TTL and idle time.
+    /// `<name>` is a path-safe, reversible escape of the key. The directory
+    /// is created if needed and must otherwise be empty. Entries are copied
+    /// out under a read lock, which is released before any file is written.
+    /// Returns the number of entries exported.
+    ///
+    /// # Errors
+    /// Returns `CacheError::IoError` if the directory is not empty or a file
+    /// cannot be written.
+    pub fn export_dir(&self, path: impl AsRef<Path>) -> CacheResult<usize> {
+        export::export_dir(&self.db, path.as_ref(), false)
+    }
+
+    /// [`Cache::export_dir`] in lexicographic key order, for golden-file
+    /// tests and diffable dumps.
+    ///
+    /// Two caches with the same keys and values produce byte-identical
+    /// dumps, whatever order they were filled or read in: file names are
+    /// picked in key order, idle times are written as 0 and the export time
+    /// is only recorded for entries with a TTL. Entries with a TTL still
+    /// differ as their remaining TTL does. Importing the dump restores key
+    /// order rather than LRU order. Sorting costs O(n log n) in the number
+    /// of entries, after the read lock is released.
+    ///
+    /// # Errors
+    /// As for [`Cache::export_dir`].
+    pub fn export_sorted(&self, path: impl AsRef<Path>) -> CacheResult<usize> {
+        export::export_dir(&self.db, path.as_ref(), true)
+    }
+
+    /// Load entries written by [`Cache::export_dir`].
+    ///
+    /// Remaining TTLs are shortened by the wall-clock time since the export,
+    /// and entries that expired in the meantime are skipped. Existing keys
+    /// are overwritten. Returns the number of entries imported.
+    ///
+    /// # Errors
+    /// Returns `CacheError::IoError` if a file cannot be read and
+    /// `CacheError::ParseError` for malformed metadata.
+    pub fn import_dir(&self, path: impl AsRef<Path>) -> CacheResult<usize> {
+        export::import_dir(&self.db, path.as_ref(), true)
+    }
+
+    /// [`Cache::import_dir`] without telling the eviction listener about
+    /// the values the import overwrites, for bulk loads over a warm cache.
+    /// Evictions are still reported.
+    ///
+    /// # Errors
+    /// As for [`Cache::import_dir`].
+    pub fn import_dir_quiet(&self, path: impl AsRef<Path>) -> CacheResult<usize> {
+        export::import_dir(&self.db, path.as_ref(), false)
+    }
+
+    /// Count live entries by remaining TTL.
+    ///
+    /// `buckets` are ascending, inclusive upper bounds: slot `i` of the result
+    /// counts entries whose remaining TTL is greater than `buckets[i - 1]` and
+    /// at most `buckets[i]`. One extra slot at the end counts entries without
+    /// a TTL. Entries that outlive the largest bound are not counted, so pass
+    /// `Duration::MAX` as the last bound to include them. Expired entries
+    /// that have not been cleaned up yet are skipped.
+    ///
+    /// This scans every entry under a read lock.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::Cache;
+    /// use std::time::Duration;
+    ///
+    /// let cache = Cache::default();
+    /// cache.set_with_ttl("a", "1", Duration::from_secs(30));
+    /// cache.set_with_ttl("b", "2", Duration::from_secs(3600));
+    /// cache.set("c", "3");
+    ///
+    /// let counts = cache.expiration_histogram(&[Duration::from_secs(300), Duration::MAX]);
+    /// assert_eq!(counts, vec![1, 1, 1]);
+    /// ```
+    pub fn expiration_histogram(&self, buckets: &[Duration]) -> Vec<usize> {
+        self.db.expiration_histogram(buckets)
+    }
+
+    /// Seed the cache from a Redis command stream (feature `tools`).
+    ///
+    /// Accepts RESP-framed or inline `SET`/`SETEX`/`EXPIRE`-style commands
+    /// as produced by Redis dump tools, and skips commands for other data
+    /// types, counting them per type in the returned report. See
+    /// [`crate::import`] for the supported syntax. Malformed input fails with
+    /// a `CacheError::ParseError` naming the line; everything before that
+    /// line has already been imported.
+    #[cfg(feature = "tools")]
+    pub fn import_redis_proto(
+        &self,
+        reader: impl std::io::BufRead,
+    ) -> CacheResult<crate::import::ImportReport> {
+        crate::import::import(&self.db, reader, true)
+    }
+
+    /// [`Cache::import_redis_proto`] without telling the eviction listener
+    /// about the values the import overwrites (feature `tools`).
+    #[cfg(feature = "tools")]
+    pub fn import_redis_proto_quiet(
+        &self,
+        reader: impl std::io::BufRead,
+    ) -> CacheResult<crate::import::ImportReport> {
+        crate::import::import(&self.db, reader, false)
+    }
+
+    /// Delete files in the [`spill_over`](CacheConfig::spill_over)
+    /// directory that no entry refers to, such as files left behind by a
+    /// crash, and return how many were deleted. Does nothing if spilling is
+    /// not configured.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::{Cache, CacheConfig};
+    ///
+    /// let dir = std::env::temp_dir().join("in-memory-cache-gc-doctest");
+    /// let cache = Cache::new(CacheConfig::new().spill_over(1024, &dir).build());
+    /// cache.set("large", vec![0u8; 4096]);
+    ///
+    /// // The live value's file is kept
+    /// assert_eq!(cache.gc_spill_dir().unwrap(), 0);
+    /// assert_eq!(cache.get("large").unwrap().len(), 4096);
+    /// # drop(cache);
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    /// ```
+    pub fn gc_spill_dir(&self) -> CacheResult<usize> {
+        self.db.gc_spill_dir()
+    }
+
+    /// Check the cache's internal invariants.
+    ///
+    /// Takes the write lock and verifies that the `size` statistic equals the
+    /// number of stored entries and that the capacity limit holds. Intended
+    /// for tests and debugging; returns `CacheError::InvariantViolation`
+    /// describing the first problem found.
+    pub fn debug_validate(&self) -> CacheResult<()> {
+        self.db.debug_validate()
+    }
+
+    /// Check the soft thresholds set with
+    /// [`CacheConfig::health_thresholds`].
+    ///
+    /// Hit rate and eviction rate cover the thresholds' window, which each
+    /// call advances. Thresholds crossed since the previous check, by this
+    /// call or by a [`HealthMonitor`], are delivered to the callbacks of
+    /// running monitors before this returns.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::{Cache, CacheConfig, HealthThresholds};
+    ///
+    /// let config = CacheConfig::new()
+    ///     .max_capacity(10)
+    ///     .health_thresholds(HealthThresholds::new().max_size_ratio(0.5))
+    ///     .build();
+    /// let cache = Cache::new(config);
+    /// for i in 0..6 {
+    ///     cache.set(format!("key_{}", i), "value");
+    /// }
+    ///
+    /// let report = cache.health();
+    /// assert_eq!(report.size_ratio, Some(0.6));
+    /// assert!(!report.is_healthy());
+    /// ```
+    pub fn health(&self) -> HealthReport {
+        self.health_at(Instant::now())
+    }
+
+    /// [`Cache::health`] at a given instant, which must not be earlier than
+    /// the one of a previous check to be meaningful.
+    pub fn health_at(&self, now: Instant) -> HealthReport {
+        let memory_bytes = self
+            .health
+            .thresholds()
+            .max_memory_bytes
+            .map(|_| self.approx_bytes() + self.len() * Self::ENTRY_OVERHEAD);
+        let input = HealthInput::new(&self.stats(), self.db.config().max_capacity, memory_bytes);
+        let (report, events) = self.health.evaluate(input, now);
+        if !events.is_empty() {
+            self.deliver_health_events(&events);
+        }
+        report
+    }
+
+    /// Check [`Cache::health`] every `interval` on a background thread and
+    /// call `callback` with every threshold crossing.
+    ///
+    /// Several monitors may run at once; all of them see every crossing,
+    /// including ones detected by direct calls to [`Cache::health`]. A
+    /// panic in `callback` is caught and counted in `callback_panics`.
+    /// The monitor runs until the returned handle is stopped or dropped.
+    pub fn start_monitor(
+        &self,
+        interval: Duration,
+        callback: impl Fn(&HealthEvent) + Send + Sync + 'static,
+    ) -> HealthMonitor {
+        HealthMonitor::start(self, Arc::clone(&self.health), interval, Arc::new(callback))
+    }
+
+    fn deliver_health_events(&self, events: &[HealthEvent]) {
+        self.health.deliver(events, &self.db.stats());
+    }
+
+    /// Get a reference to the internal statistics counter.
+    ///
+    /// This is useful for integrating with external metrics systems.
+    pub fn stats_ref(&self) -> Arc<CacheStats> {
+        self.db.stats()
+    }
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self::new(CacheConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    /// `config` with a clock the test advances instead of sleeping.
+    fn mock_clock(config: CacheConfig) -> (CacheConfig, MockClock) {
+        let clock = MockClock::new();
+        (config.clock(Arc::new(clock.clone())), clock)
+    }
+
+    #[test]
+    fn test_growth_alarm_reaches_monitor() {
+        use crate::health::HealthMetric;
+        use std::sync::Mutex;
+
+        let cache = Cache::new(CacheConfig::new().growth_alarm(4));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let _monitor = {
+            let events = Arc::clone(&events);
+            cache.start_monitor(Duration::from_secs(3600), move |event| {
+                assert_eq!(event.metric, HealthMetric::UnboundedGrowth);
+                events.lock().unwrap().push(event.threshold as u64);
+            })
+        };
+
+        for i in 0..20 {
+            cache.set(format!("key_{}", i), "value");
+            // Overwrites and deletes never report
+            cache.set(format!("key_{}", i), "other");
+        }
+        cache.delete("key_0");
+        assert_eq!(*events.lock().unwrap(), vec![4, 8, 16]);
+
+        // A shrunk cache growing back does not repeat alarms
+        cache.clear();
+        for i in 0..20 {
+            cache.set(format!("key_{}", i), "value");
+        }
+        assert_eq!(events.lock().unwrap().len(), 3);
+
+        // Bounded caches and a zero alarm never report
+        for config in [
+            CacheConfig::new().growth_alarm(4).max_capacity(100),
+            CacheConfig::new().growth_alarm(0),
+        ] {
+            let cache = Cache::new(config);
+            let _monitor = cache.start_monitor(Duration::from_secs(3600), |event| {
+                panic!("unexpected {}", event)
+            });
+            for i in 0..20 {
+                cache.set(format!("key_{}", i), "value");
+            }
+            assert_eq!(cache.stats().callback_panics, 0);
+        }
+    }
+
+    #[test]
+    fn test_health_events_reach_monitor() {
+        use crate::health::{HealthEventKind, HealthMetric, HealthThresholds};
+        use std::sync::Mutex;
+
+        let thresholds = HealthThresholds::new()
+            .min_hit_rate(50.0)
+            .max_evictions_per_minute(10.0)
+            .window(Duration::from_secs(60));
+        let cache = Cache::new(
+            CacheConfig::new()
+                .max_capacity(10)
+                .health_thresholds(thresholds),
+        );
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let monitor = {
+            let events = Arc::clone(&events);
+            // Never polls by itself here; the test drives the clock
+            cache.start_monitor(Duration::from_secs(3600), move |event| {
+                events.lock().unwrap().push((event.metric, event.kind));
+            })
+        };
+
+        let start = Instant::now();
+        assert!(cache.health_at(start).is_healthy());
+
+        // A scan over cold keys: every lookup misses and every set evicts
+        for i in 0..50 {
+            let key = format!("scan:{}", i);
+            assert!(cache.get(&key).is_none());
+            cache.set(key, "value");
+        }
+        let report = cache.health_at(start + Duration::from_secs(60));
+        assert_eq!(report.hit_rate, Some(0.0));
+        assert_eq!(report.evictions_per_minute, 40.0);
+        assert_eq!(
+            report.alerts,
+            vec![HealthMetric::HitRate, HealthMetric::EvictionRate]
+        );
+
+        // Back to a hot working set
+        for _ in 0..20 {
+            for i in 40..50 {
+                cache.get(&format!("scan:{}", i));
+            }
+        }
+        assert!(cache
+            .health_at(start + Duration::from_secs(120))
+            .is_healthy());
+
+        drop(monitor);
+        assert!(cache.health.listeners().is_empty());
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                (HealthMetric::HitRate, HealthEventKind::Raised),
+                (HealthMetric::EvictionRate, HealthEventKind::Raised),
+                (HealthMetric::HitRate, HealthEventKind::Cleared),
+                (HealthMetric::EvictionRate, HealthEventKind::Cleared),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_monitor_polls_in_background() {
+        use crate::health::HealthThresholds;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let cache = Cache::new(
+            CacheConfig::new()
+                .max_capacity(4)
+                .health_thresholds(HealthThresholds::new().max_memory_bytes(100)),
+        );
+        let raised = Arc::new(AtomicUsize::new(0));
+        let monitor = {
+            let raised = Arc::clone(&raised);
+            cache.start_monitor(Duration::from_millis(5), move |event| {
+                assert!(event.value > 100.0);
+                raised.fetch_add(1, Ordering::SeqCst);
+                panic!("callbacks may panic");
+            })
+        };
+        cache.set("big", vec![0u8; 200]);
+        std::thread::sleep(Duration::from_millis(100));
+        monitor.stop();
+
+        assert_eq!(raised.load(Ordering::SeqCst), 1);
+        assert_eq!(cache.stats().callback_panics, 1);
+        assert_eq!(
+            cache.health().memory_bytes,
+            Some(3 + 200 + Cache::ENTRY_OVERHEAD)
+        );
+    }
+
+    #[test]
+    fn test_try_get_reports_corruption() {
+        use crate::error::CacheError;
+        use crate::ops::CacheOps;
+
+        let cache = Cache::new(CacheConfig::new().checksum_values(true));
+        cache.set("key", "value");
+        cache.db.corrupt_for_test("key");
+
+        let ops: &dyn CacheOps = &cache;
+        assert!(matches!(
+            ops.try_get("key"),
+            Err(CacheError::Corrupted { .. })
+        ));
+        assert_eq!(cache.get("key"), None);
+        assert_eq!(cache.stats().checksum_failures, 1);
+    }
+
+    #[test]
+    fn test_cache_basic_operations() {
+        let cache = Cache::default();
+
+        cache.set("key", "value");
+        assert_eq!(cache.get("key"), Some(Bytes::from("value")));
+        assert!(cache.contains("key"));
+
+        cache.delete("key");
+        assert!(!cache.contains("key"));
+    }
+
+    #[test]
+    fn test_cache_is_clone() {
+        let cache1 = Cache::default();
+        cache1.set("key", "value");
+
+        let cache2 = cache1.clone();
+
+        // Both point to the same underlying data
+        assert_eq!(cache2.get("key"), Some(Bytes::from("value")));
+
+        cache2.set("key2", "value2");
+        assert_eq!(cache1.get("key2"), Some(Bytes::from("value2")));
+    }
+
+    #[test]
+    fn test_cache_stats() {
+        let cache = Cache::default();
 
         cache.set("key", "value");
         let _ = cache.get("key");
         let _ = cache.get("missing");
 
         let stats = cache.stats();
-        assert_eq!(stats.hits, 1);
-        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 1);
+    }
+
+    #[test]
+    fn test_sorted_snapshots_ignore_insertion_and_access_order() {
+        let first = Cache::default();
+        let second = Cache::default();
+
+        for key in ["c", "a", "b"] {
+            first.set(key, format!("v_{}", key));
+        }
+        for key in ["b", "c", "a"] {
+            second.set(key, format!("v_{}", key));
+        }
+        // Reorder LRU position in one of them
+        let _ = second.get("b");
+
+        assert_eq!(first.keys_sorted(), vec!["a", "b", "c"]);
+        assert_eq!(first.keys_sorted(), second.keys_sorted());
+        assert_eq!(first.iter_sorted(), second.iter_sorted());
+    }
+
+    #[test]
+    fn test_sorted_snapshots_skip_expired() {
+        let (config, clock) = mock_clock(CacheConfig::new());
+        let cache = Cache::new(config);
+        cache.set("live", "1");
+        cache.set_with_ttl("gone", "2", Duration::from_millis(1));
+        clock.advance(Duration::from_millis(10));
+
+        assert_eq!(cache.keys_sorted(), vec!["live"]);
+        assert_eq!(cache.iter_sorted().len(), 1);
+    }
+
+    #[test]
+    fn test_start_cleanup_follows_config() {
+        assert!(Cache::default().start_cleanup().is_none());
+        let disabled = CacheConfig::new()
+            .background_cleanup(true)
+            .cleanup_interval(Duration::ZERO);
+        assert!(Cache::new(disabled).start_cleanup().is_none());
+
+        let cache = Cache::new(
+            CacheConfig::new()
+                .background_cleanup(true)
+                .cleanup_interval(Duration::from_millis(5)),
+        );
+        cache.set_with_ttl("gone", "1", Duration::from_millis(1));
+        cache.set("live", "2");
+        let cleanup = cache.start_cleanup().unwrap();
+        assert_eq!(cache.stats().cleanup_interval_ms, 5);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while cache.stats().expirations == 0 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        cleanup.stop();
+        // A fixed interval does not adapt
+        let stats = cache.stats();
+        assert_eq!(stats.expirations, 1);
+        assert_eq!(stats.cleanup_interval_ms, 0);
+        assert_eq!(cache.keys_sorted(), vec!["live"]);
+        // The task is handed out once
+        assert!(cache.start_cleanup().is_none());
+    }
+
+    #[test]
+    fn test_background_cleanup_runs_without_access() {
+        let cache = Cache::new(
+            CacheConfig::new()
+                .background_cleanup(true)
+                .cleanup_interval(Duration::from_millis(5)),
+        );
+        cache.set_with_ttl("gone", "1", Duration::from_millis(1));
+        cache.set("live", "2");
+        assert_eq!(cache.stats().cleanup_interval_ms, 5);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while cache.len() > 1 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.stats().expirations, 1);
+
+        // Clones share the task: it runs until the last one is dropped
+        let clone = cache.clone();
+        drop(cache);
+        clone.set_with_ttl("gone", "1", Duration::from_millis(1));
+        while clone.len() > 1 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(clone.stats().expirations, 2);
+    }
+
+    #[test]
+    fn test_find_stops_at_first_match() {
+        let cache = Cache::new(CacheConfig::new().shards(1));
+        for i in 0..100 {
+            cache.set(format!("key{}", i), i.to_string());
+        }
+        cache.set_with_ttl("key_expired", "match", Duration::ZERO);
+
+        let mut visited = 0;
+        let found = cache.find(|_, value| {
+            visited += 1;
+            value.as_ref() == b"2"
+        });
+        assert_eq!(found, Some(("key2".to_string(), Bytes::from("2"))));
+        assert_eq!(visited, 3);
+
+        // Expired entries are never offered, so this scans everything
+        let mut visited = 0;
+        assert!(cache
+            .find(|_, value| {
+                visited += 1;
+                value.as_ref() == b"match"
+            })
+            .is_none());
+        assert_eq!(visited, 100);
+
+        // Nothing was promoted or counted
+        assert_eq!(cache.stats().hits, 0);
+        let mut first = String::new();
+        cache.for_each_while(|key, _| {
+            first = key.to_string();
+            ControlFlow::Break(())
+        });
+        assert_eq!(first, "key0");
+    }
+
+    #[test]
+    fn test_for_each_while_breaks_early() {
+        let cache = Cache::default();
+        for i in 0..10 {
+            cache.set(format!("key{}", i), "v");
+        }
+
+        let mut visited = 0;
+        cache.for_each_while(|_, _| {
+            visited += 1;
+            if visited == 4 {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        assert_eq!(visited, 4);
+
+        let mut visited = 0;
+        cache.for_each_while(|_, _| {
+            visited += 1;
+            ControlFlow::Continue(())
+        });
+        assert_eq!(visited, 10);
+    }
+
+    #[test]
+    fn test_peek_does_not_save_the_next_victim() {
+        let (config, clock) = mock_clock(CacheConfig::new().max_capacity(3));
+        let cache = Cache::new(config);
+        cache.set("a", "1");
+        cache.set("b", "2");
+        cache.set("c", "3");
+        for _ in 0..10 {
+            assert_eq!(cache.peek("a"), Some(Bytes::from("1")));
+        }
+        assert_eq!(cache.peek("missing"), None);
+
+        cache.set("d", "4");
+        assert_eq!(cache.keys(), vec!["b", "c", "d"]);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (0, 0, 1));
+
+        cache.set_with_ttl("short", "x", Duration::from_millis(1));
+        clock.advance(Duration::from_millis(10));
+        assert_eq!(cache.peek("short"), None);
+    }
+
+    #[test]
+    fn test_retain() {
+        let (config, clock) = mock_clock(CacheConfig::new());
+        let cache = Cache::new(config);
+        for i in 0..10 {
+            cache.set(format!("key:{}", i), i.to_string());
+        }
+        cache.set_with_ttl("expired", "0", Duration::from_millis(1));
+        clock.advance(Duration::from_millis(10));
+
+        let mut seen = 0;
+        let removed = cache.retain(|_, value| {
+            seen += 1;
+            value.as_ref()[0] % 2 == 0
+        });
+        // The expired entry is removed without consulting the predicate
+        assert_eq!((removed, seen), (6, 10));
+        assert_eq!(cache.len(), 5);
+        let stats = cache.stats();
+        assert_eq!((stats.deletes, stats.expirations), (5, 1));
+        assert_eq!(cache.retain(|_, _| true), 0);
+    }
+
+    #[test]
+    fn test_retain_panic_removes_nothing() {
+        let cache = Cache::default();
+        cache.set("a", "1");
+        cache.set("b", "2");
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            cache.retain(|key, _| if key == "b" { panic!("boom") } else { false })
+        }));
+        assert!(result.is_err());
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.retain(|key, _| key == "b"), 1);
+    }
+
+    #[test]
+    fn test_touch() {
+        let (config, clock) = mock_clock(CacheConfig::new().max_capacity(3));
+        let cache = Cache::new(config);
+        cache.set("a", "1");
+        cache.set("b", "2");
+        cache.set("c", "3");
+        assert!(cache.touch("a"));
+        assert_eq!(cache.keys(), vec!["b", "c", "a"]);
+        assert!(!cache.touch("missing"));
+
+        cache.set_with_ttl("b", "2", Duration::from_millis(1));
+        clock.advance(Duration::from_millis(10));
+        assert!(!cache.touch("b"));
+        assert_eq!(cache.len(), 2);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.expirations), (0, 0, 1));
+    }
+
+    #[test]
+    fn test_entry_info() {
+        let (config, clock) = mock_clock(CacheConfig::new().max_capacity(2));
+        let cache = Cache::new(config);
+        cache.set("a", "123");
+        cache.set_with_ttl("b", "4", Duration::from_secs(60));
+        clock.advance(Duration::from_millis(20));
+        cache.get("b");
+
+        let a = cache.entry_info("a").unwrap();
+        assert!(a.created_at_age >= Duration::from_millis(20));
+        assert!(a.idle >= Duration::from_millis(20));
+        assert_eq!((a.ttl_remaining, a.size_bytes), (None, 3));
+        let b = cache.entry_info("b").unwrap();
+        assert!(b.idle < a.idle);
+        assert!(b.ttl_remaining.unwrap() <= Duration::from_secs(60));
+
+        // Not an access: "a" is still the next victim
+        assert_eq!(cache.keys(), vec!["a", "b"]);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 0));
+
+        cache.set_with_ttl("b", "4", Duration::from_millis(1));
+        clock.advance(Duration::from_millis(10));
+        assert_eq!(cache.entry_info("b"), None);
+        assert_eq!(cache.entry_info("missing"), None);
+    }
+
+    #[test]
+    fn test_expire_and_persist() {
+        let (config, clock) = mock_clock(CacheConfig::new().max_capacity(2));
+        let cache = Cache::new(config);
+        cache.set("a", "1");
+        cache.set("b", "2");
+        assert!(cache.expire("a", Duration::from_millis(5)));
+        assert!(cache.ttl("a").unwrap().is_some());
+        // Not a write: "a" stays the least recently used
+        assert_eq!(cache.keys(), vec!["a", "b"]);
+
+        assert!(cache.persist("a"));
+        assert_eq!(cache.ttl("a"), Some(None));
+        assert!(cache.persist("a"));
+
+        assert!(cache.expire("b", Duration::from_millis(1)));
+        clock.advance(Duration::from_millis(10));
+        assert!(!cache.persist("b"));
+        assert!(!cache.expire("b", Duration::from_secs(60)));
+        assert!(!cache.expire("missing", Duration::from_secs(60)));
+        assert_eq!(cache.stats().sets, 2);
+    }
+
+    #[test]
+    fn test_take() {
+        let (config, clock) = mock_clock(CacheConfig::new());
+        let cache = Cache::new(config);
+        cache.set("token", "secret");
+        assert_eq!(cache.take("token"), Some(Bytes::from("secret")));
+        assert_eq!(cache.take("token"), None);
+        assert!(!cache.contains("token"));
+
+        cache.set_with_ttl("short", "x", Duration::from_millis(1));
+        clock.advance(Duration::from_millis(10));
+        assert_eq!(cache.take("short"), None);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 2));
+        assert_eq!((stats.deletes, stats.expirations), (1, 1));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_take_has_one_winner() {
+        use std::sync::Barrier;
+
+        let cache = Cache::default();
+        for round in 0..20 {
+            let key = format!("job:{}", round);
+            cache.set(key.clone(), "payload");
+            let barrier = Arc::new(Barrier::new(4));
+            let handles: Vec<_> = (0..4)
+                .map(|_| {
+                    let (cache, barrier, key) = (cache.clone(), Arc::clone(&barrier), key.clone());
+                    std::thread::spawn(move || {
+                        barrier.wait();
+                        cache.take(&key).is_some()
+                    })
+                })
+                .collect();
+            let winners = handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .filter(|&won| won)
+                .count();
+            assert_eq!(winners, 1);
+        }
+    }
+
+    #[test]
+    fn test_delete_many() {
+        let cache = Cache::default();
+        cache.set("a", "1");
+        cache.set("b", "2");
+        cache.set("c", "3");
+
+        let keys = vec![
+            "a".to_string(),
+            "missing".to_string(),
+            "b".to_string(),
+            "a".to_string(),
+        ];
+        assert_eq!(cache.delete_many(&keys), 2);
+        assert_eq!(cache.keys_sorted(), vec!["c"]);
+        assert_eq!(cache.stats().deletes, 2);
+        assert_eq!(cache.delete_many::<&str>(&[]), 0);
+    }
+
+    #[test]
+    fn test_max_memory_evicts_lru() {
+        // Each entry is a 1-byte key and a 9-byte value
+        let cache = Cache::new(CacheConfig::new().max_memory(30));
+        for key in ["a", "b", "c"] {
+            cache.set(key, "123456789");
+        }
+        assert_eq!(cache.stats().memory_bytes, 30);
+        cache.get("a");
+        cache.set("d", "123456789");
+        assert_eq!(cache.keys(), vec!["c", "a", "d"]);
+        assert_eq!(cache.stats().evictions, 1);
+
+        // Growing "a" in place needs room for the difference only, and the
+        // entry being written is never the victim
+        cache.set("a", "1234567890123456789");
+        assert_eq!(cache.keys(), vec!["a", "d"]);
+        assert_eq!(cache.stats().memory_bytes, 30);
+        // Shrinking frees room
+        cache.set("a", "1");
+        cache.set("e", "123456789");
+        assert_eq!(cache.keys(), vec!["a", "d", "e"]);
+
+        cache.delete("d");
+        cache.set_with_ttl("e", "123456789", Duration::from_millis(1));
+        std::thread::sleep(Duration::from_millis(5));
+        cache.cleanup_expired();
+        assert_eq!(cache.stats().memory_bytes, 2);
+        cache.debug_validate().unwrap();
+    }
+
+    #[test]
+    fn test_memory_bytes_across_overwrites_and_clear() {
+        let cache = Cache::default();
+        assert_eq!(cache.stats().average_entry_size, 0.0);
+
+        cache.set("a", "12345678");
+        cache.set("bb", "12");
+        assert_eq!(cache.stats().memory_bytes, 13);
+        assert_eq!(cache.stats().average_entry_size, 6.5);
+        // Overwrites count only the new value
+        cache.set("a", "1");
+        cache.set("a", "123456789012345");
+        cache.set("bb", "");
+        let stats = cache.stats();
+        assert_eq!((stats.memory_bytes, stats.size), (18, 2));
+        assert_eq!(stats.average_entry_size, 9.0);
+
+        cache.clear();
+        let stats = cache.stats();
+        assert_eq!((stats.memory_bytes, stats.size), (0, 0));
+        assert_eq!(stats.average_entry_size, 0.0);
+        cache.set("a", "1");
+        assert_eq!(cache.stats().memory_bytes, 2);
+    }
+
+    #[test]
+    fn test_max_memory_and_max_capacity_together() {
+        let cache = Cache::new(CacheConfig::new().max_capacity(2).max_memory(1000));
+        for key in ["a", "b", "c"] {
+            cache.set(key, "1");
+        }
+        assert_eq!(cache.keys(), vec!["b", "c"]);
+
+        let cache = Cache::new(CacheConfig::new().max_capacity(10).max_memory(20));
+        for key in ["a", "b", "c"] {
+            cache.set(key, "123456789");
+        }
+        assert_eq!(cache.keys(), vec!["b", "c"]);
+        cache.debug_validate().unwrap();
+    }
+
+    #[test]
+    fn test_value_larger_than_max_memory_stands_alone() {
+        let cache = Cache::new(CacheConfig::new().max_memory(10));
+        cache.set("a", "1");
+        cache.set("b", "2");
+        cache.set("big", "0123456789");
+        assert_eq!(cache.keys(), vec!["big"]);
+        assert_eq!(cache.stats().memory_bytes, 13);
+        cache.debug_validate().unwrap();
+        cache.set("a", "1");
+        assert_eq!(cache.keys(), vec!["a"]);
+    }
+
+    #[test]
+    fn test_transaction_respects_max_memory() {
+        let cache = Cache::new(CacheConfig::new().max_memory(20));
+        cache.set("x", "123456789");
+        cache.set("y", "123456789");
+        cache
+            .transaction(&["a", "y"], |txn| {
+                txn.set("a", "12345")?;
+                txn.set("y", "1234567890")
+            })
+            .unwrap();
+        // "x" made room; the declared "y" was kept although it is older
+        assert_eq!(cache.keys_sorted(), vec!["a", "y"]);
+        cache.debug_validate().unwrap();
+
+        let result = cache.transaction(&["big"], |txn| txn.set("big", "0123456789012345678"));
+        assert!(matches!(
+            result,
+            Err(CacheError::CapacityExceeded {
+                current: 22,
+                max: 20
+            })
+        ));
+        assert_eq!(cache.keys_sorted(), vec!["a", "y"]);
+    }
+
+    #[test]
+    fn test_max_weight_defaults_to_one_per_entry() {
+        let cache = Cache::new(CacheConfig::new().max_weight(2));
+        for key in ["a", "b", "c"] {
+            cache.set(key, "a longer value");
+        }
+        assert_eq!(cache.keys(), vec!["b", "c"]);
+        assert_eq!(cache.stats().total_weight, 2);
+    }
+
+    #[test]
+    fn test_weigher_overwrites_reweigh() {
+        let weigher: crate::Weigher = Arc::new(|_, value| value.len() as u32);
+        let cache = Cache::new(CacheConfig::new().weigher(weigher).max_weight(10));
+        cache.set("a", "aaaa");
+        cache.set("b", "bbbb");
+        // Heavier, but the difference fits
+        cache.set("a", "aaaaaa");
+        assert_eq!(cache.stats().total_weight, 10);
+        assert_eq!(cache.keys(), vec!["a", "b"]);
+
+        // Heavier by more than the room left: the LRU entry other than the
+        // one being written goes
+        cache.set("b", "bbbbb");
+        assert_eq!(cache.keys(), vec!["b"]);
+        assert_eq!(cache.stats().total_weight, 5);
+
+        // Lighter: frees weight for others
+        cache.set("b", "b");
+        cache.set("c", "ccccccccc");
+        assert_eq!(cache.keys(), vec!["b", "c"]);
+        assert_eq!(cache.stats().total_weight, 10);
+        assert_eq!(cache.entry_info("c").unwrap().size_bytes, 9);
+
+        cache.delete("c");
+        assert_eq!(cache.stats().total_weight, 1);
+        cache.debug_validate().unwrap();
+    }
+
+    #[test]
+    fn test_panicking_weigher_weighs_one() {
+        let weigher: crate::Weigher = Arc::new(|key, _| {
+            if key == "bad" {
+                panic!("cannot weigh");
+            }
+            5
+        });
+        let cache = Cache::new(CacheConfig::new().weigher(weigher));
+        cache.set("good", "v");
+        cache.set("bad", "v");
+        assert_eq!(cache.get("bad"), Some(Bytes::from("v")));
+        let stats = cache.stats();
+        assert_eq!((stats.total_weight, stats.callback_panics), (6, 1));
+    }
+
+    #[test]
+    fn test_transaction_respects_max_weight() {
+        let weigher: crate::Weigher = Arc::new(|_, value| value.len() as u32);
+        let cache = Cache::new(CacheConfig::new().weigher(weigher).max_weight(10));
+        cache.set("x", "xxxxx");
+        cache.set("y", "yyyyy");
+        cache
+            .transaction(&["y", "z"], |txn| txn.set("z", "zzz"))
+            .unwrap();
+        assert_eq!(cache.keys_sorted(), vec!["y", "z"]);
+
+        let result = cache.transaction(&["big"], |txn| txn.set("big", "0123456789a"));
+        assert!(matches!(
+            result,
+            Err(CacheError::CapacityExceeded {
+                current: 11,
+                max: 10
+            })
+        ));
+        cache.debug_validate().unwrap();
+    }
+
+    #[test]
+    fn test_lru_is_the_default_policy() {
+        let cache = Cache::new(CacheConfig::new().max_capacity(2));
+        cache.set("a", "1");
+        cache.set("b", "2");
+        cache.get("a");
+        cache.set("c", "3");
+        assert_eq!(cache.keys_sorted(), vec!["a", "c"]);
+    }
+
+    #[test]
+    fn test_lfu_evicts_least_used() {
+        let config = CacheConfig::new()
+            .max_capacity(3)
+            .eviction_policy(crate::EvictionPolicy::Lfu);
+        let cache = Cache::new(config);
+        cache.set("a", "1");
+        cache.set("b", "2");
+        cache.set("c", "3");
+        for _ in 0..3 {
+            cache.get("a");
+        }
+        cache.get("b");
+        cache.get("c");
+        // "b" and "c" tie on one read; "b" was read less recently
+        cache.set("d", "4");
+        assert_eq!(cache.keys_sorted(), vec!["a", "c", "d"]);
+
+        // The newcomer has no reads yet
+        cache.set("e", "5");
+        assert_eq!(cache.keys_sorted(), vec!["a", "c", "e"]);
+
+        // An overwrite keeps the count of the value it replaces
+        cache.set("a", "updated");
+        cache.set("f", "6");
+        assert!(cache.contains("a"));
+    }
+
+    #[test]
+    fn test_lfu_keeps_hot_keys_through_a_scan() {
+        let config = CacheConfig::new()
+            .max_capacity(10)
+            .eviction_policy(crate::EvictionPolicy::Lfu);
+        let cache = Cache::new(config);
+        for i in 0..5 {
+            let key = format!("hot:{}", i);
+            cache.set(key.as_str(), "v");
+            for _ in 0..3 {
+                cache.get(&key);
+            }
+        }
+        for i in 0..100 {
+            let key = format!("scan:{}", i);
+            cache.set(key.as_str(), "v");
+            cache.get(&key);
+        }
+        for i in 0..5 {
+            assert!(cache.contains(&format!("hot:{}", i)));
+        }
+        assert_eq!(cache.len(), 10);
+    }
+
+    #[test]
+    fn test_fifo_ignores_reads() {
+        let config = CacheConfig::new()
+            .max_capacity(2)
+            .eviction_policy(crate::EvictionPolicy::Fifo);
+        let cache = Cache::new(config);
+        cache.set("a", "1");
+        cache.set("b", "2");
+        cache.get("a");
+        cache.touch("a");
+        cache.set("a", "updated");
+        cache.set("c", "3");
+        assert_eq!(cache.keys_sorted(), vec!["b", "c"]);
+
+        // Limits other than the capacity evict in the same order
+        let config = CacheConfig::new()
+            .max_weight(2)
+            .eviction_policy(crate::EvictionPolicy::Fifo);
+        let cache = Cache::new(config);
+        cache.set("a", "1");
+        cache.set("b", "2");
+        cache.get("a");
+        cache.set("c", "3");
+        assert_eq!(cache.keys_sorted(), vec!["b", "c"]);
+    }
+
+    #[test]
+    fn test_delete_prefix() {
+        let cache = Cache::new(CacheConfig::new().max_capacity(4));
+        cache.set("user:1:profile", "a");
+        cache.set("other", "x");
+        cache.set("user:1:settings", "b");
+        cache.set_with_ttl("user:1:session", "c", Duration::from_secs(60));
+
+        assert_eq!(cache.delete_prefix("user:1:"), 3);
+        assert_eq!(cache.keys(), vec!["other"]);
+        assert_eq!(cache.delete_prefix("user:1:"), 0);
+        let stats = cache.stats();
+        assert_eq!((stats.deletes, stats.size), (3, 1));
+
+        // Freed slots are usable without evicting the survivor
+        for i in 0..3 {
+            cache.set(format!("user:2:{}", i), "v");
+        }
+        assert_eq!(cache.len(), 4);
+        assert_eq!(cache.delete_prefix(""), 4);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_set_many_evicts_in_lru_order() {
+        let cache = Cache::new(CacheConfig::new().max_capacity(3));
+        cache.set("old", "x");
+        cache.set("recent", "y");
+        cache.get("old");
+
+        let pairs = (0..3).map(|i| (format!("new:{}", i), Bytes::from(i.to_string())));
+        cache.set_many(pairs);
+        // "recent" was the least recently used, then "old"
+        assert_eq!(cache.keys_sorted(), vec!["new:0", "new:1", "new:2"]);
+        assert_eq!(cache.stats().evictions, 2);
+
+        cache.set_many(vec![
+            ("new:0".to_string(), Bytes::from("a")),
+            ("new:0".to_string(), Bytes::from("b")),
+        ]);
+        assert_eq!(cache.get("new:0"), Some(Bytes::from("b")));
+        assert_eq!(cache.stats().sets, 7);
+    }
+
+    #[test]
+    fn test_set_many_with_ttl() {
+        let (config, clock) = mock_clock(CacheConfig::new());
+        let cache = Cache::new(config);
+        cache.set_many_with_ttl(vec![
+            ("a".to_string(), Bytes::from("1"), Duration::from_secs(30)),
+            ("b".to_string(), Bytes::from("2"), Duration::from_millis(1)),
+        ]);
+        clock.advance(Duration::from_millis(10));
+        assert!(cache.ttl("a").unwrap().unwrap() <= Duration::from_secs(30));
+        assert!(!cache.contains("b"));
+    }
+
+    #[test]
+    fn test_get_many_in_request_order() {
+        let (config, clock) = mock_clock(CacheConfig::new().max_capacity(3));
+        let cache = Cache::new(config);
+        cache.set("a", "1");
+        cache.set("b", "2");
+        cache.set_with_ttl("gone", "x", Duration::from_millis(1));
+        clock.advance(Duration::from_millis(10));
+
+        let keys = vec![
+            "b".to_string(),
+            "missing".to_string(),
+            "a".to_string(),
+            "gone".to_string(),
+        ];
+        assert_eq!(
+            cache.get_many(&keys),
+            vec![Some(Bytes::from("2")), None, Some(Bytes::from("1")), None]
+        );
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.expirations), (2, 2, 1));
+
+        // Both hits were promoted, in request order, so "b" is evicted first
+        cache.set("c", "3");
+        cache.set("d", "4");
+        assert_eq!(cache.keys_sorted(), vec!["a", "c", "d"]);
+    }
+
+    #[test]
+    fn test_append() {
+        let cache = Cache::default();
+        cache.set_with_ttl("log", "a", Duration::from_secs(30));
+        assert_eq!(cache.append("log", "bc"), 3);
+        assert_eq!(cache.get("log"), Some(Bytes::from("abc")));
+        assert!(cache.ttl("log").unwrap().unwrap() <= Duration::from_secs(30));
+        assert_eq!(cache.stats().sets, 2);
+
+        assert_eq!(cache.append("new", "x"), 1);
+        assert_eq!(cache.ttl("new"), Some(None));
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let cache = cache.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        cache.append("shared", ".");
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(cache.get("shared").unwrap().len(), 400);
+    }
+
+    #[test]
+    fn test_increment_and_decrement() {
+        let cache = Cache::new(CacheConfig::new().default_ttl(Duration::from_secs(60)));
+        assert_eq!(cache.increment("n", 5).unwrap(), 5);
+        assert!(cache.ttl("n").unwrap().is_some());
+        assert_eq!(cache.decrement("n", 7).unwrap(), -2);
+        assert_eq!(cache.get("n"), Some(Bytes::from("-2")));
+
+        // An existing counter keeps its TTL
+        cache.set_with_ttl("m", "1", Duration::from_secs(5));
+        assert_eq!(cache.increment("m", 1).unwrap(), 2);
+        assert!(cache.ttl("m").unwrap().unwrap() <= Duration::from_secs(5));
+
+        cache.set("text", "abc");
+        assert!(matches!(
+            cache.increment("text", 1),
+            Err(CacheError::InvalidValue(_))
+        ));
+        assert_eq!(cache.get("text"), Some(Bytes::from("abc")));
+
+        cache.set("max", i64::MAX.to_string());
+        assert!(matches!(
+            cache.increment("max", 1),
+            Err(CacheError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            cache.decrement("n", i64::MIN),
+            Err(CacheError::InvalidValue(_))
+        ));
+    }
+
+    #[test]
+    fn test_increments_are_not_lost() {
+        let cache = Cache::default();
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let cache = cache.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..500 {
+                        cache.increment("hits", 1).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(cache.get("hits"), Some(Bytes::from("2000")));
+    }
+
+    #[test]
+    fn test_compare_and_swap() {
+        let (config, clock) = mock_clock(CacheConfig::new());
+        let cache = Cache::new(config);
+        cache.set_with_ttl("key", "a", Duration::from_secs(60));
+        assert!(matches!(
+            cache.compare_and_swap("key", b"b", "c"),
+            Ok(false)
+        ));
+        assert_eq!(cache.get("key"), Some(Bytes::from("a")));
+
+        assert!(matches!(cache.compare_and_swap("key", b"a", "c"), Ok(true)));
+        assert_eq!(cache.get("key"), Some(Bytes::from("c")));
+        assert!(cache.ttl("key").unwrap().unwrap() <= Duration::from_secs(60));
+
+        assert!(matches!(
+            cache.compare_and_swap("missing", b"", "x"),
+            Err(CacheError::KeyNotFound(key)) if key == "missing"
+        ));
+        cache.set_with_ttl("short", "a", Duration::from_millis(5));
+        clock.advance(Duration::from_millis(20));
+        assert!(matches!(
+            cache.compare_and_swap("short", b"a", "b"),
+            Err(CacheError::KeyNotFound(_))
+        ));
+        assert!(!cache.contains("short"));
+    }
+
+    #[test]
+    fn test_compare_and_swap_loops_do_not_lose_updates() {
+        let cache = Cache::default();
+        cache.set("counter", "0");
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let cache = cache.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..250 {
+                        loop {
+                            let current = cache.get("counter").unwrap();
+                            let n: u64 = std::str::from_utf8(&current).unwrap().parse().unwrap();
+                            let next = (n + 1).to_string();
+                            if cache.compare_and_swap("counter", &current, next).unwrap() {
+                                break;
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(cache.get("counter"), Some(Bytes::from("1000")));
+    }
+
+    #[test]
+    fn test_set_if_absent_has_one_winner() {
+        use std::sync::Barrier;
+
+        let cache = Cache::default();
+        let barrier = Arc::new(Barrier::new(8));
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let cache = cache.clone();
+                let barrier = Arc::clone(&barrier);
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    cache.set_if_absent("lock", format!("owner {}", i))
+                })
+            })
+            .collect();
+        let winners = handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .filter(|&won| won)
+            .count();
+        assert_eq!(winners, 1);
+        assert_eq!(cache.stats().sets, 1);
+    }
+
+    #[test]
+    fn test_set_if_absent_replaces_expired() {
+        let (config, clock) = mock_clock(CacheConfig::new());
+        let cache = Cache::new(config);
+        assert!(cache.set_if_absent_with_ttl("lock", "a", Duration::from_millis(5)));
+        assert!(!cache.set_if_absent_with_ttl("lock", "b", Duration::from_secs(60)));
+        clock.advance(Duration::from_millis(20));
+
+        assert!(cache.set_if_absent("lock", "c"));
+        assert_eq!(cache.get("lock"), Some(Bytes::from("c")));
+        assert_eq!(cache.ttl("lock"), Some(None));
+        assert_eq!(cache.stats().expirations, 1);
+    }
+
+    #[test]
+    fn test_get_or_insert_with_runs_init_once() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Barrier;
+
+        let cache = Cache::default();
+        let init_calls = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(2));
+        let handles: Vec<_> = (0..2)
+            .map(|i| {
+                let cache = cache.clone();
+                let init_calls = Arc::clone(&init_calls);
+                let barrier = Arc::clone(&barrier);
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    cache.get_or_insert_with("slot", || {
+                        init_calls.fetch_add(1, Ordering::SeqCst);
+                        Bytes::from(format!("thread {}", i))
+                    })
+                })
+            })
+            .collect();
+        let values: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(init_calls.load(Ordering::SeqCst), 1);
+        assert_eq!(values[0], values[1]);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.sets), (1, 1, 1));
+    }
+
+    #[test]
+    fn test_get_or_insert_with_ttl_and_expiry() {
+        let (config, clock) = mock_clock(CacheConfig::new().default_ttl(Duration::from_secs(60)));
+        let cache = Cache::new(config);
+        cache.get_or_insert_with("default", || Bytes::from("a"));
+        assert!(cache.ttl("default").unwrap().unwrap() <= Duration::from_secs(60));
+
+        cache.get_or_insert_with_ttl("short", Duration::from_millis(5), || Bytes::from("old"));
+        clock.advance(Duration::from_millis(20));
+        // The expired value is replaced, not returned
+        let value =
+            cache.get_or_insert_with_ttl("short", Duration::from_secs(5), || Bytes::from("new"));
+        assert_eq!(value, Bytes::from("new"));
+        assert_eq!(cache.stats().expirations, 1);
+    }
+
+    #[test]
+    fn test_expirations_and_sets_split_by_kind() {
+        let (config, clock) = mock_clock(CacheConfig::new());
+        let cache = Cache::new(config);
+        cache.set("a", "1");
+        cache.set("a", "2");
+        cache.set_with_ttl("lazy", "v", Duration::from_millis(5));
+        cache.set_with_ttl("swept", "v", Duration::from_millis(5));
+        cache.set_with_ttl("rewritten", "v", Duration::from_millis(5));
+        clock.advance(Duration::from_millis(10));
+
+        assert_eq!(cache.get("lazy"), None);
+        // Writing over an expired entry is an insert and a lazy expiration
+        cache.set("rewritten", "w");
+        assert_eq!(cache.cleanup_expired(), 1);
+
+        let stats = cache.stats();
+        assert_eq!((stats.inserts, stats.overwrites, stats.sets), (5, 1, 6));
+        assert_eq!((stats.expirations_lazy, stats.expirations_swept), (2, 1));
+        assert_eq!(stats.expirations, 3);
+    }
+
+    #[test]
+    fn test_get_or_insert_with_panic_stores_nothing() {
+        let cache = Cache::default();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            cache.get_or_insert_with("slot", || panic!("init failed"))
+        }));
+        assert!(result.is_err());
+        assert!(!cache.contains("slot"));
+
+        // The lock is not poisoned
+        assert_eq!(
+            cache.get_or_insert_with("slot", || Bytes::from("v")),
+            Bytes::from("v")
+        );
+    }
+
+    #[test]
+    fn test_get_or_load_collapses_and_caches() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Barrier;
+
+        let cache = Cache::default();
+        let backend_calls = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(8));
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let cache = cache.clone();
+                let backend_calls = Arc::clone(&backend_calls);
+                let barrier = Arc::clone(&barrier);
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    cache.get_or_load("page", || {
+                        backend_calls.fetch_add(1, Ordering::SeqCst);
+                        std::thread::sleep(Duration::from_millis(50));
+                        Ok::<_, ()>((Bytes::from("body"), Some(Duration::from_secs(60))))
+                    })
+                })
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), Ok(Bytes::from("body")));
+        }
+        assert_eq!(backend_calls.load(Ordering::SeqCst), 1);
+
+        // Later calls are plain hits
+        let result = cache.get_or_load("page", || -> Result<_, ()> { unreachable!() });
+        assert_eq!(result, Ok(Bytes::from("body")));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_get_or_load_does_not_cache_errors() {
+        let cache = Cache::default();
+        let result = cache.get_or_load("page", || Err::<(Bytes, Option<Duration>), _>("down"));
+        assert_eq!(result, Err("down"));
+        assert!(!cache.contains("page"));
+
+        let result = cache.get_or_load("page", || Ok::<_, &str>((Bytes::from("up"), None)));
+        assert_eq!(result, Ok(Bytes::from("up")));
+    }
+
+    #[test]
+    fn test_outcomes_use_their_own_ttls() {
+        let cache = Cache::default();
+        let (ok_ttl, err_ttl) = (Duration::from_secs(300), Duration::from_secs(5));
+        cache.set_outcome("ok", Ok(Bytes::from("42")), ok_ttl, err_ttl);
+        cache.set_outcome("err", Err(Bytes::from("503")), ok_ttl, err_ttl);
+        cache.set("plain", "42");
+
+        assert_eq!(cache.get_outcome("ok"), Some(Ok(Bytes::from("42"))));
+        assert_eq!(cache.get_outcome("err"), Some(Err(Bytes::from("503"))));
+        assert_eq!(cache.get_outcome("plain"), None);
+        assert_eq!(cache.get_outcome("missing"), None);
+        assert!(cache.ttl("ok").unwrap().unwrap() > Duration::from_secs(5));
+        assert!(cache.ttl("err").unwrap().unwrap() <= Duration::from_secs(5));
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.error_hits, stats.misses), (3, 1, 1));
+        cache.reset_stats();
+        assert_eq!(cache.stats().error_hits, 0);
+    }
+
+    #[test]
+    fn test_get_or_try_insert_with_caches_failures() {
+        let calls = std::cell::Cell::new(0);
+        let failing = || {
+            calls.set(calls.get() + 1);
+            Err::<Bytes, _>("upstream 503")
+        };
+
+        // Without error_ttl, every call runs the loader
+        let cache = Cache::default();
+        assert_eq!(
+            cache.get_or_try_insert_with("k", failing),
+            Err(TryInsertError::Loader("upstream 503"))
+        );
+        assert_eq!(
+            cache.get_or_try_insert_with("k", failing),
+            Err(TryInsertError::Loader("upstream 503"))
+        );
+        assert_eq!(calls.get(), 2);
+        assert!(cache.is_empty());
+
+        let cache = Cache::new(CacheConfig::new().error_ttl(Duration::from_millis(30)));
+        calls.set(0);
+        assert_eq!(
+            cache.get_or_try_insert_with("k", failing),
+            Err(TryInsertError::Loader("upstream 503"))
+        );
+        assert_eq!(
+            cache.get_or_try_insert_with("k", failing),
+            Err(TryInsertError::Cached(Bytes::from("upstream 503")))
+        );
+        assert_eq!(calls.get(), 1);
+        assert_eq!(cache.stats().error_hits, 1);
+        assert_eq!(
+            cache.get_outcome("k"),
+            Some(Err(Bytes::from("upstream 503")))
+        );
+
+        // Once the failure expires, the loader runs again
+        std::thread::sleep(Duration::from_millis(50));
+        let loaded = cache.get_or_try_insert_with("k", || Ok::<_, &str>(Bytes::from("v")));
+        assert_eq!(loaded, Ok(Bytes::from("v")));
+        assert_eq!(cache.get("k"), Some(Bytes::from("v")));
+        assert_eq!(cache.ttl("k"), Some(None));
+
+        // Stored outcomes are unpacked
+        cache.set_outcome(
+            "s",
+            Ok(Bytes::from("1")),
+            Duration::from_secs(60),
+            Duration::ZERO,
+        );
+        let served = cache.get_or_try_insert_with("s", || Err::<Bytes, _>("unused"));
+        assert_eq!(served, Ok(Bytes::from("1")));
+    }
+
+    #[test]
+    fn test_stuck_loader_is_visible_and_cancellable() {
+        let cache = Cache::default();
+        let (release, released) = std::sync::mpsc::channel::<()>();
+        let (started_tx, started) = std::sync::mpsc::channel::<()>();
+        let leader = {
+            let cache = cache.clone();
+            std::thread::spawn(move || {
+                cache.get_or_load_within("slow", Duration::from_secs(1), || {
+                    started_tx.send(()).unwrap();
+                    // Never completes on its own
+                    let _ = released.recv();
+                    Ok::<_, CacheError>((Bytes::from("late"), None))
+                })
+            })
+        };
+        started.recv().unwrap();
+
+        let in_flight = cache.in_flight();
+        assert_eq!(in_flight.len(), 1);
+        assert_eq!(in_flight[0].0, "slow");
+
+        let waited = cache.get_or_load_within("slow", Duration::from_millis(20), || {
+            Ok::<_, CacheError>((Bytes::from("own"), None))
+        });
+        assert!(
+            matches!(waited, Err(CacheError::Timeout(_))),
+            "{:?}",
+            waited
+        );
+        assert_eq!(cache.stats().loader_timeouts, 1);
+        assert!(cache.in_flight()[0].1 >= Duration::from_millis(20));
+
+        let waiter = {
+            let cache = cache.clone();
+            std::thread::spawn(move || {
+                cache.get_or_load_within("slow", Duration::from_secs(30), || {
+                    Ok::<_, CacheError>((Bytes::from("own"), None))
+                })
+            })
+        };
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(cache.cancel_in_flight("slow"));
+        match waiter.join().unwrap() {
+            Err(CacheError::LoadCancelled { key }) => assert_eq!(key, "slow"),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(cache.in_flight().is_empty());
+        assert!(!cache.cancel_in_flight("slow"));
+        assert_eq!(cache.stats().loader_timeouts, 1);
+
+        // The cancelled loader still finishes and stores its value
+        release.send(()).unwrap();
+        assert_eq!(leader.join().unwrap().unwrap(), Bytes::from("late"));
+        assert_eq!(cache.get("slow"), Some(Bytes::from("late")));
+    }
+
+    #[test]
+    fn test_transaction_is_never_observed_halfway() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let cache = Cache::default();
+        cache.set("hold:1", "seat");
+        let done = Arc::new(AtomicBool::new(false));
+
+        let reader = {
+            let cache = cache.clone();
+            let done = Arc::clone(&done);
+            std::thread::spawn(move || {
+                let mut reads = 0;
+                while !done.load(Ordering::SeqCst) || reads == 0 {
+                    let values = cache.multi_get(&["hold:1", "confirmed:1"]);
+                    let present = values.iter().filter(|v| v.is_some()).count();
+                    assert_eq!(present, 1, "saw intermediate state {:?}", values);
+                    reads += 1;
+                }
+            })
+        };
+
+        // Move the reservation back and forth while the reader watches
+        for i in 0..500 {
+            let (from, to) = if i % 2 == 0 {
+                ("hold:1", "confirmed:1")
+            } else {
+                ("confirmed:1", "hold:1")
+            };
+            cache
+                .transaction(&[from, to], |txn| {
+                    let seat = txn.get(from)?.expect("reservation present");
+                    txn.delete(from)?;
+                    txn.set(to, seat)
+                })
+                .unwrap();
+        }
+        done.store(true, Ordering::SeqCst);
+        reader.join().unwrap();
+        assert_eq!(cache.get("hold:1"), Some(Bytes::from("seat")));
+    }
+
+    #[test]
+    fn test_incr_window_resets_exactly_at_window_end() {
+        use crate::CacheError;
+
+        let cache = Cache::default();
+        let window = Duration::from_secs(10);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        assert_eq!(
+            cache.incr_window_at("hits", 1, window, start).unwrap(),
+            (1, window)
+        );
+        // Later increments keep the deadline set at creation
+        assert_eq!(
+            cache.incr_window_at("hits", 5, window, at(4_000)).unwrap(),
+            (6, Duration::from_millis(6_000))
+        );
+        assert_eq!(
+            cache.incr_window_at("hits", -2, window, at(9_999)).unwrap(),
+            (4, Duration::from_millis(1))
+        );
+        // The first increment at or past the deadline opens a new window
+        assert_eq!(
+            cache.incr_window_at("hits", 1, window, at(10_000)).unwrap(),
+            (1, window)
+        );
+        assert_eq!(
+            cache.incr_window_at("hits", 1, window, at(19_999)).unwrap(),
+            (2, Duration::from_millis(1))
+        );
+
+        cache.set("name", "alice");
+        assert!(matches!(
+            cache.incr_window_at("name", 1, window, start),
+            Err(CacheError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            cache.incr_window_at("other", 1, Duration::ZERO, start),
+            Err(CacheError::InvalidValue(_))
+        ));
+        cache
+            .incr_window_at("big", i64::MAX, window, start)
+            .unwrap();
+        assert!(cache.incr_window_at("big", 1, window, start).is_err());
+        assert_eq!(cache.get("big"), Some(Bytes::from(i64::MAX.to_string())));
+    }
+
+    #[test]
+    fn test_incr_window_ignores_grace_and_coalescing() {
+        let cache = Cache::new(
+            CacheConfig::new()
+                .expiration_grace(Duration::from_secs(5))
+                .coalesce_identical_writes(true),
+        );
+        let window = Duration::from_secs(1);
+        let start = Instant::now();
+
+        cache.incr_window_at("n", 0, window, start).unwrap();
+        // Same value, but past the deadline: a new window all the same
+        let later = start + Duration::from_secs(2);
+        assert_eq!(
+            cache.incr_window_at("n", 0, window, later).unwrap(),
+            (0, window)
+        );
+
+        // A counter created with a plain set gets a window on first use
+        cache.set("m", "7");
+        assert_eq!(
+            cache.incr_window_at("m", 1, window, start).unwrap(),
+            (8, window)
+        );
+    }
+
+    #[test]
+    fn test_rate_limit_fixed_window() {
+        let cache = Cache::default();
+        let window = Duration::from_secs(60);
+        let start = Instant::now();
+
+        for remaining in [2, 1, 0] {
+            let decision = cache.rate_limit_at("api:1", 3, window, start).unwrap();
+            assert!(decision.allowed);
+            assert_eq!(decision.remaining, remaining);
+        }
+
+        let at = start + Duration::from_secs(45);
+        let refused = cache.rate_limit_at("api:1", 3, window, at).unwrap();
+        assert!(!refused.allowed);
+        assert_eq!(refused.retry_after, Duration::from_secs(15));
+        // Other keys have their own window
+        assert!(cache.rate_limit_at("api:2", 3, window, at).unwrap().allowed);
+
+        // Refused calls do not extend the window
+        let reset = start + window;
+        let decision = cache.rate_limit_at("api:1", 3, window, reset).unwrap();
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 2);
+    }
+
+    #[test]
+    fn test_scan_keys_tolerates_mutation_between_pages() {
+        use std::collections::HashSet;
+
+        let cache = Cache::new(CacheConfig::new().max_capacity(200));
+        for i in 0..100 {
+            cache.set(format!("stable:{:03}", i), "v");
+            cache.set(format!("churn:{:03}", i), "v");
+        }
+
+        let mut seen = Vec::new();
+        let mut cursor = ScanCursor::start();
+        let mut pages = 0;
+        while !cursor.is_done() {
+            let (keys, next) = cache.scan_keys(cursor, 7);
+            assert!(keys.len() <= 7);
+            seen.extend(keys);
+            cursor = next;
+            pages += 1;
+
+            // Reads reorder the LRU list, writes insert, delete and evict
+            cache.get(&format!("stable:{:03}", pages));
+            cache.delete(&format!("churn:{:03}", pages));
+            cache.set(format!("added:{:03}", pages), "v");
+            cache.set(format!("stable:{:03}", pages * 3 % 100), "new");
+        }
+
+        let unique: HashSet<&String> = seen.iter().collect();
+        assert_eq!(unique.len(), seen.len(), "a key was returned twice");
+        assert!(seen.windows(2).all(|pair| pair[0] < pair[1]));
+
+        // Each page deletes one key and adds one, so nothing is evicted and
+        // every stable key must have been returned
+        let stable = seen.iter().filter(|key| key.starts_with("stable:")).count();
+        assert_eq!(stable, 100);
+        assert!(pages > 20);
+    }
+
+    #[test]
+    fn test_scan_keys_edges() {
+        let cache = Cache::default();
+        let (keys, cursor) = cache.scan_keys(ScanCursor::start(), 10);
+        assert!(keys.is_empty());
+        assert!(cursor.is_done());
+
+        cache.set("a", "1");
+        cache.set_with_ttl("b", "2", Duration::ZERO);
+        cache.set("c", "3");
+        // Zero is raised to one, and expired entries are skipped
+        let (keys, cursor) = cache.scan_keys(ScanCursor::start(), 0);
+        assert_eq!(keys, vec!["a"]);
+        let (keys, cursor) = cache.scan_keys(cursor, 0);
+        assert_eq!(keys, vec!["c"]);
+        assert!(cursor.is_done());
+
+        // Resuming after a key that no longer exists
+        let (keys, _) = cache.scan_keys(ScanCursor::after("b"), 10);
+        assert_eq!(keys, vec!["c"]);
+        assert_eq!(cache.stats().hits + cache.stats().misses, 0);
+    }
+
+    #[test]
+    fn test_scan() {
+        let cache = Cache::new(CacheConfig::new().shards(1).max_capacity(4));
+        assert!(cache.scan("*").is_empty());
+
+        cache.set("user:1", "a");
+        cache.set("user:22", "b");
+        cache.set("user:3", "c");
+        cache.set_with_ttl("user:4", "d", Duration::ZERO);
+        assert_eq!(cache.scan("user:*"), vec!["user:1", "user:22", "user:3"]);
+        assert_eq!(cache.scan("user:?"), vec!["user:1", "user:3"]);
+        assert_eq!(cache.scan("user:[12]*"), vec!["user:1", "user:22"]);
+        assert!(cache.scan("session:*").is_empty());
+        assert_eq!(cache.stats().hits + cache.stats().misses, 0);
+
+        // Scanning does not refresh "user:1", so it is still evicted first
+        assert_eq!(cache.scan("user:1"), vec!["user:1"]);
+        cache.delete("user:4");
+        cache.set("user:5", "e");
+        cache.set("user:6", "f");
+        assert_eq!(
+            cache.scan("*"),
+            vec!["user:22", "user:3", "user:5", "user:6"]
+        );
     }
 
     #[test]
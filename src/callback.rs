@@ -0,0 +1,79 @@
+//! Guarded invocation of user-supplied callbacks.
+//!
+//! Loaders, weighers, listeners and predicates are user closures that run
+//! from inside cache operations. A panicking callback must never poison the
+//! storage lock or leave the statistics half-updated, so every invocation goes
+//! through [`guard`], or through [`catch`] where the panic must reach the
+//! caller unchanged.
+//!
+//! # Rules for call sites
+//!
+//! Callbacks are run under `AssertUnwindSafe`. That is only sound if the call
+//! site upholds the following:
+//!
+//! - No storage lock guard is held while the callback runs, *or* the callback
+//!   only receives borrowed data and the guarded section performs no partial
+//!   mutation of the map before the callback returns.
+//! - Accounting (size, counters) is updated either entirely before or entirely
+//!   after the callback, never split around it.
+//!
+//! Depending on the API, a caught panic is either returned to the caller as
+//! [`CacheError::CallbackPanic`] or counted and dropped (for notifications that
+//! have no caller to report to).
+
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::error::{CacheError, CacheResult};
+
+/// Run a user callback, converting a panic into `CacheError::CallbackPanic`.
+pub(crate) fn guard<R>(f: impl FnOnce() -> R) -> CacheResult<R> {
+    panic::catch_unwind(AssertUnwindSafe(f))
+        .map_err(|payload| CacheError::CallbackPanic(panic_message(payload.as_ref())))
+}
+
+/// Run a user callback, handing back a panic payload for the caller to
+/// re-raise with `std::panic::resume_unwind` once its locks are released.
+pub(crate) fn catch<R>(f: impl FnOnce() -> R) -> std::thread::Result<R> {
+    panic::catch_unwind(AssertUnwindSafe(f))
+}
+
+/// Extract a readable message from a panic payload.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&'static str>() {
+        (*s).to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "callback panicked".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guard_passes_through_value() {
+        assert_eq!(guard(|| 42).unwrap(), 42);
+    }
+
+    #[test]
+    fn test_guard_catches_str_panic() {
+        let result: CacheResult<()> = guard(|| panic!("boom"));
+        match result {
+            Err(CacheError::CallbackPanic(msg)) => assert_eq!(msg, "boom"),
+            other => panic!("expected CallbackPanic, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_guard_catches_formatted_panic() {
+        let key = "user:1";
+        let result: CacheResult<()> = guard(|| panic!("bad key {}", key));
+        match result {
+            Err(CacheError::CallbackPanic(msg)) => assert_eq!(msg, "bad key user:1"),
+            other => panic!("expected CallbackPanic, got {:?}", other),
+        }
+    }
+}
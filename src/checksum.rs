@@ -0,0 +1,59 @@
+//! CRC-32 (IEEE 802.3, as used by zlib and PNG) for value integrity checks.
+//!
+//! Table-driven and dependency free. This guards against accidental
+//! corruption only; it is trivially forgeable.
+
+/// Lookup table for the reflected polynomial, built at compile time.
+const TABLE: [u32; 256] = table();
+
+const fn table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                0xEDB8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// CRC-32 of `data`.
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc = TABLE[((crc ^ u32::from(byte)) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_values() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(
+            crc32(b"The quick brown fox jumps over the lazy dog"),
+            0x414F_A339
+        );
+    }
+
+    #[test]
+    fn test_single_bit_flip_changes_checksum() {
+        let value = vec![0x5Au8; 1024];
+        let mut flipped = value.clone();
+        flipped[512] ^= 0x01;
+        assert_ne!(crc32(&value), crc32(&flipped));
+    }
+}
@@ -0,0 +1,337 @@
+//! Background removal of expired entries.
+//!
+//! Expired entries are dropped lazily when they are read, which leaves
+//! entries that are never read again in memory until something sweeps
+//! them. With [`background_cleanup`](crate::CacheConfig::background_cleanup)
+//! on, [`Cache::new`](crate::Cache::new) runs those sweeps on a background
+//! thread every [`cleanup_interval`](crate::CacheConfig::cleanup_interval).
+//! The thread only holds a weak reference to the storage and stops when the
+//! last clone of the cache is dropped, or when the task handed out by
+//! [`Cache::start_cleanup`](crate::Cache::start_cleanup) is stopped.
+//!
+//! With [`cleanup_bounds`](crate::CacheConfig::cleanup_bounds) set, the
+//! interval adapts to the workload instead: after every sweep
+//! [`next_interval`] shortens it when a large share of the entries had
+//! expired and lengthens it when a sweep found next to nothing, always
+//! within the bounds. The interval in effect and the figures of the last
+//! sweep are reported in [`StatsSnapshot`](crate::StatsSnapshot).
+//!
+//! ```
+//! use in_memory_cache::{Cache, CacheConfig};
+//! use std::time::Duration;
+//!
+//! let cache = Cache::new(
+//!     CacheConfig::new()
+//!         .background_cleanup(true)
+//!         .cleanup_interval(Duration::from_secs(10))
+//!         .cleanup_bounds(Duration::from_secs(1), Duration::from_secs(60)),
+//! );
+//! assert_eq!(cache.stats().cleanup_interval_ms, 10_000);
+//! drop(cache); // also stops the sweeps
+//! ```
+
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::storage::Db;
+
+/// Share of the scanned entries a sweep must remove for the next sweep to
+/// come twice as soon.
+const SHRINK_RATIO: f64 = 0.25;
+
+/// Share of the scanned entries below which the next sweep is put off to
+/// twice as late.
+const GROW_RATIO: f64 = 0.05;
+
+/// The shortest and longest interval an adaptive cleanup may use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CleanupBounds {
+    min: Duration,
+    max: Duration,
+}
+
+impl CleanupBounds {
+    /// Bounds from `min` to `max`. `min` is raised to at least 1ms and
+    /// `max` to at least `min`.
+    pub fn new(min: Duration, max: Duration) -> Self {
+        let min = min.max(Duration::from_millis(1));
+        Self {
+            min,
+            max: max.max(min),
+        }
+    }
+
+    /// The shortest interval.
+    pub fn min(&self) -> Duration {
+        self.min
+    }
+
+    /// The longest interval.
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// `interval` brought within the bounds.
+    pub fn clamp(&self, interval: Duration) -> Duration {
+        interval.max(self.min).min(self.max)
+    }
+}
+
+/// The interval until the next sweep, given the previous interval and how
+/// many of the `scanned` entries the last sweep `removed`.
+///
+/// The interval halves when at least a quarter of the entries had expired,
+/// doubles when fewer than one in twenty had (or the cache was empty), and
+/// otherwise stays as it is. The result is always within `bounds`.
+pub fn next_interval(
+    prev: Duration,
+    removed: usize,
+    scanned: usize,
+    bounds: CleanupBounds,
+) -> Duration {
+    let prev = bounds.clamp(prev);
+    let ratio = if scanned == 0 {
+        0.0
+    } else {
+        removed as f64 / scanned as f64
+    };
+    let next = if ratio >= SHRINK_RATIO {
+        prev / 2
+    } else if ratio < GROW_RATIO {
+        prev.checked_mul(2).unwrap_or(bounds.max)
+    } else {
+        prev
+    };
+    bounds.clamp(next)
+}
+
+/// Sweeps a cache's expired entries on a background thread until stopped.
+///
+/// Started by [`Cache::new`](crate::Cache::new) and handed out by
+/// [`Cache::start_cleanup`](crate::Cache::start_cleanup). Dropping the task
+/// without calling [`CleanupTask::stop`] also stops the thread, as does
+/// dropping the storage it sweeps.
+#[derive(Debug)]
+pub struct CleanupTask {
+    stop_tx: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl CleanupTask {
+    pub(crate) fn start(db: &Arc<Db>, interval: Duration, bounds: Option<CleanupBounds>) -> Self {
+        let mut interval = match bounds {
+            Some(bounds) => bounds.clamp(interval),
+            None => interval.max(Duration::from_millis(1)),
+        };
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+        db.stats().set_cleanup_interval(interval);
+
+        // Ends when a stop is requested, the task is dropped or the storage
+        // is gone
+        let db: Weak<Db> = Arc::downgrade(db);
+        let handle = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stop_rx.recv_timeout(interval) {
+                let db = match db.upgrade() {
+                    Some(db) => db,
+                    None => return,
+                };
+                let (removed, scanned) = db.sweep_at(db.now());
+                if let Some(bounds) = bounds {
+                    interval = next_interval(interval, removed, scanned, bounds);
+                }
+                let stats = db.stats();
+                stats.record_sweep(removed, scanned);
+                stats.set_cleanup_interval(interval);
+            }
+            if let Some(db) = db.upgrade() {
+                db.stats().set_cleanup_interval(Duration::ZERO);
+            }
+        });
+
+        Self {
+            stop_tx: Some(stop_tx),
+            handle: Some(handle),
+        }
+    }
+
+    /// Stop sweeping.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        if let Some(stop_tx) = self.stop_tx.take() {
+            let _ = stop_tx.send(());
+        }
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for CleanupTask {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CacheConfig;
+    use std::time::Instant;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_bounds_are_normalized() {
+        let bounds = CleanupBounds::new(Duration::ZERO, Duration::ZERO);
+        assert_eq!(bounds.min(), Duration::from_millis(1));
+        assert_eq!(bounds.max(), Duration::from_millis(1));
+
+        let bounds = CleanupBounds::new(secs(10), secs(5));
+        assert_eq!((bounds.min(), bounds.max()), (secs(10), secs(10)));
+        assert_eq!(bounds.clamp(secs(1)), secs(10));
+    }
+
+    #[test]
+    fn test_next_interval() {
+        let bounds = CleanupBounds::new(secs(1), secs(60));
+        // Heavy expiration: sweep sooner
+        assert_eq!(next_interval(secs(10), 50, 100, bounds), secs(5));
+        assert_eq!(next_interval(secs(10), 25, 100, bounds), secs(5));
+        // Moderate: keep the pace
+        assert_eq!(next_interval(secs(10), 10, 100, bounds), secs(10));
+        assert_eq!(next_interval(secs(10), 5, 100, bounds), secs(10));
+        // Next to nothing, or nothing to scan: sweep later
+        assert_eq!(next_interval(secs(10), 4, 100, bounds), secs(20));
+        assert_eq!(next_interval(secs(10), 0, 0, bounds), secs(20));
+        // Always within the bounds, also for an out-of-bounds start
+        assert_eq!(next_interval(secs(1), 100, 100, bounds), secs(1));
+        assert_eq!(next_interval(secs(40), 0, 100, bounds), secs(60));
+        assert_eq!(next_interval(secs(600), 10, 100, bounds), secs(60));
+        assert_eq!(next_interval(Duration::MAX, 0, 100, bounds), secs(60));
+    }
+
+    /// Drive sweeps by hand on a simulated clock: entries expire relative
+    /// to the simulated time, and each sweep runs at the time the interval
+    /// chosen by the previous one elapses.
+    #[test]
+    fn test_bursty_ttl_workload() {
+        let db = Db::new(CacheConfig::new());
+        let start = Instant::now();
+        let bounds = CleanupBounds::new(secs(1), secs(64));
+        for i in 0..1000 {
+            db.set(format!("static:{}", i), "v");
+        }
+
+        let mut elapsed = Duration::ZERO;
+        let mut interval = secs(8);
+        let sweep = |burst: usize, elapsed: &mut Duration, interval: &mut Duration| {
+            for i in 0..burst {
+                let key = format!("session:{}:{}", elapsed.as_millis(), i);
+                db.set_with_ttl(key, "v", *elapsed + Duration::from_millis(500));
+            }
+            *elapsed += *interval;
+            let (removed, scanned) = db.sweep_expired_at(start + *elapsed);
+            *interval = next_interval(*interval, removed, scanned, bounds);
+            *interval
+        };
+
+        // Quiet: nothing expires, sweeps back off to the maximum
+        let quiet: Vec<_> = (0..4)
+            .map(|_| sweep(0, &mut elapsed, &mut interval))
+            .collect();
+        assert_eq!(quiet, vec![secs(16), secs(32), secs(64), secs(64)]);
+
+        // Burst of short-lived sessions: every sweep finds half the cache
+        // expired and halves the interval down to the minimum
+        let burst: Vec<_> = (0..8)
+            .map(|_| sweep(1000, &mut elapsed, &mut interval))
+            .collect();
+        assert_eq!(
+            burst,
+            vec![
+                secs(32),
+                secs(16),
+                secs(8),
+                secs(4),
+                secs(2),
+                secs(1),
+                secs(1),
+                secs(1)
+            ]
+        );
+        assert_eq!(db.len(), 1000);
+
+        // Quiet again: back off
+        let after: Vec<_> = (0..3)
+            .map(|_| sweep(0, &mut elapsed, &mut interval))
+            .collect();
+        assert_eq!(after, vec![secs(2), secs(4), secs(8)]);
+    }
+
+    #[test]
+    fn test_task_sweeps_and_reports() {
+        let db = Arc::new(Db::new(CacheConfig::new()));
+        for i in 0..10 {
+            db.set_with_ttl(format!("key:{}", i), "v", Duration::from_millis(1));
+        }
+        db.set("kept", "v");
+        thread::sleep(Duration::from_millis(5));
+
+        let bounds = CleanupBounds::new(Duration::from_millis(5), Duration::from_millis(40));
+        let task = CleanupTask::start(&db, Duration::from_millis(5), Some(bounds));
+        let deadline = Instant::now() + secs(5);
+        while db.stats().snapshot().cleanup_interval_ms < 40 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        // The first sweep removed the expired entries, later ones found
+        // nothing and stretched the interval to the maximum
+        let stats = db.stats().snapshot();
+        assert_eq!(stats.expirations, 10);
+        assert_eq!(stats.cleanup_interval_ms, 40);
+        assert_eq!((stats.last_sweep_removed, stats.last_sweep_scanned), (0, 1));
+        assert_eq!(db.len(), 1);
+
+        task.stop();
+        assert_eq!(db.stats().snapshot().cleanup_interval_ms, 0);
+    }
+
+    #[test]
+    fn test_task_sweeps_in_batches() {
+        let db = Arc::new(Db::new(CacheConfig::new().cleanup_batch_size(3)));
+        for i in 0..10 {
+            db.set_with_ttl(format!("key:{}", i), "v", Duration::from_millis(1));
+        }
+        db.set("kept", "v");
+        thread::sleep(Duration::from_millis(5));
+
+        let task = CleanupTask::start(&db, Duration::from_millis(1), None);
+        let deadline = Instant::now() + secs(5);
+        while db.len() > 1 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+        task.stop();
+        let stats = db.stats().snapshot();
+        assert_eq!(stats.expirations, 10);
+        assert!(stats.last_sweep_scanned <= 3);
+    }
+
+    #[test]
+    fn test_task_ends_with_the_storage() {
+        let db = Arc::new(Db::new(CacheConfig::new()));
+        let task = CleanupTask::start(&db, Duration::from_millis(1), None);
+        drop(db);
+        let deadline = Instant::now() + secs(5);
+        while !task.handle.as_ref().unwrap().is_finished() {
+            assert!(Instant::now() < deadline, "cleanup outlived its storage");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
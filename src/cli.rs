@@ -1,6 +1,7 @@
 //! Command-line interface definitions.
 //!
-//! This module defines the CLI structure for the cache client using clap.
+//! This module defines the CLI structures for the cache client and server
+//! using clap.
 
 use clap::{Parser, Subcommand};
 
@@ -9,8 +10,17 @@ use clap::{Parser, Subcommand};
 /// A CLI tool for interacting with the in-memory cache server.
 #[derive(Parser, Debug)]
 #[command(name = "cache-client")]
-#[command(author, version, about, long_about = None)]
+#[command(author, version = crate::version::LONG_VERSION, about, long_about = None)]
 pub struct Cli {
+    /// Tag the request with a generated trace id (printed to stderr) that
+    /// the server logs and echoes back.
+    #[arg(long, global = true)]
+    pub trace: bool,
+
+    /// Ask the server to send large replies lz4-compressed.
+    #[arg(long, global = true)]
+    pub compress: bool,
+
     /// The command to execute.
     #[clap(subcommand)]
     pub command: ClientCommand,
@@ -28,6 +38,15 @@ pub enum ClientCommand {
         key: String,
     },
 
+    /// Get several values at once.
+    ///
+    /// Prints one line per key, in the order given.
+    Mget {
+        /// The keys to look up.
+        #[arg(required = true)]
+        keys: Vec<String>,
+    },
+
     /// Set a key-value pair.
     ///
     /// Stores the value at the given key. If the key already
@@ -37,6 +56,9 @@ pub enum ClientCommand {
         key: String,
         /// The value to store.
         value: String,
+        /// Also print how many entries and bytes were evicted to make room.
+        #[arg(long)]
+        verbose: bool,
     },
 
     /// Delete a key.
@@ -47,6 +69,25 @@ pub enum ClientCommand {
         key: String,
     },
 
+    /// List the keys matching a glob pattern.
+    ///
+    /// Prints one key per line, in lexicographic order. `*` matches any
+    /// run of characters and `?` any single one; quote the pattern so the
+    /// shell does not expand it.
+    Keys {
+        /// The pattern to match, such as `session:*`.
+        pattern: String,
+    },
+
+    /// Show the remaining time to live of a key.
+    ///
+    /// Prints the number of seconds left, or says that the key has no
+    /// expiration or does not exist.
+    Ttl {
+        /// The key to look up.
+        key: String,
+    },
+
     /// Ping the server.
     ///
     /// Checks if the server is running and responsive.
@@ -54,8 +95,171 @@ pub enum ClientCommand {
 
     /// Get server statistics.
     ///
-    /// Shows cache hits, misses, size, and hit rate.
-    Stats,
+    /// Shows cache hits, misses, size, hit rate, and eviction totals.
+    Stats {
+        /// Also report entries and bytes for keys starting with this prefix.
+        #[arg(long)]
+        prefix: Option<String>,
+
+        /// Reset the counters instead (needs a server started with
+        /// `--enable-admin`).
+        #[arg(long, conflicts_with = "prefix")]
+        reset: bool,
+    },
+
+    /// Import keys from a dump file into the server.
+    ///
+    /// Reads a Redis command stream and sends every string key to the
+    /// server with `set`, quoting keys and values as needed. TTLs are sent
+    /// with `ex` and expirations with `expire`, both rounded up to whole
+    /// seconds. Keys or values containing line breaks or invalid UTF-8
+    /// cannot be expressed in the server protocol and are reported as
+    /// skipped.
+    #[cfg(feature = "tools")]
+    Import {
+        /// Input format.
+        #[arg(long, value_enum, default_value_t = ImportFormat::RedisProto)]
+        format: ImportFormat,
+        /// The file to read.
+        file: std::path::PathBuf,
+        /// Do not notify the server's eviction listener of the values the
+        /// import overwrites.
+        #[arg(long)]
+        quiet: bool,
+    },
+
+    /// Get labeled server information.
+    ///
+    /// Sections are server, memory, stats, config, clients and keyspace
+    /// (entries by remaining TTL). All are shown unless one is named.
+    Info {
+        /// Only show this section.
+        section: Option<String>,
+    },
+}
+
+/// Dump formats understood by `client import`.
+#[cfg(feature = "tools")]
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    /// RESP or inline Redis commands (`SET key value EX 60`).
+    RedisProto,
+}
+
+/// In-memory cache server.
+///
+/// Runs a TCP server that accepts cache commands from clients. Each
+/// setting is taken from its flag, else its environment variable
+/// (`CACHE_HOST`, `CACHE_PORT`, `CACHE_MAX_CAPACITY`, `CACHE_DEFAULT_TTL`,
+/// `CACHE_CLEANUP_INTERVAL`, `CACHE_RECORD_LOCK_WAITS`), else the
+/// `--config` file, falling back to built-in defaults.
+#[derive(Parser, Debug)]
+#[command(name = "cache-server")]
+#[command(author, version = crate::version::LONG_VERSION, about, long_about = None)]
+pub struct ServerCli {
+    /// Read cache settings (`max_capacity`, `default_ttl_secs`,
+    /// `cleanup_interval_secs`, `background_cleanup`, `max_memory_bytes`)
+    /// from this TOML file. Environment variables and flags override it.
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<std::path::PathBuf>,
+
+    /// Address to bind [env: CACHE_HOST] [default: 127.0.0.1]
+    #[arg(long, value_name = "HOST", value_parser = non_empty)]
+    pub host: Option<String>,
+
+    /// Port to bind [env: CACHE_PORT] [default: 3000]
+    #[arg(long, value_name = "PORT")]
+    pub port: Option<u16>,
+
+    /// Maximum number of entries, 0 for unlimited [env:
+    /// CACHE_MAX_CAPACITY] [default: 10000]
+    #[arg(long, value_name = "N")]
+    pub max_capacity: Option<usize>,
+
+    /// TTL in seconds for entries set without one, 0 for none [env:
+    /// CACHE_DEFAULT_TTL]
+    #[arg(long, value_name = "SECS")]
+    pub default_ttl: Option<u64>,
+
+    /// Sweep for expired entries in the background every this many
+    /// seconds, or 0 to only expire them on access [env:
+    /// CACHE_CLEANUP_INTERVAL]
+    #[arg(long, value_name = "SECS")]
+    pub cleanup_interval: Option<u64>,
+
+    /// Validate the configuration, print the effective settings, and exit
+    /// without binding the port.
+    #[arg(long)]
+    pub check_config: bool,
+
+    /// Start the server on an ephemeral localhost port, run a scripted
+    /// client session against it, print a pass/fail report, and exit with
+    /// a non-zero status if any step failed.
+    #[arg(long)]
+    pub smoke_test: bool,
+
+    /// Accept admin commands such as `stats reset`.
+    #[arg(long)]
+    pub enable_admin: bool,
+
+    /// Close connections that send nothing for this many seconds
+    /// [default: 300]
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub idle_timeout: Option<u64>,
+
+    /// Reply `ERR TOOLARGE` to requests longer than this many bytes
+    /// instead of buffering them [default: 1048576]
+    #[arg(long, value_name = "BYTES", value_parser = parse_frame_size)]
+    pub max_frame_size: Option<usize>,
+
+    /// Commands per second allowed from each client (up to this many back
+    /// to back). Clients are told apart by IP address, so all connections
+    /// from one host share the allowance.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_ops_per_conn: Option<u32>,
+
+    /// Commands per second allowed across all clients (up to this many
+    /// back to back).
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_ops_global: Option<u32>,
+
+    /// Log a health warning when the cache holds more than this percentage
+    /// of its capacity.
+    #[arg(long, value_name = "PCT", value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub warn_size_pct: Option<u8>,
+
+    /// Log a health warning when the hit rate over the last minute drops
+    /// below this percentage.
+    #[arg(long, value_name = "PCT", value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub warn_hit_rate_pct: Option<u8>,
+
+    /// Log a health warning when more than this many entries are evicted
+    /// per minute.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub warn_evictions_per_min: Option<u64>,
+
+    /// Log a health warning when estimated memory use exceeds this many
+    /// bytes.
+    #[arg(long, value_name = "BYTES", value_parser = clap::value_parser!(u64).range(1..))]
+    pub warn_memory_bytes: Option<u64>,
+}
+
+/// A frame size of at least one byte.
+fn parse_frame_size(raw: &str) -> Result<usize, String> {
+    match raw.parse::<usize>() {
+        Ok(0) => Err("must be at least 1".to_string()),
+        Ok(bytes) => Ok(bytes),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Reject blank values, such as an empty `--host`.
+fn non_empty(raw: &str) -> Result<String, String> {
+    if raw.trim().is_empty() {
+        Err("must not be empty".to_string())
+    } else {
+        Ok(raw.to_string())
+    }
 }
 
 #[cfg(test)]
@@ -71,16 +275,46 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_mget() {
+        let cli = Cli::parse_from(["test", "mget", "a", "b"]);
+        match cli.command {
+            ClientCommand::Mget { keys } => assert_eq!(keys, vec!["a", "b"]),
+            _ => panic!("Expected Mget command"),
+        }
+        assert!(Cli::try_parse_from(["test", "mget"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_keys() {
+        let cli = Cli::parse_from(["test", "keys", "session:*"]);
+        match cli.command {
+            ClientCommand::Keys { pattern } => assert_eq!(pattern, "session:*"),
+            _ => panic!("Expected Keys command"),
+        }
+        assert!(Cli::try_parse_from(["test", "keys"]).is_err());
+    }
+
     #[test]
     fn test_parse_set() {
         let cli = Cli::parse_from(["test", "set", "mykey", "myvalue"]);
         match cli.command {
-            ClientCommand::Set { key, value } => {
+            ClientCommand::Set {
+                key,
+                value,
+                verbose,
+            } => {
                 assert_eq!(key, "mykey");
                 assert_eq!(value, "myvalue");
+                assert!(!verbose);
             }
             _ => panic!("Expected Set command"),
         }
+        let cli = Cli::parse_from(["test", "set", "mykey", "myvalue", "--verbose"]);
+        assert!(matches!(
+            cli.command,
+            ClientCommand::Set { verbose: true, .. }
+        ));
     }
 
     #[test]
@@ -92,6 +326,16 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_ttl() {
+        let cli = Cli::parse_from(["test", "ttl", "session:1"]);
+        match cli.command {
+            ClientCommand::Ttl { key } => assert_eq!(key, "session:1"),
+            _ => panic!("Expected Ttl command"),
+        }
+        assert!(Cli::try_parse_from(["test", "ttl"]).is_err());
+    }
+
     #[test]
     fn test_parse_ping() {
         let cli = Cli::parse_from(["test", "ping"]);
@@ -101,6 +345,182 @@ mod tests {
     #[test]
     fn test_parse_stats() {
         let cli = Cli::parse_from(["test", "stats"]);
-        assert!(matches!(cli.command, ClientCommand::Stats));
+        assert!(matches!(
+            cli.command,
+            ClientCommand::Stats {
+                prefix: None,
+                reset: false
+            }
+        ));
+
+        let cli = Cli::parse_from(["client", "stats", "--prefix", "user:"]);
+        match cli.command {
+            ClientCommand::Stats { prefix, reset } => {
+                assert_eq!(prefix.as_deref(), Some("user:"));
+                assert!(!reset);
+            }
+            _ => panic!("Expected Stats command"),
+        }
+
+        let cli = Cli::parse_from(["client", "stats", "--reset"]);
+        assert!(matches!(
+            cli.command,
+            ClientCommand::Stats { reset: true, .. }
+        ));
+        assert!(Cli::try_parse_from(["client", "stats", "--reset", "--prefix", "a"]).is_err());
+    }
+
+    #[cfg(feature = "tools")]
+    #[test]
+    fn test_parse_import() {
+        let cli = Cli::parse_from(["test", "import", "--format", "redis-proto", "dump.txt"]);
+        match cli.command {
+            ClientCommand::Import {
+                format,
+                file,
+                quiet,
+            } => {
+                assert_eq!(format, ImportFormat::RedisProto);
+                assert_eq!(file, std::path::PathBuf::from("dump.txt"));
+                assert!(!quiet);
+            }
+            _ => panic!("Expected Import command"),
+        }
+        let cli = Cli::parse_from(["test", "import", "--quiet", "dump.txt"]);
+        assert!(matches!(
+            cli.command,
+            ClientCommand::Import { quiet: true, .. }
+        ));
+    }
+
+    #[test]
+    fn test_parse_trace_flag() {
+        let cli = Cli::parse_from(["test", "ping"]);
+        assert!(!cli.trace);
+
+        let cli = Cli::parse_from(["test", "get", "k", "--trace"]);
+        assert!(cli.trace);
+    }
+
+    #[test]
+    fn test_parse_info() {
+        let cli = Cli::parse_from(["test", "info"]);
+        assert!(matches!(cli.command, ClientCommand::Info { section: None }));
+
+        let cli = Cli::parse_from(["test", "info", "memory"]);
+        match cli.command {
+            ClientCommand::Info { section } => assert_eq!(section.as_deref(), Some("memory")),
+            _ => panic!("Expected Info command"),
+        }
+    }
+
+    #[test]
+    fn test_parse_server_check_config() {
+        let cli = ServerCli::parse_from(["server"]);
+        assert!(!cli.check_config);
+
+        let cli = ServerCli::parse_from(["server", "--check-config"]);
+        assert!(cli.check_config);
+        assert!(!cli.enable_admin);
+
+        let cli = ServerCli::parse_from(["server", "--enable-admin"]);
+        assert!(cli.enable_admin);
+        assert!(!cli.smoke_test);
+
+        let cli = ServerCli::parse_from(["server", "--smoke-test"]);
+        assert!(cli.smoke_test);
+    }
+
+    #[test]
+    fn test_parse_server_bind_and_cache_flags() {
+        let cli = ServerCli::parse_from(["server"]);
+        assert_eq!(cli.host, None);
+        assert_eq!(cli.port, None);
+        assert_eq!(cli.max_capacity, None);
+        assert_eq!(cli.default_ttl, None);
+        assert_eq!(cli.cleanup_interval, None);
+
+        let cli = ServerCli::parse_from([
+            "server",
+            "--host",
+            "0.0.0.0",
+            "--port",
+            "3001",
+            "--max-capacity",
+            "0",
+            "--default-ttl",
+            "300",
+            "--cleanup-interval",
+            "30",
+        ]);
+        assert_eq!(cli.host.as_deref(), Some("0.0.0.0"));
+        assert_eq!(cli.port, Some(3001));
+        assert_eq!(cli.max_capacity, Some(0));
+        assert_eq!(cli.default_ttl, Some(300));
+        assert_eq!(cli.cleanup_interval, Some(30));
+
+        let cli = ServerCli::parse_from(["server", "--idle-timeout", "5"]);
+        assert_eq!(cli.idle_timeout, Some(5));
+        assert!(ServerCli::try_parse_from(["server", "--idle-timeout", "0"]).is_err());
+
+        let cli = ServerCli::parse_from(["server", "--max-frame-size", "65536"]);
+        assert_eq!(cli.max_frame_size, Some(65536));
+        assert!(ServerCli::try_parse_from(["server", "--max-frame-size", "0"]).is_err());
+
+        assert!(ServerCli::try_parse_from(["server", "--port", "70000"]).is_err());
+        assert!(ServerCli::try_parse_from(["server", "--host", " "]).is_err());
+        assert!(ServerCli::try_parse_from(["server", "--default-ttl", "5m"]).is_err());
+        assert!(ServerCli::try_parse_from(["server", "--max-capacity", "-1"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_server_config_file() {
+        assert_eq!(ServerCli::parse_from(["server"]).config, None);
+
+        let cli = ServerCli::parse_from(["server", "--config", "/etc/cache.toml"]);
+        assert_eq!(
+            cli.config,
+            Some(std::path::PathBuf::from("/etc/cache.toml"))
+        );
+        assert!(ServerCli::try_parse_from(["server", "--config"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_server_rate_limits() {
+        let cli = ServerCli::parse_from(["server"]);
+        assert_eq!(cli.max_ops_per_conn, None);
+        assert_eq!(cli.max_ops_global, None);
+
+        let cli = ServerCli::parse_from([
+            "server",
+            "--max-ops-per-conn",
+            "50",
+            "--max-ops-global",
+            "1000",
+        ]);
+        assert_eq!(cli.max_ops_per_conn, Some(50));
+        assert_eq!(cli.max_ops_global, Some(1000));
+        assert!(ServerCli::try_parse_from(["server", "--max-ops-global", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_server_health_thresholds() {
+        let cli = ServerCli::parse_from([
+            "server",
+            "--warn-size-pct",
+            "90",
+            "--warn-hit-rate-pct",
+            "75",
+            "--warn-evictions-per-min",
+            "600",
+            "--warn-memory-bytes",
+            "1048576",
+        ]);
+        assert_eq!(cli.warn_size_pct, Some(90));
+        assert_eq!(cli.warn_hit_rate_pct, Some(75));
+        assert_eq!(cli.warn_evictions_per_min, Some(600));
+        assert_eq!(cli.warn_memory_bytes, Some(1 << 20));
+        assert!(ServerCli::try_parse_from(["server", "--warn-size-pct", "101"]).is_err());
+        assert_eq!(ServerCli::parse_from(["server"]).warn_size_pct, None);
     }
 }
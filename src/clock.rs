@@ -0,0 +1,124 @@
+//! Time sources for expiration and recency.
+//!
+//! A cache reads the current time through the [`Clock`] in its
+//! [`CacheConfig`](crate::CacheConfig) whenever it decides whether an entry
+//! has expired, stamps an access or a write, or sweeps. The default is the
+//! [`SystemClock`].
+//!
+//! With the `test-util` feature, `MockClock` stands still until it is
+//! advanced, so TTL tests need no sleeps:
+//!
+//! ```
+//! # #[cfg(feature = "test-util")]
+//! # {
+//! use in_memory_cache::clock::MockClock;
+//! use in_memory_cache::{Cache, CacheConfig};
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//!
+//! let clock = MockClock::new();
+//! let cache = Cache::new(CacheConfig::new().clock(Arc::new(clock.clone())));
+//! cache.set_with_ttl("session", "data", Duration::from_secs(30));
+//!
+//! clock.advance(Duration::from_secs(29));
+//! assert!(cache.contains("session"));
+//! clock.advance(Duration::from_secs(1));
+//! assert!(!cache.contains("session"));
+//! # }
+//! ```
+//!
+//! Only cache time goes through the clock, along with the sample times of
+//! a `StatsRecorder`. Real waits, such as the interval between background
+//! sweeps, loader timeouts and lock-wait measurements, still use the system
+//! clock.
+
+use std::fmt;
+use std::time::Instant;
+
+#[cfg(any(test, feature = "test-util"))]
+use std::sync::{Arc, Mutex};
+#[cfg(any(test, feature = "test-util"))]
+use std::time::Duration;
+
+/// A source of the current time.
+///
+/// Must never go backwards: entries store times relative to the instant
+/// the cache was created at.
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// The current instant.
+    fn now(&self) -> Instant;
+}
+
+/// The system's monotonic clock.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves when advanced (feature `test-util`).
+///
+/// Clones share the same time, so a test can keep one and hand another to
+/// the cache.
+#[cfg(any(test, feature = "test-util"))]
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    now: Arc<Mutex<Instant>>,
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl MockClock {
+    /// A clock standing at the current system time.
+    pub fn new() -> Self {
+        Self {
+            now: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    /// Move the clock forward by `by`.
+    pub fn advance(&self, by: Duration) {
+        let mut now = self.now.lock().unwrap_or_else(|e| e.into_inner());
+        *now += by;
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_clock_moves_only_when_advanced() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        std::thread::sleep(Duration::from_millis(2));
+        assert_eq!(clock.now(), start);
+
+        let shared = clock.clone();
+        shared.advance(Duration::from_secs(5));
+        assert_eq!(clock.now(), start + Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_system_clock_follows_instant() {
+        let before = Instant::now();
+        let now = SystemClock.now();
+        assert!(before <= now && now <= Instant::now());
+    }
+}
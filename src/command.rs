@@ -3,20 +3,69 @@
 //! This module defines the commands that can be sent to the cache server.
 
 use crate::error::{CacheError, CacheResult};
+use crate::protocol::{split_trace_id, ProtocolError, ProtocolResult};
 
 /// Types of commands supported by the cache server.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Command {
     /// Get a value by key.
     Get,
-    /// Set a key-value pair.
+    /// Get several values at once (binary-safe multi-bulk reply).
+    MGet,
+    /// Set a key-value pair, optionally with `ex <seconds>`, `nx` (only
+    /// if the key is absent), `quiet` (do not notify the eviction listener
+    /// of the replaced value, for bulk loads) and, without `nx`, `verbose`
+    /// (report the entries and bytes evicted to make room, as in
+    /// `Ok evicted:1 bytes_evicted:6`).
     Set,
-    /// Delete a key.
+    /// Remaining TTL of a key in seconds (-1 without a TTL, -2 if missing).
+    Ttl,
+    /// Give a key a new TTL (`expire <key> <seconds>`), replying `1` if it
+    /// existed and `0` otherwise.
+    Expire,
+    /// Remove a key's TTL (`persist <key>`), replying like `expire`.
+    Persist,
+    /// Delete a key, or several (`delete <key>...`, replying with the
+    /// number removed).
     Delete,
+    /// Delete every key under a prefix (`delprefix <prefix>`), replying
+    /// with the number removed.
+    DelPrefix,
+    /// List the keys matching a glob (`keys <pattern>`, binary-safe
+    /// multi-bulk reply in lexicographic order).
+    Keys,
+    /// Page through keys (`scan <cursor> [count]`, binary-safe multi-bulk
+    /// reply of the next cursor followed by the keys).
+    Scan,
+    /// Append to a value (`append <key> <value>`), replying with the new
+    /// length.
+    Append,
+    /// Add to a counter (`incr <key> [delta]`, delta defaults to 1).
+    Incr,
+    /// Subtract from a counter (`decr <key> [delta]`, delta defaults to 1).
+    Decr,
+    /// Fixed-window counter (`incrwindow <key> <delta> <seconds>`).
+    IncrWindow,
+    /// Fixed-window rate limit check (`ratelimit <key> <limit> <seconds>`).
+    RateLimit,
     /// Ping the server (health check).
     Ping,
     /// Get server statistics.
     Stats,
+    /// Get server statistics in the Prometheus text format.
+    Metrics,
+    /// Get labeled server information, optionally a single section.
+    Info,
+    /// Diagnostic subcommands (`debug memory [samples] [depth]`,
+    /// `debug inflight`).
+    Debug,
+    /// Administrative removal (`purge idle <seconds>`). Requires admin.
+    Purge,
+    /// Bulk TTL change by glob (`expire-pattern <pattern> <seconds|persist>`).
+    /// Requires admin.
+    ExpirePattern,
+    /// Connection option negotiation (`hello compression=<codecs>`).
+    Hello,
     /// Invalid or unknown command.
     Invalid,
 }
@@ -32,10 +81,28 @@ impl Command {
     pub fn get(s: &str) -> Command {
         match s.to_lowercase().as_str() {
             "set" => Command::Set,
+            "ttl" => Command::Ttl,
             "get" => Command::Get,
+            "mget" => Command::MGet,
+            "expire" => Command::Expire,
+            "persist" => Command::Persist,
             "delete" | "del" => Command::Delete,
+            "delprefix" => Command::DelPrefix,
+            "keys" => Command::Keys,
+            "scan" => Command::Scan,
+            "append" => Command::Append,
+            "incr" => Command::Incr,
+            "decr" => Command::Decr,
+            "incrwindow" => Command::IncrWindow,
+            "ratelimit" => Command::RateLimit,
             "ping" => Command::Ping,
-            "stats" | "info" => Command::Stats,
+            "stats" => Command::Stats,
+            "metrics" => Command::Metrics,
+            "info" => Command::Info,
+            "debug" => Command::Debug,
+            "purge" => Command::Purge,
+            "expire-pattern" => Command::ExpirePattern,
+            "hello" => Command::Hello,
             _ => Command::Invalid,
         }
     }
@@ -60,15 +127,69 @@ impl Command {
     pub fn as_str(&self) -> &'static str {
         match self {
             Command::Get => "get",
+            Command::MGet => "mget",
             Command::Set => "set",
+            Command::Ttl => "ttl",
+            Command::Expire => "expire",
+            Command::Persist => "persist",
             Command::Delete => "delete",
+            Command::DelPrefix => "delprefix",
+            Command::Keys => "keys",
+            Command::Scan => "scan",
+            Command::Append => "append",
+            Command::Incr => "incr",
+            Command::Decr => "decr",
+            Command::IncrWindow => "incrwindow",
+            Command::RateLimit => "ratelimit",
             Command::Ping => "ping",
             Command::Stats => "stats",
+            Command::Metrics => "metrics",
+            Command::Info => "info",
+            Command::Debug => "debug",
+            Command::Purge => "purge",
+            Command::ExpirePattern => "expire-pattern",
+            Command::Hello => "hello",
             Command::Invalid => "invalid",
         }
     }
 }
 
+/// A tokenized request: an optional trace id, then the command word and its
+/// arguments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request<'a> {
+    /// Trace id the reply must echo.
+    pub trace_id: Option<&'a str>,
+    /// The command; [`Command::Invalid`] for an unknown name, which is
+    /// left for the command handler to reject.
+    pub command: Command,
+    /// The command word followed by its arguments; never empty.
+    pub attrs: &'a [String],
+}
+
+impl<'a> Request<'a> {
+    /// Parse the words of a request line.
+    ///
+    /// Fails with [`ProtocolError::Framing`] for an invalid trace id or a
+    /// request without a command word.
+    pub fn parse(words: &'a [String]) -> ProtocolResult<Self> {
+        let (trace_id, attrs) = split_trace_id(words)?;
+        let name = attrs
+            .first()
+            .ok_or_else(|| ProtocolError::Framing("empty command".to_string()))?;
+        Ok(Self {
+            trace_id,
+            command: Command::get(name),
+            attrs,
+        })
+    }
+
+    /// The arguments after the command word.
+    pub fn args(&self) -> &'a [String] {
+        &self.attrs[1..]
+    }
+}
+
 impl std::fmt::Display for Command {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.as_str())
@@ -83,12 +204,30 @@ mod tests {
     fn test_parse_commands() {
         assert_eq!(Command::get("get"), Command::Get);
         assert_eq!(Command::get("GET"), Command::Get);
+        assert_eq!(Command::get("mget"), Command::MGet);
         assert_eq!(Command::get("set"), Command::Set);
         assert_eq!(Command::get("SET"), Command::Set);
+        assert_eq!(Command::get("ttl"), Command::Ttl);
+        assert_eq!(Command::get("expire"), Command::Expire);
+        assert_eq!(Command::get("PERSIST"), Command::Persist);
         assert_eq!(Command::get("delete"), Command::Delete);
         assert_eq!(Command::get("del"), Command::Delete);
+        assert_eq!(Command::get("delprefix"), Command::DelPrefix);
+        assert_eq!(Command::get("keys"), Command::Keys);
+        assert_eq!(Command::get("scan"), Command::Scan);
+        assert_eq!(Command::get("append"), Command::Append);
+        assert_eq!(Command::get("incr"), Command::Incr);
+        assert_eq!(Command::get("DECR"), Command::Decr);
+        assert_eq!(Command::get("incrwindow"), Command::IncrWindow);
+        assert_eq!(Command::get("RATELIMIT"), Command::RateLimit);
         assert_eq!(Command::get("ping"), Command::Ping);
         assert_eq!(Command::get("stats"), Command::Stats);
+        assert_eq!(Command::get("info"), Command::Info);
+        assert_eq!(Command::get("debug"), Command::Debug);
+        assert_eq!(Command::get("purge"), Command::Purge);
+        assert_eq!(Command::get("EXPIRE-PATTERN"), Command::ExpirePattern);
+        assert_eq!(Command::get("hello"), Command::Hello);
+        assert_eq!(Command::get("METRICS"), Command::Metrics);
         assert_eq!(Command::get("unknown"), Command::Invalid);
     }
 
@@ -98,6 +237,34 @@ mod tests {
         assert!(Command::parse("unknown").is_err());
     }
 
+    fn words(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_parse_request() {
+        let line = words("*id=t1 SET key value");
+        let request = Request::parse(&line).unwrap();
+        assert_eq!(request.trace_id, Some("t1"));
+        assert_eq!(request.command, Command::Set);
+        assert_eq!(request.args(), &line[2..]);
+
+        let line = words("frobnicate");
+        let request = Request::parse(&line).unwrap();
+        assert_eq!(request.trace_id, None);
+        assert_eq!(request.command, Command::Invalid);
+        assert!(request.args().is_empty());
+
+        assert_eq!(
+            Request::parse(&words("*id=t1")),
+            Err(ProtocolError::Framing("empty command".to_string()))
+        );
+        assert!(matches!(
+            Request::parse(&words("*id=a/b get key")),
+            Err(ProtocolError::Framing(_))
+        ));
+    }
+
     #[test]
     fn test_as_str() {
         assert_eq!(Command::Get.as_str(), "get");
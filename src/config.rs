@@ -3,8 +3,70 @@
 //! This module provides a builder pattern for configuring cache behavior
 //! including capacity limits, TTL defaults, and cleanup intervals.
 
+use bytes::Bytes;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 
+use crate::cleanup::CleanupBounds;
+use crate::clock::{Clock, SystemClock};
+use crate::error::{CacheError, CacheResult};
+use crate::health::{HealthThresholds, DEFAULT_GROWTH_ALARM};
+use crate::listener::EvictionListener;
+use crate::overrides::ConfigOverrides;
+
+/// Shortest `cleanup_interval` [`CacheConfig::try_build`] accepts.
+const MIN_CLEANUP_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Fewest entries per shard when the shard count of a cache with
+/// `max_capacity` is picked automatically, so hash imbalance between
+/// shards barely affects how full the cache gets.
+const MIN_AUTO_SHARD_CAPACITY: usize = 1024;
+
+/// What `set` does when a value should be spilled to disk but the file
+/// cannot be written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SpillFallback {
+    /// Keep the value in memory as if spilling were disabled.
+    #[default]
+    InMemory,
+    /// Drop the write, counting it in `dropped_sets`. An existing value
+    /// for the key is left untouched.
+    Reject,
+}
+
+/// Which entry `Db` evicts when a capacity, memory or weight limit is hit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EvictionPolicy {
+    /// Least recently used: reads move an entry to the back of the
+    /// eviction order.
+    #[default]
+    Lru,
+    /// Least frequently used: the entry with the fewest reads goes, the
+    /// least recently used one among equals. Finding it scans every entry,
+    /// so each eviction is O(n). Counts are never decayed, and an
+    /// overwrite keeps the count of the live value it replaces.
+    Lfu,
+    /// First in, first out: entries leave in the order they were first
+    /// inserted. Reads, [`Cache::touch`](crate::Cache::touch) and
+    /// overwrites never reorder them, so one pass over many keys cannot
+    /// push out entries that were already there.
+    Fifo,
+}
+
+/// Callback computing the weight of an entry from its key and stored value.
+///
+/// See [`CacheConfig::weigher`].
+pub type Weigher = Arc<dyn Fn(&str, &Bytes) -> u32 + Send + Sync>;
+
+/// Where and above which size values are spilled to disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SpillOver {
+    pub(crate) threshold: usize,
+    pub(crate) dir: PathBuf,
+}
+
 /// Configuration for creating a new cache instance.
 ///
 /// Use the builder pattern to construct configuration:
@@ -18,42 +80,261 @@ use std::time::Duration;
 ///     .default_ttl(Duration::from_secs(300))
 ///     .build();
 /// ```
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct CacheConfig {
     /// Maximum number of entries the cache can hold.
     /// When this limit is reached, the least recently used entry is evicted.
     /// `None` means unlimited (not recommended for production).
     pub(crate) max_capacity: Option<usize>,
 
+    /// Number of independently locked segments the entries are split
+    /// into. `None` picks one from the number of CPUs (see
+    /// `shard_count`).
+    pub(crate) shards: Option<usize>,
+
+    /// Maximum summed key and value bytes of the stored entries, enforced
+    /// alongside `max_capacity`. `None` means unlimited.
+    pub(crate) max_memory: Option<u64>,
+
+    /// Longest key in bytes a set accepts. `None` means unlimited.
+    pub(crate) max_key_length: Option<usize>,
+
+    /// Largest value in bytes a set accepts. `None` means unlimited.
+    pub(crate) max_value_size: Option<usize>,
+
+    /// Which entry to evict when a limit is hit.
+    pub(crate) eviction_policy: EvictionPolicy,
+
+    /// Computes entry weights. `None` weighs every entry as 1.
+    pub(crate) weigher: Option<Weigher>,
+
+    /// Maximum summed weight of the stored entries. `None` means unlimited.
+    pub(crate) max_weight: Option<u64>,
+
     /// Default TTL for entries when not explicitly specified.
     /// `None` means entries don't expire by default.
     pub(crate) default_ttl: Option<Duration>,
 
+    /// Largest fraction by which a TTL is randomly lengthened or shortened.
+    /// `None` applies TTLs exactly.
+    pub(crate) ttl_jitter: Option<f64>,
+
+    /// Whether a read that promotes an entry also renews its TTL.
+    pub(crate) refresh_ttl_on_access: bool,
+
+    /// TTL of loader failures cached by `get_or_try_insert_with`. `None`
+    /// leaves failures uncached.
+    pub(crate) error_ttl: Option<Duration>,
+
     /// Interval for background cleanup of expired entries.
     /// `None` disables background cleanup (lazy expiration only).
     pub(crate) cleanup_interval: Option<Duration>,
 
     /// Whether to enable background cleanup task.
     pub(crate) background_cleanup: bool,
+
+    /// Range the cleanup interval adapts within. `None` keeps it fixed.
+    pub(crate) cleanup_bounds: Option<CleanupBounds>,
+
+    /// Entries each background sweep looks at. `None` sweeps everything.
+    pub(crate) cleanup_batch_size: Option<usize>,
+
+    /// Callback notified when entries leave the cache.
+    pub(crate) eviction_listener: Option<EvictionListener>,
+
+    /// Whether to measure how long lock acquisitions wait.
+    pub(crate) record_lock_waits: bool,
+
+    /// Extra time an entry stays readable past its nominal deadline.
+    pub(crate) expiration_grace: Duration,
+
+    /// Whether a set of the value already stored is skipped.
+    pub(crate) coalesce_identical_writes: bool,
+
+    /// Whether a coalesced set still applies the incoming TTL.
+    pub(crate) coalesce_refreshes_ttl: bool,
+
+    /// Minimum time since the last promotion before a read moves an entry
+    /// to the most recently used position again.
+    pub(crate) promotion_threshold: Duration,
+
+    /// Root seed for randomized behaviour. `None` seeds from entropy.
+    pub(crate) rng_seed: Option<u64>,
+
+    /// Source of the time entries expire and are stamped by.
+    pub(crate) clock: Arc<dyn Clock>,
+
+    /// Whether `clear` drops the old entries on a background thread.
+    pub(crate) background_clear: bool,
+
+    /// Values larger than the threshold are kept in files under the
+    /// directory instead of in memory. `None` disables spilling.
+    pub(crate) spill_over: Option<SpillOver>,
+
+    /// What to do when a spill-over file cannot be written.
+    pub(crate) spill_fallback: SpillFallback,
+
+    /// TTL multiplier for counting `shadow_hits`. `None` disables it.
+    pub(crate) shadow_ttl_factor: Option<f64>,
+
+    /// Whether values are checksummed on set and verified on get.
+    pub(crate) checksum_values: bool,
+
+    /// Soft thresholds checked by `Cache::health`.
+    pub(crate) health_thresholds: HealthThresholds,
+
+    /// Entry count at which a cache without `max_capacity` raises its
+    /// growth alarm. Zero disables the alarm.
+    pub(crate) growth_alarm: usize,
 }
 
 impl Default for CacheConfig {
     fn default() -> Self {
         Self {
             max_capacity: None,
+            shards: None,
+            max_memory: None,
+            max_key_length: None,
+            max_value_size: None,
+            eviction_policy: EvictionPolicy::Lru,
+            weigher: None,
+            max_weight: None,
             default_ttl: None,
+            ttl_jitter: None,
+            refresh_ttl_on_access: false,
+            error_ttl: None,
             cleanup_interval: Some(Duration::from_secs(60)),
             background_cleanup: false,
+            cleanup_bounds: None,
+            cleanup_batch_size: None,
+            eviction_listener: None,
+            record_lock_waits: false,
+            expiration_grace: Duration::ZERO,
+            coalesce_identical_writes: false,
+            coalesce_refreshes_ttl: false,
+            promotion_threshold: Duration::ZERO,
+            rng_seed: None,
+            clock: Arc::new(SystemClock),
+            background_clear: false,
+            spill_over: None,
+            spill_fallback: SpillFallback::InMemory,
+            shadow_ttl_factor: None,
+            checksum_values: false,
+            health_thresholds: HealthThresholds::default(),
+            growth_alarm: DEFAULT_GROWTH_ALARM,
         }
     }
 }
 
+impl fmt::Debug for CacheConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CacheConfig")
+            .field("max_capacity", &self.max_capacity)
+            .field("shards", &self.shards)
+            .field("max_memory", &self.max_memory)
+            .field("max_key_length", &self.max_key_length)
+            .field("max_value_size", &self.max_value_size)
+            .field("eviction_policy", &self.eviction_policy)
+            .field("weigher", &self.weigher.is_some())
+            .field("max_weight", &self.max_weight)
+            .field("default_ttl", &self.default_ttl)
+            .field("ttl_jitter", &self.ttl_jitter)
+            .field("refresh_ttl_on_access", &self.refresh_ttl_on_access)
+            .field("error_ttl", &self.error_ttl)
+            .field("cleanup_interval", &self.cleanup_interval)
+            .field("background_cleanup", &self.background_cleanup)
+            .field("cleanup_bounds", &self.cleanup_bounds)
+            .field("cleanup_batch_size", &self.cleanup_batch_size)
+            .field("eviction_listener", &self.eviction_listener.is_some())
+            .field("record_lock_waits", &self.record_lock_waits)
+            .field("expiration_grace", &self.expiration_grace)
+            .field("coalesce_identical_writes", &self.coalesce_identical_writes)
+            .field("coalesce_refreshes_ttl", &self.coalesce_refreshes_ttl)
+            .field("promotion_threshold", &self.promotion_threshold)
+            .field("rng_seed", &self.rng_seed)
+            .field("clock", &self.clock)
+            .field("background_clear", &self.background_clear)
+            .field("spill_over", &self.spill_over)
+            .field("spill_fallback", &self.spill_fallback)
+            .field("shadow_ttl_factor", &self.shadow_ttl_factor)
+            .field("checksum_values", &self.checksum_values)
+            .field("health_thresholds", &self.health_thresholds)
+            .field("growth_alarm", &self.growth_alarm)
+            .finish()
+    }
+}
+
 impl CacheConfig {
     /// Create a new configuration builder with default values.
     pub fn new() -> Self {
         Self::default()
     }
 
+    /// Starting point for a session store.
+    ///
+    /// Sessions are small, short-lived and mostly read by their owner, so
+    /// the preset bounds the cache at 100,000 entries, gives every entry a
+    /// 30 minute TTL and sweeps expired sessions every 30 seconds in the
+    /// background rather than waiting for them to be read again. A 2 second
+    /// expiration grace absorbs clock skew between the hosts issuing TTLs.
+    /// Pair it with [`Cache::purge_idle`](crate::Cache::purge_idle) to drop
+    /// abandoned sessions before their TTL runs out.
+    ///
+    /// ```
+    /// use in_memory_cache::CacheConfig;
+    /// use std::time::Duration;
+    ///
+    /// let config = CacheConfig::preset_session_store()
+    ///     .default_ttl(Duration::from_secs(600))
+    ///     .build();
+    /// ```
+    pub fn preset_session_store() -> Self {
+        Self::new()
+            .max_capacity(100_000)
+            .default_ttl(Duration::from_secs(30 * 60))
+            .background_cleanup(true)
+            .cleanup_interval(Duration::from_secs(30))
+            .expiration_grace(Duration::from_secs(2))
+    }
+
+    /// Starting point for a reference-data lookup table.
+    ///
+    /// Lookup tables are loaded up front and reloaded wholesale, so nothing
+    /// expires and there is no background cleanup. The capacity is a large
+    /// safety cap of 1,000,000 entries rather than a working-set bound;
+    /// hitting it means the table outgrew the cache. Reloads mostly rewrite
+    /// unchanged rows, so identical writes are coalesced.
+    ///
+    /// Eviction is still LRU; there is no FIFO mode.
+    pub fn preset_lookup_table() -> Self {
+        Self::new()
+            .max_capacity(1_000_000)
+            .default_ttl(Duration::ZERO)
+            .background_cleanup(false)
+            .cleanup_interval(Duration::ZERO)
+            .coalesce_identical_writes(true)
+    }
+
+    /// Starting point for a cache of hot, expensive-to-build objects.
+    ///
+    /// The working set is kept small (10,000 entries) so LRU eviction does
+    /// the work instead of TTLs; entries live for up to an hour as a
+    /// staleness backstop. Lock-wait recording is enabled because contention
+    /// on a few hot keys is the usual failure mode here. Add an
+    /// [`eviction_listener`](CacheConfig::eviction_listener) to observe
+    /// what falls out of the working set.
+    ///
+    /// Capacity counts entries; add [`max_memory`](CacheConfig::max_memory)
+    /// when object sizes vary widely.
+    pub fn preset_hot_objects() -> Self {
+        Self::new()
+            .max_capacity(10_000)
+            .default_ttl(Duration::from_secs(60 * 60))
+            .background_cleanup(true)
+            .cleanup_interval(Duration::from_secs(60))
+            .record_lock_waits(true)
+    }
+
     /// Set the maximum capacity of the cache.
     ///
     /// When the cache reaches this capacity, the least recently used
@@ -66,6 +347,170 @@ impl CacheConfig {
         self
     }
 
+    /// Split the entries into `count` shards, each behind its own lock.
+    ///
+    /// Every key belongs to one shard, chosen by its hash, and operations
+    /// on a single key only lock that shard, so threads working on
+    /// different keys rarely wait for each other. The price is that
+    /// recency is tracked per shard:
+    ///
+    /// - `max_capacity` is split evenly across the shards, and a full
+    ///   shard evicts its own least recently used entry even if another
+    ///   shard holds an older one. The number of shards is capped at the
+    ///   capacity, so every shard holds at least one entry.
+    /// - `max_memory` and `max_weight` need a single shard to stay limits
+    ///   on the whole cache. [`try_build`](CacheConfig::try_build) rejects
+    ///   either together with more than one shard, and `build` uses one.
+    /// - Storage order, as seen by `keys` and the iterators, runs shard by
+    ///   shard.
+    /// - Operations on several keys at once (`get_many`, `set_many`,
+    ///   transactions, `retain`, `clear` and the like) lock every shard.
+    ///
+    /// Statistics stay global. By default the count is the number of CPUs
+    /// (see [`std::thread::available_parallelism`]) rounded up to a power
+    /// of two, lowered so that each shard of a cache with `max_capacity`
+    /// holds at least 1024 entries; a cache with `max_memory` or
+    /// `max_weight` uses one. `shards(1)` keeps eviction exactly LRU. 0 is
+    /// treated as 1.
+    ///
+    /// ```
+    /// use in_memory_cache::{Cache, CacheConfig};
+    ///
+    /// let cache = Cache::new(CacheConfig::new().max_capacity(10_000).shards(8));
+    /// cache.set("a", "1");
+    /// assert_eq!(cache.get("a").as_deref(), Some(&b"1"[..]));
+    /// assert_eq!(cache.len(), 1);
+    /// ```
+    pub fn shards(mut self, count: usize) -> Self {
+        self.shards = Some(count);
+        self
+    }
+
+    /// Bound the cache by the summed length of its keys and values.
+    ///
+    /// Before a write that would take the total past `bytes`, least
+    /// recently used entries are evicted until it fits; an overwrite only
+    /// needs room for the difference in size. The limit applies together
+    /// with [`max_capacity`](CacheConfig::max_capacity), so whichever is
+    /// reached first causes evictions. A single entry larger than the limit
+    /// is still stored, after evicting every other entry. Per-entry
+    /// bookkeeping is not counted (see [`Cache::ENTRY_OVERHEAD`]), and a
+    /// spilled value counts as the size of its reference.
+    ///
+    /// The current total is reported as `memory_bytes` in
+    /// [`StatsSnapshot`](crate::StatsSnapshot).
+    ///
+    /// # Arguments
+    /// * `bytes` - Maximum key and value bytes. Use 0 for unlimited.
+    ///
+    /// [`Cache::ENTRY_OVERHEAD`]: crate::Cache::ENTRY_OVERHEAD
+    pub fn max_memory(mut self, bytes: u64) -> Self {
+        self.max_memory = if bytes == 0 { None } else { Some(bytes) };
+        self
+    }
+
+    /// Reject sets whose key is longer than `bytes`.
+    ///
+    /// [`Cache::try_set`](crate::Cache::try_set) reports a rejected key as
+    /// [`CacheError::InvalidKey`]; plain `set` drops the write and counts
+    /// it in `rejected_sets`. The limit applies to every write that stores
+    /// a value: the `set` family, `set_if_absent`, `set_many`,
+    /// `set_nonblocking`, `get_or_insert_with`, `append`, the counters and
+    /// `compare_and_swap`. Those that return a `Result` report it as an
+    /// error; `append` returns 0 and `get_or_insert_with` hands the value
+    /// back without storing it. Use 0 for unlimited, the default.
+    pub fn max_key_length(mut self, bytes: usize) -> Self {
+        self.max_key_length = if bytes == 0 { None } else { Some(bytes) };
+        self
+    }
+
+    /// Reject sets whose value is larger than `bytes`.
+    ///
+    /// Enforced like [`max_key_length`](CacheConfig::max_key_length), with
+    /// [`CacheError::InvalidValue`] as the error. A value grown by `append`
+    /// is checked at its new size. Use 0 for unlimited, the default.
+    pub fn max_value_size(mut self, bytes: usize) -> Self {
+        self.max_value_size = if bytes == 0 { None } else { Some(bytes) };
+        self
+    }
+
+    /// Choose which entry is evicted when a limit is hit. Defaults to
+    /// [`EvictionPolicy::Lru`].
+    ///
+    /// ```
+    /// use in_memory_cache::{Cache, CacheConfig, EvictionPolicy};
+    ///
+    /// let cache = Cache::new(
+    ///     CacheConfig::new()
+    ///         .max_capacity(2)
+    ///         .eviction_policy(EvictionPolicy::Lfu),
+    /// );
+    /// cache.set("hot", "1");
+    /// for _ in 0..3 {
+    ///     cache.get("hot");
+    /// }
+    /// // A scan over many keys only displaces keys nobody reads
+    /// for i in 0..100 {
+    ///     cache.set(format!("scan:{}", i), "x");
+    ///     cache.get(&format!("scan:{}", i));
+    /// }
+    /// assert!(cache.contains("hot"));
+    /// ```
+    pub fn eviction_policy(mut self, policy: EvictionPolicy) -> Self {
+        self.eviction_policy = policy;
+        self
+    }
+
+    /// Weigh entries with `weigher` for [`max_weight`](CacheConfig::max_weight).
+    ///
+    /// The weigher gets the key and the stored value whenever an entry is
+    /// written, and the weight is kept with the entry until it is removed
+    /// or overwritten; an overwrite is weighed anew, and only the
+    /// difference between the old and new weight needs room. A spilled
+    /// value is weighed as its spill reference. Without a weigher every
+    /// entry weighs 1, so `max_weight` then acts like `max_capacity`.
+    ///
+    /// The weigher runs under the write lock, so it should be cheap and
+    /// must not call back into the cache. If it panics, the panic is
+    /// counted in `callback_panics` and the entry weighs 1.
+    ///
+    /// ```
+    /// use in_memory_cache::{Cache, CacheConfig};
+    /// use std::sync::Arc;
+    ///
+    /// // Values decode to objects about ten times their encoded size
+    /// let cache = Cache::new(
+    ///     CacheConfig::new()
+    ///         .weigher(Arc::new(|_key, value| value.len() as u32 * 10))
+    ///         .max_weight(1_000),
+    /// );
+    /// cache.set("a", vec![0u8; 60]);
+    /// cache.set("b", vec![0u8; 60]);
+    /// assert_eq!(cache.keys(), vec!["b"]);
+    /// assert_eq!(cache.stats().total_weight, 600);
+    /// ```
+    pub fn weigher(mut self, weigher: Weigher) -> Self {
+        self.weigher = Some(weigher);
+        self
+    }
+
+    /// Bound the cache by the summed weight of its entries.
+    ///
+    /// Before a write that would take the total past `weight`, least
+    /// recently used entries other than the one being written are evicted
+    /// until it fits, as with [`max_memory`](CacheConfig::max_memory); a
+    /// single entry heavier than the limit is still stored, alone. The
+    /// limit applies together with `max_capacity` and `max_memory`. The
+    /// current total is reported as `total_weight` in
+    /// [`StatsSnapshot`](crate::StatsSnapshot).
+    ///
+    /// # Arguments
+    /// * `weight` - Maximum summed weight. Use 0 for unlimited.
+    pub fn max_weight(mut self, weight: u64) -> Self {
+        self.max_weight = if weight == 0 { None } else { Some(weight) };
+        self
+    }
+
     /// Set the default TTL for entries.
     ///
     /// Entries without an explicit TTL will use this value.
@@ -75,6 +520,83 @@ impl CacheConfig {
         self
     }
 
+    /// Randomize every TTL by up to `fraction` of itself in either
+    /// direction, so entries written together do not all expire together.
+    ///
+    /// With a `fraction` of 0.1, a 5 minute TTL becomes anything from 4.5 to
+    /// 5.5 minutes, drawn uniformly per write. This applies to the default
+    /// TTL and to explicit ones such as `set_with_ttl`;
+    /// [`Cache::set_with_exact_ttl`](crate::Cache::set_with_exact_ttl) opts a
+    /// single write out. TTLs given to existing entries, as by `expire`, are
+    /// never jittered. [`Cache::ttl`](crate::Cache::ttl)
+    /// reports the remaining time of the jittered deadline, so it can exceed
+    /// the TTL that was asked for.
+    ///
+    /// Draws come from a stream seeded by
+    /// [`rng_seed`](CacheConfig::rng_seed), so a seeded cache jitters the
+    /// same way on every run. `fraction` is capped at 1.0; 0 (or NaN)
+    /// disables jitter, which is the default.
+    ///
+    /// ```
+    /// use in_memory_cache::{Cache, CacheConfig};
+    /// use std::time::Duration;
+    ///
+    /// let cache = Cache::new(CacheConfig::new().ttl_jitter(0.1));
+    /// cache.set_with_ttl("warm", "v", Duration::from_secs(300));
+    /// let ttl = cache.ttl("warm").unwrap().unwrap();
+    /// assert!(ttl <= Duration::from_secs(330));
+    /// assert!(ttl > Duration::from_secs(269));
+    /// ```
+    pub fn ttl_jitter(mut self, fraction: f64) -> Self {
+        self.ttl_jitter = (fraction > 0.0).then_some(fraction.min(1.0));
+        self
+    }
+
+    /// Give entries a sliding expiration: every read that finds an entry
+    /// keeps it alive for its TTL from then on.
+    ///
+    /// Renewing a deadline needs the write lock, so with this enabled a
+    /// promoting read takes it instead of leaving the reordering to the
+    /// next write.
+    ///
+    /// An entry renews by the TTL it was written with, or the one last
+    /// given by `expire`; entries without a TTL are unaffected, and a
+    /// deadline is never moved earlier. Renewal happens when a read
+    /// promotes the entry, so `get`, `get_ref`, `multi_get`, `touch` and
+    /// the `get_or_insert_*` family slide it, while `peek`, `contains`,
+    /// `ttl` and `entry_info` do not. With a
+    /// [`promotion_threshold`](CacheConfig::promotion_threshold), reads
+    /// within the threshold skip promotion, so a deadline can lag the
+    /// latest read by up to the threshold. Rate-limit windows from
+    /// [`Cache::incr_window`](crate::Cache::incr_window) never slide.
+    /// Disabled by default.
+    ///
+    /// ```
+    /// use in_memory_cache::{Cache, CacheConfig};
+    /// use std::time::Duration;
+    ///
+    /// let cache = Cache::new(CacheConfig::new().refresh_ttl_on_access(true));
+    /// cache.set_with_ttl("session:1", "alice", Duration::from_secs(1800));
+    /// cache.get("session:1");
+    /// assert!(cache.ttl("session:1").unwrap().unwrap() > Duration::from_secs(1799));
+    /// ```
+    pub fn refresh_ttl_on_access(mut self, enabled: bool) -> Self {
+        self.refresh_ttl_on_access = enabled;
+        self
+    }
+
+    /// Cache loader failures in
+    /// [`Cache::get_or_try_insert_with`](crate::Cache::get_or_try_insert_with)
+    /// for `ttl`.
+    ///
+    /// Until then, calls for the key get the cached failure instead of
+    /// running their loader. Defaults to not caching failures; set to
+    /// `Duration::ZERO` to restore that.
+    pub fn error_ttl(mut self, ttl: Duration) -> Self {
+        self.error_ttl = if ttl.is_zero() { None } else { Some(ttl) };
+        self
+    }
+
     /// Set the interval for background cleanup of expired entries.
     ///
     /// The background task will run at this interval to remove expired entries.
@@ -90,25 +612,407 @@ impl CacheConfig {
 
     /// Enable or disable background cleanup.
     ///
-    /// When enabled, a background task periodically removes expired entries.
-    /// When disabled, entries are only removed on access (lazy expiration).
+    /// When enabled, [`Cache::new`](crate::Cache::new) starts a background
+    /// thread that periodically removes expired entries until the last
+    /// clone of the cache is dropped. When disabled, entries are only
+    /// removed on access (lazy expiration).
     pub fn background_cleanup(mut self, enabled: bool) -> Self {
         self.background_cleanup = enabled;
         self
     }
 
+    /// Let the background cleanup interval adapt between `min` and `max`.
+    ///
+    /// The task starts at [`cleanup_interval`](CacheConfig::cleanup_interval)
+    /// and after every sweep picks the next interval with
+    /// [`next_interval`](crate::cleanup::next_interval): shorter when many
+    /// entries had expired, longer when few had. Without bounds the
+    /// interval stays fixed. See [`CleanupBounds::new`] for how the bounds
+    /// are normalized.
+    pub fn cleanup_bounds(mut self, min: Duration, max: Duration) -> Self {
+        self.cleanup_bounds = Some(CleanupBounds::new(min, max));
+        self
+    }
+
+    /// Let each background sweep look at no more than `max_items` entries,
+    /// resuming where the previous one stopped, instead of the whole cache.
+    ///
+    /// This bounds how long a sweep holds the write lock on a large cache,
+    /// at the price of expired entries lingering until the sweeps come
+    /// around to them. See
+    /// [`Cache::cleanup_expired_batch`](crate::Cache::cleanup_expired_batch).
+    /// 0 (the default) sweeps the whole cache every time.
+    pub fn cleanup_batch_size(mut self, max_items: usize) -> Self {
+        self.cleanup_batch_size = (max_items > 0).then_some(max_items);
+        self
+    }
+
+    /// Set a listener that is notified when entries leave the cache.
+    ///
+    /// The listener receives the key, the removed value, and a
+    /// [`RemovalCause`](crate::RemovalCause). Notifications are batched and
+    /// delivered after the storage lock is released, so the listener may call
+    /// back into the cache.
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::{Cache, CacheConfig, RemovalCause};
+    /// use std::sync::Arc;
+    ///
+    /// let config = CacheConfig::new()
+    ///     .max_capacity(1)
+    ///     .eviction_listener(Arc::new(|key, _value, cause| {
+    ///         if cause == RemovalCause::Evicted {
+    ///             println!("evicted {}", key);
+    ///         }
+    ///     }))
+    ///     .build();
+    ///
+    /// let cache = Cache::new(config);
+    /// cache.set("a", "1");
+    /// cache.set("b", "2"); // evicts "a"
+    /// ```
+    pub fn eviction_listener(mut self, listener: EvictionListener) -> Self {
+        self.eviction_listener = Some(listener);
+        self
+    }
+
+    /// Measure how long each storage lock acquisition waits.
+    ///
+    /// When enabled, every blocking lock acquisition is timed with a pair of
+    /// `Instant::now()` calls and recorded in a histogram whose p50/p99 are
+    /// exposed as `lock_wait_p50_ns`/`lock_wait_p99_ns` in `StatsSnapshot`.
+    /// Disabled by default; when off, no timing is performed at all.
+    pub fn record_lock_waits(mut self, enabled: bool) -> Self {
+        self.record_lock_waits = enabled;
+        self
+    }
+
+    /// Keep serving entries for `grace` past their nominal expiration.
+    ///
+    /// Useful when TTLs are derived from another host's clock and entries
+    /// would otherwise expire slightly early. Reads, `contains` and cleanup
+    /// all honour the grace window, and reads inside it count as ordinary
+    /// hits. The stored deadline itself is unchanged. Defaults to zero.
+    pub fn expiration_grace(mut self, grace: Duration) -> Self {
+        self.expiration_grace = grace;
+        self
+    }
+
+    /// Skip sets whose value equals the bytes already stored.
+    ///
+    /// A coalesced set does not replace the entry, does not count as a set,
+    /// and does not notify the listener with `Replaced`; it is counted in
+    /// the `coalesced_sets` statistic instead and still marks the entry as
+    /// recently used. The stored expiration is kept unless
+    /// [`CacheConfig::coalesce_refreshes_ttl`] is enabled. Comparison stops at
+    /// a length mismatch, so differing large values stay cheap to reject.
+    /// Disabled by default.
+    pub fn coalesce_identical_writes(mut self, enabled: bool) -> Self {
+        self.coalesce_identical_writes = enabled;
+        self
+    }
+
+    /// Make coalesced sets apply the incoming TTL to the stored entry.
+    ///
+    /// Only has an effect together with
+    /// [`CacheConfig::coalesce_identical_writes`].
+    pub fn coalesce_refreshes_ttl(mut self, enabled: bool) -> Self {
+        self.coalesce_refreshes_ttl = enabled;
+        self
+    }
+
+    /// Only promote an entry to most recently used if it was last promoted
+    /// more than `threshold` ago.
+    ///
+    /// Reads of an entry promoted within the threshold are not buffered
+    /// for reordering, which removes most of that work for keys read many
+    /// times per second. Eviction order becomes approximate: an entry read
+    /// within the threshold keeps its position, and its last-access time
+    /// (as used by `idle_longer_than`) is only refreshed on promotion.
+    /// The default of zero promotes on every read.
+    pub fn promotion_threshold(mut self, threshold: Duration) -> Self {
+        self.promotion_threshold = threshold;
+        self
+    }
+
+    /// Seed every randomized component from `seed`, so that two caches with
+    /// the same seed and the same operations make the same random decisions.
+    ///
+    /// Each component (currently TTL jitter and the `test-util` fault
+    /// injection jitter and failure rates) draws from its own stream derived from the seed and
+    /// the component's name, so adding a component never changes another's
+    /// sequence. Without a seed, a fresh one is drawn from entropy for each
+    /// cache.
+    pub fn rng_seed(mut self, seed: u64) -> Self {
+        self.rng_seed = Some(seed);
+        self
+    }
+
+    /// Read the time from `clock` instead of the [`SystemClock`].
+    ///
+    /// Expiration, access and write times, idle times and sweeps all follow
+    /// it; see [`crate::clock`] for what still uses the system clock.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Drop the entries removed by `clear` on a background thread.
+    ///
+    /// `clear` always swaps the map out and releases the lock before
+    /// freeing the old entries. By default the calling thread then frees
+    /// them (and delivers `Cleared` notifications) before `clear` returns;
+    /// with this enabled, a new thread does that work and `clear` returns
+    /// as soon as the swap is done. Disabled by default.
+    pub fn background_clear(mut self, enabled: bool) -> Self {
+        self.background_clear = enabled;
+        self
+    }
+
+    /// Keep values larger than `threshold_bytes` in files under `dir`
+    /// instead of in memory.
+    ///
+    /// `set` writes an oversized value to a file named after a hash of its
+    /// content (identical values share one file) and stores only a small
+    /// reference entry; `get` reads the file back transparently, counting a
+    /// `spill_hits` statistic. The file is deleted once the last entry
+    /// referring to it is deleted, replaced, evicted, expired or cleared.
+    ///
+    /// The directory is created on first use and must not be shared with
+    /// other caches: [`Cache::gc_spill_dir`](crate::Cache::gc_spill_dir)
+    /// deletes every file in it that this cache does not reference.
+    pub fn spill_over(mut self, threshold_bytes: usize, dir: impl Into<PathBuf>) -> Self {
+        self.spill_over = Some(SpillOver {
+            threshold: threshold_bytes,
+            dir: dir.into(),
+        });
+        self
+    }
+
+    /// What `set` does when a spill-over file cannot be written. Defaults
+    /// to [`SpillFallback::InMemory`].
+    pub fn spill_fallback(mut self, fallback: SpillFallback) -> Self {
+        self.spill_fallback = fallback;
+        self
+    }
+
+    /// Count the get misses that TTLs `factor` times longer would have
+    /// turned into hits, in the `shadow_hits` statistic.
+    ///
+    /// Expiration itself is unchanged; this only answers how much a longer
+    /// TTL would help. A miss counts as a shadow hit if the key was last
+    /// written with a TTL and `factor` times that TTL (plus any
+    /// [`CacheConfig::expiration_grace`]) has not yet passed since, whether
+    /// the expired entry is still present or already removed by expiration.
+    /// Keys deleted, evicted or cleared never count.
+    ///
+    /// Cost: the cache keeps a side table with a copy of the key and a
+    /// deadline for every entry written with a TTL, and each tracked key
+    /// stays in it until its scaled TTL has passed, up to `factor - 1` TTLs
+    /// after the entry itself expired. Stale records are dropped by
+    /// `cleanup_expired` and by the next miss on the key. Every set with a
+    /// TTL and every get miss also takes the table's mutex. A `factor` of
+    /// 1.0 or less (or NaN) leaves the feature disabled.
+    pub fn shadow_ttl_factor(mut self, factor: f64) -> Self {
+        self.shadow_ttl_factor = (factor > 1.0).then_some(factor);
+        self
+    }
+
+    /// Store a CRC-32 of every value at `set` and verify it on every get.
+    ///
+    /// A value that no longer matches its checksum is removed, counted in
+    /// the `checksum_failures` statistic, and reported by
+    /// [`Cache::try_get`](crate::Cache::try_get) as
+    /// [`CacheError::Corrupted`]; the other reads treat it as a miss. Costs
+    /// one pass over the value on every set and every hit, which the
+    /// `checksum` benchmark measures. Disabled by default.
+    pub fn checksum_values(mut self, enabled: bool) -> Self {
+        self.checksum_values = enabled;
+        self
+    }
+
+    /// Set the soft thresholds checked by
+    /// [`Cache::health`](crate::Cache::health) and
+    /// [`Cache::start_monitor`](crate::Cache::start_monitor).
+    ///
+    /// Thresholds only report; crossing one never evicts or rejects
+    /// anything. None are set by default.
+    pub fn health_thresholds(mut self, thresholds: HealthThresholds) -> Self {
+        self.health_thresholds = thresholds;
+        self
+    }
+
+    /// Raise the growth alarm of a cache without `max_capacity` when it
+    /// first holds `entries` entries, and again at every doubling of that.
+    ///
+    /// The alarm is delivered as a
+    /// [`HealthMetric::UnboundedGrowth`](crate::health::HealthMetric::UnboundedGrowth)
+    /// event to the callbacks of
+    /// [`Cache::start_monitor`](crate::Cache::start_monitor). It is ignored
+    /// for bounded caches. Defaults to [`DEFAULT_GROWTH_ALARM`] (one million
+    /// entries); zero turns it off.
+    pub fn growth_alarm(mut self, entries: usize) -> Self {
+        self.growth_alarm = entries;
+        self
+    }
+
     /// Build the final configuration.
     ///
-    /// This method validates the configuration and returns the final config.
+    /// Settings are taken as given: a value the cache cannot honour is
+    /// normalized or ignored when it is used. Use
+    /// [`try_build`](CacheConfig::try_build) to reject such values instead.
     pub fn build(self) -> Self {
         self
     }
 
+    /// Build the final configuration, rejecting settings that contradict
+    /// each other or that the cache cannot honour:
+    ///
+    /// - a [`cleanup_interval`](CacheConfig::cleanup_interval) under 1 ms,
+    ///   which the background task would spin on;
+    /// - a [`shards`](CacheConfig::shards) count of zero, or of more than
+    ///   one together with [`max_memory`](CacheConfig::max_memory) or
+    ///   [`max_weight`](CacheConfig::max_weight), which only a single shard
+    ///   can enforce for the whole cache;
+    /// - a [`max_memory`](CacheConfig::max_memory) smaller than
+    ///   [`max_value_size`](CacheConfig::max_value_size), which would let a
+    ///   single accepted value evict everything else.
+    ///
+    /// The first violation is returned as [`CacheError::InvalidConfig`].
+    ///
+    /// # Example
+    /// ```
+    /// use in_memory_cache::CacheConfig;
+    ///
+    /// assert!(CacheConfig::new().shards(4).try_build().is_ok());
+    /// assert!(CacheConfig::new().shards(0).try_build().is_err());
+    /// ```
+    pub fn try_build(self) -> Result<Self, CacheError> {
+        if let Some(interval) = self.cleanup_interval {
+            if interval < MIN_CLEANUP_INTERVAL {
+                return Err(CacheError::InvalidConfig(format!(
+                    "cleanup_interval of {:?} is below the minimum of {:?}",
+                    interval, MIN_CLEANUP_INTERVAL
+                )));
+            }
+        }
+        match self.shards {
+            Some(0) => {
+                return Err(CacheError::InvalidConfig(
+                    "shards must be at least 1".to_string(),
+                ))
+            }
+            Some(shards) if shards > 1 && self.has_global_limit() => {
+                return Err(CacheError::InvalidConfig(format!(
+                    "{} shards cannot enforce max_memory or max_weight; use 1",
+                    shards
+                )))
+            }
+            _ => {}
+        }
+        if let (Some(memory), Some(value)) = (self.max_memory, self.max_value_size) {
+            if memory < value as u64 {
+                return Err(CacheError::InvalidConfig(format!(
+                    "max_memory of {} bytes is smaller than max_value_size of {} bytes",
+                    memory, value
+                )));
+            }
+        }
+        Ok(self)
+    }
+
+    /// Load a configuration from a TOML file.
+    ///
+    /// The file may set `max_capacity`, `default_ttl_secs`,
+    /// `cleanup_interval_secs`, `background_cleanup` and
+    /// `max_memory_bytes`; settings it leaves out keep their defaults.
+    /// Unknown or repeated keys, values of the wrong type and settings
+    /// [`try_build`](CacheConfig::try_build) rejects are reported as
+    /// [`CacheError::InvalidConfig`] naming the file and line.
+    ///
+    /// ```toml
+    /// # /etc/my-service/cache.toml
+    /// max_capacity = 50_000
+    /// default_ttl_secs = 300
+    /// background_cleanup = true
+    /// ```
+    pub fn from_file(path: &Path) -> CacheResult<CacheConfig> {
+        ConfigOverrides::from_file(path)?
+            .apply(CacheConfig::new())
+            .try_build()
+    }
+
+    /// Load a configuration from environment variables named after the
+    /// [`from_file`](CacheConfig::from_file) keys, upper-cased behind
+    /// `prefix`: `MYAPP_MAX_CAPACITY`, `MYAPP_DEFAULT_TTL_SECS` and so on
+    /// for the prefix `MYAPP`. Booleans also accept `1`/`0`, `yes`/`no`
+    /// and `on`/`off`.
+    ///
+    /// Every variable starting with `{prefix}_` must be one of these, so
+    /// give the cache a prefix of its own.
+    pub fn from_env(prefix: &str) -> CacheResult<CacheConfig> {
+        ConfigOverrides::from_env(prefix)?
+            .apply(CacheConfig::new())
+            .try_build()
+    }
+
     /// Get the maximum capacity, if set.
     pub fn get_max_capacity(&self) -> Option<usize> {
         self.max_capacity
     }
 
+    /// Get the number of shards asked for, or the count picked
+    /// automatically if none was.
+    pub fn get_shards(&self) -> usize {
+        self.shards.unwrap_or_else(|| self.shard_count())
+    }
+
+    /// The number of shards the cache is built with: the count asked for,
+    /// at least 1, or one picked from the number of CPUs. Caches with
+    /// `max_memory` or `max_weight` always get one.
+    pub(crate) fn shard_count(&self) -> usize {
+        if self.has_global_limit() {
+            return 1;
+        }
+        if let Some(count) = self.shards {
+            return count.max(1);
+        }
+        let cpus = std::thread::available_parallelism()
+            .map_or(1, |cpus| cpus.get())
+            .next_power_of_two();
+        match self.max_capacity {
+            Some(capacity) => cpus.min(capacity / MIN_AUTO_SHARD_CAPACITY).max(1),
+            None => cpus,
+        }
+    }
+
+    /// Whether a limit that has to be enforced across every entry at once
+    /// is set.
+    fn has_global_limit(&self) -> bool {
+        self.max_memory.is_some() || self.max_weight.is_some()
+    }
+
+    /// Get the memory limit in bytes, if set.
+    pub fn get_max_memory(&self) -> Option<u64> {
+        self.max_memory
+    }
+
+    /// Get the key length limit in bytes, if set.
+    pub fn get_max_key_length(&self) -> Option<usize> {
+        self.max_key_length
+    }
+
+    /// Get the value size limit in bytes, if set.
+    pub fn get_max_value_size(&self) -> Option<usize> {
+        self.max_value_size
+    }
+
+    /// Get the weight limit, if set.
+    pub fn get_max_weight(&self) -> Option<u64> {
+        self.max_weight
+    }
+
     /// Get the default TTL, if set.
     pub fn get_default_ttl(&self) -> Option<Duration> {
         self.default_ttl
@@ -125,6 +1029,8 @@ mod tests {
         assert!(config.max_capacity.is_none());
         assert!(config.default_ttl.is_none());
         assert!(!config.background_cleanup);
+        assert_eq!(config.expiration_grace, Duration::ZERO);
+        assert_eq!(config.promotion_threshold, Duration::ZERO);
     }
 
     #[test]
@@ -140,15 +1046,273 @@ mod tests {
         assert!(config.background_cleanup);
     }
 
+    #[test]
+    fn test_preset_session_store() {
+        let config = CacheConfig::preset_session_store();
+        assert_eq!(config.max_capacity, Some(100_000));
+        assert_eq!(config.default_ttl, Some(Duration::from_secs(1800)));
+        assert!(config.background_cleanup);
+        assert_eq!(config.cleanup_interval, Some(Duration::from_secs(30)));
+        assert_eq!(config.expiration_grace, Duration::from_secs(2));
+        assert!(!config.coalesce_identical_writes);
+    }
+
+    #[test]
+    fn test_cleanup_bounds() {
+        assert!(CacheConfig::default().cleanup_bounds.is_none());
+        let config =
+            CacheConfig::new().cleanup_bounds(Duration::from_secs(1), Duration::from_secs(60));
+        assert_eq!(
+            config.cleanup_bounds,
+            Some(CleanupBounds::new(
+                Duration::from_secs(1),
+                Duration::from_secs(60)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_shards() {
+        assert_eq!(CacheConfig::new().shards(16).get_shards(), 16);
+        assert_eq!(CacheConfig::new().shards(0).shard_count(), 1);
+    }
+
+    #[test]
+    fn test_default_shards_follow_cpus() {
+        let cpus = std::thread::available_parallelism()
+            .map_or(1, |cpus| cpus.get())
+            .next_power_of_two();
+        assert_eq!(CacheConfig::default().get_shards(), cpus);
+        assert!(CacheConfig::default().get_shards().is_power_of_two());
+        // Small caches keep enough entries per shard
+        assert_eq!(CacheConfig::new().max_capacity(100).get_shards(), 1);
+        assert_eq!(CacheConfig::new().max_capacity(1 << 20).get_shards(), cpus);
+        // Whole-cache limits need one shard, even if more were asked for
+        assert_eq!(CacheConfig::new().max_memory(1 << 20).get_shards(), 1);
+        assert_eq!(
+            CacheConfig::new().max_weight(100).shards(8).shard_count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_cleanup_batch_size() {
+        assert_eq!(CacheConfig::default().cleanup_batch_size, None);
+        assert_eq!(
+            CacheConfig::new()
+                .cleanup_batch_size(1000)
+                .cleanup_batch_size,
+            Some(1000)
+        );
+        assert_eq!(
+            CacheConfig::new().cleanup_batch_size(0).cleanup_batch_size,
+            None
+        );
+    }
+
+    #[test]
+    fn test_preset_lookup_table() {
+        let config = CacheConfig::preset_lookup_table();
+        assert_eq!(config.max_capacity, Some(1_000_000));
+        assert!(config.default_ttl.is_none());
+        assert!(!config.background_cleanup);
+        assert!(config.cleanup_interval.is_none());
+        assert!(config.coalesce_identical_writes);
+    }
+
+    #[test]
+    fn test_preset_hot_objects() {
+        let config = CacheConfig::preset_hot_objects();
+        assert_eq!(config.max_capacity, Some(10_000));
+        assert_eq!(config.default_ttl, Some(Duration::from_secs(3600)));
+        assert!(config.background_cleanup);
+        assert!(config.record_lock_waits);
+        assert!(config.eviction_listener.is_none());
+    }
+
+    #[test]
+    fn test_presets_remain_customizable() {
+        let config = CacheConfig::preset_hot_objects()
+            .max_capacity(50)
+            .record_lock_waits(false)
+            .build();
+        assert_eq!(config.max_capacity, Some(50));
+        assert!(!config.record_lock_waits);
+        assert_eq!(config.default_ttl, Some(Duration::from_secs(3600)));
+    }
+
     #[test]
     fn test_zero_capacity_means_unlimited() {
         let config = CacheConfig::new().max_capacity(0).build();
         assert!(config.max_capacity.is_none());
     }
 
+    #[test]
+    fn test_max_memory() {
+        assert_eq!(CacheConfig::new().get_max_memory(), None);
+        let config = CacheConfig::new().max_capacity(10).max_memory(4096);
+        assert_eq!(config.get_max_memory(), Some(4096));
+        assert_eq!(config.max_capacity, Some(10));
+        assert_eq!(config.max_memory(0).max_memory, None);
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let config = CacheConfig::new();
+        assert_eq!(config.get_max_key_length(), None);
+        assert_eq!(config.get_max_value_size(), None);
+        let config = config.max_key_length(64).max_value_size(1024);
+        assert_eq!(config.get_max_key_length(), Some(64));
+        assert_eq!(config.get_max_value_size(), Some(1024));
+        let config = config.max_key_length(0).max_value_size(0);
+        assert_eq!(config.get_max_key_length(), None);
+        assert_eq!(config.get_max_value_size(), None);
+    }
+
+    #[test]
+    fn test_try_build_accepts_sane_config() {
+        assert!(CacheConfig::new().try_build().is_ok());
+        let config = CacheConfig::new()
+            .shards(1)
+            .cleanup_interval(Duration::from_millis(1))
+            .max_memory(1024)
+            .max_value_size(1024)
+            .try_build()
+            .unwrap();
+        assert_eq!(config.get_shards(), 1);
+        assert!(CacheConfig::new()
+            .shards(4)
+            .max_capacity(100)
+            .try_build()
+            .is_ok());
+        // Zero still means disabled
+        assert!(CacheConfig::new()
+            .cleanup_interval(Duration::ZERO)
+            .try_build()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_try_build_rejects_short_cleanup_interval() {
+        let err = CacheConfig::new()
+            .cleanup_interval(Duration::from_micros(500))
+            .try_build()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid configuration: cleanup_interval of 500µs is below the minimum of 1ms"
+        );
+    }
+
+    #[test]
+    fn test_try_build_rejects_zero_shards() {
+        let err = CacheConfig::new().shards(0).try_build().unwrap_err();
+        assert!(matches!(err, CacheError::InvalidConfig(_)));
+        assert!(err.to_string().contains("shards"), "{}", err);
+        // The permissive path keeps it, and the cache uses one shard
+        assert_eq!(CacheConfig::new().shards(0).build().get_shards(), 0);
+    }
+
+    #[test]
+    fn test_try_build_rejects_shards_with_global_limits() {
+        for config in [
+            CacheConfig::new().max_memory(1 << 20),
+            CacheConfig::new().max_weight(100),
+        ] {
+            let err = config.shards(4).try_build().unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "invalid configuration: 4 shards cannot enforce max_memory or max_weight; use 1"
+            );
+        }
+        // The automatic count adapts instead
+        assert!(CacheConfig::new().max_memory(1 << 20).try_build().is_ok());
+    }
+
+    #[test]
+    fn test_try_build_rejects_memory_below_value_size() {
+        let err = CacheConfig::new()
+            .max_memory(100)
+            .max_value_size(101)
+            .try_build()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid configuration: max_memory of 100 bytes is smaller than \
+             max_value_size of 101 bytes"
+        );
+        // Either limit alone is fine
+        assert!(CacheConfig::new().max_memory(100).try_build().is_ok());
+        assert!(CacheConfig::new().max_value_size(101).try_build().is_ok());
+    }
+
+    #[test]
+    fn test_eviction_policy() {
+        assert_eq!(CacheConfig::new().eviction_policy, EvictionPolicy::Lru);
+        let config = CacheConfig::new().eviction_policy(EvictionPolicy::Fifo);
+        assert_eq!(config.eviction_policy, EvictionPolicy::Fifo);
+    }
+
+    #[test]
+    fn test_weigher() {
+        let config = CacheConfig::new();
+        assert!(config.weigher.is_none());
+        assert_eq!(config.get_max_weight(), None);
+
+        let config = config
+            .weigher(Arc::new(|key, _| key.len() as u32))
+            .max_weight(50);
+        assert_eq!((config.weigher.as_ref().unwrap())("abc", &Bytes::new()), 3);
+        assert_eq!(config.get_max_weight(), Some(50));
+        assert!(format!("{:?}", config).contains("weigher: true"));
+        assert_eq!(config.max_weight(0).max_weight, None);
+    }
+
+    #[test]
+    fn test_error_ttl() {
+        assert_eq!(CacheConfig::new().error_ttl, None);
+        let config = CacheConfig::new().error_ttl(Duration::from_secs(5));
+        assert_eq!(config.error_ttl, Some(Duration::from_secs(5)));
+        assert_eq!(config.error_ttl(Duration::ZERO).error_ttl, None);
+    }
+
     #[test]
     fn test_zero_ttl_means_no_default() {
         let config = CacheConfig::new().default_ttl(Duration::ZERO).build();
         assert!(config.default_ttl.is_none());
     }
+
+    #[test]
+    fn test_ttl_jitter() {
+        assert_eq!(CacheConfig::new().ttl_jitter(0.1).ttl_jitter, Some(0.1));
+        assert_eq!(CacheConfig::new().ttl_jitter(3.0).ttl_jitter, Some(1.0));
+        assert!(CacheConfig::new().ttl_jitter(0.0).ttl_jitter.is_none());
+        assert!(CacheConfig::new().ttl_jitter(f64::NAN).ttl_jitter.is_none());
+    }
+
+    #[test]
+    fn test_refresh_ttl_on_access() {
+        assert!(!CacheConfig::new().refresh_ttl_on_access);
+        assert!(
+            CacheConfig::new()
+                .refresh_ttl_on_access(true)
+                .refresh_ttl_on_access
+        );
+    }
+
+    #[test]
+    fn test_shadow_ttl_factor_needs_a_longer_ttl() {
+        assert_eq!(
+            CacheConfig::new().shadow_ttl_factor(1.5).shadow_ttl_factor,
+            Some(1.5)
+        );
+        assert!(CacheConfig::new()
+            .shadow_ttl_factor(1.0)
+            .shadow_ttl_factor
+            .is_none());
+        assert!(CacheConfig::new()
+            .shadow_ttl_factor(f64::NAN)
+            .shadow_ttl_factor
+            .is_none());
+    }
 }
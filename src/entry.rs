@@ -1,69 +1,261 @@
 //! Cache entry with metadata for TTL and LRU tracking.
+//!
+//! Entries store their creation time, deadline and last access time as
+//! `u32` millisecond offsets from an [`Epoch`] owned by the `Db`, rather than as `Instant`s.
+//! That keeps an entry at 64 bytes (including the checksum slot used by
+//! `CacheConfig::checksum_values`, the weight used by `CacheConfig::weigher`,
+//! the access count used by LFU eviction and the TTL renewed by
+//! `CacheConfig::refresh_ttl_on_access`) instead of over 100, which adds up
+//! at tens of millions of entries.
+//!
+//! A `u32` covers about 49.7 days. The epoch is moved forward (and every
+//! offset shifted back) whenever the current offset passes [`REBASE_AT`],
+//! so a long-running cache never runs out of range. The price is:
+//! - deadlines are capped at the end of the range, so TTLs longer than
+//!   about 24.8 days are shortened to somewhere between 24.8 and 49.7 days;
+//! - access and creation times older than the start of the epoch saturate
+//!   to its start, so idle times and ages of more than about 37 days are
+//!   under-reported;
+//! - if nothing takes the write lock for over 24.8 days, reads see the
+//!   clock stuck at the end of the range until the next write.
 
 use bytes::Bytes;
-use std::time::Instant;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Deadline of an entry without expiration.
+const NEVER: u32 = u32::MAX;
+
+/// Largest offset a time or deadline saturates to; `NEVER` is reserved.
+const MAX_TICK: u32 = u32::MAX - 1;
+
+/// Offset past which the epoch is moved forward.
+pub(crate) const REBASE_AT: u32 = 1 << 31;
+
+/// How far a rebase moves the epoch.
+const REBASE_BY: u32 = 1 << 30;
+
+/// Per-entry bookkeeping on top of the key and value bytes.
+pub(crate) const ENTRY_OVERHEAD: usize = std::mem::size_of::<Entry>();
+
+/// The instant entry offsets are measured from.
+///
+/// The shift only changes in [`Epoch::rebase`], which must be called with the
+/// storage write lock held; every other method must be called with the
+/// storage read or write lock held so it sees a consistent shift.
+#[derive(Debug)]
+pub(crate) struct Epoch {
+    base: Instant,
+    /// Milliseconds from `base` to the current epoch.
+    shift_ms: AtomicU64,
+}
+
+impl Epoch {
+    /// An epoch starting at `base`.
+    pub(crate) fn new(base: Instant) -> Self {
+        Self {
+            base,
+            shift_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// Offset of `now`, saturating at both ends of the range.
+    pub(crate) fn ticks(&self, now: Instant) -> u32 {
+        let since_base = now.saturating_duration_since(self.base).as_millis();
+        let shift = u128::from(self.shift_ms.load(Ordering::Relaxed));
+        u32::try_from(since_base.saturating_sub(shift))
+            .unwrap_or(MAX_TICK)
+            .min(MAX_TICK)
+    }
+
+    /// The instant `ticks` stands for.
+    pub(crate) fn instant(&self, ticks: u32) -> Instant {
+        let shift = self.shift_ms.load(Ordering::Relaxed);
+        self.base + Duration::from_millis(shift + u64::from(ticks))
+    }
+
+    /// Deadline `ttl` after `now`, capped at the end of the range.
+    pub(crate) fn deadline(&self, now: Instant, ttl: Duration) -> u32 {
+        self.ticks(now).saturating_add(millis(ttl)).min(MAX_TICK)
+    }
+
+    /// Whether the epoch should be moved forward before handling `now`.
+    pub(crate) fn needs_rebase(&self, now: Instant) -> bool {
+        self.ticks(now) >= REBASE_AT
+    }
+
+    /// Move the epoch forward by `REBASE_BY` and shift `entries` to match.
+    pub(crate) fn rebase<'a>(&self, entries: impl Iterator<Item = &'a mut Entry>) {
+        for entry in entries {
+            entry.created_at = entry.created_at.saturating_sub(REBASE_BY);
+            let last_accessed = entry.last_accessed.get_mut();
+            *last_accessed = last_accessed.saturating_sub(REBASE_BY);
+            if entry.expires_at != NEVER {
+                entry.expires_at = entry.expires_at.saturating_sub(REBASE_BY);
+            }
+        }
+        self.shift_ms
+            .fetch_add(u64::from(REBASE_BY), Ordering::Relaxed);
+    }
+}
+
+impl Clone for Epoch {
+    fn clone(&self) -> Self {
+        Self {
+            base: self.base,
+            shift_ms: AtomicU64::new(self.shift_ms.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// `d` in whole milliseconds, saturating at `u32::MAX`.
+pub(crate) fn millis(d: Duration) -> u32 {
+    u32::try_from(d.as_millis()).unwrap_or(u32::MAX)
+}
+
+/// Time between two offsets, zero if `later` is not after `earlier`.
+pub(crate) fn between(earlier: u32, later: u32) -> Duration {
+    Duration::from_millis(u64::from(later.saturating_sub(earlier)))
+}
+
+/// Metadata of a live entry, as reported by
+/// [`Cache::entry_info`](crate::Cache::entry_info).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryInfo {
+    /// Time since the value was written.
+    pub created_at_age: Duration,
+    /// Time since the entry was last accessed.
+    pub idle: Duration,
+    /// Remaining time to live, `None` if the entry never expires.
+    pub ttl_remaining: Option<Duration>,
+    /// Length of the stored value; for a spilled value, of its reference.
+    pub size_bytes: usize,
+}
 
 /// A single cache entry containing the value and metadata.
 ///
 /// Each entry tracks:
 /// - The stored value
-/// - When the entry expires (if TTL is set)
+/// - When the value was written
+/// - When the entry expires (if TTL is set), and the TTL a read renews
 /// - When the entry was last accessed (for LRU eviction)
-#[derive(Debug, Clone)]
+/// - Its weight against `CacheConfig::max_weight`
+/// - How often it was read (for LFU eviction)
+///
+/// Times are offsets from the owning `Db`'s [`Epoch`].
+#[derive(Debug)]
 pub struct Entry {
     /// The stored value.
     pub(crate) value: Bytes,
 
-    /// When this entry expires. `None` means no expiration.
-    pub(crate) expires_at: Option<Instant>,
+    /// Offset at which this entry expires. `NEVER` means no expiration.
+    pub(crate) expires_at: u32,
+
+    /// Milliseconds between setting the deadline and reaching it, which a
+    /// sliding expiration renews from each read. 0 for a fixed deadline or
+    /// none.
+    pub(crate) ttl: u32,
+
+    /// Offset of the last access (for LRU tracking). Atomic so that reads
+    /// under the shared lock can stamp it.
+    pub(crate) last_accessed: AtomicU32,
+
+    /// Offset at which the value was written.
+    pub(crate) created_at: u32,
+
+    /// CRC-32 of `value`, if the owning cache checksums values; 0 otherwise.
+    pub(crate) checksum: u32,
 
-    /// When this entry was last accessed (for LRU tracking).
-    pub(crate) last_accessed: Instant,
+    /// Weight computed when the entry was stored; 1 without a weigher.
+    pub(crate) weight: u32,
+
+    /// Reads that found the entry, saturating. Atomic so that reads under
+    /// the shared lock can count.
+    pub(crate) accesses: AtomicU32,
+}
+
+impl Clone for Entry {
+    fn clone(&self) -> Self {
+        Self {
+            value: self.value.clone(),
+            expires_at: self.expires_at,
+            ttl: self.ttl,
+            last_accessed: AtomicU32::new(self.last_accessed()),
+            created_at: self.created_at,
+            checksum: self.checksum,
+            weight: self.weight,
+            accesses: AtomicU32::new(self.accesses()),
+        }
+    }
 }
 
 impl Entry {
-    /// Create a new entry with no expiration.
-    pub fn new(value: Bytes) -> Self {
+    /// Create a new entry with no expiration, accessed at `now`.
+    pub fn new(value: Bytes, now: u32) -> Self {
         Self {
             value,
-            expires_at: None,
-            last_accessed: Instant::now(),
+            expires_at: NEVER,
+            ttl: 0,
+            last_accessed: AtomicU32::new(now),
+            created_at: now,
+            checksum: 0,
+            weight: 1,
+            accesses: AtomicU32::new(0),
         }
     }
 
-    /// Create a new entry with an expiration time.
-    pub fn with_expiration(value: Bytes, expires_at: Instant) -> Self {
+    /// Create a new entry accessed at `now` that expires at `expires_at`.
+    pub fn with_expiration(value: Bytes, now: u32, expires_at: u32) -> Self {
+        let expires_at = expires_at.min(MAX_TICK);
         Self {
             value,
-            expires_at: Some(expires_at),
-            last_accessed: Instant::now(),
+            expires_at,
+            ttl: expires_at.saturating_sub(now),
+            last_accessed: AtomicU32::new(now),
+            created_at: now,
+            checksum: 0,
+            weight: 1,
+            accesses: AtomicU32::new(0),
         }
     }
 
-    /// Check if this entry has expired.
-    pub fn is_expired(&self) -> bool {
-        self.is_expired_at(Instant::now())
+    /// Record the checksum of the value.
+    pub(crate) fn with_checksum(mut self, checksum: u32) -> Self {
+        self.checksum = checksum;
+        self
     }
 
-    /// Check if this entry has expired at a given time.
-    /// This is useful for testing with a controlled clock.
-    pub fn is_expired_at(&self, now: Instant) -> bool {
-        match self.expires_at {
-            Some(expires) => now >= expires,
-            None => false,
-        }
+    /// Check if this entry has expired at a given offset.
+    pub fn is_expired_at(&self, now: u32) -> bool {
+        self.is_expired_with_grace(now, 0)
+    }
+
+    /// Check if this entry has expired at `now`, treating it as live for
+    /// `grace` milliseconds past its nominal deadline.
+    pub fn is_expired_with_grace(&self, now: u32, grace: u32) -> bool {
+        self.expires_at != NEVER && now >= self.expires_at.saturating_add(grace)
+    }
+
+    /// Get the weight the entry counts against `max_weight`.
+    pub fn weight(&self) -> u32 {
+        self.weight
     }
 
-    /// Update the last accessed time to now.
-    pub fn touch(&mut self) {
-        self.last_accessed = Instant::now();
+    /// Get the number of reads that found the entry.
+    pub fn accesses(&self) -> u32 {
+        self.accesses.load(Ordering::Relaxed)
     }
 
-    /// Update the last accessed time to a specific instant.
-    /// This is useful for testing with a controlled clock.
-    #[allow(dead_code)]
-    pub fn touch_at(&mut self, now: Instant) {
-        self.last_accessed = now;
+    /// Count a read that found the entry.
+    pub(crate) fn record_access(&self) {
+        let _ = self
+            .accesses
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_add(1));
+    }
+
+    /// Update the last accessed offset.
+    pub fn touch(&self, now: u32) {
+        self.last_accessed.store(now, Ordering::Relaxed);
     }
 
     /// Get a reference to the value.
@@ -71,55 +263,192 @@ impl Entry {
         &self.value
     }
 
-    /// Get the expiration time, if set.
-    #[allow(dead_code)]
-    pub fn expires_at(&self) -> Option<Instant> {
-        self.expires_at
+    /// Get the expiration offset, if set.
+    pub fn expires_at(&self) -> Option<u32> {
+        (self.expires_at != NEVER).then_some(self.expires_at)
+    }
+
+    /// Move the deadline to `expires_at`, keeping the TTL a read renews.
+    pub fn set_expires_at(&mut self, expires_at: u32) {
+        self.expires_at = expires_at.min(MAX_TICK);
+    }
+
+    /// Expire `ttl` milliseconds after `now`, and renew by `ttl` from then
+    /// on.
+    pub(crate) fn expire_after(&mut self, now: u32, ttl: u32) {
+        self.expires_at = now.saturating_add(ttl).min(MAX_TICK);
+        self.ttl = self.expires_at.saturating_sub(now);
+    }
+
+    /// Remove the deadline.
+    pub fn persist(&mut self) {
+        self.expires_at = NEVER;
+        self.ttl = 0;
     }
 
-    /// Get the last accessed time.
-    #[allow(dead_code)]
-    pub fn last_accessed(&self) -> Instant {
-        self.last_accessed
+    /// The deadline and renewed TTL, for carrying over to a rewritten
+    /// value.
+    pub(crate) fn expiry(&self) -> (u32, u32) {
+        (self.expires_at, self.ttl)
+    }
+
+    /// Keep the entry alive for its TTL from `now`. A later deadline and a
+    /// fixed one are left alone.
+    pub(crate) fn slide(&mut self, now: u32) {
+        if self.ttl > 0 && self.expires_at != NEVER {
+            let renewed = now.saturating_add(self.ttl).min(MAX_TICK);
+            self.expires_at = self.expires_at.max(renewed);
+        }
+    }
+
+    /// Get the last accessed offset.
+    pub fn last_accessed(&self) -> u32 {
+        self.last_accessed.load(Ordering::Relaxed)
+    }
+
+    /// Get the creation offset.
+    pub fn created_at(&self) -> u32 {
+        self.created_at
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::time::Duration;
 
     #[test]
     fn test_new_entry_not_expired() {
-        let entry = Entry::new(Bytes::from("test"));
-        assert!(!entry.is_expired());
-        assert!(entry.expires_at.is_none());
+        let entry = Entry::new(Bytes::from("test"), 0);
+        assert!(!entry.is_expired_at(MAX_TICK));
+        assert!(entry.expires_at().is_none());
+    }
+
+    #[test]
+    fn test_entry_with_expiration() {
+        let entry = Entry::with_expiration(Bytes::from("test"), 0, 60_000);
+        assert!(!entry.is_expired_at(59_999));
+        assert!(entry.is_expired_at(60_000));
+        assert_eq!(entry.expires_at(), Some(60_000));
     }
 
     #[test]
-    fn test_entry_with_future_expiration() {
-        let future = Instant::now() + Duration::from_secs(60);
-        let entry = Entry::with_expiration(Bytes::from("test"), future);
-        assert!(!entry.is_expired());
+    fn test_expiration_grace() {
+        let entry = Entry::with_expiration(Bytes::from("test"), 0, 1000);
+
+        assert!(entry.is_expired_at(1000));
+        assert!(!entry.is_expired_with_grace(1000, 500));
+        assert!(!entry.is_expired_with_grace(1499, 500));
+        assert!(entry.is_expired_with_grace(1500, 500));
+        // A grace reaching past the range never expires
+        assert!(!entry.is_expired_with_grace(MAX_TICK, u32::MAX));
+
+        let forever = Entry::new(Bytes::from("test"), 0);
+        assert!(!forever.is_expired_with_grace(1500, 500));
     }
 
     #[test]
-    fn test_entry_with_past_expiration() {
-        // Create entry that expires in the past (already expired)
-        let past = Instant::now() - Duration::from_secs(1);
-        let entry = Entry::with_expiration(Bytes::from("test"), past);
-        assert!(entry.is_expired());
+    fn test_slide_renews_the_ttl() {
+        let mut entry = Entry::with_expiration(Bytes::from("test"), 100, 1100);
+        entry.slide(600);
+        assert_eq!(entry.expires_at(), Some(1600));
+        // Never shortened
+        entry.set_expires_at(5000);
+        entry.slide(700);
+        assert_eq!(entry.expires_at(), Some(5000));
+
+        entry.expire_after(5000, 200);
+        entry.slide(5100);
+        assert_eq!(entry.expires_at(), Some(5300));
+        assert_eq!(entry.clone().expiry(), (5300, 200));
+
+        let mut fixed = Entry::new(Bytes::from("test"), 0);
+        fixed.set_expires_at(1000);
+        fixed.slide(900);
+        assert_eq!(fixed.expires_at(), Some(1000));
+        entry.persist();
+        entry.slide(6000);
+        assert_eq!(entry.expires_at(), None);
     }
 
     #[test]
     fn test_touch_updates_access_time() {
-        let mut entry = Entry::new(Bytes::from("test"));
-        let initial = entry.last_accessed;
+        let entry = Entry::new(Bytes::from("test"), 5);
+        entry.touch(7);
+        assert_eq!(entry.last_accessed(), 7);
+        assert_eq!(entry.created_at(), 5);
+    }
+
+    #[test]
+    fn test_accesses_saturate_and_survive_clone() {
+        let entry = Entry::new(Bytes::from("test"), 0);
+        entry.record_access();
+        entry.record_access();
+        assert_eq!(entry.clone().accesses(), 2);
+
+        entry.accesses.store(u32::MAX, Ordering::Relaxed);
+        entry.record_access();
+        assert_eq!(entry.accesses(), u32::MAX);
+    }
 
-        // Small delay to ensure time advances
-        std::thread::sleep(Duration::from_millis(1));
-        entry.touch();
+    #[test]
+    fn test_entry_size_does_not_regress() {
+        assert!(
+            std::mem::size_of::<Entry>() <= 64,
+            "Entry grew to {} bytes",
+            std::mem::size_of::<Entry>()
+        );
+        assert_eq!(ENTRY_OVERHEAD, std::mem::size_of::<Entry>());
+    }
+
+    #[test]
+    fn test_epoch_saturates() {
+        let base = Instant::now();
+        let epoch = Epoch::new(base);
+        assert_eq!(epoch.ticks(base), 0);
+        assert_eq!(epoch.ticks(base + Duration::from_millis(1500)), 1500);
+        // Before the epoch and past the range
+        assert_eq!(
+            epoch.ticks(base.checked_sub(Duration::from_secs(1)).unwrap_or(base)),
+            0
+        );
+        assert_eq!(
+            epoch.ticks(base + Duration::from_secs(60 * 86_400)),
+            MAX_TICK
+        );
+        // Deadlines are capped instead of wrapping around
+        assert_eq!(epoch.deadline(base, Duration::from_secs(1)), 1000);
+        assert_eq!(epoch.deadline(base, Duration::MAX), MAX_TICK);
+    }
 
-        assert!(entry.last_accessed > initial);
+    #[test]
+    fn test_rebase_shifts_entries() {
+        let base = Instant::now();
+        let epoch = Epoch::new(base);
+        let later = base + Duration::from_millis(u64::from(REBASE_AT) + 10);
+        assert!(epoch.needs_rebase(later));
+
+        let now = epoch.ticks(later);
+        let mut live = Entry::with_expiration(Bytes::from("a"), now, now + 5000);
+        let mut old = Entry::with_expiration(Bytes::from("b"), 3, 4);
+        let mut forever = Entry::new(Bytes::from("c"), now);
+        epoch.rebase([&mut live, &mut old, &mut forever].into_iter());
+
+        assert!(!epoch.needs_rebase(later));
+        let now = epoch.ticks(later);
+        assert_eq!(now, REBASE_AT - REBASE_BY + 10);
+        assert_eq!(live.expires_at(), Some(now + 5000));
+        assert_eq!(live.last_accessed(), now);
+        assert_eq!(live.created_at(), now);
+        assert!(old.is_expired_at(now));
+        assert_eq!(old.created_at(), 0);
+        assert_eq!(old.last_accessed(), 0);
+        assert_eq!(forever.expires_at(), None);
+    }
+
+    #[test]
+    fn test_between() {
+        assert_eq!(between(10, 250), Duration::from_millis(240));
+        assert_eq!(between(250, 10), Duration::ZERO);
+        assert_eq!(millis(Duration::MAX), u32::MAX);
     }
 }
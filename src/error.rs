@@ -35,6 +35,26 @@ pub enum CacheError {
 
     /// A lock could not be acquired (poisoned mutex).
     LockError(String),
+
+    /// A user-supplied callback (loader, listener, predicate, ...) panicked.
+    CallbackPanic(String),
+
+    /// A configuration value is invalid. The message names its source.
+    InvalidConfig(String),
+
+    /// An internal consistency check failed (see `Cache::debug_validate`).
+    InvariantViolation(String),
+
+    /// An operation did not complete in time.
+    Timeout(String),
+
+    /// A stored value no longer matches its checksum (see
+    /// `CacheConfig::checksum_values`). The entry has been removed.
+    Corrupted { key: String },
+
+    /// The load being waited for was cancelled (see
+    /// `Cache::cancel_in_flight`).
+    LoadCancelled { key: String },
 }
 
 impl fmt::Display for CacheError {
@@ -50,6 +70,12 @@ impl fmt::Display for CacheError {
             CacheError::InvalidKey(reason) => write!(f, "invalid key: {}", reason),
             CacheError::InvalidValue(reason) => write!(f, "invalid value: {}", reason),
             CacheError::LockError(msg) => write!(f, "lock error: {}", msg),
+            CacheError::CallbackPanic(msg) => write!(f, "callback panicked: {}", msg),
+            CacheError::InvalidConfig(msg) => write!(f, "invalid configuration: {}", msg),
+            CacheError::InvariantViolation(msg) => write!(f, "invariant violated: {}", msg),
+            CacheError::Timeout(msg) => write!(f, "timed out: {}", msg),
+            CacheError::Corrupted { key } => write!(f, "value corrupted: '{}'", key),
+            CacheError::LoadCancelled { key } => write!(f, "load cancelled: '{}'", key),
         }
     }
 }
@@ -92,6 +118,17 @@ mod tests {
             format!("{}", err),
             "capacity exceeded: 100 items (max: 100)"
         );
+
+        let err = CacheError::CallbackPanic("boom".to_string());
+        assert_eq!(format!("{}", err), "callback panicked: boom");
+
+        let err = CacheError::Timeout("get".to_string());
+        assert_eq!(format!("{}", err), "timed out: get");
+
+        let err = CacheError::LoadCancelled {
+            key: "user:1".to_string(),
+        };
+        assert_eq!(format!("{}", err), "load cancelled: 'user:1'");
     }
 
     #[test]
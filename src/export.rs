@@ -0,0 +1,637 @@
+//! Directory export and import, one file per key.
+//!
+//! [`Cache::export_dir`](crate::Cache::export_dir) writes every live entry as
+//! two files:
+//!
+//! - `<name>.val` holds the value bytes verbatim.
+//! - `<name>.meta.json` holds the real key, the value file name, the
+//!   remaining TTL and the idle time, plus the wall-clock export time.
+//!
+//! `<name>` is the key with every byte outside `[A-Za-z0-9_-]` (and a
+//! leading `.`) percent-escaped, so it is reversible and never contains a
+//! path separator. Names that would be too long, or that collide with an
+//! earlier name on a case-insensitive file system, get a `~<hash>` suffix;
+//! `~` is always escaped in plain names, so suffixed names cannot clash with
+//! them. Import reads the key from the metadata, so it never needs to
+//! decode file names.
+//!
+//! A sorted export ([`Cache::export_sorted`](crate::Cache::export_sorted))
+//! names the keys in lexicographic order, so collision suffixes do not
+//! depend on the cache's internal order, and leaves out what varies between
+//! caches with the same contents: idle times are written as 0, and the
+//! export time only for entries with a TTL, which need it.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+
+use crate::error::{CacheError, CacheResult};
+use crate::storage::Db;
+
+const VALUE_SUFFIX: &str = ".val";
+const META_SUFFIX: &str = ".meta.json";
+
+/// Longest escaped name used before falling back to a hashed name.
+const MAX_NAME_LEN: usize = 120;
+
+/// A live entry copied out of the cache for export.
+#[derive(Debug, Clone)]
+pub(crate) struct EntryRecord {
+    pub(crate) key: String,
+    pub(crate) value: Bytes,
+    pub(crate) ttl: Option<Duration>,
+    pub(crate) idle: Duration,
+}
+
+/// Write every live entry of `db` into `path`, which must be empty or absent.
+/// With `sorted`, the dump depends only on the keys, values and TTLs.
+pub(crate) fn export_dir(db: &Db, path: &Path, sorted: bool) -> CacheResult<usize> {
+    fs::create_dir_all(path)?;
+    if fs::read_dir(path)?.next().is_some() {
+        return Err(CacheError::IoError(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("export directory {} is not empty", path.display()),
+        )));
+    }
+
+    // Values are reference-counted, so the snapshot is cheap and the lock is
+    // released before any file is written
+    let mut records = db.records();
+    if sorted {
+        records.sort_unstable_by(|a, b| a.key.cmp(&b.key));
+    }
+    let exported_at = unix_millis(SystemTime::now());
+    let mut used = HashSet::new();
+
+    for record in &records {
+        let name = unique_name(&record.key, &mut used);
+        let value_file = format!("{}{}", name, VALUE_SUFFIX);
+        fs::write(path.join(&value_file), &record.value)?;
+
+        let meta = Meta {
+            key: record.key.clone(),
+            file: value_file,
+            ttl_ms: record.ttl.map(|ttl| ttl.as_millis() as u64),
+            idle_ms: if sorted {
+                0
+            } else {
+                record.idle.as_millis() as u64
+            },
+            exported_at_ms: (!sorted || record.ttl.is_some()).then_some(exported_at),
+        };
+        fs::write(
+            path.join(format!("{}{}", name, META_SUFFIX)),
+            meta.to_json(),
+        )?;
+    }
+    Ok(records.len())
+}
+
+/// Load entries written by [`export_dir`] into `db`.
+///
+/// TTLs are shortened by the wall-clock time since the export; entries whose
+/// TTL ran out in the meantime are skipped. Entries are inserted from most
+/// to least idle, which restores their relative LRU order. Without
+/// `notify_replaced` the listener is not told about the values the import
+/// overwrites.
+pub(crate) fn import_dir(db: &Db, path: &Path, notify_replaced: bool) -> CacheResult<usize> {
+    let mut metas = Vec::new();
+    for dir_entry in fs::read_dir(path)? {
+        let file_name = dir_entry?.file_name();
+        let file_name = match file_name.to_str() {
+            Some(name) if name.ends_with(META_SUFFIX) => name.to_string(),
+            _ => continue,
+        };
+        let text = fs::read_to_string(path.join(&file_name))?;
+        let meta = Meta::from_json(&text)
+            .map_err(|e| CacheError::ParseError(format!("{}: {}", file_name, e)))?;
+        metas.push(meta);
+    }
+    metas.sort_by(|a, b| b.idle_ms.cmp(&a.idle_ms).then_with(|| a.key.cmp(&b.key)));
+
+    let now = unix_millis(SystemTime::now());
+    let mut imported = 0;
+    for meta in metas {
+        // Reject anything that would escape the export directory
+        if meta.file.contains(['/', '\\']) || meta.file.starts_with('.') {
+            return Err(CacheError::ParseError(format!(
+                "invalid value file name '{}'",
+                meta.file
+            )));
+        }
+        let value = Bytes::from(fs::read(path.join(&meta.file))?);
+        let ttl = match (meta.ttl_ms, meta.exported_at_ms) {
+            (Some(ttl_ms), Some(exported_at)) => {
+                match ttl_ms.saturating_sub(now.saturating_sub(exported_at)) {
+                    0 => continue,
+                    remaining => Some(Duration::from_millis(remaining)),
+                }
+            }
+            (Some(_), None) => {
+                return Err(CacheError::ParseError(format!(
+                    "'{}' has a TTL but no export time",
+                    meta.key
+                )))
+            }
+            (None, _) => None,
+        };
+        db.load(meta.key, value, ttl, notify_replaced);
+        imported += 1;
+    }
+    Ok(imported)
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Percent-escape `key` into a file-name-safe string.
+fn escape_name(key: &str) -> String {
+    let mut name = String::with_capacity(key.len());
+    for (i, byte) in key.bytes().enumerate() {
+        let plain =
+            byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'-' || (byte == b'.' && i > 0);
+        if plain {
+            name.push(byte as char);
+        } else {
+            name.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    if name.is_empty() {
+        // The empty key still needs a file name; "%" alone never decodes
+        name.push('%');
+    }
+    name
+}
+
+/// Reverse [`escape_name`]. Returns `None` for hashed or malformed names.
+#[cfg(test)]
+fn unescape_name(name: &str) -> Option<String> {
+    if name == "%" {
+        return Some(String::new());
+    }
+    let mut bytes = Vec::with_capacity(name.len());
+    let mut rest = name.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else if byte == b'~' {
+            return None;
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Pick a file name for `key` that is unique among `used`, compared
+/// case-insensitively.
+fn unique_name(key: &str, used: &mut HashSet<String>) -> String {
+    let mut name = escape_name(key);
+    if name.len() > MAX_NAME_LEN || used.contains(&name.to_ascii_lowercase()) {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        // Leave room for "~" and 16 hex digits, without splitting an escape
+        name.truncate(MAX_NAME_LEN - 17);
+        let len = name.len();
+        if name.ends_with('%') {
+            name.truncate(len - 1);
+        } else if len >= 2 && name.as_bytes()[len - 2] == b'%' {
+            name.truncate(len - 2);
+        }
+        name = format!("{}~{:016x}", name, hasher.finish());
+
+        let base = name.clone();
+        let mut n = 1;
+        while used.contains(&name.to_ascii_lowercase()) {
+            name = format!("{}~{}", base, n);
+            n += 1;
+        }
+    }
+    used.insert(name.to_ascii_lowercase());
+    name
+}
+
+/// Contents of a `.meta.json` sidecar.
+#[derive(Debug, Clone, PartialEq)]
+struct Meta {
+    key: String,
+    file: String,
+    ttl_ms: Option<u64>,
+    idle_ms: u64,
+    /// Only needed to age a TTL; a sorted export leaves it out otherwise.
+    exported_at_ms: Option<u64>,
+}
+
+impl Meta {
+    fn to_json(&self) -> String {
+        let number_or_null = |value: Option<u64>| match value {
+            Some(value) => value.to_string(),
+            None => "null".to_string(),
+        };
+        format!(
+            "{{\"key\":{},\"file\":{},\"ttl_ms\":{},\"idle_ms\":{},\"exported_at_ms\":{}}}\n",
+            json_string(&self.key),
+            json_string(&self.file),
+            number_or_null(self.ttl_ms),
+            self.idle_ms,
+            number_or_null(self.exported_at_ms)
+        )
+    }
+
+    fn from_json(text: &str) -> Result<Self, String> {
+        let mut parser = JsonParser {
+            input: text.as_bytes(),
+            pos: 0,
+        };
+        let mut key = None;
+        let mut file = None;
+        let mut ttl_ms = None;
+        let mut idle_ms = None;
+        let mut exported_at_ms = None;
+
+        parser.expect(b'{')?;
+        loop {
+            let field = parser.string()?;
+            parser.expect(b':')?;
+            match field.as_str() {
+                "key" => key = Some(parser.string()?),
+                "file" => file = Some(parser.string()?),
+                "ttl_ms" => ttl_ms = Some(parser.number_or_null()?),
+                "idle_ms" => idle_ms = parser.number_or_null()?,
+                "exported_at_ms" => exported_at_ms = Some(parser.number_or_null()?),
+                other => return Err(format!("unknown field '{}'", other)),
+            }
+            if parser.peek() == Some(b',') {
+                parser.pos += 1;
+                continue;
+            }
+            parser.expect(b'}')?;
+            break;
+        }
+        parser.skip_whitespace();
+        if parser.pos != parser.input.len() {
+            return Err("trailing characters".to_string());
+        }
+
+        Ok(Self {
+            key: key.ok_or("missing 'key'")?,
+            file: file.ok_or("missing 'file'")?,
+            ttl_ms: ttl_ms.ok_or("missing 'ttl_ms'")?,
+            idle_ms: idle_ms.ok_or("missing 'idle_ms'")?,
+            exported_at_ms: exported_at_ms.ok_or("missing 'exported_at_ms'")?,
+        })
+    }
+}
+
+/// Encode `s` as a JSON string literal.
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Just enough JSON to read back what [`Meta::to_json`] writes: a flat
+/// object of strings, unsigned integers and `null`.
+struct JsonParser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl JsonParser<'_> {
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.input.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .input
+            .get(self.pos)
+            .is_some_and(|b| b.is_ascii_whitespace())
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        if self.peek() != Some(byte) {
+            return Err(format!("expected '{}' at byte {}", byte as char, self.pos));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut out = Vec::new();
+        loop {
+            let byte = *self.input.get(self.pos).ok_or("unterminated string")?;
+            self.pos += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let escape = *self.input.get(self.pos).ok_or("unterminated escape")?;
+                    self.pos += 1;
+                    match escape {
+                        b'"' | b'\\' | b'/' => out.push(escape),
+                        b'n' => out.push(b'\n'),
+                        b'r' => out.push(b'\r'),
+                        b't' => out.push(b'\t'),
+                        b'b' => out.push(0x08),
+                        b'f' => out.push(0x0c),
+                        b'u' => {
+                            let c = self.unicode_escape()?;
+                            let mut buf = [0; 4];
+                            out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                        }
+                        other => return Err(format!("invalid escape '\\{}'", other as char)),
+                    }
+                }
+                byte => out.push(byte),
+            }
+        }
+        String::from_utf8(out).map_err(|_| "string is not valid UTF-8".to_string())
+    }
+
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.hex4()?;
+        if !(0xD800..0xDC00).contains(&high) {
+            return char::from_u32(high).ok_or_else(|| "invalid \\u escape".to_string());
+        }
+        // Surrogate pair
+        if self.input.get(self.pos..self.pos + 2) != Some(b"\\u") {
+            return Err("unpaired surrogate".to_string());
+        }
+        self.pos += 2;
+        let low = self.hex4()?;
+        if !(0xDC00..0xE000).contains(&low) {
+            return Err("unpaired surrogate".to_string());
+        }
+        char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00))
+            .ok_or_else(|| "invalid \\u escape".to_string())
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self
+            .input
+            .get(self.pos..self.pos + 4)
+            .and_then(|d| std::str::from_utf8(d).ok())
+            .and_then(|d| u32::from_str_radix(d, 16).ok())
+            .ok_or("invalid \\u escape")?;
+        self.pos += 4;
+        Ok(digits)
+    }
+
+    fn number_or_null(&mut self) -> Result<Option<u64>, String> {
+        self.skip_whitespace();
+        if self.input[self.pos..].starts_with(b"null") {
+            self.pos += 4;
+            return Ok(None);
+        }
+        let start = self.pos;
+        while self.input.get(self.pos).is_some_and(u8::is_ascii_digit) {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.input[start..self.pos])
+            .ok()
+            .and_then(|digits| digits.parse().ok())
+            .map(Some)
+            .ok_or_else(|| format!("expected a number at byte {}", start))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CacheConfig;
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "in-memory-cache-export-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_escape_is_reversible_and_path_safe() {
+        for key in [
+            "plain",
+            "a/b",
+            "../etc/passwd",
+            ".hidden",
+            "ключ",
+            "a b%c~",
+            "",
+        ] {
+            let name = escape_name(key);
+            assert!(!name.contains(['/', '\\']), "{}", name);
+            assert!(!name.starts_with('.'), "{}", name);
+            assert_eq!(unescape_name(&name).as_deref(), Some(key));
+        }
+    }
+
+    #[test]
+    fn test_unique_name_handles_case_and_length() {
+        let mut used = HashSet::new();
+        let upper = unique_name("Key", &mut used);
+        let lower = unique_name("key", &mut used);
+        assert_eq!(upper, "Key");
+        assert_ne!(upper.to_ascii_lowercase(), lower.to_ascii_lowercase());
+        assert!(lower.contains('~'));
+
+        let long = "é".repeat(200);
+        let name = unique_name(&long, &mut used);
+        assert!(name.len() <= MAX_NAME_LEN);
+        let (prefix, _) = name.split_once('~').unwrap();
+        assert!(prefix.len() % 3 == 0, "escape split: {}", prefix);
+    }
+
+    #[test]
+    fn test_meta_json_round_trip() {
+        let meta = Meta {
+            key: "quote\" slash\\ nl\n ctl\u{1} 日本".to_string(),
+            file: "x.val".to_string(),
+            ttl_ms: None,
+            idle_ms: 7,
+            exported_at_ms: Some(1_700_000_000_000),
+        };
+        assert_eq!(Meta::from_json(&meta.to_json()).unwrap(), meta);
+
+        let meta = Meta {
+            ttl_ms: Some(1500),
+            ..meta
+        };
+        assert_eq!(Meta::from_json(&meta.to_json()).unwrap(), meta);
+        assert!(Meta::from_json("{\"key\":\"k\"}").is_err());
+        assert!(Meta::from_json("{\"key\":\"\\ud800\"}").is_err());
+    }
+
+    #[test]
+    fn test_export_import_round_trip() {
+        let dir = temp_dir("round-trip");
+        let source = Db::new(CacheConfig::new());
+        let keys = [
+            "plain",
+            "path/with/separators",
+            "../escape",
+            "ключ:日本",
+            "Case",
+            "case",
+            "",
+        ];
+        for key in keys {
+            source.set(key, format!("value of {}", key));
+        }
+        source.set("binary", Bytes::from(vec![0u8, 255, b'\n', b'/']));
+        source.set_with_ttl("ttl", "short", Duration::from_secs(3600));
+
+        assert_eq!(export_dir(&source, &dir, false).unwrap(), keys.len() + 2);
+        assert!(
+            export_dir(&source, &dir, false).is_err(),
+            "non-empty directory"
+        );
+
+        let target = Db::new(CacheConfig::new());
+        assert_eq!(import_dir(&target, &dir, true).unwrap(), keys.len() + 2);
+        assert_eq!(target.iter_sorted(), source.iter_sorted());
+
+        let remaining = target.records();
+        let ttl = remaining.iter().find(|r| r.key == "ttl").unwrap().ttl;
+        assert!(ttl.is_some_and(|ttl| ttl > Duration::from_secs(3500)));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    /// Every file in `dir` with its contents.
+    fn dump(dir: &Path) -> std::collections::BTreeMap<String, Vec<u8>> {
+        fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                let name = entry.file_name().into_string().unwrap();
+                (name, fs::read(entry.path()).unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_sorted_exports_are_byte_identical() {
+        // "Case" and "case" collide, so one is suffixed depending on order
+        let keys = ["b", "Case", "case", "a/b", &"long".repeat(50)];
+        let first = Db::new(CacheConfig::new());
+        let second = Db::new(CacheConfig::new().shards(4));
+        for key in keys {
+            first.set(key, format!("value of {}", key));
+        }
+        std::thread::sleep(Duration::from_millis(5));
+        for key in keys.iter().rev() {
+            second.set(*key, format!("value of {}", key));
+            second.get(keys[0]);
+        }
+
+        let (a, b) = (temp_dir("sorted-a"), temp_dir("sorted-b"));
+        assert_eq!(export_dir(&first, &a, true).unwrap(), keys.len());
+        assert_eq!(export_dir(&second, &b, true).unwrap(), keys.len());
+        assert_eq!(dump(&a), dump(&b));
+        assert!(dump(&a).contains_key("Case.val"));
+
+        // The dump still imports, in key order
+        let target = Db::new(CacheConfig::new().shards(1));
+        assert_eq!(import_dir(&target, &b, true).unwrap(), keys.len());
+        assert_eq!(target.iter_sorted(), first.iter_sorted());
+        assert_eq!(target.keys(), target.keys_sorted());
+
+        // A TTL keeps its export time so it can be aged on import
+        first.set_with_ttl("ttl", "x", Duration::from_secs(3600));
+        let c = temp_dir("sorted-c");
+        export_dir(&first, &c, true).unwrap();
+        let metas = dump(&c);
+        let ttl_meta = String::from_utf8(metas["ttl.meta.json"].clone()).unwrap();
+        assert!(
+            !ttl_meta.contains("\"exported_at_ms\":null"),
+            "{}",
+            ttl_meta
+        );
+        let plain_meta = String::from_utf8(metas["b.meta.json"].clone()).unwrap();
+        assert!(plain_meta.ends_with("\"idle_ms\":0,\"exported_at_ms\":null}\n"));
+
+        for dir in [a, b, c] {
+            let _ = fs::remove_dir_all(&dir);
+        }
+    }
+
+    #[test]
+    fn test_quiet_import_over_warm_cache() {
+        use crate::listener::RemovalCause;
+        use std::sync::{Arc, Mutex};
+
+        let dir = temp_dir("quiet");
+        let source = Db::new(CacheConfig::new());
+        source.set("a", "new a");
+        source.set("b", "new b");
+        export_dir(&source, &dir, false).unwrap();
+
+        let causes = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&causes);
+        let target = Db::new(CacheConfig::new().eviction_listener(Arc::new(
+            move |_key: &str, _value, cause| sink.lock().unwrap().push(cause),
+        )));
+        target.set("a", "old a");
+        assert_eq!(import_dir(&target, &dir, false).unwrap(), 2);
+        assert!(causes.lock().unwrap().is_empty());
+        assert_eq!(target.iter_sorted(), source.iter_sorted());
+
+        // Without the flag every overwrite is reported once
+        assert_eq!(import_dir(&target, &dir, true).unwrap(), 2);
+        assert_eq!(*causes.lock().unwrap(), [RemovalCause::Replaced; 2]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_import_rejects_escaping_value_file() {
+        let dir = temp_dir("escape");
+        fs::create_dir_all(&dir).unwrap();
+        let meta = Meta {
+            key: "k".to_string(),
+            file: "../outside".to_string(),
+            ttl_ms: None,
+            idle_ms: 0,
+            exported_at_ms: Some(0),
+        };
+        fs::write(dir.join("k.meta.json"), meta.to_json()).unwrap();
+
+        let db = Db::new(CacheConfig::new());
+        assert!(matches!(
+            import_dir(&db, &dir, true),
+            Err(CacheError::ParseError(_))
+        ));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
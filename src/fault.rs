@@ -0,0 +1,463 @@
+//! Latency and fault injection for resilience tests (feature `test-util`).
+//!
+//! [`FaultyCache`] wraps a [`Cache`] and implements [`CacheOps`], applying a
+//! [`FaultPolicy`] to every operation. Code under test depends on
+//! `CacheOps`, so production paths stay untouched:
+//!
+//! ```
+//! use in_memory_cache::fault::{FaultKind, FaultPolicy};
+//! use in_memory_cache::{Cache, CacheError, CacheRead, CacheWrite};
+//!
+//! let faulty = Cache::default().with_fault_injection(
+//!     FaultPolicy::new().fail_gets(100, FaultKind::Timeout),
+//! );
+//! faulty.set("key", "value".into());
+//! assert!(matches!(faulty.try_get("key"), Err(CacheError::Timeout(_))));
+//! assert_eq!(faulty.counters().failed_gets, 1);
+//!
+//! // Heal the cache mid-test
+//! faulty.set_policy(FaultPolicy::new());
+//! assert!(faulty.get("key").is_some());
+//! ```
+//!
+//! The infallible `CacheOps` methods report an injected failure the way a
+//! degraded cache would look to a caller: a failed get is a miss and a
+//! failed set is dropped. The `try_*` methods surface the error instead.
+//!
+//! [`CacheOps`]: crate::CacheOps
+
+use bytes::Bytes;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use crate::cache::Cache;
+use crate::error::{CacheError, CacheResult};
+use crate::ops::{CacheRead, CacheWrite};
+use crate::rng::Rng;
+use crate::stats::StatsSnapshot;
+
+/// The error an injected failure produces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultKind {
+    /// `CacheError::Timeout`.
+    Timeout,
+    /// `CacheError::LockError`.
+    LockError,
+}
+
+impl FaultKind {
+    fn error(self, op: &str) -> CacheError {
+        match self {
+            FaultKind::Timeout => CacheError::Timeout(format!("injected fault in {}", op)),
+            FaultKind::LockError => CacheError::LockError(format!("injected fault in {}", op)),
+        }
+    }
+}
+
+/// What a [`FaultyCache`] does to the operations passing through it.
+///
+/// The default policy injects nothing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaultPolicy {
+    latency: Duration,
+    jitter: Duration,
+    get_failure: Option<(u8, FaultKind)>,
+    set_failure: Option<(u8, FaultKind)>,
+    blackhole_writes: bool,
+    seed: Option<u64>,
+}
+
+impl Default for FaultPolicy {
+    fn default() -> Self {
+        Self {
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            get_failure: None,
+            set_failure: None,
+            blackhole_writes: false,
+            seed: None,
+        }
+    }
+}
+
+impl FaultPolicy {
+    /// A policy that injects nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Delay every get, set, delete and contains by `latency`.
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Add a further uniformly random delay of up to `jitter`.
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Fail `percent` (clamped to 100) of gets with `kind`.
+    pub fn fail_gets(mut self, percent: u8, kind: FaultKind) -> Self {
+        self.get_failure = Some((percent.min(100), kind));
+        self
+    }
+
+    /// Fail `percent` (clamped to 100) of sets with `kind`.
+    pub fn fail_sets(mut self, percent: u8, kind: FaultKind) -> Self {
+        self.set_failure = Some((percent.min(100), kind));
+        self
+    }
+
+    /// Accept sets without storing them.
+    pub fn blackhole_writes(mut self, enabled: bool) -> Self {
+        self.blackhole_writes = enabled;
+        self
+    }
+
+    /// Seed for the jitter and failure-rate random numbers, so a test run
+    /// can be reproduced. Without one, the sequence is derived from the
+    /// cache's [`CacheConfig::rng_seed`](crate::CacheConfig::rng_seed).
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+}
+
+/// How many faults a [`FaultyCache`] has injected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FaultCounters {
+    /// Operations that were delayed.
+    pub delayed: u64,
+    /// Gets that failed.
+    pub failed_gets: u64,
+    /// Sets that failed.
+    pub failed_sets: u64,
+    /// Sets that were accepted but dropped.
+    pub blackholed_writes: u64,
+}
+
+#[derive(Debug)]
+struct PolicyState {
+    policy: FaultPolicy,
+    rng: Rng,
+}
+
+/// A [`CacheOps`] wrapper that injects latency and failures.
+///
+/// Created with [`Cache::with_fault_injection`]. The wrapped cache is a
+/// shared handle, so the test can still inspect it directly.
+///
+/// [`CacheOps`]: crate::CacheOps
+#[derive(Debug)]
+pub struct FaultyCache {
+    inner: Cache,
+    /// Seed used when the policy does not set one.
+    default_seed: u64,
+    state: Mutex<PolicyState>,
+    delayed: AtomicU64,
+    failed_gets: AtomicU64,
+    failed_sets: AtomicU64,
+    blackholed_writes: AtomicU64,
+}
+
+/// The decision for one operation, made under the policy lock.
+struct Plan {
+    delay: Duration,
+    failure: Option<FaultKind>,
+    blackhole: bool,
+}
+
+#[derive(Clone, Copy)]
+enum Op {
+    Get,
+    Set,
+    Other,
+}
+
+impl FaultyCache {
+    pub(crate) fn new(inner: Cache, policy: FaultPolicy, default_seed: u64) -> Self {
+        Self {
+            inner,
+            default_seed,
+            state: Mutex::new(PolicyState {
+                rng: Rng::new(policy.seed.unwrap_or(default_seed)),
+                policy,
+            }),
+            delayed: AtomicU64::new(0),
+            failed_gets: AtomicU64::new(0),
+            failed_sets: AtomicU64::new(0),
+            blackholed_writes: AtomicU64::new(0),
+        }
+    }
+
+    /// Replace the policy. The random sequence restarts from its seed.
+    pub fn set_policy(&self, policy: FaultPolicy) {
+        let mut state = self.lock_state();
+        state.rng = Rng::new(policy.seed.unwrap_or(self.default_seed));
+        state.policy = policy;
+    }
+
+    /// The current policy.
+    pub fn policy(&self) -> FaultPolicy {
+        self.lock_state().policy.clone()
+    }
+
+    /// Counts of injected faults so far.
+    pub fn counters(&self) -> FaultCounters {
+        FaultCounters {
+            delayed: self.delayed.load(Ordering::Relaxed),
+            failed_gets: self.failed_gets.load(Ordering::Relaxed),
+            failed_sets: self.failed_sets.load(Ordering::Relaxed),
+            blackholed_writes: self.blackholed_writes.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The wrapped cache, bypassing fault injection.
+    pub fn inner(&self) -> &Cache {
+        &self.inner
+    }
+
+    fn lock_state(&self) -> std::sync::MutexGuard<'_, PolicyState> {
+        // The state holds no invariants a panicking test could break
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn plan(&self, op: Op) -> Plan {
+        let mut state = self.lock_state();
+        let policy = state.policy.clone();
+
+        let mut delay = policy.latency;
+        if !policy.jitter.is_zero() {
+            let nanos = policy.jitter.as_nanos().min(u64::MAX as u128) as u64;
+            delay += Duration::from_nanos(state.rng.below(nanos.saturating_add(1)));
+        }
+
+        let failure = match op {
+            Op::Get => policy.get_failure,
+            Op::Set => policy.set_failure,
+            Op::Other => None,
+        };
+        let failure = match failure {
+            Some((percent, kind)) if state.rng.below(100) < u64::from(percent) => Some(kind),
+            _ => None,
+        };
+
+        Plan {
+            delay,
+            failure,
+            blackhole: matches!(op, Op::Set) && policy.blackhole_writes,
+        }
+    }
+
+    /// Sleep for the planned delay and return the planned failure, if any.
+    fn apply(&self, op: Op) -> Plan {
+        let plan = self.plan(op);
+        if !plan.delay.is_zero() {
+            self.delayed.fetch_add(1, Ordering::Relaxed);
+            thread::sleep(plan.delay);
+        }
+        match (op, plan.failure) {
+            (Op::Get, Some(_)) => self.failed_gets.fetch_add(1, Ordering::Relaxed),
+            (Op::Set, Some(_)) => self.failed_sets.fetch_add(1, Ordering::Relaxed),
+            _ => 0,
+        };
+        plan
+    }
+
+    fn set_inner(&self, key: &str, value: Bytes, ttl: Option<Duration>) -> CacheResult<()> {
+        let plan = self.apply(Op::Set);
+        if let Some(kind) = plan.failure {
+            return Err(kind.error("set"));
+        }
+        if plan.blackhole {
+            self.blackholed_writes.fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        }
+        match ttl {
+            Some(ttl) => self.inner.try_set_with_ttl(key, value, ttl),
+            None => self.inner.try_set(key, value),
+        }
+    }
+}
+
+impl CacheRead for FaultyCache {
+    fn get(&self, key: &str) -> Option<Bytes> {
+        self.try_get(key).ok().flatten()
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        self.apply(Op::Other);
+        self.inner.contains(key)
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn stats(&self) -> StatsSnapshot {
+        self.inner.stats()
+    }
+
+    fn try_get(&self, key: &str) -> CacheResult<Option<Bytes>> {
+        match self.apply(Op::Get).failure {
+            Some(kind) => Err(kind.error("get")),
+            None => Ok(self.inner.get(key)),
+        }
+    }
+}
+
+impl CacheWrite for FaultyCache {
+    fn set(&self, key: &str, value: Bytes) {
+        let _ = self.set_inner(key, value, None);
+    }
+
+    fn set_with_ttl(&self, key: &str, value: Bytes, ttl: Duration) {
+        let _ = self.set_inner(key, value, Some(ttl));
+    }
+
+    fn delete(&self, key: &str) -> bool {
+        self.apply(Op::Other);
+        self.inner.delete(key)
+    }
+
+    fn try_set(&self, key: &str, value: Bytes) -> CacheResult<()> {
+        self.set_inner(key, value, None)
+    }
+
+    fn try_set_with_ttl(&self, key: &str, value: Bytes, ttl: Duration) -> CacheResult<()> {
+        self.set_inner(key, value, Some(ttl))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn faulty(policy: FaultPolicy) -> FaultyCache {
+        Cache::default().with_fault_injection(policy)
+    }
+
+    #[test]
+    fn test_default_policy_is_transparent() {
+        let cache = faulty(FaultPolicy::new());
+        cache.set("key", Bytes::from("value"));
+        assert_eq!(cache.try_get("key").unwrap(), Some(Bytes::from("value")));
+        assert!(cache.contains("key"));
+        assert!(cache.delete("key"));
+        assert_eq!(cache.counters(), FaultCounters::default());
+    }
+
+    #[test]
+    fn test_cache_rng_seed_makes_jitter_reproducible() {
+        let policy = FaultPolicy::new()
+            .jitter(Duration::from_millis(5))
+            .fail_gets(50, FaultKind::Timeout);
+        let seeded = |seed| {
+            Cache::new(crate::CacheConfig::new().rng_seed(seed))
+                .with_fault_injection(policy.clone())
+        };
+        let plans = |cache: &FaultyCache| {
+            (0..50)
+                .map(|_| {
+                    let plan = cache.plan(Op::Get);
+                    (plan.delay, plan.failure)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let (a, b, c) = (seeded(9), seeded(9), seeded(10));
+        assert_eq!(plans(&a), plans(&b));
+        assert_ne!(plans(&a), plans(&c));
+    }
+
+    #[test]
+    fn test_fixed_latency() {
+        let cache = faulty(FaultPolicy::new().latency(Duration::from_millis(20)));
+        let start = Instant::now();
+        cache.get("key");
+        cache.set("key", Bytes::from("value"));
+        assert!(start.elapsed() >= Duration::from_millis(40));
+        assert_eq!(cache.counters().delayed, 2);
+    }
+
+    #[test]
+    fn test_jitter_is_bounded_and_reproducible() {
+        let policy = FaultPolicy::new().jitter(Duration::from_millis(5)).seed(42);
+        let a = faulty(policy.clone());
+        let b = faulty(policy);
+        for _ in 0..20 {
+            let (pa, pb) = (a.plan(Op::Get), b.plan(Op::Get));
+            assert_eq!(pa.delay, pb.delay);
+            assert!(pa.delay <= Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn test_failed_gets() {
+        let cache = faulty(FaultPolicy::new().fail_gets(100, FaultKind::LockError));
+        cache.set("key", Bytes::from("value"));
+        assert!(matches!(
+            cache.try_get("key"),
+            Err(CacheError::LockError(_))
+        ));
+        // The infallible path reports a miss
+        assert_eq!(cache.get("key"), None);
+        assert_eq!(cache.counters().failed_gets, 2);
+        assert!(cache.inner().contains("key"));
+    }
+
+    #[test]
+    fn test_failed_sets() {
+        let cache = faulty(FaultPolicy::new().fail_sets(100, FaultKind::Timeout));
+        assert!(matches!(
+            cache.try_set("key", Bytes::from("value")),
+            Err(CacheError::Timeout(_))
+        ));
+        cache.set_with_ttl("other", Bytes::from("value"), Duration::from_secs(1));
+        assert!(cache.inner().is_empty());
+        assert_eq!(cache.counters().failed_sets, 2);
+    }
+
+    #[test]
+    fn test_failure_percentage() {
+        let cache = faulty(FaultPolicy::new().fail_gets(30, FaultKind::Timeout).seed(7));
+        let failures = (0..1000).filter(|_| cache.try_get("key").is_err()).count();
+        assert!((200..400).contains(&failures), "{} failures", failures);
+        assert_eq!(cache.counters().failed_gets, failures as u64);
+    }
+
+    #[test]
+    fn test_blackholed_writes() {
+        let cache = faulty(FaultPolicy::new().blackhole_writes(true));
+        assert!(cache.try_set("key", Bytes::from("value")).is_ok());
+        cache.set("other", Bytes::from("value"));
+        assert_eq!(cache.get("key"), None);
+        assert!(cache.is_empty());
+        assert_eq!(cache.counters().blackholed_writes, 2);
+    }
+
+    #[test]
+    fn test_policy_adjustable_at_runtime() {
+        let cache = faulty(FaultPolicy::new().blackhole_writes(true));
+        cache.set("key", Bytes::from("dropped"));
+        cache.set_policy(FaultPolicy::new());
+        cache.set("key", Bytes::from("stored"));
+        assert_eq!(cache.get("key"), Some(Bytes::from("stored")));
+        assert_eq!(cache.policy(), FaultPolicy::new());
+        assert_eq!(cache.counters().blackholed_writes, 1);
+    }
+
+    #[test]
+    fn test_usable_as_trait_object() {
+        let ops: std::sync::Arc<dyn crate::ops::CacheOps> = std::sync::Arc::new(faulty(
+            FaultPolicy::new().fail_gets(100, FaultKind::Timeout),
+        ));
+        ops.set("key", Bytes::from("value"));
+        assert!(ops.try_get("key").is_err());
+    }
+}
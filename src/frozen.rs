@@ -0,0 +1,135 @@
+//! Immutable, lock-free snapshots of a cache.
+//!
+//! Some caches are really lookup tables: filled once during warm-up and only
+//! read afterwards. For those, [`Cache::freeze`] produces a [`FrozenCache`]
+//! whose reads touch no lock and no statistics counter. A frozen cache has
+//! no `set`, `delete` or TTLs; to change it, [`FrozenCache::thaw`] it back
+//! into a regular [`Cache`].
+//!
+//! ```
+//! use in_memory_cache::Cache;
+//!
+//! let cache = Cache::default();
+//! cache.set("country:de", "Germany");
+//!
+//! let frozen = cache.freeze();
+//! assert_eq!(frozen.get("country:de"), Some("Germany".into()));
+//!
+//! // The original cache is unaffected and can keep changing
+//! cache.set("country:fr", "France");
+//! assert!(!frozen.contains("country:fr"));
+//! ```
+
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::cache::Cache;
+use crate::config::CacheConfig;
+
+/// A read-only, lock-free copy of a cache's contents.
+///
+/// Cloning is cheap and shares the underlying map. Entries never expire and
+/// lookups are not counted in any statistics.
+#[derive(Debug, Clone)]
+pub struct FrozenCache {
+    entries: Arc<HashMap<String, Bytes>>,
+    config: CacheConfig,
+}
+
+impl FrozenCache {
+    pub(crate) fn new(entries: HashMap<String, Bytes>, config: CacheConfig) -> Self {
+        Self {
+            entries: Arc::new(entries),
+            config,
+        }
+    }
+
+    /// Get a value by key.
+    pub fn get(&self, key: &str) -> Option<Bytes> {
+        self.entries.get(key).cloned()
+    }
+
+    /// Borrow a value by key without cloning the `Bytes` handle.
+    pub fn get_ref(&self, key: &str) -> Option<&[u8]> {
+        self.entries.get(key).map(|value| &value[..])
+    }
+
+    /// Check whether a key exists.
+    pub fn contains(&self, key: &str) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the frozen cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterate over all entries in arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Bytes)> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value))
+    }
+
+    /// Build a new, mutable [`Cache`] with these contents.
+    ///
+    /// The new cache uses the configuration of the cache this was frozen
+    /// from, so entries receive its default TTL (if any) as on any `set`.
+    /// Statistics start from zero.
+    pub fn thaw(&self) -> Cache {
+        let cache = Cache::new(self.config.clone());
+        for (key, value) in self.entries.iter() {
+            cache.set(key.clone(), value.clone());
+        }
+        cache
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use std::time::Duration;
+
+    #[test]
+    fn test_freeze_copies_live_entries() {
+        let clock = MockClock::new();
+        let cache = Cache::new(CacheConfig::new().clock(Arc::new(clock.clone())));
+        cache.set("a", "1");
+        cache.set("b", "2");
+        cache.set_with_ttl("gone", "x", Duration::from_millis(1));
+        clock.advance(Duration::from_millis(10));
+
+        let frozen = cache.freeze();
+        assert_eq!(frozen.len(), 2);
+        assert_eq!(frozen.get("a"), Some(Bytes::from("1")));
+        assert_eq!(frozen.get_ref("b"), Some(&b"2"[..]));
+        assert!(!frozen.contains("gone"));
+
+        // Frozen reads are not counted
+        let hits = cache.stats().hits;
+        let _ = frozen.get("a");
+        assert_eq!(cache.stats().hits, hits);
+    }
+
+    #[test]
+    fn test_thaw_round_trip() {
+        let cache = Cache::new(CacheConfig::new().max_capacity(10).build());
+        cache.set("a", "1");
+        let frozen = cache.freeze();
+        cache.delete("a");
+
+        let thawed = frozen.thaw();
+        assert_eq!(thawed.get("a"), Some(Bytes::from("1")));
+        assert_eq!(thawed.stats().sets, 1);
+        thawed.set("b", "2");
+        assert!(!frozen.contains("b"));
+        assert!(!cache.contains("a"));
+    }
+}
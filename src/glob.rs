@@ -0,0 +1,195 @@
+//! Glob patterns over keys.
+//!
+//! `*` matches any run of characters, including none, and `?` matches any
+//! single character. `[...]` matches one character from a set of
+//! characters and ranges, such as `[abc]` or `[a-z0-9]`; `[!...]` or
+//! `[^...]` matches one character outside the set. A backslash makes the
+//! next character literal, and a `[` without a closing `]` is literal too.
+//!
+//! A pattern must match the whole key, so `catalog:*` selects every key
+//! under `catalog:` and `catalog` only the key `catalog`.
+
+/// One element of a compiled pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    /// `*`
+    Star,
+    /// `?`
+    Any,
+    Char(char),
+    /// Inclusive character ranges; a single character is a range of one.
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl Token {
+    /// Whether a non-`*` token matches `c`.
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Token::Star | Token::Any => true,
+            Token::Char(expected) => *expected == c,
+            Token::Class { negated, ranges } => {
+                ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != *negated
+            }
+        }
+    }
+}
+
+/// A compiled glob pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Glob {
+    tokens: Vec<Token>,
+}
+
+impl Glob {
+    /// Compile `pattern`. Every pattern is valid.
+    pub(crate) fn new(pattern: &str) -> Self {
+        let mut tokens = Vec::new();
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            let token = match c {
+                // Runs of stars match the same as one
+                '*' if tokens.last() == Some(&Token::Star) => continue,
+                '*' => Token::Star,
+                '?' => Token::Any,
+                '\\' => Token::Char(chars.next().unwrap_or('\\')),
+                '[' => {
+                    let mut rest = chars.clone();
+                    match parse_class(&mut rest) {
+                        Some(class) => {
+                            chars = rest;
+                            class
+                        }
+                        None => Token::Char('['),
+                    }
+                }
+                c => Token::Char(c),
+            };
+            tokens.push(token);
+        }
+        Self { tokens }
+    }
+
+    /// Whether the whole of `key` matches.
+    pub(crate) fn matches(&self, key: &str) -> bool {
+        let (mut t, mut k) = (0, 0);
+        // Token and key offsets just past the last `*`, to retry from with
+        // the star taking one more character
+        let mut retry: Option<(usize, usize)> = None;
+        loop {
+            if let Some(Token::Star) = self.tokens.get(t) {
+                t += 1;
+                retry = Some((t, k));
+                continue;
+            }
+            match (self.tokens.get(t), key[k..].chars().next()) {
+                (None, None) => return true,
+                (Some(token), Some(c)) if token.matches(c) => {
+                    t += 1;
+                    k += c.len_utf8();
+                    continue;
+                }
+                _ => {}
+            }
+            let (after_star, from) = match retry {
+                Some(retry) => retry,
+                None => return false,
+            };
+            match key[from..].chars().next() {
+                Some(c) => {
+                    t = after_star;
+                    k = from + c.len_utf8();
+                    retry = Some((t, k));
+                }
+                None => return false,
+            }
+        }
+    }
+}
+
+/// Parse a character class after its opening `[`, consuming the closing
+/// `]`. `None` if the class is not closed.
+fn parse_class(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> Option<Token> {
+    let negated = matches!(chars.peek(), Some('!' | '^'));
+    if negated {
+        chars.next();
+    }
+    let mut ranges = Vec::new();
+    loop {
+        let lo = match chars.next()? {
+            // A `]` right after the opening bracket is a member
+            ']' if !ranges.is_empty() => return Some(Token::Class { negated, ranges }),
+            '\\' => chars.next()?,
+            c => c,
+        };
+        let mut ahead = chars.clone();
+        let hi = match (ahead.next(), ahead.next()) {
+            (Some('-'), Some(hi)) if hi != ']' => {
+                chars.next();
+                match chars.next()? {
+                    '\\' => chars.next()?,
+                    hi => hi,
+                }
+            }
+            _ => lo,
+        };
+        ranges.push((lo.min(hi), lo.max(hi)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, key: &str) -> bool {
+        Glob::new(pattern).matches(key)
+    }
+
+    #[test]
+    fn test_wildcards() {
+        assert!(matches("catalog:*", "catalog:1"));
+        assert!(matches("catalog:*", "catalog:"));
+        assert!(!matches("catalog:*", "catalogue:1"));
+        assert!(matches("*", ""));
+        assert!(matches("*:price", "catalog:1:price"));
+        assert!(matches("a*b*c", "axxbyybc"));
+        assert!(!matches("a*b*c", "axxbyyb"));
+        assert!(matches("a**b", "ab"));
+        assert!(matches("user:?", "user:1"));
+        assert!(!matches("user:?", "user:12"));
+        assert!(!matches("user:?", "user:"));
+        // Characters, not bytes
+        assert!(matches("caf?", "café"));
+        assert!(matches("*é", "café"));
+    }
+
+    #[test]
+    fn test_literals() {
+        assert!(matches("catalog", "catalog"));
+        assert!(!matches("catalog", "catalog:1"));
+        assert!(!matches("catalog", "catalo"));
+        assert!(matches(r"a\*", "a*"));
+        assert!(!matches(r"a\*", "ab"));
+        assert!(matches(r"a\", r"a\"));
+        assert!(matches("a[b", "a[b"));
+        assert!(matches("", ""));
+        assert!(!matches("", "a"));
+    }
+
+    #[test]
+    fn test_classes() {
+        assert!(matches("v[12]", "v1"));
+        assert!(!matches("v[12]", "v3"));
+        assert!(matches("v[0-9][0-9]", "v42"));
+        assert!(matches("v[9-0]", "v5"));
+        assert!(matches("v[!0-9]", "vx"));
+        assert!(!matches("v[^0-9]", "v5"));
+        assert!(matches("v[]]", "v]"));
+        assert!(matches("v[a-]", "v-"));
+        assert!(matches(r"v[\]]", "v]"));
+        assert!(matches("v[]-]", "v-"));
+        assert!(matches("v[]-]", "v]"));
+    }
+}
@@ -0,0 +1,604 @@
+//! Read-only and write-only handles to a cache.
+//!
+//! [`Cache::split`] turns one cache into a [`CacheReader`] and a
+//! [`CacheWriter`] over the same storage, so read access can be handed to
+//! most of a codebase while mutation stays with one module:
+//!
+//! ```
+//! use in_memory_cache::Cache;
+//!
+//! let (reader, writer) = Cache::default().split();
+//! writer.set("user:1", "Alice");
+//! assert_eq!(reader.get("user:1"), Some("Alice".into()));
+//! ```
+//!
+//! A reader cannot change what the cache holds; its methods simply do not
+//! exist, and it does not implement [`CacheWrite`]:
+//!
+//! ```compile_fail,E0599
+//! # use in_memory_cache::{Cache, CacheWrite};
+//! let (reader, _writer) = Cache::default().split();
+//! reader.set("user:1", "Mallory".into());
+//! ```
+//!
+//! ```compile_fail,E0599
+//! # use in_memory_cache::{Cache, CacheWrite};
+//! let (reader, _writer) = Cache::default().split();
+//! reader.delete("user:1");
+//! ```
+//!
+//! ```compile_fail,E0599
+//! # use in_memory_cache::Cache;
+//! let (reader, _writer) = Cache::default().split();
+//! reader.clear();
+//! ```
+//!
+//! ```compile_fail,E0277
+//! # use in_memory_cache::{Cache, CacheWrite};
+//! fn mutate(cache: &dyn CacheWrite) {
+//!     cache.delete("user:1");
+//! }
+//! let (reader, _writer) = Cache::default().split();
+//! mutate(&reader);
+//! ```
+//!
+//! Nor can it be turned back into a full [`Cache`]:
+//!
+//! ```compile_fail,E0308
+//! # use in_memory_cache::Cache;
+//! let (reader, _writer) = Cache::default().split();
+//! let cache: Cache = reader.clone();
+//! ```
+//!
+//! # Which handle a method belongs on
+//!
+//! A method goes on [`CacheReader`] if it cannot change which keys and
+//! values the cache holds. Side effects that only affect bookkeeping, such
+//! as hit/miss counting, LRU promotion and lazily dropping an entry that has
+//! already expired, do not count as mutation. Everything else, including
+//! resetting statistics and housekeeping such as `cleanup_expired`, goes on
+//! [`CacheWriter`]. New `Cache` methods should be added to the matching
+//! handle in the same change.
+//!
+//! [`Cache::split`]: crate::Cache::split
+
+use bytes::Bytes;
+use std::borrow::Cow;
+use std::time::Duration;
+
+use crate::cache::Cache;
+use crate::cleanup::CleanupTask;
+use crate::entry::EntryInfo;
+use crate::error::CacheResult;
+use crate::ops::{CacheRead, CacheWrite, SetOutcome};
+use crate::ratelimit::RateDecision;
+use crate::scan::ScanCursor;
+use crate::stats::StatsSnapshot;
+use crate::txn::TxnView;
+
+/// A read-only handle to a cache. Clones share the same storage.
+///
+/// Created by [`Cache::split`](crate::Cache::split).
+#[derive(Debug, Clone)]
+pub struct CacheReader {
+    cache: Cache,
+}
+
+impl CacheReader {
+    pub(crate) fn new(cache: Cache) -> Self {
+        Self { cache }
+    }
+
+    /// See [`Cache::get`].
+    pub fn get(&self, key: &str) -> Option<Bytes> {
+        self.cache.get(key)
+    }
+
+    /// See [`Cache::try_get`].
+    pub fn try_get(&self, key: &str) -> CacheResult<Option<Bytes>> {
+        self.cache.try_get(key)
+    }
+
+    /// See [`Cache::peek`].
+    pub fn peek(&self, key: &str) -> Option<Bytes> {
+        self.cache.peek(key)
+    }
+
+    /// See [`Cache::touch`].
+    pub fn touch(&self, key: &str) -> bool {
+        self.cache.touch(key)
+    }
+
+    /// See [`Cache::ttl`].
+    pub fn ttl(&self, key: &str) -> Option<Option<Duration>> {
+        self.cache.ttl(key)
+    }
+
+    /// See [`Cache::entry_info`].
+    pub fn entry_info(&self, key: &str) -> Option<EntryInfo> {
+        self.cache.entry_info(key)
+    }
+
+    /// See [`Cache::get_outcome`].
+    pub fn get_outcome(&self, key: &str) -> Option<Result<Bytes, Bytes>> {
+        self.cache.get_outcome(key)
+    }
+
+    /// See [`Cache::multi_get`].
+    pub fn multi_get(&self, keys: &[&str]) -> Vec<Option<Bytes>> {
+        self.cache.multi_get(keys)
+    }
+
+    /// See [`Cache::get_many`].
+    pub fn get_many<I>(&self, keys: I) -> Vec<Option<Bytes>>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        self.cache.get_many(keys)
+    }
+
+    /// See [`Cache::contains`].
+    pub fn contains(&self, key: &str) -> bool {
+        self.cache.contains(key)
+    }
+
+    /// See [`Cache::len`].
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    /// See [`Cache::is_empty`].
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    /// See [`Cache::keys`].
+    pub fn keys(&self) -> Vec<String> {
+        self.cache.keys()
+    }
+
+    /// See [`Cache::iter`].
+    pub fn iter(&self) -> Vec<(String, Bytes)> {
+        self.cache.iter()
+    }
+
+    /// See [`Cache::keys_sorted`].
+    pub fn keys_sorted(&self) -> Vec<String> {
+        self.cache.keys_sorted()
+    }
+
+    /// See [`Cache::keys_matching`].
+    pub fn keys_matching(&self, pattern: &str) -> Vec<String> {
+        self.cache.keys_matching(pattern)
+    }
+
+    /// See [`Cache::scan_keys`].
+    pub fn scan_keys(&self, cursor: ScanCursor, limit: usize) -> (Vec<String>, ScanCursor) {
+        self.cache.scan_keys(cursor, limit)
+    }
+
+    /// See [`Cache::in_flight`].
+    pub fn in_flight(&self) -> Vec<(String, Duration)> {
+        self.cache.in_flight()
+    }
+
+    /// See [`Cache::stats`].
+    pub fn stats(&self) -> StatsSnapshot {
+        self.cache.stats()
+    }
+}
+
+impl CacheRead for CacheReader {
+    fn get(&self, key: &str) -> Option<Bytes> {
+        CacheReader::get(self, key)
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        CacheReader::contains(self, key)
+    }
+
+    fn len(&self) -> usize {
+        CacheReader::len(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        CacheReader::is_empty(self)
+    }
+
+    fn stats(&self) -> StatsSnapshot {
+        CacheReader::stats(self)
+    }
+
+    fn try_get(&self, key: &str) -> CacheResult<Option<Bytes>> {
+        CacheReader::try_get(self, key)
+    }
+}
+
+/// A handle to the mutating operations of a cache. Clones share the same
+/// storage.
+///
+/// Created by [`Cache::split`](crate::Cache::split). Reads are left to the
+/// [`CacheReader`]; read-modify-write updates go through
+/// [`CacheWriter::transaction`].
+#[derive(Debug, Clone)]
+pub struct CacheWriter {
+    cache: Cache,
+}
+
+impl CacheWriter {
+    pub(crate) fn new(cache: Cache) -> Self {
+        Self { cache }
+    }
+
+    /// See [`Cache::set`].
+    pub fn set<'k>(&self, key: impl Into<Cow<'k, str>>, value: impl Into<Bytes>) {
+        self.cache.set(key, value);
+    }
+
+    /// See [`Cache::set_with_ttl`].
+    pub fn set_with_ttl<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+        ttl: Duration,
+    ) {
+        self.cache.set_with_ttl(key, value, ttl);
+    }
+
+    /// See [`Cache::set_with_exact_ttl`].
+    pub fn set_with_exact_ttl<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+        ttl: Duration,
+    ) {
+        self.cache.set_with_exact_ttl(key, value, ttl);
+    }
+
+    /// See [`Cache::set_returning_outcome`].
+    pub fn set_returning_outcome<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+    ) -> SetOutcome {
+        self.cache.set_returning_outcome(key, value)
+    }
+
+    /// See [`Cache::set_with_ttl_returning_outcome`].
+    pub fn set_with_ttl_returning_outcome<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+        ttl: Duration,
+    ) -> SetOutcome {
+        self.cache.set_with_ttl_returning_outcome(key, value, ttl)
+    }
+
+    /// See [`Cache::try_set`].
+    pub fn try_set<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+    ) -> CacheResult<()> {
+        self.cache.try_set(key, value)
+    }
+
+    /// See [`Cache::try_set_with_ttl`].
+    pub fn try_set_with_ttl<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+        ttl: Duration,
+    ) -> CacheResult<()> {
+        self.cache.try_set_with_ttl(key, value, ttl)
+    }
+
+    /// See [`Cache::set_returning`].
+    pub fn set_returning<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+    ) -> Option<Bytes> {
+        self.cache.set_returning(key, value)
+    }
+
+    /// See [`Cache::set_with_ttl_returning`].
+    pub fn set_with_ttl_returning<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+        ttl: Duration,
+    ) -> Option<Bytes> {
+        self.cache.set_with_ttl_returning(key, value, ttl)
+    }
+
+    /// See [`Cache::set_outcome`].
+    pub fn set_outcome<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        outcome: Result<Bytes, Bytes>,
+        ok_ttl: Duration,
+        err_ttl: Duration,
+    ) {
+        self.cache.set_outcome(key, outcome, ok_ttl, err_ttl);
+    }
+
+    /// See [`Cache::set_many`].
+    pub fn set_many(&self, pairs: impl IntoIterator<Item = (String, Bytes)>) {
+        self.cache.set_many(pairs);
+    }
+
+    /// See [`Cache::set_many_with_ttl`].
+    pub fn set_many_with_ttl(&self, writes: impl IntoIterator<Item = (String, Bytes, Duration)>) {
+        self.cache.set_many_with_ttl(writes);
+    }
+
+    /// See [`Cache::set_if_absent`].
+    pub fn set_if_absent<'k>(&self, key: impl Into<Cow<'k, str>>, value: impl Into<Bytes>) -> bool {
+        self.cache.set_if_absent(key, value)
+    }
+
+    /// See [`Cache::set_if_absent_with_ttl`].
+    pub fn set_if_absent_with_ttl<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+        ttl: Duration,
+    ) -> bool {
+        self.cache.set_if_absent_with_ttl(key, value, ttl)
+    }
+
+    /// See [`Cache::compare_and_swap`].
+    pub fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: &[u8],
+        new: impl Into<Bytes>,
+    ) -> CacheResult<bool> {
+        self.cache.compare_and_swap(key, expected, new)
+    }
+
+    /// See [`Cache::get_or_insert_with`].
+    pub fn get_or_insert_with(&self, key: &str, init: impl FnOnce() -> Bytes) -> Bytes {
+        self.cache.get_or_insert_with(key, init)
+    }
+
+    /// See [`Cache::get_or_insert_with_ttl`].
+    pub fn get_or_insert_with_ttl(
+        &self,
+        key: &str,
+        ttl: Duration,
+        init: impl FnOnce() -> Bytes,
+    ) -> Bytes {
+        self.cache.get_or_insert_with_ttl(key, ttl, init)
+    }
+
+    /// See [`Cache::set_nonblocking`].
+    pub fn set_nonblocking<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+    ) -> bool {
+        self.cache.set_nonblocking(key, value)
+    }
+
+    /// See [`Cache::expire`].
+    pub fn expire(&self, key: &str, ttl: Duration) -> bool {
+        self.cache.expire(key, ttl)
+    }
+
+    /// See [`Cache::persist`].
+    pub fn persist(&self, key: &str) -> bool {
+        self.cache.persist(key)
+    }
+
+    /// See [`Cache::take`].
+    pub fn take(&self, key: &str) -> Option<Bytes> {
+        self.cache.take(key)
+    }
+
+    /// See [`Cache::delete_many`].
+    pub fn delete_many<K: AsRef<str>>(&self, keys: &[K]) -> usize {
+        self.cache.delete_many(keys)
+    }
+
+    /// See [`Cache::delete_prefix`].
+    pub fn delete_prefix(&self, prefix: &str) -> usize {
+        self.cache.delete_prefix(prefix)
+    }
+
+    /// See [`Cache::delete`].
+    pub fn delete(&self, key: &str) -> bool {
+        self.cache.delete(key)
+    }
+
+    /// See [`Cache::transaction`].
+    pub fn transaction(
+        &self,
+        keys: &[&str],
+        f: impl FnOnce(&mut TxnView<'_>) -> CacheResult<()>,
+    ) -> CacheResult<()> {
+        self.cache.transaction(keys, f)
+    }
+
+    /// See [`Cache::append`].
+    pub fn append(&self, key: &str, suffix: impl Into<Bytes>) -> usize {
+        self.cache.append(key, suffix)
+    }
+
+    /// See [`Cache::increment`].
+    pub fn increment(&self, key: &str, delta: i64) -> CacheResult<i64> {
+        self.cache.increment(key, delta)
+    }
+
+    /// See [`Cache::decrement`].
+    pub fn decrement(&self, key: &str, delta: i64) -> CacheResult<i64> {
+        self.cache.decrement(key, delta)
+    }
+
+    /// See [`Cache::incr_window`].
+    pub fn incr_window(
+        &self,
+        key: &str,
+        delta: i64,
+        window: Duration,
+    ) -> CacheResult<(i64, Duration)> {
+        self.cache.incr_window(key, delta, window)
+    }
+
+    /// See [`Cache::rate_limit`].
+    pub fn rate_limit(&self, key: &str, limit: u64, window: Duration) -> CacheResult<RateDecision> {
+        self.cache.rate_limit(key, limit, window)
+    }
+
+    /// See [`Cache::push_capped`].
+    pub fn push_capped(&self, prefix: &str, value: impl Into<Bytes>, cap: usize) -> Option<String> {
+        self.cache.push_capped(prefix, value, cap)
+    }
+
+    /// See [`Cache::cancel_in_flight`].
+    pub fn cancel_in_flight(&self, key: &str) -> bool {
+        self.cache.cancel_in_flight(key)
+    }
+
+    /// See [`Cache::clear`].
+    pub fn clear(&self) {
+        self.cache.clear();
+    }
+
+    /// See [`Cache::retain`].
+    pub fn retain(&self, f: impl FnMut(&str, &Bytes) -> bool) -> usize {
+        self.cache.retain(f)
+    }
+
+    /// See [`Cache::cleanup_expired`].
+    pub fn cleanup_expired(&self) -> usize {
+        self.cache.cleanup_expired()
+    }
+
+    /// See [`Cache::cleanup_expired_batch`].
+    pub fn cleanup_expired_batch(&self, max_items: usize) -> usize {
+        self.cache.cleanup_expired_batch(max_items)
+    }
+
+    /// See [`Cache::batch_writer`].
+    #[cfg(feature = "tokio")]
+    pub fn batch_writer(&self, max_batch: usize, max_delay: Duration) -> crate::BatchWriter {
+        self.cache.batch_writer(max_batch, max_delay)
+    }
+
+    /// See [`Cache::start_cleanup`].
+    pub fn start_cleanup(&self) -> Option<CleanupTask> {
+        self.cache.start_cleanup()
+    }
+
+    /// See [`Cache::purge_idle`].
+    pub fn purge_idle(&self, idle: Duration) -> usize {
+        self.cache.purge_idle(idle)
+    }
+
+    /// See [`Cache::expire_matching`].
+    pub fn expire_matching(&self, pattern: &str, ttl: Duration) -> usize {
+        self.cache.expire_matching(pattern, ttl)
+    }
+
+    /// See [`Cache::persist_matching`].
+    pub fn persist_matching(&self, pattern: &str) -> usize {
+        self.cache.persist_matching(pattern)
+    }
+
+    /// See [`Cache::reset_stats`].
+    pub fn reset_stats(&self) {
+        self.cache.reset_stats();
+    }
+}
+
+impl CacheWrite for CacheWriter {
+    fn set(&self, key: &str, value: Bytes) {
+        CacheWriter::set(self, key, value);
+    }
+
+    fn set_with_ttl(&self, key: &str, value: Bytes, ttl: Duration) {
+        CacheWriter::set_with_ttl(self, key, value, ttl);
+    }
+
+    fn delete(&self, key: &str) -> bool {
+        CacheWriter::delete(self, key)
+    }
+
+    fn try_set(&self, key: &str, value: Bytes) -> CacheResult<()> {
+        CacheWriter::try_set(self, key, value)
+    }
+
+    fn try_set_with_ttl(&self, key: &str, value: Bytes, ttl: Duration) -> CacheResult<()> {
+        CacheWriter::try_set_with_ttl(self, key, value, ttl)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CacheConfig;
+
+    fn count_users(cache: &impl CacheRead) -> usize {
+        ["user:1", "user:2", "user:3"]
+            .into_iter()
+            .filter(|key| cache.contains(key))
+            .count()
+    }
+
+    #[test]
+    fn test_handles_share_storage() {
+        let cache = Cache::default();
+        let (reader, writer) = cache.split();
+        let other_reader = reader.clone();
+
+        writer.set("user:1", "alice");
+        writer.set_with_ttl("user:2", "bob", Duration::from_secs(60));
+        assert_eq!(reader.get("user:1"), Some(Bytes::from("alice")));
+        assert_eq!(other_reader.len(), 2);
+        assert_eq!(cache.keys_sorted(), reader.keys_sorted());
+
+        assert!(writer.delete("user:1"));
+        assert!(!reader.contains("user:1"));
+        assert_eq!(cache.len(), 1);
+
+        writer.clear();
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn test_handles_through_traits() {
+        let (reader, writer) = Cache::default().split();
+        let write: &dyn CacheWrite = &writer;
+        write.set("user:1", Bytes::from("alice"));
+        write.set("user:3", Bytes::from("carol"));
+
+        let read: &dyn CacheRead = &reader;
+        assert_eq!(read.get("user:3"), Some(Bytes::from("carol")));
+        assert_eq!(read.try_get("user:2").unwrap(), None);
+        assert_eq!(count_users(&reader), 2);
+        assert_eq!(read.stats().sets, 2);
+    }
+
+    #[test]
+    fn test_peek_and_transaction() {
+        let (reader, writer) = Cache::new(CacheConfig::new().max_capacity(10)).split();
+        writer
+            .transaction(&["a", "b"], |txn| {
+                txn.set("a", "1")?;
+                txn.set("b", "2")
+            })
+            .unwrap();
+
+        assert_eq!(reader.peek("a"), Some(Bytes::from("1")));
+        assert_eq!(reader.stats().hits, 0);
+        assert_eq!(
+            reader.multi_get(&["a", "b"]),
+            vec![Some(Bytes::from("1")), Some(Bytes::from("2"))]
+        );
+        writer.reset_stats();
+        assert_eq!(reader.stats().hits, 0);
+    }
+}
@@ -0,0 +1,887 @@
+//! Soft health thresholds.
+//!
+//! Hard limits such as `max_capacity` act when they are reached; the
+//! thresholds here only warn before that happens. [`Cache::health`] checks
+//! the configured [`HealthThresholds`] against the current statistics and
+//! returns a [`HealthReport`]. Every threshold crossing in either direction
+//! is also delivered as a [`HealthEvent`] to the callbacks registered with
+//! [`Cache::start_monitor`], which polls on a background thread:
+//!
+//! ```
+//! use in_memory_cache::health::HealthThresholds;
+//! use in_memory_cache::{Cache, CacheConfig};
+//! use std::time::Duration;
+//!
+//! let config = CacheConfig::new()
+//!     .max_capacity(1000)
+//!     .health_thresholds(HealthThresholds::new().max_size_ratio(0.9))
+//!     .build();
+//! let cache = Cache::new(config);
+//!
+//! let monitor = cache.start_monitor(Duration::from_secs(1), |event| {
+//!     eprintln!("cache health: {}", event);
+//! });
+//! assert!(cache.health().is_healthy());
+//! monitor.stop();
+//! ```
+//!
+//! The hit rate and eviction rate cover a sliding window rather than the
+//! whole lifetime of the cache, so an old good hit rate does not hide a
+//! bad current one. To keep alerts from flapping, a raised alert is only
+//! cleared once its metric is back inside the threshold by a margin (see
+//! [`HealthThresholds::hysteresis`]).
+//!
+//! Caches without a `max_capacity` also get a growth alarm (see
+//! [`CacheConfig::growth_alarm`]): the first time the entry count reaches
+//! the alarm size, and again at every doubling of it, the monitor callbacks
+//! receive an [`HealthMetric::UnboundedGrowth`] event. An accidentally
+//! unbounded cache then shows up in the logs long before it runs out of
+//! memory.
+//!
+//! [`Cache::health`]: crate::Cache::health
+//! [`CacheConfig::growth_alarm`]: crate::CacheConfig::growth_alarm
+//! [`Cache::start_monitor`]: crate::Cache::start_monitor
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::cache::Cache;
+use crate::callback;
+use crate::stats::{CacheStats, StatsSnapshot};
+
+/// Default length of the hit rate and eviction rate window.
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Default hysteresis margin, as a fraction of the threshold.
+pub const DEFAULT_HYSTERESIS: f64 = 0.1;
+
+/// Default entry count at which an unbounded cache raises the growth alarm.
+pub const DEFAULT_GROWTH_ALARM: usize = 1_000_000;
+
+/// Number of samples kept per window; polls in between reuse the newest.
+const SAMPLES_PER_WINDOW: u32 = 16;
+
+/// Soft thresholds checked by [`Cache::health`](crate::Cache::health).
+///
+/// Every threshold is off by default.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HealthThresholds {
+    pub(crate) max_size_ratio: Option<f64>,
+    pub(crate) min_hit_rate: Option<f64>,
+    pub(crate) max_evictions_per_minute: Option<f64>,
+    pub(crate) max_memory_bytes: Option<usize>,
+    pub(crate) window: Duration,
+    pub(crate) hysteresis: f64,
+}
+
+impl Default for HealthThresholds {
+    fn default() -> Self {
+        Self {
+            max_size_ratio: None,
+            min_hit_rate: None,
+            max_evictions_per_minute: None,
+            max_memory_bytes: None,
+            window: DEFAULT_WINDOW,
+            hysteresis: DEFAULT_HYSTERESIS,
+        }
+    }
+}
+
+impl HealthThresholds {
+    /// Thresholds with every check off.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Warn when the entry count exceeds `ratio` of `max_capacity`
+    /// (e.g. `0.9` for 90%). Ignored for caches without a capacity.
+    pub fn max_size_ratio(mut self, ratio: f64) -> Self {
+        self.max_size_ratio = Some(ratio);
+        self
+    }
+
+    /// Warn when the hit rate over the window drops below `percent`
+    /// (0 to 100, like `StatsSnapshot::hit_rate`). A window without any
+    /// lookups leaves this alert as it was.
+    pub fn min_hit_rate(mut self, percent: f64) -> Self {
+        self.min_hit_rate = Some(percent);
+        self
+    }
+
+    /// Warn when capacity evictions over the window exceed `rate` per
+    /// minute.
+    pub fn max_evictions_per_minute(mut self, rate: f64) -> Self {
+        self.max_evictions_per_minute = Some(rate);
+        self
+    }
+
+    /// Warn when estimated memory use exceeds `bytes`.
+    ///
+    /// Memory is estimated as the key and value bytes plus
+    /// [`Cache::ENTRY_OVERHEAD`](crate::Cache::ENTRY_OVERHEAD) per entry.
+    /// Computing it scans every entry under the read lock, so it is only
+    /// done while this threshold is set.
+    pub fn max_memory_bytes(mut self, bytes: usize) -> Self {
+        self.max_memory_bytes = Some(bytes);
+        self
+    }
+
+    /// Length of the window the hit rate and eviction rate are measured
+    /// over. Defaults to [`DEFAULT_WINDOW`]; zero is raised to one
+    /// millisecond.
+    pub fn window(mut self, window: Duration) -> Self {
+        self.window = window.max(Duration::from_millis(1));
+        self
+    }
+
+    /// Margin a metric must recover by before its alert clears, as a
+    /// fraction of the threshold (clamped to 0..=1).
+    ///
+    /// With the default of 0.1, a size alert at 90% of capacity clears
+    /// below 81%. The hit rate margin applies to the miss rate: a hit rate
+    /// alert below 80% clears above 82%.
+    pub fn hysteresis(mut self, margin: f64) -> Self {
+        self.hysteresis = margin.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Whether any threshold is set.
+    pub fn is_enabled(&self) -> bool {
+        self.max_size_ratio.is_some()
+            || self.min_hit_rate.is_some()
+            || self.max_evictions_per_minute.is_some()
+            || self.max_memory_bytes.is_some()
+    }
+
+    /// The threshold for `metric`, if set.
+    fn threshold(&self, metric: HealthMetric) -> Option<f64> {
+        match metric {
+            HealthMetric::SizeRatio => self.max_size_ratio,
+            HealthMetric::HitRate => self.min_hit_rate,
+            HealthMetric::EvictionRate => self.max_evictions_per_minute,
+            HealthMetric::Memory => self.max_memory_bytes.map(|bytes| bytes as f64),
+            HealthMetric::UnboundedGrowth => None,
+        }
+    }
+}
+
+/// A metric with a soft threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HealthMetric {
+    /// Entry count as a fraction of `max_capacity`.
+    SizeRatio,
+    /// Hit rate over the window, in percent.
+    HitRate,
+    /// Capacity evictions per minute over the window.
+    EvictionRate,
+    /// Estimated memory use in bytes.
+    Memory,
+    /// Entry count of a cache without `max_capacity`, reported by the
+    /// growth alarm. It has no soft threshold and is not in
+    /// [`HealthMetric::ALL`]: its events are only ever raised.
+    UnboundedGrowth,
+}
+
+impl HealthMetric {
+    /// All metrics, in the order reports list them.
+    pub const ALL: [HealthMetric; 4] = [
+        HealthMetric::SizeRatio,
+        HealthMetric::HitRate,
+        HealthMetric::EvictionRate,
+        HealthMetric::Memory,
+    ];
+
+    /// The metric name used in reports and logs.
+    pub fn name(&self) -> &'static str {
+        match self {
+            HealthMetric::SizeRatio => "size_ratio",
+            HealthMetric::HitRate => "hit_rate",
+            HealthMetric::EvictionRate => "evictions_per_minute",
+            HealthMetric::Memory => "memory_bytes",
+            HealthMetric::UnboundedGrowth => "unbounded_growth",
+        }
+    }
+
+    /// Whether the alert is raised below the threshold rather than above.
+    fn is_minimum(&self) -> bool {
+        matches!(self, HealthMetric::HitRate)
+    }
+
+    /// `value` formatted for this metric, as in logs and `info`.
+    pub fn format_value(&self, value: f64) -> String {
+        match self {
+            HealthMetric::SizeRatio => format!("{:.2}", value),
+            HealthMetric::HitRate => format!("{:.1}%", value),
+            HealthMetric::EvictionRate => format!("{:.1}", value),
+            HealthMetric::Memory | HealthMetric::UnboundedGrowth => format!("{:.0}", value),
+        }
+    }
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+impl fmt::Display for HealthMetric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Whether a threshold was crossed into or out of the alert state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthEventKind {
+    /// The metric went past its threshold.
+    Raised,
+    /// The metric recovered past the hysteresis margin.
+    Cleared,
+}
+
+/// A threshold crossing, delivered to monitor callbacks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HealthEvent {
+    pub metric: HealthMetric,
+    pub kind: HealthEventKind,
+    /// The metric's value when the crossing was detected.
+    pub value: f64,
+    /// The configured threshold.
+    pub threshold: f64,
+}
+
+impl HealthEvent {
+    /// The growth alarm event for an unbounded cache of `size` entries
+    /// that reached the alarm size `threshold`.
+    pub(crate) fn growth(size: u64, threshold: u64) -> Self {
+        Self {
+            metric: HealthMetric::UnboundedGrowth,
+            kind: HealthEventKind::Raised,
+            value: size as f64,
+            threshold: threshold as f64,
+        }
+    }
+}
+
+impl fmt::Display for HealthEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.metric == HealthMetric::UnboundedGrowth {
+            return write!(
+                f,
+                "{} {} entries reached growth alarm {} of a cache without max_capacity",
+                self.metric,
+                self.metric.format_value(self.value),
+                self.metric.format_value(self.threshold)
+            );
+        }
+        let state = match (self.kind, self.metric.is_minimum()) {
+            (HealthEventKind::Raised, false) => "above",
+            (HealthEventKind::Raised, true) => "below",
+            (HealthEventKind::Cleared, _) => "back within",
+        };
+        write!(
+            f,
+            "{} {} {} soft limit {}",
+            self.metric,
+            self.metric.format_value(self.value),
+            state,
+            self.metric.format_value(self.threshold)
+        )
+    }
+}
+
+/// Current health of a cache, as returned by
+/// [`Cache::health`](crate::Cache::health).
+#[derive(Debug, Clone, PartialEq)]
+pub struct HealthReport {
+    /// Entry count as a fraction of `max_capacity`; `None` without a
+    /// capacity.
+    pub size_ratio: Option<f64>,
+    /// Hit rate over the window in percent; `None` if there were no
+    /// lookups in the window.
+    pub hit_rate: Option<f64>,
+    /// Capacity evictions per minute over the window.
+    pub evictions_per_minute: f64,
+    /// Estimated memory use; `None` unless a memory threshold is set.
+    pub memory_bytes: Option<usize>,
+    /// Metrics currently in the alert state.
+    pub alerts: Vec<HealthMetric>,
+}
+
+impl HealthReport {
+    /// Whether no alert is raised.
+    pub fn is_healthy(&self) -> bool {
+        self.alerts.is_empty()
+    }
+
+    /// The value of `metric` in this report, if known.
+    pub fn value(&self, metric: HealthMetric) -> Option<f64> {
+        match metric {
+            HealthMetric::SizeRatio => self.size_ratio,
+            HealthMetric::HitRate => self.hit_rate,
+            HealthMetric::EvictionRate => Some(self.evictions_per_minute),
+            HealthMetric::Memory => self.memory_bytes.map(|bytes| bytes as f64),
+            HealthMetric::UnboundedGrowth => None,
+        }
+    }
+}
+
+/// Callback invoked with each threshold crossing.
+///
+/// It runs on whichever thread evaluated the health, after the tracker's
+/// lock has been released. A panic inside it is caught and counted in the
+/// `callback_panics` statistic.
+pub type HealthListener = Arc<dyn Fn(&HealthEvent) + Send + Sync>;
+
+/// What a health evaluation reads from the cache.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct HealthInput {
+    pub(crate) size: u64,
+    pub(crate) capacity: Option<usize>,
+    pub(crate) hits: u64,
+    pub(crate) misses: u64,
+    pub(crate) evictions: u64,
+    pub(crate) memory_bytes: Option<usize>,
+}
+
+impl HealthInput {
+    pub(crate) fn new(
+        stats: &StatsSnapshot,
+        capacity: Option<usize>,
+        memory_bytes: Option<usize>,
+    ) -> Self {
+        Self {
+            size: stats.size,
+            capacity,
+            hits: stats.hits,
+            misses: stats.misses,
+            evictions: stats.evictions,
+            memory_bytes,
+        }
+    }
+}
+
+/// Growth alarm of an unbounded cache: fires once at the alarm size and
+/// once at every doubling of it.
+#[derive(Debug)]
+pub(crate) struct GrowthAlarm {
+    /// Next entry count to report; `u64::MAX` once every doubling that
+    /// fits has been reported.
+    next: AtomicU64,
+}
+
+impl GrowthAlarm {
+    /// An alarm first firing at `threshold` entries (at least 1).
+    pub(crate) fn new(threshold: usize) -> Self {
+        Self {
+            next: AtomicU64::new((threshold as u64).max(1)),
+        }
+    }
+
+    /// The alarm size an entry count of `size` reaches that no earlier
+    /// call has reported, if any.
+    ///
+    /// A jump over several doublings reports only the largest one. The
+    /// compare-and-swap hands each alarm size to exactly one caller, however
+    /// many threads see the count at once; a count that drops and grows
+    /// back does not report again.
+    pub(crate) fn check(&self, size: u64) -> Option<u64> {
+        let mut next = self.next.load(Ordering::Relaxed);
+        loop {
+            if next == u64::MAX || size < next {
+                return None;
+            }
+            let mut reached = next;
+            while let Some(doubled) = reached.checked_mul(2).filter(|&d| d <= size) {
+                reached = doubled;
+            }
+            let after = reached.saturating_mul(2);
+            match self
+                .next
+                .compare_exchange_weak(next, after, Ordering::Relaxed, Ordering::Relaxed)
+            {
+                Ok(_) => return Some(reached),
+                Err(actual) => next = actual,
+            }
+        }
+    }
+}
+
+/// Counters at one point of the window.
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    at: Instant,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+#[derive(Debug)]
+struct TrackerState {
+    /// Oldest first. The front is the window's baseline: the newest sample
+    /// taken at least one window ago, or the first one.
+    samples: VecDeque<Sample>,
+    /// Alert state per metric, indexed by `HealthMetric::index`.
+    alerts: [bool; 4],
+}
+
+/// Alert state and window samples of one cache, shared by its clones.
+pub(crate) struct HealthTracker {
+    thresholds: HealthThresholds,
+    state: Mutex<TrackerState>,
+    listeners: Mutex<Vec<(u64, HealthListener)>>,
+    next_listener: AtomicU64,
+}
+
+impl HealthTracker {
+    pub(crate) fn new(thresholds: HealthThresholds) -> Self {
+        Self {
+            thresholds,
+            state: Mutex::new(TrackerState {
+                samples: VecDeque::new(),
+                alerts: [false; 4],
+            }),
+            listeners: Mutex::new(Vec::new()),
+            next_listener: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn thresholds(&self) -> &HealthThresholds {
+        &self.thresholds
+    }
+
+    /// Update the window and alert state with `input` taken at `now`.
+    /// Returns the report and the crossings this evaluation detected.
+    pub(crate) fn evaluate(
+        &self,
+        input: HealthInput,
+        now: Instant,
+    ) -> (HealthReport, Vec<HealthEvent>) {
+        let mut state = self.lock_state();
+        let (hit_rate, evictions_per_minute) = self.window_rates(&mut state.samples, input, now);
+        let mut report = HealthReport {
+            size_ratio: input
+                .capacity
+                .map(|capacity| input.size as f64 / capacity as f64),
+            hit_rate,
+            evictions_per_minute,
+            memory_bytes: input.memory_bytes,
+            alerts: Vec::new(),
+        };
+
+        let mut events = Vec::new();
+        for metric in HealthMetric::ALL {
+            let alert = &mut state.alerts[metric.index()];
+            let threshold = match self.thresholds.threshold(metric) {
+                Some(threshold) => threshold,
+                None => {
+                    *alert = false;
+                    continue;
+                }
+            };
+            if let Some(value) = report.value(metric) {
+                let kind = if !*alert && self.raises(metric, value, threshold) {
+                    Some(HealthEventKind::Raised)
+                } else if *alert && self.clears(metric, value, threshold) {
+                    Some(HealthEventKind::Cleared)
+                } else {
+                    None
+                };
+                if let Some(kind) = kind {
+                    *alert = kind == HealthEventKind::Raised;
+                    events.push(HealthEvent {
+                        metric,
+                        kind,
+                        value,
+                        threshold,
+                    });
+                }
+            }
+            if *alert {
+                report.alerts.push(metric);
+            }
+        }
+        (report, events)
+    }
+
+    /// Register a listener; returns the id to unregister it with.
+    pub(crate) fn subscribe(&self, listener: HealthListener) -> u64 {
+        let id = self.next_listener.fetch_add(1, Ordering::Relaxed);
+        self.lock_listeners().push((id, listener));
+        id
+    }
+
+    pub(crate) fn unsubscribe(&self, id: u64) {
+        self.lock_listeners().retain(|(other, _)| *other != id);
+    }
+
+    /// The registered listeners, cloned so they can run without the lock.
+    pub(crate) fn listeners(&self) -> Vec<HealthListener> {
+        self.lock_listeners()
+            .iter()
+            .map(|(_, listener)| Arc::clone(listener))
+            .collect()
+    }
+
+    /// Call every listener with each of `events`, counting panics in
+    /// `stats`. Must be called without any cache lock held.
+    pub(crate) fn deliver(&self, events: &[HealthEvent], stats: &CacheStats) {
+        for listener in self.listeners() {
+            for event in events {
+                if callback::guard(|| listener(event)).is_err() {
+                    stats.record_callback_panic();
+                }
+            }
+        }
+    }
+
+    /// Record a sample and return the hit rate and eviction rate over the
+    /// window ending at `now`.
+    fn window_rates(
+        &self,
+        samples: &mut VecDeque<Sample>,
+        input: HealthInput,
+        now: Instant,
+    ) -> (Option<f64>, f64) {
+        let window = self.thresholds.window;
+        let current = Sample {
+            at: now,
+            hits: input.hits,
+            misses: input.misses,
+            evictions: input.evictions,
+        };
+
+        // Counters going backwards means the stats were reset
+        let reset = samples.back().is_some_and(|newest| {
+            current.hits < newest.hits
+                || current.misses < newest.misses
+                || current.evictions < newest.evictions
+        });
+        if reset {
+            samples.clear();
+        }
+        let due = samples.back().map_or(true, |newest| {
+            newest.at + window / SAMPLES_PER_WINDOW <= now
+        });
+        if due {
+            samples.push_back(current);
+        }
+        while samples.len() > 1 && samples[1].at + window <= now {
+            samples.pop_front();
+        }
+
+        let baseline = samples[0];
+        let hits = current.hits.saturating_sub(baseline.hits);
+        let lookups = hits + current.misses.saturating_sub(baseline.misses);
+        let hit_rate = (lookups > 0).then(|| hits as f64 / lookups as f64 * 100.0);
+
+        // A window younger than its length is measured as a full one, so
+        // the first few polls after startup do not extrapolate a burst
+        let elapsed = now.saturating_duration_since(baseline.at).max(window);
+        let evictions = current.evictions.saturating_sub(baseline.evictions);
+        let evictions_per_minute = evictions as f64 * 60.0 / elapsed.as_secs_f64();
+
+        (hit_rate, evictions_per_minute)
+    }
+
+    fn raises(&self, metric: HealthMetric, value: f64, threshold: f64) -> bool {
+        if metric.is_minimum() {
+            value < threshold
+        } else {
+            value > threshold
+        }
+    }
+
+    fn clears(&self, metric: HealthMetric, value: f64, threshold: f64) -> bool {
+        let margin = self.thresholds.hysteresis;
+        if metric.is_minimum() {
+            // The margin applies to the allowed miss rate
+            value > 100.0 - (100.0 - threshold) * (1.0 - margin)
+        } else {
+            value < threshold * (1.0 - margin)
+        }
+    }
+
+    /// The tracker holds no invariant a panic could break, so a poisoned
+    /// lock is recovered.
+    fn lock_state(&self) -> MutexGuard<'_, TrackerState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock_listeners(&self) -> MutexGuard<'_, Vec<(u64, HealthListener)>> {
+        self.listeners.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for HealthTracker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HealthTracker")
+            .field("thresholds", &self.thresholds)
+            .field("state", &self.state)
+            .field("listeners", &self.lock_listeners().len())
+            .finish()
+    }
+}
+
+/// Checks a cache's health on a background thread until stopped.
+///
+/// Created by [`Cache::start_monitor`](crate::Cache::start_monitor).
+/// Dropping the monitor without calling [`HealthMonitor::stop`] also stops
+/// the thread and unregisters the callback.
+#[derive(Debug)]
+pub struct HealthMonitor {
+    tracker: Arc<HealthTracker>,
+    listener: u64,
+    stop_tx: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl HealthMonitor {
+    pub(crate) fn start(
+        cache: &Cache,
+        tracker: Arc<HealthTracker>,
+        interval: Duration,
+        listener: HealthListener,
+    ) -> Self {
+        let listener = tracker.subscribe(listener);
+        let cache = cache.clone();
+        let interval = interval.max(Duration::from_millis(1));
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+
+        // Ends when a stop is requested or the monitor is dropped
+        let handle = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stop_rx.recv_timeout(interval) {
+                cache.health();
+            }
+        });
+
+        Self {
+            tracker,
+            listener,
+            stop_tx: Some(stop_tx),
+            handle: Some(handle),
+        }
+    }
+
+    /// Stop polling and unregister the callback.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.tracker.unsubscribe(self.listener);
+        if let Some(stop_tx) = self.stop_tx.take() {
+            let _ = stop_tx.send(());
+        }
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for HealthMonitor {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(size: u64, hits: u64, misses: u64, evictions: u64) -> HealthInput {
+        HealthInput {
+            size,
+            capacity: Some(100),
+            hits,
+            misses,
+            evictions,
+            memory_bytes: None,
+        }
+    }
+
+    fn kinds(events: &[HealthEvent]) -> Vec<(HealthMetric, HealthEventKind)> {
+        events.iter().map(|e| (e.metric, e.kind)).collect()
+    }
+
+    #[test]
+    fn test_size_alert_has_hysteresis() {
+        let tracker = HealthTracker::new(HealthThresholds::new().max_size_ratio(0.9));
+        let now = Instant::now();
+
+        let (report, events) = tracker.evaluate(input(91, 0, 0, 0), now);
+        assert_eq!(
+            kinds(&events),
+            vec![(HealthMetric::SizeRatio, HealthEventKind::Raised)]
+        );
+        assert_eq!(report.alerts, vec![HealthMetric::SizeRatio]);
+
+        // Hovering around the threshold does not flap
+        for size in [89, 91, 85, 90, 82] {
+            let (report, events) = tracker.evaluate(input(size, 0, 0, 0), now);
+            assert!(events.is_empty(), "event at size {}", size);
+            assert!(!report.is_healthy());
+        }
+
+        let (report, events) = tracker.evaluate(input(80, 0, 0, 0), now);
+        assert_eq!(
+            kinds(&events),
+            vec![(HealthMetric::SizeRatio, HealthEventKind::Cleared)]
+        );
+        assert!(report.is_healthy());
+    }
+
+    #[test]
+    fn test_hit_rate_is_windowed() {
+        let thresholds = HealthThresholds::new()
+            .min_hit_rate(80.0)
+            .window(Duration::from_secs(60));
+        let tracker = HealthTracker::new(thresholds);
+        let start = Instant::now();
+
+        // Lifetime hit rate stays high, but the last minute is all misses
+        let (report, _) = tracker.evaluate(input(0, 0, 0, 0), start);
+        assert_eq!(report.hit_rate, None);
+        let (report, events) =
+            tracker.evaluate(input(0, 1000, 0, 0), start + Duration::from_secs(60));
+        assert_eq!(report.hit_rate, Some(100.0));
+        assert!(events.is_empty());
+        let (report, events) =
+            tracker.evaluate(input(0, 1000, 100, 0), start + Duration::from_secs(120));
+        assert_eq!(report.hit_rate, Some(0.0));
+        assert_eq!(
+            kinds(&events),
+            vec![(HealthMetric::HitRate, HealthEventKind::Raised)]
+        );
+
+        // A recovery to 81% is inside the margin; 90% clears it
+        let (_, events) =
+            tracker.evaluate(input(0, 1081, 119, 0), start + Duration::from_secs(180));
+        assert!(events.is_empty());
+        let (_, events) =
+            tracker.evaluate(input(0, 1171, 129, 0), start + Duration::from_secs(240));
+        assert_eq!(
+            kinds(&events),
+            vec![(HealthMetric::HitRate, HealthEventKind::Cleared)]
+        );
+    }
+
+    #[test]
+    fn test_eviction_rate_per_minute() {
+        let thresholds = HealthThresholds::new()
+            .max_evictions_per_minute(100.0)
+            .window(Duration::from_secs(60));
+        let tracker = HealthTracker::new(thresholds);
+        let start = Instant::now();
+        tracker.evaluate(input(0, 0, 0, 0), start);
+
+        // A burst right after startup is spread over the full window
+        let (report, events) = tracker.evaluate(input(0, 0, 0, 50), start + Duration::from_secs(1));
+        assert_eq!(report.evictions_per_minute, 50.0);
+        assert!(events.is_empty());
+
+        let (report, events) =
+            tracker.evaluate(input(0, 0, 0, 300), start + Duration::from_secs(60));
+        assert_eq!(report.evictions_per_minute, 300.0);
+        assert_eq!(
+            kinds(&events),
+            vec![(HealthMetric::EvictionRate, HealthEventKind::Raised)]
+        );
+
+        // Evictions stop; the burst leaves the window
+        let (report, events) =
+            tracker.evaluate(input(0, 0, 0, 300), start + Duration::from_secs(121));
+        assert_eq!(report.evictions_per_minute, 0.0);
+        assert_eq!(
+            kinds(&events),
+            vec![(HealthMetric::EvictionRate, HealthEventKind::Cleared)]
+        );
+    }
+
+    #[test]
+    fn test_stats_reset_restarts_window() {
+        let tracker = HealthTracker::new(HealthThresholds::new().min_hit_rate(50.0));
+        let start = Instant::now();
+        tracker.evaluate(input(0, 0, 10, 0), start);
+        let (report, _) = tracker.evaluate(input(0, 0, 0, 0), start + Duration::from_secs(1));
+        assert_eq!(report.hit_rate, None);
+        let (report, _) = tracker.evaluate(input(0, 3, 1, 0), start + Duration::from_secs(2));
+        assert_eq!(report.hit_rate, Some(75.0));
+    }
+
+    #[test]
+    fn test_unset_thresholds_never_alert() {
+        let tracker = HealthTracker::new(HealthThresholds::new());
+        let mut full = input(100, 0, 1000, 1000);
+        full.memory_bytes = Some(usize::MAX);
+        let (report, events) = tracker.evaluate(full, Instant::now());
+        assert!(events.is_empty());
+        assert!(report.is_healthy());
+        assert_eq!(report.size_ratio, Some(1.0));
+    }
+
+    #[test]
+    fn test_event_display() {
+        let event = HealthEvent {
+            metric: HealthMetric::HitRate,
+            kind: HealthEventKind::Raised,
+            value: 42.0,
+            threshold: 80.0,
+        };
+        assert_eq!(event.to_string(), "hit_rate 42.0% below soft limit 80.0%");
+        let event = HealthEvent {
+            metric: HealthMetric::SizeRatio,
+            kind: HealthEventKind::Cleared,
+            value: 0.5,
+            threshold: 0.9,
+        };
+        assert_eq!(
+            event.to_string(),
+            "size_ratio 0.50 back within soft limit 0.90"
+        );
+        assert_eq!(
+            HealthEvent::growth(1_000_003, 1_000_000).to_string(),
+            "unbounded_growth 1000003 entries reached growth alarm 1000000 of a cache without max_capacity"
+        );
+    }
+
+    #[test]
+    fn test_growth_alarm_fires_once_per_doubling() {
+        let alarm = GrowthAlarm::new(1_000_000);
+        assert_eq!(alarm.check(999_999), None);
+        assert_eq!(alarm.check(1_000_000), Some(1_000_000));
+        assert_eq!(alarm.check(1_000_001), None);
+        assert_eq!(alarm.check(1_999_999), None);
+        assert_eq!(alarm.check(2_000_000), Some(2_000_000));
+
+        // Shrinking and growing back does not report again
+        assert_eq!(alarm.check(10), None);
+        assert_eq!(alarm.check(2_500_000), None);
+
+        // A jump over several doublings reports the largest
+        assert_eq!(alarm.check(9_000_000), Some(8_000_000));
+        assert_eq!(alarm.check(15_999_999), None);
+        assert_eq!(alarm.check(u64::MAX), Some(8_000_000 << 41));
+        assert_eq!(alarm.check(u64::MAX), None);
+    }
+
+    #[test]
+    fn test_growth_alarm_reports_to_one_thread() {
+        let alarm = Arc::new(GrowthAlarm::new(100));
+        let reports: u32 = (0..8)
+            .map(|_| {
+                let alarm = Arc::clone(&alarm);
+                thread::spawn(move || {
+                    (0..1000).filter(|_| alarm.check(150).is_some()).count() as u32
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .sum();
+        assert_eq!(reports, 1);
+    }
+}
@@ -0,0 +1,511 @@
+//! Import of Redis command streams (feature `tools`).
+//!
+//! Reads the command stream produced by tools such as `redis-dump-go` or
+//! `redis-cli --rdb` converters and applies the string commands to a cache.
+//! Both framings Redis itself accepts are understood, and may be mixed:
+//!
+//! - RESP arrays (`*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nvalue\r\n`), which are
+//!   binary-safe
+//! - inline commands, one per line (`SET key "hello world" EX 60`), with
+//!   redis-cli style double and single quoting
+//!
+//! Supported commands are `SET` (with `EX`/`PX`/`EXAT`/`PXAT`), `SETEX`,
+//! `PSETEX`, `EXPIRE`, `PEXPIRE`, `EXPIREAT` and `PEXPIREAT`. Commands for
+//! other data types are skipped and counted per type in the
+//! [`ImportReport`]. Blank lines and lines starting with `#` are ignored.
+//!
+//! ```
+//! use in_memory_cache::Cache;
+//!
+//! let dump = "SET user:1 Alice\nSET session:9 \"a b c\" EX 60\nHSET h f v\n";
+//! let cache = Cache::default();
+//! let report = cache.import_redis_proto(dump.as_bytes()).unwrap();
+//!
+//! assert_eq!(report.imported, 2);
+//! assert_eq!(report.skipped.get("hash"), Some(&1));
+//! assert_eq!(cache.get("session:9"), Some("a b c".into()));
+//! ```
+
+use bytes::Bytes;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{BufRead, Read};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::{CacheError, CacheResult};
+use crate::storage::Db;
+
+/// One operation decoded from a Redis command stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportOp {
+    /// Store a string value, optionally with a TTL.
+    Set {
+        key: String,
+        value: Bytes,
+        ttl: Option<Duration>,
+    },
+    /// Give an existing key a TTL.
+    Expire { key: String, ttl: Duration },
+    /// A command that cannot be imported. `kind` is the Redis data type
+    /// (`hash`, `list`, ...) when known, otherwise the lowercase command.
+    Skip { kind: String },
+}
+
+/// Summary of an import.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    /// String keys stored.
+    pub imported: usize,
+    /// Expiration commands applied to already imported keys.
+    pub expirations: usize,
+    /// Skipped commands, counted by data type or command name.
+    pub skipped: BTreeMap<String, usize>,
+}
+
+impl ImportReport {
+    fn skip(&mut self, kind: String) {
+        *self.skipped.entry(kind).or_insert(0) += 1;
+    }
+}
+
+impl fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "imported {} keys, applied {} expirations",
+            self.imported, self.expirations
+        )?;
+        for (kind, count) in &self.skipped {
+            write!(f, "\nskipped {} {}", count, kind)?;
+        }
+        Ok(())
+    }
+}
+
+/// Streaming decoder for Redis command streams.
+///
+/// Errors carry the number of the line on which the offending command
+/// starts.
+#[derive(Debug)]
+pub struct RedisProtoReader<R> {
+    reader: R,
+    line: usize,
+}
+
+impl<R: BufRead> RedisProtoReader<R> {
+    /// Wrap a buffered reader.
+    pub fn new(reader: R) -> Self {
+        Self { reader, line: 0 }
+    }
+
+    /// Decode the next operation, or `None` at end of input.
+    pub fn next_op(&mut self) -> CacheResult<Option<ImportOp>> {
+        loop {
+            let mut raw = Vec::new();
+            if self.reader.read_until(b'\n', &mut raw)? == 0 {
+                return Ok(None);
+            }
+            self.line += 1;
+            let start = self.line;
+            let line = trim_eol(&raw);
+
+            if line.iter().all(u8::is_ascii_whitespace) || line.first() == Some(&b'#') {
+                continue;
+            }
+
+            let args = if line.first() == Some(&b'*') {
+                self.read_array(line)
+            } else {
+                split_inline(line)
+            }
+            .map_err(|msg| error_at(start, &msg))?;
+
+            if args.is_empty() {
+                continue;
+            }
+            return interpret(&args)
+                .map(Some)
+                .map_err(|msg| error_at(start, &msg));
+        }
+    }
+
+    /// Read the bulk strings of a RESP array whose header is `header`.
+    fn read_array(&mut self, header: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+        let count = parse_header(header, b'*')?;
+        let mut args = Vec::with_capacity(count.min(64));
+
+        for _ in 0..count {
+            let mut raw = Vec::new();
+            let read = self
+                .reader
+                .read_until(b'\n', &mut raw)
+                .map_err(|e| e.to_string())?;
+            if read == 0 {
+                return Err("unexpected end of input inside array".to_string());
+            }
+            self.line += 1;
+            let len = parse_header(trim_eol(&raw), b'$')?;
+
+            // Read through `take` so a hostile length cannot force a huge
+            // allocation up front
+            let wanted = len
+                .checked_add(2)
+                .ok_or_else(|| "invalid '$' header".to_string())?;
+            let mut value = Vec::new();
+            (&mut self.reader)
+                .take(wanted as u64)
+                .read_to_end(&mut value)
+                .map_err(|e| e.to_string())?;
+            if value.len() < wanted {
+                return Err("unexpected end of input inside bulk string".to_string());
+            }
+            if !value.ends_with(b"\r\n") {
+                return Err("bulk string not terminated by CRLF".to_string());
+            }
+            value.truncate(len);
+            self.line += value.iter().filter(|&&b| b == b'\n').count() + 1;
+            args.push(value);
+        }
+        Ok(args)
+    }
+}
+
+impl<R: BufRead> Iterator for RedisProtoReader<R> {
+    type Item = CacheResult<ImportOp>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_op().transpose()
+    }
+}
+
+/// Apply a command stream to `db`. Without `notify_replaced` the listener
+/// is not told about the values the import overwrites.
+pub(crate) fn import(
+    db: &Db,
+    reader: impl BufRead,
+    notify_replaced: bool,
+) -> CacheResult<ImportReport> {
+    let mut report = ImportReport::default();
+
+    for op in RedisProtoReader::new(reader) {
+        match op? {
+            ImportOp::Set { key, value, ttl } => {
+                db.load(key, value, ttl, notify_replaced);
+                report.imported += 1;
+            }
+            ImportOp::Expire { key, ttl } => {
+                if db.set_expiration(&key, db.now() + ttl) {
+                    report.expirations += 1;
+                } else {
+                    report.skip("expire of unknown key".to_string());
+                }
+            }
+            ImportOp::Skip { kind } => report.skip(kind),
+        }
+    }
+    Ok(report)
+}
+
+fn interpret(args: &[Vec<u8>]) -> Result<ImportOp, String> {
+    let command = String::from_utf8_lossy(&args[0]).to_ascii_uppercase();
+    let arity = |n: usize| {
+        if args.len() < n {
+            Err(format!("{} expects at least {} arguments", command, n - 1))
+        } else {
+            Ok(())
+        }
+    };
+
+    match command.as_str() {
+        "SET" => {
+            arity(3)?;
+            let mut ttl = None;
+            let mut options = args[3..].iter();
+            while let Some(option) = options.next() {
+                let option = String::from_utf8_lossy(option).to_ascii_uppercase();
+                let mut amount = || {
+                    options
+                        .next()
+                        .ok_or_else(|| format!("SET option {} needs a value", option))
+                        .and_then(|raw| parse_u64(raw))
+                };
+                ttl = Some(match option.as_str() {
+                    "EX" => Duration::from_secs(amount()?),
+                    "PX" => Duration::from_millis(amount()?),
+                    "EXAT" => match until(Duration::from_secs(amount()?)) {
+                        Some(ttl) => ttl,
+                        None => return Ok(skip("expired")),
+                    },
+                    "PXAT" => match until(Duration::from_millis(amount()?)) {
+                        Some(ttl) => ttl,
+                        None => return Ok(skip("expired")),
+                    },
+                    _ => return Err(format!("unsupported SET option {}", option)),
+                });
+            }
+            Ok(set(&args[1], &args[2], ttl)?)
+        }
+        "SETEX" | "PSETEX" => {
+            arity(4)?;
+            let amount = parse_u64(&args[2])?;
+            let ttl = if command == "SETEX" {
+                Duration::from_secs(amount)
+            } else {
+                Duration::from_millis(amount)
+            };
+            Ok(set(&args[1], &args[3], Some(ttl))?)
+        }
+        "EXPIRE" | "PEXPIRE" | "EXPIREAT" | "PEXPIREAT" => {
+            arity(3)?;
+            let amount = parse_u64(&args[2])?;
+            let ttl = match command.as_str() {
+                "EXPIRE" => Some(Duration::from_secs(amount)),
+                "PEXPIRE" => Some(Duration::from_millis(amount)),
+                "EXPIREAT" => until(Duration::from_secs(amount)),
+                _ => until(Duration::from_millis(amount)),
+            };
+            match ttl {
+                Some(ttl) => Ok(ImportOp::Expire {
+                    key: utf8_key(&args[1])?,
+                    ttl,
+                }),
+                None => Ok(skip("expired")),
+            }
+        }
+        "HSET" | "HMSET" | "HSETNX" => Ok(skip("hash")),
+        "RPUSH" | "LPUSH" | "RPUSHX" | "LPUSHX" => Ok(skip("list")),
+        "SADD" => Ok(skip("set")),
+        "ZADD" => Ok(skip("zset")),
+        "XADD" => Ok(skip("stream")),
+        "PFADD" => Ok(skip("hyperloglog")),
+        "RESTORE" => Ok(skip("rdb payload")),
+        _ => Ok(ImportOp::Skip {
+            kind: command.to_ascii_lowercase(),
+        }),
+    }
+}
+
+fn set(key: &[u8], value: &[u8], ttl: Option<Duration>) -> Result<ImportOp, String> {
+    Ok(ImportOp::Set {
+        key: utf8_key(key)?,
+        value: Bytes::copy_from_slice(value),
+        ttl,
+    })
+}
+
+fn skip(kind: &str) -> ImportOp {
+    ImportOp::Skip {
+        kind: kind.to_string(),
+    }
+}
+
+fn utf8_key(raw: &[u8]) -> Result<String, String> {
+    String::from_utf8(raw.to_vec()).map_err(|_| "key is not valid UTF-8".to_string())
+}
+
+fn parse_u64(raw: &[u8]) -> Result<u64, String> {
+    std::str::from_utf8(raw)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| format!("invalid integer '{}'", String::from_utf8_lossy(raw)))
+}
+
+/// Time remaining until a Unix timestamp, or `None` if it has passed.
+fn until(timestamp: Duration) -> Option<Duration> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+    timestamp.checked_sub(now).filter(|ttl| !ttl.is_zero())
+}
+
+fn parse_header(line: &[u8], marker: u8) -> Result<usize, String> {
+    if line.first() != Some(&marker) {
+        return Err(format!("expected '{}' header", marker as char));
+    }
+    std::str::from_utf8(&line[1..])
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| format!("invalid '{}' header", marker as char))
+}
+
+fn trim_eol(raw: &[u8]) -> &[u8] {
+    let raw = raw.strip_suffix(b"\n").unwrap_or(raw);
+    raw.strip_suffix(b"\r").unwrap_or(raw)
+}
+
+/// Split an inline command the way redis-cli does.
+///
+/// Double-quoted arguments understand `\n`, `\r`, `\t`, `\\`, `\"` and
+/// `\xHH`; single-quoted arguments are literal except for `\'`.
+fn split_inline(line: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    let mut args = Vec::new();
+    let mut bytes = line.iter().copied().peekable();
+
+    loop {
+        while bytes.next_if(u8::is_ascii_whitespace).is_some() {}
+        let first = match bytes.peek() {
+            Some(&b) => b,
+            None => return Ok(args),
+        };
+
+        let mut arg = Vec::new();
+        match first {
+            b'"' => {
+                bytes.next();
+                loop {
+                    match bytes.next() {
+                        Some(b'"') => break,
+                        Some(b'\\') => match bytes.next() {
+                            Some(b'n') => arg.push(b'\n'),
+                            Some(b'r') => arg.push(b'\r'),
+                            Some(b't') => arg.push(b'\t'),
+                            Some(b'x') => {
+                                let hex = [bytes.next(), bytes.next()];
+                                let value = match hex {
+                                    [Some(hi), Some(lo)] => std::str::from_utf8(&[hi, lo])
+                                        .ok()
+                                        .and_then(|h| u8::from_str_radix(h, 16).ok()),
+                                    _ => None,
+                                };
+                                arg.push(value.ok_or("invalid \\x escape")?);
+                            }
+                            Some(other) => arg.push(other),
+                            None => return Err("unterminated quoted argument".to_string()),
+                        },
+                        Some(other) => arg.push(other),
+                        None => return Err("unterminated quoted argument".to_string()),
+                    }
+                }
+            }
+            b'\'' => {
+                bytes.next();
+                loop {
+                    match bytes.next() {
+                        Some(b'\'') => break,
+                        Some(b'\\') if bytes.peek() == Some(&b'\'') => {
+                            bytes.next();
+                            arg.push(b'\'');
+                        }
+                        Some(other) => arg.push(other),
+                        None => return Err("unterminated quoted argument".to_string()),
+                    }
+                }
+            }
+            _ => {
+                while let Some(b) = bytes.next_if(|b| !b.is_ascii_whitespace()) {
+                    arg.push(b);
+                }
+            }
+        }
+        if bytes.peek().is_some_and(|b| !b.is_ascii_whitespace()) {
+            return Err("closing quote must be followed by a space".to_string());
+        }
+        args.push(arg);
+    }
+}
+
+fn error_at(line: usize, msg: &str) -> CacheError {
+    CacheError::ParseError(format!("line {}: {}", line, msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CacheConfig;
+
+    fn ops(input: &[u8]) -> CacheResult<Vec<ImportOp>> {
+        RedisProtoReader::new(input).collect()
+    }
+
+    #[test]
+    fn test_inline_quoting() {
+        let args = split_inline(br#"SET "a b" 'it\'s' "\x41\n" plain"#).unwrap();
+        assert_eq!(
+            args,
+            vec![
+                b"SET".to_vec(),
+                b"a b".to_vec(),
+                b"it's".to_vec(),
+                b"A\n".to_vec(),
+                b"plain".to_vec(),
+            ]
+        );
+        assert!(split_inline(b"SET \"open").is_err());
+        assert!(split_inline(b"SET \"a\"b").is_err());
+    }
+
+    #[test]
+    fn test_resp_and_inline_mixed() {
+        let input = b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$4\r\na\r\nb\r\n\
+                      SETEX s 60 v\r\n\
+                      # comment\n\
+                      \n\
+                      LPUSH list x\n";
+        let ops = ops(input).unwrap();
+        assert_eq!(
+            ops,
+            vec![
+                ImportOp::Set {
+                    key: "k".to_string(),
+                    value: Bytes::from_static(b"a\r\nb"),
+                    ttl: None,
+                },
+                ImportOp::Set {
+                    key: "s".to_string(),
+                    value: Bytes::from("v"),
+                    ttl: Some(Duration::from_secs(60)),
+                },
+                skip("list"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_set_options() {
+        assert_eq!(
+            ops(b"SET k v PX 1500").unwrap(),
+            vec![ImportOp::Set {
+                key: "k".to_string(),
+                value: Bytes::from("v"),
+                ttl: Some(Duration::from_millis(1500)),
+            }]
+        );
+        assert_eq!(ops(b"SET k v EXAT 1").unwrap(), vec![skip("expired")]);
+    }
+
+    #[test]
+    fn test_errors_name_line() {
+        let err = ops(b"SET a 1\nSET b\n").unwrap_err().to_string();
+        assert!(err.contains("line 2"), "{}", err);
+
+        let err = ops(b"SET a 1\n*2\r\n$3\r\nSET\r\n$9\r\nab\r\n")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("line 2"), "{}", err);
+
+        // Line numbers account for newlines inside bulk strings
+        let input = b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$3\r\n\n\n\n\r\nSET x 1 EX soon\n";
+        let err = ops(input).unwrap_err().to_string();
+        assert!(err.contains("line 11"), "{}", err);
+
+        let err = ops(b"SET k v KEEPTTL").unwrap_err().to_string();
+        assert!(err.contains("unsupported SET option KEEPTTL"), "{}", err);
+    }
+
+    #[test]
+    fn test_import_into_db() {
+        let db = Db::new(CacheConfig::default());
+        let input = b"SET a 1\nSET b 2\nEXPIRE b 60\nEXPIRE missing 60\nSADD s x\nSADD s y\n";
+        let report = import(&db, &input[..], true).unwrap();
+
+        assert_eq!(report.imported, 2);
+        assert_eq!(report.expirations, 1);
+        assert_eq!(report.skipped.get("set"), Some(&2));
+        assert_eq!(report.skipped.get("expire of unknown key"), Some(&1));
+        assert_eq!(db.get("b"), Some(Bytes::from("2")));
+        assert_eq!(
+            report.to_string(),
+            "imported 2 keys, applied 1 expirations\n\
+             skipped 1 expire of unknown key\n\
+             skipped 2 set"
+        );
+    }
+}
@@ -0,0 +1,424 @@
+//! Caching middleware for tower services (feature `tower`).
+//!
+//! [`CacheLayer`] wraps a service whose responses convert to and from
+//! [`Bytes`]. Each request is mapped to a key; a hit is answered from the
+//! cache without calling the inner service, and on a miss the inner
+//! service's response is stored with a TTL derived from the response, for
+//! example from its `Cache-Control: max-age`.
+//!
+//! Concurrent misses on one key are collapsed: the first request calls the
+//! inner service and the others wait for its response. If that call fails,
+//! or its response is not stored, each waiter calls the inner service
+//! itself, so errors and uncacheable responses are never shared.
+//!
+//! ```
+//! use bytes::Bytes;
+//! use in_memory_cache::layer::CacheLayer;
+//! use in_memory_cache::Cache;
+//! use std::time::Duration;
+//! use tower::{Layer, Service};
+//! # use std::convert::Infallible;
+//! # use std::future::{ready, Ready};
+//! # use std::task::{Context, Poll};
+//! #
+//! # /// Renders a page for every path.
+//! # #[derive(Clone)]
+//! # struct Origin;
+//! #
+//! # impl Service<String> for Origin {
+//! #     type Response = Bytes;
+//! #     type Error = Infallible;
+//! #     type Future = Ready<Result<Bytes, Infallible>>;
+//! #
+//! #     fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+//! #         Poll::Ready(Ok(()))
+//! #     }
+//! #
+//! #     fn call(&mut self, path: String) -> Self::Future {
+//! #         ready(Ok(Bytes::from(format!("<page {}>", path))))
+//! #     }
+//! # }
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let cache = Cache::default();
+//! let layer = CacheLayer::new(
+//!     cache.clone(),
+//!     |path: &String| Some(path.clone()),
+//!     |_: &Bytes| Some(Duration::from_secs(60)),
+//! );
+//! let mut service = layer.layer(Origin);
+//!
+//! std::future::poll_fn(|cx| service.poll_ready(cx)).await.unwrap();
+//! let page = service.call("/index.html".to_string()).await.unwrap();
+//! assert_eq!(page, Bytes::from("<page /index.html>"));
+//! assert!(cache.contains("/index.html"));
+//! # });
+//! ```
+
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::watch;
+use tower::{Layer, Service};
+
+use crate::cache::Cache;
+
+/// Calls to inner services in progress, keyed by cache key. A waiter sees
+/// `Some` once the response is stored, or the channel close without a
+/// value if the call failed or was dropped.
+type Flights = Mutex<HashMap<String, watch::Receiver<Option<Bytes>>>>;
+
+/// A [`Layer`] that caches the responses of the services it wraps.
+///
+/// `key_fn` maps a request to its cache key, or to `None` to pass the
+/// request straight through. `ttl_fn` gives the TTL to store a response
+/// with, or `None` to leave it uncached (e.g. for `no-store` or an error
+/// status).
+pub struct CacheLayer<K, T> {
+    cache: Cache,
+    key_fn: Arc<K>,
+    ttl_fn: Arc<T>,
+    flights: Arc<Flights>,
+}
+
+impl<K, T> CacheLayer<K, T> {
+    /// Create a layer storing responses in `cache`.
+    ///
+    /// Services built from one layer, and their clones, share the cache
+    /// and collapse concurrent misses together.
+    pub fn new(cache: Cache, key_fn: K, ttl_fn: T) -> Self {
+        Self {
+            cache,
+            key_fn: Arc::new(key_fn),
+            ttl_fn: Arc::new(ttl_fn),
+            flights: Arc::default(),
+        }
+    }
+}
+
+impl<K, T> Clone for CacheLayer<K, T> {
+    fn clone(&self) -> Self {
+        Self {
+            cache: self.cache.clone(),
+            key_fn: Arc::clone(&self.key_fn),
+            ttl_fn: Arc::clone(&self.ttl_fn),
+            flights: Arc::clone(&self.flights),
+        }
+    }
+}
+
+impl<K, T> fmt::Debug for CacheLayer<K, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CacheLayer").finish_non_exhaustive()
+    }
+}
+
+impl<S, K, T> Layer<S> for CacheLayer<K, T> {
+    type Service = CacheService<S, K, T>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CacheService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// The [`Service`] built by a [`CacheLayer`].
+pub struct CacheService<S, K, T> {
+    inner: S,
+    layer: CacheLayer<K, T>,
+}
+
+impl<S: Clone, K, T> Clone for CacheService<S, K, T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            layer: self.layer.clone(),
+        }
+    }
+}
+
+impl<S: fmt::Debug, K, T> fmt::Debug for CacheService<S, K, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CacheService")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S, K, T, Req> Service<Req> for CacheService<S, K, T>
+where
+    S: Service<Req> + Clone + Send + 'static,
+    S::Response: From<Bytes> + Into<Bytes>,
+    S::Future: Send,
+    K: Fn(&Req) -> Option<String>,
+    T: Fn(&S::Response) -> Option<Duration> + Send + Sync + 'static,
+    Req: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Req) -> Self::Future {
+        // The clone is not ready yet, so keep it and call the ready one
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let key = match (self.layer.key_fn)(&request) {
+            Some(key) => key,
+            None => return Box::pin(inner.call(request)),
+        };
+        if let Some(value) = self.layer.cache.get(&key) {
+            return Box::pin(async move { Ok(S::Response::from(value)) });
+        }
+
+        let cache = self.layer.cache.clone();
+        let ttl_fn = Arc::clone(&self.layer.ttl_fn);
+        let flights = Arc::clone(&self.layer.flights);
+        Box::pin(async move {
+            loop {
+                let flight = match join(&flights, &cache, &key) {
+                    Joined::Hit(value) => return Ok(S::Response::from(value)),
+                    Joined::Wait(mut waiting) => {
+                        // Closed without a value: the call failed, was not
+                        // stored or was dropped, so try again
+                        if let Ok(value) = waiting.wait_for(Option::is_some).await {
+                            if let Some(value) = value.clone() {
+                                return Ok(S::Response::from(value));
+                            }
+                        }
+                        continue;
+                    }
+                    Joined::Lead(flight) => flight,
+                };
+
+                let response = inner.call(request).await?;
+                let ttl = match ttl_fn(&response) {
+                    Some(ttl) => ttl,
+                    None => return Ok(response),
+                };
+                let value: Bytes = response.into();
+                cache.set_with_ttl(key.as_str(), value.clone(), ttl);
+                flight.finish(value.clone());
+                return Ok(S::Response::from(value));
+            }
+        })
+    }
+}
+
+/// How a request on a missed key proceeds.
+enum Joined {
+    /// The value was stored since the first lookup.
+    Hit(Bytes),
+    /// Another request is calling the inner service for the key.
+    Wait(watch::Receiver<Option<Bytes>>),
+    /// This request calls the inner service.
+    Lead(Flight),
+}
+
+fn join(flights: &Arc<Flights>, cache: &Cache, key: &str) -> Joined {
+    let mut calls = lock(flights);
+    if let Some(waiting) = calls.get(key) {
+        return Joined::Wait(waiting.clone());
+    }
+    // A leader stores its value before leaving the map, so this catches a
+    // call that finished after the first lookup
+    if let Some(value) = cache.peek(key) {
+        return Joined::Hit(value);
+    }
+    let (sender, waiting) = watch::channel(None);
+    calls.insert(key.to_string(), waiting);
+    Joined::Lead(Flight {
+        flights: Arc::clone(flights),
+        key: key.to_string(),
+        sender,
+    })
+}
+
+/// The map holds no invariant a panic could break, so a poisoned lock is
+/// recovered.
+fn lock(flights: &Flights) -> MutexGuard<'_, HashMap<String, watch::Receiver<Option<Bytes>>>> {
+    flights.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// A call to the inner service that other requests may be waiting for.
+/// Dropping it, whether finished or not, retires the key and wakes them.
+struct Flight {
+    flights: Arc<Flights>,
+    key: String,
+    sender: watch::Sender<Option<Bytes>>,
+}
+
+impl Flight {
+    /// Hand the stored `value` to the waiters.
+    fn finish(self, value: Bytes) {
+        self.sender.send_replace(Some(value));
+    }
+}
+
+impl Drop for Flight {
+    fn drop(&mut self) {
+        lock(&self.flights).remove(&self.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// An origin answering `<path>` after `delay`, counting its calls.
+    /// Paths starting with `fail` are errors and `private` ones are not
+    /// cacheable.
+    #[derive(Clone)]
+    struct Origin {
+        calls: Arc<AtomicUsize>,
+        delay: Duration,
+    }
+
+    impl Service<String> for Origin {
+        type Response = Bytes;
+        type Error = String;
+        type Future = Pin<Box<dyn Future<Output = Result<Bytes, String>> + Send>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), String>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, path: String) -> Self::Future {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let delay = self.delay;
+            Box::pin(async move {
+                tokio::time::sleep(delay).await;
+                if path.starts_with("fail") {
+                    return Err(format!("{} failed", path));
+                }
+                Ok(Bytes::from(path))
+            })
+        }
+    }
+
+    fn origin(calls: &Arc<AtomicUsize>, delay: Duration) -> Origin {
+        Origin {
+            calls: Arc::clone(calls),
+            delay,
+        }
+    }
+
+    /// Wait for `service` to be ready and send it `request`.
+    async fn oneshot<S: Service<R>, R>(
+        mut service: S,
+        request: R,
+    ) -> Result<S::Response, S::Error> {
+        std::future::poll_fn(|cx| service.poll_ready(cx)).await?;
+        service.call(request).await
+    }
+
+    fn layer(
+        cache: &Cache,
+    ) -> CacheLayer<impl Fn(&String) -> Option<String>, impl Fn(&Bytes) -> Option<Duration>> {
+        CacheLayer::new(
+            cache.clone(),
+            |path: &String| (!path.starts_with("post")).then(|| path.clone()),
+            |body: &Bytes| (!body.starts_with(b"private")).then_some(Duration::from_secs(60)),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_hits_skip_the_inner_service() {
+        let cache = Cache::default();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let service = layer(&cache).layer(origin(&calls, Duration::ZERO));
+
+        for _ in 0..3 {
+            let page = oneshot(service.clone(), "/a".to_string()).await;
+            assert_eq!(page, Ok(Bytes::from("/a")));
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(cache.ttl("/a").unwrap().unwrap() > Duration::from_secs(59));
+        assert_eq!(cache.stats().hits, 2);
+    }
+
+    #[tokio::test]
+    async fn test_uncached_requests_and_responses() {
+        let cache = Cache::default();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let layer = layer(&cache);
+        let service = layer.layer(origin(&calls, Duration::ZERO));
+
+        // No key: passed straight through
+        for _ in 0..2 {
+            let reply = oneshot(service.clone(), "post /a".to_string()).await;
+            assert_eq!(reply, Ok(Bytes::from("post /a")));
+        }
+        // No TTL, or an error: returned but not stored
+        for _ in 0..2 {
+            let reply = oneshot(service.clone(), "private".to_string()).await;
+            assert_eq!(reply, Ok(Bytes::from("private")));
+            let reply = oneshot(service.clone(), "fail".to_string()).await;
+            assert_eq!(reply, Err("fail failed".to_string()));
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 6);
+        assert!(cache.is_empty());
+        assert!(lock(&layer.flights).is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_misses_collapse() {
+        let cache = Cache::default();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let layer = layer(&cache);
+        let service = layer.layer(origin(&calls, Duration::from_millis(50)));
+
+        let requests: Vec<_> = (0..16)
+            .map(|i| {
+                let service = service.clone();
+                let path = if i % 2 == 0 { "/even" } else { "/odd" };
+                tokio::spawn(oneshot(service, path.to_string()))
+            })
+            .collect();
+        for (i, request) in requests.into_iter().enumerate() {
+            let expected = if i % 2 == 0 { "/even" } else { "/odd" };
+            assert_eq!(request.await.unwrap(), Ok(Bytes::from(expected)));
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert!(lock(&layer.flights).is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_waiters_retry_after_a_failed_call() {
+        let cache = Cache::default();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let layer = layer(&cache);
+        let service = layer.layer(origin(&calls, Duration::from_millis(50)));
+
+        let requests: Vec<_> = (0..4)
+            .map(|_| tokio::spawn(oneshot(service.clone(), "fail".to_string())))
+            .collect();
+        for request in requests {
+            assert_eq!(request.await.unwrap(), Err("fail failed".to_string()));
+        }
+        // Every request saw its own error
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+
+        // A dropped leader frees its key as well
+        let leader = tokio::spawn(oneshot(service.clone(), "/slow".to_string()));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        leader.abort();
+        let _ = leader.await;
+        assert!(lock(&layer.flights).is_empty());
+        let page = oneshot(service.clone(), "/slow".to_string()).await;
+        assert_eq!(page, Ok(Bytes::from("/slow")));
+        assert_eq!(calls.load(Ordering::SeqCst), 6);
+    }
+}
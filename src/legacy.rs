@@ -0,0 +1,203 @@
+//! A bridge for code still written against the legacy `Db` API.
+//!
+//! The deprecated `Db::write` and `Db::read` take the whole command as an
+//! array of strings (`["set", key, value]`, `["get", key]`). A
+//! [`LegacyAdapter`] offers the same two methods, with the same return
+//! values and errors, on top of a [`Cache`], so old call sites can move off
+//! `Db` first and off the array interface later:
+//!
+//! ```
+//! use in_memory_cache::{Cache, LegacyAdapter};
+//!
+//! let legacy = LegacyAdapter::new(Cache::default());
+//! let set = |key: &str, value: &str| ["set".to_string(), key.to_string(), value.to_string()];
+//! assert_eq!(legacy.write(&set("user:1", "alice")).unwrap(), "Ok");
+//! assert_eq!(legacy.write(&set("user:1", "bob")).unwrap(), "r Ok");
+//!
+//! // New code uses the same storage directly
+//! assert_eq!(legacy.cache().get("user:1"), Some("bob".into()));
+//! ```
+//!
+//! The first call to each method prints a one-line reminder to stderr.
+
+use bytes::Bytes;
+use std::sync::Once;
+
+use crate::cache::Cache;
+use crate::error::{CacheError, CacheResult};
+use crate::ops::SetOutcome;
+
+static WRITE_WARNING: Once = Once::new();
+static READ_WARNING: Once = Once::new();
+
+/// The legacy `write`/`read` interface over a [`Cache`].
+///
+/// Clones share the same storage.
+#[derive(Debug, Clone)]
+pub struct LegacyAdapter {
+    cache: Cache,
+}
+
+impl LegacyAdapter {
+    /// Wrap `cache`.
+    pub fn new(cache: Cache) -> Self {
+        Self { cache }
+    }
+
+    /// The wrapped cache, for code that has already migrated.
+    pub fn cache(&self) -> &Cache {
+        &self.cache
+    }
+
+    /// Store `arr[2]` under `arr[1]`; `arr[0]` (the command name) and any
+    /// further elements are ignored.
+    ///
+    /// Returns `"r Ok"` if the key already held a value and `"Ok"` if it was
+    /// new. Fails with [`CacheError::ParseError`] if `arr` has fewer than
+    /// three elements. Prefer [`Cache::set_returning_outcome`].
+    pub fn write(&self, arr: &[String]) -> CacheResult<&'static str> {
+        write(
+            arr,
+            |key, value| self.cache.set_returning_outcome(key, value.to_string()),
+            |key| self.cache.contains(key),
+        )
+    }
+
+    /// The value stored under `arr[1]`; `arr[0]` and any further elements
+    /// are ignored.
+    ///
+    /// Fails with [`CacheError::KeyNotFound`] if the key has no value and
+    /// with [`CacheError::ParseError`] if `arr` has fewer than two
+    /// elements. Prefer [`Cache::get`].
+    pub fn read(&self, arr: &[String]) -> CacheResult<Bytes> {
+        read(arr, |key| self.cache.get(key))
+    }
+}
+
+/// Legacy `write`, given how to set a key and check whether it exists.
+pub(crate) fn write(
+    arr: &[String],
+    set: impl FnOnce(&str, &str) -> SetOutcome,
+    contains: impl FnOnce(&str) -> bool,
+) -> CacheResult<&'static str> {
+    WRITE_WARNING.call_once(|| {
+        eprintln!("in-memory-cache: legacy write() is deprecated, use Cache::set instead")
+    });
+    if arr.len() < 3 {
+        return Err(CacheError::ParseError(
+            "write requires at least 3 arguments: command key value".to_string(),
+        ));
+    }
+
+    let key = &arr[1];
+    let replaced = match set(key, &arr[2]) {
+        SetOutcome::Inserted => false,
+        SetOutcome::Replaced | SetOutcome::Unchanged => true,
+        // Nothing was written, so the key holds a value only if it already
+        // did, which is what the old contains-then-set answered
+        SetOutcome::Dropped => contains(key),
+    };
+    Ok(if replaced { "r Ok" } else { "Ok" })
+}
+
+/// Legacy `read`, given how to get a key.
+pub(crate) fn read(arr: &[String], get: impl FnOnce(&str) -> Option<Bytes>) -> CacheResult<Bytes> {
+    READ_WARNING.call_once(|| {
+        eprintln!("in-memory-cache: legacy read() is deprecated, use Cache::get instead")
+    });
+    if arr.len() < 2 {
+        return Err(CacheError::ParseError(
+            "read requires at least 2 arguments: command key".to_string(),
+        ));
+    }
+
+    let key = &arr[1];
+    get(key).ok_or_else(|| CacheError::KeyNotFound(key.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CacheConfig;
+
+    fn args(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn test_write_return_values() {
+        let legacy = LegacyAdapter::new(Cache::default());
+        assert_eq!(legacy.write(&args(&["set", "a", "1"])).unwrap(), "Ok");
+        assert_eq!(legacy.write(&args(&["set", "a", "2"])).unwrap(), "r Ok");
+        assert_eq!(legacy.write(&args(&["set", "a", "2"])).unwrap(), "r Ok");
+        // Extra words are ignored
+        assert_eq!(
+            legacy.write(&args(&["set", "b", "3", "ex", "10"])).unwrap(),
+            "Ok"
+        );
+        assert_eq!(legacy.cache().get("a"), Some(Bytes::from("2")));
+        assert_eq!(legacy.cache().ttl("b"), Some(None));
+    }
+
+    #[test]
+    fn test_coalesced_write_reports_replace() {
+        let cache = Cache::new(CacheConfig::new().coalesce_identical_writes(true));
+        let legacy = LegacyAdapter::new(cache);
+        assert_eq!(legacy.write(&args(&["set", "a", "1"])).unwrap(), "Ok");
+        assert_eq!(legacy.write(&args(&["set", "a", "1"])).unwrap(), "r Ok");
+    }
+
+    #[test]
+    fn test_expired_key_is_written_as_new() {
+        let cache = Cache::default();
+        cache.set_with_ttl("a", "old", std::time::Duration::from_millis(1));
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        let legacy = LegacyAdapter::new(cache);
+        assert_eq!(legacy.write(&args(&["set", "a", "new"])).unwrap(), "Ok");
+    }
+
+    #[test]
+    fn test_dropped_write_falls_back_to_contains() {
+        let outcome = |existed| {
+            write(
+                &args(&["set", "a", "1"]),
+                |_, _| SetOutcome::Dropped,
+                |_| existed,
+            )
+            .unwrap()
+        };
+        assert_eq!(outcome(false), "Ok");
+        assert_eq!(outcome(true), "r Ok");
+    }
+
+    #[test]
+    fn test_read_values_and_errors() {
+        let legacy = LegacyAdapter::new(Cache::default());
+        legacy.cache().set("a", "1");
+        assert_eq!(
+            legacy.read(&args(&["get", "a", "extra"])).unwrap(),
+            Bytes::from("1")
+        );
+
+        match legacy.read(&args(&["get", "missing"])) {
+            Err(CacheError::KeyNotFound(key)) => assert_eq!(key, "missing"),
+            other => panic!("unexpected {:?}", other),
+        }
+        match legacy.read(&args(&["get"])) {
+            Err(CacheError::ParseError(message)) => {
+                assert_eq!(message, "read requires at least 2 arguments: command key")
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        match legacy.write(&args(&["set", "a"])) {
+            Err(CacheError::ParseError(message)) => {
+                assert_eq!(
+                    message,
+                    "write requires at least 3 arguments: command key value"
+                )
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(legacy.cache().get("a"), Some(Bytes::from("1")));
+    }
+}
@@ -40,6 +40,37 @@
 //! println!("Hit rate: {:.1}%", stats.hit_rate);
 //! ```
 //!
+//! ## Prelude
+//!
+//! [`prelude`] re-exports the supported cache API, and nothing else:
+//!
+//! ```rust
+//! use in_memory_cache::prelude::*;
+//!
+//! let cache = Cache::new(CacheConfig::new().max_capacity(100));
+//! cache.set("key", "value");
+//! ```
+//!
+//! ## Cargo features
+//!
+//! - `legacy` (default): `buffer_to_array`, `Command` and `Db`.
+//! - `cli` (default, implies `tokio`): command-line definitions and the
+//!   `client` binary. Pulls in `clap`.
+//! - `server` (default, implies `cli` and `legacy`): the `server` module
+//!   and the `server` binary. Pulls in `tracing` and `tracing-subscriber`.
+//! - `tokio`: async helpers, such as the `BatchWriter` for high set
+//!   throughput. Pulls in `tokio`.
+//! - `test-util`: test doubles, a manually advanced clock and fault
+//!   injection.
+//! - `tools`: data migration helpers.
+//! - `tower` (implies `tokio`): the `CacheLayer` caching middleware for
+//!   tower services. Pulls in `tower`.
+//!
+//! The `legacy`, `cli` and `server` features are deprecated as default
+//! features and will stop being enabled by default in the next release.
+//! With `default-features = false` the library depends only on `bytes` and
+//! `indexmap`.
+//!
 //! ## Thread Safety
 //!
 //! The cache is safe to share across threads. Cloning a `Cache` creates a new
@@ -65,34 +96,107 @@
 
 // Public API - stable in v1.0.0
 pub mod cache;
+pub mod cleanup;
+pub mod clock;
 pub mod config;
 pub mod error;
+pub mod frozen;
+pub mod handle;
+pub mod health;
+pub mod listener;
+pub mod memory;
+pub mod ops;
+pub mod outcome;
+pub mod ratelimit;
+pub mod recorder;
+pub mod scan;
 pub mod stats;
+pub mod txn;
+
+#[cfg(feature = "test-util")]
+pub mod fault;
+#[cfg(feature = "test-util")]
+pub mod mock;
+
+#[cfg(feature = "tools")]
+pub mod import;
+
+#[cfg(feature = "tokio")]
+pub mod batch;
+#[cfg(feature = "tokio")]
+pub use batch::{Backpressure, BatchWriter};
+
+#[cfg(feature = "tower")]
+pub mod layer;
+#[cfg(feature = "tower")]
+pub use layer::{CacheLayer, CacheService};
 
 pub use cache::Cache;
-pub use config::CacheConfig;
+pub use cleanup::{CleanupBounds, CleanupTask};
+pub use clock::{Clock, SystemClock};
+pub use config::{CacheConfig, EvictionPolicy, SpillFallback, Weigher};
+pub use entry::EntryInfo;
 pub use error::{CacheError, CacheResult};
-pub use stats::{CacheStats, StatsSnapshot};
+pub use frozen::FrozenCache;
+pub use handle::{CacheReader, CacheWriter};
+pub use health::{HealthEvent, HealthReport, HealthThresholds};
+pub use listener::{EvictionListener, RemovalCause};
+pub use ops::{CacheOps, CacheRead, CacheWrite, SetOutcome};
+pub use outcome::TryInsertError;
+pub use ratelimit::RateDecision;
+pub use recorder::{StatsRecorder, TimedSnapshot};
+pub use scan::ScanCursor;
+pub use stats::{CacheStats, LockWaitHistogram, PrefixStats, StatsSnapshot};
+pub use txn::TxnView;
+pub use version::{version, VersionInfo, PROTOCOL_VERSION};
 
 // Internal modules - not part of public API
+pub(crate) mod callback;
+pub(crate) mod checksum;
 pub(crate) mod entry;
+pub(crate) mod export;
+pub(crate) mod glob;
+pub(crate) mod lz4;
+pub(crate) mod overrides;
+pub(crate) mod rng;
+pub(crate) mod singleflight;
+pub(crate) mod spill;
 pub(crate) mod storage;
+pub(crate) mod version;
+
+pub mod prelude;
+pub mod protocol;
 
 // Legacy modules - preserved for backward compatibility with server/client binaries
+#[cfg(feature = "legacy")]
 pub mod utils;
+#[cfg(feature = "legacy")]
 pub use utils::buffer_to_array;
 
+#[cfg(feature = "legacy")]
 pub mod command;
+#[cfg(feature = "legacy")]
+pub mod legacy;
+#[cfg(feature = "legacy")]
 pub use command::Command;
+#[cfg(feature = "legacy")]
+pub use legacy::LegacyAdapter;
 
 // Re-export Db for backward compatibility, but mark as deprecated
+#[cfg(feature = "legacy")]
 #[doc(hidden)]
 pub mod database {
     //! Legacy database module - use `Cache` instead.
     pub use crate::storage::Db;
 }
+#[cfg(feature = "legacy")]
 #[doc(hidden)]
 pub use storage::Db;
 
+#[cfg(feature = "cli")]
 pub mod cli;
-pub use cli::{Cli, ClientCommand};
+#[cfg(feature = "cli")]
+pub use cli::{Cli, ClientCommand, ServerCli};
+
+#[cfg(feature = "server")]
+pub mod server;
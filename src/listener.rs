@@ -0,0 +1,54 @@
+//! Removal notifications.
+//!
+//! An eviction listener is a user callback that is told whenever an entry
+//! leaves the cache, together with the value it held and the reason it was
+//! removed. Removals are collected while the storage lock is held and
+//! delivered only after it has been released, so a listener may safely call
+//! back into the cache.
+
+use bytes::Bytes;
+use std::sync::Arc;
+
+/// Why an entry was removed from the cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum RemovalCause {
+    /// The entry's TTL elapsed. Delivered from lazy expiration in
+    /// `get`/`contains` as well as from `cleanup_expired`.
+    Expired,
+
+    /// The entry was evicted to make room under the capacity, memory or
+    /// weight limit.
+    Evicted,
+
+    /// The entry was removed explicitly: by `delete`, `delete_many`,
+    /// `delete_prefix`, `take`, `retain`, or a transaction that deleted it.
+    Deleted,
+
+    /// The entry was overwritten by a new value for the same key. The old
+    /// value is delivered. Overwriting an entry that had already expired is
+    /// reported as `Expired` instead.
+    Replaced,
+
+    /// The entry was removed by `clear`. Delivered after the lock has been
+    /// released, from the thread that drops the old entries.
+    Cleared,
+
+    /// The entry was removed by `purge_idle` because it had not been read
+    /// for longer than the requested duration. Counted as an eviction.
+    Idle,
+}
+
+/// Callback invoked with the key, the removed value, and the removal cause.
+///
+/// The listener runs outside the storage lock. A panic inside it is caught,
+/// counted in the `callback_panics` statistic, and otherwise ignored.
+pub type EvictionListener = Arc<dyn Fn(&str, Bytes, RemovalCause) + Send + Sync>;
+
+/// A removal captured under the lock, waiting to be delivered.
+#[derive(Debug)]
+pub(crate) struct Removal {
+    pub(crate) key: String,
+    pub(crate) value: Bytes,
+    pub(crate) cause: RemovalCause,
+}
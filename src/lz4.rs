@@ -0,0 +1,241 @@
+//! LZ4 block compression for replies, see [`Compression::Lz4`].
+//!
+//! Writes and reads the plain LZ4 block format (no frame header or
+//! checksum), so any LZ4 block decoder can read what [`compress`] writes.
+//! Single-probe hash table and dependency free; it favours speed over
+//! ratio.
+//!
+//! [`Compression::Lz4`]: crate::protocol::Compression::Lz4
+
+/// Shortest match worth encoding.
+const MIN_MATCH: usize = 4;
+
+/// The last bytes of a block are always literals.
+const LAST_LITERALS: usize = 5;
+
+/// No match may start within this many bytes of the end of a block.
+const MF_LIMIT: usize = 12;
+
+/// Farthest back a match can reach.
+const MAX_OFFSET: usize = u16::MAX as usize;
+
+const HASH_LOG: u32 = 12;
+
+/// Compress `input` into one LZ4 block.
+pub(crate) fn compress(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len() / 2 + 16);
+    // Position of the last sequence with each hash, plus one; 0 is empty
+    let mut table = vec![0usize; 1 << HASH_LOG];
+    let mut anchor = 0;
+    let mut pos = 0;
+
+    if input.len() > MF_LIMIT {
+        let match_limit = input.len() - MF_LIMIT;
+        let end_limit = input.len() - LAST_LITERALS;
+        while pos < match_limit {
+            let sequence = read_u32(input, pos);
+            let slot = hash(sequence);
+            let candidate = table[slot].checked_sub(1);
+            table[slot] = pos + 1;
+
+            let candidate = match candidate {
+                Some(c) if pos - c <= MAX_OFFSET && read_u32(input, c) == sequence => c,
+                _ => {
+                    pos += 1;
+                    continue;
+                }
+            };
+            let mut len = MIN_MATCH;
+            while pos + len < end_limit && input[candidate + len] == input[pos + len] {
+                len += 1;
+            }
+            push_sequence(&mut out, &input[anchor..pos], Some((pos - candidate, len)));
+            pos += len;
+            anchor = pos;
+        }
+    }
+    push_sequence(&mut out, &input[anchor..], None);
+    out
+}
+
+/// Decompress one LZ4 block that holds exactly `raw_len` bytes.
+///
+/// Returns `None` if the block is malformed or its size does not match.
+pub(crate) fn decompress(input: &[u8], raw_len: usize) -> Option<Vec<u8>> {
+    // Each input byte expands to at most 255, so a lying size cannot
+    // reserve unbounded memory
+    let mut out = Vec::with_capacity(raw_len.min(input.len().saturating_mul(255)));
+    let mut pos = 0;
+
+    loop {
+        let token = *input.get(pos)?;
+        pos += 1;
+
+        let mut literals = usize::from(token >> 4);
+        if literals == 15 {
+            literals = literals.checked_add(read_length(input, &mut pos)?)?;
+        }
+        let end = pos.checked_add(literals)?;
+        if out.len() + literals > raw_len {
+            return None;
+        }
+        out.extend_from_slice(input.get(pos..end)?);
+        pos = end;
+        if pos == input.len() {
+            break;
+        }
+
+        let offset = usize::from(u16::from_le_bytes([*input.get(pos)?, *input.get(pos + 1)?]));
+        pos += 2;
+        if offset == 0 || offset > out.len() {
+            return None;
+        }
+        let mut len = usize::from(token & 0x0F) + MIN_MATCH;
+        if token & 0x0F == 15 {
+            len = len.checked_add(read_length(input, &mut pos)?)?;
+        }
+        if out.len() + len > raw_len {
+            return None;
+        }
+        // Byte by byte, as a match may overlap the bytes it produces
+        let start = out.len() - offset;
+        for i in 0..len {
+            out.push(out[start + i]);
+        }
+    }
+
+    (out.len() == raw_len).then_some(out)
+}
+
+/// Append a sequence: `literals`, then an optional `(offset, len)` match.
+fn push_sequence(out: &mut Vec<u8>, literals: &[u8], matched: Option<(usize, usize)>) {
+    let match_code = matched.map_or(0, |(_, len)| len - MIN_MATCH);
+    let token = (literals.len().min(15) << 4) as u8 | match_code.min(15) as u8;
+    out.push(token);
+    if literals.len() >= 15 {
+        push_length(out, literals.len() - 15);
+    }
+    out.extend_from_slice(literals);
+
+    if let Some((offset, _)) = matched {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        if match_code >= 15 {
+            push_length(out, match_code - 15);
+        }
+    }
+}
+
+/// Append the continuation bytes of a length that did not fit its nibble.
+fn push_length(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        out.push(255);
+        len -= 255;
+    }
+    out.push(len as u8);
+}
+
+/// Read the continuation bytes of a length, advancing `pos` past them.
+fn read_length(input: &[u8], pos: &mut usize) -> Option<usize> {
+    let mut len = 0usize;
+    loop {
+        let byte = *input.get(*pos)?;
+        *pos += 1;
+        len = len.checked_add(usize::from(byte))?;
+        if byte != 255 {
+            return Some(len);
+        }
+    }
+}
+
+fn read_u32(input: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([input[pos], input[pos + 1], input[pos + 2], input[pos + 3]])
+}
+
+fn hash(sequence: u32) -> usize {
+    (sequence.wrapping_mul(2_654_435_761) >> (32 - HASH_LOG)) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(input: &[u8]) -> Vec<u8> {
+        let compressed = compress(input);
+        assert_eq!(decompress(&compressed, input.len()).as_deref(), Some(input));
+        compressed
+    }
+
+    /// Bytes from a xorshift generator, which LZ4 cannot shrink.
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip_tiny() {
+        for input in [&b""[..], b"a", b"abcd", b"abcdabcdabcd", b"abcdabcdabcda"] {
+            round_trip(input);
+        }
+    }
+
+    #[test]
+    fn test_round_trip_compressible() {
+        let input = "user:1234 logged in from 10.0.0.1\n".repeat(200);
+        let compressed = round_trip(input.as_bytes());
+        assert!(compressed.len() < input.len() / 10, "{}", compressed.len());
+
+        // Long runs overlap their own output and need length continuations
+        let compressed = round_trip(&[b'x'; 70_000]);
+        assert!(compressed.len() < 400, "{}", compressed.len());
+    }
+
+    #[test]
+    fn test_round_trip_incompressible() {
+        let input = noise(5_000);
+        let compressed = round_trip(&input);
+        // Literals only: a token and length bytes on top of the input
+        assert!(compressed.len() <= input.len() + input.len() / 255 + 16);
+    }
+
+    #[test]
+    fn test_round_trip_mixed() {
+        let mut input = noise(300);
+        input.extend_from_slice(&"abc".repeat(100).into_bytes());
+        input.extend_from_slice(&noise(100_000));
+        input.extend_from_within(..300);
+        round_trip(&input);
+    }
+
+    #[test]
+    fn test_decompress_reference_block() {
+        // "abc", then 9 bytes from 3 back, then the literals "abcab"
+        let block = [
+            0x35, b'a', b'b', b'c', 3, 0, 0x50, b'a', b'b', b'c', b'a', b'b',
+        ];
+        assert_eq!(
+            decompress(&block, 17).as_deref(),
+            Some(&b"abcabcabcabcabcab"[..])
+        );
+    }
+
+    #[test]
+    fn test_decompress_rejects_malformed_blocks() {
+        let block = compress(&"hello hello hello hello hello".repeat(4).into_bytes());
+        // Wrong size either way
+        assert_eq!(decompress(&block, 115), None);
+        assert_eq!(decompress(&block, 117), None);
+        // Truncated
+        assert_eq!(decompress(&block[..block.len() - 1], 116), None);
+        assert_eq!(decompress(&[], 0), None);
+        // A match reaching before the start of the output
+        assert_eq!(decompress(&[0x10, b'a', 2, 0, 0x00], 6), None);
+        assert_eq!(decompress(&[0x10, b'a', 0, 0, 0x00], 5), None);
+    }
+}
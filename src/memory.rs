@@ -0,0 +1,190 @@
+//! Sampling-based memory breakdown by key prefix.
+//!
+//! [`Cache::memory_breakdown`](crate::Cache::memory_breakdown) samples stored
+//! entries, groups them by key prefix and extrapolates counts and bytes to
+//! the whole cache, answering "which key patterns use the memory?" without
+//! walking every entry.
+//!
+//! Sampling is deterministic: entries are picked at evenly spaced positions
+//! in storage order, so the same cache contents always produce the same
+//! report.
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// Estimated memory use of one key prefix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefixGroup {
+    /// The prefix, including its trailing separator (empty for keys without
+    /// one).
+    pub prefix: String,
+    /// Sampled entries in this group.
+    pub sampled: usize,
+    /// Estimated number of entries in the whole cache.
+    pub estimated_count: usize,
+    /// Estimated key and value bytes in the whole cache.
+    pub estimated_bytes: usize,
+}
+
+/// Result of a sampled memory breakdown, largest groups first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryBreakdown {
+    /// Entries stored when the sample was taken.
+    pub total_entries: usize,
+    /// Entries actually inspected.
+    pub sampled: usize,
+    /// Groups sorted by estimated bytes, largest first.
+    pub groups: Vec<PrefixGroup>,
+}
+
+impl MemoryBreakdown {
+    /// Group sampled `(key, bytes)` pairs and scale them to `total_entries`.
+    pub(crate) fn from_sample(
+        total_entries: usize,
+        sample: Vec<(String, usize)>,
+        prefix_depth: usize,
+    ) -> Self {
+        let sampled = sample.len();
+        let mut groups: HashMap<String, (usize, usize)> = HashMap::new();
+        for (key, bytes) in &sample {
+            let group = groups.entry(prefix_of(key, prefix_depth)).or_default();
+            group.0 += 1;
+            group.1 += bytes;
+        }
+
+        let scale = |n: usize| {
+            if sampled == 0 {
+                0
+            } else {
+                ((n as u128 * total_entries as u128) / sampled as u128) as usize
+            }
+        };
+        let mut groups: Vec<PrefixGroup> = groups
+            .into_iter()
+            .map(|(prefix, (count, bytes))| PrefixGroup {
+                prefix,
+                sampled: count,
+                estimated_count: scale(count),
+                estimated_bytes: scale(bytes),
+            })
+            .collect();
+        groups.sort_by(|a, b| {
+            b.estimated_bytes
+                .cmp(&a.estimated_bytes)
+                .then_with(|| a.prefix.cmp(&b.prefix))
+        });
+
+        Self {
+            total_entries,
+            sampled,
+            groups,
+        }
+    }
+}
+
+/// Renders an aligned text table.
+impl fmt::Display for MemoryBreakdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self
+            .groups
+            .iter()
+            .map(|g| display_prefix(&g.prefix).len())
+            .chain(std::iter::once("prefix".len()))
+            .max()
+            .unwrap_or(0);
+
+        writeln!(
+            f,
+            "sampled {} of {} entries",
+            self.sampled, self.total_entries
+        )?;
+        write!(
+            f,
+            "{:<width$}  {:>10}  {:>12}",
+            "prefix",
+            "entries",
+            "bytes",
+            width = width
+        )?;
+        for group in &self.groups {
+            write!(
+                f,
+                "\n{:<width$}  {:>10}  {:>12}",
+                display_prefix(&group.prefix),
+                group.estimated_count,
+                group.estimated_bytes,
+                width = width
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn display_prefix(prefix: &str) -> &str {
+    if prefix.is_empty() {
+        "(none)"
+    } else {
+        prefix
+    }
+}
+
+/// The key up to and including its `depth`-th `:` separator, or up to its
+/// last separator if it has fewer.
+fn prefix_of(key: &str, depth: usize) -> String {
+    let end = key
+        .match_indices(':')
+        .take(depth)
+        .last()
+        .map(|(idx, _)| idx + 1)
+        .unwrap_or(0);
+    key[..end].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefix_of() {
+        assert_eq!(prefix_of("user:1:profile", 1), "user:");
+        assert_eq!(prefix_of("user:1:profile", 2), "user:1:");
+        assert_eq!(prefix_of("user:1", 5), "user:");
+        assert_eq!(prefix_of("plain", 1), "");
+        assert_eq!(prefix_of("user:1", 0), "");
+    }
+
+    #[test]
+    fn test_extrapolation_and_order() {
+        let sample = vec![
+            ("user:1".to_string(), 10),
+            ("user:2".to_string(), 10),
+            ("session:1".to_string(), 100),
+            ("plain".to_string(), 1),
+        ];
+        let breakdown = MemoryBreakdown::from_sample(40, sample, 1);
+
+        assert_eq!(breakdown.sampled, 4);
+        let summary: Vec<(&str, usize, usize)> = breakdown
+            .groups
+            .iter()
+            .map(|g| (g.prefix.as_str(), g.estimated_count, g.estimated_bytes))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![("session:", 10, 1000), ("user:", 20, 200), ("", 10, 10)]
+        );
+    }
+
+    #[test]
+    fn test_display_is_aligned() {
+        let sample = vec![("user:1".to_string(), 7), ("k".to_string(), 2)];
+        let text = MemoryBreakdown::from_sample(2, sample, 1).to_string();
+        assert_eq!(
+            text,
+            "sampled 2 of 2 entries\n\
+             prefix     entries         bytes\n\
+             user:            1             7\n\
+             (none)           1             2"
+        );
+    }
+}
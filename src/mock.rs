@@ -0,0 +1,207 @@
+//! A scriptable fake cache for downstream tests (feature `test-util`).
+//!
+//! `MockCache` implements [`CacheOps`] on top of a plain map, records every
+//! call it receives, and lets a test script the result of specific lookups.
+//!
+//! ```
+//! use in_memory_cache::mock::{MockCache, MockCall};
+//! use in_memory_cache::CacheRead;
+//! use bytes::Bytes;
+//!
+//! let mock = MockCache::new();
+//! mock.script_get("user:1", Some(Bytes::from("Alice")));
+//!
+//! assert_eq!(mock.get("user:1"), Some(Bytes::from("Alice")));
+//! assert_eq!(mock.calls(), vec![MockCall::Get("user:1".to_string())]);
+//! ```
+//!
+//! [`CacheOps`]: crate::CacheOps
+
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::ops::{CacheRead, CacheWrite};
+use crate::stats::{CacheStats, StatsSnapshot};
+
+/// A call received by a [`MockCache`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MockCall {
+    Get(String),
+    Set {
+        key: String,
+        value: Bytes,
+        ttl: Option<Duration>,
+    },
+    Delete(String),
+    Contains(String),
+    Len,
+    Stats,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    data: HashMap<String, Bytes>,
+    scripted_gets: HashMap<String, Option<Bytes>>,
+    stats: Option<StatsSnapshot>,
+    calls: Vec<MockCall>,
+}
+
+/// A fake [`CacheOps`] implementation with call recording.
+///
+/// Without any scripting it behaves like an unbounded cache without TTLs
+/// (TTLs are recorded but not enforced).
+///
+/// [`CacheOps`]: crate::CacheOps
+#[derive(Debug, Default)]
+pub struct MockCache {
+    state: Mutex<MockState>,
+}
+
+impl MockCache {
+    /// Create an empty mock.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make `get(key)` return `response` regardless of stored data.
+    pub fn script_get(&self, key: impl Into<String>, response: Option<Bytes>) {
+        self.lock().scripted_gets.insert(key.into(), response);
+    }
+
+    /// Make `stats()` return `snapshot`.
+    pub fn script_stats(&self, snapshot: StatsSnapshot) {
+        self.lock().stats = Some(snapshot);
+    }
+
+    /// All calls received so far, in order.
+    pub fn calls(&self) -> Vec<MockCall> {
+        self.lock().calls.clone()
+    }
+
+    /// Forget recorded calls (scripted responses and data are kept).
+    pub fn clear_calls(&self) {
+        self.lock().calls.clear();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MockState> {
+        // A panicking test must not cascade into unrelated assertions
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn store(&self, key: &str, value: Bytes, ttl: Option<Duration>) {
+        let mut state = self.lock();
+        state.calls.push(MockCall::Set {
+            key: key.to_string(),
+            value: value.clone(),
+            ttl,
+        });
+        state.data.insert(key.to_string(), value);
+    }
+}
+
+impl CacheRead for MockCache {
+    fn get(&self, key: &str) -> Option<Bytes> {
+        let mut state = self.lock();
+        state.calls.push(MockCall::Get(key.to_string()));
+        match state.scripted_gets.get(key) {
+            Some(response) => response.clone(),
+            None => state.data.get(key).cloned(),
+        }
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        let mut state = self.lock();
+        state.calls.push(MockCall::Contains(key.to_string()));
+        state.data.contains_key(key)
+    }
+
+    fn len(&self) -> usize {
+        let mut state = self.lock();
+        state.calls.push(MockCall::Len);
+        state.data.len()
+    }
+
+    fn stats(&self) -> StatsSnapshot {
+        let mut state = self.lock();
+        state.calls.push(MockCall::Stats);
+        state
+            .stats
+            .clone()
+            .unwrap_or_else(|| CacheStats::new().snapshot())
+    }
+}
+
+impl CacheWrite for MockCache {
+    fn set(&self, key: &str, value: Bytes) {
+        self.store(key, value, None);
+    }
+
+    fn set_with_ttl(&self, key: &str, value: Bytes, ttl: Duration) {
+        self.store(key, value, Some(ttl));
+    }
+
+    fn delete(&self, key: &str) -> bool {
+        let mut state = self.lock();
+        state.calls.push(MockCall::Delete(key.to_string()));
+        state.data.remove(key).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_behaves_like_a_map() {
+        let mock = MockCache::new();
+        mock.set("a", Bytes::from("1"));
+        assert_eq!(mock.get("a"), Some(Bytes::from("1")));
+        assert!(mock.contains("a"));
+        assert_eq!(mock.len(), 1);
+        assert!(mock.delete("a"));
+        assert!(mock.is_empty());
+    }
+
+    #[test]
+    fn test_scripted_get_overrides_data() {
+        let mock = MockCache::new();
+        mock.set("a", Bytes::from("stored"));
+        mock.script_get("a", None);
+        assert_eq!(mock.get("a"), None);
+    }
+
+    #[test]
+    fn test_records_calls_in_order() {
+        let mock = MockCache::new();
+        mock.set_with_ttl("a", Bytes::from("1"), Duration::from_secs(5));
+        let _ = mock.get("a");
+        mock.delete("b");
+
+        assert_eq!(
+            mock.calls(),
+            vec![
+                MockCall::Set {
+                    key: "a".to_string(),
+                    value: Bytes::from("1"),
+                    ttl: Some(Duration::from_secs(5)),
+                },
+                MockCall::Get("a".to_string()),
+                MockCall::Delete("b".to_string()),
+            ]
+        );
+
+        mock.clear_calls();
+        assert!(mock.calls().is_empty());
+    }
+
+    #[test]
+    fn test_scripted_stats() {
+        let mock = MockCache::new();
+        let mut snapshot = CacheStats::new().snapshot();
+        snapshot.hits = 7;
+        mock.script_stats(snapshot);
+        assert_eq!(mock.stats().hits, 7);
+    }
+}
@@ -0,0 +1,191 @@
+//! The `CacheOps` trait: the core cache surface as an object-safe trait.
+//!
+//! Application code that depends on `CacheOps` instead of the concrete
+//! [`Cache`] type can substitute a fake in its own unit tests (see
+//! `MockCache` behind the `test-util` feature) or wrap the cache in
+//! decorators.
+//!
+//! `CacheOps` is the union of [`CacheRead`] and [`CacheWrite`] and is
+//! implemented for every type implementing both. Code that only reads can
+//! depend on `CacheRead` alone and accept a
+//! [`CacheReader`](crate::CacheReader) as well as a full cache.
+//!
+//! ```
+//! use in_memory_cache::{Cache, CacheOps};
+//! use std::sync::Arc;
+//!
+//! fn remember(cache: &dyn CacheOps, user: &str) {
+//!     cache.set(user, "seen".into());
+//! }
+//!
+//! let cache: Arc<dyn CacheOps> = Arc::new(Cache::default());
+//! remember(cache.as_ref(), "user:1");
+//! assert!(cache.contains("user:1"));
+//! ```
+
+use bytes::Bytes;
+use std::time::Duration;
+
+use crate::cache::Cache;
+use crate::error::CacheResult;
+use crate::stats::StatsSnapshot;
+
+/// What a write did to the cache, as reported by
+/// [`Cache::set_returning_outcome`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetOutcome {
+    /// The key had no live value and now holds the new one. Overwriting an
+    /// expired entry counts as an insert.
+    Inserted,
+    /// The key's live value was overwritten.
+    Replaced,
+    /// The key already held an identical value and the write was coalesced
+    /// (see [`CacheConfig::coalesce_identical_writes`]).
+    ///
+    /// [`CacheConfig::coalesce_identical_writes`]: crate::CacheConfig::coalesce_identical_writes
+    Unchanged,
+    /// Nothing was stored: the key or value exceeded a size limit (see
+    /// [`CacheConfig::max_key_length`]), or the value could not be
+    /// spilled.
+    ///
+    /// [`CacheConfig::max_key_length`]: crate::CacheConfig::max_key_length
+    Dropped,
+}
+
+/// Core cache operations: reads and writes.
+///
+/// The trait is object-safe, so `Arc<dyn CacheOps>` and `&dyn CacheOps` work.
+/// Method semantics are those of the corresponding [`Cache`] methods. It has
+/// no methods of its own; implement [`CacheRead`] and [`CacheWrite`] instead.
+pub trait CacheOps: CacheRead + CacheWrite {}
+
+impl<T: CacheRead + CacheWrite + ?Sized> CacheOps for T {}
+
+/// The read-only half of [`CacheOps`].
+pub trait CacheRead: Send + Sync {
+    /// Get a value. See [`Cache::get`].
+    fn get(&self, key: &str) -> Option<Bytes>;
+
+    /// Check whether a live key exists. See [`Cache::contains`].
+    fn contains(&self, key: &str) -> bool;
+
+    /// Number of entries. See [`Cache::len`].
+    fn len(&self) -> usize;
+
+    /// Whether the cache is empty. See [`Cache::is_empty`].
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Snapshot of the statistics. See [`Cache::stats`].
+    fn stats(&self) -> StatsSnapshot;
+
+    /// Fallible get, for backends that can fail (such as the fault-injecting
+    /// wrapper behind `test-util`). Defaults to `Ok(self.get(key))`.
+    fn try_get(&self, key: &str) -> CacheResult<Option<Bytes>> {
+        Ok(self.get(key))
+    }
+}
+
+/// The mutating half of [`CacheOps`].
+pub trait CacheWrite: Send + Sync {
+    /// Set a value using the default TTL. See [`Cache::set`].
+    fn set(&self, key: &str, value: Bytes);
+
+    /// Set a value with an explicit TTL. See [`Cache::set_with_ttl`].
+    fn set_with_ttl(&self, key: &str, value: Bytes, ttl: Duration);
+
+    /// Delete a key. See [`Cache::delete`].
+    fn delete(&self, key: &str) -> bool;
+
+    /// Fallible set. Defaults to [`CacheWrite::set`].
+    fn try_set(&self, key: &str, value: Bytes) -> CacheResult<()> {
+        self.set(key, value);
+        Ok(())
+    }
+
+    /// Fallible set with an explicit TTL. Defaults to
+    /// [`CacheWrite::set_with_ttl`].
+    fn try_set_with_ttl(&self, key: &str, value: Bytes, ttl: Duration) -> CacheResult<()> {
+        self.set_with_ttl(key, value, ttl);
+        Ok(())
+    }
+}
+
+impl CacheRead for Cache {
+    fn get(&self, key: &str) -> Option<Bytes> {
+        Cache::get(self, key)
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        Cache::contains(self, key)
+    }
+
+    fn len(&self) -> usize {
+        Cache::len(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        Cache::is_empty(self)
+    }
+
+    fn stats(&self) -> StatsSnapshot {
+        Cache::stats(self)
+    }
+
+    fn try_get(&self, key: &str) -> CacheResult<Option<Bytes>> {
+        Cache::try_get(self, key)
+    }
+}
+
+impl CacheWrite for Cache {
+    fn set(&self, key: &str, value: Bytes) {
+        Cache::set(self, key, value);
+    }
+
+    fn set_with_ttl(&self, key: &str, value: Bytes, ttl: Duration) {
+        Cache::set_with_ttl(self, key, value, ttl);
+    }
+
+    fn delete(&self, key: &str) -> bool {
+        Cache::delete(self, key)
+    }
+
+    fn try_set(&self, key: &str, value: Bytes) -> CacheResult<()> {
+        Cache::try_set(self, key, value)
+    }
+
+    fn try_set_with_ttl(&self, key: &str, value: Bytes, ttl: Duration) -> CacheResult<()> {
+        Cache::try_set_with_ttl(self, key, value, ttl)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn exercise(ops: &dyn CacheOps) {
+        assert!(ops.is_empty());
+        ops.set("key", Bytes::from("value"));
+        ops.set_with_ttl("session", Bytes::from("data"), Duration::from_secs(60));
+        assert_eq!(ops.get("key"), Some(Bytes::from("value")));
+        assert!(ops.contains("session"));
+        assert_eq!(ops.len(), 2);
+        assert!(ops.delete("key"));
+        assert_eq!(ops.stats().sets, 2);
+        assert!(ops.try_set("fallible", Bytes::from("value")).is_ok());
+        assert_eq!(ops.try_get("fallible").unwrap(), Some(Bytes::from("value")));
+    }
+
+    #[test]
+    fn test_cache_through_trait_object() {
+        let cache = Cache::default();
+        let ops: Arc<dyn CacheOps> = Arc::new(cache.clone());
+        exercise(ops.as_ref());
+
+        // The trait object shares data with the original handle
+        assert!(!cache.contains("key"));
+        assert!(cache.contains("session"));
+    }
+}
@@ -0,0 +1,131 @@
+//! Cached outcomes of fallible calls.
+//!
+//! [`Cache::set_outcome`](crate::Cache::set_outcome) stores either the
+//! success or the failure payload of an upstream call, each with its own
+//! TTL, and [`Cache::get_outcome`](crate::Cache::get_outcome) gives back
+//! the `Result`. Failures are usually cached briefly, so a struggling
+//! backend is not hammered but recovers quickly:
+//!
+//! ```
+//! use in_memory_cache::Cache;
+//! use bytes::Bytes;
+//! use std::time::Duration;
+//!
+//! let cache = Cache::default();
+//! let (ok_ttl, err_ttl) = (Duration::from_secs(300), Duration::from_secs(5));
+//! cache.set_outcome("price:1", Err(Bytes::from("upstream 503")), ok_ttl, err_ttl);
+//! assert_eq!(cache.get_outcome("price:1"), Some(Err(Bytes::from("upstream 503"))));
+//! assert_eq!(cache.stats().error_hits, 1);
+//! ```
+//!
+//! # Encoding
+//!
+//! An outcome is stored as an ordinary value: one byte holding the length
+//! of a tag, the tag itself, then the payload unchanged. The tag is
+//! `outcome:ok` for a success and `outcome:err` for a failure, so a success
+//! with payload `42` is stored as `\x0aoutcome:ok42`. Because the tag is
+//! length-prefixed, payloads may hold any bytes, including ones that look
+//! like a tag. A value that does not begin with either header is not an
+//! outcome, and `get_outcome` returns `None` for it.
+
+use bytes::{BufMut, Bytes, BytesMut};
+use std::fmt;
+
+const OK_TAG: &[u8] = b"outcome:ok";
+const ERR_TAG: &[u8] = b"outcome:err";
+
+/// Why [`Cache::get_or_try_insert_with`](crate::Cache::get_or_try_insert_with)
+/// produced no value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TryInsertError<E> {
+    /// The loader failed on this call.
+    Loader(E),
+    /// A failure cached by an earlier call, as its payload.
+    Cached(Bytes),
+}
+
+impl<E: fmt::Display> fmt::Display for TryInsertError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryInsertError::Loader(err) => write!(f, "{}", err),
+            TryInsertError::Cached(payload) => {
+                write!(f, "cached failure: {}", String::from_utf8_lossy(payload))
+            }
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for TryInsertError<E> {}
+
+/// Encode `outcome` as a stored value.
+pub(crate) fn encode(outcome: &Result<Bytes, Bytes>) -> Bytes {
+    let (tag, payload) = match outcome {
+        Ok(payload) => (OK_TAG, payload),
+        Err(payload) => (ERR_TAG, payload),
+    };
+    let mut buf = BytesMut::with_capacity(1 + tag.len() + payload.len());
+    buf.put_u8(tag.len() as u8);
+    buf.put_slice(tag);
+    buf.put_slice(payload);
+    buf.freeze()
+}
+
+/// Decode a stored value, or `None` if it is not an outcome.
+pub(crate) fn decode(value: &Bytes) -> Option<Result<Bytes, Bytes>> {
+    let len = usize::from(*value.first()?);
+    let tag = value.get(1..1 + len)?;
+    let payload = value.slice(1 + len..);
+    match tag {
+        OK_TAG => Some(Ok(payload)),
+        ERR_TAG => Some(Err(payload)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for outcome in [
+            Ok(Bytes::from("42")),
+            Err(Bytes::from("upstream 503")),
+            Ok(Bytes::new()),
+            Err(Bytes::new()),
+            // Payloads that look like headers themselves
+            Ok(encode(&Err(Bytes::from("nested")))),
+            Err(Bytes::from_static(b"\x0boutcome:err")),
+            Ok(Bytes::from_static(&[0xff, 0x00, 0x0a])),
+        ] {
+            assert_eq!(decode(&encode(&outcome)), Some(outcome));
+        }
+    }
+
+    #[test]
+    fn test_documented_layout() {
+        assert_eq!(
+            encode(&Ok(Bytes::from("42"))),
+            Bytes::from_static(b"\x0aoutcome:ok42")
+        );
+        assert_eq!(
+            encode(&Err(Bytes::from("x"))),
+            Bytes::from_static(b"\x0boutcome:errx")
+        );
+    }
+
+    #[test]
+    fn test_plain_values_are_not_outcomes() {
+        for value in [
+            &b""[..],
+            b"42",
+            b"outcome:ok42",
+            b"\x0aoutcome:no42",
+            b"\x0boutcome:ok",
+            b"\xffoutcome:ok",
+            b"\x0aoutcome",
+        ] {
+            assert_eq!(decode(&Bytes::copy_from_slice(value)), None, "{:?}", value);
+        }
+    }
+}
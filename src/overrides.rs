@@ -0,0 +1,372 @@
+//! Cache settings read from a config file or the environment.
+//!
+//! Both sources share one small schema:
+//!
+//! | key                     | type    | builder                                   |
+//! |-------------------------|---------|-------------------------------------------|
+//! | `max_capacity`          | integer | [`CacheConfig::max_capacity`]             |
+//! | `default_ttl_secs`      | integer | [`CacheConfig::default_ttl`]              |
+//! | `cleanup_interval_secs` | integer | [`CacheConfig::cleanup_interval`]         |
+//! | `background_cleanup`    | boolean | [`CacheConfig::background_cleanup`]       |
+//! | `max_memory_bytes`      | integer | [`CacheConfig::max_memory`]               |
+//!
+//! Files use the flat subset of TOML these keys need: one `key = value`
+//! per line, `#` comments, decimal integers (`_` separators allowed) and
+//! `true`/`false`. Tables, strings and arrays are rejected, as are unknown
+//! and repeated keys, so a typo cannot silently leave a default in place.
+//!
+//! Environment variables are the keys upper-cased behind a prefix, such as
+//! `CACHE_MAX_CAPACITY` for the prefix `CACHE`. Booleans there also accept
+//! `1`/`0`, `yes`/`no` and `on`/`off`.
+
+use std::path::Path;
+use std::time::Duration;
+
+use crate::config::CacheConfig;
+use crate::error::{CacheError, CacheResult};
+
+/// The settings a file or environment names. A `None` field was not
+/// mentioned and keeps whatever the configuration already had.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct ConfigOverrides {
+    /// 0 means unlimited.
+    pub(crate) max_capacity: Option<usize>,
+    /// Zero disables the default TTL.
+    pub(crate) default_ttl: Option<Duration>,
+    /// Zero disables the interval.
+    pub(crate) cleanup_interval: Option<Duration>,
+    pub(crate) background_cleanup: Option<bool>,
+    /// 0 means unlimited.
+    pub(crate) max_memory: Option<u64>,
+}
+
+/// The value type of a schema key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Integer,
+    Boolean,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Value {
+    Integer(u64),
+    Boolean(bool),
+}
+
+const KEYS: [(&str, Kind); 5] = [
+    ("max_capacity", Kind::Integer),
+    ("default_ttl_secs", Kind::Integer),
+    ("cleanup_interval_secs", Kind::Integer),
+    ("background_cleanup", Kind::Boolean),
+    ("max_memory_bytes", Kind::Integer),
+];
+
+fn kind_of(key: &str) -> Option<Kind> {
+    KEYS.iter()
+        .find(|(name, _)| *name == key)
+        .map(|&(_, kind)| kind)
+}
+
+impl ConfigOverrides {
+    /// Read the settings of a config file.
+    pub(crate) fn from_file(path: &Path) -> CacheResult<Self> {
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            CacheError::InvalidConfig(format!("cannot read {}: {}", path.display(), e))
+        })?;
+        Self::parse(&text).map_err(|(line, reason)| {
+            CacheError::InvalidConfig(format!("{}:{}: {}", path.display(), line, reason))
+        })
+    }
+
+    /// Parse the text of a config file. Errors carry the 1-based line.
+    fn parse(text: &str) -> Result<Self, (usize, String)> {
+        let mut overrides = Self::default();
+        let mut seen: Vec<&str> = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let fail = |reason: String| (index + 1, reason);
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('[') {
+                return Err(fail("tables are not supported".to_string()));
+            }
+            let (key, raw) = line
+                .split_once('=')
+                .ok_or_else(|| fail(format!("expected `key = value`, found `{}`", line)))?;
+            let (key, raw) = (key.trim(), raw.trim());
+            let kind = kind_of(key).ok_or_else(|| fail(format!("unknown key `{}`", key)))?;
+            if seen.contains(&key) {
+                return Err(fail(format!("duplicate key `{}`", key)));
+            }
+            seen.push(key);
+            let value = match kind {
+                Kind::Integer => parse_integer(raw).map(Value::Integer),
+                Kind::Boolean => match raw {
+                    "true" => Some(Value::Boolean(true)),
+                    "false" => Some(Value::Boolean(false)),
+                    _ => None,
+                },
+            }
+            .ok_or_else(|| fail(expected(key, kind, raw)))?;
+            overrides.set(key, value);
+        }
+        Ok(overrides)
+    }
+
+    /// Read the settings of the variables starting with `{prefix}_`.
+    pub(crate) fn from_env(prefix: &str) -> CacheResult<Self> {
+        Self::from_vars(prefix, std::env::vars())
+    }
+
+    /// Read the settings among `vars` starting with `{prefix}_`; the
+    /// others are ignored.
+    fn from_vars(
+        prefix: &str,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> CacheResult<Self> {
+        let prefix = format!("{}_", prefix);
+        let mut overrides = Self::default();
+        for (name, raw) in vars {
+            let Some(suffix) = name.strip_prefix(&prefix) else {
+                continue;
+            };
+            let key = suffix.to_ascii_lowercase();
+            let kind = kind_of(&key).ok_or_else(|| {
+                CacheError::InvalidConfig(format!("unknown variable {} (environment)", name))
+            })?;
+            let trimmed = raw.trim();
+            let value = match kind {
+                Kind::Integer => parse_integer(trimmed).map(Value::Integer),
+                Kind::Boolean => parse_bool(trimmed).map(Value::Boolean),
+            }
+            .ok_or_else(|| {
+                CacheError::InvalidConfig(format!(
+                    "invalid value '{}' for {} (environment): {}",
+                    raw,
+                    name,
+                    expected(&key, kind, trimmed)
+                ))
+            })?;
+            overrides.set(&key, value);
+        }
+        Ok(overrides)
+    }
+
+    fn set(&mut self, key: &str, value: Value) {
+        match (key, value) {
+            ("max_capacity", Value::Integer(n)) => {
+                self.max_capacity = Some(usize::try_from(n).unwrap_or(usize::MAX))
+            }
+            ("default_ttl_secs", Value::Integer(secs)) => {
+                self.default_ttl = Some(Duration::from_secs(secs))
+            }
+            ("cleanup_interval_secs", Value::Integer(secs)) => {
+                self.cleanup_interval = Some(Duration::from_secs(secs))
+            }
+            ("background_cleanup", Value::Boolean(enabled)) => {
+                self.background_cleanup = Some(enabled)
+            }
+            ("max_memory_bytes", Value::Integer(bytes)) => self.max_memory = Some(bytes),
+            _ => unreachable!("value of {} checked against its kind", key),
+        }
+    }
+
+    /// Apply the named settings to `config`, leaving the others alone.
+    pub(crate) fn apply(&self, mut config: CacheConfig) -> CacheConfig {
+        if let Some(capacity) = self.max_capacity {
+            config = config.max_capacity(capacity);
+        }
+        if let Some(ttl) = self.default_ttl {
+            config = config.default_ttl(ttl);
+        }
+        if let Some(interval) = self.cleanup_interval {
+            config = config.cleanup_interval(interval);
+        }
+        if let Some(enabled) = self.background_cleanup {
+            config = config.background_cleanup(enabled);
+        }
+        if let Some(bytes) = self.max_memory {
+            config = config.max_memory(bytes);
+        }
+        config
+    }
+}
+
+/// `line` up to a `#` comment. The schema has no strings, so a `#` always
+/// starts one.
+fn strip_comment(line: &str) -> &str {
+    line.split_once('#').map_or(line, |(before, _)| before)
+}
+
+/// A non-negative decimal integer, with `_` allowed between digits.
+fn parse_integer(raw: &str) -> Option<u64> {
+    let digits = raw.as_bytes();
+    let well_formed = !digits.is_empty()
+        && digits[0].is_ascii_digit()
+        && digits[digits.len() - 1].is_ascii_digit()
+        && !raw.contains("__");
+    if !well_formed {
+        return None;
+    }
+    raw.replace('_', "").parse().ok()
+}
+
+fn parse_bool(raw: &str) -> Option<bool> {
+    match raw.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+fn expected(key: &str, kind: Kind, raw: &str) -> String {
+    match kind {
+        Kind::Integer => format!(
+            "expected a non-negative integer for {}, found `{}`",
+            key, raw
+        ),
+        Kind::Boolean => format!("expected true or false for {}, found `{}`", key, raw),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_full_file() {
+        let overrides = ConfigOverrides::parse(
+            "# cache tuning\n\
+             max_capacity = 50_000\n\
+             default_ttl_secs = 300   # five minutes\n\
+             \n\
+             cleanup_interval_secs = 30\n\
+             background_cleanup = true\n\
+             max_memory_bytes = 1048576\n",
+        )
+        .unwrap();
+        assert_eq!(
+            overrides,
+            ConfigOverrides {
+                max_capacity: Some(50_000),
+                default_ttl: Some(Duration::from_secs(300)),
+                cleanup_interval: Some(Duration::from_secs(30)),
+                background_cleanup: Some(true),
+                max_memory: Some(1 << 20),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_partial_file_keeps_other_settings() {
+        let overrides = ConfigOverrides::parse("default_ttl_secs = 60\n").unwrap();
+        let config = overrides.apply(
+            CacheConfig::new()
+                .max_capacity(10)
+                .max_memory(4096)
+                .default_ttl(Duration::from_secs(5)),
+        );
+        assert_eq!(config.get_max_capacity(), Some(10));
+        assert_eq!(config.get_max_memory(), Some(4096));
+        assert_eq!(config.get_default_ttl(), Some(Duration::from_secs(60)));
+
+        let config = ConfigOverrides::parse("max_capacity = 0")
+            .unwrap()
+            .apply(CacheConfig::new().max_capacity(10));
+        assert_eq!(config.get_max_capacity(), None);
+    }
+
+    #[test]
+    fn test_parse_malformed_file() {
+        let cases = [
+            ("max_capacity 10", 1, "expected `key = value`"),
+            ("\nmax_capcity = 10", 2, "unknown key `max_capcity`"),
+            ("max_capacity = -1", 1, "non-negative integer"),
+            ("max_capacity = \"10\"", 1, "non-negative integer"),
+            ("max_capacity = 1__0", 1, "non-negative integer"),
+            ("max_capacity =", 1, "non-negative integer"),
+            ("background_cleanup = yes", 1, "expected true or false"),
+            ("[cache]\nmax_capacity = 1", 1, "tables are not supported"),
+            (
+                "max_capacity = 1\nmax_capacity = 2",
+                2,
+                "duplicate key `max_capacity`",
+            ),
+        ];
+        for (text, line, reason) in cases {
+            let (at, message) = ConfigOverrides::parse(text).unwrap_err();
+            assert_eq!(at, line, "{:?}", text);
+            assert!(message.contains(reason), "{:?}: {}", text, message);
+        }
+    }
+
+    #[test]
+    fn test_from_file_names_path_and_line() {
+        let path = std::env::temp_dir().join(format!(
+            "in-memory-cache-overrides-{}.toml",
+            std::process::id()
+        ));
+        std::fs::write(&path, "max_capacity = 5\nbogus = 1\n").unwrap();
+        let err = ConfigOverrides::from_file(&path).unwrap_err().to_string();
+        assert!(
+            err.contains(&format!("{}:2: unknown key `bogus`", path.display())),
+            "{}",
+            err
+        );
+
+        std::fs::write(&path, "max_capacity = 5\n").unwrap();
+        let overrides = ConfigOverrides::from_file(&path).unwrap();
+        assert_eq!(overrides.max_capacity, Some(5));
+        std::fs::remove_file(&path).unwrap();
+
+        let err = ConfigOverrides::from_file(&path).unwrap_err().to_string();
+        assert!(err.contains("cannot read"), "{}", err);
+    }
+
+    #[test]
+    fn test_from_vars() {
+        let overrides = ConfigOverrides::from_vars(
+            "APP_CACHE",
+            vars(&[
+                ("APP_CACHE_MAX_CAPACITY", "100"),
+                ("APP_CACHE_BACKGROUND_CLEANUP", "on"),
+                ("APP_OTHER", "ignored"),
+                ("PATH", "/usr/bin"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            overrides,
+            ConfigOverrides {
+                max_capacity: Some(100),
+                background_cleanup: Some(true),
+                ..ConfigOverrides::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_from_vars_rejects_unknown_and_invalid() {
+        let err = ConfigOverrides::from_vars("APP", vars(&[("APP_MAX_ENTRIES", "1")]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("unknown variable APP_MAX_ENTRIES"), "{}", err);
+
+        let err = ConfigOverrides::from_vars("APP", vars(&[("APP_DEFAULT_TTL_SECS", "5m")]))
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("invalid value '5m' for APP_DEFAULT_TTL_SECS (environment)"),
+            "{}",
+            err
+        );
+    }
+}
@@ -0,0 +1,21 @@
+//! The supported cache API in one import.
+//!
+//! ```
+//! use in_memory_cache::prelude::*;
+//!
+//! fn warm(cache: &dyn CacheOps) -> CacheResult<()> {
+//!     cache.try_set("greeting", "hello".into())
+//! }
+//!
+//! let cache = Cache::new(CacheConfig::new());
+//! warm(&cache).unwrap();
+//! ```
+//!
+//! Legacy protocol helpers, the command-line definitions and the server
+//! building blocks are deliberately left out.
+
+pub use crate::cache::Cache;
+pub use crate::config::CacheConfig;
+pub use crate::error::{CacheError, CacheResult};
+pub use crate::ops::{CacheOps, CacheRead, CacheWrite};
+pub use crate::stats::StatsSnapshot;
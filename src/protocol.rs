@@ -0,0 +1,1173 @@
+//! Wire encoding shared by the server and the client.
+//!
+//! A connection carries any number of requests, one per line: each ends
+//! with `\n` (a `\r` before it is ignored), and the server answers them in
+//! order. [`RequestFramer`] collects the lines however the bytes arrive.
+//!
+//! A request line is a command followed by its arguments, separated by
+//! spaces. A word containing spaces, or an empty one, is written in double
+//! quotes, with `\"` and `\\` standing for a quote and a backslash inside
+//! them: `set greeting "say \"hello world\""`. [`quote`] produces this
+//! form. Words cannot contain line breaks.
+//!
+//! Each reply is framed by [`encode_reply`]: a reply that fits on one line
+//! is sent followed by `\n`, and any other as `$<len>\n`, the reply bytes
+//! and `\n`, so replies spanning several lines such as `info` can be told
+//! apart. [`decode_reply`] undoes this.
+//!
+//! Most responses are short text lines, but multi-value responses such as
+//! `mget` must stay binary-safe: values may contain spaces, newlines or
+//! arbitrary bytes. Those use a multi-bulk format:
+//!
+//! ```text
+//! *<count>\r\n
+//! $<len>\r\n<len bytes>\r\n     (one per present value)
+//! $-1\r\n                        (one per missing value)
+//! ```
+//!
+//! Values appear in request order, so a reply can be zipped with the keys
+//! that were asked for.
+//!
+//! Any request may start with an optional trace id, `*id=<token> get key`.
+//! The server echoes the same `*id=<token> ` prefix in front of its reply so
+//! client and server logs can be correlated. Tokens are 1 to 64 characters
+//! from `[A-Za-z0-9_.-]`.
+//!
+//! `hello compression=<codec>[,<codec>...]` negotiates reply compression.
+//! The server answers `hello compression=<codec> version=<version>
+//! protocol=<n>` with the first offered codec it supports, falling back to
+//! `none`, followed by its crate version and
+//! [`PROTOCOL_VERSION`](crate::PROTOCOL_VERSION).
+//!
+//! With `lz4` negotiated, the server compresses each later reply of at
+//! least [`COMPRESSION_THRESHOLD`] bytes that LZ4 actually shrinks, and
+//! frames it as `$<len> lz4=<raw len>\n`, the compressed bytes and `\n`.
+//! [`decode_reply`] decompresses such frames, so readers see the original
+//! reply either way.
+//!
+//! A request that fails is answered with `ERR <code> <detail>`, where the
+//! code classifies the failure (see [`ProtocolError::code`]) and the detail
+//! describes it. [`ProtocolError::from_wire`] turns such a reply back into
+//! a [`ProtocolError`].
+
+use bytes::{BufMut, Bytes, BytesMut};
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::CacheError;
+
+const CRLF: &[u8] = b"\r\n";
+
+/// Marker that introduces a trace id.
+pub const TRACE_PREFIX: &str = "*id=";
+
+/// Marker that introduces a length-prefixed reply.
+const LENGTH_PREFIX: u8 = b'$';
+
+/// Replies shorter than this are never compressed.
+pub const COMPRESSION_THRESHOLD: usize = 256;
+
+/// Marker that introduces an error reply.
+pub const ERROR_PREFIX: &str = "ERR ";
+
+const MAX_TRACE_ID_LEN: usize = 64;
+
+/// A failure at the wire protocol layer, as opposed to a failure of the
+/// cache itself ([`CacheError`]).
+///
+/// Every variant has a wire code; the server replies with
+/// [`ProtocolError::to_wire`] and the client recovers the error with
+/// [`ProtocolError::from_wire`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    /// The request or reply is not well-formed: an empty request, an
+    /// invalid trace id or a malformed multi-bulk frame.
+    Framing(String),
+
+    /// The command name is not known to the server.
+    UnknownCommand(String),
+
+    /// The command got fewer arguments than it needs. `expected` is the
+    /// minimum number of arguments after the command name.
+    WrongArity {
+        cmd: String,
+        expected: usize,
+        got: usize,
+    },
+
+    /// An argument has the wrong form or is out of range.
+    InvalidArgument(String),
+
+    /// The request or an argument exceeds a server limit.
+    TooLarge(String),
+
+    /// The command is not permitted on this server.
+    Unauthorized(String),
+
+    /// The client exceeded a rate limit; retrying after `retry_after` will
+    /// succeed unless the limit is hit again.
+    RateLimited { retry_after: Duration },
+
+    /// The request was valid but the cache operation failed. Error replies
+    /// that carry no known code are also classified here.
+    Cache(String),
+}
+
+/// A specialized Result type for wire protocol operations.
+pub type ProtocolResult<T> = Result<T, ProtocolError>;
+
+impl ProtocolError {
+    /// The wire code of this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ProtocolError::Framing(_) => "FRAMING",
+            ProtocolError::UnknownCommand(_) => "UNKNOWN",
+            ProtocolError::WrongArity { .. } => "ARITY",
+            ProtocolError::InvalidArgument(_) => "INVALID",
+            ProtocolError::TooLarge(_) => "TOOLARGE",
+            ProtocolError::Unauthorized(_) => "NOAUTH",
+            ProtocolError::RateLimited { .. } => "RATELIMITED",
+            ProtocolError::Cache(_) => "CACHE",
+        }
+    }
+
+    /// The error reply, `ERR <code> <detail>`.
+    pub fn to_wire(&self) -> Bytes {
+        let detail = match self {
+            ProtocolError::RateLimited { retry_after } => {
+                format!("retry_after_ms={}", ceil_millis(*retry_after))
+            }
+            other => other.to_string(),
+        };
+        format!("{}{} {}", ERROR_PREFIX, self.code(), detail).into()
+    }
+
+    /// The error carried by an error reply, or `None` if `reply` is not
+    /// one.
+    ///
+    /// Every reply starting with [`ERROR_PREFIX`] is classified: one whose
+    /// code is unknown or whose detail does not parse becomes
+    /// [`ProtocolError::Cache`] with the whole text after the prefix.
+    pub fn from_wire(reply: &[u8]) -> Option<ProtocolError> {
+        let text = String::from_utf8_lossy(reply.strip_prefix(ERROR_PREFIX.as_bytes())?);
+        let (code, detail) = text.split_once(' ').unwrap_or((&text, ""));
+        let parsed = match code {
+            "FRAMING" => Some(ProtocolError::Framing(detail.to_string())),
+            "UNKNOWN" => detail
+                .strip_prefix("unknown command '")
+                .and_then(|rest| rest.strip_suffix('\''))
+                .map(|name| ProtocolError::UnknownCommand(name.to_string())),
+            "ARITY" => parse_arity(detail),
+            "INVALID" => Some(ProtocolError::InvalidArgument(detail.to_string())),
+            "TOOLARGE" => Some(ProtocolError::TooLarge(detail.to_string())),
+            "NOAUTH" => Some(ProtocolError::Unauthorized(detail.to_string())),
+            "RATELIMITED" => detail
+                .strip_prefix("retry_after_ms=")
+                .and_then(|ms| ms.parse().ok())
+                .map(|ms| ProtocolError::RateLimited {
+                    retry_after: Duration::from_millis(ms),
+                }),
+            "CACHE" => Some(ProtocolError::Cache(detail.to_string())),
+            _ => None,
+        };
+        Some(parsed.unwrap_or_else(|| ProtocolError::Cache(text.into_owned())))
+    }
+}
+
+/// Parse the detail of an `ARITY` reply.
+fn parse_arity(detail: &str) -> Option<ProtocolError> {
+    let rest = detail.strip_prefix("wrong number of arguments for '")?;
+    let (cmd, rest) = rest.split_once("': expected at least ")?;
+    let (expected, got) = rest.split_once(", got ")?;
+    Some(ProtocolError::WrongArity {
+        cmd: cmd.to_string(),
+        expected: expected.parse().ok()?,
+        got: got.parse().ok()?,
+    })
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::Framing(msg)
+            | ProtocolError::InvalidArgument(msg)
+            | ProtocolError::TooLarge(msg)
+            | ProtocolError::Unauthorized(msg)
+            | ProtocolError::Cache(msg) => write!(f, "{}", msg),
+            ProtocolError::UnknownCommand(cmd) => write!(f, "unknown command '{}'", cmd),
+            ProtocolError::WrongArity { cmd, expected, got } => write!(
+                f,
+                "wrong number of arguments for '{}': expected at least {}, got {}",
+                cmd, expected, got
+            ),
+            ProtocolError::RateLimited { retry_after } => {
+                write!(
+                    f,
+                    "rate limited, retry after {}ms",
+                    ceil_millis(*retry_after)
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+/// Cache failures on their way to the wire. Parse and command errors keep
+/// their meaning; everything else is a [`ProtocolError::Cache`].
+impl From<CacheError> for ProtocolError {
+    fn from(err: CacheError) -> Self {
+        match err {
+            CacheError::ParseError(msg) => ProtocolError::InvalidArgument(msg),
+            CacheError::InvalidCommand(cmd) => ProtocolError::UnknownCommand(cmd),
+            other => ProtocolError::Cache(other.to_string()),
+        }
+    }
+}
+
+/// The [`CacheError`] a protocol error corresponds to. Authorization, rate
+/// limit and cache failures have none and are returned unchanged.
+impl TryFrom<ProtocolError> for CacheError {
+    type Error = ProtocolError;
+
+    fn try_from(err: ProtocolError) -> Result<Self, Self::Error> {
+        match err {
+            ProtocolError::Framing(_)
+            | ProtocolError::WrongArity { .. }
+            | ProtocolError::InvalidArgument(_) => Ok(CacheError::ParseError(err.to_string())),
+            ProtocolError::UnknownCommand(cmd) => Ok(CacheError::InvalidCommand(cmd)),
+            ProtocolError::TooLarge(msg) => Ok(CacheError::InvalidValue(msg)),
+            other => Err(other),
+        }
+    }
+}
+
+/// Whole milliseconds, rounded up so that waiting the advertised delay is
+/// always enough.
+pub(crate) fn ceil_millis(d: Duration) -> u128 {
+    (d.as_nanos() + 999_999) / 1_000_000
+}
+
+/// Split an optional leading trace id off tokenized request words.
+///
+/// Returns the id (if present) and the remaining command words. A word that
+/// starts with [`TRACE_PREFIX`] but carries an invalid token is rejected
+/// with [`ProtocolError::Framing`].
+pub fn split_trace_id(attrs: &[String]) -> ProtocolResult<(Option<&str>, &[String])> {
+    match attrs.split_first() {
+        Some((first, rest)) if first.starts_with(TRACE_PREFIX) => {
+            let id = &first[TRACE_PREFIX.len()..];
+            if !is_valid_trace_id(id) {
+                return Err(ProtocolError::Framing(format!("invalid trace id '{}'", id)));
+            }
+            Ok((Some(id), rest))
+        }
+        _ => Ok((None, attrs)),
+    }
+}
+
+/// Prepend `*id=<id> ` to `payload` when an id is given.
+pub fn prefix_trace_id(id: Option<&str>, payload: &[u8]) -> Bytes {
+    match id {
+        Some(id) => {
+            let mut buf =
+                BytesMut::with_capacity(TRACE_PREFIX.len() + id.len() + 1 + payload.len());
+            buf.put_slice(TRACE_PREFIX.as_bytes());
+            buf.put_slice(id.as_bytes());
+            buf.put_u8(b' ');
+            buf.put_slice(payload);
+            buf.freeze()
+        }
+        None => Bytes::copy_from_slice(payload),
+    }
+}
+
+/// Split a leading `*id=<id> ` echo off a reply.
+///
+/// Input without a well-formed prefix is returned unchanged.
+pub fn strip_trace_id(input: &[u8]) -> (Option<&str>, &[u8]) {
+    let rest = match input.strip_prefix(TRACE_PREFIX.as_bytes()) {
+        Some(rest) => rest,
+        None => return (None, input),
+    };
+    let end = rest.iter().position(|&b| b == b' ').unwrap_or(rest.len());
+    match std::str::from_utf8(&rest[..end]) {
+        Ok(id) if is_valid_trace_id(id) => (Some(id), &rest[(end + 1).min(rest.len())..]),
+        _ => (None, input),
+    }
+}
+
+/// Generate a fresh 16-hex-digit trace id.
+///
+/// Ids mix the wall clock, the process id and a per-process counter, which
+/// is unique enough for log correlation but not cryptographically random.
+pub fn generate_trace_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    let mut x = nanos
+        ^ (u64::from(std::process::id()) << 32)
+        ^ COUNTER
+            .fetch_add(1, Ordering::Relaxed)
+            .wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    // splitmix64 finalizer so consecutive ids look unrelated
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^= x >> 31;
+    format!("{:016x}", x)
+}
+
+fn is_valid_trace_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_TRACE_ID_LEN
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'_' | b'.' | b'-'))
+}
+
+/// A reply compression codec negotiated with `hello`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// Replies are sent as-is.
+    None,
+    /// Large replies are sent as LZ4 blocks.
+    Lz4,
+}
+
+impl Compression {
+    /// Codecs this build can speak, in order of preference.
+    pub const SUPPORTED: &'static [Compression] = &[Compression::Lz4, Compression::None];
+
+    /// The codec's wire name.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Lz4 => "lz4",
+        }
+    }
+
+    /// Look up a codec by wire name (case-insensitive).
+    pub fn parse(name: &str) -> Option<Compression> {
+        Self::SUPPORTED
+            .iter()
+            .copied()
+            .find(|codec| codec.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// Pick the first codec in a comma-separated offer that this build
+/// supports, or [`Compression::None`] if there is none.
+pub fn negotiate_compression(offered: &str) -> Compression {
+    offered
+        .split(',')
+        .find_map(|name| Compression::parse(name.trim()))
+        .unwrap_or(Compression::None)
+}
+
+/// `word` as it must be written in a request line: unchanged if it is not
+/// empty, has no spaces and does not start with `"`, otherwise in double
+/// quotes with `"` and `\` escaped. Line breaks cannot be quoted; a word
+/// containing one must not be sent.
+///
+/// ```
+/// use in_memory_cache::protocol::quote;
+///
+/// assert_eq!(quote("value"), "value");
+/// assert_eq!(quote("hello world"), r#""hello world""#);
+/// assert_eq!(quote(r#"say "hi""#), r#""say \"hi\"""#);
+/// assert_eq!(quote(""), r#""""#);
+/// ```
+pub fn quote(word: &str) -> Cow<'_, str> {
+    if !word.is_empty() && !word.contains(' ') && !word.starts_with('"') {
+        return Cow::Borrowed(word);
+    }
+    let mut quoted = String::with_capacity(word.len() + 2);
+    quoted.push('"');
+    for c in word.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    Cow::Owned(quoted)
+}
+
+/// Splits the bytes read from a connection into request lines.
+///
+/// Bytes are read into [`buffer`](RequestFramer::buffer) and complete
+/// lines taken off with [`next_frame`](RequestFramer::next_frame), so a
+/// request split over several reads is only handed out once its newline
+/// arrives, and several requests in one read come out one at a time.
+///
+/// A request longer than the frame limit is not buffered: it is reported
+/// once as [`ProtocolError::TooLarge`] and its bytes are dropped up to the
+/// next newline, after which framing carries on.
+///
+/// ```
+/// use in_memory_cache::protocol::RequestFramer;
+///
+/// let mut framer = RequestFramer::new(16);
+/// framer.buffer().extend_from_slice(b"get a\r\nget");
+/// assert_eq!(&framer.next_frame().unwrap().unwrap()[..], b"get a");
+/// assert!(framer.next_frame().is_none());
+///
+/// framer.buffer().extend_from_slice(b" b\n");
+/// assert_eq!(&framer.next_frame().unwrap().unwrap()[..], b"get b");
+/// ```
+#[derive(Debug)]
+pub struct RequestFramer {
+    buf: BytesMut,
+    max_frame: usize,
+    /// Bytes of `buf` already searched for a newline.
+    scanned: usize,
+    /// Whether the rest of an oversized request is being dropped.
+    discarding: bool,
+}
+
+impl RequestFramer {
+    /// A framer accepting requests of up to `max_frame` bytes, not
+    /// counting the line ending.
+    pub fn new(max_frame: usize) -> Self {
+        Self {
+            buf: BytesMut::with_capacity(1024),
+            max_frame,
+            scanned: 0,
+            discarding: false,
+        }
+    }
+
+    /// The buffer to append received bytes to.
+    pub fn buffer(&mut self) -> &mut BytesMut {
+        &mut self.buf
+    }
+
+    /// Take the next complete request line off the buffer, without its
+    /// line ending. `None` means more bytes are needed.
+    pub fn next_frame(&mut self) -> Option<ProtocolResult<BytesMut>> {
+        loop {
+            let newline = self.buf[self.scanned..].iter().position(|&b| b == b'\n');
+            let Some(offset) = newline else {
+                if self.discarding {
+                    self.buf.clear();
+                    self.scanned = 0;
+                    return None;
+                }
+                if self.buf.len() > self.max_frame + 1 {
+                    // Longer than any request could be, even counting a
+                    // trailing '\r'
+                    self.buf.clear();
+                    self.scanned = 0;
+                    self.discarding = true;
+                    return Some(Err(self.too_large()));
+                }
+                self.scanned = self.buf.len();
+                return None;
+            };
+
+            let end = self.scanned + offset;
+            let mut line = self.buf.split_to(end + 1);
+            self.scanned = 0;
+            if self.discarding {
+                // The tail of a request that was already reported
+                self.discarding = false;
+                continue;
+            }
+            return Some(self.finish_line(&mut line, end));
+        }
+    }
+
+    /// The request left in the buffer once the peer has closed the
+    /// connection, if any. It is taken even though no newline ended it.
+    pub fn finish(&mut self) -> Option<ProtocolResult<BytesMut>> {
+        if std::mem::take(&mut self.discarding) || self.buf.is_empty() {
+            self.buf.clear();
+            return None;
+        }
+        let mut line = self.buf.split();
+        let len = line.len();
+        self.scanned = 0;
+        Some(self.finish_line(&mut line, len))
+    }
+
+    /// Cut `line` down to its first `len` bytes less a trailing `\r`, and
+    /// check it against the limit.
+    fn finish_line(&self, line: &mut BytesMut, len: usize) -> ProtocolResult<BytesMut> {
+        line.truncate(len);
+        if line.last() == Some(&b'\r') {
+            line.truncate(len - 1);
+        }
+        if line.len() > self.max_frame {
+            return Err(self.too_large());
+        }
+        Ok(std::mem::take(line))
+    }
+
+    fn too_large(&self) -> ProtocolError {
+        ProtocolError::TooLarge(format!("frame too large (max: {} bytes)", self.max_frame))
+    }
+}
+
+/// Frame a reply for the wire.
+///
+/// A reply without newlines that does not start with `$` becomes
+/// `<reply>\n`; any other becomes `$<len>\n<reply>\n`.
+///
+/// ```
+/// use in_memory_cache::protocol::encode_reply;
+///
+/// assert_eq!(&encode_reply(b"PONG")[..], b"PONG\n");
+/// assert_eq!(&encode_reply(b"a\nb")[..], b"$3\na\nb\n");
+/// ```
+pub fn encode_reply(reply: &[u8]) -> Bytes {
+    let one_line = !reply.contains(&b'\n') && reply.first() != Some(&LENGTH_PREFIX);
+    let mut buf = BytesMut::with_capacity(reply.len() + 24);
+    if !one_line {
+        buf.put_u8(LENGTH_PREFIX);
+        buf.put_slice(reply.len().to_string().as_bytes());
+        buf.put_u8(b'\n');
+    }
+    buf.put_slice(reply);
+    buf.put_u8(b'\n');
+    buf.freeze()
+}
+
+/// Frame a reply for the wire, compressing it with `compression` if it is
+/// at least [`COMPRESSION_THRESHOLD`] bytes and compression shrinks it.
+///
+/// Otherwise this is [`encode_reply`].
+///
+/// ```
+/// use in_memory_cache::protocol::{decode_reply, encode_reply_with, Compression};
+///
+/// let reply = "row\n".repeat(100);
+/// let framed = encode_reply_with(reply.as_bytes(), Compression::Lz4);
+/// assert!(framed.len() < reply.len());
+/// let (decoded, _) = decode_reply(&framed).unwrap().unwrap();
+/// assert_eq!(decoded, reply.as_bytes());
+/// ```
+pub fn encode_reply_with(reply: &[u8], compression: Compression) -> Bytes {
+    if compression == Compression::None || reply.len() < COMPRESSION_THRESHOLD {
+        return encode_reply(reply);
+    }
+    let compressed = crate::lz4::compress(reply);
+    if compressed.len() >= reply.len() {
+        return encode_reply(reply);
+    }
+    let header = format!(
+        "${} {}={}\n",
+        compressed.len(),
+        compression.name(),
+        reply.len()
+    );
+    let mut buf = BytesMut::with_capacity(header.len() + compressed.len() + 1);
+    buf.put_slice(header.as_bytes());
+    buf.put_slice(&compressed);
+    buf.put_u8(b'\n');
+    buf.freeze()
+}
+
+/// Split the first reply framed by [`encode_reply`] off `input`.
+///
+/// Returns the reply and the number of bytes its frame took up, or `None`
+/// if `input` does not hold a whole frame yet. A compressed reply from
+/// [`encode_reply_with`] is returned decompressed. A malformed length
+/// prefix or compressed reply is a [`ProtocolError::Framing`].
+pub fn decode_reply(input: &[u8]) -> ProtocolResult<Option<(Bytes, usize)>> {
+    let line_end = match input.iter().position(|&b| b == b'\n') {
+        Some(end) => end,
+        None => return Ok(None),
+    };
+    if input.first() != Some(&LENGTH_PREFIX) {
+        return Ok(Some((
+            Bytes::copy_from_slice(&input[..line_end]),
+            line_end + 1,
+        )));
+    }
+
+    let header = std::str::from_utf8(&input[1..line_end])
+        .map_err(|_| ProtocolError::Framing("invalid reply length".to_string()))?;
+    let (len, codec) = match header.split_once(' ') {
+        Some((len, codec)) => (len, Some(codec)),
+        None => (header, None),
+    };
+    let len = len
+        .parse::<usize>()
+        .map_err(|_| ProtocolError::Framing("invalid reply length".to_string()))?;
+    let start = line_end + 1;
+    let end = start
+        .checked_add(len)
+        .ok_or_else(|| ProtocolError::Framing("invalid reply length".to_string()))?;
+    if input.len() <= end {
+        return Ok(None);
+    }
+    if input[end] != b'\n' {
+        return Err(ProtocolError::Framing(
+            "reply not terminated by newline".to_string(),
+        ));
+    }
+    let reply = match codec {
+        None => Bytes::copy_from_slice(&input[start..end]),
+        Some(codec) => decompress_reply(codec, &input[start..end])?,
+    };
+    Ok(Some((reply, end + 1)))
+}
+
+/// Undo [`encode_reply_with`] for a frame whose header named `codec`, as
+/// in `lz4=<raw len>`.
+fn decompress_reply(codec: &str, compressed: &[u8]) -> ProtocolResult<Bytes> {
+    let invalid = |detail: &str| ProtocolError::Framing(format!("{}: {}", detail, codec));
+    let (name, raw_len) = codec
+        .split_once('=')
+        .ok_or_else(|| invalid("invalid reply compression"))?;
+    let raw_len = raw_len
+        .parse::<usize>()
+        .map_err(|_| invalid("invalid reply compression"))?;
+    match Compression::parse(name) {
+        Some(Compression::Lz4) => crate::lz4::decompress(compressed, raw_len)
+            .map(Bytes::from)
+            .ok_or_else(|| invalid("corrupt compressed reply")),
+        _ => Err(invalid("unsupported reply compression")),
+    }
+}
+
+/// Read the next reply framed by [`encode_reply`] from `reader`.
+///
+/// `buf` holds bytes read past the end of the reply and must be passed to
+/// the next call for the same reader. If the peer closes the connection in
+/// the middle of a frame, whatever was received is returned as the reply;
+/// if it closes before sending anything, the error is
+/// [`UnexpectedEof`](std::io::ErrorKind::UnexpectedEof).
+#[cfg(feature = "tokio")]
+pub async fn read_reply<R>(reader: &mut R, buf: &mut BytesMut) -> std::io::Result<Bytes>
+where
+    R: tokio::io::AsyncRead + Unpin,
+{
+    use bytes::Buf;
+    use tokio::io::AsyncReadExt;
+
+    loop {
+        let framed = decode_reply(buf)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))?;
+        if let Some((reply, used)) = framed {
+            buf.advance(used);
+            return Ok(reply);
+        }
+        if reader.read_buf(buf).await? == 0 {
+            if buf.is_empty() {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            return Ok(buf.split().freeze());
+        }
+    }
+}
+
+/// Encode a list of optional values as a multi-bulk reply.
+pub fn encode_multi_bulk(values: &[Option<Bytes>]) -> Bytes {
+    let payload: usize = values.iter().flatten().map(Bytes::len).sum();
+    let mut buf = BytesMut::with_capacity(payload + 16 * (values.len() + 1));
+
+    buf.put_slice(format!("*{}", values.len()).as_bytes());
+    buf.put_slice(CRLF);
+    for value in values {
+        match value {
+            Some(value) => {
+                buf.put_slice(format!("${}", value.len()).as_bytes());
+                buf.put_slice(CRLF);
+                buf.put_slice(value);
+                buf.put_slice(CRLF);
+            }
+            None => {
+                buf.put_slice(b"$-1");
+                buf.put_slice(CRLF);
+            }
+        }
+    }
+    buf.freeze()
+}
+
+/// Decode a complete multi-bulk reply.
+///
+/// Returns [`ProtocolError::Framing`] if the input is malformed, truncated,
+/// or has trailing bytes.
+pub fn decode_multi_bulk(input: &[u8]) -> ProtocolResult<Vec<Option<Bytes>>> {
+    let mut rest = input;
+
+    let count = read_header(&mut rest, b'*')?;
+    let count = usize::try_from(count)
+        .map_err(|_| ProtocolError::Framing(format!("invalid element count {}", count)))?;
+
+    // Every element needs at least "$-1\r\n"; cap the allocation accordingly
+    let mut values = Vec::with_capacity(count.min(rest.len() / 5));
+    for _ in 0..count {
+        let len = read_header(&mut rest, b'$')?;
+        if len == -1 {
+            values.push(None);
+            continue;
+        }
+        let len = usize::try_from(len)
+            .map_err(|_| ProtocolError::Framing(format!("invalid bulk length {}", len)))?;
+        if rest.len() < len + CRLF.len() {
+            return Err(ProtocolError::Framing("truncated bulk value".to_string()));
+        }
+        if &rest[len..len + CRLF.len()] != CRLF {
+            return Err(ProtocolError::Framing(
+                "bulk value not terminated by CRLF".to_string(),
+            ));
+        }
+        values.push(Some(Bytes::copy_from_slice(&rest[..len])));
+        rest = &rest[len + CRLF.len()..];
+    }
+
+    if !rest.is_empty() {
+        return Err(ProtocolError::Framing(format!(
+            "{} trailing bytes after multi-bulk reply",
+            rest.len()
+        )));
+    }
+    Ok(values)
+}
+
+/// Read a `<marker><integer>\r\n` header line and advance `rest` past it.
+fn read_header(rest: &mut &[u8], marker: u8) -> ProtocolResult<i64> {
+    let line_end = rest
+        .windows(CRLF.len())
+        .position(|w| w == CRLF)
+        .ok_or_else(|| ProtocolError::Framing("truncated header".to_string()))?;
+    let line = &rest[..line_end];
+
+    if line.first() != Some(&marker) {
+        return Err(ProtocolError::Framing(format!(
+            "expected '{}' header",
+            marker as char
+        )));
+    }
+    let number = std::str::from_utf8(&line[1..])
+        .ok()
+        .and_then(|s| s.parse::<i64>().ok())
+        .ok_or_else(|| ProtocolError::Framing("invalid header number".to_string()))?;
+
+    *rest = &rest[line_end + CRLF.len()..];
+    Ok(number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_mixed_values() {
+        let values = vec![
+            Some(Bytes::from("plain")),
+            None,
+            Some(Bytes::from_static(b"line1\r\nline2\n")),
+            Some(Bytes::new()),
+            Some(Bytes::from(vec![0u8, 255, 13, 10, 36])),
+            None,
+        ];
+        let encoded = encode_multi_bulk(&values);
+        assert_eq!(decode_multi_bulk(&encoded).unwrap(), values);
+    }
+
+    #[test]
+    fn test_encoding_format() {
+        let encoded = encode_multi_bulk(&[Some(Bytes::from("ab")), None]);
+        assert_eq!(&encoded[..], b"*2\r\n$2\r\nab\r\n$-1\r\n");
+
+        let empty = encode_multi_bulk(&[]);
+        assert_eq!(&empty[..], b"*0\r\n");
+        assert!(decode_multi_bulk(&empty).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_reply_framing_round_trip() {
+        let replies: [&[u8]; 6] = [
+            b"PONG",
+            b"",
+            b"$5",
+            b"# Server\nversion:1\n\n# Memory\n",
+            b"*2\r\n$1\r\na\r\n$-1\r\n",
+            b"ERR FRAMING empty request",
+        ];
+        let mut stream = Vec::new();
+        for reply in replies {
+            stream.extend_from_slice(&encode_reply(reply));
+        }
+
+        let mut rest = &stream[..];
+        for reply in replies {
+            let (decoded, used) = decode_reply(rest).unwrap().unwrap();
+            assert_eq!(&decoded[..], reply);
+            rest = &rest[used..];
+        }
+        assert!(rest.is_empty());
+        assert_eq!(decode_reply(rest).unwrap(), None);
+    }
+
+    #[test]
+    fn test_decode_reply_waits_for_whole_frame() {
+        assert_eq!(decode_reply(b"PON").unwrap(), None);
+        assert_eq!(decode_reply(b"$4\na\nb").unwrap(), None);
+        assert_eq!(decode_reply(b"$4\na\nbc").unwrap(), None);
+        assert!(decode_reply(b"$x\n").is_err());
+        assert!(decode_reply(b"$2\nabc\n").is_err());
+        assert!(decode_reply(b"$99999999999999999999999\n").is_err());
+    }
+
+    /// Frame `reply` with lz4, check it decodes back, and return the frame.
+    fn lz4_round_trip(reply: &[u8]) -> Bytes {
+        let framed = encode_reply_with(reply, Compression::Lz4);
+        let (decoded, used) = decode_reply(&framed).unwrap().unwrap();
+        assert_eq!(&decoded[..], reply);
+        assert_eq!(used, framed.len());
+        framed
+    }
+
+    #[test]
+    fn test_compressed_reply_round_trip() {
+        let reply = "# Keyspace\nkeys:1000\nexpires:0\n".repeat(50);
+        let framed = lz4_round_trip(reply.as_bytes());
+        let header = &framed[..framed.iter().position(|&b| b == b'\n').unwrap()];
+        let expected = format!("${} lz4={}", framed.len() - header.len() - 2, reply.len());
+        assert_eq!(header, expected.as_bytes());
+        assert!(framed.len() < reply.len() / 4, "{}", framed.len());
+
+        // Other frames in the same stream still line up
+        let mut stream = framed.to_vec();
+        stream.extend_from_slice(&encode_reply(b"PONG"));
+        let (_, used) = decode_reply(&stream).unwrap().unwrap();
+        assert_eq!(
+            &decode_reply(&stream[used..]).unwrap().unwrap().0[..],
+            b"PONG"
+        );
+    }
+
+    #[test]
+    fn test_incompressible_and_tiny_replies_are_sent_plain() {
+        let mut state = 0x9E37_79B9u32;
+        let noise: Vec<u8> = (0..4096)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        assert_eq!(lz4_round_trip(&noise), encode_reply(&noise));
+
+        let tiny = b"a\na\na\na\na\na\na\na";
+        assert_eq!(lz4_round_trip(tiny), encode_reply(tiny));
+        assert_eq!(lz4_round_trip(b""), encode_reply(b""));
+        assert_eq!(lz4_round_trip(b"PONG"), encode_reply(b"PONG"));
+
+        let large = "x".repeat(1000);
+        assert_eq!(
+            encode_reply_with(large.as_bytes(), Compression::None),
+            encode_reply(large.as_bytes())
+        );
+    }
+
+    #[test]
+    fn test_decode_rejects_bad_compressed_replies() {
+        let framed = encode_reply_with("abc".repeat(200).as_bytes(), Compression::Lz4);
+        let body = &framed[framed.iter().position(|&b| b == b'\n').unwrap()..];
+        for codec in ["lz4=601", "lz4=599", "zstd=600", "lz4"] {
+            let mut bad = format!("${} {}", body.len() - 2, codec).into_bytes();
+            bad.extend_from_slice(body);
+            assert!(
+                matches!(decode_reply(&bad), Err(ProtocolError::Framing(_))),
+                "{}",
+                codec
+            );
+        }
+    }
+
+    /// Every frame currently available from `framer`, errors as `None`.
+    fn frames(framer: &mut RequestFramer) -> Vec<Option<String>> {
+        std::iter::from_fn(|| framer.next_frame())
+            .map(|frame| {
+                frame
+                    .ok()
+                    .map(|line| String::from_utf8(line.to_vec()).unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_framer_byte_by_byte() {
+        let mut framer = RequestFramer::new(64);
+        let input = b"set key value\r\nget key\n";
+        let mut seen = Vec::new();
+        for &byte in input.iter() {
+            framer.buffer().extend_from_slice(&[byte]);
+            seen.extend(frames(&mut framer));
+        }
+        assert_eq!(
+            seen,
+            [
+                Some("set key value".to_string()),
+                Some("get key".to_string())
+            ]
+        );
+        assert!(framer.finish().is_none());
+    }
+
+    #[test]
+    fn test_framer_coalesced_requests() {
+        let mut framer = RequestFramer::new(64);
+        framer
+            .buffer()
+            .extend_from_slice(b"set a 1\nset b 2\n\nget a");
+        assert_eq!(
+            frames(&mut framer),
+            [
+                Some("set a 1".to_string()),
+                Some("set b 2".to_string()),
+                Some(String::new())
+            ]
+        );
+        assert_eq!(&framer.finish().unwrap().unwrap()[..], b"get a");
+        assert!(framer.finish().is_none());
+    }
+
+    #[test]
+    fn test_framer_rejects_oversized_requests() {
+        let mut framer = RequestFramer::new(8);
+        // Exactly at the limit, with and without '\r'
+        framer.buffer().extend_from_slice(b"12345678\r\n12345678\n");
+        assert_eq!(frames(&mut framer).len(), 2);
+
+        // A long line that arrives whole is rejected on its newline
+        framer.buffer().extend_from_slice(b"123456789\nping\n");
+        assert_eq!(frames(&mut framer), [None, Some("ping".to_string())]);
+
+        // One that trickles in is rejected once it outgrows the limit, and
+        // reported only once; its tail is dropped
+        let mut seen = Vec::new();
+        for chunk in [&b"set k "[..], b"aaaaaa", b"aaaaaaaa", b"aa\nget k\n"] {
+            framer.buffer().extend_from_slice(chunk);
+            seen.extend(frames(&mut framer));
+        }
+        assert_eq!(seen, [None, Some("get k".to_string())]);
+
+        let err = RequestFramer::new(8)
+            .finish_line(&mut BytesMut::from("123456789"), 9)
+            .unwrap_err();
+        assert_eq!(
+            &err.to_wire()[..],
+            b"ERR TOOLARGE frame too large (max: 8 bytes)"
+        );
+    }
+
+    #[test]
+    fn test_framer_buffer_stays_bounded() {
+        let mut framer = RequestFramer::new(1024);
+        let mut errors = 0;
+        for _ in 0..1000 {
+            framer.buffer().extend_from_slice(&[b'x'; 512]);
+            errors += frames(&mut framer).len();
+            assert!(framer.buffer().len() <= 1024 + 512);
+        }
+        assert_eq!(errors, 1);
+        // An unterminated oversized request is not handed out on close
+        assert!(framer.finish().is_none());
+    }
+
+    fn words(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_split_trace_id() {
+        let attrs = words("*id=abc-1 get key");
+        let (id, rest) = split_trace_id(&attrs).unwrap();
+        assert_eq!(id, Some("abc-1"));
+        assert_eq!(rest, &attrs[1..]);
+
+        let attrs = words("get key");
+        assert_eq!(split_trace_id(&attrs).unwrap(), (None, &attrs[..]));
+
+        assert!(split_trace_id(&words("*id= get")).is_err());
+        assert!(split_trace_id(&words("*id=a/b get")).is_err());
+        assert!(split_trace_id(&[format!("*id={}", "x".repeat(65))]).is_err());
+    }
+
+    #[test]
+    fn test_trace_id_echo_round_trip() {
+        let reply = prefix_trace_id(Some("t1"), b"*1\r\n$-1\r\n");
+        assert_eq!(&reply[..], b"*id=t1 *1\r\n$-1\r\n");
+        assert_eq!(strip_trace_id(&reply), (Some("t1"), &b"*1\r\n$-1\r\n"[..]));
+
+        // Empty replies keep their prefix separator
+        let reply = prefix_trace_id(Some("t1"), b"");
+        assert_eq!(strip_trace_id(&reply), (Some("t1"), &b""[..]));
+
+        assert_eq!(strip_trace_id(b"*2\r\n"), (None, &b"*2\r\n"[..]));
+        assert_eq!(&prefix_trace_id(None, b"Ok")[..], b"Ok");
+    }
+
+    #[test]
+    fn test_generated_trace_ids_are_valid_and_distinct() {
+        let a = generate_trace_id();
+        let b = generate_trace_id();
+        assert_ne!(a, b);
+        assert!(is_valid_trace_id(&a));
+        assert_eq!(a.len(), 16);
+    }
+
+    #[test]
+    fn test_negotiate_compression() {
+        assert_eq!(negotiate_compression("lz4"), Compression::Lz4);
+        assert_eq!(negotiate_compression("zstd, LZ4"), Compression::Lz4);
+        assert_eq!(negotiate_compression("none,lz4"), Compression::None);
+        assert_eq!(negotiate_compression("zstd"), Compression::None);
+        assert_eq!(negotiate_compression(""), Compression::None);
+        assert_eq!(Compression::parse("zstd"), None);
+        assert_eq!(Compression::None.name(), "none");
+        assert_eq!(Compression::Lz4.name(), "lz4");
+    }
+
+    /// One of each variant; the match makes a new variant fail to compile
+    /// here until it is added.
+    fn every_error() -> Vec<ProtocolError> {
+        let errors = vec![
+            ProtocolError::Framing("empty command".to_string()),
+            ProtocolError::UnknownCommand("frobnicate".to_string()),
+            ProtocolError::WrongArity {
+                cmd: "set".to_string(),
+                expected: 2,
+                got: 1,
+            },
+            ProtocolError::InvalidArgument("invalid count".to_string()),
+            ProtocolError::TooLarge("request exceeds 1024 bytes".to_string()),
+            ProtocolError::Unauthorized("admin commands are disabled".to_string()),
+            ProtocolError::RateLimited {
+                retry_after: Duration::from_millis(250),
+            },
+            ProtocolError::Cache("invalid value: not an integer".to_string()),
+        ];
+        for error in &errors {
+            match error {
+                ProtocolError::Framing(_)
+                | ProtocolError::UnknownCommand(_)
+                | ProtocolError::WrongArity { .. }
+                | ProtocolError::InvalidArgument(_)
+                | ProtocolError::TooLarge(_)
+                | ProtocolError::Unauthorized(_)
+                | ProtocolError::RateLimited { .. }
+                | ProtocolError::Cache(_) => {}
+            }
+        }
+        errors
+    }
+
+    #[test]
+    fn test_every_error_round_trips_through_the_wire() {
+        let expected = [
+            "ERR FRAMING empty command",
+            "ERR UNKNOWN unknown command 'frobnicate'",
+            "ERR ARITY wrong number of arguments for 'set': expected at least 2, got 1",
+            "ERR INVALID invalid count",
+            "ERR TOOLARGE request exceeds 1024 bytes",
+            "ERR NOAUTH admin commands are disabled",
+            "ERR RATELIMITED retry_after_ms=250",
+            "ERR CACHE invalid value: not an integer",
+        ];
+        let errors = every_error();
+        assert_eq!(errors.len(), expected.len());
+        for (error, wire) in errors.into_iter().zip(expected) {
+            let reply = error.to_wire();
+            assert_eq!(&reply[..], wire.as_bytes());
+            assert_eq!(ProtocolError::from_wire(&reply), Some(error));
+        }
+    }
+
+    #[test]
+    fn test_codes_are_distinct() {
+        let mut codes: Vec<_> = every_error().iter().map(ProtocolError::code).collect();
+        codes.sort_unstable();
+        codes.dedup();
+        assert_eq!(codes.len(), every_error().len());
+    }
+
+    #[test]
+    fn test_from_wire_classifies_every_error_reply() {
+        assert_eq!(ProtocolError::from_wire(b"Ok"), None);
+        assert_eq!(ProtocolError::from_wire(b""), None);
+        assert_eq!(ProtocolError::from_wire(b"ERROR"), None);
+
+        // Codeless replies of older servers and malformed details
+        for reply in [
+            &b"ERR missing key argument"[..],
+            b"ERR UNKNOWN frobnicate",
+            b"ERR ARITY set",
+            b"ERR RATELIMITED soon",
+            b"ERR ",
+        ] {
+            let text = String::from_utf8_lossy(&reply[ERROR_PREFIX.len()..]).into_owned();
+            assert_eq!(
+                ProtocolError::from_wire(reply),
+                Some(ProtocolError::Cache(text))
+            );
+        }
+
+        // Rate limit delays round up to whole milliseconds
+        let reply = ProtocolError::RateLimited {
+            retry_after: Duration::from_micros(1500),
+        }
+        .to_wire();
+        assert_eq!(&reply[..], b"ERR RATELIMITED retry_after_ms=2");
+    }
+
+    #[test]
+    fn test_cache_error_conversions() {
+        let to_wire = |err: CacheError| ProtocolError::from(err).code();
+        assert_eq!(to_wire(CacheError::ParseError("x".to_string())), "INVALID");
+        assert_eq!(
+            to_wire(CacheError::InvalidCommand("x".to_string())),
+            "UNKNOWN"
+        );
+        assert_eq!(to_wire(CacheError::KeyNotFound("x".to_string())), "CACHE");
+        assert_eq!(
+            ProtocolError::from(CacheError::InvalidValue("not an integer".to_string())),
+            ProtocolError::Cache("invalid value: not an integer".to_string())
+        );
+
+        for error in every_error() {
+            let converted = CacheError::try_from(error.clone());
+            match &error {
+                ProtocolError::Framing(_)
+                | ProtocolError::WrongArity { .. }
+                | ProtocolError::InvalidArgument(_) => {
+                    assert!(matches!(converted, Ok(CacheError::ParseError(_))))
+                }
+                ProtocolError::UnknownCommand(_) => {
+                    assert!(matches!(converted, Ok(CacheError::InvalidCommand(_))))
+                }
+                ProtocolError::TooLarge(_) => {
+                    assert!(matches!(converted, Ok(CacheError::InvalidValue(_))))
+                }
+                ProtocolError::Unauthorized(_)
+                | ProtocolError::RateLimited { .. }
+                | ProtocolError::Cache(_) => assert_eq!(converted.unwrap_err(), error),
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_rejects_malformed() {
+        assert!(decode_multi_bulk(b"").is_err());
+        assert!(decode_multi_bulk(b"$1\r\na\r\n").is_err());
+        assert!(decode_multi_bulk(b"*2\r\n$1\r\na\r\n").is_err());
+        assert!(decode_multi_bulk(b"*1\r\n$5\r\nab\r\n").is_err());
+        assert!(decode_multi_bulk(b"*1\r\n$2\r\nabXY").is_err());
+        assert!(decode_multi_bulk(b"*1\r\n$-1\r\nextra").is_err());
+        assert!(decode_multi_bulk(b"*-3\r\n").is_err());
+        assert!(decode_multi_bulk(b"*1\r\n$x\r\n").is_err());
+    }
+}
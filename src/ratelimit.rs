@@ -0,0 +1,184 @@
+//! Token buckets for rate limiting.
+//!
+//! A [`TokenBucket`] holds up to `burst` tokens and refills continuously at
+//! `rate` tokens per second; every admitted operation takes one token. A
+//! client that stays under the rate is never throttled, and one that was
+//! idle can spend a full burst at once before being held to the rate.
+//!
+//! Buckets take the current time as an argument instead of reading the
+//! clock, so callers decide how time is sourced and tests can step it.
+//!
+//! Limits that must be shared between processes are kept in the cache
+//! itself instead: [`Cache::rate_limit`](crate::Cache::rate_limit) counts
+//! calls per key in fixed windows and answers with a [`RateDecision`].
+
+use std::time::{Duration, Instant};
+
+/// A token bucket. Not synchronized; wrap it in a lock to share it.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    /// Tokens added per second.
+    rate: f64,
+    /// Maximum number of tokens held.
+    burst: f64,
+    tokens: f64,
+    /// When `tokens` was last brought up to date.
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// A full bucket admitting `rate` operations per second on average and
+    /// up to `burst` back to back. Both are raised to at least 1.
+    pub fn new(rate: u32, burst: u32, now: Instant) -> Self {
+        let burst = f64::from(burst.max(1));
+        Self {
+            rate: f64::from(rate.max(1)),
+            burst,
+            tokens: burst,
+            updated: now,
+        }
+    }
+
+    /// Take one token at `now`. If the bucket is empty, nothing is taken
+    /// and the error holds how long until a token will be available.
+    pub fn try_acquire(&mut self, now: Instant) -> Result<(), Duration> {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+        Err(Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+    }
+
+    /// Tokens available at `now`, including fractions of a token.
+    pub fn available(&mut self, now: Instant) -> f64 {
+        self.refill(now);
+        self.tokens
+    }
+
+    /// Whether the bucket has refilled completely by `now`, i.e. holds no
+    /// trace of past operations.
+    pub fn is_full(&mut self, now: Instant) -> bool {
+        self.available(now) >= self.burst
+    }
+
+    fn refill(&mut self, now: Instant) {
+        // A clock reading older than the last one adds nothing
+        let elapsed = now.saturating_duration_since(self.updated);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.burst);
+        self.updated = self.updated.max(now);
+    }
+}
+
+/// Outcome of a fixed-window rate limit check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateDecision {
+    /// Whether the call is within the limit.
+    pub allowed: bool,
+    /// Calls still allowed in the current window.
+    pub remaining: u64,
+    /// How long until the window resets; zero for an allowed call.
+    pub retry_after: Duration,
+}
+
+impl RateDecision {
+    /// The decision for the `count`th call in a window of `limit` calls
+    /// that ends in `reset_in`.
+    pub(crate) fn new(count: i64, limit: u64, reset_in: Duration) -> Self {
+        let count = u64::try_from(count).unwrap_or(0);
+        let allowed = count <= limit;
+        Self {
+            allowed,
+            remaining: limit.saturating_sub(count),
+            retry_after: if allowed { Duration::ZERO } else { reset_in },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_burst_then_throttle() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(10, 5, start);
+        for _ in 0..5 {
+            assert!(bucket.try_acquire(start).is_ok());
+        }
+        // Empty: the next token arrives after 1/rate seconds
+        assert_eq!(bucket.try_acquire(start), Err(Duration::from_millis(100)));
+        assert!(bucket.available(start) < 1.0);
+    }
+
+    #[test]
+    fn test_refill_math() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(4, 4, start);
+        for _ in 0..4 {
+            bucket.try_acquire(start).unwrap();
+        }
+
+        // 4/s: one token every 250ms, partial tokens accumulate
+        let later = start + Duration::from_millis(125);
+        assert!((bucket.available(later) - 0.5).abs() < 1e-9);
+        let wait = bucket.try_acquire(later).unwrap_err();
+        assert!((wait.as_secs_f64() - 0.125).abs() < 1e-9);
+
+        let later = start + Duration::from_millis(250);
+        assert!(bucket.try_acquire(later).is_ok());
+        assert!(bucket.try_acquire(later).is_err());
+
+        // Refill stops at the burst size
+        let much_later = start + Duration::from_secs(60);
+        assert!(bucket.is_full(much_later));
+        assert!((bucket.available(much_later) - 4.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_steady_rate_is_never_throttled() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(10, 1, start);
+        for i in 0..100 {
+            let now = start + Duration::from_millis(100 * i);
+            assert!(bucket.try_acquire(now).is_ok(), "throttled at op {}", i);
+        }
+    }
+
+    #[test]
+    fn test_clock_going_backwards_adds_nothing() {
+        let start = Instant::now();
+        let later = start + Duration::from_secs(1);
+        let mut bucket = TokenBucket::new(1, 1, later);
+        bucket.try_acquire(later).unwrap();
+        assert!(bucket.try_acquire(start).is_err());
+        assert!(bucket.try_acquire(later).is_err());
+        assert!(bucket.try_acquire(later + Duration::from_secs(1)).is_ok());
+    }
+
+    #[test]
+    fn test_zero_limits_are_raised_to_one() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(0, 0, start);
+        assert!(bucket.try_acquire(start).is_ok());
+        assert_eq!(bucket.try_acquire(start), Err(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_rate_decision() {
+        let reset_in = Duration::from_secs(3);
+        let first = RateDecision::new(1, 2, reset_in);
+        assert!(first.allowed);
+        assert_eq!(first.remaining, 1);
+        assert_eq!(first.retry_after, Duration::ZERO);
+
+        let last = RateDecision::new(2, 2, reset_in);
+        assert!(last.allowed);
+        assert_eq!(last.remaining, 0);
+
+        let refused = RateDecision::new(3, 2, reset_in);
+        assert!(!refused.allowed);
+        assert_eq!(refused.remaining, 0);
+        assert_eq!(refused.retry_after, reset_in);
+    }
+}
@@ -0,0 +1,290 @@
+//! Time-sliced statistics recording.
+//!
+//! [`StatsRecorder`] samples a cache's [`StatsSnapshot`] at a fixed interval
+//! on a background thread, which turns the ever-growing counters into a time
+//! series suitable for load tests and warm-up profiling:
+//!
+//! ```
+//! use in_memory_cache::recorder::{write_csv, StatsRecorder};
+//! use in_memory_cache::Cache;
+//! use std::time::Duration;
+//!
+//! let cache = Cache::default();
+//! let recorder = StatsRecorder::start(&cache, Duration::from_millis(10));
+//! for i in 0..100 {
+//!     cache.set(format!("key_{}", i), "value");
+//! }
+//! let samples = recorder.stop();
+//!
+//! let mut csv = Vec::new();
+//! write_csv(&samples, &mut csv).unwrap();
+//! ```
+//!
+//! Sampling only reads the atomic counters, so it never takes the storage
+//! lock or blocks cache operations. Samples are timed by the cache's
+//! [`Clock`](crate::Clock), so a recording follows a mock clock in tests.
+
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::cache::Cache;
+use crate::error::CacheResult;
+use crate::stats::{CacheStats, StatsSnapshot};
+
+/// Default maximum number of samples kept (one hour at one per second).
+pub const DEFAULT_MAX_SAMPLES: usize = 3600;
+
+/// A statistics snapshot tagged with the time since recording started.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimedSnapshot {
+    pub elapsed: Duration,
+    pub snapshot: StatsSnapshot,
+}
+
+impl TimedSnapshot {
+    /// Operations (gets, sets and deletes) per second since `previous`.
+    ///
+    /// Counters that went backwards (e.g. after a stats reset) count as zero.
+    pub fn ops_per_sec_since(&self, previous: &TimedSnapshot) -> f64 {
+        let secs = self.elapsed.saturating_sub(previous.elapsed).as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        let delta = &self.snapshot - &previous.snapshot;
+        (delta.hits + delta.misses + delta.sets + delta.deletes) as f64 / secs
+    }
+}
+
+/// Samples cache statistics on a background thread until stopped.
+///
+/// At most `max_samples` snapshots are kept; once full, the oldest are
+/// dropped. Dropping the recorder without calling [`StatsRecorder::stop`]
+/// stops the thread and discards the samples.
+#[derive(Debug)]
+pub struct StatsRecorder {
+    samples: Arc<Mutex<VecDeque<TimedSnapshot>>>,
+    stop_tx: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl StatsRecorder {
+    /// Start sampling `cache` every `interval`, keeping at most
+    /// [`DEFAULT_MAX_SAMPLES`] snapshots.
+    pub fn start(cache: &Cache, interval: Duration) -> Self {
+        Self::start_bounded(cache, interval, DEFAULT_MAX_SAMPLES)
+    }
+
+    /// Start sampling `cache` every `interval`, keeping at most
+    /// `max_samples` snapshots (minimum 1).
+    pub fn start_bounded(cache: &Cache, interval: Duration, max_samples: usize) -> Self {
+        let cache = cache.clone();
+        let max_samples = max_samples.max(1);
+        let samples = Arc::new(Mutex::new(VecDeque::with_capacity(
+            max_samples.min(DEFAULT_MAX_SAMPLES),
+        )));
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+
+        // Recording starts now, not whenever the thread gets going
+        let clock = cache.clock();
+        let started = clock.now();
+        let recorded = Arc::clone(&samples);
+        let handle = thread::spawn(move || {
+            let mut next = started + interval;
+
+            loop {
+                let now = clock.now();
+                if now < next {
+                    // Sleep for what is left in real time, then read the
+                    // clock again: a mock clock may not have moved at all
+                    match stop_rx.recv_timeout(next - now) {
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        // Stop requested or recorder dropped
+                        Ok(()) | Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+
+                let sample = TimedSnapshot {
+                    elapsed: now.saturating_duration_since(started),
+                    snapshot: cache.stats(),
+                };
+                let mut samples = lock(&recorded);
+                if samples.len() == max_samples {
+                    samples.pop_front();
+                }
+                samples.push_back(sample);
+                drop(samples);
+
+                // Intervals the clock jumped over are skipped, not sampled
+                next += interval;
+                if next <= now {
+                    next = now + interval;
+                }
+                if stop_rx.try_recv() != Err(mpsc::TryRecvError::Empty) {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            samples,
+            stop_tx: Some(stop_tx),
+            handle: Some(handle),
+        }
+    }
+
+    /// The snapshots recorded so far, oldest first. Sampling continues.
+    pub fn samples(&self) -> Vec<TimedSnapshot> {
+        lock(&self.samples).iter().cloned().collect()
+    }
+
+    /// Stop sampling and return the recorded snapshots, oldest first.
+    pub fn stop(mut self) -> Vec<TimedSnapshot> {
+        self.shutdown();
+        lock(&self.samples).drain(..).collect()
+    }
+
+    fn shutdown(&mut self) {
+        if let Some(stop_tx) = self.stop_tx.take() {
+            let _ = stop_tx.send(());
+        }
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Samples are only pushed and popped under the lock, so a poisoned lock
+/// is recovered.
+fn lock(samples: &Mutex<VecDeque<TimedSnapshot>>) -> MutexGuard<'_, VecDeque<TimedSnapshot>> {
+    samples.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl Drop for StatsRecorder {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Write samples as CSV with a header row.
+///
+/// Columns are the elapsed milliseconds, the cumulative counters, the
+/// current size and hit rate, and the operations per second over the
+/// preceding interval.
+pub fn write_csv(samples: &[TimedSnapshot], mut out: impl Write) -> CacheResult<()> {
+    writeln!(
+        out,
+        "elapsed_ms,hits,misses,sets,deletes,evictions,expirations,size,hit_rate,ops_per_sec"
+    )?;
+
+    let mut previous: Option<&TimedSnapshot> = None;
+    for sample in samples {
+        let ops_per_sec = match previous {
+            Some(previous) => sample.ops_per_sec_since(previous),
+            None => sample.ops_per_sec_since(&TimedSnapshot {
+                elapsed: Duration::ZERO,
+                snapshot: zero_snapshot(),
+            }),
+        };
+        let s = &sample.snapshot;
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{:.2},{:.1}",
+            sample.elapsed.as_millis(),
+            s.hits,
+            s.misses,
+            s.sets,
+            s.deletes,
+            s.evictions,
+            s.expirations,
+            s.size,
+            s.hit_rate,
+            ops_per_sec
+        )?;
+        previous = Some(sample);
+    }
+    Ok(())
+}
+
+fn zero_snapshot() -> StatsSnapshot {
+    CacheStats::new().snapshot()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(elapsed_ms: u64, hits: u64, sets: u64) -> TimedSnapshot {
+        let mut snapshot = zero_snapshot();
+        snapshot.hits = hits;
+        snapshot.sets = sets;
+        TimedSnapshot {
+            elapsed: Duration::from_millis(elapsed_ms),
+            snapshot,
+        }
+    }
+
+    #[test]
+    fn test_ops_per_sec_since() {
+        let first = sample(1000, 10, 0);
+        let second = sample(1500, 60, 50);
+        assert_eq!(second.ops_per_sec_since(&first), 200.0);
+        // Counters going backwards saturate instead of underflowing
+        assert_eq!(first.ops_per_sec_since(&second), 0.0);
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let samples = vec![sample(1000, 5, 5), sample(2000, 25, 5)];
+        let mut out = Vec::new();
+        write_csv(&samples, &mut out).unwrap();
+
+        let csv = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("elapsed_ms,hits,"));
+        assert_eq!(lines[1], "1000,5,0,5,0,0,0,0,0.00,10.0");
+        assert_eq!(lines[2], "2000,25,0,5,0,0,0,0,0.00,20.0");
+    }
+
+    #[test]
+    fn test_recorder_samples_and_stops() {
+        use crate::clock::MockClock;
+        use crate::CacheConfig;
+        use std::time::Instant;
+
+        let clock = MockClock::new();
+        let cache = Cache::new(CacheConfig::new().clock(Arc::new(clock.clone())));
+        let interval = Duration::from_millis(10);
+        let recorder = StatsRecorder::start_bounded(&cache, interval, 3);
+
+        for step in 1..=5u32 {
+            cache.set(format!("key_{}", step), "value");
+            clock.advance(interval);
+            // Wait for the sampler to see the clock move
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while recorder.samples().last().map(|s| s.elapsed) != Some(interval * step) {
+                assert!(Instant::now() < deadline, "no sample at step {}", step);
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+        let samples = recorder.stop();
+
+        // Only the last three are kept, one per interval of clock time
+        let elapsed: Vec<Duration> = samples.iter().map(|s| s.elapsed).collect();
+        assert_eq!(elapsed, [interval * 3, interval * 4, interval * 5]);
+        let sets: Vec<u64> = samples.iter().map(|s| s.snapshot.sets).collect();
+        assert_eq!(sets, [3, 4, 5]);
+        assert_eq!(samples[2].ops_per_sec_since(&samples[1]), 100.0);
+    }
+
+    #[test]
+    fn test_dropping_recorder_stops_thread() {
+        let cache = Cache::default();
+        let recorder = StatsRecorder::start(&cache, Duration::from_secs(3600));
+        drop(recorder);
+    }
+}
@@ -0,0 +1,154 @@
+//! Cursors for paging through keys.
+//!
+//! [`Cache::scan_keys`](crate::Cache::scan_keys) and the server's `scan`
+//! command return keys in lexicographic order, a page at a time, together
+//! with a [`ScanCursor`] naming the last key returned. The next page starts
+//! after that key, so the position does not depend on where entries sit in
+//! storage, which changes on every access, insert and eviction.
+//!
+//! A scan from [`ScanCursor::start`] until [`ScanCursor::is_done`]:
+//! - returns every key that is live for the whole scan exactly once;
+//! - returns a key at most once, whatever happens to it meanwhile;
+//! - may or may not return a key that is inserted, deleted or expires
+//!   during the scan.
+//!
+//! Cursors hold no server-side state: one can be stored, sent to a client
+//! and resumed later, against the same cache or a restarted one. Their
+//! string form is URL-safe:
+//!
+//! ```
+//! use in_memory_cache::ScanCursor;
+//!
+//! let cursor = ScanCursor::after("user:1");
+//! let text = cursor.to_string();
+//! assert_eq!(text, "k757365723a31");
+//! assert_eq!(text.parse::<ScanCursor>().unwrap(), cursor);
+//! assert_eq!(ScanCursor::start().to_string(), "0");
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::CacheError;
+
+/// Position of a key scan. See the [module docs](self) for the guarantees.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct ScanCursor {
+    state: State,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+enum State {
+    #[default]
+    Start,
+    /// The last key returned; the next page starts after it.
+    After(String),
+    Done,
+}
+
+impl ScanCursor {
+    /// A cursor at the beginning of the key space. Written as `0`.
+    pub fn start() -> Self {
+        Self::default()
+    }
+
+    /// A cursor that continues after `key`, which need not exist. Written
+    /// as `k` followed by the hex-encoded key bytes.
+    pub fn after(key: impl Into<String>) -> Self {
+        Self {
+            state: State::After(key.into()),
+        }
+    }
+
+    /// The cursor returned with the last page. Written as `end`.
+    pub fn done() -> Self {
+        Self { state: State::Done }
+    }
+
+    /// Whether the scan is complete.
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    /// The key the next page starts after, if any.
+    pub fn last_key(&self) -> Option<&str> {
+        match &self.state {
+            State::After(key) => Some(key),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ScanCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.state {
+            State::Start => f.write_str("0"),
+            State::After(key) => {
+                f.write_str("k")?;
+                for byte in key.bytes() {
+                    write!(f, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+            State::Done => f.write_str("end"),
+        }
+    }
+}
+
+impl FromStr for ScanCursor {
+    type Err = CacheError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "0" => return Ok(Self::start()),
+            "end" => return Ok(Self::done()),
+            _ => {}
+        }
+        let invalid = || CacheError::ParseError(format!("invalid scan cursor '{}'", s));
+        let hex = s.strip_prefix('k').ok_or_else(invalid)?;
+        if hex.len() % 2 != 0 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(invalid)?;
+        let key = String::from_utf8(bytes).map_err(|_| invalid())?;
+        Ok(Self::after(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_round_trip() {
+        for cursor in [
+            ScanCursor::start(),
+            ScanCursor::done(),
+            ScanCursor::after(""),
+            ScanCursor::after("user:42"),
+            ScanCursor::after("naïve key with spaces/&?="),
+        ] {
+            let text = cursor.to_string();
+            assert!(text.bytes().all(|b| b.is_ascii_alphanumeric()), "{}", text);
+            assert_eq!(text.parse::<ScanCursor>().unwrap(), cursor);
+        }
+        assert_eq!(ScanCursor::after("").to_string(), "k");
+        assert_eq!(ScanCursor::after("a").last_key(), Some("a"));
+        assert!(ScanCursor::done().is_done());
+    }
+
+    #[test]
+    fn test_invalid_cursors() {
+        for text in ["", "1", "k6", "kzz", "kff", "END", "k+6"] {
+            assert!(
+                matches!(text.parse::<ScanCursor>(), Err(CacheError::ParseError(_))),
+                "{}",
+                text
+            );
+        }
+    }
+}
@@ -0,0 +1,2532 @@
+//! Server-side building blocks shared by the `server` binary and tests.
+//!
+//! Configuration resolution is a pure function of the parsed command line and
+//! an environment lookup, so it can be exercised without touching the real
+//! process environment or binding a socket. Likewise, `info` and `stats`
+//! rendering only need a cache and a [`ServerState`].
+
+use bytes::{Bytes, BytesMut};
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Write as _;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::cache::Cache;
+use crate::cli::ServerCli;
+use crate::command::{Command, Request};
+use crate::config::CacheConfig;
+use crate::error::{CacheError, CacheResult};
+use crate::health::{HealthMetric, HealthThresholds};
+use crate::ops::SetOutcome;
+use crate::overrides::ConfigOverrides;
+use crate::protocol::{
+    ceil_millis, decode_multi_bulk, encode_multi_bulk, encode_reply_with, negotiate_compression,
+    prefix_trace_id, read_reply, split_trace_id, Compression, ProtocolError, ProtocolResult,
+    RequestFramer,
+};
+use crate::ratelimit::TokenBucket;
+use crate::scan::ScanCursor;
+use crate::stats::{prometheus_text, StatsSnapshot};
+use crate::utils::buffer_to_array;
+
+/// Environment variable for the bind host.
+pub const ENV_HOST: &str = "CACHE_HOST";
+/// Environment variable for the bind port.
+pub const ENV_PORT: &str = "CACHE_PORT";
+/// Environment variable for the maximum number of entries (0 = unlimited).
+pub const ENV_MAX_CAPACITY: &str = "CACHE_MAX_CAPACITY";
+/// Environment variable for the default TTL in seconds (0 = none).
+pub const ENV_DEFAULT_TTL: &str = "CACHE_DEFAULT_TTL";
+/// Environment variable for the background cleanup interval in seconds
+/// (0 = expire on access only).
+pub const ENV_CLEANUP_INTERVAL: &str = "CACHE_CLEANUP_INTERVAL";
+/// Environment variable enabling lock wait measurement (`true`/`false`).
+pub const ENV_RECORD_LOCK_WAITS: &str = "CACHE_RECORD_LOCK_WAITS";
+/// Environment variable enabling admin commands (`true`/`false`).
+pub const ENV_ENABLE_ADMIN: &str = "CACHE_ENABLE_ADMIN";
+
+const DEFAULT_HOST: &str = "127.0.0.1";
+const DEFAULT_PORT: u16 = 3000;
+const DEFAULT_MAX_CAPACITY: usize = 10_000;
+
+/// Metric name prefix of the `metrics` command.
+const METRICS_PREFIX: &str = "cache";
+
+/// How long a connection may sit idle between requests.
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Longest request line accepted, in bytes.
+const DEFAULT_MAX_FRAME_SIZE: usize = 1 << 20;
+
+/// Number of per-client buckets above which fully refilled ones are
+/// dropped.
+const CLIENT_BUCKETS_PRUNE_AT: usize = 1024;
+
+/// Where an effective configuration value came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigSource {
+    /// Built-in default.
+    Default,
+    /// The file given with `--config`.
+    File(PathBuf),
+    /// An environment variable.
+    Env(&'static str),
+    /// A command-line flag.
+    Flag(&'static str),
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::Default => write!(f, "default"),
+            ConfigSource::File(path) => write!(f, "file {}", path.display()),
+            ConfigSource::Env(name) => write!(f, "env {}", name),
+            ConfigSource::Flag(name) => write!(f, "flag {}", name),
+        }
+    }
+}
+
+/// A resolved configuration value together with its source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Setting<T> {
+    pub value: T,
+    pub source: ConfigSource,
+}
+
+impl<T> Setting<T> {
+    fn default_value(value: T) -> Self {
+        Self {
+            value,
+            source: ConfigSource::Default,
+        }
+    }
+}
+
+/// The effective server configuration after all sources are applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedServerConfig {
+    pub host: Setting<String>,
+    pub port: Setting<u16>,
+    /// `None` means unlimited.
+    pub max_capacity: Setting<Option<usize>>,
+    /// `None` means entries without a TTL never expire.
+    pub default_ttl: Setting<Option<Duration>>,
+    /// `None` disables the periodic sweep.
+    pub cleanup_interval: Setting<Option<Duration>>,
+    pub background_cleanup: Setting<bool>,
+    /// Memory limit in bytes. `None` means unlimited.
+    pub max_memory: Setting<Option<u64>>,
+    pub record_lock_waits: Setting<bool>,
+    /// Whether admin commands such as `stats reset` are accepted.
+    pub enable_admin: Setting<bool>,
+    /// How long a connection may sit idle before the server closes it.
+    pub idle_timeout: Setting<Duration>,
+    /// Longest request line accepted, in bytes.
+    pub max_frame_size: Setting<usize>,
+    /// Commands per second per client IP. `None` means unlimited.
+    pub max_ops_per_conn: Setting<Option<u32>>,
+    /// Commands per second across all clients. `None` means unlimited.
+    pub max_ops_global: Setting<Option<u32>>,
+    /// Soft size limit in percent of `max_capacity`. `None` means off.
+    pub warn_size_pct: Setting<Option<u8>>,
+    /// Soft minimum hit rate in percent. `None` means off.
+    pub warn_hit_rate_pct: Setting<Option<u8>>,
+    /// Soft limit on evictions per minute. `None` means off.
+    pub warn_evictions_per_min: Setting<Option<u64>>,
+    /// Soft limit on estimated memory use. `None` means off.
+    pub warn_memory_bytes: Setting<Option<u64>>,
+}
+
+impl ResolvedServerConfig {
+    /// Resolve the configuration from the command line and an environment
+    /// lookup. The `--config` file overrides the built-in defaults,
+    /// environment variables override the file, and command-line flags
+    /// override everything.
+    ///
+    /// Errors name the exact source of the invalid value.
+    pub fn resolve(cli: &ServerCli, env: impl Fn(&str) -> Option<String>) -> CacheResult<Self> {
+        let mut resolved = Self {
+            host: Setting::default_value(DEFAULT_HOST.to_string()),
+            port: Setting::default_value(DEFAULT_PORT),
+            max_capacity: Setting::default_value(Some(DEFAULT_MAX_CAPACITY)),
+            default_ttl: Setting::default_value(None),
+            cleanup_interval: Setting::default_value(CacheConfig::new().cleanup_interval),
+            background_cleanup: Setting::default_value(false),
+            max_memory: Setting::default_value(None),
+            record_lock_waits: Setting::default_value(false),
+            enable_admin: Setting::default_value(false),
+            idle_timeout: Setting::default_value(DEFAULT_IDLE_TIMEOUT),
+            max_frame_size: Setting::default_value(DEFAULT_MAX_FRAME_SIZE),
+            max_ops_per_conn: Setting::default_value(None),
+            max_ops_global: Setting::default_value(None),
+            warn_size_pct: Setting::default_value(None),
+            warn_hit_rate_pct: Setting::default_value(None),
+            warn_evictions_per_min: Setting::default_value(None),
+            warn_memory_bytes: Setting::default_value(None),
+        };
+
+        if let Some(path) = &cli.config {
+            resolved.apply_file(path)?;
+        }
+        if let Some(host) = env(ENV_HOST) {
+            if host.trim().is_empty() {
+                return Err(invalid(ENV_HOST, &host, "host must not be empty"));
+            }
+            resolved.host = from_env(ENV_HOST, host);
+        }
+        if let Some(raw) = env(ENV_PORT) {
+            let port = raw
+                .trim()
+                .parse::<u16>()
+                .map_err(|e| invalid(ENV_PORT, &raw, &e.to_string()))?;
+            resolved.port = from_env(ENV_PORT, port);
+        }
+        if let Some(raw) = env(ENV_MAX_CAPACITY) {
+            let capacity = raw
+                .trim()
+                .parse::<usize>()
+                .map_err(|e| invalid(ENV_MAX_CAPACITY, &raw, &e.to_string()))?;
+            let capacity = if capacity == 0 { None } else { Some(capacity) };
+            resolved.max_capacity = from_env(ENV_MAX_CAPACITY, capacity);
+        }
+        if let Some(raw) = env(ENV_DEFAULT_TTL) {
+            let secs = raw
+                .trim()
+                .parse::<u64>()
+                .map_err(|e| invalid(ENV_DEFAULT_TTL, &raw, &e.to_string()))?;
+            resolved.default_ttl = from_env(ENV_DEFAULT_TTL, seconds(secs));
+        }
+        if let Some(raw) = env(ENV_CLEANUP_INTERVAL) {
+            let secs = raw
+                .trim()
+                .parse::<u64>()
+                .map_err(|e| invalid(ENV_CLEANUP_INTERVAL, &raw, &e.to_string()))?;
+            resolved.cleanup_interval = from_env(ENV_CLEANUP_INTERVAL, seconds(secs));
+            resolved.background_cleanup = from_env(ENV_CLEANUP_INTERVAL, secs != 0);
+        }
+        if let Some(raw) = env(ENV_RECORD_LOCK_WAITS) {
+            let enabled = parse_bool(&raw)
+                .ok_or_else(|| invalid(ENV_RECORD_LOCK_WAITS, &raw, "expected true or false"))?;
+            resolved.record_lock_waits = from_env(ENV_RECORD_LOCK_WAITS, enabled);
+        }
+        if let Some(raw) = env(ENV_ENABLE_ADMIN) {
+            let enabled = parse_bool(&raw)
+                .ok_or_else(|| invalid(ENV_ENABLE_ADMIN, &raw, "expected true or false"))?;
+            resolved.enable_admin = from_env(ENV_ENABLE_ADMIN, enabled);
+        }
+        if let Some(host) = &cli.host {
+            resolved.host = Setting {
+                value: host.clone(),
+                source: ConfigSource::Flag("--host"),
+            };
+        }
+        if let Some(port) = cli.port {
+            resolved.port = Setting {
+                value: port,
+                source: ConfigSource::Flag("--port"),
+            };
+        }
+        if let Some(capacity) = cli.max_capacity {
+            resolved.max_capacity = Setting {
+                value: (capacity != 0).then_some(capacity),
+                source: ConfigSource::Flag("--max-capacity"),
+            };
+        }
+        if let Some(secs) = cli.default_ttl {
+            resolved.default_ttl = Setting {
+                value: seconds(secs),
+                source: ConfigSource::Flag("--default-ttl"),
+            };
+        }
+        if let Some(secs) = cli.cleanup_interval {
+            resolved.cleanup_interval = Setting {
+                value: seconds(secs),
+                source: ConfigSource::Flag("--cleanup-interval"),
+            };
+            resolved.background_cleanup = Setting {
+                value: secs != 0,
+                source: ConfigSource::Flag("--cleanup-interval"),
+            };
+        }
+        if cli.enable_admin {
+            resolved.enable_admin = Setting {
+                value: true,
+                source: ConfigSource::Flag("--enable-admin"),
+            };
+        }
+        if let Some(secs) = cli.idle_timeout {
+            resolved.idle_timeout = Setting {
+                value: Duration::from_secs(secs),
+                source: ConfigSource::Flag("--idle-timeout"),
+            };
+        }
+        if let Some(bytes) = cli.max_frame_size {
+            resolved.max_frame_size = Setting {
+                value: bytes,
+                source: ConfigSource::Flag("--max-frame-size"),
+            };
+        }
+        if let Some(limit) = cli.max_ops_per_conn {
+            resolved.max_ops_per_conn = Setting {
+                value: Some(limit),
+                source: ConfigSource::Flag("--max-ops-per-conn"),
+            };
+        }
+        if let Some(limit) = cli.max_ops_global {
+            resolved.max_ops_global = Setting {
+                value: Some(limit),
+                source: ConfigSource::Flag("--max-ops-global"),
+            };
+        }
+        if let Some(pct) = cli.warn_size_pct {
+            resolved.warn_size_pct = Setting {
+                value: Some(pct),
+                source: ConfigSource::Flag("--warn-size-pct"),
+            };
+        }
+        if let Some(pct) = cli.warn_hit_rate_pct {
+            resolved.warn_hit_rate_pct = Setting {
+                value: Some(pct),
+                source: ConfigSource::Flag("--warn-hit-rate-pct"),
+            };
+        }
+        if let Some(rate) = cli.warn_evictions_per_min {
+            resolved.warn_evictions_per_min = Setting {
+                value: Some(rate),
+                source: ConfigSource::Flag("--warn-evictions-per-min"),
+            };
+        }
+        if let Some(bytes) = cli.warn_memory_bytes {
+            resolved.warn_memory_bytes = Setting {
+                value: Some(bytes),
+                source: ConfigSource::Flag("--warn-memory-bytes"),
+            };
+        }
+
+        Ok(resolved)
+    }
+
+    /// Take the settings `path` names, leaving the others alone.
+    fn apply_file(&mut self, path: &Path) -> CacheResult<()> {
+        let file = ConfigOverrides::from_file(path)?;
+        if let Some(capacity) = file.max_capacity {
+            self.max_capacity = from_file(path, (capacity != 0).then_some(capacity));
+        }
+        if let Some(ttl) = file.default_ttl {
+            self.default_ttl = from_file(path, (!ttl.is_zero()).then_some(ttl));
+        }
+        if let Some(interval) = file.cleanup_interval {
+            self.cleanup_interval = from_file(path, (!interval.is_zero()).then_some(interval));
+        }
+        if let Some(enabled) = file.background_cleanup {
+            self.background_cleanup = from_file(path, enabled);
+        }
+        if let Some(bytes) = file.max_memory {
+            self.max_memory = from_file(path, (bytes != 0).then_some(bytes));
+        }
+        Ok(())
+    }
+
+    /// Resolve using the real process environment.
+    pub fn from_process_env(cli: &ServerCli) -> CacheResult<Self> {
+        Self::resolve(cli, |name| std::env::var(name).ok())
+    }
+
+    /// The `host:port` address to bind.
+    pub fn addr(&self) -> String {
+        format!("{}:{}", self.host.value, self.port.value)
+    }
+
+    /// Build the cache configuration for this server.
+    pub fn cache_config(&self) -> CacheConfig {
+        CacheConfig::new()
+            .max_capacity(self.max_capacity.value.unwrap_or(0))
+            .default_ttl(self.default_ttl.value.unwrap_or(Duration::ZERO))
+            .cleanup_interval(self.cleanup_interval.value.unwrap_or(Duration::ZERO))
+            .background_cleanup(self.background_cleanup.value)
+            .max_memory(self.max_memory.value.unwrap_or(0))
+            .record_lock_waits(self.record_lock_waits.value)
+            .health_thresholds(self.health_thresholds())
+            .build()
+    }
+
+    /// The soft thresholds of the `--warn-*` flags.
+    pub fn health_thresholds(&self) -> HealthThresholds {
+        let mut thresholds = HealthThresholds::new();
+        if let Some(pct) = self.warn_size_pct.value {
+            thresholds = thresholds.max_size_ratio(f64::from(pct) / 100.0);
+        }
+        if let Some(pct) = self.warn_hit_rate_pct.value {
+            thresholds = thresholds.min_hit_rate(f64::from(pct));
+        }
+        if let Some(rate) = self.warn_evictions_per_min.value {
+            thresholds = thresholds.max_evictions_per_minute(rate as f64);
+        }
+        if let Some(bytes) = self.warn_memory_bytes.value {
+            thresholds = thresholds.max_memory_bytes(usize::try_from(bytes).unwrap_or(usize::MAX));
+        }
+        thresholds
+    }
+}
+
+impl fmt::Display for ResolvedServerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "host = {} ({})", self.host.value, self.host.source)?;
+        writeln!(f, "port = {} ({})", self.port.value, self.port.source)?;
+        match self.max_capacity.value {
+            Some(capacity) => writeln!(
+                f,
+                "max_capacity = {} ({})",
+                capacity, self.max_capacity.source
+            )?,
+            None => writeln!(f, "max_capacity = unlimited ({})", self.max_capacity.source)?,
+        }
+        writeln!(
+            f,
+            "default_ttl = {} ({})",
+            off(self.default_ttl.value.map(secs)),
+            self.default_ttl.source
+        )?;
+        writeln!(
+            f,
+            "cleanup_interval = {} ({})",
+            off(self.cleanup_interval.value.map(secs)),
+            self.cleanup_interval.source
+        )?;
+        writeln!(
+            f,
+            "background_cleanup = {} ({})",
+            self.background_cleanup.value, self.background_cleanup.source
+        )?;
+        writeln!(
+            f,
+            "max_memory = {} ({})",
+            limit(self.max_memory.value),
+            self.max_memory.source
+        )?;
+        writeln!(
+            f,
+            "record_lock_waits = {} ({})",
+            self.record_lock_waits.value, self.record_lock_waits.source
+        )?;
+        writeln!(
+            f,
+            "enable_admin = {} ({})",
+            self.enable_admin.value, self.enable_admin.source
+        )?;
+        writeln!(
+            f,
+            "idle_timeout = {} ({})",
+            secs(self.idle_timeout.value),
+            self.idle_timeout.source
+        )?;
+        writeln!(
+            f,
+            "max_frame_size = {} ({})",
+            self.max_frame_size.value, self.max_frame_size.source
+        )?;
+        writeln!(
+            f,
+            "max_ops_per_conn = {} ({})",
+            limit(self.max_ops_per_conn.value),
+            self.max_ops_per_conn.source
+        )?;
+        writeln!(
+            f,
+            "max_ops_global = {} ({})",
+            limit(self.max_ops_global.value),
+            self.max_ops_global.source
+        )?;
+        writeln!(
+            f,
+            "warn_size_pct = {} ({})",
+            off(self.warn_size_pct.value),
+            self.warn_size_pct.source
+        )?;
+        writeln!(
+            f,
+            "warn_hit_rate_pct = {} ({})",
+            off(self.warn_hit_rate_pct.value),
+            self.warn_hit_rate_pct.source
+        )?;
+        writeln!(
+            f,
+            "warn_evictions_per_min = {} ({})",
+            off(self.warn_evictions_per_min.value),
+            self.warn_evictions_per_min.source
+        )?;
+        write!(
+            f,
+            "warn_memory_bytes = {} ({})",
+            off(self.warn_memory_bytes.value),
+            self.warn_memory_bytes.source
+        )
+    }
+}
+
+/// Process-wide server state reported by `info`.
+#[derive(Debug)]
+pub struct ServerState {
+    config: ResolvedServerConfig,
+    started_at: Instant,
+    connected: AtomicU64,
+    total_connections: AtomicU64,
+    /// Buckets of `--max-ops-per-conn`, one per client IP.
+    client_buckets: Mutex<HashMap<IpAddr, TokenBucket>>,
+    /// Bucket of `--max-ops-global`.
+    global_bucket: Option<Mutex<TokenBucket>>,
+    throttled: AtomicU64,
+}
+
+impl ServerState {
+    /// Create the state for a server running with `config`.
+    pub fn new(config: ResolvedServerConfig) -> Self {
+        let started_at = Instant::now();
+        let global_bucket = config
+            .max_ops_global
+            .value
+            .map(|limit| Mutex::new(TokenBucket::new(limit, limit, started_at)));
+        Self {
+            config,
+            started_at,
+            connected: AtomicU64::new(0),
+            total_connections: AtomicU64::new(0),
+            client_buckets: Mutex::new(HashMap::new()),
+            global_bucket,
+            throttled: AtomicU64::new(0),
+        }
+    }
+
+    /// The effective configuration.
+    pub fn config(&self) -> &ResolvedServerConfig {
+        &self.config
+    }
+
+    /// Time since the server started.
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    /// Count a new connection. It is counted as connected until the
+    /// returned guard is dropped.
+    pub fn connection_opened(self: &Arc<Self>) -> ConnectionGuard {
+        self.connected.fetch_add(1, Ordering::Relaxed);
+        self.total_connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard {
+            state: Arc::clone(self),
+        }
+    }
+
+    /// Number of currently open connections.
+    pub fn connected(&self) -> u64 {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    /// Number of connections accepted since startup.
+    pub fn total_connections(&self) -> u64 {
+        self.total_connections.load(Ordering::Relaxed)
+    }
+
+    /// Decide whether a command from `client` may run now, under the
+    /// per-client and global rate limits. A refused command is counted in
+    /// [`ServerState::throttled`]; the error holds how long the client
+    /// should wait before retrying.
+    pub fn admit(&self, client: IpAddr) -> Result<(), Duration> {
+        self.admit_at(client, Instant::now())
+    }
+
+    /// [`ServerState::admit`] at a given instant.
+    pub fn admit_at(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        let result = self.acquire(client, now);
+        if result.is_err() {
+            self.throttled.fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Number of commands refused by the rate limits since startup.
+    pub fn throttled(&self) -> u64 {
+        self.throttled.load(Ordering::Relaxed)
+    }
+
+    /// Take a token from the client's bucket, then from the global one. A
+    /// command refused by the global limit still uses up its client token.
+    fn acquire(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        if let Some(limit) = self.config.max_ops_per_conn.value {
+            let mut buckets = lock(&self.client_buckets);
+            if buckets.len() >= CLIENT_BUCKETS_PRUNE_AT {
+                // A full bucket behaves exactly like a fresh one
+                buckets.retain(|_, bucket| !bucket.is_full(now));
+            }
+            buckets
+                .entry(client)
+                .or_insert_with(|| TokenBucket::new(limit, limit, now))
+                .try_acquire(now)?;
+        }
+        if let Some(bucket) = &self.global_bucket {
+            lock(bucket).try_acquire(now)?;
+        }
+        Ok(())
+    }
+}
+
+/// The reply to a command refused by the rate limits.
+pub fn rate_limited_reply(retry_after: Duration) -> String {
+    let reply = ProtocolError::RateLimited { retry_after }.to_wire();
+    String::from_utf8_lossy(&reply).into_owned()
+}
+
+/// Rate limit state only holds buckets, so a poisoned lock is recovered.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Keeps a connection counted in [`ServerState::connected`] while alive.
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    state: Arc<ServerState>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.state.connected.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A section of the `info` response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InfoSection {
+    /// Version, uptime, pid and protocol.
+    Server,
+    /// Approximate memory use, entry count and capacity.
+    Memory,
+    /// The full statistics snapshot.
+    Stats,
+    /// Effective configuration limits.
+    Config,
+    /// Connection counts.
+    Clients,
+    /// Entries by remaining TTL.
+    Keyspace,
+    /// Soft threshold alerts and the metrics they watch.
+    Health,
+}
+
+impl InfoSection {
+    /// All sections, in the order `info` without arguments renders them.
+    pub const ALL: [InfoSection; 7] = [
+        InfoSection::Server,
+        InfoSection::Memory,
+        InfoSection::Stats,
+        InfoSection::Config,
+        InfoSection::Clients,
+        InfoSection::Keyspace,
+        InfoSection::Health,
+    ];
+
+    /// Parse a section name (case-insensitive).
+    pub fn parse(name: &str) -> ProtocolResult<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|section| section.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                ProtocolError::InvalidArgument(format!("unknown info section '{}'", name))
+            })
+    }
+
+    /// The section name as used on the wire.
+    pub fn name(&self) -> &'static str {
+        match self {
+            InfoSection::Server => "server",
+            InfoSection::Memory => "memory",
+            InfoSection::Stats => "stats",
+            InfoSection::Config => "config",
+            InfoSection::Clients => "clients",
+            InfoSection::Keyspace => "keyspace",
+            InfoSection::Health => "health",
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            InfoSection::Server => "Server",
+            InfoSection::Memory => "Memory",
+            InfoSection::Stats => "Stats",
+            InfoSection::Config => "Config",
+            InfoSection::Clients => "Clients",
+            InfoSection::Keyspace => "Keyspace",
+            InfoSection::Health => "Health",
+        }
+    }
+}
+
+/// Remaining-TTL buckets reported in the `keyspace` section.
+const KEYSPACE_TTL_BUCKETS: [Duration; 4] = [
+    Duration::from_secs(60),
+    Duration::from_secs(300),
+    Duration::from_secs(3600),
+    Duration::MAX,
+];
+
+/// Render the `info` response for `section`, or all sections if `None`.
+///
+/// Each section starts with a `# Title` line followed by `field:value`
+/// lines; sections are separated by a blank line.
+pub fn render_info(cache: &Cache, state: &ServerState, section: Option<InfoSection>) -> String {
+    let sections: &[InfoSection] = match &section {
+        Some(section) => std::slice::from_ref(section),
+        None => &InfoSection::ALL,
+    };
+
+    let mut out = String::new();
+    for (i, section) in sections.iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        let _ = writeln!(out, "# {}", section.title());
+        for (field, value) in info_fields(cache, state, *section) {
+            let _ = writeln!(out, "{}:{}", field, value);
+        }
+    }
+    out
+}
+
+fn info_fields(
+    cache: &Cache,
+    state: &ServerState,
+    section: InfoSection,
+) -> Vec<(&'static str, String)> {
+    let config = state.config();
+    match section {
+        InfoSection::Server => {
+            let version = crate::version();
+            vec![
+                ("version", version.crate_version.to_string()),
+                ("git_hash", version.git_hash.to_string()),
+                ("features", version.features.join(",")),
+                ("uptime_seconds", state.uptime().as_secs().to_string()),
+                ("pid", std::process::id().to_string()),
+                ("protocol", "text".to_string()),
+                ("protocol_version", version.protocol_version.to_string()),
+            ]
+        }
+        InfoSection::Memory => vec![
+            ("approx_bytes", cache.approx_bytes().to_string()),
+            ("entries", cache.len().to_string()),
+            ("max_capacity", limit(config.max_capacity.value)),
+            ("weight", cache.stats().total_weight.to_string()),
+            ("max_weight", limit(cache.config().max_weight)),
+            ("weigher", weigher_mode(cache).to_string()),
+        ],
+        InfoSection::Stats => {
+            let stats = cache.stats();
+            vec![
+                ("hits", stats.hits.to_string()),
+                ("misses", stats.misses.to_string()),
+                ("hit_rate", format!("{:.1}%", stats.hit_rate)),
+                ("sets", stats.sets.to_string()),
+                ("inserts", stats.inserts.to_string()),
+                ("overwrites", stats.overwrites.to_string()),
+                ("deletes", stats.deletes.to_string()),
+                ("size", stats.size.to_string()),
+                ("evictions", stats.evictions.to_string()),
+                ("bytes_evicted_total", stats.bytes_evicted.to_string()),
+                ("expirations", stats.expirations.to_string()),
+                ("expirations_lazy", stats.expirations_lazy.to_string()),
+                ("expirations_swept", stats.expirations_swept.to_string()),
+                ("dropped_sets", stats.dropped_sets.to_string()),
+                ("coalesced_sets", stats.coalesced_sets.to_string()),
+                ("rejected_sets", stats.rejected_sets.to_string()),
+                ("callback_panics", stats.callback_panics.to_string()),
+                ("lock_failures", stats.lock_failures.to_string()),
+                ("spill_hits", stats.spill_hits.to_string()),
+                ("spill_writes", stats.spill_writes.to_string()),
+                ("spill_errors", stats.spill_errors.to_string()),
+                ("shadow_hits", stats.shadow_hits.to_string()),
+                (
+                    "checksum_verifications",
+                    stats.checksum_verifications.to_string(),
+                ),
+                ("checksum_failures", stats.checksum_failures.to_string()),
+                ("loader_timeouts", stats.loader_timeouts.to_string()),
+                ("error_hits", stats.error_hits.to_string()),
+                ("lock_wait_p50_ns", stats.lock_wait_p50_ns.to_string()),
+                ("lock_wait_p99_ns", stats.lock_wait_p99_ns.to_string()),
+                ("cleanup_interval_ms", stats.cleanup_interval_ms.to_string()),
+                ("last_sweep_removed", stats.last_sweep_removed.to_string()),
+                ("last_sweep_scanned", stats.last_sweep_scanned.to_string()),
+                ("throttled_requests", state.throttled().to_string()),
+            ]
+        }
+        InfoSection::Config => vec![
+            ("bind", config.addr()),
+            ("max_capacity", limit(config.max_capacity.value)),
+            (
+                "record_lock_waits",
+                config.record_lock_waits.value.to_string(),
+            ),
+            ("enable_admin", config.enable_admin.value.to_string()),
+            ("max_ops_per_conn", limit(config.max_ops_per_conn.value)),
+            ("max_ops_global", limit(config.max_ops_global.value)),
+        ],
+        InfoSection::Clients => vec![
+            ("connected_clients", state.connected().to_string()),
+            ("total_connections", state.total_connections().to_string()),
+        ],
+        InfoSection::Keyspace => {
+            let counts = cache.expiration_histogram(&KEYSPACE_TTL_BUCKETS);
+            vec![
+                ("ttl_le_1m", counts[0].to_string()),
+                ("ttl_le_5m", counts[1].to_string()),
+                ("ttl_le_1h", counts[2].to_string()),
+                ("ttl_gt_1h", counts[3].to_string()),
+                ("ttl_none", counts[4].to_string()),
+            ]
+        }
+        InfoSection::Health => {
+            let report = cache.health();
+            let alerts: Vec<&str> = report.alerts.iter().map(|metric| metric.name()).collect();
+            let mut fields = vec![
+                (
+                    "status",
+                    if report.is_healthy() { "ok" } else { "warn" }.to_string(),
+                ),
+                ("alerts", alerts.join(",")),
+            ];
+            // Metrics that cannot be measured right now are left out
+            for metric in HealthMetric::ALL {
+                if let Some(value) = report.value(metric) {
+                    fields.push((metric.name(), metric.format_value(value)));
+                }
+            }
+            fields
+        }
+    }
+}
+
+/// A parsed `stats` request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StatsRequest {
+    /// `stats`: the global counters.
+    Global,
+    /// `stats prefix <p>`: the global counters plus a block for keys
+    /// starting with `p`.
+    Prefix(String),
+    /// `stats reset`: zero the counters. Requires `--enable-admin`.
+    Reset,
+}
+
+impl StatsRequest {
+    /// Parse the arguments following `stats` (subcommands are
+    /// case-insensitive).
+    pub fn parse(args: &[String]) -> ProtocolResult<Self> {
+        let usage =
+            || ProtocolError::InvalidArgument("usage: stats [prefix <p> | reset]".to_string());
+        match args {
+            [] => Ok(StatsRequest::Global),
+            [sub, prefix] if sub.eq_ignore_ascii_case("prefix") => {
+                Ok(StatsRequest::Prefix(prefix.clone()))
+            }
+            [sub] if sub.eq_ignore_ascii_case("reset") => Ok(StatsRequest::Reset),
+            _ => Err(usage()),
+        }
+    }
+}
+
+/// Run a `stats` request and render its response.
+///
+/// The first line is always the global counters as `field:value` pairs. A
+/// prefix request adds a second line `prefix <p> entries:N bytes:N
+/// with_ttl:N`. A reset replies `Ok`, or fails unless admin commands are
+/// enabled.
+pub fn render_stats(
+    cache: &Cache,
+    state: &ServerState,
+    request: &StatsRequest,
+) -> ProtocolResult<String> {
+    match request {
+        StatsRequest::Global => Ok(stats_line(&cache.stats(), cache.config().max_weight)),
+        StatsRequest::Prefix(prefix) => {
+            let scoped = cache.prefix_stats(prefix);
+            Ok(format!(
+                "{}\nprefix {} entries:{} bytes:{} with_ttl:{}",
+                stats_line(&cache.stats(), cache.config().max_weight),
+                prefix,
+                scoped.entries,
+                scoped.bytes,
+                scoped.with_ttl
+            ))
+        }
+        StatsRequest::Reset => {
+            require_admin(state)?;
+            cache.reset_stats();
+            Ok("Ok".to_string())
+        }
+    }
+}
+
+fn stats_line(stats: &StatsSnapshot, max_weight: Option<u64>) -> String {
+    format!(
+        "hits:{} misses:{} size:{} hit_rate:{:.1}% evictions:{} bytes_evicted_total:{} \
+         weight:{} max_weight:{} inserts:{} overwrites:{} expirations_lazy:{} \
+         expirations_swept:{} lock_wait_p50_ns:{} lock_wait_p99_ns:{}",
+        stats.hits,
+        stats.misses,
+        stats.size,
+        stats.hit_rate,
+        stats.evictions,
+        stats.bytes_evicted,
+        stats.total_weight,
+        limit(max_weight),
+        stats.inserts,
+        stats.overwrites,
+        stats.expirations_lazy,
+        stats.expirations_swept,
+        stats.lock_wait_p50_ns,
+        stats.lock_wait_p99_ns
+    )
+}
+
+/// A soft threshold for display; `off` when unset.
+fn off(value: Option<impl fmt::Display>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => "off".to_string(),
+    }
+}
+
+fn limit(value: Option<impl fmt::Display>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => "unlimited".to_string(),
+    }
+}
+
+/// How entries are weighed: `custom` with a weigher, otherwise `unit`, as
+/// every entry then weighs 1.
+fn weigher_mode(cache: &Cache) -> &'static str {
+    if cache.config().weigher.is_some() {
+        "custom"
+    } else {
+        "unit"
+    }
+}
+
+/// A whole-second duration for display, such as `300s`.
+fn secs(duration: Duration) -> String {
+    format!("{}s", duration.as_secs())
+}
+
+/// A duration of `secs` seconds, `None` for zero.
+fn seconds(secs: u64) -> Option<Duration> {
+    (secs != 0).then(|| Duration::from_secs(secs))
+}
+
+fn from_file<T>(path: &Path, value: T) -> Setting<T> {
+    Setting {
+        value,
+        source: ConfigSource::File(path.to_path_buf()),
+    }
+}
+
+fn from_env<T>(name: &'static str, value: T) -> Setting<T> {
+    Setting {
+        value,
+        source: ConfigSource::Env(name),
+    }
+}
+
+fn parse_bool(raw: &str) -> Option<bool> {
+    match raw.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+fn invalid(name: &str, raw: &str, reason: &str) -> CacheError {
+    CacheError::InvalidConfig(format!(
+        "invalid value '{}' for {} (environment): {}",
+        raw, name, reason
+    ))
+}
+
+/// Serve a client connection: answer each request line in order until
+/// the peer closes the connection or stays idle for the configured
+/// `idle_timeout`. A last request without a trailing newline is answered
+/// when the peer closes its side.
+pub async fn handle_connection(
+    mut socket: TcpStream,
+    peer: SocketAddr,
+    cache: &Cache,
+    state: &ServerState,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let idle_timeout = state.config().idle_timeout.value;
+    let mut framer = RequestFramer::new(state.config().max_frame_size.value);
+    // Reply codec, as negotiated by the last `hello`
+    let mut compression = Compression::None;
+
+    loop {
+        while let Some(frame) = framer.next_frame() {
+            let reply = respond(frame, peer, cache, state, &mut compression);
+            socket
+                .write_all(&encode_reply_with(&reply, compression))
+                .await?;
+        }
+
+        let read = tokio::time::timeout(idle_timeout, socket.read_buf(framer.buffer())).await;
+        let read = match read {
+            Ok(read) => read?,
+            Err(_) => return Ok(()), // Idle for too long
+        };
+        if read == 0 {
+            if let Some(frame) = framer.finish() {
+                let reply = respond(frame, peer, cache, state, &mut compression);
+                socket
+                    .write_all(&encode_reply_with(&reply, compression))
+                    .await?;
+            }
+            return Ok(()); // Connection closed
+        }
+    }
+}
+
+/// Answer one request line, echoing its trace id. A `hello` switches the
+/// connection to the codec it negotiated.
+fn respond(
+    frame: ProtocolResult<BytesMut>,
+    peer: SocketAddr,
+    cache: &Cache,
+    state: &ServerState,
+    compression: &mut Compression,
+) -> Bytes {
+    let mut line = match frame {
+        Ok(line) => line,
+        Err(e) => return e.to_wire(),
+    };
+    // Parse the command, with an optional leading trace id
+    let words = buffer_to_array(&mut line);
+    let request = match Request::parse(&words) {
+        Ok(request) => request,
+        Err(e) => {
+            // A valid trace id is still echoed
+            let trace_id = split_trace_id(&words).ok().and_then(|(id, _)| id);
+            return prefix_trace_id(trace_id, &e.to_wire());
+        }
+    };
+
+    let trace_id = request.trace_id;
+    let span = tracing::info_span!("request", trace_id, command = %request.command, %peer);
+    let _entered = span.enter();
+    if trace_id.is_some() {
+        tracing::info!("traced request");
+    }
+
+    // Rate limits apply before any work is done for the command
+    if let Err(retry_after) = state.admit(peer.ip()) {
+        let reply = rate_limited_reply(retry_after);
+        return prefix_trace_id(trace_id, reply.as_bytes());
+    }
+
+    if request.command == Command::Hello {
+        *compression = hello_compression(request.attrs);
+    }
+    let response = process_command(request.command, request.attrs, cache, state);
+    prefix_trace_id(trace_id, &response)
+}
+
+/// The codec a `hello` request negotiates; unknown options are ignored so
+/// newer clients can still connect.
+fn hello_compression(attrs: &[String]) -> Compression {
+    let offered = attrs[1..]
+        .iter()
+        .find_map(|opt| opt.strip_prefix("compression="))
+        .unwrap_or("");
+    negotiate_compression(offered)
+}
+
+/// Process a cache command and return the response; failures are replied
+/// as [`ProtocolError::to_wire`].
+pub fn process_command(
+    command: Command,
+    attrs: &[String],
+    cache: &Cache,
+    state: &ServerState,
+) -> Bytes {
+    execute(command, attrs, cache, state).unwrap_or_else(|e| e.to_wire())
+}
+
+/// Run a command. `attrs` starts with the command word.
+fn execute(
+    command: Command,
+    attrs: &[String],
+    cache: &Cache,
+    state: &ServerState,
+) -> ProtocolResult<Bytes> {
+    match command {
+        Command::Get => {
+            arity(&command, attrs, 1)?;
+
+            let key = &attrs[1];
+            Ok(match cache.get(key) {
+                Some(value) => {
+                    // Convert bytes to string for response
+                    match std::str::from_utf8(&value) {
+                        Ok(_) => value,
+                        Err(_) => format!("(binary data: {} bytes)", value.len()).into(),
+                    }
+                }
+                None => Bytes::new(), // Empty string for not found (legacy behavior)
+            })
+        }
+
+        Command::MGet => {
+            arity(&command, attrs, 1)?;
+
+            let keys: Vec<&str> = attrs[1..].iter().map(String::as_str).collect();
+            Ok(encode_multi_bulk(&cache.multi_get(&keys)))
+        }
+
+        Command::Set => {
+            arity(&command, attrs, 2)?;
+
+            let key = &attrs[1];
+            let value = &attrs[2];
+            let (mut ttl, mut nx, mut verbose, mut quiet) = (None, false, false, false);
+            let mut options = attrs[3..].iter();
+            // Parsing stops at the first unknown word; the rest are ignored,
+            // as they always were
+            while let Some(option) = options.next() {
+                if option.eq_ignore_ascii_case("ex") {
+                    match options.next().and_then(|s| s.parse::<u64>().ok()) {
+                        Some(secs) if secs > 0 => ttl = Some(Duration::from_secs(secs)),
+                        _ => return Err(bad_arg("invalid expire time")),
+                    }
+                } else if option.eq_ignore_ascii_case("nx") {
+                    nx = true;
+                } else if option.eq_ignore_ascii_case("verbose") {
+                    verbose = true;
+                } else if option.eq_ignore_ascii_case("quiet") {
+                    quiet = true;
+                } else {
+                    break;
+                }
+            }
+
+            if nx {
+                // A rejected write would otherwise read as an existing key
+                cache.check_size(key, value.as_bytes())?;
+                let inserted = match ttl {
+                    Some(ttl) => cache.set_if_absent_with_ttl(key.clone(), value.clone(), ttl),
+                    None => cache.set_if_absent(key.clone(), value.clone()),
+                };
+                // An empty reply, like a miss, when the key already exists
+                return Ok(if inserted {
+                    Bytes::from("Ok")
+                } else {
+                    Bytes::new()
+                });
+            }
+
+            let (outcome, evicted, bytes_evicted) =
+                cache.try_set_returning_outcome(key.clone(), value.clone(), ttl, !quiet)?;
+
+            let reply = match outcome {
+                SetOutcome::Inserted => "Ok",                           // New key
+                SetOutcome::Replaced | SetOutcome::Unchanged => "r Ok", // Replaced
+                // Nothing was stored, whatever the key holds now
+                SetOutcome::Dropped => {
+                    return Err(CacheError::InvalidValue(format!(
+                        "value for '{}' could not be spilled",
+                        key
+                    ))
+                    .into())
+                }
+            };
+            // Bulk loaders can watch what their writes displace
+            Ok(if verbose {
+                format!(
+                    "{} evicted:{} bytes_evicted:{}",
+                    reply, evicted, bytes_evicted
+                )
+                .into()
+            } else {
+                Bytes::from(reply)
+            })
+        }
+
+        Command::Ttl => {
+            arity(&command, attrs, 1)?;
+
+            Ok(match cache.ttl(&attrs[1]) {
+                // Rounded up, so a live key never reports 0
+                Some(Some(ttl)) => ((ttl.as_millis() + 999) / 1000).to_string().into(),
+                Some(None) => Bytes::from("-1"),
+                None => Bytes::from("-2"),
+            })
+        }
+
+        Command::Keys => {
+            arity(&command, attrs, 1)?;
+            let reply: Vec<Option<Bytes>> = cache
+                .keys_matching(&attrs[1])
+                .into_iter()
+                .map(|key| Some(Bytes::from(key)))
+                .collect();
+            Ok(encode_multi_bulk(&reply))
+        }
+
+        Command::Scan => {
+            arity(&command, attrs, 1)?;
+            let cursor = attrs[1].parse::<ScanCursor>()?;
+            let count = match parse_arg(attrs.get(2), DEFAULT_SCAN_COUNT) {
+                Some(count) if count > 0 => count.min(MAX_SCAN_COUNT),
+                _ => return Err(bad_arg("invalid count")),
+            };
+            let (keys, next) = cache.scan_keys(cursor, count);
+            let reply: Vec<Option<Bytes>> = std::iter::once(next.to_string())
+                .chain(keys)
+                .map(|item| Some(Bytes::from(item)))
+                .collect();
+            Ok(encode_multi_bulk(&reply))
+        }
+
+        Command::Append => {
+            arity(&command, attrs, 2)?;
+            let len = cache.append(&attrs[1], attrs[2].clone());
+            Ok(len.to_string().into())
+        }
+
+        Command::Incr | Command::Decr => {
+            arity(&command, attrs, 1)?;
+            let delta = match attrs.get(2) {
+                Some(delta) => delta.parse::<i64>().map_err(|_| bad_arg("invalid delta"))?,
+                None => 1,
+            };
+            let count = if command == Command::Incr {
+                cache.increment(&attrs[1], delta)?
+            } else {
+                cache.decrement(&attrs[1], delta)?
+            };
+            Ok(count.to_string().into())
+        }
+
+        Command::IncrWindow => {
+            arity(&command, attrs, 3)?;
+            let delta = attrs[2]
+                .parse::<i64>()
+                .map_err(|_| bad_arg("invalid delta"))?;
+            let window = parse_window(&attrs[3]).ok_or_else(|| bad_arg("invalid window"))?;
+            let (count, reset_in) = cache.incr_window(&attrs[1], delta, window)?;
+            Ok(format!("count:{} reset_ms:{}", count, ceil_millis(reset_in)).into())
+        }
+
+        Command::RateLimit => {
+            arity(&command, attrs, 3)?;
+            let limit = attrs[2]
+                .parse::<u64>()
+                .map_err(|_| bad_arg("invalid limit"))?;
+            let window = parse_window(&attrs[3]).ok_or_else(|| bad_arg("invalid window"))?;
+            let decision = cache.rate_limit(&attrs[1], limit, window)?;
+            Ok(format!(
+                "allowed:{} remaining:{} retry_after_ms:{}",
+                u8::from(decision.allowed),
+                decision.remaining,
+                ceil_millis(decision.retry_after)
+            )
+            .into())
+        }
+
+        Command::Expire => {
+            arity(&command, attrs, 2)?;
+            let secs = attrs[2]
+                .parse::<u64>()
+                .ok()
+                .filter(|&secs| secs > 0)
+                .ok_or_else(|| bad_arg("invalid expire time"))?;
+            let existed = cache.expire(&attrs[1], Duration::from_secs(secs));
+            Ok(Bytes::from(if existed { "1" } else { "0" }))
+        }
+
+        Command::Persist => {
+            arity(&command, attrs, 1)?;
+            Ok(Bytes::from(if cache.persist(&attrs[1]) {
+                "1"
+            } else {
+                "0"
+            }))
+        }
+
+        Command::Delete => {
+            arity(&command, attrs, 1)?;
+
+            if let [_, key] = attrs {
+                return Ok(if cache.delete(key) {
+                    Bytes::from("Ok")
+                } else {
+                    Bytes::new() // Not found
+                });
+            }
+            // Several keys: reply with how many were removed
+            Ok(cache.delete_many(&attrs[1..]).to_string().into())
+        }
+
+        Command::DelPrefix => {
+            arity(&command, attrs, 1)?;
+            Ok(cache.delete_prefix(&attrs[1]).to_string().into())
+        }
+
+        Command::Ping => Ok(Bytes::from("PONG")),
+
+        Command::Stats => {
+            let request = StatsRequest::parse(&attrs[1..])?;
+            Ok(render_stats(cache, state, &request)?.into())
+        }
+
+        Command::Metrics => Ok(prometheus_text(&cache.stats(), METRICS_PREFIX).into()),
+
+        Command::Info => {
+            let section = attrs
+                .get(1)
+                .map(|name| InfoSection::parse(name))
+                .transpose()?;
+            Ok(render_info(cache, state, section).into())
+        }
+
+        Command::Debug => {
+            arity(&command, attrs, 1)?;
+            match attrs[1].to_ascii_lowercase().as_str() {
+                "memory" => {
+                    let samples = parse_arg(attrs.get(2), DEFAULT_MEMORY_SAMPLES)
+                        .ok_or_else(|| bad_arg("invalid sample count"))?;
+                    let depth = parse_arg(attrs.get(3), 1)
+                        .ok_or_else(|| bad_arg("invalid prefix depth"))?;
+                    Ok(cache.memory_breakdown(samples, depth).to_string().into())
+                }
+                "inflight" => Ok(render_in_flight(&cache.in_flight()).into()),
+                _ => Err(bad_arg(
+                    "usage: debug memory [samples] [depth] | debug inflight",
+                )),
+            }
+        }
+
+        Command::Purge => {
+            require_admin(state)?;
+            arity(&command, attrs, 1)?;
+            match attrs[1].to_ascii_lowercase().as_str() {
+                "idle" => {
+                    arity(&command, attrs, 2)?;
+                    let secs = attrs[2]
+                        .parse::<u64>()
+                        .map_err(|_| bad_arg("invalid idle seconds"))?;
+                    let removed = cache.purge_idle(Duration::from_secs(secs));
+                    Ok(format!("purged:{}", removed).into())
+                }
+                _ => Err(bad_arg("usage: purge idle <seconds>")),
+            }
+        }
+
+        Command::ExpirePattern => {
+            require_admin(state)?;
+            arity(&command, attrs, 2)?;
+            let pattern = &attrs[1];
+            let updated = if attrs[2].eq_ignore_ascii_case("persist") {
+                cache.persist_matching(pattern)
+            } else {
+                let secs = attrs[2]
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|&secs| secs > 0)
+                    .ok_or_else(|| bad_arg("invalid expire time"))?;
+                cache.expire_matching(pattern, Duration::from_secs(secs))
+            };
+            Ok(format!("updated:{}", updated).into())
+        }
+
+        Command::Hello => {
+            let version = crate::version();
+            Ok(format!(
+                "hello compression={} version={} protocol={}",
+                hello_compression(attrs).name(),
+                version.crate_version,
+                version.protocol_version
+            )
+            .into())
+        }
+
+        Command::Invalid => Err(ProtocolError::UnknownCommand(
+            attrs.first().cloned().unwrap_or_default(),
+        )),
+    }
+}
+
+/// Fail unless `attrs` holds at least `expected` arguments after the
+/// command word.
+fn arity(command: &Command, attrs: &[String], expected: usize) -> ProtocolResult<()> {
+    let got = attrs.len().saturating_sub(1);
+    if got < expected {
+        return Err(ProtocolError::WrongArity {
+            cmd: command.as_str().to_string(),
+            expected,
+            got,
+        });
+    }
+    Ok(())
+}
+
+/// A [`ProtocolError::InvalidArgument`] with `msg`.
+fn bad_arg(msg: &str) -> ProtocolError {
+    ProtocolError::InvalidArgument(msg.to_string())
+}
+
+/// Fail with [`ProtocolError::Unauthorized`] unless admin commands are
+/// enabled.
+fn require_admin(state: &ServerState) -> ProtocolResult<()> {
+    if state.config().enable_admin.value {
+        return Ok(());
+    }
+    Err(ProtocolError::Unauthorized(
+        "admin commands are disabled (start the server with --enable-admin)".to_string(),
+    ))
+}
+
+/// Render `debug inflight`: the number of loads in flight, then one
+/// `<key> elapsed_ms:<ms>` line per load, longest first.
+fn render_in_flight(flights: &[(String, Duration)]) -> String {
+    let mut out = format!("in_flight:{}", flights.len());
+    for (key, elapsed) in flights {
+        out.push_str(&format!("\n{} elapsed_ms:{}", key, elapsed.as_millis()));
+    }
+    out
+}
+
+/// Entries sampled by `debug memory` when no count is given.
+const DEFAULT_MEMORY_SAMPLES: usize = 1000;
+
+/// Keys per `scan` page when no count is given.
+const DEFAULT_SCAN_COUNT: usize = 10;
+
+/// Largest `scan` page, so one request cannot copy out the whole cache.
+const MAX_SCAN_COUNT: usize = 1000;
+
+/// Parse an optional numeric argument, using `default` when absent.
+fn parse_arg(arg: Option<&String>, default: usize) -> Option<usize> {
+    match arg {
+        Some(arg) => arg.parse().ok(),
+        None => Some(default),
+    }
+}
+
+/// Accept connections on `listener` and serve each on its own task. Runs
+/// until the task is dropped or aborted.
+///
+/// Connections and their failures are logged through `tracing`.
+pub async fn serve(listener: TcpListener, cache: Arc<Cache>, state: Arc<ServerState>) {
+    loop {
+        match listener.accept().await {
+            Ok((socket, addr)) => {
+                tracing::info!(peer = %addr, "connection opened");
+
+                let cache = Arc::clone(&cache);
+                let state = Arc::clone(&state);
+                let guard = state.connection_opened();
+
+                tokio::spawn(async move {
+                    let _guard = guard;
+                    if let Err(e) = handle_connection(socket, addr, &cache, &state).await {
+                        tracing::warn!(peer = %addr, error = %e, "connection failed");
+                    }
+                });
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "failed to accept a connection");
+            }
+        }
+    }
+}
+
+/// A counter window given in whole seconds; zero is rejected.
+fn parse_window(secs: &str) -> Option<Duration> {
+    match secs.parse::<u64>() {
+        Ok(secs) if secs > 0 => Some(Duration::from_secs(secs)),
+        _ => None,
+    }
+}
+
+/// How long one smoke test request may take before the step fails.
+const SMOKE_STEP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// One request of the smoke test script and the check its reply must pass.
+#[derive(Debug, Clone, Copy)]
+pub struct SmokeStep {
+    /// Short name used in the report.
+    pub name: &'static str,
+    /// The request line sent to the server.
+    pub request: &'static str,
+    check: fn(&[u8]) -> Result<(), String>,
+}
+
+impl SmokeStep {
+    /// Check `reply` against what this step expects; the error describes
+    /// the mismatch.
+    pub fn check(&self, reply: &[u8]) -> Result<(), String> {
+        (self.check)(reply)
+    }
+}
+
+/// The scripted client session of `server --smoke-test`.
+///
+/// The steps build on each other and expect a fresh server: the counters
+/// checked by the final `stats` step are those produced by the earlier
+/// steps.
+pub fn smoke_script() -> Vec<SmokeStep> {
+    vec![
+        SmokeStep {
+            name: "ping",
+            request: "ping",
+            check: |reply| expect_reply(reply, b"PONG"),
+        },
+        SmokeStep {
+            name: "set",
+            request: "set smoke:key hello ex 60",
+            check: |reply| expect_reply(reply, b"Ok"),
+        },
+        SmokeStep {
+            name: "get",
+            request: "get smoke:key",
+            check: |reply| expect_reply(reply, b"hello"),
+        },
+        SmokeStep {
+            name: "ttl",
+            request: "ttl smoke:key",
+            check: |reply| match std::str::from_utf8(reply).map(str::parse::<u64>) {
+                Ok(Ok(1..=60)) => Ok(()),
+                _ => Err(format!("expected 1 to 60 seconds, got {}", show(reply))),
+            },
+        },
+        SmokeStep {
+            name: "mget",
+            request: "mget smoke:key smoke:missing",
+            check: |reply| match decode_multi_bulk(reply) {
+                Ok(values) if values == [Some(Bytes::from("hello")), None] => Ok(()),
+                _ => Err(format!("expected [hello, nil], got {}", show(reply))),
+            },
+        },
+        SmokeStep {
+            name: "delete",
+            request: "delete smoke:key",
+            check: |reply| expect_reply(reply, b"Ok"),
+        },
+        SmokeStep {
+            name: "stats",
+            request: "stats",
+            // Hits from get and mget, the miss from mget, nothing left
+            check: |reply| {
+                if reply.starts_with(b"hits:2 misses:1 size:0 ") {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "expected hits:2 misses:1 size:0, got {}",
+                        show(reply)
+                    ))
+                }
+            },
+        },
+    ]
+}
+
+/// Outcome of one smoke test step.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SmokeResult {
+    pub name: &'static str,
+    /// `Err` describes why the step failed.
+    pub outcome: Result<(), String>,
+}
+
+/// Outcome of a smoke test run, one result per step.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SmokeReport {
+    pub results: Vec<SmokeResult>,
+}
+
+impl SmokeReport {
+    /// Whether every step passed.
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|result| result.outcome.is_ok())
+    }
+}
+
+impl fmt::Display for SmokeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for result in &self.results {
+            match &result.outcome {
+                Ok(()) => writeln!(f, "PASS {}", result.name)?,
+                Err(reason) => writeln!(f, "FAIL {}: {}", result.name, reason)?,
+            }
+        }
+        let passed = self.results.iter().filter(|r| r.outcome.is_ok()).count();
+        let verdict = if self.passed() { "passed" } else { "FAILED" };
+        write!(
+            f,
+            "smoke test {}: {}/{} steps passed",
+            verdict,
+            passed,
+            self.results.len()
+        )
+    }
+}
+
+/// Run [`smoke_script`] against the server at `addr`, one connection per
+/// step so that a step whose reply goes astray cannot throw later ones off.
+/// Later steps still run after a failure.
+pub async fn run_smoke_script(addr: SocketAddr) -> SmokeReport {
+    let mut results = Vec::new();
+    for step in smoke_script() {
+        let outcome =
+            match tokio::time::timeout(SMOKE_STEP_TIMEOUT, request(addr, step.request)).await {
+                Ok(Ok(reply)) => step.check(&reply),
+                Ok(Err(e)) => Err(format!("request failed: {}", e)),
+                Err(_) => Err(format!("no reply within {:?}", SMOKE_STEP_TIMEOUT)),
+            };
+        results.push(SmokeResult {
+            name: step.name,
+            outcome,
+        });
+    }
+    SmokeReport { results }
+}
+
+/// Start a server for `config` on an ephemeral localhost port, run
+/// [`smoke_script`] against it, and shut it down again.
+///
+/// The configured host and port are not used, so this works next to a
+/// running server.
+pub async fn smoke_test(config: ResolvedServerConfig) -> std::io::Result<SmokeReport> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let cache = Arc::new(Cache::new(config.cache_config()));
+    let state = Arc::new(ServerState::new(config));
+
+    let server = tokio::spawn(serve(listener, cache, state));
+    let report = run_smoke_script(addr).await;
+    server.abort();
+    Ok(report)
+}
+
+/// Send one request on a fresh connection and read its reply.
+async fn request(addr: SocketAddr, request: &str) -> std::io::Result<Bytes> {
+    let mut stream = TcpStream::connect(addr).await?;
+    stream
+        .write_all(format!("{}\n", request).as_bytes())
+        .await?;
+    read_reply(&mut stream, &mut BytesMut::new()).await
+}
+
+fn expect_reply(reply: &[u8], expected: &[u8]) -> Result<(), String> {
+    if reply == expected {
+        Ok(())
+    } else {
+        Err(format!("expected {}, got {}", show(expected), show(reply)))
+    }
+}
+
+/// A reply for error messages, quoted and lossily decoded.
+fn show(bytes: &[u8]) -> String {
+    format!("{:?}", String::from_utf8_lossy(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PROTOCOL_VERSION;
+    use clap::Parser;
+    use std::collections::HashMap;
+
+    fn resolve_with(vars: &[(&str, &str)]) -> CacheResult<ResolvedServerConfig> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let cli = ServerCli::parse_from(["server"]);
+        ResolvedServerConfig::resolve(&cli, |name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn test_resolve_defaults() {
+        let resolved = resolve_with(&[]).unwrap();
+        assert_eq!(resolved.addr(), "127.0.0.1:3000");
+        assert_eq!(resolved.max_capacity.value, Some(10_000));
+        assert_eq!(resolved.port.source, ConfigSource::Default);
+    }
+
+    #[test]
+    fn test_resolve_env_overrides() {
+        let resolved = resolve_with(&[(ENV_PORT, "4000"), (ENV_MAX_CAPACITY, "0")]).unwrap();
+        assert_eq!(resolved.port.value, 4000);
+        assert_eq!(resolved.port.source, ConfigSource::Env(ENV_PORT));
+        assert_eq!(resolved.max_capacity.value, None);
+        assert_eq!(resolved.cache_config().get_max_capacity(), None);
+
+        let resolved = resolve_with(&[(ENV_RECORD_LOCK_WAITS, "on")]).unwrap();
+        assert!(resolved.record_lock_waits.value);
+    }
+
+    #[test]
+    fn test_resolve_bind_and_cache_flags_override_env() {
+        let cli = ServerCli::parse_from([
+            "server",
+            "--host",
+            "0.0.0.0",
+            "--port",
+            "3001",
+            "--max-capacity",
+            "0",
+            "--default-ttl",
+            "300",
+        ]);
+        let vars: HashMap<&str, &str> = [
+            (ENV_HOST, "10.0.0.1"),
+            (ENV_PORT, "4000"),
+            (ENV_DEFAULT_TTL, "60"),
+            (ENV_CLEANUP_INTERVAL, "0"),
+        ]
+        .into_iter()
+        .collect();
+        let resolved =
+            ResolvedServerConfig::resolve(&cli, |name| vars.get(name).map(|v| v.to_string()))
+                .unwrap();
+        assert_eq!(resolved.addr(), "0.0.0.0:3001");
+        assert_eq!(resolved.host.source, ConfigSource::Flag("--host"));
+        assert_eq!(resolved.max_capacity.value, None);
+        assert_eq!(resolved.default_ttl.value, Some(Duration::from_secs(300)));
+        assert_eq!(
+            resolved.default_ttl.source,
+            ConfigSource::Flag("--default-ttl")
+        );
+        // No flag, so the environment decides
+        assert_eq!(resolved.cleanup_interval.value, None);
+        assert!(!resolved.background_cleanup.value);
+        assert_eq!(
+            resolved.cleanup_interval.source,
+            ConfigSource::Env(ENV_CLEANUP_INTERVAL)
+        );
+
+        let config = resolved.cache_config();
+        assert_eq!(config.get_max_capacity(), None);
+        assert_eq!(config.get_default_ttl(), Some(Duration::from_secs(300)));
+
+        let resolved = resolve_with(&[(ENV_CLEANUP_INTERVAL, "5")]).unwrap();
+        assert_eq!(
+            resolved.cleanup_interval.value,
+            Some(Duration::from_secs(5))
+        );
+        assert!(resolved.background_cleanup.value);
+        assert!(resolve_with(&[(ENV_DEFAULT_TTL, "-1")]).is_err());
+    }
+
+    #[test]
+    fn test_resolve_error_names_source() {
+        let err = resolve_with(&[(ENV_PORT, "70000")]).unwrap_err();
+        let msg = err.to_string();
+        assert!(msg.contains("CACHE_PORT"), "{}", msg);
+        assert!(msg.contains("environment"), "{}", msg);
+        assert!(msg.contains("70000"), "{}", msg);
+
+        assert!(resolve_with(&[(ENV_HOST, " ")]).is_err());
+        assert!(resolve_with(&[(ENV_MAX_CAPACITY, "lots")]).is_err());
+        assert!(resolve_with(&[(ENV_RECORD_LOCK_WAITS, "maybe")]).is_err());
+    }
+
+    #[test]
+    fn test_resolve_config_file_under_env_and_flags() {
+        let path = std::env::temp_dir().join(format!(
+            "in-memory-cache-server-config-{}.toml",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            "max_capacity = 500\ndefault_ttl_secs = 300\nbackground_cleanup = true\n",
+        )
+        .unwrap();
+        let path_arg = path.to_str().unwrap();
+
+        let cli = ServerCli::parse_from(["server", "--config", path_arg]);
+        let resolved = ResolvedServerConfig::resolve(&cli, |_| None).unwrap();
+        assert_eq!(resolved.max_capacity.value, Some(500));
+        assert_eq!(
+            resolved.max_capacity.source,
+            ConfigSource::File(path.clone())
+        );
+        assert_eq!(resolved.default_ttl.value, Some(Duration::from_secs(300)));
+        assert!(resolved.background_cleanup.value);
+        // Keys the file leaves out keep their defaults
+        assert_eq!(resolved.port.source, ConfigSource::Default);
+        assert_eq!(resolved.max_memory.source, ConfigSource::Default);
+        let config = resolved.cache_config();
+        assert_eq!(config.get_max_capacity(), Some(500));
+        assert_eq!(config.get_default_ttl(), Some(Duration::from_secs(300)));
+
+        // The environment wins over the file
+        let resolved = ResolvedServerConfig::resolve(&cli, |name| {
+            (name == ENV_MAX_CAPACITY).then(|| "7".to_string())
+        })
+        .unwrap();
+        assert_eq!(resolved.max_capacity.value, Some(7));
+        assert_eq!(
+            resolved.max_capacity.source,
+            ConfigSource::Env(ENV_MAX_CAPACITY)
+        );
+        assert_eq!(
+            resolved.default_ttl.source.to_string(),
+            format!("file {}", path.display())
+        );
+
+        std::fs::write(&path, "max_capacity = 500\nport = 80\n").unwrap();
+        let err = ResolvedServerConfig::resolve(&cli, |_| None).unwrap_err();
+        assert!(err.to_string().contains("unknown key `port`"), "{}", err);
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(ResolvedServerConfig::resolve(&cli, |_| None).is_err());
+    }
+
+    fn info_state() -> Arc<ServerState> {
+        Arc::new(ServerState::new(resolve_with(&[]).unwrap()))
+    }
+
+    #[test]
+    fn test_info_single_section() {
+        let cache = Cache::default();
+        cache.set("a", "12345");
+        let state = info_state();
+        let _guard = state.connection_opened();
+        drop(state.connection_opened());
+
+        let info = render_info(&cache, &state, Some(InfoSection::Clients));
+        assert_eq!(
+            info,
+            "# Clients\nconnected_clients:1\ntotal_connections:2\n"
+        );
+
+        let info = render_info(&cache, &state, Some(InfoSection::parse("MEMORY").unwrap()));
+        assert_eq!(
+            info,
+            "# Memory\napprox_bytes:6\nentries:1\nmax_capacity:10000\nweight:1\n\
+             max_weight:unlimited\nweigher:unit\n"
+        );
+    }
+
+    #[test]
+    fn test_set_quiet_skips_replaced_notification() {
+        let replaced = Arc::new(AtomicU64::new(0));
+        let counter = Arc::clone(&replaced);
+        let cache = Cache::new(CacheConfig::new().eviction_listener(Arc::new(
+            move |_key: &str, _value, _cause| {
+                counter.fetch_add(1, Ordering::Relaxed);
+            },
+        )));
+        assert_eq!(run("set a 1", &cache), "Ok");
+        assert_eq!(run("set a 2 quiet", &cache), "r Ok");
+        assert_eq!(replaced.load(Ordering::Relaxed), 0);
+        assert_eq!(run("set a 3", &cache), "r Ok");
+        assert_eq!(replaced.load(Ordering::Relaxed), 1);
+        assert_eq!(cache.get("a"), Some(Bytes::from("3")));
+    }
+
+    #[test]
+    fn test_weight_and_eviction_reporting() {
+        let cache = Cache::new(
+            CacheConfig::new()
+                .weigher(Arc::new(|_key, value| value.len() as u32))
+                .max_weight(10),
+        );
+        assert_eq!(
+            run("set a 12345 verbose", &cache),
+            "Ok evicted:0 bytes_evicted:0"
+        );
+        assert_eq!(run("set b 12345", &cache), "Ok");
+        // Room for c is made by evicting a, key and value
+        assert_eq!(
+            run("set c 12345 ex 60 VERBOSE", &cache),
+            "Ok evicted:1 bytes_evicted:6"
+        );
+        assert_eq!(
+            run("set c 1 verbose", &cache),
+            "r Ok evicted:0 bytes_evicted:0"
+        );
+
+        let info = render_info(&cache, &info_state(), Some(InfoSection::Memory));
+        assert!(
+            info.ends_with("weight:6\nmax_weight:10\nweigher:custom\n"),
+            "{}",
+            info
+        );
+        let stats = render_stats(&cache, &info_state(), &StatsRequest::Global).unwrap();
+        assert!(
+            stats.contains(" evictions:1 bytes_evicted_total:6 weight:6 max_weight:10 "),
+            "{}",
+            stats
+        );
+    }
+
+    #[test]
+    fn test_info_all_sections() {
+        let cache = Cache::default();
+        cache.set_with_ttl("session", "x", Duration::from_secs(30));
+        let info = render_info(&cache, &info_state(), None);
+
+        let titles: Vec<&str> = info.lines().filter(|l| l.starts_with("# ")).collect();
+        assert_eq!(
+            titles,
+            vec![
+                "# Server",
+                "# Memory",
+                "# Stats",
+                "# Config",
+                "# Clients",
+                "# Keyspace",
+                "# Health"
+            ]
+        );
+        assert!(info.contains("\n\n# Memory\n"));
+        assert!(info.contains(&format!("version:{}\n", env!("CARGO_PKG_VERSION"))));
+        assert!(info.contains(&format!("git_hash:{}\n", crate::version().git_hash)));
+        assert!(info.contains("features:legacy,cli,server"));
+        assert!(info.contains(&format!("protocol_version:{}\n", PROTOCOL_VERSION)));
+        assert!(info.contains("ttl_le_1m:1\n"));
+        assert!(info.contains("bind:127.0.0.1:3000\n"));
+    }
+
+    #[test]
+    fn test_info_health_section() {
+        let cli = ServerCli::parse_from(["server", "--warn-size-pct", "50"]);
+        let config = ResolvedServerConfig::resolve(&cli, |name| {
+            (name == ENV_MAX_CAPACITY).then(|| "4".to_string())
+        })
+        .unwrap();
+        let cache = Cache::new(config.cache_config());
+        let state = Arc::new(ServerState::new(config));
+
+        cache.set("a", "1");
+        let info = render_info(&cache, &state, Some(InfoSection::Health));
+        assert_eq!(
+            info,
+            "# Health\nstatus:ok\nalerts:\nsize_ratio:0.25\nevictions_per_minute:0.0\n"
+        );
+
+        cache.set("b", "2");
+        cache.set("c", "3");
+        cache.get("a");
+        let info = render_info(&cache, &state, Some(InfoSection::parse("health").unwrap()));
+        assert!(info.contains("status:warn\nalerts:size_ratio\nsize_ratio:0.75\n"));
+        assert!(info.contains("hit_rate:100.0%\n"));
+    }
+
+    #[test]
+    fn test_info_unknown_section() {
+        let err = InfoSection::parse("bogus").unwrap_err();
+        assert!(err.to_string().contains("bogus"));
+        assert_eq!(err.code(), "INVALID");
+    }
+
+    #[test]
+    fn test_display_lists_sources() {
+        let resolved = resolve_with(&[(ENV_HOST, "0.0.0.0")]).unwrap();
+        assert_eq!(
+            resolved.to_string(),
+            "host = 0.0.0.0 (env CACHE_HOST)\n\
+             port = 3000 (default)\n\
+             max_capacity = 10000 (default)\n\
+             default_ttl = off (default)\n\
+             cleanup_interval = 60s (default)\n\
+             background_cleanup = false (default)\n\
+             max_memory = unlimited (default)\n\
+             record_lock_waits = false (default)\n\
+             enable_admin = false (default)\n\
+             idle_timeout = 300s (default)\n\
+             max_frame_size = 1048576 (default)\n\
+             max_ops_per_conn = unlimited (default)\n\
+             max_ops_global = unlimited (default)\n\
+             warn_size_pct = off (default)\n\
+             warn_hit_rate_pct = off (default)\n\
+             warn_evictions_per_min = off (default)\n\
+             warn_memory_bytes = off (default)"
+        );
+    }
+
+    #[test]
+    fn test_enable_admin_flag_overrides_env() {
+        let resolved = resolve_with(&[(ENV_ENABLE_ADMIN, "yes")]).unwrap();
+        assert!(resolved.enable_admin.value);
+        assert_eq!(
+            resolved.enable_admin.source,
+            ConfigSource::Env(ENV_ENABLE_ADMIN)
+        );
+
+        let cli = ServerCli::parse_from(["server", "--enable-admin"]);
+        let resolved = ResolvedServerConfig::resolve(&cli, |name| {
+            (name == ENV_ENABLE_ADMIN).then(|| "off".to_string())
+        })
+        .unwrap();
+        assert!(resolved.enable_admin.value);
+        assert_eq!(
+            resolved.enable_admin.source.to_string(),
+            "flag --enable-admin"
+        );
+    }
+
+    #[test]
+    fn test_rate_limits_resolve_from_flags() {
+        let cli = ServerCli::parse_from(["server", "--max-ops-per-conn", "5"]);
+        let resolved = ResolvedServerConfig::resolve(&cli, |_| None).unwrap();
+        assert_eq!(resolved.max_ops_per_conn.value, Some(5));
+        assert_eq!(
+            resolved.max_ops_per_conn.source,
+            ConfigSource::Flag("--max-ops-per-conn")
+        );
+        assert_eq!(resolved.max_ops_global.value, None);
+    }
+
+    #[test]
+    fn test_admit_applies_client_and_global_limits() {
+        let cli =
+            ServerCli::parse_from(["server", "--max-ops-per-conn", "2", "--max-ops-global", "3"]);
+        let state = ServerState::new(ResolvedServerConfig::resolve(&cli, |_| None).unwrap());
+        let now = Instant::now();
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.2".parse().unwrap();
+
+        assert!(state.admit_at(a, now).is_ok());
+        assert!(state.admit_at(a, now).is_ok());
+        // Client limit of 2 per second
+        let wait = state.admit_at(a, now).unwrap_err();
+        assert_eq!(wait, Duration::from_millis(500));
+        // b has its own bucket, but the global limit of 3 caps both
+        assert!(state.admit_at(b, now).is_ok());
+        assert!(state.admit_at(b, now).is_err());
+        assert_eq!(state.throttled(), 2);
+
+        assert!(state.admit_at(b, now + Duration::from_secs(1)).is_ok());
+
+        // Without limits everything is admitted
+        assert!((0..100).all(|_| info_state().admit_at(a, now).is_ok()));
+    }
+
+    #[test]
+    fn test_rate_limited_reply_rounds_up() {
+        assert_eq!(
+            rate_limited_reply(Duration::from_micros(1500)),
+            "ERR RATELIMITED retry_after_ms=2"
+        );
+        assert_eq!(
+            rate_limited_reply(Duration::from_millis(100)),
+            "ERR RATELIMITED retry_after_ms=100"
+        );
+    }
+
+    fn stats_request(line: &str) -> ProtocolResult<StatsRequest> {
+        let words: Vec<String> = line.split_whitespace().skip(1).map(String::from).collect();
+        StatsRequest::parse(&words)
+    }
+
+    #[test]
+    fn test_stats_prefix_block() {
+        let cache = Cache::default();
+        cache.set("user:1", "alice");
+        cache.set_with_ttl("user:2", "bob", Duration::from_secs(30));
+        cache.set("order:1", "book");
+        cache.get("user:1");
+
+        let request = stats_request("stats PREFIX user:").unwrap();
+        assert_eq!(request, StatsRequest::Prefix("user:".to_string()));
+        let reply = render_stats(&cache, &info_state(), &request).unwrap();
+        let lines: Vec<&str> = reply.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(
+            lines[0].starts_with("hits:1 misses:0 size:3 "),
+            "{}",
+            lines[0]
+        );
+        assert_eq!(lines[1], "prefix user: entries:2 bytes:20 with_ttl:1");
+
+        let reply = render_stats(&cache, &info_state(), &stats_request("stats").unwrap()).unwrap();
+        assert!(!reply.contains('\n'));
+        assert!(stats_request("stats prefix").is_err());
+        assert!(stats_request("stats bogus").is_err());
+    }
+
+    #[test]
+    fn test_stats_reset_requires_admin() {
+        let cache = Cache::default();
+        cache.set("a", "1");
+        cache.get("a");
+        let request = stats_request("stats reset").unwrap();
+
+        let err = render_stats(&cache, &info_state(), &request).unwrap_err();
+        assert!(err.to_string().contains("--enable-admin"), "{}", err);
+        assert!(matches!(err, ProtocolError::Unauthorized(_)));
+        assert_eq!(cache.stats().hits, 1);
+
+        let admin = Arc::new(ServerState::new(
+            resolve_with(&[(ENV_ENABLE_ADMIN, "1")]).unwrap(),
+        ));
+        assert_eq!(render_stats(&cache, &admin, &request).unwrap(), "Ok");
+        assert_eq!(cache.stats().hits, 0);
+        assert_eq!(cache.stats().size, 1);
+    }
+
+    fn run(line: &str, cache: &Cache) -> Bytes {
+        let attrs: Vec<String> = line.split_whitespace().map(String::from).collect();
+        let command = Command::get(&attrs[0]);
+        process_command(command, &attrs, cache, &info_state())
+    }
+
+    #[test]
+    fn test_set_nx() {
+        let cache = Cache::default();
+        assert_eq!(run("set lock a nx", &cache), "Ok");
+        assert_eq!(run("set lock b NX", &cache), "");
+        assert_eq!(cache.get("lock"), Some(Bytes::from("a")));
+
+        assert_eq!(run("set lease a nx ex 30", &cache), "Ok");
+        assert_eq!(run("ttl lease", &cache), "30");
+        assert_eq!(run("set lease b ex 60 nx", &cache), "");
+        assert_eq!(run("ttl lease", &cache), "30");
+        assert_eq!(
+            run("set other a nx ex 0", &cache),
+            "ERR INVALID invalid expire time"
+        );
+
+        // Without nx the value is still overwritten
+        assert_eq!(run("set lock c", &cache), "r Ok");
+    }
+
+    #[test]
+    fn test_set_relays_size_limits() {
+        let cache = Cache::new(CacheConfig::new().max_key_length(8).max_value_size(4));
+        assert_eq!(run("set a 1234", &cache), "Ok");
+        assert_eq!(
+            run("set a 12345", &cache),
+            "ERR CACHE invalid value: 5 bytes (max: 4)"
+        );
+        assert_eq!(
+            run("set very_long_key 1 nx", &cache),
+            "ERR CACHE invalid key: 13 bytes (max: 8)"
+        );
+        assert_eq!(run("get a", &cache), "1234");
+        assert_eq!(cache.stats().rejected_sets, 2);
+    }
+
+    #[test]
+    fn test_set_reports_dropped_writes() {
+        // A directory path below a regular file can never be created
+        let blocker = std::env::temp_dir().join(format!(
+            "in-memory-cache-server-spill-blocker-{}",
+            std::process::id()
+        ));
+        std::fs::write(&blocker, b"").unwrap();
+        let cache = Cache::new(
+            CacheConfig::new()
+                .spill_over(4, blocker.join("spill"))
+                .spill_fallback(crate::SpillFallback::Reject),
+        );
+
+        assert_eq!(run("set a tiny", &cache), "Ok");
+        // The key still holds its old value, but the write did not replace it
+        assert_eq!(
+            run("set a large_value", &cache),
+            "ERR CACHE invalid value: value for 'a' could not be spilled"
+        );
+        assert_eq!(
+            run("set b large_value verbose", &cache),
+            "ERR CACHE invalid value: value for 'b' could not be spilled"
+        );
+        assert_eq!(run("get a", &cache), "tiny");
+        assert_eq!(run("get b", &cache), "");
+        let _ = std::fs::remove_file(&blocker);
+    }
+
+    #[test]
+    fn test_set_ex_and_ttl_commands() {
+        let cache = Cache::default();
+        assert_eq!(run("ttl a", &cache), "-2");
+
+        assert_eq!(run("set a 1", &cache), "Ok");
+        assert_eq!(run("ttl a", &cache), "-1");
+
+        assert_eq!(run("set b 2 EX 30", &cache), "Ok");
+        assert_eq!(run("ttl b", &cache), "30");
+        assert_eq!(cache.get("b"), Some(Bytes::from("2")));
+
+        assert_eq!(
+            run("set c 3 ex 0", &cache),
+            "ERR INVALID invalid expire time"
+        );
+        assert_eq!(
+            run("set c 3 ex soon", &cache),
+            "ERR INVALID invalid expire time"
+        );
+        assert!(!cache.contains("c"));
+        assert_eq!(
+            run("ttl", &cache),
+            "ERR ARITY wrong number of arguments for 'ttl': expected at least 1, got 0"
+        );
+    }
+
+    #[test]
+    fn test_smoke_report_display() {
+        let report = SmokeReport {
+            results: vec![
+                SmokeResult {
+                    name: "ping",
+                    outcome: Ok(()),
+                },
+                SmokeResult {
+                    name: "get",
+                    outcome: Err("expected \"hello\", got \"\"".to_string()),
+                },
+            ],
+        };
+        assert!(!report.passed());
+        assert_eq!(
+            report.to_string(),
+            "PASS ping\nFAIL get: expected \"hello\", got \"\"\nsmoke test FAILED: 1/2 steps passed"
+        );
+
+        let steps = smoke_script();
+        assert_eq!(steps[0].check(b"PONG"), Ok(()));
+        assert!(steps[0].check(b"pong").is_err());
+        assert!(steps[3].check(b"0").is_err());
+        assert_eq!(steps[3].check(b"60"), Ok(()));
+    }
+
+    #[test]
+    fn test_expire_and_persist_commands() {
+        let cache = Cache::default();
+        cache.set("a", "1");
+        assert_eq!(run("expire a 30", &cache), "1");
+        assert_eq!(run("ttl a", &cache), "30");
+        assert_eq!(run("persist a", &cache), "1");
+        assert_eq!(run("ttl a", &cache), "-1");
+
+        assert_eq!(run("expire missing 30", &cache), "0");
+        assert_eq!(run("persist missing", &cache), "0");
+        assert_eq!(run("expire a 0", &cache), "ERR INVALID invalid expire time");
+        assert_eq!(
+            run("expire a soon", &cache),
+            "ERR INVALID invalid expire time"
+        );
+        assert_eq!(cache.stats().sets, 1);
+    }
+
+    #[test]
+    fn test_delete_command() {
+        let cache = Cache::default();
+        cache.set("a", "1");
+        assert_eq!(run("delete a", &cache), "Ok");
+        assert_eq!(run("del a", &cache), "");
+
+        cache.set("a", "1");
+        cache.set("b", "2");
+        assert_eq!(run("del a b c", &cache), "2");
+        assert_eq!(run("delete a b", &cache), "0");
+        assert!(cache.is_empty());
+        assert_eq!(cache.stats().deletes, 3);
+    }
+
+    #[test]
+    fn test_delprefix_command() {
+        let cache = Cache::default();
+        cache.set("user:1:profile", "a");
+        cache.set("user:1:settings", "b");
+        cache.set("user:12:profile", "c");
+        assert_eq!(run("delprefix user:1:", &cache), "2");
+        assert_eq!(run("delprefix user:1:", &cache), "0");
+        assert_eq!(cache.keys(), vec!["user:12:profile"]);
+        assert_eq!(
+            run("delprefix", &cache),
+            "ERR ARITY wrong number of arguments for 'delprefix': expected at least 1, got 0"
+        );
+    }
+
+    #[test]
+    fn test_append_command() {
+        let cache = Cache::default();
+        assert_eq!(run("append log a;", &cache), "2");
+        assert_eq!(run("append log b;", &cache), "4");
+        assert_eq!(cache.get("log"), Some(Bytes::from("a;b;")));
+        assert_eq!(
+            run("append log", &cache),
+            "ERR ARITY wrong number of arguments for 'append': expected at least 2, got 1"
+        );
+    }
+
+    #[test]
+    fn test_incr_and_decr_commands() {
+        let cache = Cache::default();
+        assert_eq!(run("incr views", &cache), "1");
+        assert_eq!(run("incr views 10", &cache), "11");
+        assert_eq!(run("decr views", &cache), "10");
+        assert_eq!(run("DECR views 15", &cache), "-5");
+        assert_eq!(run("incr views x", &cache), "ERR INVALID invalid delta");
+        cache.set("s", "text");
+        let reply = run("incr s", &cache);
+        assert!(reply.starts_with(b"ERR CACHE invalid value"), "{:?}", reply);
+        assert_eq!(cache.get("s"), Some(Bytes::from("text")));
+    }
+
+    #[test]
+    fn test_window_counter_commands() {
+        let cache = Cache::default();
+        assert_eq!(run("incrwindow n 2 60", &cache), "count:2 reset_ms:60000");
+        let reply = run("incrwindow n -1 60", &cache);
+        assert!(reply.starts_with(b"count:1 reset_ms:"), "{:?}", reply);
+        assert_eq!(
+            run("incrwindow n x 60", &cache),
+            "ERR INVALID invalid delta"
+        );
+        assert_eq!(
+            run("incrwindow n 1 0", &cache),
+            "ERR INVALID invalid window"
+        );
+        cache.set("s", "text");
+        let reply = run("incrwindow s 1 60", &cache);
+        assert!(reply.starts_with(b"ERR CACHE invalid value"), "{:?}", reply);
+
+        assert_eq!(
+            run("ratelimit api 1 60", &cache),
+            "allowed:1 remaining:0 retry_after_ms:0"
+        );
+        let reply = run("ratelimit api 1 60", &cache);
+        assert!(
+            reply.starts_with(b"allowed:0 remaining:0 retry_after_ms:"),
+            "{:?}",
+            reply
+        );
+        assert_eq!(
+            ProtocolError::from_wire(&run("ratelimit api", &cache)),
+            Some(ProtocolError::WrongArity {
+                cmd: "ratelimit".to_string(),
+                expected: 3,
+                got: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_errors_carry_wire_codes() {
+        let cache = Cache::default();
+        let code = |line: &str| {
+            let reply = run(line, &cache);
+            ProtocolError::from_wire(&reply).map(|e| e.code())
+        };
+        assert_eq!(code("frobnicate key"), Some("UNKNOWN"));
+        assert_eq!(code("get"), Some("ARITY"));
+        assert_eq!(code("set key"), Some("ARITY"));
+        assert_eq!(code("debug"), Some("ARITY"));
+        assert_eq!(code("debug bogus"), Some("INVALID"));
+        assert_eq!(code("stats bogus"), Some("INVALID"));
+        assert_eq!(code("info bogus"), Some("INVALID"));
+        assert_eq!(code("stats reset"), Some("NOAUTH"));
+        assert_eq!(code("expire-pattern * 60"), Some("NOAUTH"));
+        assert_eq!(code("purge idle 0"), Some("NOAUTH"));
+        assert_eq!(code("ping"), None);
+        assert_eq!(
+            run("frobnicate key", &cache),
+            "ERR UNKNOWN unknown command 'frobnicate'"
+        );
+    }
+
+    #[test]
+    fn test_debug_inflight_lists_stuck_loads() {
+        let cache = Cache::default();
+        assert_eq!(run("debug inflight", &cache), "in_flight:0");
+
+        let (release, released) = std::sync::mpsc::channel::<()>();
+        let (started_tx, started) = std::sync::mpsc::channel::<()>();
+        let loader = {
+            let cache = cache.clone();
+            std::thread::spawn(move || {
+                cache.get_or_load("user:1", || {
+                    started_tx.send(()).unwrap();
+                    let _ = released.recv();
+                    Ok::<_, ()>((Bytes::from("alice"), None))
+                })
+            })
+        };
+        started.recv().unwrap();
+        let reply = run("debug inflight", &cache);
+        let reply = std::str::from_utf8(&reply).unwrap();
+        let lines: Vec<&str> = reply.lines().collect();
+        assert_eq!(lines.len(), 2, "{}", reply);
+        assert_eq!(lines[0], "in_flight:1");
+        assert!(lines[1].starts_with("user:1 elapsed_ms:"), "{}", reply);
+
+        release.send(()).unwrap();
+        loader.join().unwrap().unwrap();
+        assert_eq!(run("debug inflight", &cache), "in_flight:0");
+        assert_eq!(
+            render_in_flight(&[
+                ("a".to_string(), Duration::from_millis(1500)),
+                ("b".to_string(), Duration::from_millis(2)),
+            ]),
+            "in_flight:2\na elapsed_ms:1500\nb elapsed_ms:2"
+        );
+    }
+
+    #[test]
+    fn test_metrics_command() {
+        let cache = Cache::default();
+        cache.set("a", "1");
+        run("get a", &cache);
+        run("get missing", &cache);
+
+        let reply = run("metrics", &cache);
+        let text = std::str::from_utf8(&reply).unwrap();
+        assert!(text.contains("# TYPE cache_hits_total counter\ncache_hits_total 1\n"));
+        assert!(text.contains("cache_misses_total 1\n"));
+        assert!(text.contains("cache_size 1\n"));
+        assert!(text.contains("cache_hit_rate 50\n"));
+    }
+
+    #[test]
+    fn test_expire_pattern_command() {
+        let cache = Cache::default();
+        let admin = ServerState::new(resolve_with(&[(ENV_ENABLE_ADMIN, "1")]).unwrap());
+        let run = |line: &str| {
+            let attrs: Vec<String> = line.split_whitespace().map(String::from).collect();
+            process_command(Command::get(&attrs[0]), &attrs, &cache, &admin)
+        };
+        cache.set("catalog:1", "a");
+        cache.set("catalog:2", "b");
+        cache.set("cart:1", "c");
+
+        assert_eq!(run("expire-pattern nothing:* 60"), "updated:0");
+        assert_eq!(run("expire-pattern catalog:* 60"), "updated:2");
+        assert_eq!(run("ttl catalog:1"), "60");
+        assert_eq!(run("ttl cart:1"), "-1");
+        assert_eq!(run("expire-pattern catalog:? PERSIST"), "updated:2");
+        assert_eq!(run("ttl catalog:2"), "-1");
+
+        assert_eq!(
+            run("expire-pattern catalog:* 0"),
+            "ERR INVALID invalid expire time"
+        );
+        assert!(matches!(
+            ProtocolError::from_wire(&run("expire-pattern catalog:*")),
+            Some(ProtocolError::WrongArity { .. })
+        ));
+        // Without admin the command is refused before anything is touched
+        cache.set("catalog:1", "a");
+        let reply = process_command(
+            Command::ExpirePattern,
+            &["expire-pattern".into(), "*".into(), "1".into()],
+            &cache,
+            &info_state(),
+        );
+        assert!(reply.starts_with(b"ERR NOAUTH "), "{:?}", reply);
+        assert_eq!(cache.ttl("catalog:1"), Some(None));
+    }
+
+    #[test]
+    fn test_purge_idle_requires_admin() {
+        let cache = Cache::default();
+        cache.set("a", "1");
+        cache.set("b", "2");
+        std::thread::sleep(Duration::from_millis(5));
+
+        // Without admin the command is refused before anything is removed
+        let reply = run("purge idle 0", &cache);
+        assert!(reply.starts_with(b"ERR NOAUTH "), "{:?}", reply);
+        assert_eq!(cache.len(), 2);
+
+        let admin = ServerState::new(resolve_with(&[(ENV_ENABLE_ADMIN, "1")]).unwrap());
+        let run = |line: &str| {
+            let attrs: Vec<String> = line.split_whitespace().map(String::from).collect();
+            process_command(Command::get(&attrs[0]), &attrs, &cache, &admin)
+        };
+        let code = |line: &str| ProtocolError::from_wire(&run(line)).map(|e| e.code());
+        assert_eq!(code("purge idle"), Some("ARITY"));
+        assert_eq!(code("purge idle soon"), Some("INVALID"));
+        assert_eq!(code("purge busy 0"), Some("INVALID"));
+        assert_eq!(run("purge idle 3600"), "purged:0");
+        assert_eq!(run("purge idle 0"), "purged:2");
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn test_hello_reports_versions() {
+        let cache = Cache::default();
+        assert_eq!(
+            run("hello compression=zstd,lz4,none", &cache),
+            format!(
+                "hello compression=lz4 version={} protocol={}",
+                env!("CARGO_PKG_VERSION"),
+                PROTOCOL_VERSION
+            )
+        );
+    }
+
+    #[test]
+    fn test_keys_command() {
+        let cache = Cache::new(CacheConfig::new().shards(1));
+        cache.set("session:b", "1");
+        cache.set("session:a", "1");
+        cache.set("user:1", "1");
+        cache.set_with_ttl("session:c", "1", Duration::from_millis(1));
+        std::thread::sleep(Duration::from_millis(10));
+
+        let keys = |request: &str| -> Vec<String> {
+            decode_multi_bulk(&run(request, &cache))
+                .unwrap()
+                .into_iter()
+                .map(|item| String::from_utf8(item.unwrap().to_vec()).unwrap())
+                .collect()
+        };
+        assert_eq!(keys("keys session:*"), ["session:a", "session:b"]);
+        assert_eq!(keys("keys user:?"), ["user:1"]);
+        assert!(keys("keys nothing*").is_empty());
+        // Listing is not an access: "session:b" is still the next victim
+        assert_eq!(cache.keys()[0], "session:b");
+    }
+
+    #[test]
+    fn test_scan_command_pages_with_cursor() {
+        let cache = Cache::default();
+        for key in ["a", "b", "c"] {
+            cache.set(key, "1");
+        }
+
+        let page = |request: &str| -> Vec<String> {
+            decode_multi_bulk(&run(request, &cache))
+                .unwrap()
+                .into_iter()
+                .map(|item| String::from_utf8(item.unwrap().to_vec()).unwrap())
+                .collect()
+        };
+        let first = page("scan 0 2");
+        assert_eq!(first[1..], ["a", "b"]);
+        assert_eq!(first[0], ScanCursor::after("b").to_string());
+
+        let second = page(&format!("scan {}", first[0]));
+        assert_eq!(second, ["end", "c"]);
+        assert_eq!(page("scan end"), ["end"]);
+
+        let reply = run("scan bogus", &cache);
+        assert!(reply.starts_with(b"ERR INVALID "), "{:?}", reply);
+        assert_eq!(run("scan 0 0", &cache), "ERR INVALID invalid count");
+        assert!(matches!(
+            ProtocolError::from_wire(&run("scan", &cache)),
+            Some(ProtocolError::WrongArity {
+                expected: 1,
+                got: 0,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,370 @@
+//! Collapsing of concurrent loads for the same key.
+//!
+//! The first caller to miss on a key becomes the leader and runs its loader;
+//! callers arriving while that load is in flight wait for its result instead
+//! of running their own. If the leader fails or panics, waiters do not see
+//! the error: each retries, and one of them becomes the next leader.
+//!
+//! Waiters may give up at a deadline, and a load can be cancelled, which
+//! frees its key at once and wakes its waiters. A cancelled leader keeps
+//! running its loader; only its outcome is no longer shared.
+
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Outcome of a finished load, as seen by waiters.
+#[derive(Debug, Clone)]
+enum Outcome {
+    Pending,
+    Loaded(Bytes),
+    Failed,
+    Cancelled,
+}
+
+/// A load in progress.
+#[derive(Debug)]
+struct Call {
+    outcome: Mutex<Outcome>,
+    done: Condvar,
+    started: Instant,
+}
+
+/// Why a load produced no value for its caller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum LoadError<E> {
+    /// The caller's own loader failed.
+    Failed(E),
+    /// Another caller's load was still running at the deadline.
+    TimedOut,
+    /// The load being waited for was cancelled.
+    Cancelled,
+}
+
+/// Map of in-flight loads, keyed by cache key.
+#[derive(Debug, Default)]
+pub(crate) struct InFlight {
+    calls: Mutex<HashMap<String, Arc<Call>>>,
+}
+
+impl InFlight {
+    /// Run `load` for `key`, or wait for the load another thread is already
+    /// running and return its value.
+    ///
+    /// Waiting gives up at `deadline`. Without one, waiting never ends early
+    /// and a cancelled load is retried like a failed one, so the caller only
+    /// ever sees its own loader's error.
+    pub(crate) fn load<E>(
+        &self,
+        key: &str,
+        deadline: Option<Instant>,
+        load: impl FnOnce() -> Result<Bytes, E>,
+    ) -> Result<Bytes, LoadError<E>> {
+        loop {
+            let mut calls = self.lock_calls();
+            if let Some(call) = calls.get(key).cloned() {
+                drop(calls);
+                match call.wait(deadline) {
+                    Outcome::Loaded(value) => return Ok(value),
+                    Outcome::Pending => return Err(LoadError::TimedOut),
+                    Outcome::Cancelled if deadline.is_some() => return Err(LoadError::Cancelled),
+                    // The leader failed; try again, possibly as the new leader
+                    Outcome::Failed | Outcome::Cancelled => continue,
+                }
+            }
+
+            let call = Arc::new(Call {
+                outcome: Mutex::new(Outcome::Pending),
+                done: Condvar::new(),
+                started: Instant::now(),
+            });
+            calls.insert(key.to_string(), Arc::clone(&call));
+            drop(calls);
+
+            // Completes the call as failed if `load` panics
+            let mut leader = Leader {
+                flights: self,
+                key,
+                call,
+                outcome: Outcome::Failed,
+            };
+            let result = load();
+            if let Ok(value) = &result {
+                leader.outcome = Outcome::Loaded(value.clone());
+            }
+            return result.map_err(LoadError::Failed);
+        }
+    }
+
+    /// Keys being loaded and how long each load has been running, longest
+    /// first.
+    pub(crate) fn snapshot(&self) -> Vec<(String, Duration)> {
+        let now = Instant::now();
+        let mut flights: Vec<_> = self
+            .lock_calls()
+            .iter()
+            .map(|(key, call)| (key.clone(), now.saturating_duration_since(call.started)))
+            .collect();
+        flights.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        flights
+    }
+
+    /// Cancel the load of `key`, waking its waiters. Returns `false` if no
+    /// load was in flight.
+    pub(crate) fn cancel(&self, key: &str) -> bool {
+        let call = match self.lock_calls().remove(key) {
+            Some(call) => call,
+            None => return false,
+        };
+        call.finish(Outcome::Cancelled);
+        true
+    }
+
+    /// Number of loads currently in flight.
+    #[allow(dead_code)]
+    pub(crate) fn len(&self) -> usize {
+        self.lock_calls().len()
+    }
+
+    /// The map holds no invariant a panic could break, so a poisoned lock
+    /// is recovered.
+    fn lock_calls(&self) -> MutexGuard<'_, HashMap<String, Arc<Call>>> {
+        self.calls.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Call {
+    /// Wait for the outcome, or until `deadline`; `Pending` if the deadline
+    /// passed first.
+    fn wait(&self, deadline: Option<Instant>) -> Outcome {
+        let mut outcome = self.outcome.lock().unwrap_or_else(|e| e.into_inner());
+        while matches!(*outcome, Outcome::Pending) {
+            outcome = match deadline {
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    if left.is_zero() {
+                        break;
+                    }
+                    self.done
+                        .wait_timeout(outcome, left)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+                None => self.done.wait(outcome).unwrap_or_else(|e| e.into_inner()),
+            };
+        }
+        outcome.clone()
+    }
+
+    /// Publish `outcome` unless the call already has one, and wake the
+    /// waiters.
+    fn finish(&self, outcome: Outcome) {
+        let mut current = self.outcome.lock().unwrap_or_else(|e| e.into_inner());
+        if matches!(*current, Outcome::Pending) {
+            *current = outcome;
+        }
+        drop(current);
+        self.done.notify_all();
+    }
+}
+
+/// Publishes the leader's outcome and retires the call when dropped.
+struct Leader<'a> {
+    flights: &'a InFlight,
+    key: &'a str,
+    call: Arc<Call>,
+    outcome: Outcome,
+}
+
+impl Drop for Leader<'_> {
+    fn drop(&mut self) {
+        {
+            // After a cancel the key may already belong to a new leader
+            let mut calls = self.flights.lock_calls();
+            if calls
+                .get(self.key)
+                .is_some_and(|call| Arc::ptr_eq(call, &self.call))
+            {
+                calls.remove(self.key);
+            }
+        }
+        let outcome = std::mem::replace(&mut self.outcome, Outcome::Failed);
+        self.call.finish(outcome);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+    use std::sync::Barrier;
+    use std::thread;
+
+    #[test]
+    fn test_concurrent_loads_collapse() {
+        let flights = Arc::new(InFlight::default());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(8));
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let flights = Arc::clone(&flights);
+                let calls = Arc::clone(&calls);
+                let barrier = Arc::clone(&barrier);
+                thread::spawn(move || {
+                    barrier.wait();
+                    flights.load("key", None, || {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(50));
+                        Ok::<_, ()>(Bytes::from("value"))
+                    })
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), Ok(Bytes::from("value")));
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(flights.len(), 0);
+    }
+
+    #[test]
+    fn test_failed_leader_lets_waiter_retry() {
+        let flights = Arc::new(InFlight::default());
+        let started = Arc::new(Barrier::new(2));
+
+        let leader = {
+            let flights = Arc::clone(&flights);
+            let started = Arc::clone(&started);
+            thread::spawn(move || {
+                flights.load("key", None, || {
+                    started.wait();
+                    thread::sleep(Duration::from_millis(50));
+                    Err::<Bytes, _>("backend down")
+                })
+            })
+        };
+
+        started.wait();
+        let result = flights.load("key", None, || Ok::<_, &str>(Bytes::from("retried")));
+        assert_eq!(result, Ok(Bytes::from("retried")));
+        assert_eq!(
+            leader.join().unwrap(),
+            Err(LoadError::Failed("backend down"))
+        );
+    }
+
+    #[test]
+    fn test_panicking_leader_releases_key() {
+        let flights = InFlight::default();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            flights.load("key", None, || -> Result<Bytes, ()> {
+                panic!("loader bug")
+            })
+        }));
+        assert!(result.is_err());
+        assert_eq!(flights.len(), 0);
+        assert_eq!(
+            flights.load("key", None, || Ok::<_, ()>(Bytes::from("ok"))),
+            Ok(Bytes::from("ok"))
+        );
+    }
+
+    /// Start a leader for `key` whose loader blocks until `release` fires,
+    /// and wait until it is in flight.
+    fn stuck_leader(
+        flights: &Arc<InFlight>,
+        key: &'static str,
+    ) -> (
+        mpsc::Sender<()>,
+        thread::JoinHandle<Result<Bytes, LoadError<()>>>,
+    ) {
+        let (release, released) = mpsc::channel::<()>();
+        let started = Arc::new(Barrier::new(2));
+        let handle = {
+            let flights = Arc::clone(flights);
+            let started = Arc::clone(&started);
+            thread::spawn(move || {
+                flights.load(key, None, || {
+                    started.wait();
+                    let _ = released.recv();
+                    Ok(Bytes::from("late"))
+                })
+            })
+        };
+        started.wait();
+        (release, handle)
+    }
+
+    #[test]
+    fn test_waiter_times_out_at_deadline() {
+        let flights = Arc::new(InFlight::default());
+        let (release, leader) = stuck_leader(&flights, "key");
+
+        let snapshot = flights.snapshot();
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot[0].0, "key");
+
+        let started = Instant::now();
+        let deadline = started + Duration::from_millis(30);
+        let result = flights.load("key", Some(deadline), || Ok::<_, ()>(Bytes::new()));
+        assert_eq!(result, Err(LoadError::TimedOut));
+        assert!(started.elapsed() >= Duration::from_millis(30));
+        // Timing out leaves the running load alone
+        assert_eq!(flights.len(), 1);
+        assert!(flights.snapshot()[0].1 >= Duration::from_millis(30));
+
+        release.send(()).unwrap();
+        assert_eq!(leader.join().unwrap(), Ok(Bytes::from("late")));
+        assert!(flights.snapshot().is_empty());
+    }
+
+    #[test]
+    fn test_cancel_wakes_waiters_and_frees_key() {
+        let flights = Arc::new(InFlight::default());
+        let (release, leader) = stuck_leader(&flights, "key");
+
+        let waiter = {
+            let flights = Arc::clone(&flights);
+            thread::spawn(move || {
+                let deadline = Instant::now() + Duration::from_secs(30);
+                flights.load("key", Some(deadline), || Ok::<_, ()>(Bytes::new()))
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert!(flights.cancel("key"));
+        assert_eq!(waiter.join().unwrap(), Err(LoadError::Cancelled));
+        assert_eq!(flights.len(), 0);
+        assert!(!flights.cancel("key"));
+
+        // The key is free for a new leader while the old one still runs,
+        // and the old one finishing does not retire the new load
+        let (new_release, new_leader) = stuck_leader(&flights, "key");
+        release.send(()).unwrap();
+        assert_eq!(leader.join().unwrap(), Ok(Bytes::from("late")));
+        assert_eq!(flights.len(), 1);
+        new_release.send(()).unwrap();
+        assert_eq!(new_leader.join().unwrap(), Ok(Bytes::from("late")));
+        assert_eq!(flights.len(), 0);
+    }
+
+    #[test]
+    fn test_cancel_without_deadline_retries() {
+        let flights = Arc::new(InFlight::default());
+        let (release, leader) = stuck_leader(&flights, "key");
+
+        let waiter = {
+            let flights = Arc::clone(&flights);
+            thread::spawn(move || flights.load("key", None, || Ok::<_, ()>(Bytes::from("own"))))
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert!(flights.cancel("key"));
+        assert_eq!(waiter.join().unwrap(), Ok(Bytes::from("own")));
+
+        release.send(()).unwrap();
+        leader.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,279 @@
+//! Spill-over files for values too large to keep in memory.
+//!
+//! A spilled value lives in a file named after a hash of its content and its
+//! length; the cache entry only holds a reference: this store's random magic
+//! followed by the file name. The magic is drawn from entropy for every
+//! store, so no value a user stores can be mistaken for a reference.
+//!
+//! Identical values share a file. The store counts the live references to
+//! each file and deletes it when the last one is released; a same-named file
+//! that is already referenced is compared byte for byte before it is reused,
+//! so a hash collision fails the spill instead of returning the wrong data.
+
+use bytes::Bytes;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::Hasher;
+use std::io::{self, ErrorKind};
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::config::SpillOver;
+use crate::rng;
+
+const MAGIC_LEN: usize = 16;
+
+/// Suffix of files being written; they are renamed into place when complete.
+const TMP_SUFFIX: &str = ".tmp";
+
+/// Spill-over files of one cache.
+#[derive(Debug)]
+pub(crate) struct SpillStore {
+    dir: PathBuf,
+    threshold: usize,
+    magic: [u8; MAGIC_LEN],
+    /// Live references per file name. Held while files are written or
+    /// deleted, so a file is never removed while a reference to it is being
+    /// created.
+    refs: Mutex<HashMap<String, usize>>,
+}
+
+impl SpillStore {
+    pub(crate) fn new(config: &SpillOver) -> Self {
+        let mut magic = [0; MAGIC_LEN];
+        magic[..8].copy_from_slice(&rng::entropy_seed().to_le_bytes());
+        magic[8..].copy_from_slice(&rng::entropy_seed().to_le_bytes());
+        Self {
+            dir: config.dir.clone(),
+            threshold: config.threshold,
+            magic,
+            refs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether `value` is large enough to be spilled.
+    pub(crate) fn wants(&self, value: &[u8]) -> bool {
+        value.len() > self.threshold
+    }
+
+    /// Whether a stored value is a reference created by this store.
+    pub(crate) fn is_ref(&self, value: &[u8]) -> bool {
+        value.len() > MAGIC_LEN && value[..MAGIC_LEN] == self.magic
+    }
+
+    /// Write `value` to its file (unless an identical one is already
+    /// referenced) and return a reference to it. The caller owns one
+    /// reference and must eventually `release` it.
+    pub(crate) fn store(&self, value: &[u8]) -> io::Result<Bytes> {
+        let name = file_name(value);
+        let mut refs = self.lock_refs();
+        match refs.get_mut(&name) {
+            Some(count) => {
+                if fs::read(self.dir.join(&name))? != value {
+                    return Err(io::Error::new(
+                        ErrorKind::Other,
+                        format!("spill file {} holds different content", name),
+                    ));
+                }
+                *count += 1;
+            }
+            None => {
+                self.write_file(&name, value)?;
+                refs.insert(name.clone(), 1);
+            }
+        }
+        Ok(self.reference(&name))
+    }
+
+    /// Read the value a reference points to.
+    pub(crate) fn load(&self, reference: &[u8]) -> io::Result<Bytes> {
+        let name = self.name_of(reference)?;
+        fs::read(self.dir.join(name)).map(Bytes::from)
+    }
+
+    /// Take another reference to an already referenced file.
+    pub(crate) fn retain(&self, reference: &[u8]) {
+        if let Ok(name) = self.name_of(reference) {
+            if let Some(count) = self.lock_refs().get_mut(name) {
+                *count += 1;
+            }
+        }
+    }
+
+    /// Drop one reference, deleting the file when it was the last.
+    pub(crate) fn release(&self, reference: &[u8]) -> io::Result<()> {
+        let name = self.name_of(reference)?;
+        let mut refs = self.lock_refs();
+        let count = match refs.get_mut(name) {
+            Some(count) => count,
+            None => return Ok(()),
+        };
+        *count -= 1;
+        if *count > 0 {
+            return Ok(());
+        }
+        refs.remove(name);
+        match fs::remove_file(self.dir.join(name)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Delete every file in the directory that is not referenced, such as
+    /// files left behind by a crash. Returns how many were deleted.
+    pub(crate) fn gc(&self) -> io::Result<usize> {
+        let refs = self.lock_refs();
+        let dir = match fs::read_dir(&self.dir) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+        let mut removed = 0;
+        for item in dir {
+            let item = item?;
+            if !item.file_type()?.is_file() {
+                continue;
+            }
+            let referenced = item
+                .file_name()
+                .to_str()
+                .is_some_and(|name| refs.contains_key(name));
+            if !referenced {
+                fs::remove_file(item.path())?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn write_file(&self, name: &str, value: &[u8]) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let tmp = self.dir.join(format!("{}{}", name, TMP_SUFFIX));
+        fs::write(&tmp, value)?;
+        fs::rename(&tmp, self.dir.join(name)).map_err(|e| {
+            let _ = fs::remove_file(&tmp);
+            e
+        })
+    }
+
+    fn reference(&self, name: &str) -> Bytes {
+        let mut reference = Vec::with_capacity(MAGIC_LEN + name.len());
+        reference.extend_from_slice(&self.magic);
+        reference.extend_from_slice(name.as_bytes());
+        Bytes::from(reference)
+    }
+
+    fn name_of<'a>(&self, reference: &'a [u8]) -> io::Result<&'a str> {
+        if !self.is_ref(reference) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "not a spill reference",
+            ));
+        }
+        std::str::from_utf8(&reference[MAGIC_LEN..])
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+    }
+
+    /// The reference map holds no invariant a panic could break, so a
+    /// poisoned lock is recovered.
+    fn lock_refs(&self) -> MutexGuard<'_, HashMap<String, usize>> {
+        self.refs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// File name for `value`: its hash and length.
+fn file_name(value: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(value);
+    format!("{:016x}-{}", hasher.finish(), value.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "in-memory-cache-spill-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn store(dir: &std::path::Path) -> SpillStore {
+        SpillStore::new(&SpillOver {
+            threshold: 4,
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    fn files(dir: &std::path::Path) -> usize {
+        fs::read_dir(dir).map(|d| d.count()).unwrap_or(0)
+    }
+
+    #[test]
+    fn test_store_load_release() {
+        let dir = temp_dir("store");
+        let spill = store(&dir);
+        assert!(!spill.wants(b"tiny"));
+        assert!(spill.wants(b"large"));
+
+        let a = spill.store(b"large value").unwrap();
+        let b = spill.store(b"large value").unwrap();
+        assert_eq!(a, b);
+        assert!(spill.is_ref(&a));
+        assert!(!spill.is_ref(b"large value"));
+        assert_eq!(files(&dir), 1);
+        assert_eq!(spill.load(&a).unwrap(), Bytes::from("large value"));
+
+        // Shared file survives until the last reference goes
+        spill.release(&a).unwrap();
+        assert_eq!(files(&dir), 1);
+        spill.release(&b).unwrap();
+        assert_eq!(files(&dir), 0);
+        assert!(spill.load(&a).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_references_are_store_specific() {
+        let dir = temp_dir("magic");
+        let one = store(&dir);
+        let other = store(&dir);
+        let reference = one.store(b"large value").unwrap();
+        assert!(!other.is_ref(&reference));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_gc_removes_unreferenced_files() {
+        let dir = temp_dir("gc");
+        let spill = store(&dir);
+        let kept = spill.store(b"kept value").unwrap();
+        fs::write(dir.join("orphan-1"), b"x").unwrap();
+        fs::write(dir.join(format!("partial{}", TMP_SUFFIX)), b"x").unwrap();
+
+        assert_eq!(spill.gc().unwrap(), 2);
+        assert_eq!(files(&dir), 1);
+        assert_eq!(spill.load(&kept).unwrap(), Bytes::from("kept value"));
+        let _ = fs::remove_dir_all(&dir);
+
+        // A directory that was never created holds nothing to collect
+        assert_eq!(spill.gc().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_collision_with_referenced_file_fails() {
+        let dir = temp_dir("collision");
+        let spill = store(&dir);
+        let reference = spill.store(b"large value").unwrap();
+        // Simulate a different value hashing to the same name
+        let name = spill.name_of(&reference).unwrap().to_string();
+        fs::write(dir.join(&name), b"other value").unwrap();
+        assert!(spill.store(b"large value").is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
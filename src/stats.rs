@@ -3,7 +3,68 @@
 //! This module provides atomic counters for tracking cache operations,
 //! enabling observability without impacting performance.
 
+use std::ops::Sub;
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Number of log2 buckets in the lock wait histogram.
+const LOCK_WAIT_BUCKETS: usize = 32;
+
+/// Histogram of lock acquisition wait times.
+///
+/// Bucket `i` counts waits in `[2^i, 2^(i+1))` nanoseconds (bucket 0 also
+/// holds zero-length waits, the last bucket everything above ~2 seconds).
+/// Percentiles are reported as the upper bound of the bucket they fall in,
+/// so they are accurate to within a factor of two.
+#[derive(Debug, Default)]
+pub struct LockWaitHistogram {
+    buckets: [AtomicU64; LOCK_WAIT_BUCKETS],
+}
+
+impl LockWaitHistogram {
+    /// Record a single wait.
+    pub fn record(&self, wait: Duration) {
+        let nanos = u64::try_from(wait.as_nanos()).unwrap_or(u64::MAX);
+        let bucket = (63 - nanos.max(1).leading_zeros() as usize).min(LOCK_WAIT_BUCKETS - 1);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Total number of recorded waits.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().map(|b| b.load(Ordering::Relaxed)).sum()
+    }
+
+    /// Approximate wait time at the given quantile (0.0 to 1.0).
+    /// Returns `Duration::ZERO` if nothing has been recorded.
+    pub fn quantile(&self, q: f64) -> Duration {
+        let counts: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|b| b.load(Ordering::Relaxed))
+            .collect();
+        let total: u64 = counts.iter().sum();
+        if total == 0 {
+            return Duration::ZERO;
+        }
+
+        let target = ((total as f64) * q.clamp(0.0, 1.0)).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, count) in counts.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return Duration::from_nanos(1u64 << (i + 1));
+            }
+        }
+        Duration::from_nanos(1u64 << LOCK_WAIT_BUCKETS)
+    }
+
+    /// Forget all recorded waits.
+    pub fn reset(&self) {
+        for bucket in &self.buckets {
+            bucket.store(0, Ordering::Relaxed);
+        }
+    }
+}
 
 /// Statistics for cache operations.
 ///
@@ -28,17 +89,86 @@ pub struct CacheStats {
     /// Number of entries evicted due to capacity limits.
     evictions: AtomicU64,
 
-    /// Number of entries removed due to TTL expiration.
-    expirations: AtomicU64,
+    /// Total bytes (key + value) released by capacity evictions.
+    bytes_evicted: AtomicU64,
+
+    /// Number of expired entries removed by an operation that came across
+    /// them (a read, a write or `retain`).
+    expirations_lazy: AtomicU64,
+
+    /// Number of expired entries removed by `cleanup_expired` sweeps.
+    expirations_swept: AtomicU64,
 
     /// Current number of entries in the cache.
     size: AtomicU64,
 
-    /// Total number of set operations performed.
-    sets: AtomicU64,
+    /// Current summed key and value bytes of the stored entries.
+    memory_bytes: AtomicU64,
+
+    /// Current summed weight of the stored entries.
+    total_weight: AtomicU64,
+
+    /// Number of sets that stored a new key (or replaced an expired one).
+    inserts: AtomicU64,
+
+    /// Number of sets that replaced a live value.
+    overwrites: AtomicU64,
 
     /// Total number of delete operations performed.
     deletes: AtomicU64,
+
+    /// Number of non-blocking sets dropped because the lock was contended.
+    dropped_sets: AtomicU64,
+
+    /// Number of sets skipped because the stored value was identical.
+    coalesced_sets: AtomicU64,
+
+    /// Number of sets dropped for exceeding a key or value size limit.
+    rejected_sets: AtomicU64,
+
+    /// Number of user callbacks that panicked and were caught.
+    callback_panics: AtomicU64,
+
+    /// Number of lock acquisitions that found the storage lock poisoned and
+    /// recovered it.
+    lock_failures: AtomicU64,
+
+    /// Number of reads served from a spill-over file.
+    spill_hits: AtomicU64,
+
+    /// Number of values written to a spill-over file.
+    spill_writes: AtomicU64,
+
+    /// Number of spill-over file writes or reads that failed.
+    spill_errors: AtomicU64,
+
+    /// Number of get misses that would have been hits under the shadow TTL.
+    shadow_hits: AtomicU64,
+
+    /// Number of value checksums verified on reads.
+    checksum_verifications: AtomicU64,
+
+    /// Number of values found not to match their checksum.
+    checksum_failures: AtomicU64,
+
+    /// Number of callers that stopped waiting for another caller's load at
+    /// their deadline.
+    loader_timeouts: AtomicU64,
+
+    /// Number of hits that served a cached failure outcome.
+    error_hits: AtomicU64,
+
+    /// Lock acquisition wait times (only fed when `record_lock_waits` is on).
+    lock_waits: LockWaitHistogram,
+
+    /// Interval of the running cleanup task in milliseconds; 0 if none runs.
+    cleanup_interval_ms: AtomicU64,
+
+    /// Entries removed by the last background sweep.
+    last_sweep_removed: AtomicU64,
+
+    /// Entries the last background sweep looked at.
+    last_sweep_scanned: AtomicU64,
 }
 
 impl CacheStats {
@@ -62,14 +192,29 @@ impl CacheStats {
         self.evictions.fetch_add(1, Ordering::Relaxed);
     }
 
-    /// Record an expiration (due to TTL).
-    pub fn record_expiration(&self) {
-        self.expirations.fetch_add(1, Ordering::Relaxed);
+    /// Record the number of bytes released by an eviction.
+    pub fn record_evicted_bytes(&self, bytes: u64) {
+        self.bytes_evicted.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Record an expired entry removed by the operation that found it.
+    pub fn record_lazy_expiration(&self) {
+        self.expirations_lazy.fetch_add(1, Ordering::Relaxed);
     }
 
-    /// Record a set operation.
-    pub fn record_set(&self) {
-        self.sets.fetch_add(1, Ordering::Relaxed);
+    /// Record an expired entry removed by a cleanup sweep.
+    pub fn record_swept_expiration(&self) {
+        self.expirations_swept.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a set that stored a new key.
+    pub fn record_insert(&self) {
+        self.inserts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a set that replaced a live value.
+    pub fn record_overwrite(&self) {
+        self.overwrites.fetch_add(1, Ordering::Relaxed);
     }
 
     /// Record a delete operation.
@@ -77,14 +222,88 @@ impl CacheStats {
         self.deletes.fetch_add(1, Ordering::Relaxed);
     }
 
+    /// Record a non-blocking set that was dropped due to contention.
+    pub fn record_dropped_set(&self) {
+        self.dropped_sets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a set that was coalesced into an identical stored value.
+    pub fn record_coalesced_set(&self) {
+        self.coalesced_sets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a set rejected by a size limit.
+    pub fn record_rejected_set(&self) {
+        self.rejected_sets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a panic caught in a user callback.
+    pub fn record_callback_panic(&self) {
+        self.callback_panics.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a storage lock found poisoned by an earlier panic.
+    pub fn record_lock_failure(&self) {
+        self.lock_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a read served from a spill-over file.
+    pub fn record_spill_hit(&self) {
+        self.spill_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a value written to a spill-over file.
+    pub fn record_spill_write(&self) {
+        self.spill_writes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a failed spill-over file write or read.
+    pub fn record_spill_error(&self) {
+        self.spill_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a get miss that the shadow TTL would have served.
+    pub fn record_shadow_hit(&self) {
+        self.shadow_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a verified value checksum.
+    pub fn record_checksum_verification(&self) {
+        self.checksum_verifications.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a value that did not match its checksum.
+    pub fn record_checksum_failure(&self) {
+        self.checksum_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a caller that gave up waiting for another caller's load.
+    pub fn record_loader_timeout(&self) {
+        self.loader_timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a hit that served a cached failure.
+    pub fn record_error_hit(&self) {
+        self.error_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record how long a lock acquisition waited.
+    pub fn record_lock_wait(&self, wait: Duration) {
+        self.lock_waits.record(wait);
+    }
+
     /// Increment the size counter.
     pub fn increment_size(&self) {
         self.size.fetch_add(1, Ordering::Relaxed);
     }
 
-    /// Decrement the size counter.
+    /// Decrement the size counter, saturating at zero.
     pub fn decrement_size(&self) {
-        self.size.fetch_sub(1, Ordering::Relaxed);
+        let _ = self
+            .size
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |size| {
+                size.checked_sub(1)
+            });
     }
 
     /// Set the size to a specific value.
@@ -92,6 +311,58 @@ impl CacheStats {
         self.size.store(size, Ordering::Relaxed);
     }
 
+    /// Add `bytes` to the memory counter.
+    pub fn add_memory(&self, bytes: u64) {
+        self.memory_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Subtract `bytes` from the memory counter, saturating at zero.
+    pub fn sub_memory(&self, bytes: u64) {
+        let _ = self
+            .memory_bytes
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |memory| {
+                Some(memory.saturating_sub(bytes))
+            });
+    }
+
+    /// Set the memory counter to a specific value.
+    pub fn set_memory(&self, bytes: u64) {
+        self.memory_bytes.store(bytes, Ordering::Relaxed);
+    }
+
+    /// Add `weight` to the weight counter.
+    pub fn add_weight(&self, weight: u64) {
+        self.total_weight.fetch_add(weight, Ordering::Relaxed);
+    }
+
+    /// Subtract `weight` from the weight counter, saturating at zero.
+    pub fn sub_weight(&self, weight: u64) {
+        let _ = self
+            .total_weight
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |total| {
+                Some(total.saturating_sub(weight))
+            });
+    }
+
+    /// Set the weight counter to a specific value.
+    pub fn set_weight(&self, weight: u64) {
+        self.total_weight.store(weight, Ordering::Relaxed);
+    }
+
+    /// Record the outcome of a background sweep.
+    pub fn record_sweep(&self, removed: usize, scanned: usize) {
+        self.last_sweep_removed
+            .store(removed as u64, Ordering::Relaxed);
+        self.last_sweep_scanned
+            .store(scanned as u64, Ordering::Relaxed);
+    }
+
+    /// Set the interval of the running cleanup task; zero when none runs.
+    pub fn set_cleanup_interval(&self, interval: Duration) {
+        let millis = u64::try_from(interval.as_millis()).unwrap_or(u64::MAX);
+        self.cleanup_interval_ms.store(millis, Ordering::Relaxed);
+    }
+
     // Getters for reading statistics
 
     /// Get the number of cache hits.
@@ -109,9 +380,25 @@ impl CacheStats {
         self.evictions.load(Ordering::Relaxed)
     }
 
-    /// Get the number of expirations.
+    /// Get the total bytes released by evictions.
+    pub fn bytes_evicted(&self) -> u64 {
+        self.bytes_evicted.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of expirations, lazy and swept.
     pub fn expirations(&self) -> u64 {
-        self.expirations.load(Ordering::Relaxed)
+        self.expirations_lazy() + self.expirations_swept()
+    }
+
+    /// Get the number of expirations removed by the operation that found
+    /// them.
+    pub fn expirations_lazy(&self) -> u64 {
+        self.expirations_lazy.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of expirations removed by cleanup sweeps.
+    pub fn expirations_swept(&self) -> u64 {
+        self.expirations_swept.load(Ordering::Relaxed)
     }
 
     /// Get the current cache size.
@@ -119,9 +406,29 @@ impl CacheStats {
         self.size.load(Ordering::Relaxed)
     }
 
-    /// Get the total number of set operations.
+    /// Get the summed key and value bytes of the stored entries.
+    pub fn memory_bytes(&self) -> u64 {
+        self.memory_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Get the summed weight of the stored entries.
+    pub fn total_weight(&self) -> u64 {
+        self.total_weight.load(Ordering::Relaxed)
+    }
+
+    /// Get the total number of set operations, inserts and overwrites.
     pub fn sets(&self) -> u64 {
-        self.sets.load(Ordering::Relaxed)
+        self.inserts() + self.overwrites()
+    }
+
+    /// Get the number of sets that stored a new key.
+    pub fn inserts(&self) -> u64 {
+        self.inserts.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of sets that replaced a live value.
+    pub fn overwrites(&self) -> u64 {
+        self.overwrites.load(Ordering::Relaxed)
     }
 
     /// Get the total number of delete operations.
@@ -129,6 +436,138 @@ impl CacheStats {
         self.deletes.load(Ordering::Relaxed)
     }
 
+    /// Get the number of dropped non-blocking sets.
+    pub fn dropped_sets(&self) -> u64 {
+        self.dropped_sets.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of coalesced identical sets.
+    pub fn coalesced_sets(&self) -> u64 {
+        self.coalesced_sets.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of sets rejected by a size limit.
+    pub fn rejected_sets(&self) -> u64 {
+        self.rejected_sets.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of caught callback panics.
+    pub fn callback_panics(&self) -> u64 {
+        self.callback_panics.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of storage lock acquisitions that recovered a
+    /// poisoned lock.
+    pub fn lock_failures(&self) -> u64 {
+        self.lock_failures.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of reads served from spill-over files.
+    pub fn spill_hits(&self) -> u64 {
+        self.spill_hits.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of values written to spill-over files.
+    pub fn spill_writes(&self) -> u64 {
+        self.spill_writes.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of failed spill-over file operations.
+    pub fn spill_errors(&self) -> u64 {
+        self.spill_errors.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of misses that TTLs scaled by `shadow_ttl_factor`
+    /// would have turned into hits.
+    pub fn shadow_hits(&self) -> u64 {
+        self.shadow_hits.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of value checksums verified.
+    pub fn checksum_verifications(&self) -> u64 {
+        self.checksum_verifications.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of values that failed checksum verification.
+    pub fn checksum_failures(&self) -> u64 {
+        self.checksum_failures.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of callers that gave up waiting for another caller's
+    /// load.
+    pub fn loader_timeouts(&self) -> u64 {
+        self.loader_timeouts.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of hits that served a cached failure.
+    pub fn error_hits(&self) -> u64 {
+        self.error_hits.load(Ordering::Relaxed)
+    }
+
+    /// Get the lock wait histogram.
+    pub fn lock_waits(&self) -> &LockWaitHistogram {
+        &self.lock_waits
+    }
+
+    /// Get the interval of the running cleanup task in milliseconds.
+    pub fn cleanup_interval_ms(&self) -> u64 {
+        self.cleanup_interval_ms.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of entries removed by the last background sweep.
+    pub fn last_sweep_removed(&self) -> u64 {
+        self.last_sweep_removed.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of entries the last background sweep looked at.
+    pub fn last_sweep_scanned(&self) -> u64 {
+        self.last_sweep_scanned.load(Ordering::Relaxed)
+    }
+
+    /// Zero every counter except `size`, `memory_bytes`, `total_weight` and
+    /// the cleanup figures, which describe the current state rather than
+    /// accumulated events.
+    pub fn reset(&self) {
+        for counter in [
+            &self.hits,
+            &self.misses,
+            &self.evictions,
+            &self.bytes_evicted,
+            &self.expirations_lazy,
+            &self.expirations_swept,
+            &self.inserts,
+            &self.overwrites,
+            &self.deletes,
+            &self.dropped_sets,
+            &self.coalesced_sets,
+            &self.rejected_sets,
+            &self.callback_panics,
+            &self.lock_failures,
+            &self.spill_hits,
+            &self.spill_writes,
+            &self.spill_errors,
+            &self.shadow_hits,
+            &self.checksum_verifications,
+            &self.checksum_failures,
+            &self.loader_timeouts,
+            &self.error_hits,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+        self.lock_waits.reset();
+    }
+
+    /// Average key plus value bytes per stored entry.
+    /// Returns 0.0 if the cache is empty.
+    pub fn average_entry_size(&self) -> f64 {
+        let size = self.size();
+        if size == 0 {
+            0.0
+        } else {
+            self.memory_bytes() as f64 / size as f64
+        }
+    }
+
     /// Calculate the hit rate as a percentage (0.0 to 100.0).
     /// Returns 0.0 if no operations have been performed.
     pub fn hit_rate(&self) -> f64 {
@@ -149,11 +588,37 @@ impl CacheStats {
             hits: self.hits(),
             misses: self.misses(),
             evictions: self.evictions(),
+            bytes_evicted: self.bytes_evicted(),
             expirations: self.expirations(),
+            expirations_lazy: self.expirations_lazy(),
+            expirations_swept: self.expirations_swept(),
             size: self.size(),
+            memory_bytes: self.memory_bytes(),
+            average_entry_size: self.average_entry_size(),
+            total_weight: self.total_weight(),
             sets: self.sets(),
+            inserts: self.inserts(),
+            overwrites: self.overwrites(),
             deletes: self.deletes(),
+            dropped_sets: self.dropped_sets(),
+            coalesced_sets: self.coalesced_sets(),
+            rejected_sets: self.rejected_sets(),
+            callback_panics: self.callback_panics(),
+            lock_failures: self.lock_failures(),
+            spill_hits: self.spill_hits(),
+            spill_writes: self.spill_writes(),
+            spill_errors: self.spill_errors(),
+            shadow_hits: self.shadow_hits(),
+            checksum_verifications: self.checksum_verifications(),
+            checksum_failures: self.checksum_failures(),
+            loader_timeouts: self.loader_timeouts(),
+            error_hits: self.error_hits(),
+            lock_wait_p50_ns: duration_nanos(self.lock_waits.quantile(0.50)),
+            lock_wait_p99_ns: duration_nanos(self.lock_waits.quantile(0.99)),
             hit_rate: self.hit_rate(),
+            cleanup_interval_ms: self.cleanup_interval_ms(),
+            last_sweep_removed: self.last_sweep_removed(),
+            last_sweep_scanned: self.last_sweep_scanned(),
         }
     }
 }
@@ -162,16 +627,355 @@ impl CacheStats {
 ///
 /// Unlike `CacheStats`, this struct contains plain values (not atomics)
 /// and can be easily serialized or logged.
+///
+/// Subtracting an earlier snapshot from a later one gives the activity in
+/// between, for windowed metrics:
+///
+/// ```
+/// use in_memory_cache::Cache;
+///
+/// let cache = Cache::default();
+/// cache.set("a", "1");
+/// cache.get("a");
+/// let before = cache.stats();
+/// cache.get("a");
+/// cache.get("missing");
+///
+/// let window = cache.stats() - before;
+/// assert_eq!((window.hits, window.misses), (1, 1));
+/// assert_eq!(window.hit_rate, 50.0);
+/// assert_eq!(window.size, 1);
+/// ```
+///
+/// Event counters are subtracted, saturating at zero when the stats were
+/// reset in between. `hit_rate` is recomputed from the differences. Fields
+/// that describe the current state (`size`, `memory_bytes`,
+/// `average_entry_size`, `total_weight`, the lock wait percentiles and the
+/// cleanup figures) are taken from the later snapshot.
 #[derive(Debug, Clone, PartialEq)]
 pub struct StatsSnapshot {
     pub hits: u64,
     pub misses: u64,
     pub evictions: u64,
+    pub bytes_evicted: u64,
+    /// `expirations_lazy` plus `expirations_swept`.
     pub expirations: u64,
+    /// Expired entries removed by the read or write that found them.
+    pub expirations_lazy: u64,
+    /// Expired entries removed by cleanup sweeps.
+    pub expirations_swept: u64,
     pub size: u64,
+    /// Summed key and value bytes of the stored entries (see
+    /// `CacheConfig::max_memory`).
+    pub memory_bytes: u64,
+    /// `memory_bytes` divided by `size`; 0 when the cache is empty.
+    pub average_entry_size: f64,
+    /// Summed weight of the stored entries (see `CacheConfig::weigher`).
+    pub total_weight: u64,
+    /// `inserts` plus `overwrites`.
     pub sets: u64,
+    /// Sets that stored a new key, or replaced an expired entry.
+    pub inserts: u64,
+    /// Sets that replaced a live value.
+    pub overwrites: u64,
     pub deletes: u64,
+    pub dropped_sets: u64,
+    pub coalesced_sets: u64,
+    /// Sets dropped for exceeding `CacheConfig::max_key_length` or
+    /// `max_value_size`.
+    pub rejected_sets: u64,
+    pub callback_panics: u64,
+    /// Storage lock acquisitions that found the lock poisoned by a panic
+    /// and recovered it. A lock stays poisoned, so once nonzero this keeps
+    /// counting the operations on the affected shard.
+    pub lock_failures: u64,
+    pub spill_hits: u64,
+    pub spill_writes: u64,
+    pub spill_errors: u64,
+    /// Misses a longer TTL would have served (see
+    /// `CacheConfig::shadow_ttl_factor`).
+    pub shadow_hits: u64,
+    pub checksum_verifications: u64,
+    pub checksum_failures: u64,
+    /// Callers that stopped waiting for another caller's load at their
+    /// deadline (see `Cache::get_or_load_within`).
+    pub loader_timeouts: u64,
+    /// Hits that served a cached failure (see `Cache::get_outcome`); also
+    /// counted in `hits`.
+    pub error_hits: u64,
+    /// Median lock wait in nanoseconds (0 unless `record_lock_waits` is on).
+    pub lock_wait_p50_ns: u64,
+    /// 99th percentile lock wait in nanoseconds.
+    pub lock_wait_p99_ns: u64,
     pub hit_rate: f64,
+    /// Current interval of the background cleanup in milliseconds; 0 when
+    /// no cleanup task runs (see `Cache::start_cleanup`).
+    pub cleanup_interval_ms: u64,
+    /// Entries removed by the last background sweep.
+    pub last_sweep_removed: u64,
+    /// Entries the last background sweep looked at.
+    pub last_sweep_scanned: u64,
+}
+
+impl Sub for &StatsSnapshot {
+    type Output = StatsSnapshot;
+
+    fn sub(self, earlier: &StatsSnapshot) -> StatsSnapshot {
+        let hits = self.hits.saturating_sub(earlier.hits);
+        let misses = self.misses.saturating_sub(earlier.misses);
+        let total = hits + misses;
+        StatsSnapshot {
+            hits,
+            misses,
+            evictions: self.evictions.saturating_sub(earlier.evictions),
+            bytes_evicted: self.bytes_evicted.saturating_sub(earlier.bytes_evicted),
+            expirations: self.expirations.saturating_sub(earlier.expirations),
+            expirations_lazy: self
+                .expirations_lazy
+                .saturating_sub(earlier.expirations_lazy),
+            expirations_swept: self
+                .expirations_swept
+                .saturating_sub(earlier.expirations_swept),
+            size: self.size,
+            memory_bytes: self.memory_bytes,
+            average_entry_size: self.average_entry_size,
+            total_weight: self.total_weight,
+            sets: self.sets.saturating_sub(earlier.sets),
+            inserts: self.inserts.saturating_sub(earlier.inserts),
+            overwrites: self.overwrites.saturating_sub(earlier.overwrites),
+            deletes: self.deletes.saturating_sub(earlier.deletes),
+            dropped_sets: self.dropped_sets.saturating_sub(earlier.dropped_sets),
+            coalesced_sets: self.coalesced_sets.saturating_sub(earlier.coalesced_sets),
+            rejected_sets: self.rejected_sets.saturating_sub(earlier.rejected_sets),
+            callback_panics: self.callback_panics.saturating_sub(earlier.callback_panics),
+            lock_failures: self.lock_failures.saturating_sub(earlier.lock_failures),
+            spill_hits: self.spill_hits.saturating_sub(earlier.spill_hits),
+            spill_writes: self.spill_writes.saturating_sub(earlier.spill_writes),
+            spill_errors: self.spill_errors.saturating_sub(earlier.spill_errors),
+            shadow_hits: self.shadow_hits.saturating_sub(earlier.shadow_hits),
+            checksum_verifications: self
+                .checksum_verifications
+                .saturating_sub(earlier.checksum_verifications),
+            checksum_failures: self
+                .checksum_failures
+                .saturating_sub(earlier.checksum_failures),
+            loader_timeouts: self.loader_timeouts.saturating_sub(earlier.loader_timeouts),
+            error_hits: self.error_hits.saturating_sub(earlier.error_hits),
+            lock_wait_p50_ns: self.lock_wait_p50_ns,
+            lock_wait_p99_ns: self.lock_wait_p99_ns,
+            hit_rate: if total == 0 {
+                0.0
+            } else {
+                (hits as f64 / total as f64) * 100.0
+            },
+            cleanup_interval_ms: self.cleanup_interval_ms,
+            last_sweep_removed: self.last_sweep_removed,
+            last_sweep_scanned: self.last_sweep_scanned,
+        }
+    }
+}
+
+impl Sub for StatsSnapshot {
+    type Output = StatsSnapshot;
+
+    fn sub(self, earlier: StatsSnapshot) -> StatsSnapshot {
+        &self - &earlier
+    }
+}
+
+/// Render a snapshot in the Prometheus text exposition format.
+///
+/// Every metric name starts with `prefix` followed by an underscore.
+/// Event counters are exported as counters with a `_total` suffix; the
+/// current size, memory, weight, hit rate (in percent), lock wait
+/// percentiles and last sweep figure as gauges.
+///
+/// ```
+/// use in_memory_cache::{stats::prometheus_text, Cache};
+///
+/// let cache = Cache::default();
+/// cache.set("a", "1");
+/// cache.get("a");
+///
+/// let text = prometheus_text(&cache.stats(), "cache");
+/// assert!(text.contains("# TYPE cache_hits_total counter\ncache_hits_total 1\n"));
+/// assert!(text.contains("cache_size 1\n"));
+/// ```
+pub fn prometheus_text(snapshot: &StatsSnapshot, prefix: &str) -> String {
+    let counters: [(&str, &str, u64); 24] = [
+        (
+            "hits_total",
+            "Reads that found a live entry.",
+            snapshot.hits,
+        ),
+        ("misses_total", "Reads that found nothing.", snapshot.misses),
+        (
+            "evictions_total",
+            "Entries evicted to stay within a limit.",
+            snapshot.evictions,
+        ),
+        (
+            "evicted_bytes_total",
+            "Key and value bytes of evicted entries.",
+            snapshot.bytes_evicted,
+        ),
+        (
+            "expirations_total",
+            "Entries removed after their TTL.",
+            snapshot.expirations,
+        ),
+        (
+            "expirations_lazy_total",
+            "Expired entries removed by the read or write that found them.",
+            snapshot.expirations_lazy,
+        ),
+        (
+            "expirations_swept_total",
+            "Expired entries removed by cleanup sweeps.",
+            snapshot.expirations_swept,
+        ),
+        ("sets_total", "Writes.", snapshot.sets),
+        (
+            "inserts_total",
+            "Writes that stored a new key.",
+            snapshot.inserts,
+        ),
+        (
+            "overwrites_total",
+            "Writes that replaced a live value.",
+            snapshot.overwrites,
+        ),
+        ("deletes_total", "Explicit removals.", snapshot.deletes),
+        (
+            "dropped_sets_total",
+            "Non-blocking writes dropped under contention.",
+            snapshot.dropped_sets,
+        ),
+        (
+            "coalesced_sets_total",
+            "Writes of an identical value that were skipped.",
+            snapshot.coalesced_sets,
+        ),
+        (
+            "rejected_sets_total",
+            "Writes rejected for exceeding a key or value size limit.",
+            snapshot.rejected_sets,
+        ),
+        (
+            "callback_panics_total",
+            "Panics caught in user callbacks.",
+            snapshot.callback_panics,
+        ),
+        (
+            "lock_failures_total",
+            "Storage lock acquisitions that recovered a poisoned lock.",
+            snapshot.lock_failures,
+        ),
+        (
+            "spill_hits_total",
+            "Reads served from spill-over files.",
+            snapshot.spill_hits,
+        ),
+        (
+            "spill_writes_total",
+            "Values written to spill-over files.",
+            snapshot.spill_writes,
+        ),
+        (
+            "spill_errors_total",
+            "Failed spill-over file operations.",
+            snapshot.spill_errors,
+        ),
+        (
+            "shadow_hits_total",
+            "Misses a longer TTL would have served.",
+            snapshot.shadow_hits,
+        ),
+        (
+            "checksum_verifications_total",
+            "Value checksums verified.",
+            snapshot.checksum_verifications,
+        ),
+        (
+            "checksum_failures_total",
+            "Values that failed checksum verification.",
+            snapshot.checksum_failures,
+        ),
+        (
+            "loader_timeouts_total",
+            "Callers that gave up waiting for another caller's load.",
+            snapshot.loader_timeouts,
+        ),
+        (
+            "error_hits_total",
+            "Hits that served a cached failure.",
+            snapshot.error_hits,
+        ),
+    ];
+    let gauges: [(&str, &str, f64); 8] = [
+        ("size", "Entries stored.", snapshot.size as f64),
+        (
+            "memory_bytes",
+            "Key and value bytes stored.",
+            snapshot.memory_bytes as f64,
+        ),
+        (
+            "average_entry_size_bytes",
+            "Key and value bytes per stored entry.",
+            snapshot.average_entry_size,
+        ),
+        (
+            "total_weight",
+            "Summed weight of the stored entries.",
+            snapshot.total_weight as f64,
+        ),
+        ("hit_rate", "Hits per read in percent.", snapshot.hit_rate),
+        (
+            "lock_wait_p50_seconds",
+            "Median lock wait.",
+            snapshot.lock_wait_p50_ns as f64 / 1e9,
+        ),
+        (
+            "lock_wait_p99_seconds",
+            "99th percentile lock wait.",
+            snapshot.lock_wait_p99_ns as f64 / 1e9,
+        ),
+        (
+            "last_sweep_removed",
+            "Entries removed by the last background sweep.",
+            snapshot.last_sweep_removed as f64,
+        ),
+    ];
+
+    let mut out = String::new();
+    for (name, help, value) in counters {
+        push_metric(&mut out, prefix, name, help, "counter", &value.to_string());
+    }
+    for (name, help, value) in gauges {
+        push_metric(&mut out, prefix, name, help, "gauge", &value.to_string());
+    }
+    out
+}
+
+fn push_metric(out: &mut String, prefix: &str, name: &str, help: &str, kind: &str, value: &str) {
+    out.push_str(&format!(
+        "# HELP {prefix}_{name} {help}\n# TYPE {prefix}_{name} {kind}\n{prefix}_{name} {value}\n"
+    ));
+}
+
+/// Live entries under one key prefix, as returned by `Cache::prefix_stats`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrefixStats {
+    /// Number of live entries whose key starts with the prefix.
+    pub entries: u64,
+    /// Sum of key and value lengths of those entries.
+    pub bytes: u64,
+    /// How many of those entries have a TTL.
+    pub with_ttl: u64,
+}
+
+fn duration_nanos(d: Duration) -> u64 {
+    u64::try_from(d.as_nanos()).unwrap_or(u64::MAX)
 }
 
 #[cfg(test)]
@@ -224,13 +1028,43 @@ mod tests {
 
         stats.decrement_size();
         assert_eq!(stats.size(), 1);
+
+        stats.add_memory(100);
+        stats.sub_memory(30);
+        assert_eq!(stats.memory_bytes(), 70);
+        stats.sub_memory(100);
+        assert_eq!(stats.memory_bytes(), 0);
+
+        stats.add_weight(5);
+        stats.sub_weight(7);
+        assert_eq!(stats.total_weight(), 0);
+    }
+
+    #[test]
+    fn test_lock_wait_histogram() {
+        let histogram = LockWaitHistogram::default();
+        assert_eq!(histogram.quantile(0.5), Duration::ZERO);
+
+        for _ in 0..98 {
+            histogram.record(Duration::from_nanos(100)); // bucket [64, 128)
+        }
+        histogram.record(Duration::from_micros(50)); // bucket [32768, 65536)
+        histogram.record(Duration::from_secs(3600)); // clamped to last bucket
+
+        assert_eq!(histogram.count(), 100);
+        assert_eq!(histogram.quantile(0.5), Duration::from_nanos(128));
+        assert_eq!(histogram.quantile(0.99), Duration::from_nanos(65536));
+        assert_eq!(histogram.quantile(1.0), Duration::from_nanos(1 << 32));
+
+        histogram.record(Duration::ZERO);
+        assert_eq!(histogram.count(), 101);
     }
 
     #[test]
     fn test_snapshot() {
         let stats = CacheStats::new();
         stats.record_hit();
-        stats.record_set();
+        stats.record_insert();
         stats.increment_size();
 
         let snapshot = stats.snapshot();
@@ -238,4 +1072,116 @@ mod tests {
         assert_eq!(snapshot.sets, 1);
         assert_eq!(snapshot.size, 1);
     }
+
+    #[test]
+    fn test_reset_keeps_size() {
+        let stats = CacheStats::new();
+        stats.record_hit();
+        stats.record_miss();
+        stats.record_eviction();
+        stats.record_lock_wait(Duration::from_micros(5));
+        stats.increment_size();
+        stats.add_memory(10);
+
+        stats.reset();
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.hits, 0);
+        assert_eq!(snapshot.misses, 0);
+        assert_eq!(snapshot.evictions, 0);
+        assert_eq!(stats.lock_waits().count(), 0);
+        assert_eq!(snapshot.size, 1);
+        assert_eq!(snapshot.memory_bytes, 10);
+    }
+
+    #[test]
+    fn test_snapshot_difference() {
+        let stats = CacheStats::new();
+        stats.record_hit();
+        stats.record_insert();
+        let earlier = stats.snapshot();
+        stats.record_hit();
+        stats.record_hit();
+        stats.record_hit();
+        stats.record_miss();
+        stats.record_insert();
+        stats.increment_size();
+
+        let window = &stats.snapshot() - &earlier;
+        assert_eq!((window.hits, window.misses, window.sets), (3, 1, 1));
+        assert_eq!(window.hit_rate, 75.0);
+        assert_eq!(window.size, 1);
+        assert_eq!(stats.snapshot() - stats.snapshot(), {
+            let mut idle = stats.snapshot();
+            idle.hits = 0;
+            idle.misses = 0;
+            idle.sets = 0;
+            idle.inserts = 0;
+            idle.hit_rate = 0.0;
+            idle
+        });
+
+        // A reset in between saturates instead of wrapping
+        let before_reset = stats.snapshot();
+        stats.reset();
+        stats.record_miss();
+        let window = stats.snapshot() - before_reset;
+        assert_eq!((window.hits, window.misses, window.sets), (0, 0, 0));
+        assert_eq!(window.hit_rate, 0.0);
+    }
+
+    /// Check `text` line by line: every sample must follow its `# HELP`
+    /// and `# TYPE` lines and carry a numeric value. Returns the samples.
+    fn parse_exposition(text: &str) -> Vec<(String, f64)> {
+        let mut samples = Vec::new();
+        let mut lines = text.lines();
+        while let Some(help) = lines.next() {
+            let name = help
+                .strip_prefix("# HELP ")
+                .unwrap()
+                .split(' ')
+                .next()
+                .unwrap();
+            let kind = lines.next().unwrap().strip_prefix("# TYPE ").unwrap();
+            assert!(
+                kind == format!("{} counter", name) || kind == format!("{} gauge", name),
+                "bad TYPE line: {}",
+                kind
+            );
+            let (sample, value) = lines.next().unwrap().split_once(' ').unwrap();
+            assert_eq!(sample, name);
+            assert!(name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'));
+            samples.push((name.to_string(), value.parse::<f64>().unwrap()));
+        }
+        samples
+    }
+
+    #[test]
+    fn test_prometheus_text() {
+        let stats = CacheStats::new();
+        stats.record_hit();
+        stats.record_hit();
+        stats.record_hit();
+        stats.record_miss();
+        stats.increment_size();
+
+        let text = prometheus_text(&stats.snapshot(), "app_cache");
+        assert!(text.ends_with('\n'));
+        let samples = parse_exposition(&text);
+        let value = |name: &str| {
+            samples
+                .iter()
+                .find(|(sample, _)| sample == name)
+                .map(|(_, value)| *value)
+        };
+        assert_eq!(value("app_cache_hits_total"), Some(3.0));
+        assert_eq!(value("app_cache_misses_total"), Some(1.0));
+        assert_eq!(value("app_cache_size"), Some(1.0));
+        assert_eq!(value("app_cache_hit_rate"), Some(75.0));
+        assert_eq!(value("app_cache_lock_wait_p99_seconds"), Some(0.0));
+        assert!(samples
+            .iter()
+            .all(|(name, _)| name.starts_with("app_cache_")));
+        assert!(text.contains("# TYPE app_cache_evictions_total counter\n"));
+        assert!(text.contains("# TYPE app_cache_size gauge\n"));
+    }
 }
@@ -1,41 +1,236 @@
 //! Internal storage implementation for the cache.
 //!
 //! This module provides the low-level storage using an `IndexMap` for
-//! maintaining insertion order (used for LRU eviction).
+//! maintaining insertion order (used for LRU eviction). The map can be split
+//! into shards by key hash (see `CacheConfig::shards`), each with its own
+//! lock.
 
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use indexmap::IndexMap;
-use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::hash_map::RandomState;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::ops::{ControlFlow, Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, LockResult, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError};
 use std::time::{Duration, Instant};
 
-use crate::config::CacheConfig;
-use crate::entry::Entry;
+use crate::callback;
+use crate::checksum;
+use crate::config::{CacheConfig, EvictionPolicy, SpillFallback};
+use crate::entry::{self, Entry, EntryInfo, Epoch};
 use crate::error::{CacheError, CacheResult};
-use crate::stats::CacheStats;
+use crate::export::EntryRecord;
+use crate::glob::Glob;
+use crate::health::{GrowthAlarm, HealthEvent, HealthTracker};
+use crate::listener::{EvictionListener, Removal, RemovalCause};
+use crate::ops::SetOutcome;
+use crate::rng::{self, Rng};
+use crate::spill::SpillStore;
+use crate::stats::{CacheStats, PrefixStats};
+use crate::txn::{TxnView, Write};
+
+/// Maximum number of keys `purge_idle` removes per write-lock acquisition.
+const PURGE_CHUNK: usize = 256;
+
+/// Reads a shard buffers for replay before a reader tries to replay them
+/// itself. Reads past this while the write lock is busy are dropped.
+const READ_BUFFER: usize = 1024;
+
+/// Deadline records allowed beyond twice the entry count before they are
+/// rebuilt from the map.
+const STALE_DEADLINES: usize = 1024;
+
+/// Longest scaled TTL tracked for shadow hits, so huge factors cannot
+/// overflow an `Instant`.
+const MAX_SHADOW_TTL: Duration = Duration::from_secs(10 * 365 * 86_400);
+
+/// Sequence numbers of the keys pushed under one prefix by `push_capped`.
+#[derive(Debug, Clone, Default)]
+struct PrefixQueue {
+    next_seq: u64,
+    /// Pushed sequence numbers, oldest first. May include keys that have
+    /// since been deleted, evicted or expired; those are pruned lazily.
+    seqs: VecDeque<u64>,
+}
+
+/// One segment of the map, holding the keys that hash to it.
+#[derive(Debug)]
+struct Shard {
+    /// The entries, in LRU order within the shard.
+    entries: RwLock<IndexMap<String, Entry>>,
+
+    /// When each entry written with a deadline may be swept, earliest
+    /// first, so sweeps find expired entries without a scan. Records go
+    /// stale when their entry is removed or retimed and are checked against
+    /// the map as they come up. Only locked while holding the `entries`
+    /// lock.
+    deadlines: Mutex<BinaryHeap<Reverse<(Instant, String)>>>,
+
+    /// Index the next batched sweep starts at (see `cleanup_expired_batch`).
+    /// Only changed under the write lock.
+    sweep_cursor: AtomicUsize,
+
+    /// Keys read since the write lock was last taken, oldest first. Hits
+    /// only hold the read lock, so they are replayed into the LRU order by
+    /// the next writer (see `Db::replay_reads`). Locked while holding the
+    /// `entries` lock, read or write.
+    reads: Mutex<Vec<String>>,
+
+    /// This shard's part of `max_capacity`.
+    capacity: Option<usize>,
+}
+
+impl Shard {
+    fn new(entries: IndexMap<String, Entry>, capacity: Option<usize>) -> Self {
+        Self {
+            entries: RwLock::new(entries),
+            deadlines: Mutex::new(BinaryHeap::new()),
+            sweep_cursor: AtomicUsize::new(0),
+            reads: Mutex::new(Vec::new()),
+            capacity,
+        }
+    }
+
+    fn lock_deadlines(&self) -> std::sync::MutexGuard<'_, BinaryHeap<Reverse<(Instant, String)>>> {
+        self.deadlines.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock_reads(&self) -> std::sync::MutexGuard<'_, Vec<String>> {
+        self.reads.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Buffer a read of `key` for replay, returning whether the buffer is
+    /// full. A read that finds it full is dropped.
+    fn record_read(&self, key: &str) -> bool {
+        let mut reads = self.lock_reads();
+        if reads.len() < READ_BUFFER {
+            reads.push(key.to_string());
+        }
+        reads.len() >= READ_BUFFER
+    }
+}
+
+/// What a hit found under the read lock still needs once that lock is
+/// released.
+enum AfterHit {
+    /// Nothing; the hit is fully recorded.
+    Done,
+    /// Promote the entry under the write lock, to slide its deadline.
+    Promote,
+    /// Take the write lock to replay the full read buffer.
+    Replay,
+}
+
+/// A write-locked shard. Derefs to its map, and keeps the shard at hand
+/// for the helpers that need its capacity or deadline records.
+struct ShardGuard<'a> {
+    shard: &'a Shard,
+    entries: RwLockWriteGuard<'a, IndexMap<String, Entry>>,
+}
+
+impl Deref for ShardGuard<'_> {
+    type Target = IndexMap<String, Entry>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.entries
+    }
+}
+
+impl DerefMut for ShardGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.entries
+    }
+}
 
 /// Thread-safe wrapper around the internal database.
 ///
 /// This is the internal implementation; users should use `Cache` instead.
 #[derive(Debug)]
 pub struct Db {
-    /// The actual storage, protected by a read-write lock.
-    /// IndexMap maintains insertion order, which we use for LRU eviction.
-    entries: RwLock<IndexMap<String, Entry>>,
+    /// The actual storage, split into shards that are each protected by a
+    /// read-write lock. IndexMap maintains insertion order, which we use
+    /// for LRU eviction. Operations that lock several shards lock them in
+    /// index order.
+    shards: Box<[Shard]>,
+
+    /// Picks the shard of a key. Clones keep it, so keys stay put.
+    hasher: RandomState,
 
     /// Configuration for this cache instance.
     config: CacheConfig,
 
     /// Statistics for cache operations.
     stats: Arc<CacheStats>,
+
+    /// Per-prefix queues for `push_capped`. Only locked while holding the
+    /// lock of every shard, which keeps pushes to one prefix serialized.
+    queues: Mutex<HashMap<String, PrefixQueue>>,
+
+    /// Root seed for randomized components (see `crate::rng`).
+    rng_seed: u64,
+
+    /// Draws for `ttl_jitter`. Only locked while holding a shard lock.
+    ttl_rng: Mutex<Rng>,
+
+    /// Instant entry times are measured from. Rebased under the write lock
+    /// of every shard.
+    epoch: Epoch,
+
+    /// Shadow deadline of each key last written with a TTL, kept while
+    /// `shadow_ttl_factor` is set (see `record_miss`). Only locked while
+    /// holding a shard lock, or on its own.
+    shadow: Mutex<HashMap<String, Instant>>,
+
+    /// Files for spilled values, if `spill_over` is configured. Stored
+    /// values that are references into it are resolved on the way out.
+    spill: Option<Arc<SpillStore>>,
+
+    /// Soft threshold state and monitor callbacks, shared with the `Cache`
+    /// wrapping this database.
+    health: Arc<HealthTracker>,
+
+    /// Growth alarm, kept for caches without `max_capacity` (see
+    /// `CacheConfig::growth_alarm`).
+    growth: Option<GrowthAlarm>,
+
+    /// Shard the next batched sweep continues in (see
+    /// `cleanup_expired_batch`). Only changed under a write lock.
+    sweep_shard: AtomicUsize,
 }
 
 impl Db {
     /// Create a new database with the given configuration.
     pub fn new(config: CacheConfig) -> Self {
+        let rng_seed = config.rng_seed.unwrap_or_else(rng::entropy_seed);
+        let spill = config
+            .spill_over
+            .as_ref()
+            .map(|spill_over| Arc::new(SpillStore::new(spill_over)));
+        let growth = (config.max_capacity.is_none() && config.growth_alarm > 0)
+            .then(|| GrowthAlarm::new(config.growth_alarm));
+        let epoch = Epoch::new(config.clock.now());
+        let ttl_rng = Rng::new(rng::component_seed(rng_seed, "ttl_jitter"));
+        let shards = split_capacity(config.max_capacity, config.shard_count())
+            .into_iter()
+            .map(|capacity| Shard::new(IndexMap::new(), capacity))
+            .collect();
         Self {
-            entries: RwLock::new(IndexMap::new()),
+            health: Arc::new(HealthTracker::new(config.health_thresholds.clone())),
+            growth,
+            spill,
+            rng_seed,
+            ttl_rng: Mutex::new(ttl_rng),
+            shards,
+            hasher: RandomState::new(),
             config,
             stats: Arc::new(CacheStats::new()),
+            queues: Mutex::new(HashMap::new()),
+            shadow: Mutex::new(HashMap::new()),
+            epoch,
+            sweep_shard: AtomicUsize::new(0),
         }
     }
 
@@ -49,434 +244,5054 @@ impl Db {
     /// Returns `None` if the key doesn't exist or has expired.
     /// Updates the entry's last accessed time (LRU tracking).
     pub fn get(&self, key: &str) -> Option<Bytes> {
+        self.try_get(key).ok().flatten()
+    }
+
+    /// Like `get`, but a value that fails checksum verification is
+    /// reported as `CacheError::Corrupted` instead of as a miss.
+    pub fn try_get(&self, key: &str) -> CacheResult<Option<Bytes>> {
+        Ok(self.get_stored(key)?.and_then(|value| self.resolve(value)))
+    }
+
+    /// `try_get` without resolving spill references.
+    fn get_stored(&self, key: &str) -> CacheResult<Option<Bytes>> {
         // First, try to read with a read lock
         {
-            let entries = self.read_lock()?;
+            let entries = self.read_lock(key);
 
             if let Some(entry) = entries.get(key) {
-                if entry.is_expired() {
+                if self.is_expired(entry, self.now()) {
                     // Entry expired - need write lock to remove it
                     drop(entries);
                     self.remove_expired(key);
-                    self.stats.record_miss();
-                    self.stats.record_expiration();
-                    return None;
+                    self.record_miss(key);
+                    return Ok(None);
+                }
+                if !self.verify(entry) {
+                    drop(entries);
+                    self.remove_corrupted(key);
+                    self.record_miss(key);
+                    return Err(CacheError::Corrupted {
+                        key: key.to_string(),
+                    });
                 }
 
                 // Clone the value before dropping the read lock
                 let value = entry.value().clone();
                 self.stats.record_hit();
-
-                // Update access time (need write lock)
+                let after = self.record_hit(key, entry);
                 drop(entries);
-                if let Some(mut entries) = self.write_lock() {
-                    if let Some(idx) = entries.get_index_of(key) {
-                        if let Some(entry) = entries.get_index_mut(idx) {
-                            entry.1.touch();
-                        }
-                        // Move to end for LRU (most recently used)
-                        let new_idx = entries.len() - 1;
-                        entries.move_index(idx, new_idx);
-                    }
-                }
+                self.finish_hit(key, after, true);
 
-                return Some(value);
+                return Ok(Some(value));
             }
         }
 
-        self.stats.record_miss();
-        None
+        self.record_miss(key);
+        Ok(None)
     }
 
-    /// Set a value in the cache without TTL.
-    pub fn set(&self, key: impl Into<String>, value: impl Into<Bytes>) {
-        let key = key.into();
-        let value = value.into();
+    /// Run `f` on a borrow of the stored bytes while holding the read lock.
+    ///
+    /// Counts as a hit or miss and promotes the entry like `get`, but the
+    /// promotion happens only after `f` has returned and the read lock has
+    /// been released.
+    pub fn get_ref<R>(&self, key: &str, f: impl FnOnce(&[u8]) -> R) -> Option<R> {
+        if self.spill.is_some() {
+            // A spilled value has to be read from disk first, which must not
+            // happen under the lock
+            return self.get(key).map(|value| f(&value));
+        }
+        let entries = self.read_lock(key);
 
-        let ttl = self.config.default_ttl;
-        self.set_internal(key, value, ttl);
-    }
+        let entry = match entries.get(key) {
+            Some(entry) => entry,
+            None => {
+                self.record_miss(key);
+                return None;
+            }
+        };
 
-    /// Set a value in the cache with a specific TTL.
-    pub fn set_with_ttl(&self, key: impl Into<String>, value: impl Into<Bytes>, ttl: Duration) {
-        let key = key.into();
-        let value = value.into();
+        if self.is_expired(entry, self.now()) {
+            drop(entries);
+            self.remove_expired(key);
+            self.record_miss(key);
+            return None;
+        }
+        if !self.verify(entry) {
+            drop(entries);
+            self.remove_corrupted(key);
+            self.record_miss(key);
+            return None;
+        }
 
-        self.set_internal(key, value, Some(ttl));
-    }
+        self.stats.record_hit();
+        let result = f(entry.value());
+        let after = self.record_hit(key, entry);
+        drop(entries);
+        self.finish_hit(key, after, true);
 
-    /// Internal set implementation.
-    fn set_internal(&self, key: String, value: Bytes, ttl: Option<Duration>) {
-        let entry = match ttl {
-            Some(duration) => Entry::with_expiration(value, Instant::now() + duration),
-            None => Entry::new(value),
-        };
+        Some(result)
+    }
 
-        let mut entries = match self.write_lock() {
-            Some(e) => e,
-            None => return, // Lock poisoned, silently fail
+    /// Get a live value without counting a hit or miss and without
+    /// promoting it. Expired and corrupted entries read as missing but are
+    /// left for the next regular read to remove.
+    pub fn peek(&self, key: &str) -> Option<Bytes> {
+        let value = {
+            let entries = self.read_lock(key);
+            let entry = entries.get(key)?;
+            if self.is_expired(entry, self.now()) || self.is_corrupted(entry) {
+                return None;
+            }
+            entry.value().clone()
         };
+        self.resolve(value)
+    }
 
-        // Check if we need to evict
-        if let Some(max_capacity) = self.config.max_capacity {
-            // If key already exists, we're replacing, not adding
-            if !entries.contains_key(&key) {
-                while entries.len() >= max_capacity {
-                    self.evict_one(&mut entries);
-                }
-            }
+    /// Metadata of a live entry. Counts no hit or miss and does not promote.
+    pub fn entry_info(&self, key: &str) -> Option<EntryInfo> {
+        let entries = self.read_lock(key);
+        let entry = entries.get(key)?;
+        let now = self.now();
+        if self.is_expired(entry, now) {
+            return None;
         }
+        let ticks = self.epoch.ticks(now);
+        Some(EntryInfo {
+            created_at_age: entry::between(entry.created_at(), ticks),
+            idle: entry::between(entry.last_accessed(), ticks),
+            ttl_remaining: entry
+                .expires_at()
+                .map(|expires| entry::between(ticks, expires)),
+            size_bytes: entry.value().len(),
+        })
+    }
 
-        let is_new = !entries.contains_key(&key);
-        entries.insert(key, entry);
+    /// Mark a live entry as most recently used without reading it, under one
+    /// write lock. Counts no hit or miss. Returns `false` if the key is
+    /// missing or expired; an expired entry is removed.
+    pub fn touch(&self, key: &str) -> bool {
+        let mut entries = self.write_lock(key);
+        let mut pending = Vec::new();
+        self.remove_if_expired(&mut entries, key, &mut pending);
+        // Promoted even within the promotion threshold: recency is the point
+        let live = entries.contains_key(key);
+        if live {
+            self.promote(&mut entries, key);
+        }
+        drop(entries);
+        self.notify(pending);
+        live
+    }
 
-        if is_new {
-            self.stats.increment_size();
+    /// Remaining TTL of a live entry: `Some(None)` if it never expires,
+    /// `None` if the key is missing or expired. Counts no hit or miss.
+    pub fn ttl(&self, key: &str) -> Option<Option<Duration>> {
+        let now = self.now();
+        let entries = self.read_lock(key);
+        let entry = entries.get(key)?;
+        if self.is_expired(entry, now) {
+            return None;
         }
-        self.stats.record_set();
+        let ticks = self.epoch.ticks(now);
+        Some(
+            entry
+                .expires_at()
+                .map(|expires_at| entry::between(ticks, expires_at)),
+        )
     }
 
-    /// Delete a key from the cache.
+    /// Add `delta` to the integer counter at `key` within a fixed window.
     ///
-    /// Returns `true` if the key existed and was removed.
-    pub fn delete(&self, key: &str) -> bool {
-        let mut entries = match self.write_lock() {
-            Some(e) => e,
-            None => return false,
+    /// A missing or expired key is created with the value `delta` and a TTL
+    /// of `window`; later increments keep that deadline, so the window ends
+    /// `window` after the first increment no matter how often the counter
+    /// is bumped. Returns the new count and the time left in the window.
+    ///
+    /// The window ends exactly at its deadline: the expiration grace does
+    /// not stretch it. A live key without a TTL (as left by a plain `set`)
+    /// is given one of `window`. Counters are always kept in memory, never
+    /// spilled.
+    pub fn incr_window_at(
+        &self,
+        key: &str,
+        delta: i64,
+        window: Duration,
+        now: Instant,
+    ) -> CacheResult<(i64, Duration)> {
+        if window < Duration::from_millis(1) {
+            return Err(CacheError::InvalidValue(
+                "counter window must be at least 1ms".to_string(),
+            ));
+        }
+        let mut entries = self.write_lock(key);
+        let ticks = self.epoch.ticks(now);
+
+        let current = match entries.get(key) {
+            Some(entry) if !entry.is_expired_at(ticks) => {
+                let count = std::str::from_utf8(entry.value())
+                    .ok()
+                    .and_then(|value| value.parse::<i64>().ok())
+                    .ok_or_else(|| {
+                        CacheError::InvalidValue(format!("'{}' does not hold an integer", key))
+                    })?;
+                Some((count, entry.expires_at()))
+            }
+            _ => None,
+        };
+        let (count, expires_at) = match current {
+            Some((count, expires_at)) => {
+                let count = count.checked_add(delta).ok_or_else(|| {
+                    CacheError::InvalidValue(format!("incrementing '{}' would overflow", key))
+                })?;
+                (count, expires_at)
+            }
+            None => (delta, None),
         };
+        let expires_at = expires_at.unwrap_or_else(|| self.epoch.deadline(now, window));
+        let value = Bytes::from(count.to_string());
+        self.check_size(key, &value)?;
 
-        let existed = entries.shift_remove(key).is_some();
-        if existed {
-            self.stats.decrement_size();
-            self.stats.record_delete();
-        }
-        existed
+        let mut entry = self.make_entry_at(value, None, now);
+        entry.set_expires_at(expires_at);
+        let mut pending = Vec::new();
+        // Never coalesced: an identical count must still start a new window
+        self.store_entry(&mut entries, Cow::Borrowed(key), entry, &mut pending, now);
+        drop(entries);
+        self.notify(pending);
+        Ok((count, entry::between(ticks, expires_at)))
     }
 
-    /// Check if a key exists in the cache (and is not expired).
-    pub fn contains(&self, key: &str) -> bool {
-        let entries = match self.read_lock() {
-            Some(e) => e,
-            None => return false,
-        };
+    /// Add `delta` to the decimal integer stored under `key` under one write
+    /// lock, returning the new value. A missing key counts as 0 and is
+    /// created with the default TTL; an existing one keeps its TTL.
+    pub fn increment(&self, key: &str, delta: i64) -> CacheResult<i64> {
+        let mut entries = self.write_lock(key);
+        let now = self.now();
 
-        match entries.get(key) {
-            Some(entry) => {
-                if entry.is_expired() {
-                    drop(entries);
-                    self.remove_expired(key);
-                    false
-                } else {
-                    true
-                }
+        let current = match entries.get(key) {
+            Some(entry) if !self.is_expired(entry, now) => {
+                let count = std::str::from_utf8(entry.value())
+                    .ok()
+                    .and_then(|value| value.parse::<i64>().ok())
+                    .ok_or_else(|| {
+                        CacheError::InvalidValue(format!("'{}' does not hold an integer", key))
+                    })?;
+                Some((count, entry.expiry()))
             }
-            None => false,
-        }
+            _ => None,
+        };
+        let (count, expiry) = match current {
+            Some((count, expiry)) => (count, Some(expiry)),
+            None => (0, None),
+        };
+        let count = count.checked_add(delta).ok_or_else(|| {
+            CacheError::InvalidValue(format!("incrementing '{}' would overflow", key))
+        })?;
+
+        let value = Bytes::from(count.to_string());
+        self.check_size(key, &value)?;
+
+        let entry = self.rewritten_entry(value, expiry, now);
+        let mut pending = Vec::new();
+        self.store_entry(&mut entries, Cow::Borrowed(key), entry, &mut pending, now);
+        drop(entries);
+        self.notify(pending);
+        Ok(count)
     }
 
-    /// Get the number of entries in the cache.
-    ///
-    /// Note: This may include expired entries that haven't been cleaned up yet.
-    pub fn len(&self) -> usize {
-        match self.read_lock() {
-            Some(entries) => entries.len(),
-            None => 0,
+    /// Append `suffix` to the value of `key`, creating it if it has no live
+    /// value, and return the new length. The entry keeps its TTL; a new key
+    /// gets the default TTL. Returns 0 if the write was dropped, as it is
+    /// when the key or the new value is over its size limit.
+    pub fn append(&self, key: &str, suffix: Bytes) -> usize {
+        if self.spill.is_some() {
+            return self.append_spilled(key, suffix);
         }
+        let mut entries = self.write_lock(key);
+        let now = self.now();
+        let (value, expiry) = match entries.get(key) {
+            Some(entry) if !self.is_expired(entry, now) => {
+                let mut value = BytesMut::with_capacity(entry.value().len() + suffix.len());
+                value.extend_from_slice(entry.value());
+                value.extend_from_slice(&suffix);
+                (value.freeze(), Some(entry.expiry()))
+            }
+            _ => (suffix, None),
+        };
+        if self.check_size(key, &value).is_err() {
+            return 0;
+        }
+        let len = value.len();
+        let entry = self.rewritten_entry(value, expiry, now);
+        let mut pending = Vec::new();
+        self.store_entry(&mut entries, Cow::Borrowed(key), entry, &mut pending, now);
+        drop(entries);
+        self.notify(pending);
+        len
     }
 
-    /// Check if the cache is empty.
-    pub fn is_empty(&self) -> bool {
-        self.len() == 0
-    }
+    /// [`Db::append`] for a cache that spills values. Spill files must not
+    /// be written under the lock, so the new value is built and spilled
+    /// first and stored only if the value it was built from is still the
+    /// stored one; otherwise the append starts over.
+    fn append_spilled(&self, key: &str, suffix: Bytes) -> usize {
+        loop {
+            let observed = self
+                .read_lock(key)
+                .get(key)
+                .filter(|entry| !self.is_expired(entry, self.now()))
+                .map(|entry| entry.value().clone());
+            let value = match observed.clone().map(|stored| self.resolve(stored)) {
+                Some(Some(current)) => {
+                    let mut value = BytesMut::with_capacity(current.len() + suffix.len());
+                    value.extend_from_slice(&current);
+                    value.extend_from_slice(&suffix);
+                    value.freeze()
+                }
+                // An unreadable spill file cannot be appended to
+                Some(None) => return 0,
+                None => suffix.clone(),
+            };
+            if self.check_size(key, &value).is_err() {
+                return 0;
+            }
+            let len = value.len();
+            let stored = match self.spill_value(value) {
+                Some(stored) => stored,
+                None => return 0,
+            };
+            let spilled = self.spilled_ref(&stored);
 
-    /// Remove all entries from the cache.
-    pub fn clear(&self) {
-        if let Some(mut entries) = self.write_lock() {
-            entries.clear();
-            self.stats.set_size(0);
+            let mut entries = self.write_lock(key);
+            let now = self.now();
+            let current = entries
+                .get(key)
+                .filter(|entry| !self.is_expired(entry, now))
+                .map(|entry| (entry.value(), entry.expiry()));
+            if current.map(|(value, _)| value) != observed.as_ref() {
+                drop(entries);
+                self.release_spilled(spilled);
+                continue;
+            }
+            let expiry = current.map(|(_, expiry)| expiry);
+            let entry = self.rewritten_entry(stored, expiry, now);
+            let mut pending = Vec::new();
+            self.store_entry(&mut entries, Cow::Borrowed(key), entry, &mut pending, now);
+            drop(entries);
+            self.notify(pending);
+            return len;
         }
     }
 
-    /// Get a reference to the statistics.
-    pub fn stats(&self) -> Arc<CacheStats> {
-        Arc::clone(&self.stats)
+    /// Get several values under the write lock of every shard.
+    ///
+    /// Equivalent to `multi_get_and_touch(keys, None)`.
+    pub fn multi_get(&self, keys: &[&str]) -> Vec<Option<Bytes>> {
+        self.multi_get_and_touch(keys, None)
     }
 
-    /// Remove all expired entries from the cache.
+    /// Get several values under the write lock of every shard, promoting
+    /// each hit and optionally sliding its expiration.
     ///
-    /// This is called by the background cleanup task.
-    pub fn cleanup_expired(&self) -> usize {
-        let mut entries = match self.write_lock() {
-            Some(e) => e,
-            None => return 0,
-        };
+    /// With `extend_by`, a hit entry's deadline becomes `now + extend_by`
+    /// unless it already expires later. Entries without a TTL keep having no
+    /// TTL. Misses and expired entries count as misses and return `None`.
+    pub fn multi_get_and_touch(
+        &self,
+        keys: &[&str],
+        extend_by: Option<Duration>,
+    ) -> Vec<Option<Bytes>> {
+        let mut guards = self.write_all();
 
-        let initial_len = entries.len();
-        let now = Instant::now();
+        let now = self.now();
+        let mut pending = Vec::new();
+        let mut released = Vec::new();
+        let mut values = Vec::with_capacity(keys.len());
 
-        entries.retain(|_, entry| {
-            let expired = entry.is_expired_at(now);
-            if expired {
-                self.stats.record_expiration();
-                self.stats.decrement_size();
+        for key in keys {
+            let entries = &mut guards[self.shard_index(key)];
+            let entry = match entries.get_mut(*key) {
+                Some(entry) if !self.is_expired(entry, now) => entry,
+                Some(_) => {
+                    self.remove_if_expired(entries, key, &mut pending);
+                    self.record_miss(key);
+                    values.push(None);
+                    continue;
+                }
+                None => {
+                    self.record_miss(key);
+                    values.push(None);
+                    continue;
+                }
+            };
+            if !self.verify(entry) {
+                released.extend(self.remove_if_corrupted(entries, key));
+                self.record_miss(key);
+                values.push(None);
+                continue;
             }
-            !expired
-        });
-
-        initial_len - entries.len()
-    }
 
-    // Private helper methods
+            if let (Some(extend_by), Some(expires_at)) = (extend_by, entry.expires_at()) {
+                entry.set_expires_at(expires_at.max(self.epoch.deadline(now, extend_by)));
+                self.schedule(key, entry);
+            }
+            values.push(Some(entry.value().clone()));
+            self.stats.record_hit();
+            entry.record_access();
+            if self.needs_promotion(entry, now) {
+                self.promote(entries, key);
+            }
+        }
+        for entries in &guards {
+            self.trim_deadlines(entries);
+        }
 
-    /// Acquire a read lock, returning None if poisoned.
-    fn read_lock(&self) -> Option<RwLockReadGuard<'_, IndexMap<String, Entry>>> {
-        self.entries.read().ok()
+        drop(guards);
+        self.notify(pending);
+        for reference in released {
+            self.release_spilled(Some(reference));
+        }
+        if self.spill.is_some() {
+            return values
+                .into_iter()
+                .map(|value| value.and_then(|value| self.resolve(value)))
+                .collect();
+        }
+        values
     }
 
-    /// Acquire a write lock, returning None if poisoned.
-    fn write_lock(&self) -> Option<RwLockWriteGuard<'_, IndexMap<String, Entry>>> {
-        self.entries.write().ok()
-    }
+    /// Get the value for `key`, or store the value `init` returns, under a
+    /// single write-lock acquisition.
+    ///
+    /// A live value counts as a hit and is promoted. Otherwise `init` runs
+    /// with the lock held, counting a miss and a set, and its value is
+    /// stored with `ttl` (`None` for the default TTL), unless the key or the
+    /// value is over its size limit. If `init` panics, nothing is stored
+    /// and the panic continues once the lock is released.
+    ///
+    /// A value large enough to spill is written to its file with the lock
+    /// released and stored only if the key is still missing once the lock
+    /// is taken again; if another write got there first, that write is
+    /// kept and `init`'s value is still returned. A stored value whose
+    /// spill file cannot be read is removed and loaded anew, as a miss.
+    pub fn get_or_insert_with(
+        &self,
+        key: &str,
+        ttl: Option<Duration>,
+        init: impl FnOnce() -> Bytes,
+    ) -> Bytes {
+        let mut entries = self.write_lock(key);
 
-    /// Remove a specific expired key.
-    fn remove_expired(&self, key: &str) {
-        if let Some(mut entries) = self.write_lock() {
-            if let Some(entry) = entries.get(key) {
-                if entry.is_expired() {
-                    entries.shift_remove(key);
-                    self.stats.decrement_size();
-                }
+        let now = self.now();
+        let mut released = self.remove_if_corrupted(&mut entries, key);
+        if let Some(entry) = entries.get(key).filter(|e| !self.is_expired(e, now)) {
+            let stored = entry.value().clone();
+            entry.record_access();
+            if self.needs_promotion(entry, now) {
+                self.promote(&mut entries, key);
+            }
+            drop(entries);
+            // A spilled value is read back only once the lock is released
+            if let Some(value) = self.resolve(stored.clone()) {
+                self.stats.record_hit();
+                return value;
             }
+            self.remove_unreadable(key, &stored);
+            return self.get_or_insert_with(key, ttl, init);
         }
-    }
 
-    /// Evict one entry (the least recently used).
-    fn evict_one(&self, entries: &mut IndexMap<String, Entry>) {
-        // IndexMap maintains insertion order; the first entry is the oldest
-        // We move recently accessed entries to the end, so first = LRU
-        if let Some((key, _)) = entries.first() {
-            let key = key.clone();
-            entries.shift_remove(&key);
-            self.stats.record_eviction();
-            self.stats.decrement_size();
+        self.record_miss(key);
+        // `init` sees nothing of the map, so a panic leaves it untouched
+        let value = match callback::catch(init) {
+            Ok(value) => value,
+            Err(payload) => {
+                drop(entries);
+                self.release_spilled(released);
+                std::panic::resume_unwind(payload);
+            }
+        };
+        // An oversized value is handed back without being stored
+        if self.check_size(key, &value).is_err() {
+            drop(entries);
+            self.release_spilled(released);
+            return value;
         }
+        let stored = if self.spill.as_ref().is_some_and(|spill| spill.wants(&value)) {
+            drop(entries);
+            self.release_spilled(released.take());
+            let stored = match self.spill_value(value.clone()) {
+                Some(stored) => stored,
+                None => return value,
+            };
+            entries = self.write_lock(key);
+            let now = self.now();
+            if entries
+                .get(key)
+                .is_some_and(|entry| !self.is_expired(entry, now))
+            {
+                drop(entries);
+                self.release_spilled(self.spilled_ref(&stored));
+                return value;
+            }
+            stored
+        } else {
+            value.clone()
+        };
+        let now = self.now();
+        let entry = self.make_entry_at(stored, ttl.or(self.config.default_ttl), now);
+        let mut pending = Vec::new();
+        self.store_entry(&mut entries, Cow::Borrowed(key), entry, &mut pending, now);
+        drop(entries);
+        self.notify(pending);
+        self.release_spilled(released);
+        value
     }
-}
 
-impl Default for Db {
-    fn default() -> Self {
-        Self::with_defaults()
+    /// Set a value in the cache without TTL.
+    ///
+    /// A borrowed key is only copied into an owned `String` when it is not
+    /// already present.
+    pub fn set<'k>(&self, key: impl Into<Cow<'k, str>>, value: impl Into<Bytes>) {
+        let key = key.into();
+        let value = value.into();
+
+        let ttl = self.config.default_ttl;
+        self.set_internal(key, value, ttl, true);
     }
-}
 
-// Implement Clone by creating a new Db with cloned data
-impl Clone for Db {
-    fn clone(&self) -> Self {
-        let entries = self.read_lock().map(|e| e.clone()).unwrap_or_default();
+    /// [`Db::set`], reporting what the write did.
+    pub fn set_returning_outcome<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+    ) -> SetOutcome {
+        let ttl = self.config.default_ttl;
+        self.set_internal(key.into(), value.into(), ttl, true)
+    }
 
-        Self {
-            entries: RwLock::new(entries),
-            config: self.config.clone(),
-            stats: Arc::new(CacheStats::new()), // New stats for cloned instance
-        }
+    /// [`Db::set_with_ttl`], reporting what the write did.
+    pub fn set_with_ttl_returning_outcome<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+        ttl: Duration,
+    ) -> SetOutcome {
+        self.set_internal(key.into(), value.into(), Some(ttl), true)
     }
-}
 
-/// Legacy API support for backward compatibility.
-/// These methods match the original API signature.
-impl Db {
-    /// Legacy write method - parses key/value from array.
-    ///
-    /// # Deprecated
-    /// Use `set(key, value)` instead.
-    #[deprecated(since = "1.0.0", note = "Use set() instead")]
-    pub fn write(&self, arr: &[String]) -> CacheResult<&'static str> {
-        if arr.len() < 3 {
-            return Err(CacheError::ParseError(
-                "write requires at least 3 arguments: command key value".to_string(),
-            ));
-        }
+    /// [`Db::set`], returning the live value it displaced.
+    pub fn set_returning<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+    ) -> Option<Bytes> {
+        let ttl = self.config.default_ttl;
+        self.store_value(key.into(), value.into(), ttl, true, true, true)
+            .ok()
+            .and_then(|(_, old, _)| old)
+    }
 
-        let key = &arr[1];
-        let value = &arr[2];
+    /// [`Db::set_with_ttl`], returning the live value it displaced.
+    pub fn set_with_ttl_returning<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+        ttl: Duration,
+    ) -> Option<Bytes> {
+        self.store_value(key.into(), value.into(), Some(ttl), true, true, true)
+            .ok()
+            .and_then(|(_, old, _)| old)
+    }
 
-        let existed = self.contains(key);
-        self.set(key.clone(), value.clone());
+    /// [`Db::set`], reporting a key or value over its size limit as an
+    /// error instead of dropping the write silently.
+    pub fn try_set<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+    ) -> CacheResult<()> {
+        let ttl = self.config.default_ttl;
+        self.store_value(key.into(), value.into(), ttl, true, false, true)
+            .map(|_| ())
+    }
 
-        if existed {
-            Ok("r Ok") // Replaced existing key
-        } else {
-            Ok("Ok") // New key
-        }
+    /// [`Db::set_with_ttl`], reporting size limits like [`Db::try_set`].
+    pub fn try_set_with_ttl<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+        ttl: Duration,
+    ) -> CacheResult<()> {
+        self.store_value(key.into(), value.into(), Some(ttl), true, false, true)
+            .map(|_| ())
     }
 
-    /// Legacy read method - parses key from array.
-    ///
-    /// # Deprecated
-    /// Use `get(key)` instead.
-    #[deprecated(since = "1.0.0", note = "Use get() instead")]
-    pub fn read(&self, arr: &[String]) -> CacheResult<Bytes> {
-        if arr.len() < 2 {
-            return Err(CacheError::ParseError(
-                "read requires at least 2 arguments: command key".to_string(),
-            ));
-        }
+    /// [`Db::try_set`] with `ttl` (`None` for the default TTL), reporting
+    /// what the write did and the entries and key and value bytes evicted
+    /// to make room for it. Without `notify_replaced` the listener is not
+    /// told about a value the write replaces.
+    #[cfg(feature = "server")]
+    pub fn try_set_returning_outcome<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+        ttl: Option<Duration>,
+        notify_replaced: bool,
+    ) -> CacheResult<(SetOutcome, u64, u64)> {
+        let ttl = ttl.or(self.config.default_ttl);
+        self.store_value(key.into(), value.into(), ttl, true, false, notify_replaced)
+            .map(|(outcome, _, (evicted, bytes))| (outcome, evicted, bytes))
+    }
 
-        let key = &arr[1];
-        self.get(key)
-            .ok_or_else(|| CacheError::KeyNotFound(key.clone()))
+    /// Store an entry for a bulk load, with `ttl` (`None` for the default
+    /// TTL). Without `notify_replaced` the listener is not told about a
+    /// value the load replaces.
+    pub(crate) fn load(
+        &self,
+        key: String,
+        value: Bytes,
+        ttl: Option<Duration>,
+        notify_replaced: bool,
+    ) {
+        let ttl = ttl.or(self.config.default_ttl);
+        let _ = self.store_value(key.into(), value, ttl, true, false, notify_replaced);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Set a value in the cache with a specific TTL.
+    pub fn set_with_ttl<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+        ttl: Duration,
+    ) {
+        let key = key.into();
+        let value = value.into();
 
-    #[test]
-    fn test_basic_set_get() {
-        let db = Db::with_defaults();
+        self.set_internal(key, value, Some(ttl), true);
+    }
 
-        db.set("key1", "value1");
-        let result = db.get("key1");
+    /// [`Db::set_with_ttl`] without `ttl_jitter`: the entry expires exactly
+    /// `ttl` from now.
+    pub fn set_with_exact_ttl<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+        ttl: Duration,
+    ) {
+        self.set_internal(key.into(), value.into(), Some(ttl), false);
+    }
 
-        assert_eq!(result, Some(Bytes::from("value1")));
+    /// Get a value without ever blocking on the lock.
+    ///
+    /// Returns `None` if the read lock is currently unavailable, otherwise
+    /// `Some` with the lookup result. LRU promotion and lazy expiration are
+    /// skipped (not waited for) when the write lock is contended.
+    pub fn get_nonblocking(&self, key: &str) -> Option<Option<Bytes>> {
+        self.get_stored_nonblocking(key)
+            .map(|value| value.and_then(|value| self.resolve(value)))
     }
 
-    #[test]
-    fn test_get_nonexistent() {
-        let db = Db::with_defaults();
+    /// `get_nonblocking` without resolving spill references.
+    fn get_stored_nonblocking(&self, key: &str) -> Option<Option<Bytes>> {
+        let entries = match self.shard(key).entries.try_read() {
+            Ok(entries) => entries,
+            Err(TryLockError::WouldBlock) => return None,
+            Err(TryLockError::Poisoned(poisoned)) => {
+                self.stats.record_lock_failure();
+                poisoned.into_inner()
+            }
+        };
 
-        let result = db.get("nonexistent");
-        assert!(result.is_none());
-    }
+        let entry = match entries.get(key) {
+            Some(entry) => entry,
+            None => {
+                self.record_miss(key);
+                return Some(None);
+            }
+        };
 
-    #[test]
-    fn test_delete() {
-        let db = Db::with_defaults();
+        if self.is_expired(entry, self.now()) {
+            drop(entries);
+            self.record_miss(key);
+            let mut pending = Vec::new();
+            if let Some(mut entries) = self.try_write_lock(key) {
+                self.remove_if_expired(&mut entries, key, &mut pending);
+            }
+            self.notify(pending);
+            return Some(None);
+        }
+        if !self.verify(entry) {
+            drop(entries);
+            self.record_miss(key);
+            let spilled = self
+                .try_write_lock(key)
+                .and_then(|mut entries| self.remove_if_corrupted(&mut entries, key));
+            self.release_spilled(spilled);
+            return Some(None);
+        }
 
-        db.set("key1", "value1");
-        assert!(db.contains("key1"));
+        let value = entry.value().clone();
+        self.stats.record_hit();
+        let after = self.record_hit(key, entry);
+        drop(entries);
+        self.finish_hit(key, after, false);
 
-        let deleted = db.delete("key1");
-        assert!(deleted);
-        assert!(!db.contains("key1"));
+        Some(Some(value))
     }
 
-    #[test]
-    fn test_delete_nonexistent() {
+    /// Set a value without ever blocking on the lock.
+    ///
+    /// Uses the default TTL. Returns `false` and counts a dropped set if the
+    /// write lock is currently held by someone else.
+    pub fn set_nonblocking<'k>(
+        &self,
+        key: impl Into<Cow<'k, str>>,
+        value: impl Into<Bytes>,
+    ) -> bool {
+        let key = key.into();
+        let value = value.into();
+        if self.check_size(&key, &value).is_err() {
+            return false;
+        }
+        let value = match self.spill_value(value) {
+            Some(value) => value,
+            None => return false,
+        };
+        match self.try_write_lock(&key) {
+            Some(mut entries) => {
+                let entry = self.make_entry(value, self.config.default_ttl);
+                let mut pending = Vec::new();
+                let spilled = self.spilled_ref(&entry.value);
+                let outcome = self.insert_entry(&mut entries, key, entry, &mut pending);
+                drop(entries);
+                self.notify(pending);
+                if outcome == SetOutcome::Unchanged {
+                    self.release_spilled(spilled);
+                }
+                true
+            }
+            None => {
+                self.stats.record_dropped_set();
+                self.release_spilled(self.spilled_ref(&value));
+                false
+            }
+        }
+    }
+
+    /// Internal set implementation. `jitter` is false to apply `ttl`
+    /// exactly, whatever `ttl_jitter` says.
+    fn set_internal(
+        &self,
+        key: Cow<'_, str>,
+        value: Bytes,
+        ttl: Option<Duration>,
+        jitter: bool,
+    ) -> SetOutcome {
+        self.store_value(key, value, ttl, jitter, false, true)
+            .map_or(SetOutcome::Dropped, |(outcome, _, _)| outcome)
+    }
+
+    /// `set_internal`, also returning the live value the write displaced
+    /// if `previous` is set, and the number and key and value bytes of the
+    /// entries evicted to make room. An expired value counts as an
+    /// expiration and is not returned. A key or value over its size limit
+    /// is an error. Without `notify_replaced` the listener does not hear
+    /// of the replaced value.
+    fn store_value(
+        &self,
+        key: Cow<'_, str>,
+        value: Bytes,
+        ttl: Option<Duration>,
+        jitter: bool,
+        previous: bool,
+        notify_replaced: bool,
+    ) -> CacheResult<(SetOutcome, Option<Bytes>, (u64, u64))> {
+        self.check_size(&key, &value)?;
+        let value = match self.spill_value(value) {
+            Some(value) => value,
+            None => return Ok((SetOutcome::Dropped, None, (0, 0))),
+        };
+        let spilled = self.spilled_ref(&value);
+        let mut entries = self.write_lock(&key);
+        let entry = if jitter {
+            self.make_entry(value, ttl)
+        } else {
+            self.make_exact_entry_at(value, ttl, self.now())
+        };
+
+        let mut pending = Vec::new();
+        let old = previous
+            .then(|| entries.get(key.as_ref()).map(|old| old.value().clone()))
+            .flatten();
+        let outcome = self.insert_entry(&mut entries, key, entry, &mut pending);
+        drop(entries);
+        // Only a live value was replaced or kept; read a spilled one back
+        // before its file goes
+        let old = match outcome {
+            SetOutcome::Replaced | SetOutcome::Unchanged => old.and_then(|old| self.resolve(old)),
+            SetOutcome::Inserted | SetOutcome::Dropped => None,
+        };
+        let evicted = pending
+            .iter()
+            .filter(|removal| removal.cause == RemovalCause::Evicted)
+            .fold((0, 0), |(count, bytes), removal| {
+                let size = (removal.key.len() + removal.value.len()) as u64;
+                (count + 1, bytes + size)
+            });
+        if !notify_replaced {
+            self.drop_replaced(&mut pending);
+        }
+        self.notify(pending);
+        if outcome == SetOutcome::Unchanged {
+            self.release_spilled(spilled);
+        }
+        Ok((outcome, old, evicted))
+    }
+
+    /// Check a write against `max_key_length` and `max_value_size`,
+    /// counting a rejected one.
+    pub(crate) fn check_size(&self, key: &str, value: &[u8]) -> CacheResult<()> {
+        let result = match (self.config.max_key_length, self.config.max_value_size) {
+            (Some(max), _) if key.len() > max => Err(CacheError::InvalidKey(format!(
+                "{} bytes (max: {})",
+                key.len(),
+                max
+            ))),
+            (_, Some(max)) if value.len() > max => Err(CacheError::InvalidValue(format!(
+                "{} bytes (max: {})",
+                value.len(),
+                max
+            ))),
+            _ => Ok(()),
+        };
+        if result.is_err() {
+            self.stats.record_rejected_set();
+        }
+        result
+    }
+
+    /// Insert `value` only if `key` has no live entry, checking and writing
+    /// under one write lock. An expired entry counts as absent and is
+    /// replaced. A `None` TTL uses the default TTL.
+    ///
+    /// Returns `true` if the value was inserted.
+    pub fn set_if_absent(&self, key: Cow<'_, str>, value: Bytes, ttl: Option<Duration>) -> bool {
+        if self.check_size(&key, &value).is_err() {
+            return false;
+        }
+        let value = match self.spill_value(value) {
+            Some(value) => value,
+            None => return false,
+        };
+        let spilled = self.spilled_ref(&value);
+        let mut entries = self.write_lock(&key);
+
+        let now = self.now();
+        if entries
+            .get(key.as_ref())
+            .is_some_and(|entry| !self.is_expired(entry, now))
+        {
+            drop(entries);
+            self.release_spilled(spilled);
+            return false;
+        }
+        let entry = self.make_entry(value, ttl.or(self.config.default_ttl));
+        let mut pending = Vec::new();
+        self.store_entry(&mut entries, key, entry, &mut pending, now);
+        drop(entries);
+        self.notify(pending);
+        true
+    }
+
+    /// Replace the value of `key` with `new` if the stored value equals
+    /// `expected`, under one write lock. The entry keeps its TTL.
+    ///
+    /// Returns `Ok(false)` on a mismatch. Fails with
+    /// [`CacheError::KeyNotFound`] if the key has no live value, with
+    /// [`CacheError::Corrupted`] if its value fails verification, and like
+    /// [`Db::try_set`] if `key` or `new` is over its size limit.
+    pub fn compare_and_swap(&self, key: &str, expected: &[u8], new: Bytes) -> CacheResult<bool> {
+        self.check_size(key, &new)?;
+        let new = self.spill_value(new).ok_or_else(|| {
+            CacheError::InvalidValue(format!("value for '{}' could not be spilled", key))
+        })?;
+        let spilled = self.spilled_ref(&new);
+        let mut entries = self.write_lock(key);
+
+        let now = self.now();
+        let mut pending = Vec::new();
+        self.remove_if_expired(&mut entries, key, &mut pending);
+        let current = entries
+            .get(key)
+            .map(|entry| (self.verify(entry), entry.value().clone(), entry.expiry()));
+        let mut released = None;
+        let result = match current {
+            None => Err(CacheError::KeyNotFound(key.to_string())),
+            Some((false, ..)) => {
+                released = self.remove_if_corrupted(&mut entries, key);
+                Err(CacheError::Corrupted {
+                    key: key.to_string(),
+                })
+            }
+            // Like a transaction, a spilled value is read back under the lock
+            Some((true, value, expiry)) => match self.resolve(value) {
+                Some(value) if value == expected => {
+                    let entry = self.rewritten_entry(new, Some(expiry), now);
+                    self.store_entry(&mut entries, Cow::Borrowed(key), entry, &mut pending, now);
+                    Ok(true)
+                }
+                _ => Ok(false),
+            },
+        };
+        drop(entries);
+        self.notify(pending);
+        self.release_spilled(released);
+        if !matches!(result, Ok(true)) {
+            // `new` was not stored
+            self.release_spilled(spilled);
+        }
+        result
+    }
+
+    /// Apply sets in order under the write lock of every shard. A `None`
+    /// TTL uses the default TTL.
+    pub fn set_many(&self, writes: Vec<(String, Bytes, Option<Duration>)>) {
+        // Spilling writes files, so it happens before the lock is taken
+        let writes: Vec<_> = writes
+            .into_iter()
+            .filter_map(|(key, value, ttl)| {
+                self.check_size(&key, &value).ok()?;
+                let value = self.spill_value(value)?;
+                Some((key, value, ttl.or(self.config.default_ttl)))
+            })
+            .collect();
+        let mut guards = self.write_all();
+
+        let mut pending = Vec::new();
+        let mut unchanged = Vec::new();
+        for (key, value, ttl) in writes {
+            let spilled = self.spilled_ref(&value);
+            let entry = self.make_entry(value, ttl);
+            let entries = &mut guards[self.shard_index(&key)];
+            let outcome = self.insert_entry(entries, Cow::Owned(key), entry, &mut pending);
+            if outcome == SetOutcome::Unchanged {
+                unchanged.push(spilled);
+            }
+        }
+        drop(guards);
+        self.notify(pending);
+        for spilled in unchanged {
+            self.release_spilled(spilled);
+        }
+    }
+
+    /// Move `value` to a spill-over file if it is large enough, returning
+    /// what to store: the value itself or a reference to the file. `None`
+    /// means the write is rejected.
+    fn spill_value(&self, value: Bytes) -> Option<Bytes> {
+        let spill = match &self.spill {
+            Some(spill) if spill.wants(&value) => spill,
+            _ => return Some(value),
+        };
+        match spill.store(&value) {
+            Ok(reference) => {
+                self.stats.record_spill_write();
+                Some(reference)
+            }
+            Err(_) => {
+                self.stats.record_spill_error();
+                match self.config.spill_fallback {
+                    SpillFallback::InMemory => Some(value),
+                    SpillFallback::Reject => {
+                        self.stats.record_dropped_set();
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    /// A copy of `value` if it is a spill reference.
+    fn spilled_ref(&self, value: &Bytes) -> Option<Bytes> {
+        match &self.spill {
+            Some(spill) if spill.is_ref(value) => Some(value.clone()),
+            _ => None,
+        }
+    }
+
+    /// Release a spill reference that did not end up stored.
+    fn release_spilled(&self, reference: Option<Bytes>) {
+        if let (Some(spill), Some(reference)) = (&self.spill, reference) {
+            if spill.release(&reference).is_err() {
+                self.stats.record_spill_error();
+            }
+        }
+    }
+
+    /// Turn a stored value into the value it represents, reading spilled
+    /// values back from disk. A spill file that cannot be read counts as a
+    /// spill error and yields `None`.
+    fn resolve(&self, value: Bytes) -> Option<Bytes> {
+        let spill = match &self.spill {
+            Some(spill) if spill.is_ref(&value) => spill,
+            _ => return Some(value),
+        };
+        match spill.load(&value) {
+            Ok(value) => {
+                self.stats.record_spill_hit();
+                Some(value)
+            }
+            Err(_) => {
+                self.stats.record_spill_error();
+                None
+            }
+        }
+    }
+
+    /// Build an entry for the given value and optional TTL.
+    ///
+    /// Entry times are epoch offsets, so this must be called with the write
+    /// lock held; a rebase in between would otherwise skew them.
+    fn make_entry(&self, value: Bytes, ttl: Option<Duration>) -> Entry {
+        self.make_entry_at(value, ttl, self.now())
+    }
+
+    /// [`Db::make_entry`] created at `now`. The TTL is jittered if
+    /// `ttl_jitter` is set.
+    fn make_entry_at(&self, value: Bytes, ttl: Option<Duration>, now: Instant) -> Entry {
+        let ttl = ttl.map(|ttl| self.jittered(ttl));
+        self.make_exact_entry_at(value, ttl, now)
+    }
+
+    /// [`Db::make_entry_at`] without jitter.
+    fn make_exact_entry_at(&self, value: Bytes, ttl: Option<Duration>, now: Instant) -> Entry {
+        let entry = match ttl {
+            Some(duration) => Entry::with_expiration(
+                value,
+                self.epoch.ticks(now),
+                self.epoch.deadline(now, duration),
+            ),
+            None => Entry::new(value, self.epoch.ticks(now)),
+        };
+        if self.config.checksum_values {
+            let crc = checksum::crc32(entry.value());
+            entry.with_checksum(crc)
+        } else {
+            entry
+        }
+    }
+
+    /// `ttl` lengthened or shortened by a uniformly drawn amount of up to
+    /// `ttl_jitter` of itself. TTLs too long to count in nanoseconds are
+    /// left alone.
+    fn jittered(&self, ttl: Duration) -> Duration {
+        let (fraction, nanos) = match (self.config.ttl_jitter, u64::try_from(ttl.as_nanos())) {
+            (Some(fraction), Ok(nanos)) => (fraction, nanos),
+            _ => return ttl,
+        };
+        let spread = (nanos as f64 * fraction) as u64;
+        let offset = self
+            .ttl_rng
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .below(spread.saturating_mul(2).saturating_add(1));
+        Duration::from_nanos(nanos.saturating_sub(spread).saturating_add(offset))
+    }
+
+    /// An entry for a value computed from a previous one: it keeps the
+    /// previous entry's expiration (see `Entry::expiry`), or gets the
+    /// default TTL for a new key.
+    fn rewritten_entry(&self, value: Bytes, expiry: Option<(u32, u32)>, now: Instant) -> Entry {
+        match expiry {
+            Some((expires_at, ttl)) => {
+                let mut entry = self.make_entry_at(value, None, now);
+                entry.expires_at = expires_at;
+                entry.ttl = ttl;
+                entry
+            }
+            None => self.make_entry_at(value, self.config.default_ttl, now),
+        }
+    }
+
+    /// Insert an entry into the locked shard, evicting as needed.
+    ///
+    /// Overwrites happen in place with the borrowed key; an owned key is only
+    /// materialized for a new entry (or for a listener notification).
+    /// Returns [`SetOutcome::Unchanged`] if the write was coalesced and
+    /// `entry` dropped.
+    fn insert_entry(
+        &self,
+        entries: &mut ShardGuard<'_>,
+        key: Cow<'_, str>,
+        entry: Entry,
+        pending: &mut Vec<Removal>,
+    ) -> SetOutcome {
+        if self.config.coalesce_identical_writes && self.coalesce(entries, &key, &entry) {
+            return SetOutcome::Unchanged;
+        }
+        self.store_entry(entries, key, entry, pending, self.now())
+    }
+
+    /// Like `insert_entry`, for an entry that already carries its weight.
+    fn insert_weighed(
+        &self,
+        entries: &mut ShardGuard<'_>,
+        key: Cow<'_, str>,
+        entry: Entry,
+        pending: &mut Vec<Removal>,
+    ) -> SetOutcome {
+        if self.config.coalesce_identical_writes && self.coalesce(entries, &key, &entry) {
+            return SetOutcome::Unchanged;
+        }
+        self.place_entry(entries, key, entry, pending, self.now())
+    }
+
+    /// Store an entry in the locked shard without coalescing, evicting as
+    /// needed. `now` decides whether an overwritten entry had expired.
+    fn store_entry(
+        &self,
+        entries: &mut ShardGuard<'_>,
+        key: Cow<'_, str>,
+        mut entry: Entry,
+        pending: &mut Vec<Removal>,
+        now: Instant,
+    ) -> SetOutcome {
+        entry.weight = self.weigh(&key, entry.value());
+        self.place_entry(entries, key, entry, pending, now)
+    }
+
+    /// `store_entry` for an entry that already carries its weight.
+    fn place_entry(
+        &self,
+        entries: &mut ShardGuard<'_>,
+        key: Cow<'_, str>,
+        mut entry: Entry,
+        pending: &mut Vec<Removal>,
+        now: Instant,
+    ) -> SetOutcome {
+        if let Some(factor) = self.config.shadow_ttl_factor {
+            let mut shadow = self.lock_shadow();
+            match self.shadow_deadline(&entry, factor) {
+                Some(deadline) => shadow.insert(key.to_string(), deadline),
+                None => shadow.remove(key.as_ref()),
+            };
+        }
+
+        self.schedule(&key, &entry);
+        let incoming = footprint(&key, &entry);
+        let weight = u64::from(entry.weight());
+        self.make_room(entries, &key, incoming, weight, pending);
+        let outcome = if let Some(slot) = entries.get_mut(key.as_ref()) {
+            if !self.is_expired(slot, now) {
+                *entry.accesses.get_mut() = slot.accesses();
+            }
+            let old = std::mem::replace(slot, entry);
+            self.stats.sub_memory(footprint(&key, &old));
+            self.stats.add_memory(incoming);
+            self.stats.sub_weight(u64::from(old.weight()));
+            self.stats.add_weight(weight);
+            // An overwritten entry that had already expired is reported
+            // as an expiration, not a replacement.
+            let (cause, outcome) = if self.is_expired(&old, now) {
+                self.stats.record_lazy_expiration();
+                self.stats.record_insert();
+                (RemovalCause::Expired, SetOutcome::Inserted)
+            } else {
+                self.stats.record_overwrite();
+                (RemovalCause::Replaced, SetOutcome::Replaced)
+            };
+            if self.wants_removals() {
+                self.collect(pending, key.into_owned(), &old, cause);
+            }
+            outcome
+        } else {
+            if let Some(capacity) = entries.shard.capacity {
+                while entries.len() >= capacity {
+                    self.evict_one(entries, pending);
+                }
+            }
+            entries.insert(key.into_owned(), entry);
+            self.stats.increment_size();
+            self.stats.add_memory(incoming);
+            self.stats.add_weight(weight);
+            self.stats.record_insert();
+            SetOutcome::Inserted
+        };
+        self.trim_deadlines(entries);
+        outcome
+    }
+
+    /// Absorb a set whose value matches the live stored value.
+    ///
+    /// Returns `true` if the write was coalesced and must not be stored.
+    fn coalesce(&self, entries: &mut ShardGuard<'_>, key: &str, incoming: &Entry) -> bool {
+        let now = self.now();
+        let existing = match entries.get_mut(key) {
+            Some(existing) if !self.is_expired(existing, now) => existing,
+            _ => return false,
+        };
+        // Length first so large differing values are rejected without a scan
+        if existing.value.len() != incoming.value.len() || existing.value != incoming.value {
+            return false;
+        }
+
+        if self.config.coalesce_refreshes_ttl {
+            existing.expires_at = incoming.expires_at;
+            existing.ttl = incoming.ttl;
+            self.schedule(key, existing);
+            self.trim_deadlines(entries);
+        }
+        self.promote(entries, key);
+        self.stats.record_coalesced_set();
+        true
+    }
+
+    /// Append `value` under `prefix` and trim the prefix to its newest `cap`
+    /// live entries, all under the write lock of every shard. Returns the
+    /// generated key.
+    pub fn push_capped(&self, prefix: &str, value: Bytes, cap: usize) -> Option<String> {
+        let value = self.spill_value(value)?;
+        let mut guards = self.write_all();
+        let entry = self.make_entry(value, None);
+        let mut queues = self.lock_queues();
+        let queue = queues.entry(prefix.to_string()).or_default();
+        let now = self.now();
+
+        let seq = queue.next_seq;
+        queue.next_seq += 1;
+        let key = queue_key(prefix, seq);
+
+        let mut pending = Vec::new();
+        // Keys are unique, so this never coalesces
+        let entries = &mut guards[self.shard_index(&key)];
+        self.insert_entry(entries, Cow::Borrowed(&key), entry, &mut pending);
+        queue.seqs.push_back(seq);
+
+        // Forget entries removed by other means, then drop the oldest
+        queue.seqs.retain(|&seq| {
+            let key = queue_key(prefix, seq);
+            guards[self.shard_index(&key)]
+                .get(key.as_str())
+                .is_some_and(|entry| !self.is_expired(entry, now))
+        });
+        while queue.seqs.len() > cap {
+            let oldest = match queue.seqs.pop_front() {
+                Some(oldest) => queue_key(prefix, oldest),
+                None => break,
+            };
+            let entries = &mut guards[self.shard_index(&oldest)];
+            if let Some((_, key, entry)) = entries.shift_remove_full(oldest.as_str()) {
+                self.stats.record_eviction();
+                self.stats
+                    .record_evicted_bytes((key.len() + entry.value().len()) as u64);
+                self.account_removal(&key, &entry);
+                self.forget_shadow(&key);
+                self.collect(&mut pending, key, &entry, RemovalCause::Evicted);
+            }
+        }
+        if queue.seqs.is_empty() && cap == 0 {
+            queues.remove(prefix);
+        }
+
+        drop(queues);
+        drop(guards);
+        self.notify(pending);
+        Some(key)
+    }
+
+    /// Values of the newest `n` live entries pushed under `prefix`, oldest
+    /// first. Does not affect LRU order or statistics.
+    pub fn recent(&self, prefix: &str, n: usize) -> Vec<Bytes> {
+        let guards = self.read_all();
+        let queues = self.lock_queues();
+        let queue = match queues.get(prefix) {
+            Some(queue) => queue,
+            None => return Vec::new(),
+        };
+        let now = self.now();
+
+        let mut values: Vec<Bytes> = queue
+            .seqs
+            .iter()
+            .rev()
+            .filter_map(|&seq| {
+                let key = queue_key(prefix, seq);
+                guards[self.shard_index(&key)].get(key.as_str())
+            })
+            .filter(|entry| !self.is_expired(entry, now))
+            .map(|entry| entry.value().clone())
+            .take(n)
+            .collect();
+        drop(queues);
+        drop(guards);
+        values.reverse();
+        if self.spill.is_some() {
+            values.retain_mut(|value| match self.resolve(value.clone()) {
+                Some(resolved) => {
+                    *value = resolved;
+                    true
+                }
+                None => false,
+            });
+        }
+        values
+    }
+
+    /// Give a live entry a TTL of `ttl` from now. Returns `false` if the key
+    /// is missing or expired. Not a set: the value and LRU order are kept.
+    pub fn expire(&self, key: &str, ttl: Duration) -> bool {
+        self.retime(key, Some(ttl))
+    }
+
+    /// Remove the TTL of a live entry. Returns `false` if the key is missing
+    /// or expired.
+    pub fn persist(&self, key: &str) -> bool {
+        self.retime(key, None)
+    }
+
+    fn retime(&self, key: &str, ttl: Option<Duration>) -> bool {
+        let mut entries = self.write_lock(key);
+        let now = self.now();
+        let entry = match entries.get_mut(key) {
+            Some(entry) if !self.is_expired(entry, now) => entry,
+            _ => return false,
+        };
+        match ttl {
+            Some(ttl) => entry.expire_after(self.epoch.ticks(now), entry::millis(ttl)),
+            None => entry.persist(),
+        }
+        self.schedule(key, entry);
+        self.trim_deadlines(&entries);
+        // As in `retime_matching_at`, the old shadow deadline no longer applies
+        self.forget_shadow(key);
+        true
+    }
+
+    /// Set the expiration of a live entry. Returns `false` if the key is
+    /// missing or already expired.
+    #[cfg_attr(not(feature = "tools"), allow(dead_code))]
+    pub(crate) fn set_expiration(&self, key: &str, expires_at: Instant) -> bool {
+        let mut entries = self.write_lock(key);
+        let now = self.now();
+        match entries.get_mut(key) {
+            Some(entry) if !self.is_expired(entry, now) => {
+                let now = self.epoch.ticks(now);
+                entry.expire_after(now, self.epoch.ticks(expires_at).saturating_sub(now));
+                self.schedule(key, entry);
+                self.trim_deadlines(&entries);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Delete a key from the cache.
+    ///
+    /// Returns `true` if the key existed and was removed.
+    pub fn delete(&self, key: &str) -> bool {
+        let mut entries = self.write_lock(key);
+
+        let removed = entries.shift_remove_full(key);
+        if let Some((_, key, entry)) = &removed {
+            self.forget_shadow(key);
+            // Under the lock, so the memory total never lags the map
+            self.account_removal(key, entry);
+        }
+        drop(entries);
+        match removed {
+            Some((_, key, entry)) => {
+                self.stats.record_delete();
+                let mut pending = Vec::new();
+                self.collect(&mut pending, key, &entry, RemovalCause::Deleted);
+                self.notify(pending);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove `key` and return its value, under one write lock. A live value
+    /// counts as a hit and a delete; an expired one is removed as an
+    /// expiration and reads as missing.
+    pub fn take(&self, key: &str) -> Option<Bytes> {
+        let mut entries = self.write_lock(key);
+        let mut pending = Vec::new();
+        self.remove_if_expired(&mut entries, key, &mut pending);
+        let released = self.remove_if_corrupted(&mut entries, key);
+        let removed = entries.shift_remove_full(key);
+        if let Some((_, key, entry)) = &removed {
+            self.forget_shadow(key);
+            self.account_removal(key, entry);
+        }
+        drop(entries);
+        self.release_spilled(released);
+
+        let (key, entry) = match removed {
+            Some((_, key, entry)) => (key, entry),
+            None => {
+                self.notify(pending);
+                self.record_miss(key);
+                return None;
+            }
+        };
+        self.stats.record_hit();
+        self.stats.record_delete();
+        // Read a spilled value back before its file goes
+        let value = self.resolve(entry.value.clone());
+        self.collect(&mut pending, key, &entry, RemovalCause::Deleted);
+        self.notify(pending);
+        value
+    }
+
+    /// Delete every key in `keys` under the write lock of every shard,
+    /// returning how many were removed.
+    pub fn delete_many<K: AsRef<str>>(&self, keys: &[K]) -> usize {
+        let mut guards = self.write_all();
+
+        let mut removed = 0;
+        let mut pending = Vec::new();
+        for key in keys {
+            let key = key.as_ref();
+            if let Some((_, key, entry)) = guards[self.shard_index(key)].shift_remove_full(key) {
+                self.forget_shadow(&key);
+                self.account_removal(&key, &entry);
+                self.stats.record_delete();
+                self.collect(&mut pending, key, &entry, RemovalCause::Deleted);
+                removed += 1;
+            }
+        }
+        drop(guards);
+        self.notify(pending);
+        removed
+    }
+
+    /// Delete every key starting with `prefix`, one shard at a time under
+    /// its write lock, returning how many were removed.
+    pub fn delete_prefix(&self, prefix: &str) -> usize {
+        let mut removed = 0;
+        for shard in self.shards.iter() {
+            let mut entries = self.write_shard(shard);
+
+            let mut pending = Vec::new();
+            let initial_len = entries.len();
+            entries.retain(|key, entry| {
+                if !key.starts_with(prefix) {
+                    return true;
+                }
+                self.forget_shadow(key);
+                self.account_removal(key, entry);
+                self.stats.record_delete();
+                self.collect(&mut pending, key.clone(), entry, RemovalCause::Deleted);
+                false
+            });
+            removed += initial_len - entries.len();
+            drop(entries);
+            self.notify(pending);
+        }
+        removed
+    }
+
+    /// Run `f` on a view of `keys` and apply its writes atomically.
+    ///
+    /// The write lock of every shard is held from the snapshot until the
+    /// writes are applied, so no other operation observes part of the
+    /// transaction. Nothing is applied if `f` fails or panics (reported as
+    /// `CallbackPanic`). Entries evicted to make room are never among the
+    /// declared keys, which is why more keys than a shard's part of
+    /// `max_capacity`, or writes larger than `max_memory`, are rejected.
+    pub fn transaction(
+        &self,
+        keys: &[&str],
+        f: impl FnOnce(&mut TxnView<'_>) -> CacheResult<()>,
+    ) -> CacheResult<()> {
+        let declared: HashSet<&str> = keys.iter().copied().collect();
+        if self.config.max_capacity.is_some() {
+            let mut counts = vec![0; self.shards.len()];
+            for key in &declared {
+                counts[self.shard_index(key)] += 1;
+            }
+            for (shard, current) in self.shards.iter().zip(counts) {
+                if let Some(max) = shard.capacity.filter(|&max| current > max) {
+                    return Err(CacheError::CapacityExceeded { current, max });
+                }
+            }
+        }
+
+        let mut guards = self.write_all();
+        let now = self.now();
+        let snapshot = declared
+            .iter()
+            .map(|&key| {
+                let value = guards[self.shard_index(key)]
+                    .get(key)
+                    .filter(|entry| !self.is_expired(entry, now))
+                    .map(|entry| entry.value().clone());
+                (key, value)
+            })
+            .collect();
+        let resolve = |value| self.resolve(value);
+        let mut view = TxnView::new(snapshot, self.config.default_ttl, &resolve);
+        // `f` only sees the snapshot, so a panic leaves the map untouched
+        callback::guard(|| f(&mut view))??;
+        let writes = view.into_writes();
+
+        // Spill before touching the map, so a rejected value aborts cleanly
+        let mut staged = Vec::with_capacity(writes.len());
+        for (key, write) in writes {
+            let write = match write {
+                Write::Set(value, ttl) => match self.spill_value(value) {
+                    Some(value) => Write::Set(value, ttl),
+                    None => {
+                        drop(guards);
+                        for (_, write) in staged {
+                            if let Write::Set(value, _) = write {
+                                self.release_spilled(self.spilled_ref(&value));
+                            }
+                        }
+                        return Err(CacheError::InvalidValue(format!(
+                            "value for '{}' could not be spilled",
+                            key
+                        )));
+                    }
+                },
+                Write::Delete => Write::Delete,
+            };
+            staged.push((key, write));
+        }
+        // Sets are weighed before anything changes, so the limits can be
+        // checked up front
+        let mut deletes = Vec::new();
+        let mut sets = Vec::new();
+        for (key, write) in staged {
+            match write {
+                Write::Set(value, ttl) => {
+                    let mut entry = self.make_entry(value, ttl);
+                    entry.weight = self.weigh(key, entry.value());
+                    sets.push((key, entry));
+                }
+                Write::Delete => deletes.push(key),
+            }
+        }
+        // Like the entry count, the declared writes alone must fit
+        let incoming: u64 = sets.iter().map(|(key, entry)| footprint(key, entry)).sum();
+        let weight: u64 = sets
+            .iter()
+            .map(|(_, entry)| u64::from(entry.weight()))
+            .sum();
+        let exceeded = [
+            (self.config.max_memory, incoming),
+            (self.config.max_weight, weight),
+        ]
+        .into_iter()
+        .find_map(|(limit, total)| limit.filter(|&max| total > max).map(|max| (total, max)));
+        if let Some((total, max)) = exceeded {
+            drop(guards);
+            for (_, entry) in sets {
+                self.release_spilled(self.spilled_ref(entry.value()));
+            }
+            return Err(CacheError::CapacityExceeded {
+                current: usize::try_from(total).unwrap_or(usize::MAX),
+                max: usize::try_from(max).unwrap_or(usize::MAX),
+            });
+        }
+
+        let mut pending = Vec::new();
+        let mut released = Vec::new();
+        for key in deletes {
+            if let Some((_, key, entry)) = guards[self.shard_index(key)].shift_remove_full(key) {
+                self.forget_shadow(&key);
+                self.account_removal(&key, &entry);
+                self.stats.record_delete();
+                self.collect(&mut pending, key, &entry, RemovalCause::Deleted);
+            }
+        }
+        for (index, entries) in guards.iter_mut().enumerate() {
+            let capacity = match entries.shard.capacity {
+                Some(capacity) => capacity,
+                None => continue,
+            };
+            let new = sets
+                .iter()
+                .filter(|(key, _)| self.shard_index(key) == index && !entries.contains_key(*key))
+                .count();
+            while entries.len() + new > capacity {
+                // Declared keys fit (checked above), so a victim exists
+                match self.victim(entries, |key| declared.contains(key)) {
+                    Some(index) => self.evict_at(entries, index, &mut pending),
+                    None => break,
+                }
+            }
+        }
+        let (replaced, replaced_weight) = sets
+            .iter()
+            .filter_map(|(key, _)| {
+                guards[self.shard_index(key)]
+                    .get(*key)
+                    .map(|old| (key, old))
+            })
+            .fold((0, 0), |(bytes, weight), (key, old)| {
+                (
+                    bytes + footprint(key, old),
+                    weight + u64::from(old.weight()),
+                )
+            });
+        // Shards give up their victims in turn
+        for entries in guards.iter_mut() {
+            while self.over_limits(replaced, incoming, replaced_weight, weight) {
+                match self.victim(entries, |key| declared.contains(key)) {
+                    Some(index) => self.evict_at(entries, index, &mut pending),
+                    None => break,
+                }
+            }
+        }
+        for (key, entry) in sets {
+            let spilled = self.spilled_ref(entry.value());
+            let entries = &mut guards[self.shard_index(key)];
+            let outcome = self.insert_weighed(entries, Cow::Borrowed(key), entry, &mut pending);
+            if outcome == SetOutcome::Unchanged {
+                released.extend(spilled);
+            }
+        }
+        drop(guards);
+        self.notify(pending);
+        for reference in released {
+            self.release_spilled(Some(reference));
+        }
+        Ok(())
+    }
+
+    /// Check if a key exists in the cache (and is not expired).
+    pub fn contains(&self, key: &str) -> bool {
+        let entries = self.read_lock(key);
+
+        match entries.get(key) {
+            Some(entry) => {
+                if self.is_expired(entry, self.now()) {
+                    drop(entries);
+                    self.remove_expired(key);
+                    false
+                } else {
+                    true
+                }
+            }
+            None => false,
+        }
+    }
+
+    /// Get the number of entries in the cache.
+    ///
+    /// Note: This may include expired entries that haven't been cleaned up yet.
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| self.read_shard(shard).len())
+            .sum()
+    }
+
+    /// Check if the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove all entries from the cache.
+    ///
+    /// The maps are swapped out under the write lock of every shard and the
+    /// old entries are freed (and reported as `Cleared`) only after the
+    /// locks are released, optionally on a background thread.
+    pub fn clear(&self) {
+        let old: Vec<_> = self
+            .write_all()
+            .iter_mut()
+            .map(|entries| {
+                entries.shard.lock_deadlines().clear();
+                std::mem::take(&mut **entries)
+            })
+            .collect();
+        self.stats.set_size(0);
+        self.stats.set_memory(0);
+        self.stats.set_weight(0);
+        self.lock_shadow().clear();
+
+        let listener = self.config.eviction_listener.clone();
+        let spill = self.spill.clone();
+        if self.config.background_clear {
+            let stats = Arc::clone(&self.stats);
+            std::thread::spawn(move || drop_cleared(old, listener, spill.as_deref(), &stats));
+        } else {
+            drop_cleared(old, listener, spill.as_deref(), &self.stats);
+        }
+    }
+
+    /// Snapshot of all live keys in storage order, shard by shard.
+    pub fn keys(&self) -> Vec<String> {
+        let now = self.now();
+        self.read_all()
+            .iter()
+            .flat_map(|entries| entries.iter())
+            .filter(|(_, entry)| !self.is_expired(entry, now))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Snapshot of all live keys in lexicographic order.
+    pub fn keys_sorted(&self) -> Vec<String> {
+        let mut keys = self.keys();
+        keys.sort_unstable();
+        keys
+    }
+
+    /// Live keys matching the glob `pattern`, in lexicographic order.
+    pub fn keys_matching(&self, pattern: &str) -> Vec<String> {
+        let mut keys = self.keys_matching_at(self.now(), &Glob::new(pattern));
+        keys.sort_unstable();
+        keys
+    }
+
+    fn keys_matching_at(&self, now: Instant, glob: &Glob) -> Vec<String> {
+        self.read_all()
+            .iter()
+            .flat_map(|entries| entries.iter())
+            .filter(|(key, entry)| !self.is_expired(entry, now) && glob.matches(key))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Up to `limit` live keys that sort after `after` (or from the first
+    /// key), in lexicographic order. Returns whether more keys follow.
+    ///
+    /// Only the candidates kept in a bounded heap are copied, so a page costs
+    /// O(n log limit) under the read lock, without sorting every key.
+    pub fn scan_keys(&self, after: Option<&str>, limit: usize) -> (Vec<String>, bool) {
+        let now = self.now();
+        let guards = self.read_all();
+        // One extra key shows whether another page follows
+        let keep = limit.saturating_add(1);
+        let len = guards.iter().map(|entries| entries.len()).sum();
+        let mut smallest: BinaryHeap<&str> = BinaryHeap::with_capacity(keep.min(len));
+        for (key, entry) in guards.iter().flat_map(|entries| entries.iter()) {
+            if after.is_some_and(|after| key.as_str() <= after) || self.is_expired(entry, now) {
+                continue;
+            }
+            if smallest.len() < keep {
+                smallest.push(key);
+            } else if smallest
+                .peek()
+                .is_some_and(|largest| key.as_str() < *largest)
+            {
+                smallest.pop();
+                smallest.push(key);
+            }
+        }
+        let mut keys: Vec<String> = smallest.into_iter().map(str::to_string).collect();
+        drop(guards);
+        keys.sort_unstable();
+        let more = keys.len() > limit;
+        keys.truncate(limit);
+        (keys, more)
+    }
+
+    /// Snapshot of all live entries in lexicographic key order.
+    pub fn iter_sorted(&self) -> Vec<(String, Bytes)> {
+        let mut items: Vec<(String, Bytes)> = self.live_entries();
+        items.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        items
+    }
+
+    /// Call `f` on live entries in storage order under the read lock of
+    /// every shard until it breaks. Does not promote entries or touch
+    /// statistics.
+    pub fn for_each_while(&self, mut f: impl FnMut(&str, &Bytes) -> ControlFlow<()>) {
+        let guards = self.read_all();
+        let now = self.now();
+        for (key, entry) in guards.iter().flat_map(|entries| entries.iter()) {
+            if self.is_expired(entry, now) {
+                continue;
+            }
+            // Spilled values are read back from disk under the read lock
+            let resolved;
+            let value = match self.spilled_ref(entry.value()) {
+                Some(reference) => match self.resolve(reference) {
+                    Some(value) => {
+                        resolved = value;
+                        &resolved
+                    }
+                    None => continue,
+                },
+                None => entry.value(),
+            };
+            if f(key, value).is_break() {
+                return;
+            }
+        }
+    }
+
+    /// The first live entry in storage order satisfying `pred`.
+    pub fn find(&self, mut pred: impl FnMut(&str, &Bytes) -> bool) -> Option<(String, Bytes)> {
+        let mut found = None;
+        self.for_each_while(|key, value| {
+            if pred(key, value) {
+                found = Some((key.to_string(), value.clone()));
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        found
+    }
+
+    /// Collect all live entries in storage order.
+    pub fn live_entries<C: FromIterator<(String, Bytes)>>(&self) -> C {
+        if self.spill.is_none() {
+            return self.stored_entries();
+        }
+        // Read spilled values back only after the lock is released
+        let stored: Vec<(String, Bytes)> = self.stored_entries();
+        stored
+            .into_iter()
+            .filter_map(|(key, value)| self.resolve(value).map(|value| (key, value)))
+            .collect()
+    }
+
+    /// `live_entries` without resolving spill references.
+    fn stored_entries<C: FromIterator<(String, Bytes)>>(&self) -> C {
+        let now = self.now();
+        self.read_all()
+            .iter()
+            .flat_map(|entries| entries.iter())
+            .filter(|(_, entry)| !self.is_expired(entry, now))
+            .map(|(key, entry)| (key.clone(), entry.value().clone()))
+            .collect()
+    }
+
+    /// The configuration this database was created with.
+    pub fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+
+    /// The health tracker, whose callbacks also receive growth alarms.
+    pub(crate) fn health(&self) -> Arc<HealthTracker> {
+        Arc::clone(&self.health)
+    }
+
+    /// Get a reference to the statistics.
+    pub fn stats(&self) -> Arc<CacheStats> {
+        Arc::clone(&self.stats)
+    }
+
+    /// Remove all expired entries from the cache.
+    pub fn cleanup_expired(&self) -> usize {
+        self.sweep_expired_at(self.now()).0
+    }
+
+    /// Remove the entries expired at `now`. Returns how many were removed
+    /// and how many entries the cache held.
+    ///
+    /// Shards are swept one at a time, each under its own write lock.
+    /// Expired entries are found through the deadline records, so a sweep
+    /// that finds nothing costs no more than looking at the earliest one.
+    /// Removing entries still shifts the ones stored after them.
+    pub(crate) fn sweep_expired_at(&self, now: Instant) -> (usize, usize) {
+        let (mut removed, mut len) = (0, 0);
+        for shard in self.shards.iter() {
+            let (shard_removed, shard_len) = self.sweep_shard_at(shard, now);
+            removed += shard_removed;
+            len += shard_len;
+        }
+        if self.config.shadow_ttl_factor.is_some() {
+            self.lock_shadow().retain(|_, deadline| now < *deadline);
+        }
+        (removed, len)
+    }
+
+    /// `sweep_expired_at` for one shard.
+    fn sweep_shard_at(&self, shard: &Shard, now: Instant) -> (usize, usize) {
+        let mut entries = self.write_shard(shard);
+
+        let initial_len = entries.len();
+        let mut expired = Vec::new();
+        {
+            let mut deadlines = shard.lock_deadlines();
+            let mut slid = Vec::new();
+            while let Some(Reverse((due, _))) = deadlines.peek() {
+                if *due > now {
+                    break;
+                }
+                if let Some(Reverse((_, key))) = deadlines.pop() {
+                    // Stale if the key was removed or retimed since
+                    match entries.get_index_of(&key) {
+                        Some(index) if self.is_expired(&entries[index], now) => expired.push(index),
+                        // Sliding deadlines are not scheduled as they move,
+                        // only once a record for them comes up
+                        Some(index) if self.config.refresh_ttl_on_access => {
+                            slid.extend(self.due(&entries[index]).map(|due| (due, key)));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            deadlines.extend(slid.into_iter().map(Reverse));
+        }
+        expired.sort_unstable();
+        expired.dedup();
+
+        let mut pending = Vec::new();
+        let mut expire = |key: &String, entry: &Entry| {
+            self.stats.record_swept_expiration();
+            self.account_removal(key, entry);
+            self.collect(&mut pending, key.clone(), entry, RemovalCause::Expired);
+        };
+        // Each in-order removal shifts the entries after it. Entries with a
+        // TTL tend to sit near the recently used end, where removing them
+        // one by one is cheaper than compacting the whole map.
+        let shifted: usize = expired.iter().map(|&index| initial_len - index).sum();
+        if shifted < initial_len {
+            for &index in expired.iter().rev() {
+                if let Some((key, entry)) = entries.shift_remove_index(index) {
+                    expire(&key, &entry);
+                }
+            }
+            // Report in storage order, as the compacting path does
+            pending.reverse();
+        } else if !expired.is_empty() {
+            let mut expired = expired.into_iter().peekable();
+            let mut index = 0;
+            entries.retain(|key, entry| {
+                let here = index;
+                index += 1;
+                if expired.next_if_eq(&here).is_none() {
+                    return true;
+                }
+                expire(key, entry);
+                false
+            });
+        }
+
+        let removed = initial_len - entries.len();
+        drop(entries);
+        self.notify(pending);
+        (removed, initial_len)
+    }
+
+    /// Remove the expired entries among up to `max_items` entries, in
+    /// storage order from where the previous batch stopped, and return how
+    /// many were removed. A batch stays within one shard, so it looks at
+    /// fewer entries when the shard ends first. After the last entry of the
+    /// last shard the next batch starts over.
+    pub fn cleanup_expired_batch(&self, max_items: usize) -> usize {
+        self.sweep_expired_batch_at(self.now(), max_items).0
+    }
+
+    /// Sweep one batch of the configured `cleanup_batch_size`, or the whole
+    /// map without one. Returns how many entries were removed and how many
+    /// were looked at.
+    pub(crate) fn sweep_at(&self, now: Instant) -> (usize, usize) {
+        match self.config.cleanup_batch_size {
+            Some(max_items) => self.sweep_expired_batch_at(now, max_items),
+            None => self.sweep_expired_at(now),
+        }
+    }
+
+    /// `cleanup_expired_batch` at `now`. Returns how many entries were
+    /// removed and how many were looked at.
+    ///
+    /// Finding the expired entries costs only the batch; removing any still
+    /// compacts the whole map once, as every in-order removal does.
+    fn sweep_expired_batch_at(&self, now: Instant, max_items: usize) -> (usize, usize) {
+        let index = match self.sweep_shard.load(Ordering::Relaxed) {
+            index if index < self.shards.len() => index,
+            _ => 0,
+        };
+        let shard = &self.shards[index];
+        let mut entries = self.write_shard(shard);
+
+        let len = entries.len();
+        let start = match shard.sweep_cursor.load(Ordering::Relaxed) {
+            cursor if cursor < len => cursor,
+            _ => 0,
+        };
+        let end = start + max_items.min(len - start);
+        let expired = (start..end)
+            .filter_map(|index| entries.get_index(index))
+            .filter(|(_, entry)| self.is_expired(entry, now))
+            .count();
+
+        let mut pending = Vec::new();
+        if expired > 0 {
+            let mut index = 0;
+            entries.retain(|key, entry| {
+                let in_batch = (start..end).contains(&index);
+                index += 1;
+                if !in_batch || !self.is_expired(entry, now) {
+                    return true;
+                }
+                self.stats.record_swept_expiration();
+                self.account_removal(key, entry);
+                self.collect(&mut pending, key.clone(), entry, RemovalCause::Expired);
+                false
+            });
+        }
+        // Resume right after the entries this batch kept, or in the next
+        // shard
+        let mut next = end - expired;
+        if next >= entries.len() {
+            next = 0;
+            let next_shard = (index + 1) % self.shards.len();
+            if next_shard == 0 && self.config.shadow_ttl_factor.is_some() {
+                self.lock_shadow().retain(|_, deadline| now < *deadline);
+            }
+            self.sweep_shard.store(next_shard, Ordering::Relaxed);
+        }
+        shard.sweep_cursor.store(next, Ordering::Relaxed);
+
+        drop(entries);
+        self.notify(pending);
+        (expired, end - start)
+    }
+
+    /// Remove every live entry for which `f` returns `false`, and every
+    /// expired entry, under the write lock of every shard. Returns how many
+    /// entries were removed in total.
+    ///
+    /// `f` sees every live entry before anything is removed, so it never
+    /// observes a half-updated map. If it panics, nothing is removed and
+    /// the panic continues once the lock is released.
+    pub fn retain(&self, mut f: impl FnMut(&str, &Bytes) -> bool) -> usize {
+        enum Fate {
+            Keep,
+            Delete,
+            Expire,
+        }
+
+        let mut guards = self.write_all();
+        let now = self.now();
+        let decided = callback::catch(|| {
+            guards
+                .iter()
+                .flat_map(|entries| entries.iter())
+                .map(|(key, entry)| {
+                    if self.is_expired(entry, now) {
+                        return Fate::Expire;
+                    }
+                    // Spilled values are read back from disk under the lock
+                    let value = match self.spilled_ref(entry.value()) {
+                        Some(reference) => match self.resolve(reference) {
+                            Some(value) => value,
+                            // Nothing to judge an unreadable value by
+                            None => return Fate::Keep,
+                        },
+                        None => entry.value().clone(),
+                    };
+                    if f(key, &value) {
+                        Fate::Keep
+                    } else {
+                        Fate::Delete
+                    }
+                })
+                .collect::<Vec<_>>()
+        });
+        let mut fates = match decided {
+            Ok(fates) => fates.into_iter(),
+            Err(payload) => {
+                drop(guards);
+                std::panic::resume_unwind(payload);
+            }
+        };
+
+        let mut removed = 0;
+        let mut pending = Vec::new();
+        for entries in guards.iter_mut() {
+            let initial_len = entries.len();
+            // `retain` visits entries in the order they were judged
+            entries.retain(|key, entry| match fates.next() {
+                Some(Fate::Keep) | None => true,
+                Some(Fate::Delete) => {
+                    self.forget_shadow(key);
+                    self.account_removal(key, entry);
+                    self.stats.record_delete();
+                    self.collect(&mut pending, key.clone(), entry, RemovalCause::Deleted);
+                    false
+                }
+                Some(Fate::Expire) => {
+                    self.stats.record_lazy_expiration();
+                    self.account_removal(key, entry);
+                    self.collect(&mut pending, key.clone(), entry, RemovalCause::Expired);
+                    false
+                }
+            });
+            removed += initial_len - entries.len();
+        }
+        drop(guards);
+        self.notify(pending);
+        removed
+    }
+
+    /// Keys of live entries not accessed for longer than `idle`, least
+    /// recently used first within each shard.
+    pub fn idle_longer_than(&self, idle: Duration) -> Vec<String> {
+        self.idle_longer_than_at(self.now(), idle)
+    }
+
+    fn idle_longer_than_at(&self, now: Instant, idle: Duration) -> Vec<String> {
+        let guards = self.read_all();
+        guards
+            .iter()
+            .flat_map(|entries| entries.iter())
+            .filter(|(_, entry)| self.is_idle(entry, now, idle))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Remove live entries not accessed for longer than `idle`.
+    ///
+    /// Candidates are found under the read locks and then removed in chunks
+    /// of `PURGE_CHUNK` keys, re-checking each under the write locks, so a
+    /// large purge never holds them for long. Removals count as evictions.
+    pub fn purge_idle(&self, idle: Duration) -> usize {
+        self.purge_idle_at(self.now(), idle)
+    }
+
+    fn purge_idle_at(&self, now: Instant, idle: Duration) -> usize {
+        let candidates = self.idle_longer_than_at(now, idle);
+        let mut removed = 0;
+
+        for chunk in candidates.chunks(PURGE_CHUNK) {
+            let mut pending = Vec::new();
+            {
+                let mut guards = self.write_all();
+                for key in chunk {
+                    let entries = &mut guards[self.shard_index(key)];
+                    // The entry may have been read or replaced since the scan
+                    if !entries
+                        .get(key.as_str())
+                        .is_some_and(|entry| self.is_idle(entry, now, idle))
+                    {
+                        continue;
+                    }
+                    if let Some((_, key, entry)) = entries.shift_remove_full(key.as_str()) {
+                        self.stats.record_eviction();
+                        self.stats
+                            .record_evicted_bytes((key.len() + entry.value().len()) as u64);
+                        self.account_removal(&key, &entry);
+                        self.forget_shadow(&key);
+                        self.collect(&mut pending, key, &entry, RemovalCause::Idle);
+                        removed += 1;
+                    }
+                }
+            }
+            self.notify(pending);
+        }
+        removed
+    }
+
+    /// Give every live entry whose key matches the glob `pattern` a TTL of
+    /// `ttl` from now, returning how many entries were updated.
+    ///
+    /// Matching keys are found under a read lock and updated in chunks of
+    /// `PURGE_CHUNK` keys, like `purge_idle`.
+    pub fn expire_matching(&self, pattern: &str, ttl: Duration) -> usize {
+        self.retime_matching_at(self.now(), pattern, Some(ttl))
+    }
+
+    /// Remove the TTL of every live entry whose key matches the glob
+    /// `pattern`, returning how many entries had one.
+    pub fn persist_matching(&self, pattern: &str) -> usize {
+        self.retime_matching_at(self.now(), pattern, None)
+    }
+
+    /// Set the TTL of the live entries matching `pattern` to `ttl`, or
+    /// remove it for `None`.
+    fn retime_matching_at(&self, now: Instant, pattern: &str, ttl: Option<Duration>) -> usize {
+        let candidates = self.keys_matching_at(now, &Glob::new(pattern));
+        let mut updated = 0;
+
+        for chunk in candidates.chunks(PURGE_CHUNK) {
+            let mut guards = self.write_all();
+            for key in chunk {
+                // The entry may have expired or been removed since the scan
+                let entry = match guards[self.shard_index(key)].get_mut(key.as_str()) {
+                    Some(entry) if !self.is_expired(entry, now) => entry,
+                    _ => continue,
+                };
+                match ttl {
+                    Some(ttl) => entry.expire_after(self.epoch.ticks(now), entry::millis(ttl)),
+                    None if entry.expires_at().is_some() => entry.persist(),
+                    None => continue,
+                }
+                self.schedule(key, entry);
+                // A shadow deadline follows from the TTL the key was
+                // written with, which no longer applies
+                self.forget_shadow(key);
+                updated += 1;
+            }
+            for entries in &guards {
+                self.trim_deadlines(entries);
+            }
+        }
+        updated
+    }
+
+    /// Count live entries by remaining TTL in a single read-lock scan.
+    pub fn expiration_histogram(&self, buckets: &[Duration]) -> Vec<usize> {
+        self.expiration_histogram_at(self.now(), buckets)
+    }
+
+    fn expiration_histogram_at(&self, now: Instant, buckets: &[Duration]) -> Vec<usize> {
+        let mut counts = vec![0; buckets.len() + 1];
+        let guards = self.read_all();
+        let ticks = self.epoch.ticks(now);
+
+        for entry in guards.iter().flat_map(|entries| entries.values()) {
+            let expires_at = match entry.expires_at() {
+                Some(expires_at) => expires_at,
+                None => {
+                    counts[buckets.len()] += 1;
+                    continue;
+                }
+            };
+            if self.is_expired(entry, now) {
+                continue;
+            }
+            let remaining = entry::between(ticks, expires_at);
+            let idx = buckets.partition_point(|bound| *bound < remaining);
+            if idx < buckets.len() {
+                counts[idx] += 1;
+            }
+        }
+        counts
+    }
+
+    /// Copy out every live entry with its remaining TTL and idle time.
+    ///
+    /// Values are reference-counted, so this only copies the keys.
+    pub(crate) fn records(&self) -> Vec<EntryRecord> {
+        let records = self.stored_records();
+        if self.spill.is_none() {
+            return records;
+        }
+        records
+            .into_iter()
+            .filter_map(|mut record| {
+                record.value = self.resolve(record.value)?;
+                Some(record)
+            })
+            .collect()
+    }
+
+    /// `records` without resolving spill references.
+    fn stored_records(&self) -> Vec<EntryRecord> {
+        let guards = self.read_all();
+        let now = self.now();
+        let ticks = self.epoch.ticks(now);
+        guards
+            .iter()
+            .flat_map(|entries| entries.iter())
+            .filter(|(_, entry)| !self.is_expired(entry, now))
+            .map(|(key, entry)| EntryRecord {
+                key: key.clone(),
+                value: entry.value().clone(),
+                ttl: entry
+                    .expires_at()
+                    .map(|expires| entry::between(ticks, expires)),
+                idle: entry::between(entry.last_accessed(), ticks),
+            })
+            .collect()
+    }
+
+    /// Count and size of live entries whose key starts with `prefix`.
+    pub fn prefix_stats(&self, prefix: &str) -> PrefixStats {
+        let guards = self.read_all();
+        let now = self.now();
+        let mut stats = PrefixStats::default();
+        for (key, entry) in guards.iter().flat_map(|entries| entries.iter()) {
+            if !key.starts_with(prefix) || self.is_expired(entry, now) {
+                continue;
+            }
+            stats.entries += 1;
+            stats.bytes += (key.len() + entry.value().len()) as u64;
+            if entry.expires_at().is_some() {
+                stats.with_ttl += 1;
+            }
+        }
+        stats
+    }
+
+    /// Sum of key and value lengths of all stored entries.
+    pub fn approx_bytes(&self) -> usize {
+        self.read_all()
+            .iter()
+            .flat_map(|entries| entries.iter())
+            .map(|(key, entry)| key.len() + entry.value().len())
+            .sum()
+    }
+
+    /// Key and byte size of up to `samples` entries at evenly spaced
+    /// positions, together with the total number of stored entries.
+    pub fn sample_sizes(&self, samples: usize) -> (usize, Vec<(String, usize)>) {
+        let guards = self.read_all();
+        let total = guards.iter().map(|entries| entries.len()).sum();
+        let samples = samples.min(total);
+        // Position `index` in storage order, counted across the shards
+        let get_index = |mut index: usize| {
+            for entries in &guards {
+                if index < entries.len() {
+                    return entries.get_index(index);
+                }
+                index -= entries.len();
+            }
+            None
+        };
+        let sample = (0..samples)
+            .filter_map(|i| get_index(i * total / samples))
+            .map(|(key, entry)| (key.clone(), key.len() + entry.value().len()))
+            .collect();
+        (total, sample)
+    }
+
+    /// Delete files in the spill directory that no entry refers to.
+    pub fn gc_spill_dir(&self) -> CacheResult<usize> {
+        match &self.spill {
+            Some(spill) => Ok(spill.gc()?),
+            None => Ok(0),
+        }
+    }
+
+    /// Check internal invariants under the write lock of every shard.
+    ///
+    /// Verifies that the size and memory statistics match the stored
+    /// entries and that the capacity and memory limits hold. With several
+    /// shards, `max_memory` and `max_weight` are not checked, as a write
+    /// cannot evict from other shards to stay within them.
+    pub fn debug_validate(&self) -> CacheResult<()> {
+        let guards = self.write_all();
+        let len: usize = guards.iter().map(|entries| entries.len()).sum();
+
+        let size = self.stats.size();
+        if size != len as u64 {
+            return Err(CacheError::InvariantViolation(format!(
+                "size stat is {} but {} entries are stored",
+                size, len
+            )));
+        }
+        for entries in &guards {
+            if let Some(capacity) = entries.shard.capacity {
+                if entries.len() > capacity {
+                    return Err(CacheError::InvariantViolation(format!(
+                        "{} entries stored in a shard of capacity {}",
+                        entries.len(),
+                        capacity
+                    )));
+                }
+            }
+        }
+        let entries = || guards.iter().flat_map(|entries| entries.iter());
+        let memory: u64 = entries().map(|(key, entry)| footprint(key, entry)).sum();
+        if self.stats.memory_bytes() != memory {
+            return Err(CacheError::InvariantViolation(format!(
+                "memory stat is {} but stored entries take {} bytes",
+                self.stats.memory_bytes(),
+                memory
+            )));
+        }
+        // A lone entry may exceed the limits, and so may a sharded cache
+        let exempt = len <= 1 || guards.len() > 1;
+        if let Some(max_memory) = self.config.max_memory {
+            if memory > max_memory && !exempt {
+                return Err(CacheError::InvariantViolation(format!(
+                    "{} bytes stored with max_memory {}",
+                    memory, max_memory
+                )));
+            }
+        }
+        let weight: u64 = entries().map(|(_, entry)| u64::from(entry.weight())).sum();
+        if self.stats.total_weight() != weight {
+            return Err(CacheError::InvariantViolation(format!(
+                "weight stat is {} but stored entries weigh {}",
+                self.stats.total_weight(),
+                weight
+            )));
+        }
+        if let Some(max_weight) = self.config.max_weight {
+            if weight > max_weight && !exempt {
+                return Err(CacheError::InvariantViolation(format!(
+                    "weight {} stored with max_weight {}",
+                    weight, max_weight
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // Private helper methods
+
+    /// Whether `entry` counts as expired at `now`, including the configured
+    /// grace window.
+    fn is_expired(&self, entry: &Entry, now: Instant) -> bool {
+        entry.is_expired_with_grace(
+            self.epoch.ticks(now),
+            entry::millis(self.config.expiration_grace),
+        )
+    }
+
+    /// Time since `entry` was last accessed.
+    fn idle_time(&self, entry: &Entry, now: Instant) -> Duration {
+        entry::between(entry.last_accessed(), self.epoch.ticks(now))
+    }
+
+    /// Whether `entry` is live and was last accessed more than `idle` ago.
+    fn is_idle(&self, entry: &Entry, now: Instant, idle: Duration) -> bool {
+        !self.is_expired(entry, now) && self.idle_time(entry, now) > idle
+    }
+
+    /// The current time, as this cache's clock tells it.
+    pub(crate) fn now(&self) -> Instant {
+        self.config.clock.now()
+    }
+
+    /// Index of the shard `key` belongs to.
+    fn shard_index(&self, key: &str) -> usize {
+        if self.shards.len() == 1 {
+            return 0;
+        }
+        let mut hasher = self.hasher.build_hasher();
+        key.hash(&mut hasher);
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+
+    /// The shard `key` belongs to.
+    fn shard(&self, key: &str) -> &Shard {
+        &self.shards[self.shard_index(key)]
+    }
+
+    /// Run `lock`, timing it if `record_lock_waits` is enabled.
+    fn timed<T>(&self, lock: impl FnOnce() -> T) -> T {
+        if !self.config.record_lock_waits {
+            return lock();
+        }
+        let start = Instant::now();
+        let guard = lock();
+        self.stats.record_lock_wait(start.elapsed());
+        guard
+    }
+
+    /// Take the guard out of a lock result, recovering a lock poisoned by
+    /// a panic on another thread. Every operation leaves the map itself
+    /// valid, so its entries stay usable; the recovery is counted in
+    /// `lock_failures`.
+    fn recover<G>(&self, result: LockResult<G>) -> G {
+        result.unwrap_or_else(|poisoned| {
+            self.stats.record_lock_failure();
+            poisoned.into_inner()
+        })
+    }
+
+    /// Acquire the read lock of the shard of `key`.
+    fn read_lock(&self, key: &str) -> RwLockReadGuard<'_, IndexMap<String, Entry>> {
+        self.read_shard(self.shard(key))
+    }
+
+    /// Acquire the read lock of `shard`.
+    fn read_shard<'a>(&self, shard: &'a Shard) -> RwLockReadGuard<'a, IndexMap<String, Entry>> {
+        self.timed(|| self.recover(shard.entries.read()))
+    }
+
+    /// Acquire the read lock of every shard, in index order.
+    fn read_all(&self) -> Vec<RwLockReadGuard<'_, IndexMap<String, Entry>>> {
+        self.shards
+            .iter()
+            .map(|shard| self.read_shard(shard))
+            .collect()
+    }
+
+    /// Acquire the write lock of the shard of `key`.
+    fn write_lock(&self, key: &str) -> ShardGuard<'_> {
+        self.write_shard(self.shard(key))
+    }
+
+    /// Acquire the write lock of `shard`.
+    fn write_shard<'a>(&self, shard: &'a Shard) -> ShardGuard<'a> {
+        let lock = || self.recover(shard.entries.write());
+        let mut entries = self.timed(lock);
+        if self.epoch.needs_rebase(self.now()) {
+            if self.shards.len() == 1 {
+                self.epoch.rebase(entries.values_mut());
+            } else {
+                // A rebase shifts every entry, so it needs every shard
+                drop(entries);
+                drop(self.write_all());
+                entries = self.timed(lock);
+            }
+        }
+        let mut guard = ShardGuard { shard, entries };
+        self.replay_reads(&mut guard);
+        guard
+    }
+
+    /// Acquire the write lock of every shard, in index order.
+    fn write_all(&self) -> Vec<ShardGuard<'_>> {
+        let mut guards: Vec<_> = self
+            .shards
+            .iter()
+            .map(|shard| ShardGuard {
+                shard,
+                entries: self.timed(|| self.recover(shard.entries.write())),
+            })
+            .collect();
+        self.rebase_if_needed(&mut guards);
+        for guard in &mut guards {
+            self.replay_reads(guard);
+        }
+        guards
+    }
+
+    /// Move the epoch forward once entry offsets approach the end of their
+    /// range. Must be called with the write lock of every shard held; see
+    /// `crate::entry`.
+    fn rebase_if_needed(&self, guards: &mut [ShardGuard<'_>]) {
+        if self.epoch.needs_rebase(self.now()) {
+            self.epoch
+                .rebase(guards.iter_mut().flat_map(|guard| guard.values_mut()));
+        }
+    }
+
+    /// Seed for the randomized component named `component`.
+    #[cfg_attr(not(feature = "test-util"), allow(dead_code))]
+    pub(crate) fn component_seed(&self, component: &str) -> u64 {
+        rng::component_seed(self.rng_seed, component)
+    }
+
+    /// Lock the `push_capped` queues. They hold no invariant a panic could
+    /// break, so a poisoned lock is recovered.
+    fn lock_queues(&self) -> std::sync::MutexGuard<'_, HashMap<String, PrefixQueue>> {
+        self.queues.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Check `entry` against its checksum, counting the verification.
+    /// Always passes when values are not checksummed.
+    fn verify(&self, entry: &Entry) -> bool {
+        if !self.config.checksum_values {
+            return true;
+        }
+        self.stats.record_checksum_verification();
+        if self.is_corrupted(entry) {
+            self.stats.record_checksum_failure();
+            return false;
+        }
+        true
+    }
+
+    /// Whether `entry` no longer matches its checksum.
+    fn is_corrupted(&self, entry: &Entry) -> bool {
+        self.config.checksum_values && checksum::crc32(entry.value()) != entry.checksum
+    }
+
+    /// Remove `key` from the locked map if its value is still corrupted;
+    /// it may have been replaced since it was verified. Returns the spill
+    /// reference to release once the lock is dropped. The listener is not
+    /// notified, as with `delete`.
+    fn remove_if_corrupted(
+        &self,
+        entries: &mut IndexMap<String, Entry>,
+        key: &str,
+    ) -> Option<Bytes> {
+        if !entries
+            .get(key)
+            .is_some_and(|entry| self.is_corrupted(entry))
+        {
+            return None;
+        }
+        let entry = entries.shift_remove(key)?;
+        self.account_removal(key, &entry);
+        self.forget_shadow(key);
+        self.spilled_ref(&entry.value)
+    }
+
+    /// Remove `key` if it still holds the spill reference `stored`, whose
+    /// file could not be read, and release the reference. The listener is
+    /// not notified, as for a corrupted value.
+    fn remove_unreadable(&self, key: &str, stored: &Bytes) {
+        let mut entries = self.write_lock(key);
+        if entries.get(key).map(Entry::value) != Some(stored) {
+            return;
+        }
+        if let Some(entry) = entries.shift_remove(key) {
+            self.account_removal(key, &entry);
+            self.forget_shadow(key);
+        }
+        drop(entries);
+        self.release_spilled(Some(stored.clone()));
+    }
+
+    /// Remove a specific corrupted key.
+    fn remove_corrupted(&self, key: &str) {
+        let spilled = self.remove_if_corrupted(&mut self.write_lock(key), key);
+        self.release_spilled(spilled);
+    }
+
+    /// Flip the first byte of `key`'s stored value but keep its checksum,
+    /// simulating memory corruption.
+    #[cfg(test)]
+    pub(crate) fn corrupt_for_test(&self, key: &str) {
+        let mut entries = self.write_lock(key);
+        let entry = entries.get_mut(key).unwrap();
+        let mut bytes = entry.value.to_vec();
+        bytes[0] ^= 0xFF;
+        entry.value = Bytes::from(bytes);
+    }
+
+    /// Lock the shadow deadlines. A poisoned lock only risks a miscounted
+    /// statistic, so it is recovered.
+    fn lock_shadow(&self) -> std::sync::MutexGuard<'_, HashMap<String, Instant>> {
+        self.shadow.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Record when `entry`, stored under `key`, may be swept: its deadline
+    /// plus the expiration grace. Entries without a deadline are not
+    /// recorded.
+    fn schedule(&self, key: &str, entry: &Entry) {
+        if let Some(due) = self.due(entry) {
+            self.shard(key)
+                .lock_deadlines()
+                .push(Reverse((due, key.to_string())));
+        }
+    }
+
+    /// When `entry` may be swept, or `None` if it never expires.
+    fn due(&self, entry: &Entry) -> Option<Instant> {
+        let expires_at = entry.expires_at()?;
+        Some(self.epoch.instant(expires_at) + self.config.expiration_grace)
+    }
+
+    /// Rebuild the deadline records from the map once stale ones could
+    /// make up most of them, so retiming the same keys over and over keeps
+    /// the records bounded.
+    fn trim_deadlines(&self, entries: &ShardGuard<'_>) {
+        let mut deadlines = entries.shard.lock_deadlines();
+        if deadlines.len() <= 2 * entries.len() + STALE_DEADLINES {
+            return;
+        }
+        *deadlines = entries
+            .iter()
+            .filter_map(|(key, entry)| Some(Reverse((self.due(entry)?, key.clone()))))
+            .collect();
+    }
+
+    /// Count a get miss on `key`, and a shadow hit if the key would still
+    /// be live under the shadow TTL. A record found to have run out is
+    /// dropped.
+    fn record_miss(&self, key: &str) {
+        self.stats.record_miss();
+        if self.config.shadow_ttl_factor.is_none() {
+            return;
+        }
+        let mut shadow = self.lock_shadow();
+        match shadow.get(key) {
+            Some(deadline) if self.now() < *deadline => self.stats.record_shadow_hit(),
+            Some(_) => {
+                shadow.remove(key);
+            }
+            None => {}
+        }
+    }
+
+    /// When a freshly made `entry` would expire if its TTL were `factor`
+    /// times longer, including the grace window. `None` without a TTL.
+    fn shadow_deadline(&self, entry: &Entry, factor: f64) -> Option<Instant> {
+        let ttl = entry::between(entry.last_accessed(), entry.expires_at()?);
+        let scaled = Duration::try_from_secs_f64(ttl.as_secs_f64() * factor)
+            .unwrap_or(Duration::MAX)
+            .saturating_add(self.config.expiration_grace)
+            .min(MAX_SHADOW_TTL);
+        Some(self.now() + scaled)
+    }
+
+    /// Drop the shadow record of a key removed for a reason other than
+    /// expiration, so later misses on it do not count.
+    fn forget_shadow(&self, key: &str) {
+        if self.config.shadow_ttl_factor.is_some() {
+            self.lock_shadow().remove(key);
+        }
+    }
+
+    /// Acquire the write lock of the shard of `key` only if it is
+    /// immediately available.
+    fn try_write_lock(&self, key: &str) -> Option<ShardGuard<'_>> {
+        self.try_write_shard(self.shard(key))
+    }
+
+    /// Acquire the write lock of `shard` only if it is immediately
+    /// available.
+    fn try_write_shard<'a>(&self, shard: &'a Shard) -> Option<ShardGuard<'a>> {
+        let mut entries = match shard.entries.try_write() {
+            Ok(entries) => entries,
+            Err(TryLockError::WouldBlock) => return None,
+            Err(TryLockError::Poisoned(poisoned)) => {
+                self.stats.record_lock_failure();
+                poisoned.into_inner()
+            }
+        };
+        if self.epoch.needs_rebase(self.now()) {
+            if self.shards.len() > 1 {
+                // Rebasing would mean waiting for every other shard
+                return None;
+            }
+            self.epoch.rebase(entries.values_mut());
+        }
+        let mut guard = ShardGuard { shard, entries };
+        self.replay_reads(&mut guard);
+        Some(guard)
+    }
+
+    /// Whether a read at `now` should promote `entry`, given the configured
+    /// promotion threshold.
+    fn needs_promotion(&self, entry: &Entry, now: Instant) -> bool {
+        let threshold = self.config.promotion_threshold;
+        threshold.is_zero() || self.idle_time(entry, now) >= threshold
+    }
+
+    /// Record a hit on `entry`, found under the read lock of the shard of
+    /// `key`, without waiting for the write lock: the access time is
+    /// stamped in place and the read buffered for `replay_reads`. Sliding
+    /// deadlines still need the write lock, so those hits ask for a
+    /// promotion instead.
+    fn record_hit(&self, key: &str, entry: &Entry) -> AfterHit {
+        entry.record_access();
+        let now = self.now();
+        if !self.needs_promotion(entry, now) {
+            return AfterHit::Done;
+        }
+        if self.config.refresh_ttl_on_access {
+            return AfterHit::Promote;
+        }
+        entry.touch(self.epoch.ticks(now));
+        if self.config.eviction_policy == EvictionPolicy::Fifo {
+            return AfterHit::Done;
+        }
+        if self.shard(key).record_read(key) {
+            AfterHit::Replay
+        } else {
+            AfterHit::Done
+        }
+    }
+
+    /// Finish a hit on `key` once the read lock is released. Waits for the
+    /// write lock only if `blocking`; a full read buffer is replayed only if
+    /// the lock is free, since whoever holds it replays it anyway.
+    fn finish_hit(&self, key: &str, after: AfterHit, blocking: bool) {
+        match after {
+            AfterHit::Done => {}
+            AfterHit::Promote => {
+                let entries = if blocking {
+                    Some(self.write_lock(key))
+                } else {
+                    self.try_write_lock(key)
+                };
+                if let Some(mut entries) = entries {
+                    self.promote(&mut entries, key);
+                }
+            }
+            // Taking the lock replays the buffer
+            AfterHit::Replay => drop(self.try_write_lock(key)),
+        }
+    }
+
+    /// Move the keys read since the write lock was last taken to the most
+    /// recently used end, in the order of their last read. Called on every
+    /// write-lock acquisition, so evictions see the reads first.
+    fn replay_reads(&self, entries: &mut ShardGuard<'_>) {
+        let reads = std::mem::take(&mut *entries.shard.lock_reads());
+        match reads.len() {
+            0 => {}
+            1 => {
+                if let Some(idx) = entries.get_index_of(&reads[0]) {
+                    let last = entries.len() - 1;
+                    entries.move_index(idx, last);
+                }
+            }
+            _ => {
+                // Rebuilding is linear, where moving each key would shift
+                // the map once per read
+                let rank: HashMap<&str, usize> = reads
+                    .iter()
+                    .enumerate()
+                    .map(|(rank, key)| (key.as_str(), rank))
+                    .collect();
+                let capacity = entries.capacity();
+                let old = std::mem::replace(&mut **entries, IndexMap::with_capacity(capacity));
+                let mut read = Vec::new();
+                read.resize_with(reads.len(), || None);
+                for (key, entry) in old {
+                    match rank.get(key.as_str()).copied() {
+                        Some(rank) => read[rank] = Some((key, entry)),
+                        None => {
+                            entries.insert(key, entry);
+                        }
+                    }
+                }
+                entries.extend(read.into_iter().flatten());
+            }
+        }
+    }
+
+    /// Touch an entry and, unless the policy is FIFO, move it to the most
+    /// recently used position. With `refresh_ttl_on_access`, a live entry's
+    /// deadline also slides.
+    fn promote(&self, entries: &mut IndexMap<String, Entry>, key: &str) {
+        if let Some(idx) = entries.get_index_of(key) {
+            if let Some((_, entry)) = entries.get_index_mut(idx) {
+                let now = self.now();
+                entry.touch(self.epoch.ticks(now));
+                // The entry may have expired since the caller checked it
+                if self.config.refresh_ttl_on_access && !self.is_expired(entry, now) {
+                    entry.slide(self.epoch.ticks(now));
+                }
+            }
+            if self.config.eviction_policy == EvictionPolicy::Fifo {
+                return;
+            }
+            // Move to end for LRU (most recently used)
+            let new_idx = entries.len() - 1;
+            entries.move_index(idx, new_idx);
+        }
+    }
+
+    /// Remove a specific expired key.
+    fn remove_expired(&self, key: &str) {
+        let mut pending = Vec::new();
+        self.remove_if_expired(&mut self.write_lock(key), key, &mut pending);
+        self.notify(pending);
+    }
+
+    /// Remove `key` from the locked map if it is still present and expired.
+    ///
+    /// Re-checking under the write lock means two threads racing on the same
+    /// expired key only count (and notify) the removal once.
+    fn remove_if_expired(
+        &self,
+        entries: &mut IndexMap<String, Entry>,
+        key: &str,
+        pending: &mut Vec<Removal>,
+    ) {
+        if !entries
+            .get(key)
+            .is_some_and(|entry| self.is_expired(entry, self.now()))
+        {
+            return;
+        }
+        if let Some((_, key, entry)) = entries.shift_remove_full(key) {
+            self.account_removal(&key, &entry);
+            self.stats.record_lazy_expiration();
+            self.collect(pending, key, &entry, RemovalCause::Expired);
+        }
+    }
+
+    /// Evict entries other than `key` until storing
+    /// `incoming` bytes of the given `weight` under `key` keeps the totals
+    /// within `max_memory` and `max_weight`, or nothing else is left to
+    /// evict.
+    fn make_room(
+        &self,
+        entries: &mut IndexMap<String, Entry>,
+        key: &str,
+        incoming: u64,
+        weight: u64,
+        pending: &mut Vec<Removal>,
+    ) {
+        if self.config.max_memory.is_none() && self.config.max_weight.is_none() {
+            return;
+        }
+        // An overwrite frees the old value and weight
+        let (replaced, replaced_weight) = entries
+            .get(key)
+            .map_or((0, 0), |old| (footprint(key, old), u64::from(old.weight())));
+        while self.over_limits(replaced, incoming, replaced_weight, weight) {
+            match self.victim(entries, |other| other == key) {
+                Some(index) => self.evict_at(entries, index, pending),
+                None => break,
+            }
+        }
+    }
+
+    /// Whether replacing `replaced` bytes and `replaced_weight` with
+    /// `incoming` bytes and `weight` would exceed `max_memory` or
+    /// `max_weight`.
+    fn over_limits(&self, replaced: u64, incoming: u64, replaced_weight: u64, weight: u64) -> bool {
+        let over = |limit: Option<u64>, total: u64, freed: u64, added: u64| {
+            limit.is_some_and(|max| total.saturating_sub(freed) + added > max)
+        };
+        over(
+            self.config.max_memory,
+            self.stats.memory_bytes(),
+            replaced,
+            incoming,
+        ) || over(
+            self.config.max_weight,
+            self.stats.total_weight(),
+            replaced_weight,
+            weight,
+        )
+    }
+
+    /// The weight of `value` stored under `key`: the weigher's verdict, or
+    /// 1 without a weigher or if it panics.
+    fn weigh(&self, key: &str, value: &Bytes) -> u32 {
+        let weigher = match &self.config.weigher {
+            Some(weigher) => weigher,
+            None => return 1,
+        };
+        // The weigher only borrows, and nothing has been changed yet
+        callback::guard(|| weigher(key, value)).unwrap_or_else(|_| {
+            self.stats.record_callback_panic();
+            1
+        })
+    }
+
+    /// Count a removed entry out of the size, memory and weight totals.
+    fn account_removal(&self, key: &str, entry: &Entry) {
+        self.stats.decrement_size();
+        self.stats.sub_memory(footprint(key, entry));
+        self.stats.sub_weight(u64::from(entry.weight()));
+    }
+
+    /// Evict one entry, as chosen by the eviction policy.
+    fn evict_one(&self, entries: &mut IndexMap<String, Entry>, pending: &mut Vec<Removal>) {
+        if let Some(index) = self.victim(entries, |_| false) {
+            self.evict_at(entries, index, pending);
+        }
+    }
+
+    /// Index of the next entry to evict among those `spare` does not
+    /// protect, or `None` if every entry is spared.
+    ///
+    /// IndexMap keeps insertion order and reads move entries to the end
+    /// (except under FIFO), so the first entry is the LRU or FIFO victim.
+    /// LFU scans for the fewest accesses; taking the first minimum breaks
+    /// ties by recency.
+    fn victim(
+        &self,
+        entries: &IndexMap<String, Entry>,
+        spare: impl Fn(&str) -> bool,
+    ) -> Option<usize> {
+        let mut candidates = entries
+            .iter()
+            .enumerate()
+            .filter(|(_, (key, _))| !spare(key));
+        match self.config.eviction_policy {
+            EvictionPolicy::Lru | EvictionPolicy::Fifo => candidates.next().map(|(index, _)| index),
+            EvictionPolicy::Lfu => candidates
+                .min_by_key(|(_, (_, entry))| entry.accesses())
+                .map(|(index, _)| index),
+        }
+    }
+
+    /// Evict the entry at `index` in eviction order.
+    fn evict_at(
+        &self,
+        entries: &mut IndexMap<String, Entry>,
+        index: usize,
+        pending: &mut Vec<Removal>,
+    ) {
+        if let Some((key, entry)) = entries.shift_remove_index(index) {
+            self.forget_shadow(&key);
+            self.stats.record_eviction();
+            self.stats
+                .record_evicted_bytes((key.len() + entry.value().len()) as u64);
+            self.account_removal(&key, &entry);
+            // Collected even without a listener, so a write can report
+            // what it evicted
+            pending.push(Removal {
+                key,
+                value: entry.value().clone(),
+                cause: RemovalCause::Evicted,
+            });
+        }
+    }
+
+    /// Take the replacements out of `pending`, releasing the spill files of
+    /// the replaced values, so the listener does not hear of them.
+    fn drop_replaced(&self, pending: &mut Vec<Removal>) {
+        pending.retain_mut(|removal| {
+            if removal.cause != RemovalCause::Replaced {
+                return true;
+            }
+            if let Some(spill) = &self.spill {
+                settle_spilled(spill, removal, false, &self.stats);
+            }
+            false
+        });
+    }
+
+    /// Queue a removal for the listener, if one is configured.
+    fn collect(&self, pending: &mut Vec<Removal>, key: String, entry: &Entry, cause: RemovalCause) {
+        if self.wants_removals() {
+            pending.push(Removal {
+                key,
+                value: entry.value().clone(),
+                cause,
+            });
+        }
+    }
+
+    /// Whether removals have to be collected: for the listener, or to
+    /// release the spill files of removed entries.
+    fn wants_removals(&self) -> bool {
+        self.config.eviction_listener.is_some() || self.spill.is_some()
+    }
+
+    /// Deliver queued removals and a reached growth alarm. Must be called
+    /// after the lock is released.
+    fn notify(&self, mut pending: Vec<Removal>) {
+        if let Some(alarm) = &self.growth {
+            let size = self.stats.size();
+            if let Some(reached) = alarm.check(size) {
+                self.health
+                    .deliver(&[HealthEvent::growth(size, reached)], &self.stats);
+            }
+        }
+        if let Some(spill) = &self.spill {
+            let wanted = self.config.eviction_listener.is_some();
+            for removal in &mut pending {
+                settle_spilled(spill, removal, wanted, &self.stats);
+            }
+        }
+        if let Some(listener) = &self.config.eviction_listener {
+            deliver(listener, &self.stats, pending);
+        }
+    }
+}
+
+/// Release the spill file of a removed entry, first reading the value back
+/// into `removal` if a listener is going to see it.
+fn settle_spilled(spill: &SpillStore, removal: &mut Removal, wanted: bool, stats: &CacheStats) {
+    if !spill.is_ref(&removal.value) {
+        return;
+    }
+    let reference = std::mem::take(&mut removal.value);
+    if wanted {
+        match spill.load(&reference) {
+            Ok(value) => removal.value = value,
+            Err(_) => stats.record_spill_error(),
+        }
+    }
+    if spill.release(&reference).is_err() {
+        stats.record_spill_error();
+    }
+}
+
+/// Invoke `listener` for each removal, counting panics.
+fn deliver(
+    listener: &EvictionListener,
+    stats: &CacheStats,
+    removals: impl IntoIterator<Item = Removal>,
+) {
+    for removal in removals {
+        let result = callback::guard(|| listener(&removal.key, removal.value, removal.cause));
+        if result.is_err() {
+            stats.record_callback_panic();
+        }
+    }
+}
+
+/// Free the maps swapped out by `clear`, releasing spill files and
+/// reporting each entry as `Cleared`.
+fn drop_cleared(
+    old: Vec<IndexMap<String, Entry>>,
+    listener: Option<EvictionListener>,
+    spill: Option<&SpillStore>,
+    stats: &CacheStats,
+) {
+    if listener.is_none() && spill.is_none() {
+        return drop(old);
+    }
+    let removals = old.into_iter().flatten().map(|(key, entry)| {
+        let mut removal = Removal {
+            key,
+            value: entry.value,
+            cause: RemovalCause::Cleared,
+        };
+        if let Some(spill) = spill {
+            settle_spilled(spill, &mut removal, listener.is_some(), stats);
+        }
+        removal
+    });
+    match &listener {
+        Some(listener) => deliver(listener, stats, removals),
+        None => removals.for_each(drop),
+    }
+}
+
+/// Key of the `seq`th entry pushed under `prefix`. Zero padding keeps
+/// lexicographic and push order the same.
+fn queue_key(prefix: &str, seq: u64) -> String {
+    format!("{}{:020}", prefix, seq)
+}
+
+/// The capacity of each of `shards` shards sharing `max_capacity`, using no
+/// more shards than there are entries to hold. The first shards hold one
+/// more entry each until the division's remainder is used up.
+fn split_capacity(max_capacity: Option<usize>, shards: usize) -> Vec<Option<usize>> {
+    let shards = shards.min(max_capacity.unwrap_or(usize::MAX)).max(1);
+    (0..shards)
+        .map(|index| max_capacity.map(|max| max / shards + usize::from(index < max % shards)))
+        .collect()
+}
+
+/// Bytes an entry counts against `max_memory`: its key and stored value.
+fn footprint(key: &str, entry: &Entry) -> u64 {
+    (key.len() + entry.value().len()) as u64
+}
+
+impl Default for Db {
+    fn default() -> Self {
+        Self::with_defaults()
+    }
+}
+
+// Implement Clone by creating a new Db with cloned data
+impl Clone for Db {
+    fn clone(&self) -> Self {
+        // The epoch is copied while the read locks pin it, so the cloned
+        // offsets stay meaningful
+        let guards = self.read_all();
+        let maps: Vec<_> = guards.iter().map(|entries| (**entries).clone()).collect();
+        let epoch = self.epoch.clone();
+        drop(guards);
+        let entries = || maps.iter().flat_map(|entries| entries.iter());
+        if let Some(spill) = &self.spill {
+            for (_, entry) in entries() {
+                if spill.is_ref(&entry.value) {
+                    spill.retain(&entry.value);
+                }
+            }
+        }
+
+        // New stats for the cloned instance, but the memory total has to
+        // match its entries for `max_memory` to hold
+        let stats = CacheStats::new();
+        stats.set_size(entries().count() as u64);
+        stats.set_memory(entries().map(|(key, entry)| footprint(key, entry)).sum());
+        stats.set_weight(entries().map(|(_, entry)| u64::from(entry.weight())).sum());
+
+        let shards = self
+            .shards
+            .iter()
+            .zip(maps)
+            .map(|(shard, entries)| {
+                let clone = Shard::new(entries, shard.capacity);
+                *clone.lock_deadlines() = shard.lock_deadlines().clone();
+                clone
+            })
+            .collect();
+
+        Self {
+            shards,
+            // Keys have to land in the shards they were copied into
+            hasher: self.hasher.clone(),
+            config: self.config.clone(),
+            stats: Arc::new(stats),
+            queues: Mutex::new(self.lock_queues().clone()),
+            shadow: Mutex::new(self.lock_shadow().clone()),
+            rng_seed: self.rng_seed,
+            ttl_rng: Mutex::new(
+                self.ttl_rng
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .clone(),
+            ),
+            epoch,
+            sweep_shard: AtomicUsize::new(0),
+            spill: self.spill.clone(),
+            // Like the stats, health state and alarms start over
+            health: Arc::new(HealthTracker::new(self.config.health_thresholds.clone())),
+            growth: self
+                .growth
+                .as_ref()
+                .map(|_| GrowthAlarm::new(self.config.growth_alarm)),
+        }
+    }
+}
+
+impl Drop for Db {
+    fn drop(&mut self) {
+        // Spill files are only kept alive by entries
+        let spill = match &self.spill {
+            Some(spill) => spill,
+            None => return,
+        };
+        for shard in self.shards.iter_mut() {
+            let entries = shard.entries.get_mut().unwrap_or_else(|e| e.into_inner());
+            for entry in entries.values() {
+                if spill.is_ref(&entry.value) {
+                    let _ = spill.release(&entry.value);
+                }
+            }
+        }
+    }
+}
+
+/// Legacy API support for backward compatibility.
+/// These methods match the original API signature and share their
+/// implementation with [`LegacyAdapter`](crate::LegacyAdapter).
+#[cfg(feature = "legacy")]
+impl Db {
+    /// Legacy write method - parses key/value from array.
+    ///
+    /// # Deprecated
+    /// Use `set(key, value)` instead, or `LegacyAdapter::write` while
+    /// migrating.
+    #[deprecated(since = "1.0.0", note = "Use set() instead")]
+    pub fn write(&self, arr: &[String]) -> CacheResult<&'static str> {
+        crate::legacy::write(
+            arr,
+            |key, value| self.set_returning_outcome(key, value.to_string()),
+            |key| self.contains(key),
+        )
+    }
+
+    /// Legacy read method - parses key from array.
+    ///
+    /// # Deprecated
+    /// Use `get(key)` instead, or `LegacyAdapter::read` while migrating.
+    #[deprecated(since = "1.0.0", note = "Use get() instead")]
+    pub fn read(&self, arr: &[String]) -> CacheResult<Bytes> {
+        crate::legacy::read(arr, |key| self.get(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    /// `config` with a clock the test advances instead of sleeping.
+    fn mock_clock(config: CacheConfig) -> (CacheConfig, MockClock) {
+        let clock = MockClock::new();
+        (config.clock(Arc::new(clock.clone())), clock)
+    }
+
+    #[test]
+    fn test_basic_set_get() {
+        let db = Db::with_defaults();
+
+        db.set("key1", "value1");
+        let result = db.get("key1");
+
+        assert_eq!(result, Some(Bytes::from("value1")));
+    }
+
+    #[test]
+    fn test_get_nonexistent() {
+        let db = Db::with_defaults();
+
+        let result = db.get("nonexistent");
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn test_delete() {
+        let db = Db::with_defaults();
+
+        db.set("key1", "value1");
+        assert!(db.contains("key1"));
+
+        let deleted = db.delete("key1");
+        assert!(deleted);
+        assert!(!db.contains("key1"));
+    }
+
+    #[test]
+    fn test_delete_nonexistent() {
+        let db = Db::with_defaults();
+
+        let deleted = db.delete("nonexistent");
+        assert!(!deleted);
+    }
+
+    #[test]
+    fn test_overwrite() {
+        let db = Db::with_defaults();
+
+        db.set("key1", "value1");
+        db.set("key1", "value2");
+
+        assert_eq!(db.get("key1"), Some(Bytes::from("value2")));
+        assert_eq!(db.len(), 1);
+    }
+
+    #[test]
+    fn test_transaction_applies_all_or_nothing() {
+        let db = Db::with_defaults();
+        db.set("a", "1");
+
+        let result = db.transaction(&["a", "b"], |txn| {
+            txn.delete("a")?;
+            txn.set("b", "2")?;
+            Err(CacheError::InvalidValue("changed my mind".to_string()))
+        });
+        assert!(matches!(result, Err(CacheError::InvalidValue(_))));
+        assert_eq!(db.get("a"), Some(Bytes::from("1")));
+        assert_eq!(db.get("b"), None);
+
+        db.transaction(&["a", "b"], |txn| {
+            txn.delete("a")?;
+            txn.set("b", "2")
+        })
+        .unwrap();
+        assert_eq!(db.get("a"), None);
+        assert_eq!(db.get("b"), Some(Bytes::from("2")));
+        assert_eq!(db.stats().size(), 1);
+
+        // Undeclared keys abort the transaction
+        let result = db.transaction(&["a"], |txn| txn.set("c", "3"));
+        assert!(matches!(result, Err(CacheError::InvalidKey(_))));
+        assert!(!db.contains("c"));
+    }
+
+    #[test]
+    fn test_transaction_panic_leaves_cache_usable() {
+        let db = Db::with_defaults();
+        let result = db.transaction(&["a"], |txn| {
+            txn.set("a", "1")?;
+            panic!("bug in transaction");
+        });
+        assert!(matches!(result, Err(CacheError::CallbackPanic(_))));
+        assert!(!db.contains("a"));
+        db.set("a", "2");
+        assert_eq!(db.get("a"), Some(Bytes::from("2")));
+    }
+
+    #[test]
+    fn test_transaction_eviction_spares_declared_keys() {
+        let db = Db::new(CacheConfig::new().max_capacity(3));
+        db.set("a", "1");
+        db.set("b", "2");
+        db.set("other", "x");
+
+        // "a" is the LRU entry, but belongs to the transaction
+        db.transaction(&["a", "c", "d"], |txn| {
+            txn.set("c", "3")?;
+            txn.set("d", "4")
+        })
+        .unwrap();
+        assert!(db.contains("a"));
+        assert!(db.contains("c"));
+        assert!(db.contains("d"));
+        assert!(!db.contains("b"));
+        assert!(!db.contains("other"));
+        assert_eq!(db.len(), 3);
+
+        let result = db.transaction(&["w", "x", "y", "z"], |_| Ok(()));
+        assert!(matches!(
+            result,
+            Err(CacheError::CapacityExceeded { current: 4, max: 3 })
+        ));
+    }
+
+    #[test]
+    fn test_clear() {
+        let db = Db::with_defaults();
+
+        db.set("key1", "value1");
+        db.set("key2", "value2");
+        assert_eq!(db.len(), 2);
+
+        db.clear();
+        assert!(db.is_empty());
+    }
+
+    #[test]
+    fn test_capacity_eviction() {
+        let config = CacheConfig::new().max_capacity(3).build();
+        let db = Db::new(config);
+
+        db.set("key1", "value1");
+        db.set("key2", "value2");
+        db.set("key3", "value3");
+        assert_eq!(db.len(), 3);
+
+        // This should evict key1 (oldest)
+        db.set("key4", "value4");
+        assert_eq!(db.len(), 3);
+        assert!(!db.contains("key1"));
+        assert!(db.contains("key4"));
+
+        // "key1" + "value1"
+        assert_eq!(db.stats().evictions(), 1);
+        assert_eq!(db.stats().bytes_evicted(), 10);
+    }
+
+    #[test]
+    fn test_lru_eviction_order() {
+        let config = CacheConfig::new().max_capacity(3).build();
+        let db = Db::new(config);
+
+        db.set("key1", "value1");
+        db.set("key2", "value2");
+        db.set("key3", "value3");
+
+        // Access key1, making it recently used
+        let _ = db.get("key1");
+
+        // Now key2 should be the LRU
+        db.set("key4", "value4");
+
+        assert!(db.contains("key1")); // Was accessed, not evicted
+        assert!(!db.contains("key2")); // Was LRU, evicted
+        assert!(db.contains("key3"));
+        assert!(db.contains("key4"));
+    }
+
+    #[test]
+    fn test_hits_are_replayed_in_read_order() {
+        let db = Db::new(CacheConfig::new().max_capacity(4));
+        for key in ["a", "b", "c", "d"] {
+            db.set(key, "1");
+        }
+
+        // Only the last read of "c" counts
+        for key in ["c", "a", "b", "c"] {
+            db.get(key);
+        }
+        db.set("e", "1");
+        assert_eq!(db.keys(), ["a", "b", "c", "e"]);
+
+        // A reader replays a full buffer itself
+        for _ in 0..READ_BUFFER {
+            db.get("a");
+        }
+        assert!(db.shards[0].lock_reads().is_empty());
+        assert_eq!(db.keys(), ["b", "c", "e", "a"]);
+    }
+
+    #[test]
+    fn test_hits_do_not_wait_for_the_write_lock() {
+        let db = Db::new(CacheConfig::new().max_capacity(2));
+        db.set("a", "1");
+        db.set("b", "2");
+
+        // Holding a read lock keeps writers out, but not hits
+        let entries = db.shards[0].entries.read().unwrap();
+        assert_eq!(db.get("a"), Some(Bytes::from("1")));
+        drop(entries);
+
+        db.set("c", "3");
+        assert!(db.contains("a"));
+        assert!(!db.contains("b"));
+    }
+
+    #[test]
+    fn test_promotion_threshold_skips_recent_promotions() {
+        let config = CacheConfig::new()
+            .max_capacity(2)
+            .promotion_threshold(Duration::from_secs(3600));
+        let db = Db::new(config);
+        db.set("a", "1");
+        db.set("b", "2");
+
+        // "a" was just written, so this read does not move it
+        db.get("a");
+        db.set("c", "3");
+        assert!(!db.contains("a"));
+        assert!(db.contains("b"));
+        assert_eq!(db.stats().snapshot().hits, 1);
+    }
+
+    #[test]
+    fn test_promotion_threshold_promotes_stale_entries() {
+        let (config, clock) = mock_clock(
+            CacheConfig::new()
+                .max_capacity(2)
+                .promotion_threshold(Duration::from_millis(20)),
+        );
+        let db = Db::new(config);
+        db.set("a", "1");
+        db.set("b", "2");
+        clock.advance(Duration::from_millis(30));
+
+        db.get("a");
+        db.set("c", "3");
+        assert!(db.contains("a"));
+        assert!(!db.contains("b"));
+    }
+
+    #[test]
+    fn test_ttl_expiration() {
+        let (config, clock) = mock_clock(CacheConfig::new());
+        let db = Db::new(config);
+
+        // Set with very short TTL
+        db.set_with_ttl("key1", "value1", Duration::from_millis(1));
+
+        // Should exist immediately
+        assert!(db.contains("key1"));
+
+        // Wait for expiration
+        clock.advance(Duration::from_millis(10));
+
+        // Should be expired
+        assert!(db.get("key1").is_none());
+    }
+
+    #[test]
+    fn test_stats_tracking() {
+        let db = Db::with_defaults();
+
+        db.set("key1", "value1");
+        let _ = db.get("key1"); // Hit
+        let _ = db.get("nonexistent"); // Miss
+
+        let stats = db.stats();
+        assert_eq!(stats.hits(), 1);
+        assert_eq!(stats.misses(), 1);
+        assert_eq!(stats.sets(), 1);
+    }
+
+    #[test]
+    fn test_lock_waits_recorded_only_when_enabled() {
+        let db = Db::with_defaults();
+        db.set("key1", "value1");
+        let _ = db.get("key1");
+        assert_eq!(db.stats().lock_waits().count(), 0);
+
+        let db = Db::new(CacheConfig::new().record_lock_waits(true));
+        db.set("key1", "value1"); // one write lock
+        let _ = db.get("key1"); // read lock only; the promotion is buffered
+        assert_eq!(db.stats().lock_waits().count(), 2);
+        assert!(db.stats().snapshot().lock_wait_p99_ns > 0);
+    }
+
+    /// Hold the write lock on another thread while `f` runs.
+    #[test]
+    fn test_multi_get_and_touch_promotes_and_counts() {
+        let db = Db::new(CacheConfig::new().max_capacity(3).build());
+        db.set("a", "1");
+        db.set("b", "2");
+        db.set("c", "3");
+
+        let values = db.multi_get_and_touch(&["a", "missing", "b"], None);
+        assert_eq!(
+            values,
+            vec![Some(Bytes::from("1")), None, Some(Bytes::from("2"))]
+        );
+        assert_eq!(db.stats().hits(), 2);
+        assert_eq!(db.stats().misses(), 1);
+
+        // "c" is now the least recently used
+        db.set("d", "4");
+        assert!(!db.contains("c"));
+        assert!(db.contains("a"));
+        assert_eq!(db.len(), 3);
+    }
+
+    #[test]
+    fn test_ttl_jitter_spreads_deadlines_reproducibly() {
+        let ttls = |seed| {
+            let (config, _clock) = mock_clock(
+                CacheConfig::new()
+                    .ttl_jitter(0.1)
+                    .default_ttl(Duration::from_secs(100))
+                    .rng_seed(seed),
+            );
+            let db = Db::new(config);
+            for i in 0..100 {
+                db.set(format!("default:{}", i), "v");
+                db.set_with_ttl(format!("explicit:{}", i), "v", Duration::from_secs(100));
+            }
+            let mut ttls: Vec<_> = db
+                .records()
+                .into_iter()
+                .map(|record| (record.key, record.ttl.unwrap()))
+                .collect();
+            ttls.sort();
+            ttls
+        };
+
+        let first = ttls(7);
+        assert_eq!(first, ttls(7));
+        assert_ne!(first, ttls(8));
+        for (key, ttl) in &first {
+            assert!(
+                *ttl >= Duration::from_secs(89) && *ttl <= Duration::from_secs(110),
+                "{} {:?}",
+                key,
+                ttl
+            );
+        }
+        let mut distinct: Vec<_> = first.iter().map(|(_, ttl)| *ttl).collect();
+        distinct.sort();
+        distinct.dedup();
+        assert!(distinct.len() > 100);
+    }
+
+    #[test]
+    fn test_exact_ttl_ignores_jitter() {
+        let (config, clock) = mock_clock(CacheConfig::new().ttl_jitter(1.0));
+        let db = Db::new(config);
+        db.set_with_exact_ttl("lock", "v", Duration::from_secs(10));
+        assert_eq!(db.ttl("lock"), Some(Some(Duration::from_secs(10))));
+
+        clock.advance(Duration::from_millis(9_999));
+        assert!(db.contains("lock"));
+        clock.advance(Duration::from_millis(1));
+        assert!(!db.contains("lock"));
+    }
+
+    #[test]
+    fn test_refresh_ttl_on_access_keeps_read_entries_alive() {
+        let (config, clock) = mock_clock(CacheConfig::new().refresh_ttl_on_access(true));
+        let db = Db::new(config);
+        let ttl = Duration::from_secs(10);
+        db.set_with_ttl("read", "v", ttl);
+        db.set_with_ttl("peeked", "v", ttl);
+        db.set_with_ttl("counter", "1", ttl);
+        for round in 0..5 {
+            clock.advance(Duration::from_secs(6));
+            assert_eq!(db.get("read"), Some(Bytes::from("v")));
+            // Neither renews
+            assert_eq!(db.contains("peeked"), round == 0);
+            assert_eq!(db.peek("peeked").is_some(), round == 0);
+            // A rewritten value keeps sliding by the same TTL
+            db.increment("counter", 1).unwrap();
+            db.touch("counter");
+        }
+        assert_eq!(db.ttl("read"), Some(Some(ttl)));
+        assert_eq!(db.get("counter"), Some(Bytes::from("6")));
+
+        // A new TTL is what later reads renew by
+        assert!(db.expire("read", Duration::from_secs(2)));
+        clock.advance(Duration::from_secs(1));
+        db.get("read");
+        assert_eq!(db.ttl("read"), Some(Some(Duration::from_secs(2))));
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(db.get("read"), None);
+
+        // Off by default
+        let (config, clock) = mock_clock(CacheConfig::new());
+        let plain = Db::new(config);
+        plain.set_with_ttl("read", "v", ttl);
+        clock.advance(Duration::from_secs(6));
+        plain.get("read");
+        clock.advance(Duration::from_secs(6));
+        assert_eq!(plain.get("read"), None);
+    }
+
+    #[test]
+    fn test_sliding_entries_are_swept_at_their_renewed_deadline() {
+        let (config, clock) = mock_clock(CacheConfig::new().refresh_ttl_on_access(true));
+        let db = Db::new(config);
+        db.set_with_ttl("session", "v", Duration::from_secs(10));
+        db.set_with_ttl("idle", "v", Duration::from_secs(10));
+        clock.advance(Duration::from_secs(6));
+        db.get("session");
+
+        clock.advance(Duration::from_secs(6));
+        assert_eq!(db.cleanup_expired(), 1);
+        assert!(db.contains("session"));
+        clock.advance(Duration::from_secs(4));
+        assert_eq!(db.cleanup_expired(), 1);
+        assert!(db.is_empty());
+        assert!(db.shards[0].lock_deadlines().is_empty());
+    }
+
+    #[test]
+    fn test_multi_get_and_touch_extends_ttl() {
+        let (config, clock) = mock_clock(CacheConfig::new().shards(1));
+        let db = Db::new(config);
+        db.set_with_ttl("session", "s", Duration::from_millis(30));
+        db.set_with_ttl("long", "l", Duration::from_secs(3600));
+        db.set("forever", "f");
+
+        let values = db.multi_get_and_touch(
+            &["session", "long", "forever"],
+            Some(Duration::from_secs(60)),
+        );
+        assert!(values.iter().all(Option::is_some));
+
+        let entries = db.shards[0].entries.read().unwrap();
+        let in_a_minute = db.epoch.deadline(Instant::now(), Duration::from_secs(60));
+        assert!(
+            entries["session"].expires_at().unwrap()
+                > db.epoch.deadline(db.now(), Duration::from_secs(50))
+        );
+        // A later deadline is never shortened
+        assert!(entries["long"].expires_at().unwrap() > in_a_minute);
+        // Entries without a TTL do not gain one
+        assert!(entries["forever"].expires_at().is_none());
+        drop(entries);
+
+        clock.advance(Duration::from_millis(50));
+        assert_eq!(db.get("session"), Some(Bytes::from("s")));
+    }
+
+    #[test]
+    fn test_multi_get_and_touch_skips_expired() {
+        let (config, clock) = mock_clock(CacheConfig::new());
+        let db = Db::new(config);
+        db.set_with_ttl("old", "x", Duration::from_millis(10));
+        clock.advance(Duration::from_millis(20));
+
+        let values = db.multi_get_and_touch(&["old"], Some(Duration::from_secs(60)));
+        assert_eq!(values, vec![None]);
+        assert_eq!(db.len(), 0);
+        assert_eq!(db.stats().expirations(), 1);
+        assert_eq!(db.stats().misses(), 1);
+    }
+
+    #[test]
+    fn test_expiration_grace_serves_past_deadline() {
+        let (config, clock) =
+            mock_clock(CacheConfig::new().expiration_grace(Duration::from_millis(200)));
+        let db = Db::new(config);
+        db.set_with_ttl("key", "value", Duration::from_millis(10));
+        clock.advance(Duration::from_millis(30));
+
+        // Past the nominal deadline but inside the grace window: a plain hit
+        assert_eq!(db.get("key"), Some(Bytes::from("value")));
+        assert!(db.contains("key"));
+        assert_eq!(db.cleanup_expired(), 0);
+        assert_eq!(db.stats().hits(), 1);
+        assert_eq!(db.stats().expirations(), 0);
+
+        clock.advance(Duration::from_millis(220));
+        assert_eq!(db.get("key"), None);
+        assert_eq!(db.stats().expirations(), 1);
+        assert_eq!(db.stats().misses(), 1);
+    }
+
+    #[test]
+    fn test_shadow_hits_count_misses_a_longer_ttl_would_serve() {
+        let (config, clock) = mock_clock(CacheConfig::new().shadow_ttl_factor(10.0));
+        let db = Db::new(config);
+        db.set_with_ttl("short", "v", Duration::from_millis(20));
+        db.set_with_ttl("deleted", "v", Duration::from_millis(20));
+        db.set("forever", "v");
+        db.delete("deleted");
+        clock.advance(Duration::from_millis(40));
+
+        // Expired but still present, then already removed: both count
+        assert_eq!(db.get("short"), None);
+        assert_eq!(db.get("short"), None);
+        assert_eq!(db.stats().shadow_hits(), 2);
+        // Deleted and never-written keys are plain misses
+        assert_eq!(db.get("deleted"), None);
+        assert_eq!(db.get("missing"), None);
+        assert_eq!(db.stats().shadow_hits(), 2);
+        assert_eq!(db.stats().misses(), 4);
+
+        // Past the scaled TTL the record is gone
+        clock.advance(Duration::from_millis(200));
+        assert_eq!(db.get("short"), None);
+        assert_eq!(db.stats().shadow_hits(), 2);
+        assert!(db.lock_shadow().is_empty());
+    }
+
+    #[test]
+    fn test_sweep_checks_deadline_records_against_entries() {
+        let db = Db::new(CacheConfig::new().shards(1));
+        let ttl = Duration::from_secs(10);
+        for key in ["due", "overwritten", "persisted", "deleted", "retimed"] {
+            db.set_with_ttl(key, "v", ttl);
+        }
+        db.set("forever", "v");
+        db.set_with_ttl("overwritten", "v2", Duration::from_secs(100));
+        assert!(db.persist("persisted"));
+        assert!(db.delete("deleted"));
+        assert!(db.expire("retimed", Duration::from_secs(100)));
+        db.set_with_ttl("shortened", "v", Duration::from_secs(100));
+        assert!(db.expire("shortened", Duration::from_secs(1)));
+
+        let later = Instant::now() + Duration::from_secs(20);
+        assert_eq!(db.sweep_expired_at(later), (2, 6));
+        assert_eq!(
+            db.keys_sorted(),
+            vec!["forever", "overwritten", "persisted", "retimed"]
+        );
+        // Records that came due were used up, stale or not
+        assert_eq!(db.shards[0].lock_deadlines().len(), 3);
+
+        let much_later = Instant::now() + Duration::from_secs(200);
+        assert_eq!(db.sweep_expired_at(much_later), (2, 4));
+        assert!(db.shards[0].lock_deadlines().is_empty());
+    }
+
+    #[test]
+    fn test_deadline_records_stay_bounded() {
+        let db = Db::with_defaults();
+        db.set_with_ttl("key", "v", Duration::from_secs(10));
+        for _ in 0..10_000 {
+            db.expire("key", Duration::from_secs(10));
+            db.set_with_ttl("key", "v2", Duration::from_secs(10));
+        }
+        assert!(db.shards[0].lock_deadlines().len() <= 2 + STALE_DEADLINES + 1);
+
+        // A rebuild keeps every live deadline
+        let later = Instant::now() + Duration::from_secs(20);
+        assert_eq!(db.sweep_expired_at(later), (1, 1));
+    }
+
+    #[test]
+    fn test_shadow_records_follow_overwrites_and_cleanup() {
+        let (config, clock) = mock_clock(CacheConfig::new().shadow_ttl_factor(2.0));
+        let db = Db::new(config);
+        db.set_with_ttl("key", "v", Duration::from_millis(10));
+        // Rewriting without a TTL stops tracking the key
+        db.set("key", "v2");
+        assert!(db.lock_shadow().is_empty());
+
+        db.set_with_ttl("key", "v3", Duration::from_millis(10));
+        clock.advance(Duration::from_millis(40));
+        assert_eq!(db.cleanup_expired(), 1);
+        assert!(db.lock_shadow().is_empty());
+
+        // Disabled by default
+        let (config, clock) = mock_clock(CacheConfig::new());
+        let plain = Db::new(config);
+        plain.set_with_ttl("key", "v", Duration::from_millis(10));
+        clock.advance(Duration::from_millis(20));
+        assert_eq!(plain.get("key"), None);
+        assert_eq!(plain.stats().shadow_hits(), 0);
+        assert!(plain.lock_shadow().is_empty());
+    }
+
+    #[test]
+    fn test_checksum_detects_corruption() {
+        let db = Db::new(CacheConfig::new().checksum_values(true));
+        db.set("good", "value");
+        db.set("bad", "value");
+        db.corrupt_for_test("bad");
+
+        assert_eq!(db.try_get("good").unwrap(), Some(Bytes::from("value")));
+        match db.try_get("bad") {
+            Err(CacheError::Corrupted { key }) => assert_eq!(key, "bad"),
+            other => panic!("expected Corrupted, got {:?}", other),
+        }
+        // The corrupted entry is gone
+        assert!(!db.contains("bad"));
+        assert_eq!(db.try_get("bad").unwrap(), None);
+        assert_eq!(db.stats().size(), 1);
+        assert_eq!(db.stats().checksum_verifications(), 2);
+        assert_eq!(db.stats().checksum_failures(), 1);
+
+        // Every read path verifies
+        db.set("bad", "value");
+        db.corrupt_for_test("bad");
+        assert_eq!(db.get_ref("bad", |v| v.len()), None);
+        db.set("bad", "value");
+        db.corrupt_for_test("bad");
+        assert_eq!(
+            db.multi_get(&["good", "bad"]),
+            vec![Some("value".into()), None]
+        );
+        db.set("bad", "value");
+        db.corrupt_for_test("bad");
+        assert_eq!(db.get_nonblocking("bad"), Some(None));
+        assert!(!db.contains("bad"));
+        assert_eq!(db.stats().checksum_failures(), 4);
+    }
+
+    #[test]
+    fn test_checksum_off_by_default() {
+        let db = Db::with_defaults();
+        db.set("key", "value");
+        db.corrupt_for_test("key");
+        assert!(db.try_get("key").unwrap().is_some());
+        assert_eq!(db.stats().checksum_verifications(), 0);
+    }
+
+    #[test]
+    fn test_debug_validate_detects_size_drift() {
+        let db = Db::with_defaults();
+        db.set("a", "1");
+        assert!(db.debug_validate().is_ok());
+
+        db.stats.increment_size();
+        let err = db.debug_validate().unwrap_err();
+        assert!(matches!(err, CacheError::InvariantViolation(_)));
+    }
+
+    #[test]
+    fn test_memory_total_follows_every_write_path() {
+        let db = Db::new(CacheConfig::new().max_memory(64));
+        let check = |expected: u64| {
+            db.debug_validate().unwrap();
+            assert_eq!(db.stats().memory_bytes(), expected);
+        };
+        db.set("a", "1234");
+        check(5);
+        db.set("a", "12");
+        check(3);
+        assert_eq!(db.append("a", Bytes::from("345")), 5);
+        check(6);
+        db.increment("n", 10).unwrap();
+        check(9);
+        db.compare_and_swap("n", b"10", Bytes::from("7")).unwrap();
+        check(8);
+        db.set_many(vec![
+            ("b".to_string(), Bytes::from("xx"), None),
+            ("c".to_string(), Bytes::from("yyy"), None),
+        ]);
+        check(15);
+        assert_eq!(db.take("b"), Some(Bytes::from("xx")));
+        check(12);
+        let key = db.push_capped("log:", Bytes::from("1"), 1).unwrap();
+        db.push_capped("log:", Bytes::from("2"), 1);
+        check(12 + key.len() as u64 + 1);
+        assert_eq!(db.delete_prefix("log:"), 1);
+        check(12);
+        db.retain(|key, _| key != "c");
+        check(8);
+        db.set_with_ttl("short", "v", Duration::from_millis(1));
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(db.cleanup_expired(), 1);
+        check(8);
+
+        let copy = db.clone();
+        copy.debug_validate().unwrap();
+        assert_eq!(copy.stats().memory_bytes(), 8);
+        db.clear();
+        check(0);
+    }
+
+    #[test]
+    fn test_debug_validate_detects_memory_drift() {
+        let db = Db::with_defaults();
+        db.set("a", "1");
+        db.stats.add_memory(1);
+        let err = db.debug_validate().unwrap_err();
+        assert!(matches!(err, CacheError::InvariantViolation(_)));
+    }
+
+    #[test]
+    fn test_expiration_histogram_buckets() {
         let db = Db::with_defaults();
+        let now = Instant::now();
+        {
+            let mut entries = db.shards[0].entries.write().unwrap();
+            let ticks = db.epoch.ticks(now);
+            let mut put = |key: &str, ttl: Option<Duration>| {
+                let entry = match ttl {
+                    Some(ttl) => {
+                        Entry::with_expiration(Bytes::from("v"), ticks, db.epoch.deadline(now, ttl))
+                    }
+                    None => Entry::new(Bytes::from("v"), ticks),
+                };
+                entries.insert(key.to_string(), entry);
+            };
+            put("soon", Some(Duration::from_secs(5)));
+            put("edge", Some(Duration::from_secs(10)));
+            put(
+                "past_edge",
+                Some(Duration::from_secs(10) + Duration::from_millis(1)),
+            );
+            put("minute", Some(Duration::from_secs(60)));
+            put("beyond", Some(Duration::from_secs(600)));
+            put("forever", None);
+        }
+        {
+            // Deadlines before the epoch saturate to its start
+            let mut entries = db.shards[0].entries.write().unwrap();
+            entries.insert(
+                "expired".to_string(),
+                Entry::with_expiration(Bytes::from("v"), 0, 0),
+            );
+        }
+
+        let buckets = [Duration::from_secs(10), Duration::from_secs(60)];
+        // Upper bounds are inclusive; "beyond" and "expired" are not counted
+        assert_eq!(db.expiration_histogram_at(now, &buckets), vec![2, 2, 1]);
+        assert_eq!(db.expiration_histogram_at(now, &[]), vec![1]);
+    }
+
+    #[test]
+    fn test_epoch_rebase_keeps_ttls_and_idle_times() {
+        // Pretend the cache has been running for most of the offset range
+        let age = Duration::from_millis(u64::from(entry::REBASE_AT) - 1000);
+        let base = match Instant::now().checked_sub(age) {
+            Some(base) => base,
+            None => return,
+        };
+        let (config, clock) = mock_clock(CacheConfig::new());
+        let mut db = Db::new(config);
+        db.epoch = Epoch::new(base);
+        db.set_with_ttl("session", "s", Duration::from_secs(60));
+        db.set("forever", "f");
+
+        // The next write lands past the rebase point
+        clock.advance(Duration::from_millis(1100));
+        db.set("trigger", "t");
+        assert!(!db.epoch.needs_rebase(db.now()));
+
+        let records = db.records();
+        let session = records.iter().find(|r| r.key == "session").unwrap();
+        let ttl = session.ttl.unwrap();
+        assert!(
+            ttl > Duration::from_secs(57) && ttl <= Duration::from_secs(59),
+            "{:?}",
+            ttl
+        );
+        assert!(session.idle >= Duration::from_secs(1) && session.idle < Duration::from_secs(3));
+        assert_eq!(
+            records.iter().find(|r| r.key == "forever").unwrap().ttl,
+            None
+        );
+        assert_eq!(db.get("session"), Some(Bytes::from("s")));
+    }
+
+    #[test]
+    fn test_epoch_rebase_spans_every_shard() {
+        let age = Duration::from_millis(u64::from(entry::REBASE_AT) - 1000);
+        let base = match Instant::now().checked_sub(age) {
+            Some(base) => base,
+            None => return,
+        };
+        let (config, clock) = mock_clock(CacheConfig::new().shards(4));
+        let mut db = Db::new(config);
+        db.epoch = Epoch::new(base);
+        for i in 0..20 {
+            db.set_with_ttl(format!("key:{}", i), "v", Duration::from_secs(60));
+        }
+
+        clock.advance(Duration::from_millis(1100));
+        db.set("trigger", "t");
+        assert!(!db.epoch.needs_rebase(db.now()));
+        for record in db.records().iter().filter(|r| r.key != "trigger") {
+            let ttl = record.ttl.unwrap();
+            assert!(ttl > Duration::from_secs(57) && ttl <= Duration::from_secs(59));
+        }
+    }
+
+    #[test]
+    fn test_split_capacity() {
+        assert_eq!(
+            split_capacity(Some(10), 4),
+            vec![Some(3), Some(3), Some(2), Some(2)]
+        );
+        // No more shards than entries
+        assert_eq!(split_capacity(Some(2), 8), vec![Some(1), Some(1)]);
+        assert_eq!(split_capacity(None, 3), vec![None, None, None]);
+        assert_eq!(split_capacity(Some(5), 0), vec![Some(5)]);
+    }
+
+    #[test]
+    fn test_sharded_operations_span_every_shard() {
+        let db = Db::new(CacheConfig::new().shards(8));
+        assert_eq!(db.shards.len(), 8);
+        for i in 0..200 {
+            db.set(format!("key:{:03}", i), format!("{}", i));
+        }
+        assert!(db.shards.iter().all(|shard| {
+            let entries = shard.entries.read().unwrap();
+            !entries.is_empty()
+        }));
+        assert_eq!(db.len(), 200);
+        assert_eq!(db.keys().len(), 200);
+        assert_eq!(db.get("key:042"), Some(Bytes::from("42")));
+
+        let (page, more) = db.scan_keys(Some("key:009"), 3);
+        assert_eq!(page, vec!["key:010", "key:011", "key:012"]);
+        assert!(more);
+        assert_eq!(
+            db.multi_get(&["key:001", "missing", "key:199"]),
+            vec![Some(Bytes::from("1")), None, Some(Bytes::from("199"))]
+        );
+        db.transaction(&["key:001", "key:002"], |txn| {
+            txn.set("key:001", Bytes::from("one"))?;
+            txn.delete("key:002")?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(db.get("key:001"), Some(Bytes::from("one")));
+        assert_eq!(db.get("key:002"), None);
+        assert_eq!(db.delete_many(&["key:003", "key:004", "missing"]), 2);
+        assert_eq!(db.delete_prefix("key:19"), 10);
+        assert_eq!(db.retain(|key, _| key < "key:100"), 90);
+        assert_eq!(db.len(), 97);
+        db.debug_validate().unwrap();
+
+        let clone = db.clone();
+        assert_eq!(clone.keys_sorted(), db.keys_sorted());
+        assert_eq!(clone.get("key:050"), Some(Bytes::from("50")));
+        clone.debug_validate().unwrap();
+
+        db.clear();
+        assert!(db.is_empty());
+        assert_eq!(db.stats().size(), 0);
+        assert_eq!(clone.len(), 97);
+    }
+
+    #[test]
+    fn test_sharded_capacity_is_split() {
+        let db = Db::new(CacheConfig::new().max_capacity(100).shards(8));
+        for i in 0..1000 {
+            db.set(format!("key:{}", i), "v");
+        }
+        // Every shard filled up to its part of the capacity
+        assert_eq!(db.len(), 100);
+        assert_eq!(db.stats().evictions(), 900);
+        db.debug_validate().unwrap();
+
+        // Declared keys have to fit the shard they land in
+        let db = Db::new(CacheConfig::new().max_capacity(2).shards(2));
+        let result = db.transaction(&["a", "b", "c"], |_| Ok(()));
+        assert!(matches!(
+            result,
+            Err(CacheError::CapacityExceeded { max: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_sharded_sweeps_cover_every_shard() {
+        let (config, clock) = mock_clock(CacheConfig::new().shards(4));
+        let full = Db::new(config.clone());
+        let batched = Db::new(config);
+        for db in [&full, &batched] {
+            for i in 0..100 {
+                let key = format!("key:{}", i);
+                if i % 3 == 0 {
+                    db.set(key, "v");
+                } else {
+                    db.set_with_ttl(key, "v", Duration::from_secs(10));
+                }
+            }
+        }
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(full.cleanup_expired(), 66);
+        assert_eq!(full.len(), 34);
+
+        // A batch stops at the end of its shard, so a few more are needed
+        let mut removed = 0;
+        for _ in 0..40 {
+            removed += batched.cleanup_expired_batch(7);
+        }
+        assert_eq!(removed, 66);
+        assert_eq!(batched.keys_sorted(), full.keys_sorted());
+    }
+
+    #[test]
+    fn test_sharded_concurrent_writes_keep_totals() {
+        let db = Arc::new(Db::new(CacheConfig::new().max_capacity(64).shards(8)));
+        let handles: Vec<_> = (0..8)
+            .map(|t| {
+                let db = Arc::clone(&db);
+                std::thread::spawn(move || {
+                    for i in 0..2_000 {
+                        let key = format!("key:{}", (t * 7 + i) % 300);
+                        match i % 4 {
+                            0 => drop(db.delete(&key)),
+                            1 => drop(db.get(&key)),
+                            _ => db.set(key, vec![0u8; i % 40]),
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        db.debug_validate().unwrap();
+        assert!(db.len() <= 64);
+    }
+
+    #[test]
+    fn test_sharded_config_keeps_global_limits() {
+        // Memory and weight limits are enforced over one shard, so they
+        // hold for the whole cache even if more shards were asked for
+        let memory = Db::new(CacheConfig::new().max_memory(1_000).shards(8));
+        let weight = Db::new(
+            CacheConfig::new()
+                .max_weight(50)
+                .weigher(Arc::new(|_: &str, value: &Bytes| value.len() as u32))
+                .shards(8),
+        );
+        for i in 0..500 {
+            memory.set(format!("key:{}", i), vec![0u8; 10]);
+            assert!(memory.stats().memory_bytes() <= 1_000);
+            weight.set(format!("key:{}", i), vec![0u8; 10]);
+            assert!(weight.stats().total_weight() <= 50);
+        }
+        for db in [&memory, &weight] {
+            assert_eq!(db.shards.len(), 1);
+            assert!(db.stats().evictions() > 0);
+            db.debug_validate().unwrap();
+        }
+        assert_eq!(weight.len(), 5);
+    }
+
+    #[test]
+    fn test_idle_longer_than_and_purge() {
+        let (config, log) = recording_config(CacheConfig::new().shards(1));
+        let db = Db::new(config);
+        db.set("old", "1");
+        db.set("fresh", "2");
+        db.set_with_ttl("expired", "3", Duration::from_millis(1));
+
+        // Age everything by looking from the future, then read "fresh" there
+        let later = Instant::now() + Duration::from_secs(120);
+        {
+            let mut entries = db.shards[0].entries.write().unwrap();
+            entries
+                .get_mut("fresh")
+                .unwrap()
+                .touch(db.epoch.ticks(later));
+        }
+
+        assert_eq!(
+            db.idle_longer_than_at(later, Duration::from_secs(60)),
+            vec!["old".to_string()]
+        );
+        assert!(db
+            .idle_longer_than_at(later, Duration::from_secs(600))
+            .is_empty());
+
+        assert_eq!(db.purge_idle_at(later, Duration::from_secs(60)), 1);
+        assert!(!db.contains("old"));
+        assert!(db.contains("fresh"));
+
+        let stats = db.stats().snapshot();
+        assert_eq!(stats.evictions, 1);
+        assert_eq!(stats.bytes_evicted, 4);
+        assert_eq!(
+            log.lock().unwrap().as_slice(),
+            &[("old".to_string(), Bytes::from("1"), RemovalCause::Idle)]
+        );
+    }
+
+    #[test]
+    fn test_purge_idle_spans_chunks() {
+        let db = Db::new(CacheConfig::new());
+        for i in 0..(PURGE_CHUNK * 2 + 3) {
+            db.set(format!("key_{}", i), "v");
+        }
+        let later = Instant::now() + Duration::from_secs(10);
+        assert_eq!(
+            db.purge_idle_at(later, Duration::from_secs(1)),
+            PURGE_CHUNK * 2 + 3
+        );
+        assert!(db.is_empty());
+        assert!(db.debug_validate().is_ok());
+    }
+
+    #[test]
+    fn test_expire_matching_spans_chunks() {
+        let db = Db::new(CacheConfig::new());
+        let hour = Duration::from_secs(3600);
+        for i in 0..(PURGE_CHUNK * 2 + 3) {
+            db.set(format!("catalog:{}", i), "v");
+        }
+        db.set("catalogue", "v");
+        db.set_with_ttl("cart:1", "v", hour);
+
+        assert_eq!(db.expire_matching("nothing:*", Duration::from_secs(60)), 0);
+        assert_eq!(
+            db.expire_matching("catalog:*", Duration::from_secs(60)),
+            PURGE_CHUNK * 2 + 3
+        );
+        assert_eq!(db.keys_matching("catalog:*").len(), PURGE_CHUNK * 2 + 3);
+        for i in 0..(PURGE_CHUNK * 2 + 3) {
+            let ttl = db.ttl(&format!("catalog:{}", i)).unwrap().unwrap();
+            assert!(ttl <= Duration::from_secs(60), "{:?}", ttl);
+        }
+        // Non-matching entries keep their TTL, or lack of one
+        assert_eq!(db.ttl("catalogue"), Some(None));
+        assert!(db.ttl("cart:1").unwrap().unwrap() > Duration::from_secs(60));
+
+        // Once expired, entries are no longer updated
+        let later = Instant::now() + Duration::from_secs(61);
+        assert_eq!(db.retime_matching_at(later, "catalog:*", Some(hour)), 0);
+        assert_eq!(db.retime_matching_at(later, "*", None), 1);
+        assert_eq!(db.ttl("cart:1"), Some(None));
+        assert!(db.debug_validate().is_ok());
+    }
+
+    #[test]
+    fn test_persist_matching_counts_only_ttls() {
+        let db = Db::new(CacheConfig::new().shadow_ttl_factor(2.0));
+        db.set_with_ttl("session:1", "v", Duration::from_secs(60));
+        db.set_with_ttl("session:2", "v", Duration::from_secs(60));
+        db.set("session:3", "v");
+        db.set_with_ttl("user:1", "v", Duration::from_secs(60));
+
+        assert_eq!(db.persist_matching("session:[12]"), 2);
+        assert_eq!(db.persist_matching("session:*"), 0);
+        assert_eq!(db.ttl("session:1"), Some(None));
+        assert!(db.ttl("user:1").unwrap().is_some());
+        assert_eq!(db.lock_shadow().len(), 1);
+        assert!(db.lock_shadow().contains_key("user:1"));
+    }
+
+    #[test]
+    fn test_push_capped_trims_oldest() {
+        let db = Db::new(CacheConfig::new());
+        for i in 0..5 {
+            db.push_capped("feed:", Bytes::from(format!("item{}", i)), 3);
+        }
+        assert_eq!(db.len(), 3);
+        assert_eq!(
+            db.recent("feed:", 10),
+            vec![
+                Bytes::from("item2"),
+                Bytes::from("item3"),
+                Bytes::from("item4")
+            ]
+        );
+        assert_eq!(
+            db.recent("feed:", 2),
+            vec![Bytes::from("item3"), Bytes::from("item4")]
+        );
+        assert_eq!(db.stats().snapshot().evictions, 2);
+        assert!(db.recent("other:", 10).is_empty());
+    }
 
-        let deleted = db.delete("nonexistent");
-        assert!(!deleted);
+    #[test]
+    fn test_push_capped_ignores_externally_removed_keys() {
+        let db = Db::new(CacheConfig::new());
+        let first = db.push_capped("q:", Bytes::from("a"), 2).unwrap();
+        db.push_capped("q:", Bytes::from("b"), 2);
+        assert!(db.delete(&first));
+
+        // Only one live entry remains, so nothing is trimmed
+        db.push_capped("q:", Bytes::from("c"), 2);
+        assert_eq!(
+            db.recent("q:", 10),
+            vec![Bytes::from("b"), Bytes::from("c")]
+        );
+        assert_eq!(db.stats().snapshot().evictions, 0);
     }
 
     #[test]
-    fn test_overwrite() {
+    fn test_push_capped_concurrent_pushes_keep_cap_exact() {
+        // With one shard, `len` sees every push whole
+        let db = Arc::new(Db::new(CacheConfig::new().shards(1)));
+        let handles: Vec<_> = (0..8)
+            .map(|t| {
+                let db = Arc::clone(&db);
+                std::thread::spawn(move || {
+                    for i in 0..200 {
+                        db.push_capped("user:1:", Bytes::from(format!("{}-{}", t, i)), 10);
+                        assert!(db.len() <= 10);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(db.len(), 10);
+        assert_eq!(db.recent("user:1:", 100).len(), 10);
+        assert!(db.debug_validate().is_ok());
+    }
+
+    #[test]
+    fn test_coalesce_identical_writes() {
+        let (config, log) = recording_config(CacheConfig::new().coalesce_identical_writes(true));
+        let db = Db::new(config);
+        db.set("key", "value");
+        db.set("key", "value");
+        db.set("key", "other");
+        db.set("key", "other!");
+
+        assert_eq!(db.get("key"), Some(Bytes::from("other!")));
+        assert_eq!(db.stats().coalesced_sets(), 1);
+        assert_eq!(db.stats().sets(), 3);
+        let causes: Vec<RemovalCause> = log.lock().unwrap().iter().map(|r| r.2).collect();
+        assert_eq!(causes, vec![RemovalCause::Replaced, RemovalCause::Replaced]);
+    }
+
+    #[test]
+    fn test_coalesce_keeps_ttl_unless_refresh_enabled() {
+        let db = Db::new(
+            CacheConfig::new()
+                .shards(1)
+                .coalesce_identical_writes(true)
+                .build(),
+        );
+        db.set_with_ttl("key", "value", Duration::from_secs(10));
+        let original = db.shards[0].entries.read().unwrap()["key"].expires_at();
+        db.set_with_ttl("key", "value", Duration::from_secs(3600));
+        assert_eq!(
+            db.shards[0].entries.read().unwrap()["key"].expires_at(),
+            original
+        );
+
+        let db = Db::new(
+            CacheConfig::new()
+                .shards(1)
+                .coalesce_identical_writes(true)
+                .coalesce_refreshes_ttl(true)
+                .build(),
+        );
+        db.set_with_ttl("key", "value", Duration::from_secs(10));
+        db.set_with_ttl("key", "value", Duration::from_secs(3600));
+        let expires_at = db.shards[0].entries.read().unwrap()["key"]
+            .expires_at()
+            .unwrap();
+        assert!(expires_at > db.epoch.deadline(Instant::now(), Duration::from_secs(3000)));
+        assert_eq!(db.stats().coalesced_sets(), 1);
+    }
+
+    #[test]
+    fn test_coalesce_does_not_revive_expired_entry() {
+        let (config, clock) = mock_clock(CacheConfig::new().coalesce_identical_writes(true));
+        let db = Db::new(config);
+        db.set_with_ttl("key", "value", Duration::from_millis(10));
+        clock.advance(Duration::from_millis(20));
+        db.set("key", "value");
+
+        assert_eq!(db.stats().coalesced_sets(), 0);
+        assert_eq!(db.stats().expirations(), 1);
+        assert_eq!(db.get("key"), Some(Bytes::from("value")));
+    }
+
+    fn with_write_lock_held(db: &Db, f: impl FnOnce()) {
+        use std::sync::mpsc;
+
+        let (locked_tx, locked_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+
+        std::thread::scope(|scope| {
+            scope.spawn(move || {
+                let _guard = db.shards[0].entries.write().unwrap();
+                locked_tx.send(()).unwrap();
+                let _ = release_rx.recv();
+            });
+            locked_rx.recv().unwrap();
+            f();
+            release_tx.send(()).unwrap();
+        });
+    }
+
+    #[test]
+    fn test_get_ref_borrows_value() {
         let db = Db::with_defaults();
+        db.set("key1", "header:payload");
+
+        let header = db.get_ref("key1", |bytes| {
+            bytes.split(|b| *b == b':').next().map(|h| h.to_vec())
+        });
+        assert_eq!(header, Some(Some(b"header".to_vec())));
+        assert_eq!(db.get_ref("missing", |bytes| bytes.len()), None);
+
+        let stats = db.stats();
+        assert_eq!(stats.hits(), 1);
+        assert_eq!(stats.misses(), 1);
+    }
 
+    #[test]
+    fn test_get_ref_holds_read_lock_during_closure() {
+        let db = Db::new(CacheConfig::new().shards(1));
         db.set("key1", "value1");
-        db.set("key1", "value2");
 
-        assert_eq!(db.get("key1"), Some(Bytes::from("value2")));
-        assert_eq!(db.len(), 1);
+        // A blocking write here would deadlock; the non-blocking variant
+        // shows the lock is held for the duration of the closure.
+        let stored = db.get_ref("key1", |_| db.set_nonblocking("key2", "value2"));
+        assert_eq!(stored, Some(false));
+        assert!(db.set_nonblocking("key2", "value2"));
     }
 
     #[test]
-    fn test_clear() {
+    fn test_nonblocking_uncontended() {
         let db = Db::with_defaults();
 
+        assert!(db.set_nonblocking("key1", "value1"));
+        assert_eq!(
+            db.get_nonblocking("key1"),
+            Some(Some(Bytes::from("value1")))
+        );
+        assert_eq!(db.get_nonblocking("missing"), Some(None));
+        assert_eq!(db.stats().dropped_sets(), 0);
+    }
+
+    #[test]
+    fn test_nonblocking_bypasses_held_lock() {
+        let db = Db::new(CacheConfig::new().shards(1));
         db.set("key1", "value1");
-        db.set("key2", "value2");
-        assert_eq!(db.len(), 2);
 
+        with_write_lock_held(&db, || {
+            let start = Instant::now();
+            assert_eq!(db.get_nonblocking("key1"), None);
+            assert!(!db.set_nonblocking("key2", "value2"));
+            assert!(start.elapsed() < Duration::from_secs(1));
+        });
+
+        assert_eq!(db.stats().dropped_sets(), 1);
+        assert!(!db.contains("key2"));
+        assert_eq!(db.get("key1"), Some(Bytes::from("value1")));
+    }
+
+    type Log = Arc<std::sync::Mutex<Vec<(String, Bytes, RemovalCause)>>>;
+
+    fn recording_config(config: CacheConfig) -> (CacheConfig, Log) {
+        let log: Log = Arc::default();
+        let sink = Arc::clone(&log);
+        let config = config.eviction_listener(Arc::new(move |key, value, cause| {
+            sink.lock().unwrap().push((key.to_string(), value, cause));
+        }));
+        (config, log)
+    }
+
+    fn spill_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "in-memory-cache-db-spill-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn spill_files(dir: &std::path::Path) -> usize {
+        std::fs::read_dir(dir).map(|d| d.count()).unwrap_or(0)
+    }
+
+    #[test]
+    fn test_spill_over_round_trip() {
+        let dir = spill_dir("round-trip");
+        let db = Db::new(CacheConfig::new().shards(1).spill_over(8, &dir).build());
+        db.set("small", "tiny");
+        db.set("large", "a value over the threshold");
+
+        assert_eq!(spill_files(&dir), 1);
+        assert!(db.shards[0].entries.read().unwrap()["large"].value().len() < 60);
+        assert_eq!(
+            db.get("large"),
+            Some(Bytes::from("a value over the threshold"))
+        );
+        assert_eq!(db.get("small"), Some(Bytes::from("tiny")));
+        assert_eq!(db.get_ref("large", |v| v.len()), Some(26));
+        assert_eq!(
+            db.multi_get(&["large"]),
+            vec![Some(Bytes::from("a value over the threshold"))]
+        );
+        assert_eq!(db.stats().spill_hits(), 3);
+        assert_eq!(db.stats().spill_writes(), 1);
+
+        let items: Vec<(String, Bytes)> = db.live_entries();
+        assert!(items.contains(&(
+            "large".to_string(),
+            Bytes::from("a value over the threshold")
+        )));
+        assert!(db
+            .find(|_, value| value.as_ref() == b"a value over the threshold")
+            .is_some());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_append_with_spilling() {
+        let dir = spill_dir("append");
+        let db = Db::new(CacheConfig::new().spill_over(8, &dir).build());
+        assert_eq!(db.append("log", Bytes::from("abc")), 3);
+        assert_eq!(spill_files(&dir), 0);
+        // Crossing the threshold spills the value, and the old file goes
+        assert_eq!(db.append("log", Bytes::from("defghi")), 9);
+        assert_eq!(db.append("log", Bytes::from("jkl")), 12);
+        assert_eq!(spill_files(&dir), 1);
+        assert_eq!(db.get("log"), Some(Bytes::from("abcdefghijkl")));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_get_or_insert_with_spilling() {
+        let dir = spill_dir("get-or-insert");
+        let db = Db::new(CacheConfig::new().spill_over(8, &dir).build());
+        let value = db.get_or_insert_with("a", None, || Bytes::from("a value over the threshold"));
+        assert_eq!(value, Bytes::from("a value over the threshold"));
+        assert_eq!(spill_files(&dir), 1);
+        assert_eq!(
+            db.get_or_insert_with("a", None, || unreachable!()),
+            Bytes::from("a value over the threshold")
+        );
+
+        // A value whose file is gone is a miss, not an empty hit
+        std::fs::remove_dir_all(&dir).unwrap();
+        let value = db.get_or_insert_with("a", None, || Bytes::from("loaded again from source"));
+        assert_eq!(value, Bytes::from("loaded again from source"));
+        assert_eq!(db.get("a"), Some(Bytes::from("loaded again from source")));
+        let stats = db.stats().snapshot();
+        assert_eq!((stats.hits, stats.misses), (2, 2));
+        assert_eq!(spill_files(&dir), 1);
+        db.debug_validate().unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_spill_files_follow_every_removal() {
+        let dir = spill_dir("removals");
+        let (config, log) =
+            recording_config(CacheConfig::new().spill_over(4, &dir).max_capacity(2));
+        let db = Db::new(config);
+
+        // Replaced: the listener sees the old content, not the reference
+        db.set("a", "first large");
+        db.set("a", "second large");
+        assert_eq!(spill_files(&dir), 1);
+        assert_eq!(log.lock().unwrap()[0].1, Bytes::from("first large"));
+
+        // Deleted
+        db.delete("a");
+        assert_eq!(spill_files(&dir), 0);
+
+        // Shared content keeps its file until the last reference goes
+        db.set("b", "shared value");
+        db.set("c", "shared value");
+        assert_eq!(spill_files(&dir), 1);
+        db.set("d", "other value"); // evicts "b"
+        assert_eq!(spill_files(&dir), 2);
+        db.delete("c");
+        assert_eq!(spill_files(&dir), 1);
+
+        // Expired
+        db.set_with_ttl("e", "short lived", Duration::from_millis(1));
+        std::thread::sleep(Duration::from_millis(5));
+        db.cleanup_expired();
+        assert_eq!(spill_files(&dir), 1);
+
+        // Cleared
         db.clear();
-        assert!(db.is_empty());
+        assert_eq!(spill_files(&dir), 0);
+        let _ = std::fs::remove_dir_all(&dir);
     }
 
     #[test]
-    fn test_capacity_eviction() {
-        let config = CacheConfig::new().max_capacity(3).build();
+    fn test_spill_files_released_on_drop() {
+        let dir = spill_dir("drop");
+        let db = Db::new(CacheConfig::new().spill_over(4, &dir).build());
+        db.set("a", "large value");
+        let copy = db.clone();
+        drop(db);
+        assert_eq!(copy.get("a"), Some(Bytes::from("large value")));
+        drop(copy);
+        assert_eq!(spill_files(&dir), 0);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_spill_write_failure_fallback() {
+        // A directory path below a regular file can never be created
+        let blocker = spill_dir("blocker");
+        std::fs::write(&blocker, b"").unwrap();
+        let dir = blocker.join("spill");
+
+        let db = Db::new(CacheConfig::new().spill_over(4, &dir).build());
+        db.set("a", "large value");
+        assert_eq!(db.get("a"), Some(Bytes::from("large value")));
+        assert_eq!(db.stats().spill_errors(), 1);
+
+        let db = Db::new(
+            CacheConfig::new()
+                .spill_over(4, &dir)
+                .spill_fallback(SpillFallback::Reject)
+                .build(),
+        );
+        db.set("a", "tiny");
+        db.set("a", "large value");
+        assert_eq!(db.get("a"), Some(Bytes::from("tiny")));
+        assert_eq!(db.stats().dropped_sets(), 1);
+        let _ = std::fs::remove_file(&blocker);
+    }
+
+    #[test]
+    fn test_gc_spill_dir_removes_orphans() {
+        let dir = spill_dir("gc");
+        let db = Db::new(CacheConfig::new().spill_over(4, &dir).build());
+        db.set("a", "large value");
+        std::fs::write(dir.join("0000000000000000-3"), b"old").unwrap();
+
+        assert_eq!(db.gc_spill_dir().unwrap(), 1);
+        assert_eq!(db.get("a"), Some(Bytes::from("large value")));
+        assert_eq!(Db::with_defaults().gc_spill_dir().unwrap(), 0);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_listener_expired_on_lazy_get() {
+        let (config, clock) = mock_clock(CacheConfig::new());
+        let (config, log) = recording_config(config);
         let db = Db::new(config);
 
-        db.set("key1", "value1");
-        db.set("key2", "value2");
-        db.set("key3", "value3");
-        assert_eq!(db.len(), 3);
+        db.set_with_ttl("key1", "value1", Duration::from_millis(1));
+        clock.advance(Duration::from_millis(10));
+
+        assert!(db.get("key1").is_none());
+        assert!(db.get("key1").is_none());
+
+        let log = log.lock().unwrap();
+        assert_eq!(
+            *log,
+            vec![(
+                "key1".to_string(),
+                Bytes::from("value1"),
+                RemovalCause::Expired
+            )]
+        );
+        assert_eq!(db.stats().expirations(), 1);
+    }
+
+    #[test]
+    fn test_listener_expired_on_contains() {
+        let (config, clock) = mock_clock(CacheConfig::new());
+        let (config, log) = recording_config(config);
+        let db = Db::new(config);
+
+        db.set_with_ttl("key1", "value1", Duration::from_millis(1));
+        clock.advance(Duration::from_millis(10));
 
-        // This should evict key1 (oldest)
-        db.set("key4", "value4");
-        assert_eq!(db.len(), 3);
         assert!(!db.contains("key1"));
-        assert!(db.contains("key4"));
+        assert!(!db.contains("key1"));
+        assert_eq!(log.lock().unwrap().len(), 1);
+        assert_eq!(log.lock().unwrap()[0].2, RemovalCause::Expired);
     }
 
     #[test]
-    fn test_lru_eviction_order() {
-        let config = CacheConfig::new().max_capacity(3).build();
+    fn test_listener_expired_on_cleanup() {
+        let (config, clock) = mock_clock(CacheConfig::new());
+        let (config, log) = recording_config(config);
+        let db = Db::new(config);
+
+        db.set_with_ttl("key1", "value1", Duration::from_millis(1));
+        db.set_with_ttl("key2", "value2", Duration::from_millis(1));
+        db.set("key3", "value3");
+        clock.advance(Duration::from_millis(10));
+
+        assert_eq!(db.cleanup_expired(), 2);
+        assert_eq!(db.cleanup_expired(), 0);
+        assert!(db.get("key1").is_none());
+
+        let mut log = log.lock().unwrap().clone();
+        log.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            log,
+            vec![
+                (
+                    "key1".to_string(),
+                    Bytes::from("value1"),
+                    RemovalCause::Expired
+                ),
+                (
+                    "key2".to_string(),
+                    Bytes::from("value2"),
+                    RemovalCause::Expired
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_batched_cleanup_converges_to_full_sweep() {
+        let (config, log) = recording_config(CacheConfig::new().shards(1));
+        let full = Db::new(CacheConfig::new().shards(1));
+        let batched = Db::new(config);
+        for db in [&full, &batched] {
+            for i in 0..100 {
+                let key = format!("key:{}", i);
+                if i % 3 == 0 {
+                    db.set(key, "v");
+                } else {
+                    db.set_with_ttl(key, "v", Duration::from_secs(10));
+                }
+            }
+        }
+        let later = Instant::now() + Duration::from_secs(60);
+        assert_eq!(full.sweep_expired_at(later), (66, 100));
+
+        // Batches of 7 resume after what the previous one kept, so 15 of
+        // them cover all 100 entries
+        let mut removed = 0;
+        for _ in 0..15 {
+            let (batch_removed, scanned) = batched.sweep_expired_batch_at(later, 7);
+            assert!(scanned <= 7);
+            removed += batch_removed;
+        }
+        assert_eq!(removed, 66);
+        assert_eq!(batched.keys_sorted(), full.keys_sorted());
+        assert_eq!(batched.stats().expirations(), 66);
+        assert_eq!(log.lock().unwrap().len(), 66);
+
+        // The next batch starts over
+        assert_eq!(batched.sweep_expired_batch_at(later, 7), (0, 7));
+        assert_eq!(batched.sweep_expired_batch_at(later, 0), (0, 0));
+    }
+
+    #[test]
+    fn test_listener_evicted() {
+        let (config, log) = recording_config(CacheConfig::new().max_capacity(1));
         let db = Db::new(config);
 
         db.set("key1", "value1");
         db.set("key2", "value2");
-        db.set("key3", "value3");
 
-        // Access key1, making it recently used
-        let _ = db.get("key1");
+        let log = log.lock().unwrap();
+        assert_eq!(
+            *log,
+            vec![(
+                "key1".to_string(),
+                Bytes::from("value1"),
+                RemovalCause::Evicted
+            )]
+        );
+    }
 
-        // Now key2 should be the LRU
-        db.set("key4", "value4");
+    #[test]
+    fn test_listener_replaced_once_per_overwrite() {
+        let (config, log) = recording_config(CacheConfig::new());
+        let db = Db::new(config);
 
-        assert!(db.contains("key1")); // Was accessed, not evicted
-        assert!(!db.contains("key2")); // Was LRU, evicted
-        assert!(db.contains("key3"));
-        assert!(db.contains("key4"));
+        db.set("key1", "v1");
+        db.set("key2", "other");
+        assert!(log.lock().unwrap().is_empty());
+
+        db.set("key1", "v2");
+        db.set("key1", "v3");
+
+        let log = log.lock().unwrap();
+        assert_eq!(
+            *log,
+            vec![
+                (
+                    "key1".to_string(),
+                    Bytes::from("v1"),
+                    RemovalCause::Replaced
+                ),
+                (
+                    "key1".to_string(),
+                    Bytes::from("v2"),
+                    RemovalCause::Replaced
+                ),
+            ]
+        );
+        assert_eq!(db.stats().size(), 2);
     }
 
     #[test]
-    fn test_ttl_expiration() {
-        let db = Db::with_defaults();
+    fn test_quiet_load_skips_replaced_notifications() {
+        let (config, log) = recording_config(CacheConfig::new().max_capacity(2).shards(1));
+        let db = Db::new(config);
+        db.set("a", "1");
+        db.set("b", "2");
 
-        // Set with very short TTL
-        db.set_with_ttl("key1", "value1", Duration::from_millis(1));
+        db.load("a".into(), Bytes::from("3"), None, false);
+        assert!(log.lock().unwrap().is_empty());
+        assert_eq!(db.get("a"), Some(Bytes::from("3")));
 
-        // Should exist immediately
-        assert!(db.contains("key1"));
+        // Evictions are still reported, and a loud load reports replacements
+        db.load("c".into(), Bytes::from("4"), None, false);
+        db.load("a".into(), Bytes::from("5"), None, true);
+        let causes: Vec<_> = log.lock().unwrap().iter().map(|r| r.2).collect();
+        assert_eq!(causes, [RemovalCause::Evicted, RemovalCause::Replaced]);
+    }
 
-        // Wait for expiration
-        std::thread::sleep(Duration::from_millis(10));
+    #[test]
+    fn test_quiet_load_releases_replaced_spill_files() {
+        let dir = spill_dir("quiet-load");
+        let (config, log) = recording_config(CacheConfig::new().spill_over(4, &dir));
+        let db = Db::new(config);
+        db.set("a", "large value");
+        db.load("a".into(), Bytes::from("larger value"), None, false);
 
-        // Should be expired
-        assert!(db.get("key1").is_none());
+        assert!(log.lock().unwrap().is_empty());
+        assert_eq!(db.get("a"), Some(Bytes::from("larger value")));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
     }
 
     #[test]
-    fn test_stats_tracking() {
-        let db = Db::with_defaults();
+    fn test_listener_overwrite_of_expired_entry() {
+        let (config, clock) = mock_clock(CacheConfig::new());
+        let (config, log) = recording_config(config);
+        let db = Db::new(config);
+
+        db.set_with_ttl("key1", "old", Duration::from_millis(1));
+        clock.advance(Duration::from_millis(10));
+        db.set("key1", "new");
+
+        let log = log.lock().unwrap();
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].1, Bytes::from("old"));
+        assert_eq!(log[0].2, RemovalCause::Expired);
+        assert_eq!(db.stats().expirations(), 1);
+    }
+
+    #[test]
+    fn test_listener_deleted() {
+        let (config, log) = recording_config(CacheConfig::new().shards(1));
+        let db = Db::new(config);
+        for key in ["a", "b", "c", "p:1", "p:2", "r", "t", "x"] {
+            db.set(key, key);
+        }
+
+        assert!(db.delete("a"));
+        assert!(!db.delete("a"));
+        assert_eq!(db.delete_many(&["b", "c", "missing"]), 2);
+        assert_eq!(db.delete_prefix("p:"), 2);
+        assert_eq!(db.take("t"), Some(Bytes::from("t")));
+        assert_eq!(db.retain(|key, _| key != "r"), 1);
+        db.transaction(&["x"], |txn| txn.delete("x").map(|_| ()))
+            .unwrap();
+
+        let log = log.lock().unwrap();
+        let keys: Vec<_> = log.iter().map(|(key, _, _)| key.as_str()).collect();
+        assert_eq!(keys, vec!["a", "b", "c", "p:1", "p:2", "t", "r", "x"]);
+        assert!(log
+            .iter()
+            .all(|(key, value, cause)| value == key.as_str() && *cause == RemovalCause::Deleted));
+        assert_eq!(db.stats().deletes(), 8);
+    }
 
+    #[test]
+    fn test_listener_may_reenter_cache() {
+        let db = Arc::new_cyclic(|handle: &std::sync::Weak<Db>| {
+            let handle = handle.clone();
+            Db::new(
+                CacheConfig::new()
+                    .max_capacity(1)
+                    .eviction_listener(Arc::new(move |key, _, _| {
+                        if let Some(db) = handle.upgrade() {
+                            // Would deadlock if invoked while the write lock is held
+                            let _ = db.contains(key);
+                        }
+                    })),
+            )
+        });
         db.set("key1", "value1");
-        let _ = db.get("key1"); // Hit
-        let _ = db.get("nonexistent"); // Miss
+        db.set("key2", "value2");
+        assert!(db.contains("key2"));
+    }
 
-        let stats = db.stats();
-        assert_eq!(stats.hits(), 1);
-        assert_eq!(stats.misses(), 1);
-        assert_eq!(stats.sets(), 1);
+    #[test]
+    fn test_listener_cleared_outside_lock() {
+        let db = Arc::new_cyclic(|handle: &std::sync::Weak<Db>| {
+            let handle = handle.clone();
+            Db::new(
+                CacheConfig::new().eviction_listener(Arc::new(move |key, _, cause| {
+                    assert_eq!(cause, RemovalCause::Cleared);
+                    if let Some(db) = handle.upgrade() {
+                        // Would deadlock if clear still held the write lock
+                        db.set(format!("seen:{}", key), "1");
+                    }
+                })),
+            )
+        });
+        db.set("a", "1");
+        db.set("b", "2");
+        db.clear();
+        assert_eq!(db.keys_sorted(), vec!["seen:a", "seen:b"]);
+
+        let (config, log) = recording_config(CacheConfig::new());
+        let db = Db::new(config);
+        db.set("a", "1");
+        db.clear();
+        assert_eq!(
+            log.lock().unwrap().as_slice(),
+            &[("a".to_string(), Bytes::from("1"), RemovalCause::Cleared)]
+        );
+    }
+
+    #[test]
+    fn test_background_clear_returns_before_drop_finishes() {
+        let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+        let release_rx = std::sync::Mutex::new(release_rx);
+        let (done_tx, done_rx) = std::sync::mpsc::channel::<String>();
+        let config = CacheConfig::new()
+            .background_clear(true)
+            .eviction_listener(Arc::new(move |key, _, _| {
+                let _ = release_rx.lock().unwrap().recv();
+                let _ = done_tx.send(key.to_string());
+            }));
+        let db = Db::new(config);
+        db.set("old", "1");
+
+        db.clear();
+        // The drop thread is parked in the listener; the cache stays usable
+        assert!(db.is_empty());
+        db.set("new", "2");
+        assert_eq!(db.get("new"), Some(Bytes::from("2")));
+
+        release_tx.send(()).unwrap();
+        assert_eq!(done_rx.recv_timeout(Duration::from_secs(5)).unwrap(), "old");
+    }
+
+    #[test]
+    fn test_clear_of_large_map_does_not_block_readers() {
+        let db = Arc::new(Db::new(CacheConfig::new().background_clear(true)));
+        for i in 0..200_000 {
+            db.set(format!("key_{}", i), "value");
+        }
+        db.clear();
+        // The swap already happened, so this does not wait for the drop
+        db.set("after", "1");
+        assert_eq!(db.len(), 1);
+        assert_eq!(db.stats().snapshot().size, 1);
+    }
+
+    #[test]
+    fn test_panicking_listener_leaves_cache_usable() {
+        let config = CacheConfig::new()
+            .max_capacity(2)
+            .eviction_listener(Arc::new(|_, _, _| panic!("listener failure")));
+        let db = Db::new(config);
+
+        db.set("key1", "value1");
+        db.set("key2", "value2");
+        db.set("key3", "value3"); // evicts key1, listener panics
+
+        assert_eq!(db.stats().callback_panics(), 1);
+        assert_eq!(db.len(), 2);
+        assert_eq!(db.stats().size(), 2);
+        assert_eq!(db.stats().evictions(), 1);
+
+        // Lock is not poisoned: reads and writes keep working
+        db.set("key4", "value4");
+        assert_eq!(db.get("key4"), Some(Bytes::from("value4")));
+        assert_eq!(db.stats().size(), 2);
+        assert_eq!(db.stats().callback_panics(), 2);
+    }
+
+    #[test]
+    fn test_poisoned_lock_is_recovered() {
+        let db = Db::new(CacheConfig::new().shards(1));
+        db.set("key1", "value1");
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _entries = db.shards[0].entries.write().unwrap();
+            panic!("panic while holding the lock");
+        }));
+        assert!(result.is_err());
+        assert!(db.shards[0].entries.is_poisoned());
+
+        // Writes still land and reads still find them
+        db.set("key2", "value2");
+        assert_eq!(db.get("key1"), Some(Bytes::from("value1")));
+        assert_eq!(db.get("key2"), Some(Bytes::from("value2")));
+        assert_eq!(
+            db.get_nonblocking("key2"),
+            Some(Some(Bytes::from("value2")))
+        );
+        assert!(db.delete("key1"));
+        assert_eq!(db.len(), 1);
+        assert!(db.stats().lock_failures() >= 5);
     }
 
     #[test]
+    #[cfg(feature = "legacy")]
     #[allow(deprecated)]
     fn test_legacy_write_read() {
         let db = Db::with_defaults();
 
         let arr = vec!["set".to_string(), "key1".to_string(), "value1".to_string()];
-        let result = db.write(&arr);
-        assert!(result.is_ok());
+        assert_eq!(db.write(&arr).unwrap(), "Ok");
+        assert_eq!(db.write(&arr).unwrap(), "r Ok");
 
         let arr = vec!["get".to_string(), "key1".to_string()];
         let result = db.read(&arr);
         assert_eq!(result.unwrap(), Bytes::from("value1"));
+
+        let arr = vec!["get".to_string(), "key2".to_string()];
+        assert!(matches!(db.read(&arr), Err(CacheError::KeyNotFound(key)) if key == "key2"));
     }
 
     #[test]
+    #[cfg(feature = "legacy")]
     #[allow(deprecated)]
     fn test_legacy_read_missing_args() {
         let db = Db::with_defaults();
@@ -484,5 +5299,142 @@ mod tests {
         let arr = vec!["get".to_string()]; // Missing key
         let result = db.read(&arr);
         assert!(result.is_err());
+
+        let arr = vec!["set".to_string(), "key1".to_string()]; // Missing value
+        match db.write(&arr) {
+            Err(CacheError::ParseError(message)) => {
+                assert_eq!(
+                    message,
+                    "write requires at least 3 arguments: command key value"
+                )
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(db.is_empty());
+    }
+
+    #[test]
+    fn test_set_returning_outcome() {
+        let (config, clock) = mock_clock(CacheConfig::new().coalesce_identical_writes(true));
+        let db = Db::new(config);
+        assert_eq!(db.set_returning_outcome("a", "1"), SetOutcome::Inserted);
+        assert_eq!(db.set_returning_outcome("a", "1"), SetOutcome::Unchanged);
+        assert_eq!(db.set_returning_outcome("a", "2"), SetOutcome::Replaced);
+
+        let ttl = Duration::from_millis(1);
+        assert_eq!(
+            db.set_with_ttl_returning_outcome("b", "1", ttl),
+            SetOutcome::Inserted
+        );
+        clock.advance(Duration::from_millis(5));
+        assert_eq!(db.set_returning_outcome("b", "2"), SetOutcome::Inserted);
+        assert_eq!(db.stats().sets(), 4);
+    }
+
+    #[test]
+    fn test_size_limits_reject_sets() {
+        let db = Db::new(CacheConfig::new().max_key_length(4).max_value_size(4));
+        assert!(db.try_set("key", "1234").is_ok());
+        assert!(matches!(
+            db.try_set("key", "12345"),
+            Err(CacheError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            db.try_set_with_ttl("long_key", "1", Duration::from_secs(1)),
+            Err(CacheError::InvalidKey(_))
+        ));
+
+        // The infallible writes drop what is over the limits
+        db.set("key", "12345");
+        assert_eq!(
+            db.set_returning_outcome("long_key", "1"),
+            SetOutcome::Dropped
+        );
+        assert!(!db.set_if_absent(Cow::Borrowed("new"), Bytes::from("12345"), None));
+        assert!(!db.set_nonblocking("new", "12345"));
+        db.set_many(vec![
+            ("a".to_string(), Bytes::from("1"), None),
+            ("b".to_string(), Bytes::from("12345"), None),
+        ]);
+
+        assert_eq!(db.get("key"), Some(Bytes::from("1234")));
+        assert_eq!(db.keys_sorted(), ["a", "key"]);
+        let stats = db.stats().snapshot();
+        assert_eq!((stats.sets, stats.rejected_sets), (2, 7));
+    }
+
+    #[test]
+    fn test_size_limits_cover_read_modify_writes() {
+        let db = Db::new(CacheConfig::new().max_key_length(4).max_value_size(4));
+        db.set("n", "9999");
+        assert!(matches!(
+            db.increment("n", 1),
+            Err(CacheError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            db.increment("long_key", 1),
+            Err(CacheError::InvalidKey(_))
+        ));
+        assert!(matches!(
+            db.incr_window_at("long_key", 1, Duration::from_secs(1), db.now()),
+            Err(CacheError::InvalidKey(_))
+        ));
+        assert_eq!(db.get("n"), Some(Bytes::from("9999")));
+
+        db.set("s", "ab");
+        assert_eq!(db.append("s", Bytes::from("cd")), 4);
+        assert_eq!(db.append("s", Bytes::from("e")), 0);
+        assert_eq!(db.append("long_key", Bytes::from("e")), 0);
+        assert_eq!(db.get("s"), Some(Bytes::from("abcd")));
+
+        assert!(matches!(
+            db.compare_and_swap("s", b"abcd", Bytes::from("12345")),
+            Err(CacheError::InvalidValue(_))
+        ));
+        assert_eq!(db.get("s"), Some(Bytes::from("abcd")));
+
+        // The loader's value is returned but not stored
+        let value = db.get_or_insert_with("g", None, || Bytes::from("12345"));
+        assert_eq!(value, Bytes::from("12345"));
+        assert!(!db.contains("g"));
+        let value = db.get_or_insert_with("long_key", None, || Bytes::from("1"));
+        assert_eq!(value, Bytes::from("1"));
+        assert!(!db.contains("long_key"));
+
+        assert_eq!(db.stats().snapshot().rejected_sets, 8);
+        db.debug_validate().unwrap();
+    }
+
+    #[test]
+    fn test_set_returning_previous_value() {
+        let (config, clock) = mock_clock(CacheConfig::new().coalesce_identical_writes(true));
+        let db = Db::new(config);
+        assert_eq!(db.set_returning("a", "1"), None);
+        assert_eq!(db.set_returning("a", "2"), Some(Bytes::from("1")));
+        // A coalesced write displaced nothing, but the value was there
+        assert_eq!(db.set_returning("a", "2"), Some(Bytes::from("2")));
+
+        let ttl = Duration::from_millis(1);
+        assert_eq!(db.set_with_ttl_returning("b", "1", ttl), None);
+        clock.advance(Duration::from_millis(5));
+        assert_eq!(db.set_with_ttl_returning("b", "2", ttl), None);
+        assert_eq!(db.get("b"), Some(Bytes::from("2")));
+
+        let stats = db.stats().snapshot();
+        assert_eq!((stats.inserts, stats.overwrites), (3, 1));
+        assert_eq!(stats.expirations_lazy, 1);
+    }
+
+    #[test]
+    fn test_set_returning_reads_back_spilled_value() {
+        let dir = spill_dir("set-returning");
+        let db = Db::new(CacheConfig::new().spill_over(8, &dir).build());
+        db.set("large", "a value over the threshold");
+        assert_eq!(
+            db.set_returning("large", "small"),
+            Some(Bytes::from("a value over the threshold"))
+        );
+        assert_eq!(spill_files(&dir), 0);
+        let _ = std::fs::remove_dir_all(&dir);
     }
 }
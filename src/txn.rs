@@ -0,0 +1,179 @@
+//! Multi-key transactions.
+//!
+//! [`Cache::transaction`](crate::Cache::transaction) runs a closure on a
+//! [`TxnView`] of a declared set of keys. The view reads from a snapshot
+//! taken under the write lock and buffers every write; the storage applies
+//! the buffered writes together, still under the same lock, once the
+//! closure returns `Ok`. Other operations therefore see either none or all
+//! of a transaction.
+
+use bytes::Bytes;
+use indexmap::IndexMap;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+use crate::error::{CacheError, CacheResult};
+
+/// A write buffered by a transaction.
+#[derive(Debug)]
+pub(crate) enum Write {
+    /// Store a value with an optional TTL.
+    Set(Bytes, Option<Duration>),
+    /// Remove the key.
+    Delete,
+}
+
+/// Get, set and delete access to the keys declared for a transaction.
+///
+/// Reads see the transaction's own earlier writes. Touching a key that was
+/// not declared fails with [`CacheError::InvalidKey`] and leaves the view
+/// unchanged.
+pub struct TxnView<'a> {
+    /// Stored value of each declared key when the transaction started;
+    /// `None` for a missing or expired key.
+    snapshot: HashMap<&'a str, Option<Bytes>>,
+    /// Last write per key, in the order keys were first written.
+    writes: IndexMap<&'a str, Write>,
+    default_ttl: Option<Duration>,
+    /// Turns a stored value into the value it represents (see `crate::spill`).
+    resolve: &'a dyn Fn(Bytes) -> Option<Bytes>,
+}
+
+impl<'a> TxnView<'a> {
+    pub(crate) fn new(
+        snapshot: HashMap<&'a str, Option<Bytes>>,
+        default_ttl: Option<Duration>,
+        resolve: &'a dyn Fn(Bytes) -> Option<Bytes>,
+    ) -> Self {
+        Self {
+            snapshot,
+            writes: IndexMap::new(),
+            default_ttl,
+            resolve,
+        }
+    }
+
+    /// Current value of `key` within the transaction.
+    pub fn get(&self, key: &str) -> CacheResult<Option<Bytes>> {
+        let key = self.declared(key)?;
+        match self.writes.get(key) {
+            Some(Write::Set(value, _)) => Ok(Some(value.clone())),
+            Some(Write::Delete) => Ok(None),
+            None => Ok(self.snapshot[key].clone().and_then(self.resolve)),
+        }
+    }
+
+    /// Whether `key` currently has a value within the transaction.
+    pub fn contains(&self, key: &str) -> CacheResult<bool> {
+        let key = self.declared(key)?;
+        Ok(match self.writes.get(key) {
+            Some(Write::Set(..)) => true,
+            Some(Write::Delete) => false,
+            None => self.snapshot[key].is_some(),
+        })
+    }
+
+    /// Set `key` with the cache's default TTL.
+    pub fn set(&mut self, key: &str, value: impl Into<Bytes>) -> CacheResult<()> {
+        let ttl = self.default_ttl;
+        self.write(key, Write::Set(value.into(), ttl))
+    }
+
+    /// Set `key` with a specific TTL.
+    pub fn set_with_ttl(
+        &mut self,
+        key: &str,
+        value: impl Into<Bytes>,
+        ttl: Duration,
+    ) -> CacheResult<()> {
+        self.write(key, Write::Set(value.into(), Some(ttl)))
+    }
+
+    /// Delete `key`. Returns `true` if it had a value.
+    pub fn delete(&mut self, key: &str) -> CacheResult<bool> {
+        let existed = self.contains(key)?;
+        self.write(key, Write::Delete)?;
+        Ok(existed)
+    }
+
+    /// The buffered writes, at most one per key.
+    pub(crate) fn into_writes(self) -> IndexMap<&'a str, Write> {
+        self.writes
+    }
+
+    fn write(&mut self, key: &str, write: Write) -> CacheResult<()> {
+        let key = self.declared(key)?;
+        self.writes.insert(key, write);
+        Ok(())
+    }
+
+    /// The declared key equal to `key`.
+    fn declared(&self, key: &str) -> CacheResult<&'a str> {
+        self.snapshot
+            .get_key_value(key)
+            .map(|(declared, _)| *declared)
+            .ok_or_else(|| {
+                CacheError::InvalidKey(format!("'{}' was not declared for this transaction", key))
+            })
+    }
+}
+
+impl fmt::Debug for TxnView<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TxnView")
+            .field("keys", &self.snapshot.keys().collect::<Vec<_>>())
+            .field("writes", &self.writes)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(value: Bytes) -> Option<Bytes> {
+        Some(value)
+    }
+
+    fn view(snapshot: &[(&'static str, Option<&'static str>)]) -> TxnView<'static> {
+        let snapshot = snapshot
+            .iter()
+            .map(|(key, value)| (*key, value.map(Bytes::from)))
+            .collect();
+        TxnView::new(snapshot, None, &identity)
+    }
+
+    #[test]
+    fn test_reads_see_own_writes() {
+        let mut txn = view(&[("a", Some("1")), ("b", None)]);
+        assert_eq!(txn.get("a").unwrap(), Some(Bytes::from("1")));
+        assert!(!txn.contains("b").unwrap());
+
+        txn.set("b", "2").unwrap();
+        assert!(txn.delete("a").unwrap());
+        assert!(!txn.delete("a").unwrap());
+        assert_eq!(txn.get("a").unwrap(), None);
+        assert_eq!(txn.get("b").unwrap(), Some(Bytes::from("2")));
+
+        let writes = txn.into_writes();
+        assert_eq!(writes.len(), 2);
+        assert!(matches!(writes["a"], Write::Delete));
+        assert!(matches!(writes["b"], Write::Set(_, None)));
+    }
+
+    #[test]
+    fn test_undeclared_keys_are_rejected() {
+        let mut txn = view(&[("a", None)]);
+        assert!(matches!(txn.get("other"), Err(CacheError::InvalidKey(_))));
+        assert!(matches!(
+            txn.set("other", "x"),
+            Err(CacheError::InvalidKey(_))
+        ));
+        assert!(matches!(
+            txn.delete("other"),
+            Err(CacheError::InvalidKey(_))
+        ));
+        assert!(txn.into_writes().is_empty());
+    }
+}
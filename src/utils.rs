@@ -1,13 +1,19 @@
 //! Utility functions for buffer parsing and manipulation.
 
-use bytes::{Buf, BytesMut};
+use bytes::BytesMut;
 
 use crate::error::{CacheError, CacheResult};
 
 /// Receives buffer and converts it to vector of strings.
 ///
-/// Splits the buffer on space characters. Note that this simple
-/// implementation doesn't handle quoted strings or escaping.
+/// Splits the buffer on space characters. A word that starts with `"` runs
+/// to the next unescaped `"` and may contain spaces or be empty; inside
+/// it, `\"` stands for `"` and `\\` for `\`. Text right after the closing
+/// quote is part of the same word, and a quote that is never closed runs
+/// to the end of the buffer. Quotes and backslashes elsewhere are ordinary
+/// characters. Each word is decoded as UTF-8; invalid sequences become
+/// `U+FFFD`. [`protocol::quote`](crate::protocol::quote) quotes a word
+/// when needed.
 ///
 /// # Arguments
 /// * `buf` - The buffer to parse. Will be consumed.
@@ -16,40 +22,53 @@ use crate::error::{CacheError, CacheResult};
 /// A vector of strings, split by spaces.
 ///
 /// # Example
-/// ```ignore
+/// ```
+/// use bytes::BytesMut;
+/// use in_memory_cache::buffer_to_array;
+///
 /// let mut buf = BytesMut::from("set key value");
 /// let parts = buffer_to_array(&mut buf);
 /// assert_eq!(parts, vec!["set", "key", "value"]);
+///
+/// let mut buf = BytesMut::from(r#"set greeting "say \"hello world\"""#);
+/// let parts = buffer_to_array(&mut buf);
+/// assert_eq!(parts, vec!["set", "greeting", r#"say "hello world""#]);
 /// ```
 pub fn buffer_to_array(buf: &mut BytesMut) -> Vec<String> {
-    let mut vec = vec![];
-    let length = buf.len();
+    let data = buf.split();
+    let mut rest = &data[..];
+    let mut words = Vec::new();
 
-    if length == 0 {
-        return vec;
-    }
-
-    let mut word = String::new();
+    loop {
+        while let [b' ', tail @ ..] = rest {
+            rest = tail;
+        }
+        if rest.is_empty() {
+            return words;
+        }
 
-    for i in 0..length {
-        match buf.get_u8() {
-            // Space indicates end of word
-            b' ' => {
-                if !word.is_empty() {
-                    vec.push(word);
-                    word = String::new();
-                }
-            }
-            // Collect character into current word
-            other => {
-                word.push(other as char);
-                if i == length - 1 && !word.is_empty() {
-                    vec.push(word.clone());
+        let mut word = Vec::new();
+        if let [b'"', tail @ ..] = rest {
+            rest = tail;
+            loop {
+                match rest {
+                    [] => break,
+                    [b'"', tail @ ..] => {
+                        rest = tail;
+                        break;
+                    }
+                    [b'\\', escaped @ (b'"' | b'\\'), tail @ ..] | [escaped, tail @ ..] => {
+                        word.push(*escaped);
+                        rest = tail;
+                    }
                 }
             }
         }
+        let end = rest.iter().position(|&b| b == b' ').unwrap_or(rest.len());
+        word.extend_from_slice(&rest[..end]);
+        rest = &rest[end..];
+        words.push(String::from_utf8_lossy(&word).into_owned());
     }
-    vec
 }
 
 /// Parse a buffer into command parts with validation.
@@ -104,6 +123,94 @@ mod tests {
         assert_eq!(result, vec!["set", "key", "value"]);
     }
 
+    #[test]
+    fn test_buffer_to_array_multibyte_utf8() {
+        let mut buf = BytesMut::from("set héllo 日本");
+        let result = buffer_to_array(&mut buf);
+        assert_eq!(result, vec!["set", "héllo", "日本"]);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_buffer_to_array_quoted_words() {
+        let mut buf = BytesMut::from(r#"set greeting "hello world""#);
+        assert_eq!(
+            buffer_to_array(&mut buf),
+            vec!["set", "greeting", "hello world"]
+        );
+
+        // Embedded quotes and backslashes
+        let mut buf = BytesMut::from(r#"set k "a \"b\" c:\\d""#);
+        assert_eq!(buffer_to_array(&mut buf), vec!["set", "k", r#"a "b" c:\d"#]);
+
+        // Leading and trailing spaces inside quotes are kept
+        let mut buf = BytesMut::from(r#"set k "  padded  "   "#);
+        assert_eq!(buffer_to_array(&mut buf), vec!["set", "k", "  padded  "]);
+
+        // Empty values
+        let mut buf = BytesMut::from(r#"set k """#);
+        assert_eq!(buffer_to_array(&mut buf), vec!["set", "k", ""]);
+        let mut buf = BytesMut::from(r#""" "" x"#);
+        assert_eq!(buffer_to_array(&mut buf), vec!["", "", "x"]);
+    }
+
+    #[test]
+    fn test_buffer_to_array_quote_edge_cases() {
+        // Quotes and backslashes inside unquoted words are literal
+        let mut buf = BytesMut::from(r#"set say"what c:\dir"#);
+        assert_eq!(
+            buffer_to_array(&mut buf),
+            vec!["set", r#"say"what"#, r"c:\dir"]
+        );
+
+        // Text after the closing quote continues the word
+        let mut buf = BytesMut::from(r#""a b"c d"#);
+        assert_eq!(buffer_to_array(&mut buf), vec!["a bc", "d"]);
+
+        // An unterminated quote runs to the end
+        let mut buf = BytesMut::from(r#"set k "open ended \"#);
+        assert_eq!(buffer_to_array(&mut buf), vec!["set", "k", r"open ended \"]);
+
+        // Other escapes are kept as written
+        let mut buf = BytesMut::from(r#""\n""#);
+        assert_eq!(buffer_to_array(&mut buf), vec![r"\n"]);
+    }
+
+    #[test]
+    fn test_quote_round_trips() {
+        let words = [
+            "plain",
+            "hello world",
+            "",
+            "trailing ",
+            r#"say "hi""#,
+            r#""starts quoted"#,
+            r"back\slash",
+            r#"mixed \" both"#,
+            "日本 語",
+        ];
+        let line: Vec<String> = words
+            .iter()
+            .map(|word| crate::protocol::quote(word).into_owned())
+            .collect();
+        let mut buf = BytesMut::from(line.join(" ").as_str());
+        assert_eq!(buffer_to_array(&mut buf), words);
+        assert_eq!(crate::protocol::quote("plain"), "plain");
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_legacy_write_reads_quoted_values() {
+        let db = crate::storage::Db::new(crate::CacheConfig::default());
+        let mut buf = BytesMut::from(r#"set greeting "hello world""#);
+        assert_eq!(db.write(&buffer_to_array(&mut buf)).unwrap(), "Ok");
+        assert_eq!(db.get("greeting"), Some("hello world".into()));
+
+        let mut buf = BytesMut::from(r#"set greeting """#);
+        assert_eq!(db.write(&buffer_to_array(&mut buf)).unwrap(), "r Ok");
+        assert_eq!(db.get("greeting"), Some("".into()));
+    }
+
     #[test]
     fn test_parse_command_empty() {
         let mut buf = BytesMut::new();
@@ -0,0 +1,117 @@
+//! Build and protocol version information.
+//!
+//! Clients and servers from different builds may disagree on the wire
+//! protocol; [`version`] gives both sides something concrete to log and
+//! compare. The server reports it in `info server` and the `hello` reply,
+//! and both binaries print it for `--version`.
+
+use std::fmt;
+
+/// Version of the client/server wire protocol, bumped whenever a change
+/// would break an older peer.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// `--version` text of the binaries. `concat!` only takes literals, so the
+/// protocol version is repeated here; a test keeps the two in sync.
+#[cfg(feature = "cli")]
+pub(crate) const LONG_VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("IN_MEMORY_CACHE_GIT_HASH"),
+    ", protocol 2)"
+);
+
+/// Cargo features this build of the library was compiled with.
+const FEATURES: &[&str] = &[
+    #[cfg(feature = "legacy")]
+    "legacy",
+    #[cfg(feature = "cli")]
+    "cli",
+    #[cfg(feature = "server")]
+    "server",
+    #[cfg(feature = "test-util")]
+    "test-util",
+    #[cfg(feature = "tools")]
+    "tools",
+];
+
+/// What [`version`] reports about this build.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionInfo {
+    /// The crate version from `Cargo.toml`.
+    pub crate_version: &'static str,
+    /// Abbreviated git commit hash of the build, or `"unknown"` when built
+    /// outside a git checkout.
+    pub git_hash: &'static str,
+    /// Enabled cargo features.
+    pub features: &'static [&'static str],
+    /// See [`PROTOCOL_VERSION`].
+    pub protocol_version: u32,
+}
+
+/// Version information of this build.
+///
+/// # Example
+/// ```
+/// let version = in_memory_cache::version();
+/// assert_eq!(version.crate_version, env!("CARGO_PKG_VERSION"));
+/// println!("{}", version);
+/// ```
+pub fn version() -> VersionInfo {
+    VersionInfo {
+        crate_version: env!("CARGO_PKG_VERSION"),
+        git_hash: env!("IN_MEMORY_CACHE_GIT_HASH"),
+        features: FEATURES,
+        protocol_version: PROTOCOL_VERSION,
+    }
+}
+
+impl fmt::Display for VersionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}, protocol {})",
+            self.crate_version, self.git_hash, self.protocol_version
+        )?;
+        if !self.features.is_empty() {
+            write!(f, " features: {}", self.features.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_info() {
+        let version = version();
+        assert!(!version.git_hash.is_empty());
+        assert_eq!(version.protocol_version, PROTOCOL_VERSION);
+        assert_eq!(
+            version.features.contains(&"server"),
+            cfg!(feature = "server")
+        );
+
+        let text = version.to_string();
+        assert!(
+            text.starts_with(&format!("{} (", env!("CARGO_PKG_VERSION"))),
+            "{}",
+            text
+        );
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn test_long_version_matches() {
+        let version = version();
+        assert_eq!(
+            LONG_VERSION,
+            format!(
+                "{} ({}, protocol {})",
+                version.crate_version, version.git_hash, version.protocol_version
+            )
+        );
+    }
+}
@@ -0,0 +1,297 @@
+//! Several requests over one connection to a real server.
+
+#![cfg(feature = "server")]
+
+use bytes::{Bytes, BytesMut};
+use clap::Parser;
+use in_memory_cache::protocol::{decode_multi_bulk, decode_reply, read_reply};
+use in_memory_cache::server::{serve, ResolvedServerConfig, ServerState};
+use in_memory_cache::{Cache, ServerCli};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Start a server with the given flags on an ephemeral port.
+async fn start(args: &[&str]) -> SocketAddr {
+    let cli = ServerCli::parse_from(std::iter::once("server").chain(args.iter().copied()));
+    let config = ResolvedServerConfig::resolve(&cli, |_| None).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let cache = Arc::new(Cache::new(config.cache_config()));
+    tokio::spawn(serve(listener, cache, Arc::new(ServerState::new(config))));
+    addr
+}
+
+/// Send one request line and read its reply.
+async fn call(stream: &mut TcpStream, pending: &mut BytesMut, request: &str) -> String {
+    stream
+        .write_all(format!("{}\n", request).as_bytes())
+        .await
+        .unwrap();
+    let reply = read_reply(stream, pending).await.unwrap();
+    String::from_utf8(reply.to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn test_sequential_commands_share_a_connection() {
+    let addr = start(&[]).await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let mut pending = BytesMut::new();
+
+    assert_eq!(call(&mut stream, &mut pending, "set a 1").await, "Ok");
+    assert_eq!(call(&mut stream, &mut pending, "set a 2").await, "r Ok");
+    assert_eq!(call(&mut stream, &mut pending, "get a").await, "2");
+    assert_eq!(call(&mut stream, &mut pending, "get missing").await, "");
+    // Replies spanning several lines are framed, so the next one lines up
+    let info = call(&mut stream, &mut pending, "info").await;
+    assert!(info.starts_with("# Server\n"), "{}", info);
+    assert!(info.contains("\n\n# Memory\n"), "{}", info);
+    let mget = call(&mut stream, &mut pending, "mget a missing").await;
+    assert_eq!(
+        decode_multi_bulk(mget.as_bytes()).unwrap(),
+        [Some("2".into()), None]
+    );
+    assert_eq!(call(&mut stream, &mut pending, "delete a").await, "Ok");
+    assert_eq!(
+        call(&mut stream, &mut pending, "bogus").await,
+        "ERR UNKNOWN unknown command 'bogus'"
+    );
+    assert_eq!(call(&mut stream, &mut pending, "ping").await, "PONG");
+    assert!(pending.is_empty());
+}
+
+#[tokio::test]
+async fn test_pipelined_requests_are_answered_in_order() {
+    let addr = start(&[]).await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"set k v\r\nget k\nping\n*id=abc get k\n")
+        .await
+        .unwrap();
+
+    let mut pending = BytesMut::new();
+    let mut replies = Vec::new();
+    for _ in 0..4 {
+        replies.push(read_reply(&mut stream, &mut pending).await.unwrap());
+    }
+    assert_eq!(replies, ["Ok", "v", "PONG", "*id=abc v"]);
+}
+
+/// A log writer appending to a shared buffer.
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl std::io::Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_trace_id_is_logged_in_the_request_span() {
+    // The test runtime is single-threaded, so the server's tasks log
+    // through this thread's subscriber
+    let logs = Captured::default();
+    let writer = logs.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(move || writer.clone())
+        .with_ansi(false)
+        .finish();
+    let _default = tracing::subscriber::set_default(subscriber);
+
+    let addr = start(&[]).await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let mut pending = BytesMut::new();
+    assert_eq!(
+        call(&mut stream, &mut pending, "*id=req-7f3a set k v").await,
+        "*id=req-7f3a Ok"
+    );
+    assert_eq!(call(&mut stream, &mut pending, "get k").await, "v");
+
+    let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+    let traced: Vec<&str> = logs
+        .lines()
+        .filter(|l| l.contains("traced request"))
+        .collect();
+    assert_eq!(traced.len(), 1, "{}", logs);
+    assert!(traced[0].contains("trace_id=\"req-7f3a\""), "{}", logs);
+    assert!(traced[0].contains("command=set"), "{}", logs);
+}
+
+#[tokio::test]
+async fn test_last_request_without_newline_is_answered_on_close() {
+    let addr = start(&[]).await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"set k v\nget k").await.unwrap();
+    stream.shutdown().await.unwrap();
+
+    let mut replies = Vec::new();
+    stream.read_to_end(&mut replies).await.unwrap();
+    assert_eq!(replies, b"Ok\nv\n");
+}
+
+#[tokio::test]
+async fn test_idle_connection_is_closed() {
+    let addr = start(&["--idle-timeout", "1"]).await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let mut pending = BytesMut::new();
+    assert_eq!(call(&mut stream, &mut pending, "ping").await, "PONG");
+
+    // The server hangs up once the connection has been quiet for a second
+    let mut rest = Vec::new();
+    let read = tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut rest))
+        .await
+        .expect("connection left open");
+    assert_eq!(read.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_request_written_byte_by_byte() {
+    let addr = start(&[]).await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.set_nodelay(true).unwrap();
+    for &byte in b"set slow value\nget slow\n".iter() {
+        stream.write_all(&[byte]).await.unwrap();
+        stream.flush().await.unwrap();
+        tokio::task::yield_now().await;
+    }
+
+    let mut pending = BytesMut::new();
+    assert_eq!(read_reply(&mut stream, &mut pending).await.unwrap(), "Ok");
+    assert_eq!(
+        read_reply(&mut stream, &mut pending).await.unwrap(),
+        "value"
+    );
+}
+
+#[tokio::test]
+async fn test_two_commands_in_one_write() {
+    let addr = start(&[]).await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"set a 1\nset b 2\n").await.unwrap();
+
+    let mut pending = BytesMut::new();
+    assert_eq!(read_reply(&mut stream, &mut pending).await.unwrap(), "Ok");
+    assert_eq!(read_reply(&mut stream, &mut pending).await.unwrap(), "Ok");
+    assert_eq!(call(&mut stream, &mut pending, "get b").await, "2");
+}
+
+#[tokio::test]
+async fn test_large_set_spanning_many_segments() {
+    let addr = start(&[]).await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let value = "v".repeat(64 * 1024);
+    let request = format!("set big {}\n", value);
+    for chunk in request.as_bytes().chunks(1500) {
+        stream.write_all(chunk).await.unwrap();
+        stream.flush().await.unwrap();
+    }
+
+    let mut pending = BytesMut::new();
+    assert_eq!(read_reply(&mut stream, &mut pending).await.unwrap(), "Ok");
+    assert_eq!(call(&mut stream, &mut pending, "get big").await, value);
+}
+
+#[tokio::test]
+async fn test_oversized_frame_is_rejected() {
+    let addr = start(&["--max-frame-size", "32"]).await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let mut pending = BytesMut::new();
+
+    let reply = call(
+        &mut stream,
+        &mut pending,
+        &format!("set k {}", "x".repeat(64)),
+    )
+    .await;
+    assert_eq!(reply, "ERR TOOLARGE frame too large (max: 32 bytes)");
+    // The connection stays usable and nothing was stored
+    assert_eq!(call(&mut stream, &mut pending, "get k").await, "");
+
+    // A request that never ends is cut off rather than buffered
+    for _ in 0..16 {
+        stream.write_all(&[b'x'; 1024]).await.unwrap();
+    }
+    assert_eq!(
+        read_reply(&mut stream, &mut pending).await.unwrap(),
+        "ERR TOOLARGE frame too large (max: 32 bytes)"
+    );
+    // Its end is dropped with the rest of it
+    assert_eq!(call(&mut stream, &mut pending, "xxx\nping").await, "PONG");
+}
+
+#[tokio::test]
+async fn test_quoted_values() {
+    let addr = start(&[]).await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let mut pending = BytesMut::new();
+
+    let cases = [
+        (r#"set greeting "hello world""#, "hello world"),
+        (r#"set greeting "say \"hi\" to C:\\""#, r#"say "hi" to C:\"#),
+        (r#"set greeting "trailing  "  "#, "trailing  "),
+        (r#"set greeting "" ex 60"#, ""),
+    ];
+    for (request, stored) in cases {
+        call(&mut stream, &mut pending, request).await;
+        assert_eq!(
+            call(&mut stream, &mut pending, "get greeting").await,
+            stored
+        );
+    }
+
+    let mget = call(
+        &mut stream,
+        &mut pending,
+        r#"mget "greeting" "no such key""#,
+    )
+    .await;
+    assert_eq!(
+        decode_multi_bulk(mget.as_bytes()).unwrap(),
+        [Some(Bytes::new()), None]
+    );
+}
+
+#[tokio::test]
+async fn test_negotiated_lz4_compresses_large_replies() {
+    let addr = start(&[]).await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let mut pending = BytesMut::new();
+
+    let value = "row-".repeat(4096);
+    call(&mut stream, &mut pending, &format!("set big {}", value)).await;
+    // Nothing is compressed before a hello asks for it
+    assert_eq!(call(&mut stream, &mut pending, "get big").await, value);
+
+    let hello = call(&mut stream, &mut pending, "hello compression=lz4").await;
+    assert!(hello.starts_with("hello compression=lz4 "), "{}", hello);
+
+    // The frame is marked and much smaller, and read_reply undoes it
+    stream.write_all(b"get big\nping\n").await.unwrap();
+    let mut raw = BytesMut::new();
+    while decode_reply(&raw).unwrap().is_none() {
+        stream.read_buf(&mut raw).await.unwrap();
+    }
+    let header_end = raw.iter().position(|&b| b == b'\n').unwrap();
+    let header = std::str::from_utf8(&raw[..header_end]).unwrap();
+    assert!(
+        header.ends_with(&format!(" lz4={}", value.len())),
+        "{}",
+        header
+    );
+    let (reply, used) = decode_reply(&raw).unwrap().unwrap();
+    assert!(used < value.len() / 10, "{}", used);
+    assert_eq!(reply, value);
+
+    // Short replies stay plain on the same connection
+    let mut pending = raw.split_off(used);
+    assert_eq!(read_reply(&mut stream, &mut pending).await.unwrap(), "PONG");
+    assert_eq!(call(&mut stream, &mut pending, "get big").await, value);
+}
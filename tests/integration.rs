@@ -1,6 +1,7 @@
 //! Integration tests for the cache library.
 
-use in_memory_cache::{Cache, CacheConfig};
+use in_memory_cache::{Cache, CacheConfig, ScanCursor};
+use std::collections::HashSet;
 use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
@@ -224,3 +225,133 @@ fn test_binary_values() {
     assert!(retrieved.is_some());
     assert_eq!(&retrieved.unwrap()[..], &binary_data[..]);
 }
+
+#[test]
+fn test_size_stat_survives_concurrent_expiration() {
+    let cache = Cache::new(CacheConfig::new().max_capacity(2_000).build());
+    let keys: Arc<Vec<String>> = Arc::new((0..5_000).map(|i| format!("key_{}", i)).collect());
+
+    let writer = {
+        let cache = cache.clone();
+        let keys = Arc::clone(&keys);
+        thread::spawn(move || {
+            for _ in 0..3 {
+                for key in keys.iter() {
+                    cache.set_with_ttl(key.clone(), "v", Duration::from_millis(1));
+                }
+            }
+        })
+    };
+
+    let readers: Vec<_> = (0..4)
+        .map(|t| {
+            let cache = cache.clone();
+            let keys = Arc::clone(&keys);
+            thread::spawn(move || {
+                for _ in 0..3 {
+                    for key in keys.iter().skip(t).step_by(2) {
+                        let _ = cache.get(key);
+                        let _ = cache.contains(key);
+                    }
+                }
+            })
+        })
+        .collect();
+
+    let cleaner = {
+        let cache = cache.clone();
+        thread::spawn(move || {
+            for _ in 0..200 {
+                cache.cleanup_expired();
+                thread::sleep(Duration::from_micros(200));
+            }
+        })
+    };
+
+    writer.join().unwrap();
+    for reader in readers {
+        reader.join().unwrap();
+    }
+    cleaner.join().unwrap();
+
+    cache.debug_validate().unwrap();
+    thread::sleep(Duration::from_millis(5));
+    cache.cleanup_expired();
+    cache.debug_validate().unwrap();
+    assert_eq!(cache.len(), 0);
+    assert_eq!(cache.stats().size, 0);
+}
+
+#[test]
+fn test_scan_keys_during_concurrent_writes() {
+    let cache = Cache::new(CacheConfig::new().max_capacity(5_000));
+    for i in 0..1_000 {
+        cache.set(format!("kept:{:04}", i), "v");
+    }
+
+    let writer = {
+        let cache = cache.clone();
+        thread::spawn(move || {
+            for i in 0..6_000 {
+                let key = format!("temp:{:05}", i);
+                cache.set(key.clone(), "v");
+                if i % 2 == 0 {
+                    cache.delete(&key);
+                }
+                cache.get(&format!("kept:{:04}", i % 1_000));
+            }
+        })
+    };
+
+    let mut seen = HashSet::new();
+    let mut cursor = ScanCursor::start();
+    while !cursor.is_done() {
+        let (keys, next) = cache.scan_keys(cursor, 50);
+        for key in keys {
+            assert!(seen.insert(key), "a key was returned twice");
+        }
+        // Through the string form, as a paging client would
+        cursor = next.to_string().parse().unwrap();
+    }
+    writer.join().unwrap();
+
+    // Nothing is evicted at this size, so every kept key must be covered
+    let kept = seen.iter().filter(|key| key.starts_with("kept:")).count();
+    assert_eq!(kept, 1_000);
+}
+
+#[test]
+fn test_snapshots_of_a_large_cache() {
+    const ENTRIES: usize = 1_000_000;
+    let cache = Cache::default();
+    cache.set_many((0..ENTRIES).map(|i| (format!("key:{}", i), "v".into())));
+    cache.set_with_ttl("expired", "v", Duration::from_millis(1));
+    thread::sleep(Duration::from_millis(10));
+
+    let keys = cache.keys();
+    let items = cache.iter();
+    assert_eq!((keys.len(), items.len()), (ENTRIES, ENTRIES));
+    assert!(!keys.iter().any(|key| key == "expired"));
+
+    // The lock is not held while the snapshot is processed: a writer
+    // finishes in the middle of it, and its writes are not in the snapshot
+    let mut writer = Some({
+        let cache = cache.clone();
+        thread::spawn(move || {
+            for i in 0..1_000 {
+                cache.set(format!("late:{}", i), "v");
+            }
+        })
+    });
+    let mut len_mid_way = 0;
+    for (i, (key, value)) in items.iter().enumerate() {
+        if i == ENTRIES / 2 {
+            writer.take().unwrap().join().unwrap();
+            len_mid_way = cache.len();
+        }
+        assert_eq!(value.as_ref(), b"v", "{}", key);
+    }
+    assert!(len_mid_way >= ENTRIES + 1_000);
+    assert_eq!(items.len(), ENTRIES);
+    assert_eq!(cache.stats().hits, 0);
+}
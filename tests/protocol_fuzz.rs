@@ -0,0 +1,176 @@
+//! Property-based fuzzing of the wire parsers.
+//!
+//! These feed arbitrary and adversarial bytes into everything that parses
+//! network input and check that parsing never panics, never allocates far
+//! beyond the input size, and that valid input round-trips through the
+//! encoder. Run with a larger budget via `PROPTEST_CASES=100000`.
+
+#![cfg(feature = "legacy")]
+
+use bytes::{Bytes, BytesMut};
+use in_memory_cache::protocol::{decode_multi_bulk, encode_multi_bulk, quote, RequestFramer};
+use in_memory_cache::{buffer_to_array, Command};
+use proptest::prelude::*;
+
+/// Hand-picked inputs that random generation is unlikely to hit.
+fn corpus() -> Vec<Vec<u8>> {
+    vec![
+        b"".to_vec(),
+        b" ".to_vec(),
+        b"get".to_vec(),
+        b"set key value".to_vec(),
+        br#"set k "a \"b\" \\ c" "" "unterminated \"#.to_vec(),
+        "set clé 値".as_bytes().to_vec(),
+        // Truncated multibyte UTF-8 sequences
+        vec![b's', b'e', b't', b' ', 0xE6, 0x97],
+        vec![0xF0, 0x9F, 0x98],
+        vec![0xFF, 0xFE, b' ', 0xC0, 0x80],
+        // Oversized frames: huge counts and lengths with little data behind them
+        b"*9999999999999\r\n".to_vec(),
+        b"*1\r\n$9223372036854775807\r\nab\r\n".to_vec(),
+        b"*2\r\n$-2\r\n".to_vec(),
+        b"*-1\r\n".to_vec(),
+        vec![b'a'; 1 << 20],
+        [b"set k ".as_slice(), &vec![b'x'; 1 << 20]].concat(),
+    ]
+}
+
+fn check_buffer_to_array(input: &[u8]) {
+    let parts = buffer_to_array(&mut BytesMut::from(input));
+
+    let total: usize = parts.iter().map(String::len).sum();
+    // Lossy decoding expands each invalid byte to at most 3 bytes
+    assert!(total <= input.len() * 3);
+    // Only quoted words may be empty or contain spaces
+    if !input.contains(&b'"') {
+        for part in &parts {
+            assert!(!part.is_empty());
+            assert!(!part.contains(' '));
+        }
+    }
+    if let Some(first) = parts.first() {
+        let _ = Command::get(first);
+    }
+}
+
+fn check_decode_multi_bulk(input: &[u8]) {
+    if let Ok(values) = decode_multi_bulk(input) {
+        let payload: usize = values.iter().flatten().map(Bytes::len).sum();
+        assert!(payload <= input.len());
+        assert_eq!(&encode_multi_bulk(&values)[..], input);
+    }
+}
+
+/// Feed `input` to a framer in `chunk`-sized reads; every frame handed out
+/// respects the limit and the buffer never grows far past it.
+fn check_framer(input: &[u8], chunk: usize, max_frame: usize) {
+    let mut framer = RequestFramer::new(max_frame);
+    for piece in input.chunks(chunk.max(1)) {
+        framer.buffer().extend_from_slice(piece);
+        while let Some(frame) = framer.next_frame() {
+            if let Ok(line) = frame {
+                assert!(line.len() <= max_frame);
+                assert!(!line.contains(&b'\n'));
+            }
+        }
+        assert!(framer.buffer().len() <= max_frame + 1 + chunk.max(1));
+    }
+    if let Some(Ok(line)) = framer.finish() {
+        assert!(line.len() <= max_frame);
+    }
+}
+
+#[test]
+fn corpus_does_not_panic() {
+    for input in corpus() {
+        check_buffer_to_array(&input);
+        check_decode_multi_bulk(&input);
+        check_framer(&input, 4096, 1024);
+    }
+}
+
+#[test]
+fn oversized_counts_are_rejected() {
+    assert!(decode_multi_bulk(b"*9999999999999\r\n").is_err());
+    assert!(decode_multi_bulk(b"*1\r\n$9223372036854775807\r\nab\r\n").is_err());
+}
+
+proptest! {
+    #[test]
+    fn buffer_to_array_never_panics(input in proptest::collection::vec(any::<u8>(), 0..512)) {
+        check_buffer_to_array(&input);
+    }
+
+    #[test]
+    fn buffer_to_array_round_trips_utf8(
+        words in proptest::collection::vec("[^ \"]{1,16}", 0..8),
+        gaps in proptest::collection::vec(1usize..4, 8),
+    ) {
+        let mut line = String::new();
+        for (word, gap) in words.iter().zip(&gaps) {
+            line.push_str(word);
+            line.push_str(&" ".repeat(*gap));
+        }
+        prop_assert_eq!(buffer_to_array(&mut BytesMut::from(line.as_str())), words);
+    }
+
+    #[test]
+    fn quoted_words_round_trip(words in proptest::collection::vec("[^\r\n]{0,16}", 0..8)) {
+        let line = words.iter().map(|word| quote(word)).collect::<Vec<_>>().join(" ");
+        prop_assert_eq!(buffer_to_array(&mut BytesMut::from(line.as_str())), words);
+    }
+
+    #[test]
+    fn framer_never_panics(
+        input in proptest::collection::vec(any::<u8>(), 0..512),
+        chunk in 1usize..64,
+        max_frame in 1usize..64,
+    ) {
+        check_framer(&input, chunk, max_frame);
+    }
+
+    #[test]
+    fn framer_splits_lines_however_they_arrive(
+        lines in proptest::collection::vec("[^\r\n]{0,16}", 0..8),
+        chunk in 1usize..32,
+    ) {
+        let input: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+        let mut framer = RequestFramer::new(16 * 4);
+        let mut seen = Vec::new();
+        for piece in input.as_bytes().chunks(chunk) {
+            framer.buffer().extend_from_slice(piece);
+            while let Some(frame) = framer.next_frame() {
+                seen.push(String::from_utf8(frame.unwrap().to_vec()).unwrap());
+            }
+        }
+        prop_assert!(framer.finish().is_none());
+        prop_assert_eq!(seen, lines);
+    }
+
+    #[test]
+    fn decode_multi_bulk_never_panics(input in proptest::collection::vec(any::<u8>(), 0..512)) {
+        check_decode_multi_bulk(&input);
+    }
+
+    #[test]
+    fn decode_multi_bulk_handles_mutated_frames(
+        values in proptest::collection::vec(
+            proptest::option::of(proptest::collection::vec(any::<u8>(), 0..32)),
+            0..8,
+        ),
+        flip in any::<(usize, u8)>(),
+        cut in any::<usize>(),
+    ) {
+        let values: Vec<Option<Bytes>> = values.into_iter().map(|v| v.map(Bytes::from)).collect();
+        let encoded = encode_multi_bulk(&values);
+        prop_assert_eq!(decode_multi_bulk(&encoded).unwrap(), values);
+
+        let mut mutated = encoded.to_vec();
+        let idx = flip.0 % mutated.len();
+        mutated[idx] ^= flip.1 | 1;
+        check_decode_multi_bulk(&mutated);
+
+        let truncated = &encoded[..cut % encoded.len()];
+        prop_assert!(decode_multi_bulk(truncated).is_err());
+    }
+}
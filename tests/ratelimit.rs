@@ -0,0 +1,77 @@
+//! Server rate limiting with several clients sharing one server state.
+//!
+//! Clients are told apart by IP address, as the server binary does with the
+//! peer address of each connection.
+
+#![cfg(feature = "server")]
+
+use clap::Parser;
+use in_memory_cache::server::{ResolvedServerConfig, ServerState};
+use in_memory_cache::ServerCli;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+fn state(args: &[&str]) -> Arc<ServerState> {
+    let cli = ServerCli::parse_from(std::iter::once("server").chain(args.iter().copied()));
+    let config = ResolvedServerConfig::resolve(&cli, |_| None).unwrap();
+    Arc::new(ServerState::new(config))
+}
+
+#[test]
+fn test_fast_client_throttled_slow_client_unaffected() {
+    let state = state(&["--max-ops-per-conn", "20"]);
+    let fast: IpAddr = "10.0.0.1".parse().unwrap();
+    let slow: IpAddr = "10.0.0.2".parse().unwrap();
+
+    // A client stuck in a retry loop
+    let hammer = {
+        let state = Arc::clone(&state);
+        thread::spawn(move || {
+            let mut throttled = 0;
+            for _ in 0..2000 {
+                if state.admit(fast).is_err() {
+                    throttled += 1;
+                }
+            }
+            throttled
+        })
+    };
+
+    // A well-behaved client at a quarter of the limit
+    let mut slow_throttled = 0;
+    for _ in 0..8 {
+        if state.admit(slow).is_err() {
+            slow_throttled += 1;
+        }
+        thread::sleep(Duration::from_millis(200));
+    }
+
+    let fast_throttled = hammer.join().unwrap();
+    assert!(
+        fast_throttled > 1000,
+        "fast client throttled {} times",
+        fast_throttled
+    );
+    assert_eq!(slow_throttled, 0);
+    assert_eq!(state.throttled(), fast_throttled);
+}
+
+#[test]
+fn test_global_limit_is_shared() {
+    let state = state(&["--max-ops-global", "10"]);
+    let admitted = (0..40)
+        .map(|i| IpAddr::from([10, 0, 0, i as u8]))
+        .filter(|&client| state.admit(client).is_ok())
+        .count();
+    // One burst's worth, plus whatever refilled during the loop
+    assert!((10..=11).contains(&admitted), "admitted {}", admitted);
+    assert_eq!(state.throttled(), 40 - admitted as u64);
+
+    // The advertised delay is enough to get through again
+    let wait = state.admit("10.0.1.1".parse().unwrap()).unwrap_err();
+    assert!(wait <= Duration::from_millis(100));
+    thread::sleep(wait);
+    assert!(state.admit("10.0.1.1".parse().unwrap()).is_ok());
+}
@@ -0,0 +1,50 @@
+//! The `server --smoke-test` script against a real server and a broken one.
+
+#![cfg(feature = "server")]
+
+use clap::Parser;
+use in_memory_cache::server::{run_smoke_script, smoke_script, smoke_test, ResolvedServerConfig};
+use in_memory_cache::ServerCli;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+fn config() -> ResolvedServerConfig {
+    ResolvedServerConfig::resolve(&ServerCli::parse_from(["server"]), |_| None).unwrap()
+}
+
+#[tokio::test]
+async fn test_smoke_test_passes_against_server() {
+    let report = smoke_test(config()).await.unwrap();
+    assert!(report.passed(), "{}", report);
+    assert_eq!(report.results.len(), smoke_script().len());
+}
+
+#[tokio::test]
+async fn test_smoke_test_reports_wrong_replies() {
+    // A server that answers everything with PONG
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 256];
+            let _ = socket.read(&mut buf).await;
+            let _ = socket.write_all(b"PONG").await;
+        }
+    });
+
+    let report = run_smoke_script(addr).await;
+    server.abort();
+
+    assert!(!report.passed());
+    let failed: Vec<_> = report
+        .results
+        .iter()
+        .filter(|result| result.outcome.is_err())
+        .map(|result| result.name)
+        .collect();
+    assert_eq!(failed, ["set", "get", "ttl", "mget", "delete", "stats"]);
+    assert!(report
+        .to_string()
+        .ends_with("smoke test FAILED: 1/7 steps passed"));
+}